@@ -10,8 +10,8 @@ use pyo3::prelude::*;
 use pyo3::types::PyString;
 use pyo3::{exceptions::PyValueError, types::PyBytes};
 use std::{
-    fs::{self, File},
-    io::{BufReader, Cursor, Read},
+    fs,
+    io::{Cursor, Read},
     path::PathBuf,
     sync::Arc,
 };
@@ -265,10 +265,10 @@ impl PyTagger {
     #[text_signature = "(word, add_lower=None)"]
     fn get_data(&self, word: &str, add_lower: Option<bool>) -> Vec<(String, String)> {
         self.tagger
-            .get_tags(
+            .get_tags_with(
                 word,
                 add_lower.unwrap_or(self.options.always_add_lower_tags),
-                self.options.use_compound_split_heuristic,
+                &self.options,
             )
             .into_iter()
             .map(|x| (x.lemma.as_ref().to_string(), x.pos.as_ref().to_string()))
@@ -282,8 +282,12 @@ impl PyTagger {
     ///
     /// Returns:
     ///     group_members (List[str]): The words in the dictionary with the same lemma.
-    fn get_group_members(&self, lemma: &str) -> Vec<&str> {
-        self.tagger.get_group_members(&lemma.to_string())
+    fn get_group_members(&self, lemma: &str) -> Vec<String> {
+        self.tagger
+            .group_members(lemma)
+            .into_iter()
+            .map(|x| x.into_owned())
+            .collect()
     }
 }
 
@@ -300,6 +304,8 @@ impl PyTagger {
 /// * lemmas (List[str]): A list of lemmas of this token
 /// * tags (List[str]): A list of possible POS tags for this token. Including special SENT_START and SENT_END tags.
 /// * chunks (List[str]): Chunks of this token. Are not set for some languages (e. g. German).
+/// * chunk_confidence (Optional[float]): The probability the chunker assigned to the chunks of this token.
+/// * paragraph (int): The index of the paragraph this token is in.
 #[pyclass(name = "Token", module = "nlprule")]
 pub struct PyToken {
     token: owned::Token,
@@ -377,6 +383,16 @@ impl PyToken {
     fn chunks(&self) -> Vec<&str> {
         self.token.chunks.iter().map(|x| x.as_str()).collect()
     }
+
+    #[getter]
+    fn chunk_confidence(&self) -> Option<f32> {
+        self.token.chunk_confidence
+    }
+
+    #[getter]
+    fn paragraph(&self) -> usize {
+        self.token.paragraph
+    }
 }
 
 /// A replacement suggestion with the attributes:
@@ -385,6 +401,7 @@ impl PyToken {
 /// * text (List[str]): A list of suggested replacements.
 /// * source (str): The ID of the rule that triggered this suggestion.
 /// * message (str): A human-readable message for this suggestion.
+/// * short (Optional[str]): A short message for compact display, if the rule has one.
 #[pyclass(name = "Suggestion", module = "nlprule")]
 struct PySuggestion {
     suggestion: Suggestion,
@@ -420,6 +437,11 @@ impl PySuggestion {
     fn message(&self) -> &str {
         &self.suggestion.message
     }
+
+    #[getter]
+    fn short(&self) -> Option<&str> {
+        self.suggestion.short.as_deref()
+    }
 }
 
 impl From<Suggestion> for PySuggestion {
@@ -461,8 +483,8 @@ impl PyTokenizer {
     fn load(code: &str, sentence_splitter: Option<PyObject>) -> PyResult<Self> {
         let bytes = get_resource(code, "tokenizer.bin.gz")?;
 
-        let tokenizer: Tokenizer = bincode::deserialize_from(bytes)
-            .map_err(|x| PyValueError::new_err(format!("{}", x)))?;
+        let tokenizer =
+            Tokenizer::new_from(bytes).map_err(|x| PyValueError::new_err(format!("{}", x)))?;
         Ok(PyTokenizer {
             tokenizer,
             sentence_splitter,
@@ -472,8 +494,7 @@ impl PyTokenizer {
     #[new]
     fn new(path: Option<&str>, sentence_splitter: Option<PyObject>) -> PyResult<Self> {
         let tokenizer = if let Some(path) = path {
-            let reader = BufReader::new(File::open(path).unwrap());
-            bincode::deserialize_from(reader).unwrap()
+            Tokenizer::new(path).map_err(|x| PyValueError::new_err(format!("{}", x)))?
         } else {
             Tokenizer::default()
         };
@@ -727,13 +748,14 @@ impl PyRules {
     #[text_signature = "(code, tokenizer, sentence_splitter=None)"]
     #[staticmethod]
     fn load(
+        py: Python,
         code: &str,
         tokenizer: Py<PyTokenizer>,
         sentence_splitter: Option<PyObject>,
     ) -> PyResult<Self> {
         let bytes = get_resource(code, "rules.bin.gz")?;
 
-        let rules: Rules = bincode::deserialize_from(bytes)
+        let rules = Rules::load_from(bytes, tokenizer.borrow(py).tokenizer())
             .map_err(|x| PyValueError::new_err(format!("{}", x)))?;
         Ok(PyRules {
             rules,
@@ -750,8 +772,7 @@ impl PyRules {
         sentence_splitter: Option<PyObject>,
     ) -> PyResult<Self> {
         let rules = if let Some(path) = path {
-            let reader = BufReader::new(File::open(path).unwrap());
-            bincode::deserialize_from(reader).unwrap()
+            Rules::new(path).map_err(|x| PyValueError::new_err(format!("{}", x)))?
         } else {
             Rules::default()
         };
@@ -760,6 +781,9 @@ impl PyRules {
         } else {
             Py::new(py, PyTokenizer::default())?
         };
+        rules
+            .check_tokenizer(tokenizer.borrow(py).tokenizer())
+            .map_err(|x| PyValueError::new_err(format!("{}", x)))?;
 
         Ok(PyRules {
             rules,
@@ -900,17 +924,7 @@ impl PyRules {
     fn apply_suggestions(py: Python, text: &str, suggestions: Vec<Py<PySuggestion>>) -> String {
         let suggestions: Vec<Suggestion> = suggestions
             .into_iter()
-            .map(|x| {
-                let x = x.borrow(py);
-
-                Suggestion {
-                    source: x.source().to_string(),
-                    message: x.message().to_string(),
-                    replacements: x.replacements().iter().map(|x| x.to_string()).collect(),
-                    start: x.start(),
-                    end: x.end(),
-                }
-            })
+            .map(|x| x.borrow(py).suggestion.clone())
             .collect();
 
         apply_suggestions(text, &suggestions)
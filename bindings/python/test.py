@@ -96,3 +96,23 @@ def test_pickle_roundtrip_works(tokenizer_and_rules):
     (tokenizer, rules) = pickle.loads(dump)
 
     assert len(rules.rules) > 0
+
+
+def test_invalid_rules_raise(tokenizer_and_rules, tmp_path):
+    (tokenizer, _) = tokenizer_and_rules
+
+    with pytest.raises(ValueError):
+        Rules(str(tmp_path / "missing.bin"), tokenizer)
+
+    invalid_path = tmp_path / "invalid.bin"
+    invalid_path.write_bytes(b"not a binary")
+    with pytest.raises(ValueError):
+        Rules(str(invalid_path), tokenizer)
+
+
+def test_invalid_tokenizer_raises(tmp_path):
+    invalid_path = tmp_path / "invalid.bin"
+    invalid_path.write_bytes(b"not a binary")
+
+    with pytest.raises(ValueError):
+        Tokenizer(str(invalid_path))
@@ -0,0 +1,118 @@
+//! Measures the chunker alone and the speed / accuracy tradeoff of the chunker beam options.
+//! Accuracy is the fraction of tokens whose chunks agree with the chunks at the default options.
+//! Needs the compiled English tokenizer at `../storage/en_tokenizer.bin`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use nlprule::{
+    tokenizer::{chunk::ChunkerOptions, AnalyzeOptions},
+    Tokenizer,
+};
+
+// held out from the chunker training data, from the English Wikipedia
+const SENTENCES: &[&str] = &[
+    "The Danube is the second-longest river in Europe, after the Volga in Russia.",
+    "It flows through much of Central and Southeastern Europe, from the Black Forest into the Black Sea.",
+    "A large and historically important river, it was once a frontier of the Roman Empire.",
+    "The river was a natural barrier which the Romans had to cross with bridges built by their engineers.",
+    "Photosynthesis is a process used by plants and other organisms to convert light energy into chemical energy.",
+    "Most plants, algae, and cyanobacteria perform photosynthesis and are called photoautotrophs.",
+    "The first photosynthetic organisms probably evolved early in the evolutionary history of life.",
+    "The printing press is a mechanical device for applying pressure to an inked surface resting upon a print medium.",
+    "Its introduction in the Renaissance is widely regarded as one of the most influential events of the second millennium.",
+    "Within several decades the new technology had spread to more than two hundred cities in a dozen European countries.",
+];
+
+fn chunks(tokenizer: &Tokenizer) -> Vec<Vec<String>> {
+    SENTENCES
+        .iter()
+        .flat_map(|x| tokenizer.tokenize(x))
+        .map(|x| x.chunks)
+        .collect()
+}
+
+fn apply(c: &mut Criterion) {
+    let tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+    let chunker = tokenizer.chunker().as_ref().unwrap();
+    let options = AnalyzeOptions {
+        chunk: false,
+        ..AnalyzeOptions::default()
+    };
+    let sentences: Vec<_> = SENTENCES
+        .iter()
+        .map(|x| tokenizer.tokenize_with(x, &options))
+        .collect();
+
+    c.bench_function("chunker apply", |b| {
+        b.iter(|| {
+            for tokens in sentences.iter() {
+                chunker.apply(&mut black_box(tokens.clone()));
+            }
+        })
+    });
+}
+
+fn beam_options(c: &mut Criterion) {
+    let mut tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+    let reference = chunks(&tokenizer);
+
+    let configs = [
+        ("default", ChunkerOptions::default()),
+        (
+            "pos 2, chunk 4",
+            ChunkerOptions::builder()
+                .pos_beam_size(2)
+                .chunk_beam_size(4)
+                .build()
+                .unwrap(),
+        ),
+        (
+            "pos 1, chunk 2",
+            ChunkerOptions::builder()
+                .pos_beam_size(1)
+                .chunk_beam_size(2)
+                .build()
+                .unwrap(),
+        ),
+        (
+            "pos 1, chunk 1, threshold 0.3",
+            ChunkerOptions::builder()
+                .pos_beam_size(1)
+                .chunk_beam_size(1)
+                .pos_threshold(0.3)
+                .chunk_threshold(0.3)
+                .build()
+                .unwrap(),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("chunker");
+    for (name, options) in configs.iter() {
+        tokenizer
+            .mut_chunker()
+            .unwrap()
+            .set_options(options.clone());
+
+        let agreeing = chunks(&tokenizer)
+            .iter()
+            .zip(reference.iter())
+            .filter(|(a, b)| a == b)
+            .count();
+        println!(
+            "{}: {:.2}% of tokens agree with the default",
+            name,
+            agreeing as f32 / reference.len() as f32 * 100.
+        );
+
+        group.bench_with_input(BenchmarkId::from_parameter(name), &tokenizer, |b, x| {
+            b.iter(|| {
+                for sentence in SENTENCES {
+                    x.tokenize(black_box(sentence));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, apply, beam_options);
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+//! Measures loading the tokenizer and the rules by reading the binaries, by reading gzip compressed binaries and by
+//! memory-mapping them with the regexes compiled on first use, and checks that all give the same suggestions.
+//! Needs the compiled English tokenizer and rules at `../storage/en_tokenizer.bin` and `../storage/en_rules.bin`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use nlprule::{Compression, Rules, Tokenizer};
+
+const TOKENIZER_PATH: &str = "../storage/en_tokenizer.bin";
+const RULES_PATH: &str = "../storage/en_rules.bin";
+
+const TEXT: &str =
+    "She was not been here since Monday. It flows through much of Central and Southeastern \
+Europe, from the Black Forest into the Black Sea.";
+
+fn load(c: &mut Criterion) {
+    let tokenizer = Tokenizer::new(TOKENIZER_PATH).unwrap();
+    let rules = Rules::load(RULES_PATH, &tokenizer).unwrap();
+    let mapped_tokenizer = Tokenizer::from_mmap(TOKENIZER_PATH).unwrap();
+    let mapped_rules = Rules::from_mmap(RULES_PATH).unwrap();
+    assert_eq!(
+        rules.suggest(TEXT, &tokenizer),
+        mapped_rules.suggest(TEXT, &mapped_tokenizer)
+    );
+
+    let mut tokenizer_gzip = Vec::new();
+    tokenizer
+        .to_writer_compressed(&mut tokenizer_gzip, Compression::Gzip)
+        .unwrap();
+    let mut rules_gzip = Vec::new();
+    rules
+        .to_writer_compressed(&mut rules_gzip, Compression::Gzip)
+        .unwrap();
+    let gzip_tokenizer = Tokenizer::new_from(tokenizer_gzip.as_slice()).unwrap();
+    let gzip_rules = Rules::load_from(rules_gzip.as_slice(), &gzip_tokenizer).unwrap();
+    assert_eq!(
+        rules.suggest(TEXT, &tokenizer),
+        gzip_rules.suggest(TEXT, &gzip_tokenizer)
+    );
+
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            let tokenizer = Tokenizer::new(TOKENIZER_PATH).unwrap();
+            Rules::load(RULES_PATH, &tokenizer).unwrap()
+        })
+    });
+    // decompressed while deserializing, without the decompressed binary in memory
+    group.bench_function("read gzip", |b| {
+        b.iter(|| {
+            let tokenizer = Tokenizer::new_from(tokenizer_gzip.as_slice()).unwrap();
+            Rules::load_from(rules_gzip.as_slice(), &tokenizer).unwrap()
+        })
+    });
+    group.bench_function("mmap", |b| {
+        b.iter(|| {
+            let tokenizer = Tokenizer::from_mmap(TOKENIZER_PATH).unwrap();
+            let rules = Rules::from_mmap(RULES_PATH).unwrap();
+            rules.check_tokenizer(&tokenizer).unwrap();
+            rules
+        })
+    });
+    // a CLI checking one text per run also pays for the regexes it uses
+    group.bench_function("mmap and check a text", |b| {
+        b.iter(|| {
+            let tokenizer = Tokenizer::from_mmap(TOKENIZER_PATH).unwrap();
+            let rules = Rules::from_mmap(RULES_PATH).unwrap();
+            rules.suggest(TEXT, &tokenizer)
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, load);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+//! Measures checking a paragraph with and without the index of the positions at which rules can start,
+//! and the size of the index in the serialized rules as a proxy for its memory cost. A long sentence measures
+//! the matchers which compare every token many times, with and without matching the regexes of alternatives at
+//! once (which only makes a difference with the `regex-fancy` backend).
+//! Needs the compiled English tokenizer and rules at `../storage/en_tokenizer.bin` and `../storage/en_rules.bin`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nlprule::{Rules, Tokenizer};
+
+// from the English Wikipedia article on the Danube
+const PARAGRAPH: &str = "The Danube is the second-longest river in Europe, after the Volga in Russia. \
+It flows through much of Central and Southeastern Europe, from the Black Forest into the Black Sea. \
+A large and historically important river, it was once a frontier of the Roman Empire. \
+In the 21st century, it connects ten European countries, running through their territories or marking a border. \
+Originating in Germany, the Danube flows southeast for a distance of some 2,850 km, \
+passing through or bordering Austria, Slovakia, Hungary, Croatia, Serbia, Romania, Bulgaria, Moldova, and Ukraine. \
+Among the many cities on the river are four national capitals: Vienna, Bratislava, Budapest, and Belgrade.";
+
+// one long sentence, so case-insensitive matchers compare the same tokens many times
+const LONG_SENTENCE: &str = "Originating in the Black Forest in Germany, the Danube flows southeast for a distance of \
+some 2,850 km, passing through or bordering Austria, Slovakia, Hungary, Croatia, Serbia, Romania, Bulgaria, \
+Moldova, and Ukraine, and connects Vienna, Bratislava, Budapest, and Belgrade, four national capitals which \
+were once on the frontier of the Roman Empire, before it drains into the Black Sea through the Danube Delta \
+in Romania and Ukraine, which is one of the largest wetlands in Europe and a UNESCO World Heritage Site.";
+
+fn rules(c: &mut Criterion) {
+    let tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+    let rules = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+    let mut unindexed = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+    unindexed.clear_index();
+    let mut unbatched = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+    unbatched.set_batch_regexes(false);
+
+    assert_eq!(
+        rules.suggest(PARAGRAPH, &tokenizer),
+        unindexed.suggest(PARAGRAPH, &tokenizer)
+    );
+    assert_eq!(
+        rules.suggest(LONG_SENTENCE, &tokenizer),
+        unbatched.suggest(LONG_SENTENCE, &tokenizer)
+    );
+
+    let size = bincode::serialized_size(&rules).unwrap();
+    let index_size = size - bincode::serialized_size(&unindexed).unwrap();
+    println!(
+        "rule index: {} bytes serialized ({:.1}% of the rules)",
+        index_size,
+        index_size as f32 / size as f32 * 100.
+    );
+
+    c.bench_function("suggest paragraph", |b| {
+        b.iter(|| rules.suggest(black_box(PARAGRAPH), &tokenizer))
+    });
+    c.bench_function("suggest paragraph without rule index", |b| {
+        b.iter(|| unindexed.suggest(black_box(PARAGRAPH), &tokenizer))
+    });
+    c.bench_function("suggest long sentence", |b| {
+        b.iter(|| rules.suggest(black_box(LONG_SENTENCE), &tokenizer))
+    });
+    c.bench_function("suggest long sentence without regex sets", |b| {
+        b.iter(|| unbatched.suggest(black_box(LONG_SENTENCE), &tokenizer))
+    });
+}
+
+criterion_group!(benches, rules);
+criterion_main!(benches);
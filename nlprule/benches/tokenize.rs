@@ -0,0 +1,81 @@
+//! Measures tokenization runtime and the number of allocations made while tokenizing a paragraph.
+//! Needs the compiled English tokenizer at `../storage/en_tokenizer.bin`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nlprule::Tokenizer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// from the English Wikipedia article on the Danube
+const PARAGRAPH: &str = "The Danube is the second-longest river in Europe, after the Volga in Russia. \
+It flows through much of Central and Southeastern Europe, from the Black Forest into the Black Sea. \
+A large and historically important river, it was once a frontier of the Roman Empire. \
+In the 21st century, it connects ten European countries, running through their territories or marking a border. \
+Originating in Germany, the Danube flows southeast for a distance of some 2,850 km, \
+passing through or bordering Austria, Slovakia, Hungary, Croatia, Serbia, Romania, Bulgaria, Moldova, and Ukraine. \
+Among the many cities on the river are four national capitals: Vienna, Bratislava, Budapest, and Belgrade.";
+
+fn count_allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn tokenize(c: &mut Criterion) {
+    let tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+
+    // warm up once so lazily built structures are not counted
+    tokenizer.tokenize(PARAGRAPH);
+
+    let mut n_tokens = 0;
+    let allocations = count_allocations(|| n_tokens = tokenizer.tokenize(PARAGRAPH).len());
+    println!(
+        "tokenize: {} allocations for {} tokens ({:.1} per token)",
+        allocations,
+        n_tokens,
+        allocations as f32 / n_tokens as f32
+    );
+
+    let allocations = count_allocations(|| {
+        tokenizer.disambiguate(tokenizer.tokenize(PARAGRAPH));
+    });
+    println!(
+        "tokenize + disambiguate: {} allocations for {} tokens ({:.1} per token)",
+        allocations,
+        n_tokens,
+        allocations as f32 / n_tokens as f32
+    );
+
+    c.bench_function("tokenize paragraph", |b| {
+        b.iter(|| tokenizer.tokenize(black_box(PARAGRAPH)))
+    });
+    c.bench_function("tokenize and disambiguate paragraph", |b| {
+        b.iter(|| tokenizer.disambiguate(tokenizer.tokenize(black_box(PARAGRAPH))))
+    });
+}
+
+criterion_group!(benches, tokenize);
+criterion_main!(benches);
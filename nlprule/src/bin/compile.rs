@@ -1,9 +1,101 @@
+use std::sync::Mutex;
+
 use clap::Clap;
-use nlprule::compile::{compile, BuildOptions};
+use indicatif::{ProgressBar, ProgressStyle};
+use nlprule::compile::{build, BuildOptions, CompileProgress, CompileReport, RuleKind};
+
+/// The number of rules shown per kind with `--profile`.
+const PROFILE_TOP: usize = 20;
+
+fn kind_name(kind: RuleKind) -> &'static str {
+    match kind {
+        RuleKind::Disambiguation => "disambiguation",
+        RuleKind::Grammar => "grammar",
+    }
+}
+
+fn print_profile(kind: RuleKind, report: &CompileReport) {
+    println!("Most expensive {} rules:", kind_name(kind));
+    for profile in report.profiles.iter().take(PROFILE_TOP) {
+        println!(
+            "{:>10.1}ms {:>10}B {:>5} scans {:>8} set words  {}",
+            profile.micros as f64 / 1000.,
+            profile.size,
+            profile.regex_scans,
+            profile.set_words,
+            profile.id
+        );
+    }
+}
 
 fn main() {
     env_logger::init();
-    let opts = BuildOptions::parse();
+    let mut opts = BuildOptions::parse();
+
+    let bar = ProgressBar::new(0);
+    bar.set_style(ProgressStyle::default_bar().template("{msg:50} [{bar:40}] {pos}/{len} ({eta})"));
+
+    let progress_bar = bar.clone();
+    // the message of the current phase, extended with the number of matched regexes
+    let phase = Mutex::new(String::new());
+
+    opts.options = opts.options.with_progress(move |progress| match progress {
+        CompileProgress::ParsingXml { kind } => {
+            progress_bar.set_message(&format!("Parsing {} XML", kind_name(kind)));
+        }
+        CompileProgress::BuildingRules {
+            kind,
+            done: 0,
+            total,
+        } => {
+            let mut phase = phase.lock().unwrap();
+            *phase = format!("Building {} rules", kind_name(kind));
+            progress_bar.set_message(&phase);
+            progress_bar.set_length(total as u64);
+            progress_bar.set_position(0);
+        }
+        // the rules are built in parallel so `done` is not necessarily increasing
+        CompileProgress::BuildingRules { .. } => progress_bar.inc(1),
+        CompileProgress::BuildingRegexCache { matched } => {
+            let phase = phase.lock().unwrap();
+            progress_bar.set_message(&format!("{}, {} new regexes", phase, matched));
+        }
+    });
+
+    let result = build(&opts);
+    bar.finish_and_clear();
+
+    let output = match result {
+        Ok(output) => output,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if opts.options.profile {
+        print_profile(RuleKind::Disambiguation, &output.tokenizer_report);
+        print_profile(RuleKind::Grammar, &output.rules_report);
+    }
+
+    if let Some(report) = output.validation {
+        for rule in report.failed() {
+            for failure in &rule.failures {
+                let found: Vec<_> = failure.found.iter().map(|x| &x.replacements).collect();
+                println!(
+                    "{}: \"{}\" expected {:?}, found {:?}",
+                    rule.id,
+                    failure.text,
+                    failure.expected.as_ref().map(|x| &x.replacements),
+                    found
+                );
+            }
+        }
 
-    compile(&opts);
+        println!(
+            "Rules passing examples: {}/{}",
+            report.passed().count(),
+            report.rules.len()
+        );
+    }
 }
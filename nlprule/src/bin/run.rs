@@ -22,11 +22,12 @@ fn main() {
     let opts = Opts::parse();
 
     let tokenizer = Tokenizer::new(opts.tokenizer).unwrap();
-    let rules = Rules::new(opts.rules).unwrap();
+    let rules = Rules::load(opts.rules, &tokenizer).unwrap();
 
-    let incomplete_tokens = tokenizer.disambiguate(tokenizer.tokenize(&opts.text));
+    let (incomplete_tokens, trace) = tokenizer.analyze_traced(&opts.text);
 
     println!("Tokens: {:#?}", incomplete_tokens);
+    println!("Disambiguation: {:#?}", trace);
     println!(
         "Suggestions: {:#?}",
         rules.apply(&finalize(incomplete_tokens), &tokenizer)
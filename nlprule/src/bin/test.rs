@@ -1,5 +1,8 @@
 use clap::Clap;
-use nlprule::{rules::Rules, tokenizer::Tokenizer};
+use nlprule::{
+    rules::Rules,
+    tokenizer::{finalize, Tokenizer},
+};
 
 #[derive(Clap)]
 #[clap(
@@ -13,6 +16,9 @@ struct Opts {
     rules: String,
     #[clap(long, short)]
     ids: Vec<String>,
+    /// Instead of testing, explain where the rules stop matching this sentence.
+    #[clap(long, short)]
+    explain: Option<String>,
 }
 
 fn main() {
@@ -20,9 +26,20 @@ fn main() {
     let opts = Opts::parse();
 
     let tokenizer = Tokenizer::new(opts.tokenizer).unwrap();
-    let rules_container = Rules::new(opts.rules).unwrap();
+    let rules_container = Rules::load(opts.rules, &tokenizer).unwrap();
     let rules = rules_container.rules();
 
+    if let Some(sentence) = &opts.explain {
+        let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize(sentence)));
+
+        for rule in rules {
+            if opts.ids.is_empty() || opts.ids.contains(&rule.id().to_string()) {
+                println!("{}", rule.explain(&tokens));
+            }
+        }
+        return;
+    }
+
     println!("Runnable rules: {}", rules.len());
 
     let mut passes = 0;
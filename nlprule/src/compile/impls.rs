@@ -1,4 +1,11 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -6,15 +13,27 @@ use crate::{
     rule::{
         disambiguation::POSFilter,
         engine::composition::{Matcher, PosMatcher, TextMatcher},
-        DisambiguationRule, MatchGraph, Rule,
+        grammar::CaseMapping,
+        DefaultState, DisambiguationRule, MatchGraph, Rule,
     },
-    rules::{Rules, RulesOptions},
-    tokenizer::{chunk, Tokenizer, TokenizerOptions},
+    rules::{IgnoreWords, Prefilter, RuleIndex, Rules, RulesOptions},
+    tokenizer::{chunk, multiword, Tokenizer, TokenizerOptions},
     types::*,
-    utils::parallelism::MaybeParallelIterator,
+    utils::{
+        bitset::BitSet,
+        parallelism::{MaybeParallelIterator, ParallelismConfig},
+    },
+    DuplicateIdPolicy, ErrorPolicy, FormatVersion,
 };
 
-use super::parse_structure::BuildInfo;
+use super::{
+    parse_structure::{
+        parse_default_state, resolve_default_state, BuildInfo, BuildStats, RegexCacheKey,
+        MAX_QUANTIFIER,
+    },
+    ChunkerLoadError, CompileProgress, CompileReport, Error, RegexSetReport, RuleKind, RuleOutcome,
+    RuleProfile, RuleReport,
+};
 
 impl TextMatcher {
     pub fn new(matcher: Matcher, info: &mut BuildInfo) -> Self {
@@ -23,203 +42,933 @@ impl TextMatcher {
         let set = if matcher.needs_graph() {
             None
         } else if let either::Right(regex) = &matcher.matcher {
-            let mut hasher = DefaultHasher::default();
-            regex.hash(&mut hasher);
-            matcher.negate.hash(&mut hasher);
-            matcher.empty_always_false.hash(&mut hasher);
-            let matcher_hash = hasher.finish();
-
-            if let Some(set) = info.mut_regex_cache().get(&matcher_hash) {
-                set.clone()
+            let key = RegexCacheKey {
+                regex: regex.regex_str().to_string(),
+                case_sensitive: regex.case_sensitive(),
+                negate: matcher.negate,
+                empty_always_false: matcher.empty_always_false,
+            };
+
+            let set = if let Some(set) = info.regex_cache().get(&key) {
+                set
             } else {
-                let data: Vec<_> = info.tagger().word_store().iter().collect();
+                let data: Vec<_> = info.tagger().words().iter().collect();
 
+                info.stats_mut().regex_scans += 1;
                 let set: DefaultHashSet<u32> = data
-                    .into_maybe_par_iter()
+                    .into_maybe_par_iter_with(&ParallelismConfig::default())
                     .filter_map(|(word, id)| {
                         if matcher.is_match(word.as_str(), &graph, None) {
-                            Some(*id)
+                            Some(id)
                         } else {
                             None
                         }
                     })
                     .collect();
 
-                // there are some regexes which match lots of strings
-                // this cutoff is pretty arbitrary but without any threshold the size of some sets blows up
-                // the vast majority of regexes matches less than 100 strings from manual inspection
-                let set = if set.len() > 100 { None } else { Some(set) };
-                info.mut_regex_cache().insert(matcher_hash, set.clone());
+                // there are some regexes which match lots of strings, without any threshold
+                // the size of some sets blows up
+                let set = if set.len() > info.max_regex_set_size() {
+                    None
+                } else {
+                    Some(set)
+                };
+                info.regex_cache().insert(key, set.clone());
+                info.report(CompileProgress::BuildingRegexCache {
+                    matched: info.regex_cache().misses(),
+                });
                 set
+            };
+
+            // the cache could have been written with a higher maximum
+            let set = set.filter(|x| x.len() <= info.max_regex_set_size());
+            match &set {
+                Some(set) => info.stats_mut().set_words += set.len(),
+                None => info.regex_cache().record_fallback(),
             }
+            info.stats_mut()
+                .regex_sets
+                .push((regex.regex_str().to_string(), set.as_ref().map(|x| x.len())));
+            set
         } else {
             None
         };
 
-        TextMatcher { matcher, set }
+        TextMatcher {
+            matcher,
+            set,
+            fallbacks: None,
+        }
+    }
+
+    /// Creates a matcher for the lemmas of a token. In addition to regexes, the IDs of plain strings are precomputed
+    /// since they are compared against the lemmas of every reading.
+    pub fn new_lemma(matcher: Matcher, info: &mut BuildInfo) -> Self {
+        if let either::Left(either::Left(string)) = &matcher.matcher {
+            // a negated string matches almost every word
+            if !matcher.negate {
+                let set = info.word_ids(string, matcher.case_sensitive);
+                info.stats_mut().set_words += set.len();
+
+                return TextMatcher {
+                    matcher,
+                    set: Some(set),
+                    fallbacks: None,
+                };
+            }
+        }
+
+        TextMatcher::new(matcher, info)
     }
 }
 
 impl PosMatcher {
-    pub fn new(matcher: Matcher, info: &mut BuildInfo) -> Self {
-        let mut mask = vec![false; info.tagger().tag_store().len()];
+    /// Errors if the matcher matches a single tag (i. e. is not a regex) which the tagger does not know
+    /// since it would never match.
+    pub fn new(matcher: Matcher, info: &mut BuildInfo) -> Result<Self, Error> {
+        if let either::Left(either::Left(tag)) = &matcher.matcher {
+            if info.tagger().tag_store().get_by_left(tag).is_none() {
+                return Err(Error::unknown_pos_tag(tag, info.tagger()));
+            }
+        }
+
+        let tag_store = info.tagger().tag_store();
         let graph = MatchGraph::default();
 
-        for (word, id) in info.tagger().tag_store().iter() {
-            mask[*id as usize] = matcher.is_match(word.as_str(), &graph, None);
+        let mask = BitSet::from_fn(tag_store.len(), |id| {
+            let tag = tag_store.get_by_right(&(id as u16)).unwrap();
+            matcher.is_match(tag.as_str(), &graph, None)
+        });
+
+        Ok(PosMatcher { mask })
+    }
+}
+
+/// Logs the errors grouped by their kind, the most frequent kind first.
+/// Within a kind, identical messages are counted.
+fn log_errors(context: &str, errors: &[Error], level: log::Level) {
+    use std::collections::HashMap;
+
+    let mut groups: HashMap<&str, HashMap<String, usize>> = HashMap::new();
+    for error in errors {
+        *groups
+            .entry(error.kind())
+            .or_default()
+            .entry(error.to_string())
+            .or_insert(0) += 1;
+    }
+
+    // kind, total count and messages with their count
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .map(|(kind, messages)| {
+            let mut messages: Vec<_> = messages.into_iter().collect();
+            messages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            (kind, messages.iter().map(|x| x.1).sum::<usize>(), messages)
+        })
+        .collect();
+    groups.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    for (kind, count, messages) in groups {
+        log::log!(
+            level,
+            "{} {} errors constructing {}: {:#?}",
+            count,
+            kind,
+            context,
+            messages
+        );
+    }
+}
+
+/// Applies the error policy to the errors of the rules which could not be compiled.
+/// Returns the errors if the rules can be skipped.
+fn check_errors(
+    context: &str,
+    errors: Vec<Error>,
+    policy: ErrorPolicy,
+) -> Result<Vec<Error>, Error> {
+    if errors.is_empty() {
+        return Ok(errors);
+    }
+
+    match policy {
+        ErrorPolicy::Ignore => log_errors(context, &errors, log::Level::Debug),
+        ErrorPolicy::WarnAboveThreshold(threshold) => {
+            let level = if errors.len() > threshold {
+                log::Level::Warn
+            } else {
+                log::Level::Debug
+            };
+            log_errors(context, &errors, level);
+        }
+        ErrorPolicy::Fail => return Err(Error::RuleErrors(errors)),
+    }
+
+    Ok(errors)
+}
+
+/// Checks that the configured `ids` and `ignore_ids` are IDs of rules in `reports`. Unknown IDs fail with
+/// [ErrorPolicy::Fail] and are logged otherwise. Returns the unknown IDs with the closest existing IDs.
+fn check_option_ids(
+    context: &str,
+    ids: &[String],
+    ignore_ids: &[String],
+    reports: &[RuleReport],
+    policy: ErrorPolicy,
+) -> Result<BTreeMap<String, Vec<String>>, Error> {
+    let known: DefaultHashSet<&str> = reports.iter().filter_map(|x| x.id.as_deref()).collect();
+    let mut unknown = BTreeMap::new();
+
+    for id in ids.iter().chain(ignore_ids) {
+        if known.contains(id.as_str()) {
+            continue;
+        }
+
+        let error = Error::unknown_rule_id(id, known.iter().copied());
+        let level = match policy {
+            ErrorPolicy::Ignore => log::Level::Debug,
+            ErrorPolicy::WarnAboveThreshold(_) => log::Level::Warn,
+            ErrorPolicy::Fail => return Err(error),
+        };
+        log::log!(level, "{}: {}", context, error);
+
+        if let Error::UnknownRuleId { id, suggestions } = error {
+            unknown.insert(id, suggestions);
+        }
+    }
+
+    Ok(unknown)
+}
+
+/// Counts the built rules to report [CompileProgress::BuildingRules].
+struct RuleProgress {
+    kind: RuleKind,
+    done: AtomicUsize,
+    total: usize,
+}
+
+impl RuleProgress {
+    fn new(info: &BuildInfo, kind: RuleKind, total: usize) -> Self {
+        info.report(CompileProgress::BuildingRules {
+            kind,
+            done: 0,
+            total,
+        });
+
+        RuleProgress {
+            kind,
+            done: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    fn increment(&self, info: &BuildInfo) {
+        // without a callback, the shared counter would only cause contention between the threads
+        if info.reports_progress() {
+            let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+            info.report(CompileProgress::BuildingRules {
+                kind: self.kind,
+                done,
+                total: self.total,
+            });
         }
+    }
+}
+
+/// The cost of building a rule, turned into a [RuleProfile] once the rule is included.
+struct RuleCost {
+    elapsed: Duration,
+    stats: BuildStats,
+}
 
-        PosMatcher { mask }
+impl RuleCost {
+    fn measure<T>(info: &mut BuildInfo, build: impl FnOnce(&mut BuildInfo) -> T) -> (T, Self) {
+        info.take_stats();
+        let start = Instant::now();
+        let value = build(info);
+
+        let cost = RuleCost {
+            elapsed: start.elapsed(),
+            stats: info.take_stats(),
+        };
+        (value, cost)
+    }
+
+    /// Takes the regexes matched against the words of the tagger, see [CompileReport::regex_sets].
+    fn take_regex_sets(&mut self, id: &str) -> impl Iterator<Item = RegexSetReport> + '_ {
+        let id = id.to_string();
+        self.stats
+            .regex_sets
+            .drain(..)
+            .map(move |(regex, words)| RegexSetReport {
+                rule_id: id.clone(),
+                regex,
+                words,
+            })
+    }
+
+    fn into_profile<T: Serialize>(self, id: &str, rule: &T) -> RuleProfile {
+        RuleProfile {
+            id: id.to_string(),
+            micros: self.elapsed.as_micros() as u64,
+            size: bincode::serialized_size(rule).expect("rules can be serialized"),
+            regex_scans: self.stats.regex_scans,
+            set_words: self.stats.set_words,
+        }
     }
 }
 
+/// A reader of rules with the path of its file, if any, to attribute the rules in the [CompileReport].
+pub(crate) type RuleSource<R> = (R, Option<String>);
+
+/// Opens the files at `paths`.
+fn open_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<RuleSource<BufReader<File>>>, Error> {
+    paths
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            Ok((
+                BufReader::new(File::open(path)?),
+                Some(path.display().to_string()),
+            ))
+        })
+        .collect()
+}
+
+/// The rules read from multiple readers, see [read_all].
+struct Readings<T> {
+    readings: Vec<T>,
+    /// The index of the reader of each reading.
+    file_indices: Vec<usize>,
+    /// The path of the file of each reader, if any.
+    files: Vec<Option<String>>,
+}
+
+/// Reads the rules of all readers with `read`.
+fn read_all<R, T, I: Iterator<Item = T>>(
+    readers: Vec<RuleSource<R>>,
+    read: impl Fn(R) -> I,
+) -> Readings<T> {
+    let mut readings = Vec::new();
+    let mut file_indices = Vec::new();
+    let mut files = Vec::new();
+
+    for (i, (reader, file)) in readers.into_iter().enumerate() {
+        readings.extend(read(reader));
+        file_indices.resize(readings.len(), i);
+        files.push(file);
+    }
+
+    Readings {
+        readings,
+        file_indices,
+        files,
+    }
+}
+
+/// The language code of the first rule which has one.
+fn lang_code<T, E>(
+    readings: &[Result<T, E>],
+    lang_code: impl Fn(&T) -> &Option<String>,
+) -> Option<String> {
+    readings
+        .iter()
+        .filter_map(|x| x.as_ref().ok())
+        .find_map(|x| lang_code(x).clone())
+}
+
+/// Handles rules with the same ID as a rule in a previous file according to `policy`.
+/// Returns whether each rule is replaced by a rule in a later file. Rules with the same ID in one file are
+/// not checked, the XML of LanguageTool is expected to be consistent.
+fn resolve_duplicate_ids<T>(
+    rules: &mut [Result<T, Error>],
+    id: impl Fn(&T) -> &str,
+    file_indices: &[usize],
+    files: &[Option<String>],
+    policy: DuplicateIdPolicy,
+) -> Vec<bool> {
+    let mut replaced = vec![false; rules.len()];
+    let mut seen: DefaultHashMap<String, usize> = DefaultHashMap::default();
+
+    for i in 0..rules.len() {
+        let rule_id = match &rules[i] {
+            Ok(rule) => id(rule).to_string(),
+            Err(_) => continue,
+        };
+
+        if let Some(&previous) = seen.get(&rule_id) {
+            if file_indices[previous] != file_indices[i] {
+                match policy {
+                    DuplicateIdPolicy::Error => {
+                        rules[i] = Err(Error::DuplicateId {
+                            file: files[file_indices[previous]].clone(),
+                            rule_id: Some(rule_id),
+                        });
+                        continue;
+                    }
+                    DuplicateIdPolicy::LastWins => replaced[previous] = true,
+                }
+            }
+        }
+        seen.insert(rule_id, i);
+    }
+
+    replaced
+}
+
 impl Rules {
+    /// Creates the rules from the grammar rules at `path`.
+    /// Fails according to [RulesOptions::error_policy] if rules can not be compiled.
     pub fn from_xml<P: AsRef<std::path::Path>>(
         path: P,
         build_info: &mut BuildInfo,
         options: RulesOptions,
-    ) -> Self {
-        use log::warn;
-        use std::collections::HashMap;
+    ) -> Result<Self, Error> {
+        Self::from_xml_multi(&[path], build_info, options)
+    }
 
-        let rules = super::parse_structure::read_rules(path);
-        let mut errors: HashMap<String, usize> = HashMap::new();
+    /// Like [Rules::from_xml] but compiles the grammar rules of all files at `paths` into one set
+    /// e. g. if the rules of a language are split across files or to add rules from a user file.
+    /// Rules with the same ID in different files are handled according to [RulesOptions::duplicate_ids].
+    pub fn from_xml_multi<P: AsRef<std::path::Path>>(
+        paths: &[P],
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<Self, Error> {
+        Self::from_xml_inner(open_files(paths)?, build_info, options).map(|x| x.0)
+    }
 
-        let rules: Vec<_> = rules
+    /// Like [Rules::from_xml] but reads the grammar rules from `reader` e. g. to compile XML which is in memory.
+    pub fn from_xml_reader<R: BufRead>(
+        reader: R,
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<Self, Error> {
+        Self::from_xml_reader_with_errors(reader, build_info, options).map(|x| x.0)
+    }
+
+    /// Like [Rules::from_xml] but additionally returns the errors of the rules which were skipped
+    /// e. g. to compare them with the errors of a previous build.
+    pub fn from_xml_with_errors<P: AsRef<std::path::Path>>(
+        path: P,
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<(Self, Vec<Error>), Error> {
+        Self::from_xml_inner(open_files(&[path])?, build_info, options).map(|x| (x.0, x.1))
+    }
+
+    /// Like [Rules::from_xml_reader] but additionally returns the errors of the rules which were skipped.
+    pub fn from_xml_reader_with_errors<R: BufRead>(
+        reader: R,
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<(Self, Vec<Error>), Error> {
+        Self::from_xml_inner(vec![(reader, None)], build_info, options).map(|x| (x.0, x.1))
+    }
+
+    /// Like [Rules::from_xml] but additionally returns the outcome of compiling each rule.
+    pub fn from_xml_with_report<P: AsRef<std::path::Path>>(
+        path: P,
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<(Self, CompileReport), Error> {
+        Self::from_xml_multi_with_report(&[path], build_info, options)
+    }
+
+    /// Like [Rules::from_xml_multi] but additionally returns the outcome of compiling each rule
+    /// together with the file of the rule.
+    pub fn from_xml_multi_with_report<P: AsRef<std::path::Path>>(
+        paths: &[P],
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<(Self, CompileReport), Error> {
+        Self::from_xml_inner(open_files(paths)?, build_info, options).map(|x| (x.0, x.2))
+    }
+
+    /// Like [Rules::from_xml_reader] but additionally returns the outcome of compiling each rule.
+    pub fn from_xml_reader_with_report<R: BufRead>(
+        reader: R,
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<(Self, CompileReport), Error> {
+        Self::from_xml_inner(vec![(reader, None)], build_info, options).map(|x| (x.0, x.2))
+    }
+
+    /// Compiles the rules of all readers into one set. Each reader has the path of its file, if any.
+    pub(crate) fn from_xml_inner<R: BufRead>(
+        readers: Vec<RuleSource<R>>,
+        build_info: &mut BuildInfo,
+        options: RulesOptions,
+    ) -> Result<(Self, Vec<Error>, CompileReport), Error> {
+        build_info.report(CompileProgress::ParsingXml {
+            kind: RuleKind::Grammar,
+        });
+        let Readings {
+            readings: rules,
+            file_indices,
+            files,
+        } = read_all(readers, super::parse_structure::read_rules);
+        let header = build_info.header(lang_code(&rules, |x| &x.0.lang_code));
+        let mut errors = Vec::new();
+        let group_ids: Vec<_> = rules
+            .iter()
+            .map(|x| {
+                x.as_ref()
+                    .ok()
+                    .and_then(|x| x.1.as_ref())
+                    .map(|x| x.id.clone())
+            })
+            .collect();
+
+        // the structures are read sequentially, the rules are then built in parallel
+        // collecting keeps the original order so the resulting binaries are reproducible
+        let mut rules: Vec<_> = rules
             .into_iter()
-            .filter_map(|x| match x {
-                Ok((rule_structure, group, category)) => {
-                    let id = rule_structure.id.as_ref().map_or_else(
-                        || {
-                            let group = group.as_ref().expect("must have group if ID not set");
-                            format!("{}.{}", group.id, group.n)
-                        },
-                        |x| x.clone(),
+            .map(|x| {
+                x.and_then(|(rule_structure, group, category)| {
+                    // the reader reports rules without ID, name or category with their line,
+                    // these errors only guard against readings constructed otherwise
+                    let id = match (&rule_structure.id, &group) {
+                        (Some(id), _) => id.clone(),
+                        (None, Some(group)) => format!("{}.{}", group.id, group.n),
+                        (None, None) => {
+                            return Err(Error::unexpected(
+                                "rule has no `id` and is not in a `rulegroup`",
+                            ))
+                        }
+                    };
+                    let category = category.ok_or_else(|| {
+                        Error::unexpected("grammar rule is not in a `category`").with_rule_id(&id)
+                    })?;
+                    let parse = |default: Option<&str>| {
+                        parse_default_state(default).map_err(|x| x.with_rule_id(&id))
+                    };
+                    let default_state = resolve_default_state(
+                        parse(rule_structure.default.as_deref())?,
+                        parse(group.as_ref().and_then(|x| x.default.as_deref()))?,
+                        parse(category.default.as_deref())?,
                     );
-                    let category = category.expect("grammar rules must have category");
-                    let off = rule_structure
-                        .default
-                        .as_ref()
-                        .map(|x| x == "off")
-                        .or_else(|| {
-                            group
-                                .as_ref()
-                                .and_then(|x| x.default.as_ref().map(|x| x == "off"))
-                        })
-                        .or_else(|| category.default.as_ref().map(|x| x == "off"))
-                        .unwrap_or(false);
-                    let name = rule_structure.name.as_ref().map_or_else(
-                        || {
-                            let group = group.as_ref().expect("must have group if name not set");
-                            group.name.clone()
+                    let name = match (&rule_structure.name, &group) {
+                        (Some(name), _) => name.clone(),
+                        (None, Some(group)) => group.name.clone(),
+                        (None, None) => {
+                            return Err(Error::unexpected(
+                                "rule has no `name` and is not in a `rulegroup`",
+                            )
+                            .with_rule_id(&id))
+                        }
+                    };
+
+                    Ok((rule_structure, id, name, default_state, category))
+                })
+            })
+            .collect();
+        let replaced = resolve_duplicate_ids(
+            &mut rules,
+            |x| &x.1,
+            &file_indices,
+            &files,
+            options.duplicate_ids,
+        );
+
+        let category_ids: Vec<_> = rules
+            .iter()
+            .map(|x| x.as_ref().ok().map(|x| x.4.id.clone()))
+            .collect();
+
+        let progress = RuleProgress::new(build_info, RuleKind::Grammar, rules.len());
+        let rules: Vec<_> = rules
+            .into_maybe_par_iter_with(&ParallelismConfig::default())
+            .map_with(build_info.clone(), |info, x| {
+                let rule = match x {
+                    Ok((rule_structure, id, name, default_state, category)) => {
+                        match RuleCost::measure(info, |info| {
+                            Rule::from_rule_structure(rule_structure, info)
+                        }) {
+                            (Ok(rule), cost) => Ok((rule, id, name, default_state, category, cost)),
+                            (Err(x), _) => Err(x.with_rule_id(&id)),
+                        }
+                    }
+                    Err(x) => Err(x),
+                };
+                progress.increment(info);
+                rule
+            })
+            .collect();
+
+        let lint = build_info.lints();
+        let profile = build_info.profiles();
+        let mut lints = Vec::new();
+        let mut profiles = Vec::new();
+        let mut regex_sets = Vec::new();
+        let mut reports = Vec::new();
+        let mut included_group_ids = Vec::new();
+        let rules: Vec<_> = rules
+            .into_iter()
+            .zip(category_ids)
+            .enumerate()
+            .filter_map(|(i, (x, category_id))| match x {
+                Ok((mut rule, id, name, default_state, category, mut cost)) => {
+                    let included = !replaced[i]
+                        && (options.ids.is_empty() || options.ids.contains(&id))
+                        && !options.ignore_ids.contains(&id);
+                    reports.push(RuleReport {
+                        id: Some(id.clone()),
+                        category_id,
+                        file: files[file_indices[i]].clone(),
+                        outcome: if included {
+                            RuleOutcome::Ok
+                        } else {
+                            RuleOutcome::FilteredOut
                         },
-                        |x| x.clone(),
-                    );
+                    });
 
-                    match Rule::from_rule_structure(rule_structure, build_info) {
-                        Ok(mut rule) => {
-                            if (options.ids.is_empty() || options.ids.contains(&id))
-                                && !options.ignore_ids.contains(&id)
-                            {
-                                rule.id = id;
-                                rule.name = name;
-                                rule.on = !off;
-                                rule.category_id = category.id;
-                                rule.category_name = category.name;
-                                rule.category_type = category.kind;
-                                Some(rule)
-                            } else {
-                                None
-                            }
+                    if included {
+                        if lint {
+                            lints.extend(super::lint::lint_engine(&rule.engine, &id));
                         }
-                        Err(x) => {
-                            *errors.entry(format!("[Rule] {}", x)).or_insert(0) += 1;
-                            None
+                        rule.id = id;
+                        rule.name = name;
+                        rule.on = default_state == DefaultState::On;
+                        rule.default_state = default_state;
+                        rule.category_id = category.id;
+                        rule.category_name = category.name;
+                        rule.category_type = category.kind;
+                        if options.strip_metadata {
+                            rule.strip_metadata();
+                        }
+                        regex_sets.extend(cost.take_regex_sets(&rule.id));
+                        if profile {
+                            profiles.push(cost.into_profile(&rule.id, &rule));
                         }
+                        included_group_ids.push(group_ids[i].clone());
+                        Some(rule)
+                    } else {
+                        None
                     }
                 }
                 Err(x) => {
-                    *errors.entry(format!("[Structure] {}", x)).or_insert(0) += 1;
+                    reports.push(RuleReport::from_error(
+                        &x,
+                        category_id,
+                        files[file_indices[i]].clone(),
+                    ));
+                    errors.push(x);
                     None
                 }
             })
             .collect();
 
-        if !errors.is_empty() {
-            let mut errors: Vec<(String, usize)> = errors.into_iter().collect();
-            errors.sort_by_key(|x| -(x.1 as i32));
-
-            warn!("Errors constructing Rules: {:#?}", &errors);
+        if lint {
+            lints.extend(super::lint::lint_shadowed(&rules, &included_group_ids));
         }
 
-        Rules { rules }
+        let errors = check_errors("Rules", errors, options.error_policy)?;
+        let unknown_ids = check_option_ids(
+            "Rules",
+            &options.ids,
+            &options.ignore_ids,
+            &reports,
+            options.error_policy,
+        )?;
+        let mut report = CompileReport::new(reports, &errors, lints, profiles, regex_sets);
+        report.unknown_ids = unknown_ids;
+
+        Ok((
+            Rules {
+                header: Some(header),
+                prefilter: Prefilter::new(&rules),
+                index: RuleIndex::new(&rules, build_info),
+                rules,
+                ..Default::default()
+            },
+            errors,
+            report,
+        ))
     }
 }
 
 impl Tokenizer {
+    /// Creates a tokenizer with the disambiguation rules at `path`.
+    /// Fails according to [TokenizerOptions::error_policy] if rules can not be compiled.
     pub fn from_xml<P: AsRef<std::path::Path>>(
         path: P,
         build_info: &mut BuildInfo,
         chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<Self, Error> {
+        Self::from_xml_multi(&[path], build_info, chunker, multiword_tagger, options)
+    }
+
+    /// Like [Tokenizer::from_xml] but compiles the disambiguation rules of all files at `paths`, in order.
+    /// Rules with the same ID in different files are handled according to [TokenizerOptions::duplicate_ids],
+    /// a rule replacing a rule of a previous file is applied at its own position.
+    pub fn from_xml_multi<P: AsRef<std::path::Path>>(
+        paths: &[P],
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<Self, Error> {
+        Self::from_xml_inner(
+            open_files(paths)?,
+            build_info,
+            chunker,
+            multiword_tagger,
+            options,
+        )
+        .map(|x| x.0)
+    }
+
+    /// Like [Tokenizer::from_xml] but reads the disambiguation rules from `reader` e. g. to compile XML which is in memory.
+    pub fn from_xml_reader<R: BufRead>(
+        reader: R,
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<Self, Error> {
+        Self::from_xml_reader_with_errors(reader, build_info, chunker, multiword_tagger, options)
+            .map(|x| x.0)
+    }
+
+    /// Like [Tokenizer::from_xml] but additionally returns the errors of the rules which were skipped
+    /// e. g. to compare them with the errors of a previous build.
+    pub fn from_xml_with_errors<P: AsRef<std::path::Path>>(
+        path: P,
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
         options: TokenizerOptions,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        use log::warn;
+    ) -> Result<(Self, Vec<Error>), Error> {
+        Self::from_xml_inner(
+            open_files(&[path])?,
+            build_info,
+            chunker,
+            multiword_tagger,
+            options,
+        )
+        .map(|x| (x.0, x.1))
+    }
 
-        let rules = super::parse_structure::read_disambiguation_rules(path);
-        let mut error = None;
+    /// Like [Tokenizer::from_xml_reader] but additionally returns the errors of the rules which were skipped.
+    pub fn from_xml_reader_with_errors<R: BufRead>(
+        reader: R,
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<(Self, Vec<Error>), Error> {
+        Self::from_xml_inner(
+            vec![(reader, None)],
+            build_info,
+            chunker,
+            multiword_tagger,
+            options,
+        )
+        .map(|x| (x.0, x.1))
+    }
 
+    /// Like [Tokenizer::from_xml] but additionally returns the outcome of compiling each rule.
+    pub fn from_xml_with_report<P: AsRef<std::path::Path>>(
+        path: P,
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<(Self, CompileReport), Error> {
+        Self::from_xml_multi_with_report(&[path], build_info, chunker, multiword_tagger, options)
+    }
+
+    /// Like [Tokenizer::from_xml_multi] but additionally returns the outcome of compiling each rule
+    /// together with the file of the rule.
+    pub fn from_xml_multi_with_report<P: AsRef<std::path::Path>>(
+        paths: &[P],
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<(Self, CompileReport), Error> {
+        Self::from_xml_inner(
+            open_files(paths)?,
+            build_info,
+            chunker,
+            multiword_tagger,
+            options,
+        )
+        .map(|x| (x.0, x.2))
+    }
+
+    /// Like [Tokenizer::from_xml_reader] but additionally returns the outcome of compiling each rule.
+    pub fn from_xml_reader_with_report<R: BufRead>(
+        reader: R,
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<(Self, CompileReport), Error> {
+        Self::from_xml_inner(
+            vec![(reader, None)],
+            build_info,
+            chunker,
+            multiword_tagger,
+            options,
+        )
+        .map(|x| (x.0, x.2))
+    }
+
+    /// Compiles the disambiguation rules of all readers, in order. Each reader has the path of its file, if any.
+    pub(crate) fn from_xml_inner<R: BufRead>(
+        readers: Vec<RuleSource<R>>,
+        build_info: &mut BuildInfo,
+        chunker: Option<chunk::Chunker>,
+        multiword_tagger: Option<multiword::MultiwordTagger>,
+        options: TokenizerOptions,
+    ) -> Result<(Self, Vec<Error>, CompileReport), Error> {
+        build_info.report(CompileProgress::ParsingXml {
+            kind: RuleKind::Disambiguation,
+        });
+        let Readings {
+            readings: rules,
+            file_indices,
+            files,
+        } = read_all(readers, super::parse_structure::read_disambiguation_rules);
+        let header = build_info.header(lang_code(&rules, |x| &x.0.lang_code));
+        let mut errors = Vec::new();
+
+        // see `Rules::from_xml_inner`, the order of the rules is preserved
+        let mut rules: Vec<_> = rules
+            .into_iter()
+            .map(|x| {
+                x.and_then(|(rule_structure, group, category)| {
+                    let id = match (&rule_structure.id, &group) {
+                        (Some(id), _) => id.clone(),
+                        (None, Some(group)) => format!("{}.{}", group.id, group.n),
+                        (None, None) => {
+                            return Err(Error::unexpected(
+                                "rule has no `id` and is not in a `rulegroup`",
+                            ))
+                        }
+                    };
+
+                    Ok((rule_structure, id, category.map(|x| x.id)))
+                })
+            })
+            .collect();
+        let replaced = resolve_duplicate_ids(
+            &mut rules,
+            |x| &x.1,
+            &file_indices,
+            &files,
+            options.duplicate_ids,
+        );
+
+        let category_ids: Vec<_> = rules
+            .iter()
+            .map(|x| x.as_ref().ok().and_then(|x| x.2.clone()))
+            .collect();
+
+        let progress = RuleProgress::new(build_info, RuleKind::Disambiguation, rules.len());
+        let rules: Vec<_> = rules
+            .into_maybe_par_iter_with(&ParallelismConfig::default())
+            .map_with(build_info.clone(), |info, x| {
+                let rule = match x {
+                    Ok((rule_structure, id, _)) => {
+                        match RuleCost::measure(info, |info| {
+                            DisambiguationRule::from_rule_structure(rule_structure, info)
+                        }) {
+                            (Ok(rule), cost) => Ok((rule, id, cost)),
+                            (Err(x), _) => Err(x.with_rule_id(&id)),
+                        }
+                    }
+                    Err(x) => Err(x),
+                };
+                progress.increment(info);
+                rule
+            })
+            .collect();
+
+        let lint = build_info.lints();
+        let profile = build_info.profiles();
+        let mut lints = Vec::new();
+        let mut profiles = Vec::new();
+        let mut regex_sets = Vec::new();
+        let mut reports = Vec::new();
         let rules: Vec<_> = rules
             .into_iter()
-            .filter_map(|x| match x {
-                Ok((rule_structure, group, _)) => {
-                    let id = rule_structure.id.as_ref().map_or_else(
-                        || {
-                            let group = group.expect("must have group if ID not set");
-                            format!("{}.{}", group.id, group.n)
+            .zip(category_ids)
+            .enumerate()
+            .filter_map(|(i, (x, category_id))| match x {
+                Ok((mut rule, id, mut cost)) => {
+                    let included = errors.is_empty()
+                        && !replaced[i]
+                        && (options.ids.is_empty() || options.ids.contains(&id))
+                        && !options.ignore_ids.contains(&id);
+                    reports.push(RuleReport {
+                        id: Some(id.clone()),
+                        category_id,
+                        file: files[file_indices[i]].clone(),
+                        outcome: if included {
+                            RuleOutcome::Ok
+                        } else {
+                            RuleOutcome::FilteredOut
                         },
-                        |x| x.clone(),
-                    );
+                    });
 
-                    match DisambiguationRule::from_rule_structure(rule_structure, build_info) {
-                        Ok(mut rule) => {
-                            if error.is_none()
-                                && (options.ids.is_empty() || options.ids.contains(&id))
-                                && !options.ignore_ids.contains(&id)
-                            {
-                                rule.id = id;
-
-                                Some(rule)
-                            } else {
-                                None
-                            }
+                    if included {
+                        if lint {
+                            lints.extend(super::lint::lint_engine(&rule.engine, &id));
                         }
-                        Err(x) => {
-                            error = Some(format!("[Rule] {}", x));
-                            None
+                        rule.id = id;
+                        regex_sets.extend(cost.take_regex_sets(&rule.id));
+                        if profile {
+                            profiles.push(cost.into_profile(&rule.id, &rule));
                         }
+
+                        Some(rule)
+                    } else {
+                        None
                     }
                 }
                 Err(x) => {
-                    error = Some(format!("[Structure] {}", x));
+                    reports.push(RuleReport::from_error(
+                        &x,
+                        category_id,
+                        files[file_indices[i]].clone(),
+                    ));
+                    errors.push(x);
                     None
                 }
             })
             .collect();
 
-        if let Some(x) = error {
-            if options.allow_errors {
-                warn!("Error constructing Disambiguator: {}", x)
-            } else {
-                return Err(format!("Error constructing Disambiguator: {}", x).into());
-            }
-        }
+        let errors = check_errors("Disambiguator", errors, options.error_policy)?;
+        let unknown_ids = check_option_ids(
+            "Disambiguator",
+            &options.ids,
+            &options.ignore_ids,
+            &reports,
+            options.error_policy,
+        )?;
+        let mut report = CompileReport::new(reports, &errors, lints, profiles, regex_sets);
+        report.unknown_ids = unknown_ids;
 
-        Ok(Tokenizer {
-            tagger: build_info.tagger().clone(),
-            chunker,
-            rules,
-            options,
-        })
+        Ok((
+            Tokenizer {
+                format_version: FormatVersion,
+                header: Some(header),
+                tagger: build_info.tagger().clone(),
+                chunker,
+                multiword_tagger,
+                rules,
+                options,
+                parallelism: Default::default(),
+            },
+            errors,
+            report,
+        ))
     }
 }
 
@@ -229,6 +978,184 @@ struct ModelData {
     pmap: DefaultHashMap<String, chunk::Context>,
 }
 
+/// Reads the primitives of a Java `DataInputStream` which OpenNLP uses to write its models.
+struct DataReader<R: std::io::Read> {
+    reader: R,
+}
+
+impl<R: std::io::Read> DataReader<R> {
+    fn read_int(&mut self) -> std::io::Result<i32> {
+        let mut bytes = [0u8; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(i32::from_be_bytes(bytes))
+    }
+
+    fn read_count(&mut self) -> Result<usize, ChunkerLoadError> {
+        let count = self.read_int()?;
+        std::convert::TryFrom::try_from(count)
+            .map_err(|_| ChunkerLoadError::Invalid(format!("negative count: {}", count)))
+    }
+
+    fn read_double(&mut self) -> std::io::Result<f64> {
+        let mut bytes = [0u8; 8];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(f64::from_be_bytes(bytes))
+    }
+
+    /// Reads a string written with `DataOutputStream::writeUTF` i. e. its length in bytes followed by
+    /// the string in modified UTF-8.
+    fn read_utf(&mut self) -> Result<String, ChunkerLoadError> {
+        let mut length = [0u8; 2];
+        self.reader.read_exact(&mut length)?;
+
+        let mut bytes = vec![0u8; u16::from_be_bytes(length) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        decode_modified_utf8(&bytes).ok_or_else(|| {
+            ChunkerLoadError::Invalid(format!("invalid modified UTF-8: {:?}", bytes))
+        })
+    }
+}
+
+/// Decodes the modified UTF-8 of Java. It differs from UTF-8 in that the null character is encoded with two bytes
+/// and characters outside of the basic multilingual plane are encoded as two surrogates with three bytes each.
+/// Each one to three bytes encode one UTF-16 code unit, so the code units are decoded first.
+fn decode_modified_utf8(bytes: &[u8]) -> Option<String> {
+    let continuation = |i: usize| {
+        bytes
+            .get(i)
+            .filter(|x| *x & 0xC0 == 0x80)
+            .map(|x| u16::from(x & 0x3F))
+    };
+
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = u16::from(bytes[i]);
+
+        if byte & 0x80 == 0 {
+            units.push(byte);
+            i += 1;
+        } else if byte & 0xE0 == 0xC0 {
+            units.push((byte & 0x1F) << 6 | continuation(i + 1)?);
+            i += 2;
+        } else if byte & 0xF0 == 0xE0 {
+            units.push((byte & 0x0F) << 12 | continuation(i + 1)? << 6 | continuation(i + 2)?);
+            i += 3;
+        } else {
+            return None;
+        }
+    }
+
+    String::from_utf16(&units).ok()
+}
+
+impl ModelData {
+    /// Reads a model in the binary format of OpenNLP i. e. the `*.model` file in the zip archive of an OpenNLP `.bin` model.
+    /// GIS (maxent) and perceptron models are supported.
+    fn from_opennlp<R: std::io::Read>(reader: R) -> Result<Self, ChunkerLoadError> {
+        let mut reader = DataReader { reader };
+
+        match reader.read_utf()?.as_str() {
+            "GIS" => {
+                // the correction constant and parameter are not used anymore
+                reader.read_int()?;
+                reader.read_double()?;
+            }
+            "Perceptron" => {}
+            name => {
+                return Err(ChunkerLoadError::Invalid(format!(
+                    "unsupported OpenNLP model type: {}",
+                    name
+                )))
+            }
+        }
+
+        let outcome_labels = (0..reader.read_count()?)
+            .map(|_| reader.read_utf())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // each pattern is the number of contexts which use it followed by the indices of their outcomes
+        let mut outcome_patterns = Vec::new();
+        for _ in 0..reader.read_count()? {
+            let pattern = reader
+                .read_utf()?
+                .split(' ')
+                .map(|x| x.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|x| {
+                    ChunkerLoadError::Invalid(format!("invalid outcome pattern: {}", x))
+                })?;
+
+            if pattern.is_empty() {
+                return Err(ChunkerLoadError::Invalid("empty outcome pattern".into()));
+            }
+            outcome_patterns.push(pattern);
+        }
+
+        let pred_labels = (0..reader.read_count()?)
+            .map(|_| reader.read_utf())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut contexts = Vec::with_capacity(pred_labels.len());
+        for pattern in outcome_patterns {
+            for _ in 0..pattern[0] {
+                let parameters = (1..pattern.len())
+                    .map(|_| reader.read_double().map(|x| x as f32))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                contexts.push(chunk::Context {
+                    parameters,
+                    outcomes: pattern[1..].to_vec(),
+                });
+            }
+        }
+
+        if contexts.len() != pred_labels.len() {
+            return Err(ChunkerLoadError::Invalid(format!(
+                "{} predicates but {} contexts",
+                pred_labels.len(),
+                contexts.len()
+            )));
+        }
+
+        Ok(ModelData {
+            outcome_labels,
+            pmap: pred_labels.into_iter().zip(contexts).collect(),
+        })
+    }
+
+    /// Checks that the outcomes referenced by the contexts are in the outcome labels.
+    fn validate(&self, name: &str) -> Result<(), ChunkerLoadError> {
+        for (key, context) in self.pmap.iter() {
+            if context.outcomes.len() != context.parameters.len() {
+                return Err(ChunkerLoadError::Invalid(format!(
+                    "{}: context {:?} has {} outcomes but {} parameters",
+                    name,
+                    key,
+                    context.outcomes.len(),
+                    context.parameters.len()
+                )));
+            }
+
+            if let Some(outcome) = context
+                .outcomes
+                .iter()
+                .find(|x| **x >= self.outcome_labels.len())
+            {
+                return Err(ChunkerLoadError::Invalid(format!(
+                    "{}: context {:?} references outcome {} but there are only {} outcome labels",
+                    name,
+                    key,
+                    outcome,
+                    self.outcome_labels.len()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl From<ModelData> for chunk::Model {
     fn from(data: ModelData) -> Self {
         chunk::Model {
@@ -242,18 +1169,54 @@ impl From<ModelData> for chunk::Model {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct ChunkData {
+    token_model: ModelData,
+    pos_model: ModelData,
+    pos_tagdict: DefaultHashMap<String, Vec<String>>,
+    chunk_model: ModelData,
+}
+
+/// Reads the part-of-speech tag dictionary of OpenNLP i. e. the `tags.tagdict` file of the POS model
+/// where each entry looks like `<entry tags="DT"><token>the</token></entry>`.
+fn read_opennlp_tagdict<R: std::io::Read>(
+    mut reader: R,
+) -> Result<DefaultHashMap<String, Vec<String>>, ChunkerLoadError> {
+    let mut xml = String::new();
+    reader.read_to_string(&mut xml)?;
+
+    let document = roxmltree::Document::parse(&xml)
+        .map_err(|x| ChunkerLoadError::Invalid(format!("invalid tag dictionary: {}", x)))?;
+    let mut tagdict = DefaultHashMap::default();
+
+    for entry in document.descendants().filter(|x| x.has_tag_name("entry")) {
+        let tags = entry
+            .attribute("tags")
+            .ok_or_else(|| ChunkerLoadError::Invalid("tag dictionary entry without tags".into()))?;
+        let token = entry
+            .children()
+            .find(|x| x.has_tag_name("token"))
+            .and_then(|x| x.text())
+            .ok_or_else(|| {
+                ChunkerLoadError::Invalid("tag dictionary entry without token".into())
+            })?;
+
+        tagdict.insert(
+            token.to_string(),
+            tags.split_whitespace().map(|x| x.to_string()).collect(),
+        );
+    }
+
+    Ok(tagdict)
+}
+
 impl chunk::Chunker {
-    pub fn from_json<R: std::io::Read>(reader: R) -> chunk::Chunker {
-        #[derive(Serialize, Deserialize)]
-        struct ChunkData {
-            token_model: ModelData,
-            pos_model: ModelData,
-            pos_tagdict: DefaultHashMap<String, Vec<String>>,
-            chunk_model: ModelData,
-        }
+    fn from_data(chunk_data: ChunkData) -> Result<chunk::Chunker, ChunkerLoadError> {
+        chunk_data.token_model.validate("token_model")?;
+        chunk_data.pos_model.validate("pos_model")?;
+        chunk_data.chunk_model.validate("chunk_model")?;
 
-        let chunk_data: ChunkData = serde_json::from_reader(reader).unwrap();
-        chunk::Chunker {
+        Ok(chunk::Chunker {
             token_model: chunk::MaxentTokenizer {
                 model: chunk_data.token_model.into(),
             },
@@ -264,6 +1227,150 @@ impl chunk::Chunker {
             chunk_model: chunk::MaxentChunker {
                 model: chunk_data.chunk_model.into(),
             },
+            options: chunk::ChunkerOptions::default(),
+        })
+    }
+
+    /// Loads a chunker directly from the OpenNLP models. The readers are the files in the zip archives of
+    /// the OpenNLP `.bin` models i. e. `token.model`, `pos.model`, `chunker.model` and `tags.tagdict` of the POS model.
+    pub fn from_opennlp<R: std::io::Read>(
+        token: R,
+        pos: R,
+        chunk: R,
+        tagdict: R,
+    ) -> Result<chunk::Chunker, ChunkerLoadError> {
+        Self::from_data(ChunkData {
+            token_model: ModelData::from_opennlp(token)?,
+            pos_model: ModelData::from_opennlp(pos)?,
+            pos_tagdict: read_opennlp_tagdict(tagdict)?,
+            chunk_model: ModelData::from_opennlp(chunk)?,
+        })
+    }
+
+    /// Loads a chunker from the JSON export of the OpenNLP models.
+    pub fn from_json<R: std::io::Read>(reader: R) -> Result<chunk::Chunker, ChunkerLoadError> {
+        let chunk_data: ChunkData = serde_json::from_reader(reader).map_err(|x| {
+            use serde_json::error::Category;
+
+            match x.classify() {
+                Category::Io => ChunkerLoadError::Io(x.into()),
+                Category::Syntax | Category::Eof => ChunkerLoadError::Syntax(x),
+                Category::Data => ChunkerLoadError::Invalid(x.to_string()),
+            }
+        })?;
+
+        Self::from_data(chunk_data)
+    }
+}
+
+impl multiword::MultiwordTagger {
+    /// Reads the tags of a multiwords file in LanguageTool format i. e. one expression per line
+    /// followed by a tab and the phrase tag. Lines starting with '#' are comments.
+    pub fn tags_from_dump<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Vec<String>> {
+        Ok(Self::read_dump(path)?
+            .into_iter()
+            .map(|(_, tag)| tag)
+            .collect())
+    }
+
+    fn read_dump<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+
+        for line in std::fs::read_to_string(path)?.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+
+            let mut parts = line.split('\t');
+            if let (Some(phrase), Some(tag)) = (parts.next(), parts.next()) {
+                entries.push((phrase.trim().to_string(), tag.trim().to_string()));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Creates a multiword tagger from a multiwords file. The tags must be known to the tagger
+    /// so they should be added with [tags_from_dump][Self::tags_from_dump] as extra tags first.
+    pub fn from_dump<P: AsRef<std::path::Path>>(
+        path: P,
+        info: &BuildInfo,
+    ) -> std::io::Result<Self> {
+        let mut multiwords: DefaultHashMap<String, Vec<multiword::Multiword>> =
+            DefaultHashMap::default();
+
+        for (phrase, tag) in Self::read_dump(path)? {
+            let words: Vec<String> = phrase.split_whitespace().map(|x| x.to_string()).collect();
+            if words.is_empty() {
+                continue;
+            }
+
+            multiwords
+                .entry(words[0].clone())
+                .or_default()
+                .push(multiword::Multiword {
+                    phrase: words.join(" "),
+                    words,
+                    tag: info.tagger().id_tag(&tag).to_owned_id(),
+                });
+        }
+
+        for candidates in multiwords.values_mut() {
+            candidates.sort_by_key(|x| std::cmp::Reverse(x.words.len()));
+        }
+
+        Ok(multiword::MultiwordTagger { multiwords })
+    }
+}
+
+impl IgnoreWords {
+    /// Reads word lists in the format of the `ignore.txt` and `spelling.txt` files of LanguageTool
+    /// i. e. one word per line, see [IgnoreWords::parse_line].
+    pub fn from_dumps<P: AsRef<std::path::Path>>(paths: &[P]) -> std::io::Result<Self> {
+        let mut ignore_words = IgnoreWords::default();
+
+        for path in paths {
+            for line in std::fs::read_to_string(path)?.lines() {
+                if let Some((category_id, word)) = IgnoreWords::parse_line(line) {
+                    ignore_words.insert(word, category_id);
+                }
+            }
+        }
+
+        Ok(ignore_words)
+    }
+
+    /// Parses one line of a word list into the category ID, if any, and the word.
+    /// Lines starting with '#' are comments, Hunspell flags after a '/' are removed.
+    /// A word can be prefixed with the ID of a category and ':' e. g. `TYPOS:nlprule`
+    /// to only ignore it in the rules of that category. Category IDs start with an uppercase ASCII letter
+    /// and consist of uppercase ASCII letters, digits and '_', so other text before a ':' (e. g. in `3:30`)
+    /// is part of the word.
+    fn parse_line(line: &str) -> Option<(Option<&str>, &str)> {
+        let line = line.trim();
+        if line.starts_with('#') || line.is_empty() {
+            return None;
+        }
+
+        let (category_id, word) = match line.split_once(':') {
+            Some((category_id, word))
+                if !word.is_empty()
+                    && category_id.starts_with(|c: char| c.is_ascii_uppercase())
+                    && category_id
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') =>
+            {
+                (Some(category_id), word)
+            }
+            _ => (None, line),
+        };
+        let word = word.split('/').next().unwrap_or(word).trim();
+
+        if word.is_empty() {
+            None
+        } else {
+            Some((category_id, word))
         }
     }
 }
@@ -278,11 +1385,14 @@ mod composition {
     use super::*;
     use crate::{
         rule::engine::composition::{
-            AndAtom, Atom, Composition, FalseAtom, NotAtom, OffsetAtom, OrAtom, Part, Quantifier,
-            TrueAtom,
+            concrete::CaseAtom, AndAtom, Atom, Composition, FalseAtom, Greediness, NotAtom,
+            OffsetAtom, OrAtom, Part, Quantifier, TokenCase, TrueAtom, Unification,
+            DEFAULT_MATCH_BUDGET,
         },
         utils::regex::SerializeRegex,
     };
+    use once_cell::sync::OnceCell;
+    use std::sync::atomic::AtomicUsize;
 
     impl Matcher {
         pub fn new_regex(regex: SerializeRegex, negate: bool, empty_always_false: bool) -> Self {
@@ -290,7 +1400,9 @@ mod composition {
                 matcher: either::Right(regex),
                 negate,
                 case_sensitive: true, // handled by regex
+                case_mapping: CaseMapping::Default,
                 empty_always_false,
+                lower: LowerText::default(),
             }
         }
 
@@ -304,56 +1416,111 @@ mod composition {
                 matcher: either::Left(string_or_idx),
                 negate,
                 case_sensitive,
+                case_mapping: CaseMapping::Default,
                 empty_always_false,
+                lower: LowerText::default(),
             }
         }
 
+        /// Sets how the case of strings is compared if they are not case-sensitive.
+        pub fn with_case_mapping(mut self, case_mapping: CaseMapping) -> Self {
+            self.case_mapping = case_mapping;
+            self
+        }
+
         pub fn needs_graph(&self) -> bool {
             matches!(&self.matcher, either::Left(either::Right(_)))
         }
     }
 
     impl Quantifier {
-        pub fn new(min: usize, max: usize) -> Self {
-            assert!(max >= min);
-            Quantifier { min, max }
+        /// A greedy quantifier taking `min` to `max` tokens. Fails if `min` is larger than `max` or `max` is
+        /// larger than [MAX_QUANTIFIER].
+        pub fn try_new(min: usize, max: usize) -> Result<Self, Error> {
+            if min > max {
+                return Err(Error::unexpected(format!(
+                    "the minimum {} is larger than the maximum {}",
+                    min, max
+                )));
+            }
+            if max > MAX_QUANTIFIER {
+                return Err(Error::unexpected(format!(
+                    "the maximum {} is larger than {}",
+                    max, MAX_QUANTIFIER
+                )));
+            }
+
+            Ok(Quantifier {
+                min,
+                max,
+                greediness: Greediness::Lazy,
+            })
+        }
+
+        pub fn with_greediness(mut self, greediness: Greediness) -> Self {
+            self.greediness = greediness;
+            self
         }
     }
 
     impl AndAtom {
         pub fn and(atoms: Vec<Atom>) -> Atom {
-            let mut atoms: Vec<_> = atoms
-                .into_iter()
-                .filter(|x| !matches!(x, Atom::TrueAtom { .. }))
-                .collect();
+            let mut flat = Vec::new();
+
+            for atom in atoms {
+                match atom {
+                    Atom::TrueAtom { .. } => {}
+                    Atom::FalseAtom { .. } => return FalseAtom::default().into(),
+                    Atom::AndAtom(x) => flat.extend(x.atoms),
+                    x => flat.push(x),
+                }
+            }
 
-            if atoms.is_empty() {
+            if flat.is_empty() {
                 (TrueAtom {}).into()
-            } else if atoms.len() == 1 {
-                atoms.remove(0)
+            } else if flat.len() == 1 {
+                flat.remove(0)
             } else {
-                (AndAtom { atoms }).into()
+                (AndAtom { atoms: flat }).into()
             }
         }
     }
 
     impl OrAtom {
         pub fn or(atoms: Vec<Atom>) -> Atom {
-            let mut atoms: Vec<_> = atoms
-                .into_iter()
-                .filter(|x| !matches!(x, Atom::FalseAtom { .. }))
-                .collect();
+            let mut flat = Vec::new();
+
+            for atom in atoms {
+                match atom {
+                    Atom::FalseAtom { .. } => {}
+                    Atom::TrueAtom { .. } => return TrueAtom::default().into(),
+                    Atom::OrAtom(x) => flat.extend(x.atoms),
+                    x => flat.push(x),
+                }
+            }
 
-            if atoms.is_empty() {
+            if flat.is_empty() {
                 (FalseAtom {}).into()
-            } else if atoms.len() == 1 {
-                atoms.remove(0)
+            } else if flat.len() == 1 {
+                flat.remove(0)
             } else {
-                (OrAtom { atoms }).into()
+                (OrAtom {
+                    atoms: flat,
+                    batch: OnceCell::new(),
+                    fallbacks: None,
+                })
+                .into()
             }
         }
     }
 
+    impl CaseAtom {
+        /// Matches tokens with the case, or tokens without it if `negate` is true.
+        pub fn case(case: TokenCase, negate: bool) -> Atom {
+            (CaseAtom { case, negate }).into()
+        }
+    }
+
     impl NotAtom {
         pub fn not(atom: Atom) -> Atom {
             match atom {
@@ -365,16 +1532,38 @@ mod composition {
     }
 
     impl OffsetAtom {
-        pub fn new(atom: Atom, offset: isize) -> Self {
+        pub fn new(atom: Atom, offset: isize, out_of_bounds: bool) -> Self {
             OffsetAtom {
                 atom: Box::new(atom),
                 offset,
+                out_of_bounds,
             }
         }
     }
 
     impl Composition {
-        pub fn new(parts: Vec<Part>) -> Self {
+        /// Fails if there are no parts, a quantifier is invalid (see [Quantifier::try_new]) or a unification
+        /// refers to a part which does not exist.
+        pub fn try_new(parts: Vec<Part>, unifications: Vec<Unification>) -> Result<Self, Error> {
+            if parts.is_empty() {
+                return Err(Error::unexpected("a pattern must have at least one token"));
+            }
+            for part in &parts {
+                let Quantifier { min, max, .. } = part.quantifier;
+                Quantifier::try_new(min, max)?;
+            }
+            if let Some(idx) = unifications
+                .iter()
+                .flat_map(|x| x.parts.iter())
+                .find(|idx| **idx >= parts.len())
+            {
+                return Err(Error::unexpected(format!(
+                    "a unification refers to token {} of a pattern with {} tokens",
+                    idx,
+                    parts.len()
+                )));
+            }
+
             let mut group_ids_to_idx = DefaultHashMap::default();
             group_ids_to_idx.insert(0, 0);
             let mut current_id = 1;
@@ -390,11 +1579,71 @@ mod composition {
                 .map(|i| parts[i..].iter().all(|x| x.quantifier.min == 0))
                 .collect();
 
-            Composition {
+            Ok(Composition {
                 parts,
                 group_ids_to_idx,
                 can_stop_mask,
-            }
+                unifications,
+                budget: DEFAULT_MATCH_BUDGET,
+                overflows: AtomicUsize::new(0),
+            })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_word_lines_are_parsed() {
+        let parse = IgnoreWords::parse_line;
+
+        assert_eq!(parse("nlprule"), Some((None, "nlprule")));
+        assert_eq!(parse("  nlprule \t"), Some((None, "nlprule")));
+        assert_eq!(parse("# a comment"), None);
+        assert_eq!(parse("   "), None);
+        // Hunspell flags
+        assert_eq!(parse("walk/SM"), Some((None, "walk")));
+        assert_eq!(parse("/SM"), None);
+        // category prefixes
+        assert_eq!(parse("TYPOS:nlprule"), Some((Some("TYPOS"), "nlprule")));
+        assert_eq!(
+            parse("EN_TYPOS2:walk/SM"),
+            Some((Some("EN_TYPOS2"), "walk"))
+        );
+        assert_eq!(parse("TYPOS:a:b"), Some((Some("TYPOS"), "a:b")));
+        // text before a ':' which is not a category ID is part of the word
+        assert_eq!(parse("3:30"), Some((None, "3:30")));
+        assert_eq!(parse("_X:y"), Some((None, "_X:y")));
+        assert_eq!(parse("Re:"), Some((None, "Re:")));
+        assert_eq!(parse("TV:"), Some((None, "TV:")));
+        assert_eq!(parse("typos:nlprule"), Some((None, "typos:nlprule")));
+        assert_eq!(parse(":nlprule"), Some((None, ":nlprule")));
+        // limits of the heuristic: an uppercase word before a ':' is always read as a category ID
+        assert_eq!(parse("HH:MM"), Some((Some("HH"), "MM")));
+        // and a '/' always starts Hunspell flags
+        assert_eq!(parse("km/h"), Some((None, "km")));
+    }
+
+    #[test]
+    fn modified_utf8_is_decoded() {
+        assert_eq!(decode_modified_utf8(b"B-NP").as_deref(), Some("B-NP"));
+        assert_eq!(
+            decode_modified_utf8("Straße".as_bytes()).as_deref(),
+            Some("Straße")
+        );
+        // the null character and a character outside of the basic multilingual plane
+        assert_eq!(
+            decode_modified_utf8(&[0x61, 0xC0, 0x80, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80])
+                .as_deref(),
+            Some("a\0\u{1F600}")
+        );
+
+        // plain UTF-8 of a character outside of the basic multilingual plane is not modified UTF-8
+        assert_eq!(decode_modified_utf8("\u{1F600}".as_bytes()), None);
+        // a lone surrogate and a truncated sequence
+        assert_eq!(decode_modified_utf8(&[0xED, 0xA0, 0xBD]), None);
+        assert_eq!(decode_modified_utf8(&[0xC3]), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Builds the [RuleIndex] of a rule set from the first part of each rule.
+
+use super::BuildInfo;
+use crate::{
+    rule::{
+        engine::{
+            composition::{
+                concrete::{TextAtom, WordDataAtom},
+                Atom, Composition,
+            },
+            Engine,
+        },
+        Rule,
+    },
+    rules::RuleIndex,
+    types::*,
+};
+
+/// POS matchers which match more than this fraction of all tags are not used as anchor since
+/// they would list the rule for most tokens.
+const MAX_ANCHOR_TAG_FRACTION: f32 = 0.25;
+
+/// The word and tag IDs one of which a token must have to match an atom. If `unknown_words` is set, tokens
+/// without word ID can match too.
+#[derive(Default)]
+struct Anchor {
+    words: DefaultHashSet<u32>,
+    tags: Vec<u16>,
+    unknown_words: bool,
+}
+
+impl Anchor {
+    fn union(mut self, other: Anchor) -> Anchor {
+        self.words.extend(other.words);
+        self.tags.extend(other.tags);
+        self.unknown_words |= other.unknown_words;
+        self
+    }
+}
+
+fn atom_anchor(atom: &Atom, info: &BuildInfo) -> Option<Anchor> {
+    match atom {
+        Atom::TextAtom(TextAtom { matcher }) => match &matcher.matcher.matcher {
+            either::Left(either::Left(string)) if !matcher.matcher.negate => Some(Anchor {
+                words: info.word_ids(string, matcher.matcher.case_sensitive),
+                unknown_words: true,
+                ..Default::default()
+            }),
+            _ => None,
+        },
+        // every match has a reading with one of the tags, regardless of the lemma
+        Atom::WordDataAtom(WordDataAtom { matcher, .. }) => {
+            let mask = &matcher.pos_matcher.as_ref()?.mask;
+            let tags: Vec<_> = mask.iter().map(|i| i as u16).collect();
+
+            if tags.len() as f32 > mask.capacity() as f32 * MAX_ANCHOR_TAG_FRACTION {
+                None
+            } else {
+                Some(Anchor {
+                    tags,
+                    ..Default::default()
+                })
+            }
+        }
+        // all of the atoms must match, so the anchor of any of them can be used
+        Atom::AndAtom(atom) => {
+            let mut anchors: Vec<_> = atom
+                .atoms
+                .iter()
+                .filter_map(|x| atom_anchor(x, info))
+                .collect();
+            // words are preferred since they are more specific than tags
+            let best = anchors.iter().position(|x| x.unknown_words).unwrap_or(0);
+
+            if anchors.is_empty() {
+                None
+            } else {
+                Some(anchors.swap_remove(best))
+            }
+        }
+        Atom::OrAtom(atom) if !atom.atoms.is_empty() => atom
+            .atoms
+            .iter()
+            .map(|x| atom_anchor(x, info))
+            .collect::<Option<Vec<_>>>()
+            .map(|x| x.into_iter().fold(Anchor::default(), Anchor::union)),
+        _ => None,
+    }
+}
+
+/// The anchor of the first part if it is required i. e. if the token at the start of every match matches it.
+fn composition_anchor(composition: &Composition, info: &BuildInfo) -> Option<Anchor> {
+    let first = composition.parts.first()?;
+
+    if first.quantifier.min > 0 {
+        atom_anchor(&first.atom, info)
+    } else {
+        None
+    }
+}
+
+impl RuleIndex {
+    pub(crate) fn new(rules: &[Rule], info: &BuildInfo) -> Self {
+        let mut index = RuleIndex::default();
+
+        for (i, rule) in rules.iter().enumerate() {
+            let anchor = match &rule.engine {
+                Engine::Token(engine) => composition_anchor(&engine.composition, info),
+                Engine::Text(..) => None,
+            };
+
+            index.anchored.push(anchor.is_some());
+            if let Some(anchor) = anchor {
+                for id in anchor.words {
+                    index.words.entry(id).or_default().push(i);
+                }
+                for id in anchor.tags {
+                    let rules = index.tags.entry(id).or_default();
+                    if rules.last() != Some(&i) {
+                        rules.push(i);
+                    }
+                }
+                if anchor.unknown_words {
+                    index.unknown_words.push(i);
+                }
+            }
+        }
+
+        index
+    }
+}
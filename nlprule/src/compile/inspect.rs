@@ -0,0 +1,409 @@
+//! Renders compiled rules as JSON to inspect what was built, see [Rules::to_json_pretty].
+//!
+//! The JSON is a view for reading, built from the compiled rules instead of their serialized form: it does not
+//! change with the binary format and leaves out what only speeds up matching.
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    filter::Filter,
+    rule::{
+        engine::{
+            composition::{
+                Atom, Composition, Matcher, Part, TextMatcher, Unification, WordDataMatcher,
+            },
+            Engine,
+        },
+        grammar::{Match, Synthesizer, SynthesizerPart},
+        Rule,
+    },
+    tokenizer::tag::Tagger,
+    utils::bitset::BitSet,
+    Rules,
+};
+
+impl Rules {
+    /// Renders the compiled rules as pretty-printed JSON e. g. to check the parts, matchers and suggesters a rule
+    /// was compiled to. Regexes are shown as their pattern, the order of sets is stable.
+    /// Words and part-of-speech tags are shown as their IDs, see [Rules::to_json_pretty_with] to resolve them.
+    ///
+    /// This is a diagnostic format, it can not be read back into rules.
+    pub fn to_json_pretty(&self) -> String {
+        self.render_json(None)
+    }
+
+    /// Renders the compiled rules like [Rules::to_json_pretty] with the words and part-of-speech tags resolved
+    /// by the `tagger` the rules were compiled with.
+    pub fn to_json_pretty_with(&self, tagger: &Tagger) -> String {
+        self.render_json(Some(tagger))
+    }
+
+    fn render_json(&self, tagger: Option<&Tagger>) -> String {
+        let view = View { tagger };
+        let n_rules = self.rules.len();
+
+        // the prefilter and the index are stored by literal and by ID, they are shown for each rule
+        let mut literals = vec![Vec::new(); n_rules];
+        for (literal, rules) in self
+            .prefilter
+            .literals
+            .iter()
+            .zip(&self.prefilter.literal_rules)
+        {
+            for i in rules {
+                literals[*i].push(literal.as_str());
+            }
+        }
+        let mut anchor_words = vec![Vec::new(); n_rules];
+        for (id, rules) in &self.index.words {
+            for i in rules {
+                anchor_words[*i].push(*id as u64);
+            }
+        }
+        let mut anchor_tags = vec![Vec::new(); n_rules];
+        for (id, rules) in &self.index.tags {
+            for i in rules {
+                anchor_tags[*i].push(*id as u64);
+            }
+        }
+
+        let rules: Vec<_> = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let mut object = view.rule(rule);
+
+                let filtered = self.prefilter.filtered.get(i).copied().unwrap_or(false);
+                literals[i].sort_unstable();
+                object.insert(
+                    "prefilter".into(),
+                    if filtered {
+                        json!(literals[i])
+                    } else {
+                        Value::Null
+                    },
+                );
+
+                let anchored = self.index.anchored.get(i).copied().unwrap_or(false);
+                object.insert(
+                    "anchor".into(),
+                    if anchored {
+                        json!({
+                            "words": view.ids(std::mem::take(&mut anchor_words[i]), word_name),
+                            "tags": view.ids(std::mem::take(&mut anchor_tags[i]), tag_name),
+                        })
+                    } else {
+                        Value::Null
+                    },
+                );
+
+                Value::Object(object)
+            })
+            .collect();
+
+        let sorted = |words: &mut dyn Iterator<Item = &String>| {
+            let mut words: Vec<_> = words.collect();
+            words.sort_unstable();
+            json!(words)
+        };
+        let category_words: Map<_, _> = self
+            .ignore_words
+            .category_words
+            .iter()
+            .map(|(id, words)| (id.clone(), sorted(&mut words.iter())))
+            .collect();
+
+        let value = json!({
+            "header": self.header,
+            "match_budget": self.match_budget,
+            "ignore_words": {
+                "words": sorted(&mut self.ignore_words.words.iter()),
+                "category_words": category_words,
+            },
+            "rules": rules,
+        });
+
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+}
+
+fn word_name(tagger: &Tagger, id: u64) -> String {
+    tagger
+        .words()
+        .get_word(id as u32)
+        .unwrap_or_else(|| format!("<unknown word {}>", id))
+}
+
+fn tag_name(tagger: &Tagger, id: u64) -> String {
+    tagger
+        .tag_store()
+        .get_by_right(&(id as u16))
+        .cloned()
+        .unwrap_or_else(|| format!("<unknown tag {}>", id))
+}
+
+/// Renders the parts of the rules, resolving word and tag IDs if there is a tagger.
+struct View<'a> {
+    tagger: Option<&'a Tagger>,
+}
+
+impl View<'_> {
+    /// The IDs as sorted names resolved by `name` if there is a tagger, otherwise as sorted numbers.
+    fn ids(&self, mut ids: Vec<u64>, name: fn(&Tagger, u64) -> String) -> Value {
+        match self.tagger {
+            Some(tagger) => {
+                let mut names: Vec<_> = ids.into_iter().map(|x| name(tagger, x)).collect();
+                names.sort_unstable();
+                json!(names)
+            }
+            None => {
+                ids.sort_unstable();
+                json!(ids)
+            }
+        }
+    }
+
+    fn tags(&self, mask: &BitSet) -> Value {
+        self.ids(mask.iter().map(|x| x as u64).collect(), tag_name)
+    }
+
+    fn rule(&self, rule: &Rule) -> Map<String, Value> {
+        let value = json!({
+            "id": rule.id,
+            "name": rule.name,
+            "category": {
+                "id": rule.category_id,
+                "name": rule.category_name,
+                "type": rule.category_type,
+            },
+            "on": rule.on,
+            "default_state": rule.default_state,
+            "url": rule.url,
+            "short": rule.short,
+            "engine": self.engine(&rule.engine),
+            "start": rule.start,
+            "end": rule.end,
+            "message": self.synthesizer(&rule.message),
+            "suggesters": rule.suggesters.iter().map(|x| self.synthesizer(x)).collect::<Vec<_>>(),
+            "filter": rule.filter.as_ref().map(Filter::inspect),
+            "examples": rule
+                .examples
+                .iter()
+                .map(|x| json!({ "text": x.text, "suggestion": x.suggestion }))
+                .collect::<Vec<_>>(),
+        });
+
+        match value {
+            Value::Object(object) => object,
+            _ => unreachable!(),
+        }
+    }
+
+    fn engine(&self, engine: &Engine) -> Value {
+        match engine {
+            Engine::Token(engine) => json!({
+                "type": "token",
+                "composition": self.composition(&engine.composition),
+                "antipatterns": engine
+                    .antipatterns
+                    .iter()
+                    .map(|x| self.composition(x))
+                    .collect::<Vec<_>>(),
+            }),
+            Engine::Text(regex, group_ids_to_idx) => json!({
+                "type": "text",
+                "regex": regex.regex_str(),
+                "groups": groups(group_ids_to_idx.iter()),
+            }),
+        }
+    }
+
+    fn composition(&self, composition: &Composition) -> Value {
+        json!({
+            "parts": composition.parts.iter().map(|x| self.part(x)).collect::<Vec<_>>(),
+            "groups": groups(composition.group_ids_to_idx.iter()),
+            "unifications": composition
+                .unifications
+                .iter()
+                .map(|x| self.unification(x))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    fn part(&self, part: &Part) -> Value {
+        json!({
+            "atom": self.atom(&part.atom),
+            "quantifier": {
+                "min": part.quantifier.min,
+                "max": part.quantifier.max,
+                "greediness": part.quantifier.greediness,
+            },
+            "visible": part.visible,
+        })
+    }
+
+    fn unification(&self, unification: &Unification) -> Value {
+        let features: Vec<Vec<_>> = unification
+            .features
+            .iter()
+            .map(|values| values.iter().map(|x| self.tags(&x.matcher.mask)).collect())
+            .collect();
+
+        json!({
+            "features": features,
+            "parts": unification.parts,
+            "negate": unification.negate,
+        })
+    }
+
+    fn atom(&self, atom: &Atom) -> Value {
+        let atoms = |atoms: &[Atom]| atoms.iter().map(|x| self.atom(x)).collect::<Vec<_>>();
+
+        match atom {
+            Atom::ChunkAtom(atom) => json!({ "type": "chunk", "matcher": matcher(&atom.matcher) }),
+            Atom::SpaceBeforeAtom(atom) => json!({ "type": "space_before", "value": atom.value }),
+            Atom::TextAtom(atom) => {
+                json!({ "type": "text", "matcher": self.text_matcher(&atom.matcher) })
+            }
+            Atom::WordDataAtom(atom) => json!({
+                "type": "word_data",
+                "matcher": self.word_data_matcher(&atom.matcher),
+                "case_sensitive": atom.case_sensitive,
+            }),
+            Atom::TrueAtom(_) => json!({ "type": "true" }),
+            Atom::FalseAtom(_) => json!({ "type": "false" }),
+            Atom::AndAtom(atom) => json!({ "type": "and", "atoms": atoms(&atom.atoms) }),
+            Atom::OrAtom(atom) => json!({ "type": "or", "atoms": atoms(&atom.atoms) }),
+            Atom::NotAtom(atom) => json!({ "type": "not", "atom": self.atom(&atom.atom) }),
+            Atom::OffsetAtom(atom) => json!({
+                "type": "offset",
+                "offset": atom.offset,
+                "out_of_bounds": atom.out_of_bounds,
+                "atom": self.atom(&atom.atom),
+            }),
+            Atom::CaseAtom(atom) => json!({
+                "type": "case",
+                "case": atom.case,
+                "negate": atom.negate,
+            }),
+        }
+    }
+
+    /// The matcher and, if the words it matches are stored, the words.
+    fn text_matcher(&self, text_matcher: &TextMatcher) -> Value {
+        let mut object = matcher(&text_matcher.matcher);
+        if let Some(set) = &text_matcher.set {
+            object.insert(
+                "words".into(),
+                self.ids(set.iter().map(|x| *x as u64).collect(), word_name),
+            );
+        }
+        Value::Object(object)
+    }
+
+    fn word_data_matcher(&self, matcher: &WordDataMatcher) -> Value {
+        json!({
+            "tags": matcher.pos_matcher.as_ref().map(|x| self.tags(&x.mask)),
+            "lemma": matcher.inflect_matcher.as_ref().map(|x| self.text_matcher(x)),
+        })
+    }
+
+    fn synthesizer(&self, synthesizer: &Synthesizer) -> Value {
+        let parts: Vec<_> = synthesizer
+            .parts
+            .iter()
+            .map(|part| match part {
+                SynthesizerPart::Text(text) => json!({ "text": text }),
+                SynthesizerPart::Match(x) => json!({ "match": self.synthesizer_match(x) }),
+            })
+            .collect();
+
+        json!({
+            "parts": parts,
+            "use_titlecase_adjust": synthesizer.use_titlecase_adjust,
+        })
+    }
+
+    fn synthesizer_match(&self, x: &Match) -> Value {
+        json!({
+            "group": x.id,
+            "conversion": x.conversion,
+            "case_mapping": x.case_mapping,
+            "include_skipped": x.include_skipped,
+            "tags": x.pos_replacer.as_ref().map(|x| self.tags(&x.matcher.mask)),
+            "regex_replacer": x.regex_replacer.as_ref().map(|(regex, replacement)| json!({
+                "regex": regex.regex_str(),
+                "replacement": replacement,
+            })),
+        })
+    }
+}
+
+/// The string, group reference or regex a matcher compares with and its options.
+fn matcher(matcher: &Matcher) -> Map<String, Value> {
+    let mut object = Map::new();
+    let (key, value) = match &matcher.matcher {
+        either::Left(either::Left(string)) => ("string", json!(string)),
+        either::Left(either::Right(id)) => ("group", json!(id)),
+        either::Right(regex) => ("regex", json!(regex.regex_str())),
+    };
+    object.insert(key.into(), value);
+    object.insert("negate".into(), json!(matcher.negate));
+    object.insert("case_sensitive".into(), json!(matcher.case_sensitive));
+    object.insert("case_mapping".into(), json!(matcher.case_mapping));
+    object.insert(
+        "empty_always_false".into(),
+        json!(matcher.empty_always_false),
+    );
+    object
+}
+
+/// The index of the group of each group ID, see [Composition].
+fn groups<'a>(group_ids_to_idx: impl Iterator<Item = (&'a usize, &'a usize)>) -> Value {
+    let groups: Map<_, _> = group_ids_to_idx
+        .map(|(id, idx)| (id.to_string(), json!(idx)))
+        .collect();
+    Value::Object(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::testing::tiny_rules;
+
+    #[test]
+    fn rules_can_be_rendered_as_json() {
+        let (tagger, rules) = tiny_rules();
+        let rendered = rules.to_json_pretty_with(&tagger);
+
+        // the header contains the version of nlprule, only the rules are compared
+        let json: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(
+            serde_json::to_string_pretty(&json["rules"]).unwrap() + "\n",
+            include_str!("fixtures/tiny_rules.json"),
+            "the JSON of compiled rules changed, run the `write_fixtures` test if that is intended"
+        );
+        assert_eq!(tiny_rules().1.to_json_pretty_with(&tagger), rendered);
+
+        // the view does not show how the types are serialized
+        for internal in ["Left", "Right", "can_stop_mask", "backend", "blocks"] {
+            assert!(!rendered.contains(internal), "{}", internal);
+        }
+
+        // without tagger the IDs are shown
+        let json: serde_json::Value = serde_json::from_str(&rules.to_json_pretty()).unwrap();
+        let ids = |word: &str| tagger.words().get_id(word).unwrap();
+        let mut expected = vec![ids("they"), ids("we")];
+        expected.sort_unstable();
+        let parts = &json["rules"][0]["engine"]["composition"]["parts"];
+        assert_eq!(
+            parts[1]["atom"]["matcher"]["words"],
+            serde_json::json!(expected)
+        );
+        let tag_id = *tagger.tag_store().get_by_left("VB").unwrap();
+        assert_eq!(
+            parts[2]["atom"]["matcher"]["tags"],
+            serde_json::json!([tag_id])
+        );
+    }
+}
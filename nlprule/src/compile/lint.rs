@@ -0,0 +1,605 @@
+//! Finds parts of compiled rules which can never match and rules which are shadowed by another rule,
+//! see [CompileOptions::lint][super::CompileOptions::lint].
+
+use regex_syntax::hir::{Class, Hir, HirKind};
+use unicase::UniCase;
+
+use crate::rule::{
+    engine::{
+        composition::{concrete, Atom, Composition, Matcher, PosMatcher, TextMatcher},
+        Engine,
+    },
+    Rule,
+};
+
+use super::{Lint, LintKind};
+
+/// Whether a regex can match text which is not in the word store e. g. numbers or arbitrarily long words.
+/// The regex cache only knows the words in the store so such a regex is never reported.
+///
+/// A regex is open-ended if it repeats something without an upper bound or contains a class which
+/// stands for arbitrary text (digits, `.`, `\w`, negated classes). Bounded quantifiers over known
+/// characters like `colou?r` or `x{1,3}` are not. Regexes the parser does not understand are treated as
+/// open-ended so they are never reported.
+fn is_open_ended(regex: &str) -> bool {
+    regex_syntax::Parser::new()
+        .parse(regex)
+        .map_or(true, |hir| hir_is_open_ended(&hir))
+}
+
+fn hir_is_open_ended(hir: &Hir) -> bool {
+    /// Classes with more characters than this are taken to stand for arbitrary text.
+    const MAX_CLASS_LEN: u32 = 256;
+
+    match hir.kind() {
+        HirKind::Repetition(repetition) => {
+            repetition.max.is_none() || hir_is_open_ended(&repetition.sub)
+        }
+        HirKind::Class(Class::Unicode(class)) => {
+            class
+                .ranges()
+                .iter()
+                .map(|x| x.end() as u32 - x.start() as u32 + 1)
+                .sum::<u32>()
+                > MAX_CLASS_LEN
+                || class
+                    .ranges()
+                    .iter()
+                    .any(|x| x.start() <= '9' && x.end() >= '0')
+        }
+        HirKind::Class(Class::Bytes(class)) => class
+            .ranges()
+            .iter()
+            .any(|x| x.start() <= b'9' && x.end() >= b'0' || x.end() - x.start() > 127),
+        HirKind::Capture(capture) => hir_is_open_ended(&capture.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(hir_is_open_ended),
+        HirKind::Empty | HirKind::Literal(_) | HirKind::Look(_) => false,
+    }
+}
+
+fn pos_never_matches(matcher: &PosMatcher) -> bool {
+    matcher.mask.is_empty()
+}
+
+/// Whether the regex of the matcher is known to match none of the words in the word store.
+fn text_never_matches(matcher: &TextMatcher) -> Option<&str> {
+    match (&matcher.matcher.matcher, &matcher.set) {
+        (either::Right(regex), Some(set))
+            if set.is_empty() && !matcher.matcher.negate && !is_open_ended(regex.regex_str()) =>
+        {
+            Some(regex.regex_str())
+        }
+        _ => None,
+    }
+}
+
+struct Linter<'a> {
+    rule_id: &'a str,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn push(&mut self, kind: LintKind, location: &str, message: String) {
+        self.lints.push(Lint {
+            rule_id: self.rule_id.to_string(),
+            kind,
+            message: format!("{}: {}", location, message),
+        });
+    }
+
+    fn atom(&mut self, atom: &Atom, location: &str) {
+        match atom {
+            Atom::TextAtom(concrete::TextAtom { matcher }) => self.text(matcher, location),
+            Atom::WordDataAtom(atom) => {
+                if let Some(matcher) = &atom.matcher.pos_matcher {
+                    if pos_never_matches(matcher) {
+                        self.push(
+                            LintKind::PosNeverMatches,
+                            location,
+                            "the POS tag matches none of the known tags".into(),
+                        );
+                    }
+                }
+                if let Some(matcher) = &atom.matcher.inflect_matcher {
+                    self.text(matcher, location);
+                }
+            }
+            Atom::AndAtom(atom) => atom.atoms.iter().for_each(|x| self.atom(x, location)),
+            Atom::OrAtom(atom) => atom.atoms.iter().for_each(|x| self.atom(x, location)),
+            Atom::NotAtom(atom) => self.atom(&atom.atom, location),
+            Atom::OffsetAtom(atom) => self.atom(&atom.atom, location),
+            Atom::ChunkAtom(_)
+            | Atom::CaseAtom(_)
+            | Atom::SpaceBeforeAtom(_)
+            | Atom::TrueAtom(_)
+            | Atom::FalseAtom(_) => {}
+        }
+    }
+
+    fn text(&mut self, matcher: &TextMatcher, location: &str) {
+        if let Some(regex) = text_never_matches(matcher) {
+            self.push(
+                LintKind::TextNeverMatches,
+                location,
+                format!("the regex `{}` matches none of the known words", regex),
+            );
+        }
+    }
+
+    fn composition(&mut self, composition: &Composition, name: &str) {
+        if composition.can_stop_mask.first().copied().unwrap_or(true) {
+            self.push(
+                LintKind::EmptyMatch,
+                name,
+                "all tokens are optional so the match can be empty".into(),
+            );
+        }
+
+        for (i, part) in composition.parts.iter().enumerate() {
+            let location = format!("{} part {}", name, i);
+
+            // skipping zero tokens after a token is harmless, only visible tokens are reported
+            if part.visible && part.quantifier.max == 0 {
+                self.push(
+                    LintKind::ZeroQuantifier,
+                    &location,
+                    "the part can occur at most zero times".into(),
+                );
+            }
+            self.atom(&part.atom, &location);
+        }
+    }
+}
+
+/// Checks the pattern and antipatterns of a rule for parts which can never match.
+/// Text-level (regex) rules are not checked.
+pub(crate) fn lint_engine(engine: &Engine, rule_id: &str) -> Vec<Lint> {
+    let mut linter = Linter {
+        rule_id,
+        lints: Vec::new(),
+    };
+
+    if let Engine::Token(engine) = engine {
+        linter.composition(&engine.composition, "pattern");
+        for (i, antipattern) in engine.antipatterns.iter().enumerate() {
+            linter.composition(antipattern, &format!("antipattern {}", i));
+        }
+    }
+
+    linter.lints
+}
+
+/// The words a matcher accepts if it only accepts a fixed set of words i. e. it is a string or a regex
+/// which is an alternation of plain words.
+struct Literals<'a> {
+    words: Vec<&'a str>,
+    case_sensitive: bool,
+}
+
+impl<'a> Literals<'a> {
+    /// `case_sensitive` overrides the case sensitivity of strings, as for [Matcher::is_match].
+    fn new(matcher: &'a Matcher, case_sensitive: Option<bool>) -> Option<Self> {
+        if matcher.negate {
+            return None;
+        }
+
+        match &matcher.matcher {
+            either::Left(either::Left(string)) => Some(Literals {
+                words: vec![string.as_str()],
+                case_sensitive: case_sensitive.unwrap_or(matcher.case_sensitive),
+            }),
+            // references to other tokens depend on the text
+            either::Left(either::Right(_)) => None,
+            either::Right(regex) => {
+                let inner = regex
+                    .regex_str()
+                    .strip_prefix("^(")
+                    .and_then(|x| x.strip_suffix(")$"))?;
+                let words: Vec<_> = inner.split('|').collect();
+                let is_plain = |word: &str| {
+                    !word.is_empty()
+                        && word
+                            .chars()
+                            .all(|c| c.is_alphanumeric() || c == '\'' || c == ' ')
+                };
+
+                if words.iter().all(|x| is_plain(x)) {
+                    Some(Literals {
+                        words,
+                        case_sensitive: regex.case_sensitive(),
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Whether these literals accept every word `other` accepts.
+    fn covers(&self, other: &Literals) -> bool {
+        other.words.iter().all(|b| {
+            self.words.iter().any(|a| {
+                if self.case_sensitive {
+                    other.case_sensitive && a == b
+                } else {
+                    UniCase::new(a) == UniCase::new(b)
+                }
+            })
+        })
+    }
+}
+
+fn text_covers(
+    a: &Matcher,
+    a_case_sensitive: Option<bool>,
+    b: &Matcher,
+    b_case_sensitive: Option<bool>,
+) -> bool {
+    match (
+        Literals::new(a, a_case_sensitive),
+        Literals::new(b, b_case_sensitive),
+    ) {
+        (Some(a), Some(b)) => a.covers(&b),
+        _ => false,
+    }
+}
+
+fn pos_covers(a: &PosMatcher, b: &PosMatcher) -> bool {
+    a.mask.capacity() == b.mask.capacity() && b.mask.is_subset(&a.mask)
+}
+
+/// Whether `a` provably matches every token `b` matches. `false` if it can not be determined.
+fn atom_covers(a: &Atom, b: &Atom) -> bool {
+    match (a, b) {
+        (Atom::TrueAtom(_), _) | (_, Atom::FalseAtom(_)) => true,
+        (_, Atom::OrAtom(b)) => b.atoms.iter().all(|b| atom_covers(a, b)),
+        (Atom::AndAtom(a), _) => a.atoms.iter().all(|a| atom_covers(a, b)),
+        (Atom::OrAtom(a), _) => a.atoms.iter().any(|a| atom_covers(a, b)),
+        (_, Atom::AndAtom(b)) => b.atoms.iter().any(|b| atom_covers(a, b)),
+        (Atom::TextAtom(a), Atom::TextAtom(b)) => {
+            text_covers(&a.matcher.matcher, None, &b.matcher.matcher, None)
+        }
+        (Atom::WordDataAtom(a), Atom::WordDataAtom(b)) => {
+            let pos = match (&a.matcher.pos_matcher, &b.matcher.pos_matcher) {
+                (None, _) => true,
+                (Some(a), Some(b)) => pos_covers(a, b),
+                (Some(_), None) => false,
+            };
+            let inflect = match (&a.matcher.inflect_matcher, &b.matcher.inflect_matcher) {
+                (None, _) => true,
+                (Some(x), Some(y)) => text_covers(
+                    &x.matcher,
+                    Some(a.case_sensitive),
+                    &y.matcher,
+                    Some(b.case_sensitive),
+                ),
+                (Some(_), None) => false,
+            };
+            pos && inflect
+        }
+        (Atom::SpaceBeforeAtom(a), Atom::SpaceBeforeAtom(b)) => a.value == b.value,
+        (Atom::CaseAtom(a), Atom::CaseAtom(b)) => a.case == b.case && a.negate == b.negate,
+        _ => false,
+    }
+}
+
+/// Whether `a` matches every sequence of tokens `b` matches, comparing the parts at the same position.
+fn composition_covers(a: &Composition, b: &Composition) -> bool {
+    a.unifications.is_empty()
+        && a.parts.len() == b.parts.len()
+        && a.parts.iter().zip(&b.parts).all(|(a, b)| {
+            a.quantifier.min == b.quantifier.min
+                && a.quantifier.max == b.quantifier.max
+                && a.quantifier.greediness == b.quantifier.greediness
+                && atom_covers(&a.atom, &b.atom)
+        })
+}
+
+/// Finds rules which are shadowed by a later rule in the same group i. e. the later rule matches
+/// wherever the rule matches and marks the same tokens. Of two suggestions starting at the same character
+/// [Rules::apply][crate::rules::Rules::apply] keeps the one of the later rule, so the rule never
+/// applies. `groups` are the IDs of the groups of the rules. Rules with antipatterns or a filter do not
+/// shadow other rules since they can be suppressed where the other rule matches. The check is
+/// conservative: rules are only reported if the shadowing can be proven without comparing regexes and
+/// every part matches a fixed number of tokens, so both rules mark the same span.
+pub(crate) fn lint_shadowed(rules: &[Rule], groups: &[Option<String>]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    for (i, (rule, group)) in rules.iter().zip(groups).enumerate() {
+        let (group, composition) = match (group, &rule.engine) {
+            (Some(group), Engine::Token(engine)) => (group, &engine.composition),
+            _ => continue,
+        };
+        if composition
+            .parts
+            .iter()
+            .any(|x| x.quantifier.min != x.quantifier.max)
+        {
+            continue;
+        }
+
+        let shadowing = rules[i + 1..]
+            .iter()
+            .zip(&groups[i + 1..])
+            .filter(|(_, other_group)| other_group.as_ref() == Some(group))
+            .find(|(other, _)| match &other.engine {
+                Engine::Token(engine) => {
+                    other.on
+                        && other.filter.is_none()
+                        && engine.antipatterns.is_empty()
+                        && (other.start, other.end) == (rule.start, rule.end)
+                        && composition_covers(&engine.composition, composition)
+                }
+                Engine::Text(..) => false,
+            });
+
+        if let Some((other, _)) = shadowing {
+            lints.push(Lint {
+                rule_id: rule.id.clone(),
+                kind: LintKind::Shadowed,
+                message: format!(
+                    "the rule is shadowed by `{}` which matches wherever this rule matches",
+                    other.id
+                ),
+            });
+        }
+    }
+
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compile::{
+            testing::{build_info_for, setup},
+            LintKind,
+        },
+        rules::{Rules, RulesOptions},
+    };
+
+    #[test]
+    fn lints_find_parts_which_never_match() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="UNKNOWN_POS" name="Unknown POS">
+            <pattern>
+                <token postag="ZZ.*" postag_regexp="yes"/>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="UNKNOWN_WORDS" name="Unknown words">
+            <pattern>
+                <token regexp="yes">xyzzy|plugh</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="ZERO_QUANTIFIER" name="Zero quantifier">
+            <pattern>
+                <token>here</token>
+                <token min="0" max="0">the</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="EMPTY_MATCH" name="Empty match">
+            <pattern>
+                <token min="0">here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="BOUNDED" name="Bounded">
+            <pattern>
+                <token regexp="yes">xyz{1,3}y|plugh\.|[xy]zzy</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="OPEN_ENDED" name="Open-ended">
+            <pattern>
+                <token>here</token>
+                <token regexp="yes">\d+|zz</token>
+                <token regexp="yes">x{2}|[0-9]{4}|zz.</token>
+                <token postag="RB|NN"/>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let build_info = build_info_for(&tokenizer);
+
+        let (_, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info.clone(),
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert!(report.lints.is_empty());
+
+        let (_, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info.with_lint(true),
+            RulesOptions::default(),
+        )
+        .unwrap();
+        let lints: Vec<_> = report
+            .lints
+            .iter()
+            .map(|x| (x.rule_id.as_str(), x.kind))
+            .collect();
+        assert_eq!(
+            lints,
+            vec![
+                ("UNKNOWN_POS", LintKind::PosNeverMatches),
+                ("UNKNOWN_WORDS", LintKind::TextNeverMatches),
+                ("ZERO_QUANTIFIER", LintKind::ZeroQuantifier),
+                ("EMPTY_MATCH", LintKind::EmptyMatch),
+                ("BOUNDED", LintKind::TextNeverMatches),
+            ]
+        );
+        assert_eq!(
+            report.lints[1].message,
+            "pattern part 0: the regex `^(xyzzy|plugh)$` matches none of the known words"
+        );
+    }
+
+    #[test]
+    fn lints_find_shadowed_rules_in_groups() {
+        let rule = |pattern: &str| {
+            // a pattern starting with an antipattern is split at the start of the pattern
+            let (antipattern, pattern) = pattern.split_once("<pattern>").unwrap_or(("", pattern));
+            format!(
+                r#"<rule>
+                {}<pattern>{}</pattern>
+                <message>Did you mean <suggestion>there</suggestion>?</message>
+                <example>here</example>
+            </rule>"#,
+                antipattern, pattern
+            )
+        };
+        let group = |id: &str, rules: &[&str]| {
+            format!(
+                r#"<rulegroup id="{}" name="Test">{}</rulegroup>"#,
+                id,
+                rules.iter().map(|x| rule(x)).collect::<String>()
+            )
+        };
+
+        let groups = [
+            // alternations of words cover single words
+            group(
+                "ALTERNATION",
+                &[
+                    "<token>here</token><token>went</token>",
+                    r#"<token>here</token><token regexp="yes">went|gone</token>"#,
+                ],
+            ),
+            // a POS tag regex covers a more specific tag, no POS tag covers everything
+            group(
+                "POS",
+                &[
+                    r#"<token postag="VBD"/><token>here</token>"#,
+                    r#"<token postag="VB.*" postag_regexp="yes"/><token/>"#,
+                ],
+            ),
+            // a case-sensitive token does not cover a case-insensitive one
+            group(
+                "CASE",
+                &[
+                    "<token>here</token>",
+                    r#"<token case_sensitive="yes">here</token>"#,
+                ],
+            ),
+            // regexes are not compared
+            group(
+                "REGEX",
+                &[
+                    r#"<token regexp="yes">we.*</token>"#,
+                    r#"<token regexp="yes">w.*</token>"#,
+                ],
+            ),
+            // the general rule comes first, the specific one wins where both match
+            group(
+                "ORDER",
+                &[
+                    r#"<token regexp="yes">here|there</token>"#,
+                    "<token>here</token>",
+                ],
+            ),
+            // an antipattern can suppress the general rule
+            group(
+                "ANTIPATTERN",
+                &[
+                    "<token>here</token>",
+                    "<antipattern><token>here</token></antipattern><pattern><token>here</token>",
+                ],
+            ),
+            // different lengths
+            group(
+                "LENGTH",
+                &["<token>here</token><token/>", "<token>here</token>"],
+            ),
+            // the general rule marks other tokens
+            group(
+                "MARKER",
+                &[
+                    "<token>here</token><token>went</token>",
+                    "<marker><token>here</token></marker><token/>",
+                ],
+            ),
+            // optional tokens can shift the marked span
+            group(
+                "QUANTIFIER",
+                &[
+                    r#"<token min="0">here</token><token>went</token>"#,
+                    r#"<token min="0">here</token><token regexp="yes">went|gone</token>"#,
+                ],
+            ),
+        ];
+        let grammar = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        {}
+    </category>
+</rules>
+"#,
+            groups.join("\n")
+        );
+
+        let (tokenizer, _) = setup();
+        let build_info = build_info_for(&tokenizer);
+        let (rules, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info.clone().with_lint(true),
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rules.rules().len(), 18);
+
+        let shadowed: Vec<_> = report
+            .lints
+            .iter()
+            .filter(|x| x.kind == LintKind::Shadowed)
+            .map(|x| (x.rule_id.as_str(), x.message.as_str()))
+            .collect();
+        assert_eq!(
+            shadowed,
+            vec![
+                (
+                    "ALTERNATION.0",
+                    "the rule is shadowed by `ALTERNATION.1` which matches wherever this rule matches"
+                ),
+                (
+                    "POS.0",
+                    "the rule is shadowed by `POS.1` which matches wherever this rule matches"
+                ),
+            ]
+        );
+
+        // the shadowing rule is the one which applies
+        let rules = Rules::from_xml_reader(
+            grammar.as_bytes(),
+            &mut build_info.clone(),
+            RulesOptions::builder()
+                .ids(vec!["ALTERNATION.0".into(), "ALTERNATION.1".into()])
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rules.rules().len(), 2);
+        let sources: Vec<_> = rules
+            .suggest("Here went.", &tokenizer)
+            .into_iter()
+            .map(|x| x.source)
+            .collect();
+        assert_eq!(sources, vec!["ALTERNATION.1"]);
+    }
+}
@@ -1,121 +1,2273 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     fs::{read_to_string, File},
     hash::{Hash, Hasher},
     io::{BufReader, BufWriter},
+    path::Path,
     sync::Arc,
 };
 
+#[cfg(feature = "bin")]
 use clap::Clap;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use crate::{
-    rules::{Rules, RulesOptions},
-    tokenizer::{chunk::Chunker, tag::Tagger, Tokenizer, TokenizerOptions},
+    rules::{IgnoreWords, Rules, RulesOptions, ValidationReport},
+    tokenizer::{
+        chunk::Chunker, multiword::MultiwordTagger, tag::Tagger, Tokenizer, TokenizerOptions,
+    },
     types::DefaultHasher,
+    Compression,
 };
 
-use self::parse_structure::{BuildInfo, RegexCache};
+pub use self::parse_structure::{BuildInfo, RegexCache};
 
 mod impls;
+mod index;
+mod inspect;
+mod lint;
+pub(crate) mod morfologik;
 mod parse_structure;
+mod prefilter;
 mod structure;
+#[cfg(test)]
+pub(crate) mod testing;
 
-#[derive(Clap)]
-#[clap(
-    version = env!("CARGO_PKG_VERSION"),
-    author = "Benjamin Minixhofer <bminixhofer@gmail.com>"
-)]
-pub struct BuildOptions {
-    #[clap(long)]
+/// An error while loading a chunker with [Chunker::from_json].
+#[derive(thiserror::Error, Debug)]
+pub enum ChunkerLoadError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The JSON is malformed or truncated. The inner error knows the line and column.
+    #[error("malformed chunker JSON: {0}")]
+    Syntax(serde_json::Error),
+    /// The JSON is well-formed but does not describe a valid chunker e. g. because a field is missing
+    /// or a model references an outcome which is not in its `outcome_labels`.
+    #[error("invalid chunker: {0}")]
+    Invalid(String),
+}
+
+/// An error while compiling the tokenizer or the rules.
+/// Errors in a single rule skip the rule, so compiling a set of rules can result in many errors.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// A rule uses a part-of-speech tag the tagger does not know.
+    /// `suggestions` are the known tags closest to `tag`, most likely a typo of one of them.
+    #[error("unknown part-of-speech tag `{tag}`{}", format_suggestions(.suggestions))]
+    UnknownPosTag {
+        tag: String,
+        suggestions: Vec<String>,
+        rule_id: Option<String>,
+    },
+    /// A rule uses a feature of the LanguageTool rule format which nlprule does not implement (yet).
+    #[error("unsupported feature: {feature}")]
+    UnsupportedFeature {
+        feature: String,
+        rule_id: Option<String>,
+    },
+    /// A regular expression in a rule can not be compiled.
+    #[error("invalid regex `{regex}`: {message}")]
+    Regex {
+        regex: String,
+        message: String,
+        rule_id: Option<String>,
+    },
+    /// The XML of a rule is malformed or has unknown elements or attributes.
+    /// The line is relative to the start of the rule, if known. `rule_id` is the `id` attribute of the rule or rule group, if any.
+    /// If the document itself can not be read e. g. because it is truncated, line and column are the position in the document.
+    #[error("malformed XML{}: {message}", format_position(*.line, *.column))]
+    Xml {
+        line: Option<u64>,
+        column: Option<u64>,
+        message: String,
+        rule_id: Option<String>,
+    },
+    /// A rule is well-formed but inconsistent e. g. it has an example with two markers.
+    #[error("unexpected condition: {message}")]
+    Unexpected {
+        message: String,
+        rule_id: Option<String>,
+    },
+    /// An ID in the `ids` or `ignore_ids` of [RulesOptions] or [TokenizerOptions] is not the ID of any rule,
+    /// so the option has no effect. `suggestions` are the existing IDs closest to `id`.
+    #[error("unknown rule ID `{id}` in the options{}", format_suggestions(.suggestions))]
+    UnknownRuleId {
+        id: String,
+        suggestions: Vec<String>,
+    },
+    /// A rule has the same ID as a rule in a previous file and [DuplicateIdPolicy::Error][crate::DuplicateIdPolicy::Error]
+    /// is used. `file` is the path of the file with the previous rule, if known.
+    #[error("duplicate rule ID, the ID is already used{}", file.as_ref().map_or_else(String::new, |x| format!(" in `{}`", x)))]
+    DuplicateId {
+        file: Option<String>,
+        rule_id: Option<String>,
+    },
+    /// Rules can not be compiled and the [ErrorPolicy][crate::ErrorPolicy] does not allow skipping them.
+    /// Contains the error of each failing rule.
+    #[error("{} rules can not be compiled", .0.len())]
+    RuleErrors(Vec<Error>),
+    #[error(transparent)]
+    Chunker(#[from] ChunkerLoadError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Formats a position as e. g. " at line 3, column 5".
+fn format_position(line: Option<u64>, column: Option<u64>) -> String {
+    match (line, column) {
+        (Some(line), Some(column)) => format!(" at line {}, column {}", line, column),
+        (Some(line), None) => format!(" at line {}", line),
+        _ => String::new(),
+    }
+}
+
+/// Formats suggestions as e. g. ", did you mean `NNP` or `NNPS`?".
+fn format_suggestions(suggestions: &[String]) -> String {
+    let quoted: Vec<_> = suggestions.iter().map(|x| format!("`{}`", x)).collect();
+
+    match quoted.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!(", did you mean {}?", last),
+        Some((last, rest)) => format!(", did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+/// Number of known tags or IDs suggested for an unknown one.
+const N_SUGGESTIONS: usize = 3;
+
+/// The candidates with the smallest edit distance to `target`, closest first.
+/// Candidates which differ in more than half of the characters are not returned.
+fn closest<'a>(target: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let max_distance = (target.chars().count() / 2).max(1);
+
+    let mut candidates: Vec<_> = candidates
+        .map(|x| (crate::utils::edit_distance(target, x), x))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    candidates.sort_unstable();
+
+    candidates
+        .into_iter()
+        .take(N_SUGGESTIONS)
+        .map(|(_, x)| x.to_string())
+        .collect()
+}
+
+impl Error {
+    /// An [Error::UnknownPosTag] suggesting the tags of the tagger with the smallest edit distance to `tag`.
+    /// Tags which differ in more than half of the characters are not suggested.
+    pub(crate) fn unknown_pos_tag(tag: &str, tagger: &Tagger) -> Self {
+        Error::UnknownPosTag {
+            tag: tag.to_string(),
+            suggestions: closest(
+                tag,
+                tagger
+                    .tag_store()
+                    .iter()
+                    .map(|(x, _)| x.as_str())
+                    .filter(|x| !x.is_empty()),
+            ),
+            rule_id: None,
+        }
+    }
+
+    /// An [Error::UnknownRuleId] suggesting the IDs in `ids` closest to `id`.
+    pub(crate) fn unknown_rule_id<'a>(id: &str, ids: impl Iterator<Item = &'a str>) -> Self {
+        Error::UnknownRuleId {
+            id: id.to_string(),
+            suggestions: closest(id, ids),
+        }
+    }
+
+    pub(crate) fn unsupported<S: Into<String>>(feature: S) -> Self {
+        Error::UnsupportedFeature {
+            feature: feature.into(),
+            rule_id: None,
+        }
+    }
+
+    pub(crate) fn unexpected<S: Into<String>>(message: S) -> Self {
+        Error::Unexpected {
+            message: message.into(),
+            rule_id: None,
+        }
+    }
+
+    /// The name of the variant, used to group errors.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::UnknownPosTag { .. } => "UnknownPosTag",
+            Error::UnsupportedFeature { .. } => "UnsupportedFeature",
+            Error::Regex { .. } => "Regex",
+            Error::Xml { .. } => "Xml",
+            Error::Unexpected { .. } => "Unexpected",
+            Error::DuplicateId { .. } => "DuplicateId",
+            Error::UnknownRuleId { .. } => "UnknownRuleId",
+            Error::RuleErrors(_) => "RuleErrors",
+            Error::Chunker(_) => "Chunker",
+            Error::Io(_) => "Io",
+        }
+    }
+
+    /// The ID of the rule which caused this error, if any.
+    pub fn rule_id(&self) -> Option<&str> {
+        match self {
+            Error::UnknownPosTag { rule_id, .. }
+            | Error::UnsupportedFeature { rule_id, .. }
+            | Error::Regex { rule_id, .. }
+            | Error::Unexpected { rule_id, .. }
+            | Error::DuplicateId { rule_id, .. }
+            | Error::Xml { rule_id, .. } => rule_id.as_deref(),
+            Error::UnknownRuleId { .. }
+            | Error::RuleErrors(_)
+            | Error::Chunker(_)
+            | Error::Io(_) => None,
+        }
+    }
+
+    /// Sets the ID of the rule which caused this error. Has no effect on errors which do not belong to a rule.
+    pub(crate) fn with_rule_id(mut self, id: &str) -> Self {
+        match &mut self {
+            Error::UnknownPosTag { rule_id, .. }
+            | Error::UnsupportedFeature { rule_id, .. }
+            | Error::Regex { rule_id, .. }
+            | Error::Unexpected { rule_id, .. }
+            | Error::DuplicateId { rule_id, .. }
+            | Error::Xml { rule_id, .. } => *rule_id = Some(id.to_string()),
+            Error::UnknownRuleId { .. }
+            | Error::RuleErrors(_)
+            | Error::Chunker(_)
+            | Error::Io(_) => {}
+        }
+        self
+    }
+
+    /// A JSON representation for tooling with the keys `kind`, `rule_id` and `message`.
+    /// [Error::RuleErrors] is an array of the errors of each rule.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Error::RuleErrors(errors) => errors.iter().map(|x| x.to_json()).collect(),
+            _ => json!({
+                "kind": self.kind(),
+                "rule_id": self.rule_id(),
+                "message": self.to_string(),
+            }),
+        }
+    }
+}
+
+/// The outcome of compiling a single rule, see [CompileReport].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleOutcome {
+    /// The rule was compiled and is part of the output.
+    Ok,
+    /// The rule could not be compiled. `kind` is [Error::kind], `message` the string representation of the error.
+    Error { kind: String, message: String },
+    /// The rule was compiled but left out by the options e. g. [RulesOptions::ignore_ids].
+    /// Disambiguation rules after a rule which could not be compiled are also left out since
+    /// the disambiguation rules depend on each other. Rules replaced by a rule with the same ID in a later file
+    /// (see [DuplicateIdPolicy::LastWins][crate::DuplicateIdPolicy::LastWins]) are left out as well.
+    FilteredOut,
+}
+
+/// The outcome of compiling a single rule, see [CompileReport].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleReport {
+    /// The ID of the rule, or of its rule group if the XML of the group is malformed. Not known if the element has no `id`.
+    pub id: Option<String>,
+    /// The ID of the category of the rule, if known.
+    pub category_id: Option<String>,
+    /// The path of the file the rule was read from. Not known if the rules are read from a reader.
+    pub file: Option<String>,
+    pub outcome: RuleOutcome,
+}
+
+impl RuleReport {
+    pub(crate) fn from_error(
+        error: &Error,
+        category_id: Option<String>,
+        file: Option<String>,
+    ) -> Self {
+        RuleReport {
+            id: error.rule_id().map(|x| x.to_string()),
+            category_id,
+            file,
+            outcome: RuleOutcome::Error {
+                kind: error.kind().to_string(),
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Number of rules with each [RuleOutcome].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutcomeCounts {
+    pub ok: usize,
+    pub error: usize,
+    pub filtered_out: usize,
+}
+
+impl OutcomeCounts {
+    fn add(&mut self, outcome: &RuleOutcome) {
+        match outcome {
+            RuleOutcome::Ok => self.ok += 1,
+            RuleOutcome::Error { .. } => self.error += 1,
+            RuleOutcome::FilteredOut => self.filtered_out += 1,
+        }
+    }
+}
+
+/// A machine-readable report of the outcome of compiling each rule in a set of rules
+/// e. g. to compare the coverage of consecutive builds. The rules are in the order of the XML,
+/// the totals are sorted by key so the JSON of two reports can be diffed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompileReport {
+    pub rules: Vec<RuleReport>,
+    /// The totals across all rules.
+    pub total: OutcomeCounts,
+    /// The totals per category ID. Rules without known category are not counted.
+    pub categories: BTreeMap<String, OutcomeCounts>,
+    /// The number of rules which could not be compiled per [Error::kind].
+    pub errors: BTreeMap<String, usize>,
+    /// The rules using each unknown part-of-speech tag so typos can be fixed in one go.
+    pub unknown_tags: BTreeMap<String, UnknownTagReport>,
+    /// The IDs in the `ids` and `ignore_ids` options which are not the ID of any rule,
+    /// with the closest existing IDs. See [Error::UnknownRuleId].
+    pub unknown_ids: BTreeMap<String, Vec<String>>,
+    /// Parts of the included rules which can never match. Only checked if linting is enabled,
+    /// see [CompileOptions::lint].
+    pub lints: Vec<Lint>,
+    /// The cost of building each included rule, the slowest first. Only recorded if profiling is enabled,
+    /// see [CompileOptions::profile].
+    pub profiles: Vec<RuleProfile>,
+    /// Whether the words matched by each regex of the included rules are stored, in the order of the rules.
+    pub regex_sets: Vec<RegexSetReport>,
+}
+
+/// The cost of building a single rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleProfile {
+    pub id: String,
+    /// The wall time to build the rule in microseconds. Depends on the machine and on the regex cache.
+    pub micros: u64,
+    /// The size of the rule in the binary in bytes.
+    pub size: u64,
+    /// The number of regexes which were matched against all words of the tagger because they were not in
+    /// the regex cache. Each creates one cache entry.
+    pub regex_scans: usize,
+    /// The total number of words in the sets of matched words stored in the rule.
+    pub set_words: usize,
+}
+
+/// A regex matching the text or the lemma of a token, see [CompileReport::regex_sets].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegexSetReport {
+    pub rule_id: String,
+    pub regex: String,
+    /// The number of words of the tagger the regex matches if they are stored in the binary. `None` if it matches
+    /// more than [CompileOptions::max_regex_set_size] words, then the regex is matched at runtime and counted in
+    /// the [MatchStats::regex_fallbacks][crate::rules::MatchStats::regex_fallbacks].
+    pub words: Option<usize>,
+}
+
+/// The rules which use an unknown part-of-speech tag, see [Error::UnknownPosTag].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnknownTagReport {
+    /// The known tags closest to the unknown tag.
+    pub suggestions: Vec<String>,
+    /// The IDs of the rules using the tag, if known.
+    pub rule_ids: Vec<String>,
+}
+
+/// The kind of a [Lint].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LintKind {
+    /// A POS matcher matches none of the tags known to the tagger.
+    PosNeverMatches,
+    /// A regex matches none of the words in the word store and can not match other words
+    /// e. g. because it does not contain `\d` or unbounded repetitions.
+    TextNeverMatches,
+    /// A token has a maximum of zero occurrences.
+    ZeroQuantifier,
+    /// All tokens of a pattern are optional i. e. the pattern can match zero tokens.
+    EmptyMatch,
+    /// A later rule in the same group matches wherever the rule matches and marks the same tokens. The
+    /// later rule wins ties, so the rule never applies.
+    Shadowed,
+}
+
+/// A part of a compiled rule which can never match, likely because of a mistake in the XML.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lint {
+    pub rule_id: String,
+    pub kind: LintKind,
+    /// Where in the rule the problem is and what it is.
+    pub message: String,
+}
+
+impl CompileReport {
+    pub(crate) fn new(
+        rules: Vec<RuleReport>,
+        rule_errors: &[Error],
+        lints: Vec<Lint>,
+        mut profiles: Vec<RuleProfile>,
+        regex_sets: Vec<RegexSetReport>,
+    ) -> Self {
+        let mut total = OutcomeCounts::default();
+        let mut categories: BTreeMap<String, OutcomeCounts> = BTreeMap::new();
+        let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+
+        for rule in &rules {
+            total.add(&rule.outcome);
+            if let Some(category_id) = &rule.category_id {
+                categories
+                    .entry(category_id.clone())
+                    .or_default()
+                    .add(&rule.outcome);
+            }
+            if let RuleOutcome::Error { kind, .. } = &rule.outcome {
+                *errors.entry(kind.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let mut unknown_tags: BTreeMap<String, UnknownTagReport> = BTreeMap::new();
+        for error in rule_errors {
+            if let Error::UnknownPosTag {
+                tag,
+                suggestions,
+                rule_id,
+            } = error
+            {
+                let report = unknown_tags
+                    .entry(tag.clone())
+                    .or_insert_with(|| UnknownTagReport {
+                        suggestions: suggestions.clone(),
+                        rule_ids: Vec::new(),
+                    });
+                report.rule_ids.extend(rule_id.clone());
+            }
+        }
+
+        CompileReport {
+            rules,
+            total,
+            categories,
+            errors,
+            unknown_tags,
+            unknown_ids: BTreeMap::new(),
+            lints,
+            profiles: {
+                profiles.sort_by(|a, b| b.micros.cmp(&a.micros).then(b.size.cmp(&a.size)));
+                profiles
+            },
+            regex_sets,
+        }
+    }
+
+    /// Writes the report as JSON.
+    pub fn to_writer<W: std::io::Write>(&self, writer: W) -> Result<(), Error> {
+        serde_json::to_writer_pretty(writer, self).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+}
+
+impl From<crate::Error> for Error {
+    fn from(error: crate::Error) -> Self {
+        match error {
+            crate::Error::Unimplemented(feature) => Error::unsupported(feature),
+            crate::Error::UnknownTag(tag) => Error::UnknownPosTag {
+                tag,
+                suggestions: Vec::new(),
+                rule_id: None,
+            },
+            crate::Error::Io(error) => Error::Io(error),
+            error => Error::unexpected(error.to_string()),
+        }
+    }
+}
+
+impl From<serde_xml_rs::Error> for Error {
+    fn from(error: serde_xml_rs::Error) -> Self {
+        use xml::common::Position;
+
+        let (line, column) = match &error {
+            serde_xml_rs::Error::Syntax { source } => (
+                Some(source.position().row + 1),
+                Some(source.position().column + 1),
+            ),
+            _ => (None, None),
+        };
+
+        Error::Xml {
+            line,
+            column,
+            message: error.to_string(),
+            rule_id: None,
+        }
+    }
+}
+
+/// Which rules are compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    Disambiguation,
+    Grammar,
+}
+
+/// The progress of compiling, reported to the callback set with [CompileOptions::with_progress].
+///
+/// The callback is called from the thread doing the work. Rules are built in parallel so
+/// [CompileProgress::BuildingRules] and [CompileProgress::BuildingRegexCache] can be reported from multiple
+/// threads at once and the counts are not necessarily reported in increasing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompileProgress {
+    /// The XML of the rules is being parsed. Reported once per kind of rules, from the calling thread.
+    ParsingXml { kind: RuleKind },
+    /// `done` of `total` rules are built. Reported once with `done = 0` before building the first rule.
+    BuildingRules {
+        kind: RuleKind,
+        done: usize,
+        total: usize,
+    },
+    /// A regex which is not in the regex cache was matched against all words of the tagger.
+    /// `matched` is the number of these regexes so far.
+    BuildingRegexCache { matched: usize },
+}
+
+/// The files the tokenizer and the rules are compiled from, see [compile].
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "bin", derive(Clap))]
+pub struct CompilePaths {
+    #[cfg_attr(feature = "bin", clap(long))]
     pub tag_paths: Vec<String>,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub tag_remove_paths: Vec<String>,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub disambiguation_path: String,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub grammar_path: String,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub tokenizer_config_path: String,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub rules_config_path: String,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub chunker_path: Option<String>,
-    #[clap(long)]
+    /// A directory with the files extracted from the OpenNLP models i. e. `token.model`, `pos.model`,
+    /// `tags.tagdict` and `chunker.model`. Used instead of `chunker_path` to skip the conversion to JSON.
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub opennlp_chunker_dir: Option<String>,
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub multiword_path: Option<String>,
+    #[cfg_attr(feature = "bin", clap(long))]
     pub common_words_path: Option<String>,
-    #[clap(long)]
-    pub regex_cache_path: String,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub frequency_path: Option<String>,
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub tag_description_path: Option<String>,
+    /// Word lists like the `ignore.txt` and `spelling.txt` files of LanguageTool. No suggestions are made
+    /// for text which is exactly one of the words, see [IgnoreWords::from_dumps].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub ignore_word_paths: Vec<String>,
+    /// Where to store the words matched by the regexes in the rules. Loaded at the start of the next run
+    /// so only regexes which changed have to be matched against the word store again.
+    #[cfg_attr(
+        feature = "bin",
+        clap(long = "regex-cache", alias = "regex-cache-path", value_name = "PATH")
+    )]
+    pub regex_cache_path: Option<String>,
+}
+
+/// How the tokenizer and the rules are compiled, see [compile].
+#[derive(Clone)]
+#[cfg_attr(feature = "bin", derive(Clap))]
+pub struct CompileOptions {
+    /// The maximum number of words a regex can match for the words to be stored in the binary,
+    /// see [BuildInfo::max_regex_set_size].
+    #[cfg_attr(feature = "bin", clap(long, default_value = "100"))]
+    pub max_regex_set_size: usize,
+    /// Whether to check the compiled rules for parts which can never match. The findings are part of
+    /// the report, see [CompileReport::lints].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub lint: bool,
+    /// Whether to record the time and size of building each rule. The profiles are part of the report,
+    /// see [CompileReport::profiles].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub profile: bool,
+    #[cfg_attr(feature = "bin", clap(skip))]
+    progress: Option<Arc<dyn Fn(CompileProgress) + Send + Sync>>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            max_regex_set_size: parse_structure::DEFAULT_MAX_REGEX_SET_SIZE,
+            lint: false,
+            profile: false,
+            progress: None,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Sets a callback which is called with the progress of compiling, see [CompileProgress].
+    pub fn with_progress<F: Fn(CompileProgress) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+}
+
+/// Options of the `compile` binary: where to read the inputs from, how to compile them and where to write the outputs.
+#[cfg_attr(feature = "bin", derive(Clap))]
+#[cfg_attr(
+    feature = "bin",
+    clap(
+        version = env!("CARGO_PKG_VERSION"),
+        author = "Benjamin Minixhofer <bminixhofer@gmail.com>"
+    )
+)]
+pub struct BuildOptions {
+    #[cfg_attr(feature = "bin", clap(flatten))]
+    pub paths: CompilePaths,
+    #[cfg_attr(feature = "bin", clap(flatten))]
+    pub options: CompileOptions,
+    #[cfg_attr(feature = "bin", clap(long))]
     pub out_tokenizer_path: String,
-    #[clap(long)]
+    #[cfg_attr(feature = "bin", clap(long))]
     pub out_rules_path: String,
+    /// How to compress the tokenizer and rules binaries: `none`, `gzip` or `zstd`. Compressed binaries are
+    /// decompressed when loading, see [Compression].
+    #[cfg_attr(feature = "bin", clap(long, default_value = "none"))]
+    pub compress: Compression,
+    /// Where to additionally write the chunker as standalone binary, see [Chunker::to_writer].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub out_chunker_path: Option<String>,
+    /// Where to write the errors of rules which could not be compiled as JSON array, see [Error::to_json].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub errors_json_path: Option<String>,
+    /// Where to write the outcome of compiling each rule as JSON, see [CompileReport].
+    /// Not written if rules fail according to the error policy.
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub report_json_path: Option<String>,
+    /// Whether to check the grammar rules against their examples after compiling, see [Rules::validate].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub validate_examples: bool,
+    /// Where to write the result of checking the examples as JSON, see [ValidationReport].
+    /// Implies [BuildOptions::validate_examples].
+    #[cfg_attr(feature = "bin", clap(long))]
+    pub validation_json_path: Option<String>,
 }
 
-pub fn compile(opts: &BuildOptions) {
-    let common_words = opts
-        .common_words_path
-        .as_ref()
-        .map_or_else(HashSet::new, |path| {
-            read_to_string(path)
-                .unwrap()
-                .lines()
-                .map(|x| x.to_string())
-                .collect()
+/// The result of [compile_with_report].
+pub struct Compiled {
+    pub tokenizer: Tokenizer,
+    pub rules: Rules,
+    /// The outcome of compiling the disambiguation rules.
+    pub tokenizer_report: CompileReport,
+    /// The outcome of compiling the grammar rules.
+    pub rules_report: CompileReport,
+    /// The errors of the rules which could not be compiled and were skipped according to the
+    /// [ErrorPolicy][crate::ErrorPolicy].
+    pub errors: Vec<Error>,
+}
+
+/// The result of [build].
+#[derive(Debug, Clone)]
+pub struct CompileOutput {
+    /// The outcome of compiling the disambiguation rules.
+    pub tokenizer_report: CompileReport,
+    /// The outcome of compiling the grammar rules.
+    pub rules_report: CompileReport,
+    /// The result of checking the examples, if [BuildOptions::validate_examples] or
+    /// [BuildOptions::validation_json_path] is set.
+    pub validation: Option<ValidationReport>,
+}
+
+/// Writes the reports of the disambiguation and grammar rules as JSON object with the keys
+/// `disambiguation` and `grammar` to [BuildOptions::report_json_path], if set.
+fn write_report(
+    opts: &BuildOptions,
+    tokenizer_report: &CompileReport,
+    rules_report: &CompileReport,
+) -> Result<(), Error> {
+    if let Some(path) = &opts.report_json_path {
+        let f = BufWriter::new(File::create(path)?);
+        let report = json!({
+            "disambiguation": tokenizer_report,
+            "grammar": rules_report,
         });
+        serde_json::to_writer_pretty(f, &report).map_err(std::io::Error::from)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the errors as JSON array to [BuildOptions::errors_json_path], if set.
+fn write_errors(opts: &BuildOptions, errors: &[Error]) -> Result<(), Error> {
+    if let Some(path) = &opts.errors_json_path {
+        let f = BufWriter::new(File::create(path)?);
+        let errors: Vec<_> = errors.iter().map(|x| x.to_json()).collect();
+        serde_json::to_writer_pretty(f, &errors).map_err(std::io::Error::from)?;
+    }
+
+    Ok(())
+}
+
+/// Compiles the tokenizer and the rules from the files in `paths`. Both are built with the same tagger,
+/// so the word IDs the rules are precomputed on are the IDs the tokenizer assigns.
+///
+/// ```no_run
+/// use nlprule::compile::{compile, CompileOptions, CompilePaths};
+///
+/// let paths = CompilePaths {
+///     tag_paths: vec!["data/en/tags/output.dump".into()],
+///     disambiguation_path: "data/en/disambiguation.canonic.xml".into(),
+///     grammar_path: "data/en/grammar.canonic.xml".into(),
+///     tokenizer_config_path: "configs/en/tokenizer.json".into(),
+///     rules_config_path: "configs/en/rules.json".into(),
+///     ..CompilePaths::default()
+/// };
+/// let (tokenizer, rules) = compile(paths, CompileOptions::default())?;
+///
+/// rules.check_tokenizer(&tokenizer)?;
+/// # Ok::<(), nlprule::compile::Error>(())
+/// ```
+pub fn compile(paths: CompilePaths, opts: CompileOptions) -> Result<(Tokenizer, Rules), Error> {
+    compile_with_report(paths, opts).map(|compiled| (compiled.tokenizer, compiled.rules))
+}
+
+/// Compiles like [compile] and additionally returns the outcome of compiling each rule.
+pub fn compile_with_report(paths: CompilePaths, opts: CompileOptions) -> Result<Compiled, Error> {
+    let common_words = match &paths.common_words_path {
+        Some(path) => read_to_string(path)?
+            .lines()
+            .map(|x| x.to_string())
+            .collect(),
+        None => HashSet::new(),
+    };
 
     let tokenizer_options: TokenizerOptions =
-        serde_json::from_str(&read_to_string(&opts.tokenizer_config_path).unwrap()).unwrap();
+        serde_json::from_str(&read_to_string(&paths.tokenizer_config_path)?)
+            .map_err(std::io::Error::from)?;
     let rules_options: RulesOptions =
-        serde_json::from_str(&read_to_string(&opts.rules_config_path).unwrap()).unwrap();
+        serde_json::from_str(&read_to_string(&paths.rules_config_path)?)
+            .map_err(std::io::Error::from)?;
+
+    // the chunker is loaded first so a broken chunker file fails before the expensive steps
+    let chunker = if let Some(path) = &paths.chunker_path {
+        let reader = BufReader::new(File::open(path)?);
+        Some(Chunker::from_json(reader)?)
+    } else if let Some(dir) = &paths.opennlp_chunker_dir {
+        let open = |name: &str| File::open(Path::new(dir).join(name)).map(BufReader::new);
+        Some(Chunker::from_opennlp(
+            open("token.model")?,
+            open("pos.model")?,
+            open("chunker.model")?,
+            open("tags.tagdict")?,
+        )?)
+    } else {
+        None
+    };
+
+    for rule in &tokenizer_options.special_token_rules {
+        rule.regex()?;
+    }
 
-    let tagger = Tagger::from_dumps(
-        &opts.tag_paths,
-        &opts.tag_remove_paths,
-        &tokenizer_options.extra_tags,
+    // phrase tags of multiword expressions and guessed tags must be known to the tagger
+    let mut extra_tags = tokenizer_options.extra_tags.clone();
+    extra_tags.extend(
+        tokenizer_options
+            .unknown_word_heuristics
+            .iter()
+            .map(|x| x.tag.clone()),
+    );
+    extra_tags.extend(
+        tokenizer_options
+            .special_token_rules
+            .iter()
+            .map(|x| x.tag.clone()),
+    );
+    if let Some(path) = &paths.multiword_path {
+        extra_tags.extend(MultiwordTagger::tags_from_dump(path)?);
+    }
+
+    let mut tagger = Tagger::from_dumps(
+        &paths.tag_paths,
+        &paths.tag_remove_paths,
+        &extra_tags,
         &common_words,
-    )
-    .unwrap();
+    )?;
+
+    if let Some(path) = &paths.frequency_path {
+        tagger.add_frequencies_from_dump(path)?;
+    }
+
+    if let Some(path) = &paths.tag_description_path {
+        tagger.add_tag_descriptions_from_dump(path)?;
+    }
 
     let mut hasher = DefaultHasher::default();
-    let mut word_store = tagger.word_store().iter().collect::<Vec<_>>();
-    word_store.sort_by(|a, b| a.1.cmp(b.1));
+    // the word store iterates in order of the IDs
+    let word_store = tagger.words().iter().collect::<Vec<_>>();
     word_store.hash(&mut hasher);
     let word_store_hash = hasher.finish();
 
-    let regex_cache = if let Ok(file) = File::open(&opts.regex_cache_path) {
-        let cache: RegexCache = bincode::deserialize_from(BufReader::new(file)).unwrap();
-        if *cache.word_hash() == word_store_hash {
-            cache
-        } else {
-            RegexCache::new(word_store_hash)
-        }
-    } else {
-        RegexCache::new(word_store_hash)
+    let regex_cache = match &paths.regex_cache_path {
+        Some(path) => RegexCache::load(path, word_store_hash),
+        None => RegexCache::new(word_store_hash),
     };
 
-    let mut build_info = BuildInfo::new(Arc::new(tagger), regex_cache);
+    // the tokenizer and the rules share this build info and thus the tagger
+    let mut build_info = BuildInfo::new(Arc::new(tagger), regex_cache)
+        .with_lint(opts.lint)
+        .with_profile(opts.profile)
+        .with_max_regex_set_size(opts.max_regex_set_size);
+    if let Some(callback) = opts.progress {
+        build_info = build_info.with_progress(move |progress| callback(progress));
+    }
 
-    let tokenizer = Tokenizer::from_xml(
-        &opts.disambiguation_path,
+    let multiword_tagger = match &paths.multiword_path {
+        Some(path) => Some(MultiwordTagger::from_dump(path, &build_info)?),
+        None => None,
+    };
+
+    // the errors are also returned if rules fail according to the error policy
+    let (tokenizer, mut errors, tokenizer_report) = Tokenizer::from_xml_inner(
+        vec![(
+            BufReader::new(File::open(&paths.disambiguation_path)?),
+            Some(paths.disambiguation_path.clone()),
+        )],
         &mut build_info,
-        if let Some(path) = &opts.chunker_path {
-            let reader = BufReader::new(File::open(path).unwrap());
-            let chunker = Chunker::from_json(reader);
-            Some(chunker)
-        } else {
-            None
-        },
+        chunker,
+        multiword_tagger,
         tokenizer_options,
-    )
-    .unwrap();
+    )?;
+
+    let rules = Rules::from_xml_inner(
+        vec![(
+            BufReader::new(File::open(&paths.grammar_path)?),
+            Some(paths.grammar_path.clone()),
+        )],
+        &mut build_info,
+        rules_options,
+    );
+
+    info!(
+        "{} regexes match more than {} words and are matched at runtime.",
+        build_info.regex_cache().fallbacks(),
+        opts.max_regex_set_size
+    );
 
-    let f = BufWriter::new(File::create(&opts.out_tokenizer_path).unwrap());
-    bincode::serialize_into(f, &tokenizer).unwrap();
+    // the cache is also useful when iterating on rules which fail to compile
+    if let Some(path) = &paths.regex_cache_path {
+        build_info.regex_cache().save(path)?;
+    }
 
-    let rules = Rules::from_xml(&opts.grammar_path, &mut build_info, rules_options);
+    let (mut rules, rule_errors, rules_report) = match rules {
+        Ok(rules) => rules,
+        Err(Error::RuleErrors(rule_errors)) => {
+            errors.extend(rule_errors);
+            return Err(Error::RuleErrors(errors));
+        }
+        Err(error) => return Err(error),
+    };
+    errors.extend(rule_errors);
+    rules.ignore_words = IgnoreWords::from_dumps(&paths.ignore_word_paths)?;
+
+    Ok(Compiled {
+        tokenizer,
+        rules,
+        tokenizer_report,
+        rules_report,
+        errors,
+    })
+}
+
+/// Compiles the tokenizer and the rules with [compile_with_report] and writes them and the reports
+/// to the paths in `opts`. This is what the `compile` binary does.
+///
+/// Returns the result of checking the grammar rules against their examples
+/// if [BuildOptions::validate_examples] or [BuildOptions::validation_json_path] is set.
+pub fn build(opts: &BuildOptions) -> Result<CompileOutput, Error> {
+    // fail before compiling for minutes if the binaries can not be written
+    opts.compress.supported().map_err(crate::Error::from)?;
+
+    let compiled = match compile_with_report(opts.paths.clone(), opts.options.clone()) {
+        Ok(compiled) => compiled,
+        Err(Error::RuleErrors(errors)) => {
+            write_errors(opts, &errors)?;
+            return Err(Error::RuleErrors(errors));
+        }
+        Err(error) => return Err(error),
+    };
+    write_errors(opts, &compiled.errors)?;
+    write_report(opts, &compiled.tokenizer_report, &compiled.rules_report)?;
+
+    let Compiled {
+        tokenizer,
+        rules,
+        tokenizer_report,
+        rules_report,
+        ..
+    } = compiled;
+
+    if let (Some(chunker), Some(path)) = (tokenizer.chunker(), &opts.out_chunker_path) {
+        let f = BufWriter::new(File::create(path)?);
+        chunker.to_writer(f).map_err(crate::Error::from)?;
+    }
+
+    let f = BufWriter::new(File::create(&opts.out_tokenizer_path)?);
+    tokenizer.to_writer_compressed(f, opts.compress)?;
+
+    let f = BufWriter::new(File::create(&opts.out_rules_path)?);
+    rules.to_writer_compressed(f, opts.compress)?;
+
+    let mut output = CompileOutput {
+        tokenizer_report,
+        rules_report,
+        validation: None,
+    };
+    if !opts.validate_examples && opts.validation_json_path.is_none() {
+        return Ok(output);
+    }
+
+    let report = rules.validate(&tokenizer);
+    info!(
+        "{} of {} rules pass their examples.",
+        report.passed().count(),
+        report.rules.len()
+    );
+
+    if let Some(path) = &opts.validation_json_path {
+        let f = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(f, &report).map_err(std::io::Error::from)?;
+    }
+
+    output.validation = Some(report);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs::File,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{
+        compile,
+        parse_structure::RegexCacheKey,
+        testing::{
+            build_info_for, compile_rules_with_errors, setup, tiny_rules, write_temp,
+            DISAMBIGUATION, GRAMMAR,
+        },
+        BuildInfo, CompileOptions, CompilePaths, CompileProgress, CompileReport,
+        Error as CompileError, OutcomeCounts, RegexCache, RuleKind, RuleOutcome, UnknownTagReport,
+    };
+    use crate::{
+        rule::engine::composition::{Matcher, TextMatcher},
+        rules::{MatchStats, Rules, RulesOptions},
+        tokenizer::{tag::Tagger, SpecialTokenRule, Tokenizer, TokenizerOptions},
+        types::DefaultHashSet,
+        utils::{parallelism::ParallelismConfig, regex::SerializeRegex},
+        BinaryError, Compression, DuplicateIdPolicy, Error, ErrorPolicy, Parallelism,
+    };
+
+    #[test]
+    fn compile_builds_the_fixture_from_files() {
+        let options = TokenizerOptions::builder()
+            .special_token_rules(vec![
+                SpecialTokenRule::new("[0-9]+([.,][0-9]+)*", "CD"),
+                SpecialTokenRule::new("[0-9]*(1st|2nd|3rd|[04-9]th|1[1-3]th)", "JJ"),
+                SpecialTokenRule::new("[0-9]*(1st|2nd|3rd|[04-9]th|1[1-3]th)", "ORD"),
+            ])
+            .build()
+            .unwrap();
+        let path =
+            |name: &str, content: &str| write_temp(name, content).to_str().unwrap().to_string();
+
+        let paths = CompilePaths {
+            tag_paths: vec![path(
+                "compile_tags.txt",
+                "here\there\tRB\nNext\tnext\tJJ\nruns\trun\tVBZ\nthe\tthe\tDT\ntool\ttool\tNN\n\
+                 go\tgo\tVB\nwent\tgo\tVBD\ngone\tgo\tVBN\n",
+            )],
+            disambiguation_path: path("compile_disambiguation.xml", DISAMBIGUATION),
+            grammar_path: path("compile_grammar.xml", GRAMMAR),
+            tokenizer_config_path: path(
+                "compile_tokenizer.json",
+                &serde_json::to_string(&options).unwrap(),
+            ),
+            rules_config_path: path("compile_rules.json", "{}"),
+            multiword_path: Some(path(
+                "compile_multiwords.txt",
+                "# comment\nNew York\tNNP\nNew York City\tNNP\n",
+            )),
+            ..CompilePaths::default()
+        };
+
+        let (tokenizer, rules) = compile(paths.clone(), CompileOptions::default()).unwrap();
+
+        // the same as building the tagger, the tokenizer and the rules one by one
+        let (expected_tokenizer, expected_rules) = setup();
+        assert!(
+            bincode::serialize(&tokenizer).unwrap()
+                == bincode::serialize(&expected_tokenizer).unwrap()
+        );
+        assert!(
+            bincode::serialize(&rules).unwrap() == bincode::serialize(&expected_rules).unwrap()
+        );
+        assert_eq!(
+            rules.suggest("He uses tool.", &tokenizer)[0].source,
+            "USES_NOUN"
+        );
+
+        let missing = CompilePaths {
+            rules_config_path: "missing.json".into(),
+            ..paths.clone()
+        };
+        assert!(matches!(
+            compile(missing, CompileOptions::default()),
+            Err(CompileError::Io(_))
+        ));
+
+        // an invalid special token regex in the options is an error, not a panic
+        let mut invalid_options = options;
+        invalid_options.special_token_rules = vec![SpecialTokenRule::new("[0-9", "CD")];
+        let invalid_regex = CompilePaths {
+            tokenizer_config_path: path(
+                "compile_invalid_tokenizer.json",
+                &serde_json::to_string(&invalid_options).unwrap(),
+            ),
+            ..paths
+        };
+        assert!(matches!(
+            compile(invalid_regex, CompileOptions::default()),
+            Err(CompileError::Unexpected { .. })
+        ));
+    }
+
+    #[test]
+    fn rules_are_checked_against_the_tokenizer() {
+        let (tokenizer, rules) = setup();
+
+        let header = rules.header().unwrap();
+        assert_eq!(header.lang_code.as_deref(), Some("en"));
+        assert_eq!(header.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(header.fingerprint, tokenizer.tagger().fingerprint());
+        assert_eq!(tokenizer.header(), Some(header));
+
+        let bytes = bincode::serialize(&rules).unwrap();
+        let loaded = Rules::load_from(bytes.as_slice(), &tokenizer).unwrap();
+        assert_eq!(loaded.rules().len(), rules.rules().len());
+
+        // binaries start with a header
+        let mut other_format = bytes.clone();
+        other_format[0] = other_format[0].wrapping_add(1);
+        let error = Rules::new_from(other_format.as_slice()).err().unwrap();
+        assert!(matches!(error, Error::Binary(BinaryError::MissingHeader)));
+
+        // a tokenizer with one more word assigns other IDs
+        let tag_path = write_temp("other_tags.txt", "here\there\tRB\nthere\tthere\tRB\n");
+        let tagger = Tagger::from_dumps::<_, &str, String>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+        let other = Tokenizer::from_xml_reader(
+            r#"<?xml version="1.0" encoding="UTF-8"?><rules lang="en"></rules>"#.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+        assert_ne!(other.header().unwrap().fingerprint, header.fingerprint);
+
+        let error = match Rules::load_from(bytes.as_slice(), &other) {
+            Err(error @ Error::IncompatibleBinaries { .. }) => error,
+            _ => panic!("rules of another tagger are loaded"),
+        };
+        assert!(error
+            .to_string()
+            .starts_with("the rules (language en, nlprule "));
+
+        // unchecked loading is still possible, rules which are not compiled are not checked
+        assert!(Rules::new_from(bytes.as_slice()).is_ok());
+        assert!(Rules::default().check_tokenizer(&other).is_ok());
+    }
+
+    /// Writes the binaries of [setup] and the JSON of [tiny_rules] to `src/compile/fixtures`.
+    /// Has to be run when the binary format or the structure of compiled rules changes.
+    #[test]
+    #[ignore]
+    fn write_fixtures() {
+        let (tokenizer, rules) = setup();
+
+        std::fs::create_dir_all("src/compile/fixtures").unwrap();
+        let tokenizer_file = File::create("src/compile/fixtures/tokenizer.bin").unwrap();
+        tokenizer
+            .to_writer_compressed(tokenizer_file, Compression::None)
+            .unwrap();
+        let rules_file = File::create("src/compile/fixtures/rules.bin").unwrap();
+        rules
+            .to_writer_compressed(rules_file, Compression::None)
+            .unwrap();
+
+        let (tagger, rules) = tiny_rules();
+        let json: serde_json::Value =
+            serde_json::from_str(&rules.to_json_pretty_with(&tagger)).unwrap();
+        let json = serde_json::to_string_pretty(&json["rules"]).unwrap();
+        std::fs::write("src/compile/fixtures/tiny_rules.json", json + "\n").unwrap();
+    }
+
+    #[test]
+    fn compile_errors_are_typed() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="BAD_REGEX" name="Bad regex">
+            <pattern>
+                <token regexp="yes">(here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+        <rule id="UNKNOWN_ATTRIBUTE" name="Unknown attribute">
+            <pattern>
+                <token unknown="yes">here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+        <rule id="AND_CHUNK" name="Chunk and chunk regex">
+            <pattern>
+                <token chunk="B-NP" chunk_re="I-NP">here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+        <rule id="NEGATE_CHUNK" name="Unknown negate_chunk value">
+            <pattern>
+                <token chunk="B-NP" negate_chunk="maybe">here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <rule id="UNKNOWN_TAG" name="Unknown tag">
+        <pattern>
+            <token>here</token>
+        </pattern>
+        <disambig postag="XYZ" />
+    </rule>
+</rules>
+"#;
+
+        let tag_path = write_temp("error_tags.txt", "here\there\tRB\n");
+        let tagger = Tagger::from_dumps::<_, &str, _>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[] as &[String],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let grammar_path = write_temp("error_grammar.xml", grammar);
+        let (rules, errors) =
+            Rules::from_xml_with_errors(&grammar_path, &mut build_info, RulesOptions::default())
+                .unwrap();
+        assert!(rules.rules().is_empty());
+
+        let errors: Vec<_> = errors.iter().map(|x| (x.kind(), x.rule_id())).collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Regex", Some("BAD_REGEX")),
+                ("Xml", Some("UNKNOWN_ATTRIBUTE")),
+                ("UnsupportedFeature", Some("AND_CHUNK")),
+                ("Unexpected", Some("NEGATE_CHUNK")),
+            ]
+        );
+
+        let disambiguation_path = write_temp("error_disambiguation.xml", disambiguation);
+        let result = Tokenizer::from_xml(
+            &disambiguation_path,
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        );
+        let error = match result {
+            Err(CompileError::RuleErrors(mut errors)) if errors.len() == 1 => errors.remove(0),
+            _ => panic!("expected exactly one rule error"),
+        };
+        assert!(matches!(
+            &error,
+            CompileError::UnknownPosTag { tag, suggestions, rule_id: Some(id) }
+                if tag == "XYZ" && suggestions.is_empty() && id == "UNKNOWN_TAG"
+        ));
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({
+                "kind": "UnknownPosTag",
+                "rule_id": "UNKNOWN_TAG",
+                "message": "unknown part-of-speech tag `XYZ`",
+            })
+        );
+
+        // with a policy which allows errors the rule is skipped instead
+        let options = TokenizerOptions::builder()
+            .error_policy(ErrorPolicy::Ignore)
+            .build()
+            .unwrap();
+        let (tokenizer, errors) = Tokenizer::from_xml_with_errors(
+            &disambiguation_path,
+            &mut build_info,
+            None,
+            None,
+            options,
+        )
+        .unwrap();
+        assert!(tokenizer.rules().is_empty());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn unknown_tags_suggest_closest_tags() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="FIRST_TYPO" name="First typo">
+            <pattern>
+                <token postag="NNSP" />
+            </pattern>
+            <message>Did you mean <suggestion>tool</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="SECOND_TYPO" name="Second typo">
+            <pattern>
+                <token>the</token>
+                <token postag="NNSP" />
+            </pattern>
+            <message>Did you mean <suggestion>tool</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="NO_SUGGESTION" name="No suggestion">
+            <pattern>
+                <token postag="XYZ" />
+            </pattern>
+            <message>Did you mean <suggestion>tool</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="REGEX" name="Regex">
+            <pattern>
+                <token postag="NNSP?" postag_regexp="yes" />
+            </pattern>
+            <message>Did you mean <suggestion>tool</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let tag_path = write_temp(
+            "typo_tags.txt",
+            "tool\ttool\tNN\ntools\ttool\tNNS\nSmith\tSmith\tNNP\nSmiths\tSmith\tNNPS\n",
+        );
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rules.rules().len(), 1);
+
+        let messages: Vec<_> = errors.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown part-of-speech tag `NNSP`, did you mean `NNP`, `NNS` or `NN`?",
+                "unknown part-of-speech tag `NNSP`, did you mean `NNP`, `NNS` or `NN`?",
+                "unknown part-of-speech tag `XYZ`",
+            ]
+        );
+
+        let (_, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            report.unknown_tags["NNSP"],
+            UnknownTagReport {
+                suggestions: vec!["NNP".into(), "NNS".into(), "NN".into()],
+                rule_ids: vec!["FIRST_TYPO".into(), "SECOND_TYPO".into()],
+            }
+        );
+        assert!(report.unknown_tags["XYZ"].suggestions.is_empty());
+    }
+
+    #[test]
+    fn compile_report_lists_outcomes() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="FIRST" name="First">
+        <rule id="GOOD" name="Good">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">It is <marker>here</marker>.</example>
+        </rule>
+        <rule id="BAD_REGEX" name="Bad regex">
+            <pattern>
+                <token regexp="yes">(here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+    </category>
+    <category id="SECOND" name="Second">
+        <rule id="IGNORED" name="Ignored">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>where</suggestion>?</message>
+            <example correction="where">It is <marker>here</marker>.</example>
+        </rule>
+        <rule id="UNKNOWN_ATTRIBUTE" name="Unknown attribute">
+            <pattern>
+                <token unknown="yes">here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <rule id="UNKNOWN_TAG" name="Unknown tag">
+        <pattern>
+            <token>here</token>
+        </pattern>
+        <disambig postag="XYZ" />
+    </rule>
+    <rule id="AFTER_ERROR" name="After error">
+        <pattern>
+            <token>here</token>
+        </pattern>
+        <disambig action="remove" postag="RB" />
+    </rule>
+</rules>
+"#;
+
+        let tag_path = write_temp("report_tags.txt", "here\there\tRB\n");
+        let tagger = Tagger::from_dumps::<_, &str, _>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[] as &[String],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let options = RulesOptions::builder()
+            .ignore_ids(vec!["IGNORED".into()])
+            .build()
+            .unwrap();
+        let (rules, report) =
+            Rules::from_xml_reader_with_report(grammar.as_bytes(), &mut build_info, options)
+                .unwrap();
+        assert_eq!(rules.rules().len(), 1);
+
+        let outcomes: Vec<_> = report
+            .rules
+            .iter()
+            .map(|x| (x.id.as_deref(), x.category_id.as_deref(), &x.outcome))
+            .collect();
+        assert_eq!(outcomes[0], (Some("GOOD"), Some("FIRST"), &RuleOutcome::Ok));
+        assert!(matches!(
+            outcomes[1],
+            (Some("BAD_REGEX"), Some("FIRST"), RuleOutcome::Error { kind, .. }) if kind == "Regex"
+        ));
+        assert_eq!(
+            outcomes[2],
+            (Some("IGNORED"), Some("SECOND"), &RuleOutcome::FilteredOut)
+        );
+        // the category is not known if the XML is malformed, the ID is taken from the attribute
+        assert!(matches!(
+            outcomes[3],
+            (Some("UNKNOWN_ATTRIBUTE"), None, RuleOutcome::Error { kind, .. }) if kind == "Xml"
+        ));
+
+        assert_eq!(
+            report.total,
+            OutcomeCounts {
+                ok: 1,
+                error: 2,
+                filtered_out: 1
+            }
+        );
+        let categories: Vec<_> = report.categories.keys().map(|x| x.as_str()).collect();
+        assert_eq!(categories, vec!["FIRST", "SECOND"]);
+        assert_eq!(report.categories["SECOND"].filtered_out, 1);
+        let errors: Vec<_> = report
+            .errors
+            .iter()
+            .map(|(k, v)| (k.as_str(), *v))
+            .collect();
+        assert_eq!(errors, vec![("Regex", 1), ("Xml", 1)]);
+
+        let mut json = Vec::new();
+        report.to_writer(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["rules"][0]["outcome"], "Ok");
+        assert_eq!(json["rules"][1]["outcome"]["Error"]["kind"], "Regex");
+        assert_eq!(json["total"]["error"], 2);
+
+        let options = TokenizerOptions::builder()
+            .error_policy(ErrorPolicy::Ignore)
+            .build()
+            .unwrap();
+        let (tokenizer, report) = Tokenizer::from_xml_reader_with_report(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            options,
+        )
+        .unwrap();
+        assert!(tokenizer.rules().is_empty());
+        let outcomes: Vec<_> = report.rules.iter().map(|x| &x.outcome).collect();
+        assert!(matches!(outcomes[0], RuleOutcome::Error { kind, .. } if kind == "UnknownPosTag"));
+        // disambiguation rules after an error are left out
+        assert_eq!(outcomes[1], &RuleOutcome::FilteredOut);
+    }
+
+    #[test]
+    fn rules_can_be_compiled_from_memory() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="HAVE_WENT" name="Have went">
+            <pattern>
+                <token>have</token>
+                <token>went</token>
+            </pattern>
+            <message>Did you mean <suggestion>have gone</suggestion>?</message>
+            <example correction="have gone">I <marker>have went</marker> home.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+</rules>
+"#;
+
+        let tagger =
+            Tagger::from_dumps::<&str, &str, &str>(&[], &[], &[], &HashSet::new()).unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let tokenizer = Tokenizer::from_xml_reader(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+        let rules =
+            Rules::from_xml_reader(grammar.as_bytes(), &mut build_info, RulesOptions::default())
+                .unwrap();
+
+        let suggestions = rules.suggest("I have went home.", &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].source, "HAVE_WENT");
+        assert_eq!(suggestions[0].replacements, vec!["have gone"]);
+    }
+
+    #[test]
+    fn regex_cache_is_persisted() {
+        let key = RegexCacheKey {
+            regex: "^(went|gone)$".to_string(),
+            case_sensitive: false,
+            negate: false,
+            empty_always_false: false,
+        };
+        let set: DefaultHashSet<u32> = vec![1, 2].into_iter().collect();
+        let path = write_temp("regex_cache.bin", "");
+
+        let cache = RegexCache::load(&path, 1);
+        assert_eq!(cache.get(&key), None);
+        cache.insert(key.clone(), Some(set.clone()));
+        cache.save(&path).unwrap();
+
+        assert_eq!(RegexCache::load(&path, 1).get(&key), Some(Some(set)));
+        // the word store changed
+        assert_eq!(RegexCache::load(&path, 2).get(&key), None);
+
+        let path = write_temp("broken_regex_cache.bin", "not a cache");
+        assert_eq!(RegexCache::load(&path, 1).get(&key), None);
+    }
+
+    #[test]
+    fn distinct_regexes_do_not_share_cache_entries() {
+        let (tokenizer, _) = setup();
+        let build_info = build_info_for(&tokenizer);
+        let matcher = |regex: &str, info: &mut BuildInfo| {
+            let regex = SerializeRegex::new(regex, true, true).unwrap();
+            TextMatcher::new(Matcher::new_regex(regex, false, false), info).set
+        };
+        let words = |words: &[&str]| -> Option<DefaultHashSet<u32>> {
+            let word_store = tokenizer.tagger().words();
+            Some(
+                words
+                    .iter()
+                    .map(|x| word_store.get_id(x).unwrap())
+                    .collect(),
+            )
+        };
+
+        let mut info = build_info.clone();
+        assert_eq!(matcher("went|gone", &mut info), words(&["went", "gone"]));
+        assert_eq!(matcher("went|go", &mut info), words(&["went", "go"]));
+        assert_eq!(matcher("went|gone", &mut info), words(&["went", "gone"]));
+        assert_eq!(info.regex_cache().misses(), 2);
+        assert_eq!(info.regex_cache().fallbacks(), 0);
+
+        // sets with more words than the maximum are not used, also if they are cached
+        let mut info = build_info.with_max_regex_set_size(1);
+        assert_eq!(matcher("went|gone", &mut info), None);
+        assert_eq!(matcher("here", &mut info), words(&["here"]));
+        assert_eq!(info.regex_cache().fallbacks(), 1);
+    }
+
+    #[test]
+    fn unknown_ids_in_the_options_are_reported() {
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+
+        let options = |policy| {
+            RulesOptions::builder()
+                .error_policy(policy)
+                .ids(vec!["HAVE_WENT".into()])
+                .ignore_ids(vec!["HAVE_WNT".into(), "XYZZY".into()])
+                .build()
+                .unwrap()
+        };
+        let (rules, report) = Rules::from_xml_reader_with_report(
+            GRAMMAR.as_bytes(),
+            &mut build_info,
+            options(ErrorPolicy::WarnAboveThreshold(0)),
+        )
+        .unwrap();
+        assert_eq!(rules.rules().len(), 1);
+        assert_eq!(
+            report.unknown_ids.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("HAVE_WNT".to_string(), vec!["HAVE_WENT".to_string()]),
+                ("XYZZY".to_string(), vec![]),
+            ]
+        );
+
+        let error = Rules::from_xml_reader(
+            GRAMMAR.as_bytes(),
+            &mut build_info,
+            options(ErrorPolicy::Fail),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "unknown rule ID `HAVE_WNT` in the options, did you mean `HAVE_WENT`?"
+        );
+
+        let options = TokenizerOptions::builder()
+            .ignore_ids(vec!["NEXT_AFTER_HER".into()])
+            .build()
+            .unwrap();
+        let error = Tokenizer::from_xml_reader(
+            DISAMBIGUATION.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            options,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.kind(), "UnknownRuleId");
+        assert_eq!(
+            error.to_string(),
+            "unknown rule ID `NEXT_AFTER_HER` in the options, did you mean `NEXT_AFTER_HERE`?"
+        );
+    }
+
+    #[test]
+    fn rules_from_multiple_files_are_combined() {
+        let grammar = |id: &str, word: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="{}" name="Test">
+            <pattern>
+                <token>{}</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#,
+                id, word
+            )
+        };
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <rule id="HERE_RB" name="here">
+        <pattern>
+            <token>here</token>
+        </pattern>
+        <disambig postag="RB" />
+    </rule>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+
+        let first = write_temp("multi_grammar_1.xml", &grammar("HERE", "here"));
+        let second = write_temp("multi_grammar_2.xml", &grammar("TOOL", "tool"));
+        let duplicate = write_temp("multi_grammar_3.xml", &grammar("HERE", "tool"));
+
+        let (rules, report) = Rules::from_xml_multi_with_report(
+            &[&first, &second],
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        let sources: Vec<_> = rules
+            .suggest("here is the tool", &tokenizer)
+            .into_iter()
+            .map(|x| x.source)
+            .collect();
+        assert_eq!(sources, vec!["HERE", "TOOL"]);
+        assert_eq!(
+            report.rules[1].file.as_deref(),
+            Some(second.to_str().unwrap())
+        );
+
+        let (rules, report) = Rules::from_xml_multi_with_report(
+            &[&first, &duplicate],
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rules.rules().len(), 1);
+        assert_eq!(
+            report.rules[1].outcome,
+            RuleOutcome::Error {
+                kind: "DuplicateId".into(),
+                message: format!(
+                    "duplicate rule ID, the ID is already used in `{}`",
+                    first.display()
+                ),
+            }
+        );
+
+        // the later rule replaces the earlier one
+        let options = RulesOptions::builder()
+            .duplicate_ids(DuplicateIdPolicy::LastWins)
+            .build()
+            .unwrap();
+        let (rules, report) =
+            Rules::from_xml_multi_with_report(&[&first, &duplicate], &mut build_info, options)
+                .unwrap();
+        let found: Vec<_> = rules
+            .suggest("here is the tool", &tokenizer)
+            .into_iter()
+            .map(|x| (x.source, x.start))
+            .collect();
+        assert_eq!(found, vec![("HERE".to_string(), 12)]);
+        assert_eq!(report.rules[0].outcome, RuleOutcome::FilteredOut);
+
+        let paths = [
+            write_temp("multi_disambiguation_1.xml", disambiguation),
+            write_temp("multi_disambiguation_2.xml", disambiguation),
+        ];
+        let result = Tokenizer::from_xml_multi(
+            &paths,
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        );
+        assert!(matches!(result, Err(CompileError::RuleErrors(x)) if x[0].kind() == "DuplicateId"));
+
+        let options = TokenizerOptions::builder()
+            .duplicate_ids(DuplicateIdPolicy::LastWins)
+            .build()
+            .unwrap();
+        let (_, report) =
+            Tokenizer::from_xml_multi_with_report(&paths, &mut build_info, None, None, options)
+                .unwrap();
+        assert_eq!(report.total.ok, 1);
+        assert_eq!(report.total.filtered_out, 1);
+    }
+
+    #[test]
+    fn group_references_are_validated() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="MATCH_IN_MESSAGE" name="Match in message">
+            <pattern>
+                <token>a</token>
+                <token>b</token>
+                <token>c</token>
+                <token>d</token>
+            </pattern>
+            <message>Did you mean <match no="5"/>? <suggestion>e</suggestion></message>
+            <example>here</example>
+        </rule>
+        <rule id="MATCH_IN_SUGGESTION_TEXT" name="Match in suggestion text">
+            <pattern>
+                <token skip="-1">a</token>
+                <token>b</token>
+            </pattern>
+            <message>Did you mean <suggestion>\3</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="REGEX_MARK" name="Regex mark">
+            <regexp mark="2">(a) b</regexp>
+            <message>Did you mean <suggestion>c</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="VALID" name="Valid">
+            <pattern>
+                <token>x</token>
+                <token>y</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="2"/> \1</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <rule id="DISAMBIGUATION_MATCH" name="Disambiguation match">
+        <pattern>
+            <token>here</token>
+            <token>runs</token>
+        </pattern>
+        <disambig action="filter"><match no="3" postag="VBZ"/></disambig>
+    </rule>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let (rules, errors) = compile_rules_with_errors(grammar, &tokenizer);
+
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(ids, vec!["VALID"]);
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|x| (x.rule_id().unwrap(), x.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "MATCH_IN_MESSAGE",
+                    "unexpected condition: match no=5 but pattern defines 4 groups".to_string()
+                ),
+                (
+                    "MATCH_IN_SUGGESTION_TEXT",
+                    "unexpected condition: match no=3 but pattern defines 2 groups".to_string()
+                ),
+                (
+                    "REGEX_MARK",
+                    "unexpected condition: mark=2 but pattern defines 1 group".to_string()
+                ),
+            ]
+        );
+
+        let (_, errors) = Tokenizer::from_xml_reader_with_errors(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::builder()
+                .error_policy(ErrorPolicy::Ignore)
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        let errors: Vec<_> = errors.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["unexpected condition: match no=3 but pattern defines 2 groups"]
+        );
+    }
+
+    #[test]
+    fn rules_without_id_name_or_category_are_errors() {
+        let rule = |attributes: &str| {
+            format!(
+                r#"<rule {}>
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>"#,
+                attributes
+            )
+        };
+        let grammar = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        {}
+        {}
+        {}
+        {}
+    </category>
+    <category id="NAMELESS">
+        {}
+    </category>
+</rules>
+"#,
+            rule(r#"id="FIRST" name="First""#),
+            rule(r#"name="No ID""#),
+            rule(r#"id="NO_NAME""#),
+            rule(r#"id="LAST" name="Last""#),
+            rule(r#"id="NO_CATEGORY" name="No category""#),
+        );
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <rule id="VALID" name="Valid">
+        <pattern>
+            <token>here</token>
+        </pattern>
+        <disambig action="ignore_spelling"/>
+    </rule>
+    <rule name="No ID">
+        <pattern>
+            <token>here</token>
+        </pattern>
+        <disambig action="ignore_spelling"/>
+    </rule>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let (rules, errors) = compile_rules_with_errors(&grammar, &tokenizer);
+
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(ids, vec!["FIRST", "LAST"]);
+        let errors: Vec<_> = errors.iter().map(|x| (x.kind(), x.to_string())).collect();
+        let error = |message: &str| ("Xml", format!("malformed XML: custom: {}", message));
+        assert_eq!(
+            errors,
+            vec![
+                error("rule at line 11 has no `id` and is not in a `rulegroup`"),
+                error("rule at line 18 has no `name` and is not in a `rulegroup`"),
+                error("rule at line 34 is not inside a `category` with `id` and `name`"),
+            ]
+        );
+
+        let options = TokenizerOptions::builder()
+            .error_policy(ErrorPolicy::Ignore)
+            .build()
+            .unwrap();
+        let (tokenizer, errors) = Tokenizer::from_xml_reader_with_errors(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            options,
+        )
+        .unwrap();
+        // disambiguation rules after an error are left out since they can depend on the rules before
+        let ids: Vec<_> = tokenizer.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(ids, vec!["VALID"]);
+        let errors: Vec<_> = errors.iter().map(|x| (x.kind(), x.to_string())).collect();
+        assert_eq!(
+            errors,
+            vec![error(
+                "rule at line 9 has no `id` and is not in a `rulegroup`"
+            )]
+        );
+
+        // with the strict policy the whole file fails
+        let options = RulesOptions::builder()
+            .error_policy(ErrorPolicy::Fail)
+            .build()
+            .unwrap();
+        assert!(Rules::from_xml_reader(grammar.as_bytes(), &mut build_info, options).is_err());
+    }
+
+    #[test]
+    fn progress_is_reported() {
+        let (tokenizer, _) = setup();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let callback_events = events.clone();
+        let mut build_info = build_info_for(&tokenizer)
+            .with_progress(move |x| callback_events.lock().unwrap().push(x));
+        let rules =
+            Rules::from_xml_reader(GRAMMAR.as_bytes(), &mut build_info, RulesOptions::default())
+                .unwrap();
+
+        let events = events.lock().unwrap();
+        let total = rules.rules().len();
+        assert_eq!(
+            events[..2],
+            [
+                CompileProgress::ParsingXml {
+                    kind: RuleKind::Grammar
+                },
+                CompileProgress::BuildingRules {
+                    kind: RuleKind::Grammar,
+                    done: 0,
+                    total
+                }
+            ]
+        );
+
+        let mut done: Vec<_> = events
+            .iter()
+            .filter_map(|x| match x {
+                CompileProgress::BuildingRules { done, .. } if *done > 0 => Some(*done),
+                _ => None,
+            })
+            .collect();
+        done.sort_unstable();
+        assert_eq!(done, (1..=total).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parallel_compilation_is_reproducible() {
+        let (tokenizer, _) = setup();
+        let text = "It ends here Next one. He lives in New York City. I have went home.";
+
+        let compile = |threads: usize| {
+            let mut build_info = build_info_for(&tokenizer);
+            let config = ParallelismConfig::new(Parallelism::Threads(threads)).unwrap();
+
+            config.install(|| {
+                let rules = Rules::from_xml_reader(
+                    GRAMMAR.as_bytes(),
+                    &mut build_info,
+                    RulesOptions::default(),
+                )
+                .unwrap();
+                let disambiguator = Tokenizer::from_xml_reader(
+                    DISAMBIGUATION.as_bytes(),
+                    &mut build_info,
+                    None,
+                    None,
+                    TokenizerOptions::default(),
+                )
+                .unwrap();
+
+                let rule_ids: Vec<_> = rules.rules().iter().map(|x| x.id().to_string()).collect();
+                let disambiguation_ids: Vec<_> = disambiguator
+                    .rules()
+                    .iter()
+                    .map(|x| x.id().to_string())
+                    .collect();
+
+                (
+                    rule_ids,
+                    disambiguation_ids,
+                    rules.suggest(text, &tokenizer),
+                    bincode::serialize(&rules).unwrap(),
+                    bincode::serialize(&disambiguator).unwrap(),
+                )
+            })
+        };
+
+        let sequential = compile(1);
+        assert_eq!(sequential.0.len(), 11);
+        assert!(!sequential.2.is_empty());
+        for threads in &[2, 4] {
+            assert_eq!(compile(*threads), sequential);
+        }
+    }
+
+    #[test]
+    fn streamed_rules_match_single_rule_documents() {
+        let header = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <phrases>
+        <phrase id="THE_TOOL">
+            <token>the</token>
+            <token>tool</token>
+        </phrase>
+    </phrases>
+"#;
+        let rule = |id: String| {
+            format!(
+                r#"<rule id="{0}" name="Rule {0}">
+                <pattern>
+                    <token>here</token>
+                    <marker>
+                        <token regexp="yes">{0}s?</token>
+                    </marker>
+                    <phraseref idref="THE_TOOL"/>
+                </pattern>
+                <message>Did you mean <suggestion>the  <match no="2"/></suggestion>?</message>
+                <example>here</example>
+            </rule>"#,
+                id
+            )
+        };
+
+        // each rule or rule group of the fixture, wrapped in its category
+        let mut parts = Vec::new();
+        for category in 0..10 {
+            for i in 0..20 {
+                let id = format!("C{}_R{}", category, i);
+                let xml = if i % 5 == 0 {
+                    format!(
+                        r#"<rulegroup id="{0}" name="Group {0}">{1}{2}</rulegroup>"#,
+                        id,
+                        rule(format!("{}_A", id)),
+                        rule(format!("{}_B", id))
+                    )
+                } else {
+                    rule(id)
+                };
+                parts.push(format!(
+                    r#"<category id="C{0}" name="Category {0}">{1}</category>"#,
+                    category, xml
+                ));
+            }
+        }
+
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let mut compile = |grammar: String| {
+            let (rules, errors) = Rules::from_xml_reader_with_errors(
+                grammar.as_bytes(),
+                &mut build_info,
+                RulesOptions::default(),
+            )
+            .unwrap();
+            assert!(errors.is_empty(), "{:?}", errors);
+
+            rules
+                .rules()
+                .iter()
+                .map(|x| (x.id().to_string(), bincode::serialize(x).unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        let streamed = compile(format!("{}{}</rules>", header, parts.join("\n")));
+        let single: Vec<_> = parts
+            .iter()
+            .flat_map(|part| compile(format!("{}{}</rules>", header, part)))
+            .collect();
+
+        assert_eq!(streamed.len(), 240);
+        assert!(streamed == single, "streamed rules differ");
+    }
+
+    #[test]
+    fn compilation_is_deterministic() {
+        // builds the tagger, tokenizer and rules from scratch
+        let compile = || {
+            let (tokenizer, rules) = setup();
+            (
+                bincode::serialize(&tokenizer).unwrap(),
+                bincode::serialize(&rules).unwrap(),
+            )
+        };
+
+        let first = compile();
+        for _ in 0..3 {
+            assert!(compile() == first, "compiled binaries differ");
+        }
+    }
+
+    #[test]
+    fn error_policy_is_applied() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="BAD_REGEX" name="Bad regex">
+            <pattern>
+                <token regexp="yes">(here</token>
+            </pattern>
+            <message>Message</message>
+            <example>here</example>
+        </rule>
+        <rule id="HERE" name="Here">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">It is <marker>here</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let tag_path = write_temp("policy_tags.txt", "here\there\tRB\n");
+        let tagger = Tagger::from_dumps::<_, &str, _>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[] as &[String],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+        let grammar_path = write_temp("policy_grammar.xml", grammar);
+
+        for policy in &[
+            ErrorPolicy::Ignore,
+            ErrorPolicy::WarnAboveThreshold(0),
+            ErrorPolicy::WarnAboveThreshold(10),
+        ] {
+            let options = RulesOptions::builder()
+                .error_policy(*policy)
+                .build()
+                .unwrap();
+            let (rules, errors) =
+                Rules::from_xml_with_errors(&grammar_path, &mut build_info, options).unwrap();
+
+            // the failing rule is skipped, the other one is kept
+            assert_eq!(rules.rules().len(), 1);
+            assert_eq!(errors.len(), 1);
+        }
+
+        let options = RulesOptions::builder()
+            .error_policy(ErrorPolicy::Fail)
+            .build()
+            .unwrap();
+        match Rules::from_xml(&grammar_path, &mut build_info, options) {
+            Err(CompileError::RuleErrors(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].rule_id(), Some("BAD_REGEX"));
+            }
+            _ => panic!("expected the rules to fail"),
+        }
+    }
+
+    #[test]
+    fn max_regex_set_size_does_not_change_suggestions() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="REGEX_SETS" name="Regex sets">
+            <pattern>
+                <token regexp="yes">th.*|an?</token>
+                <token inflected="yes" regexp="yes">go|tools?</token>
+                <or>
+                    <token regexp="yes">r.*s</token>
+                    <token regexp="yes">h.*e|\d+</token>
+                </or>
+            </pattern>
+            <message>Did you mean <suggestion>\2 \3</suggestion>?</message>
+            <example correction="went here">Next <marker>the went here</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let compile = |max_regex_set_size| {
+            let mut build_info =
+                build_info_for(&tokenizer).with_max_regex_set_size(max_regex_set_size);
+            Rules::from_xml_reader_with_report(
+                grammar.as_bytes(),
+                &mut build_info,
+                RulesOptions::default(),
+            )
+            .unwrap()
+        };
+
+        let (mut runtime, runtime_report) = compile(0);
+        let (mut stored, stored_report) = compile(10_000);
+
+        fn words(report: &CompileReport) -> Vec<(&str, &str, Option<usize>)> {
+            report
+                .regex_sets
+                .iter()
+                .map(|x| (x.rule_id.as_str(), x.regex.as_str(), x.words))
+                .collect()
+        }
+        assert_eq!(
+            words(&runtime_report),
+            [
+                ("REGEX_SETS", "^(th.*|an?)$", None),
+                ("REGEX_SETS", "^(go|tools?)$", None),
+                ("REGEX_SETS", "^(r.*s)$", None),
+                ("REGEX_SETS", "^(h.*e|\\d+)$", None),
+            ]
+        );
+        assert_eq!(
+            words(&stored_report),
+            [
+                ("REGEX_SETS", "^(th.*|an?)$", Some(1)),
+                ("REGEX_SETS", "^(go|tools?)$", Some(2)),
+                ("REGEX_SETS", "^(r.*s)$", Some(1)),
+                ("REGEX_SETS", "^(h.*e|\\d+)$", Some(1)),
+            ]
+        );
+
+        let texts = [
+            "the went here",
+            "the tool runs",
+            "a tools 42",
+            "the gone here",
+            "then goes rows",
+            "the the here",
+            "went here",
+        ];
+        let suggestions = |rules: &Rules| -> Vec<_> {
+            texts
+                .iter()
+                .map(|text| rules.suggest(text, &tokenizer))
+                .collect()
+        };
 
-    let f = BufWriter::new(File::create(&opts.regex_cache_path).unwrap());
-    bincode::serialize_into(f, build_info.mut_regex_cache()).unwrap();
+        stored.set_count_regex_fallbacks(true);
+        runtime.set_count_regex_fallbacks(true);
+        let stored_suggestions = suggestions(&stored);
+        let matched: Vec<_> = stored_suggestions.iter().map(|x| !x.is_empty()).collect();
+        assert_eq!(matched, [true, true, true, true, false, false, false]);
+        assert_eq!(suggestions(&runtime), stored_suggestions);
+        assert!(runtime.match_stats().regex_fallbacks > 0);
+        assert_eq!(stored.match_stats().regex_fallbacks, 0);
 
-    let f = BufWriter::new(File::create(&opts.out_rules_path).unwrap());
-    bincode::serialize_into(f, &rules).unwrap();
+        runtime.reset_match_stats();
+        assert_eq!(runtime.match_stats(), MatchStats::default());
+    }
 }
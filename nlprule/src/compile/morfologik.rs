@@ -0,0 +1,692 @@
+//! Reads the morfologik dictionaries shipped with LanguageTool i. e. a `.dict` file with the automaton
+//! in the FSA5 or CFSA2 format and a `.info` file with the conventions used to encode the entries.
+//! Each sequence accepted by the automaton is `word<SEP>encoded lemma<SEP>tag`.
+
+use std::path::Path;
+
+/// Every file starts with the magic bytes followed by the version of the format.
+const MAGIC: &[u8] = b"\\fsa";
+const VERSION_FSA5: u8 = 5;
+const VERSION_CFSA2: u8 = 0xc6;
+
+/// A trim code which removes the whole word, used if the lemma shares nothing with the word.
+const REMOVE_EVERYTHING: usize = 255;
+
+/// Guards against cycles in malformed automata, no word is this long.
+const MAX_SEQUENCE_LENGTH: usize = 4096;
+
+fn invalid<S: Into<String>>(path: &Path, message: S) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message.into()),
+    )
+}
+
+/// How the lemma is stored relative to the word, `fsa.dict.encoder` in the `.info` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LemmaEncoder {
+    /// The lemma is stored as is.
+    None,
+    /// The number of bytes to remove from the end of the word, then the bytes to append.
+    Suffix,
+    /// The number of bytes to remove from the start and from the end of the word, then the bytes to append.
+    Prefix,
+    /// The position and length of bytes to remove from the word, the number of bytes to remove from the end,
+    /// then the bytes to append.
+    Infix,
+}
+
+fn trim_code(encoded: &[u8], i: usize) -> Option<usize> {
+    encoded.get(i).map(|x| x.wrapping_sub(b'A') as usize)
+}
+
+impl LemmaEncoder {
+    /// Decodes the lemma of `word`. `None` if the encoded lemma is too short or removes more bytes than the word has.
+    pub(crate) fn decode(self, word: &[u8], encoded: &[u8]) -> Option<Vec<u8>> {
+        let (kept, appended): (Vec<&[u8]>, _) = match self {
+            LemmaEncoder::None => (vec![], encoded),
+            LemmaEncoder::Suffix => {
+                let mut suffix = trim_code(encoded, 0)?;
+                if suffix == REMOVE_EVERYTHING {
+                    suffix = word.len();
+                }
+                (
+                    vec![word.get(..word.len().checked_sub(suffix)?)?],
+                    &encoded[1..],
+                )
+            }
+            LemmaEncoder::Prefix => {
+                let mut prefix = trim_code(encoded, 0)?;
+                let mut suffix = trim_code(encoded, 1)?;
+                if prefix == REMOVE_EVERYTHING || suffix == REMOVE_EVERYTHING {
+                    prefix = word.len();
+                    suffix = 0;
+                }
+                let end = word.len().checked_sub(suffix)?;
+                (vec![word.get(prefix..end)?], &encoded[2..])
+            }
+            LemmaEncoder::Infix => {
+                let mut index = trim_code(encoded, 0)?;
+                let mut length = trim_code(encoded, 1)?;
+                let mut suffix = trim_code(encoded, 2)?;
+                if length == REMOVE_EVERYTHING || suffix == REMOVE_EVERYTHING {
+                    index = 0;
+                    length = word.len();
+                    suffix = 0;
+                }
+                let end = word.len().checked_sub(suffix)?;
+                (
+                    vec![word.get(..index)?, word.get(index + length..end)?],
+                    &encoded[3..],
+                )
+            }
+        };
+
+        let mut lemma = kept.concat();
+        lemma.extend_from_slice(appended);
+        Some(lemma)
+    }
+}
+
+/// The encoding of the bytes in the automaton, `fsa.dict.encoding` in the `.info` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    Latin1,
+}
+
+impl Encoding {
+    fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Encoding::Latin1 => Some(bytes.iter().map(|x| *x as char).collect()),
+        }
+    }
+}
+
+/// The conventions of a dictionary, read from its `.info` file.
+#[derive(Debug, Clone)]
+struct DictionaryInfo {
+    separator: u8,
+    encoding: Encoding,
+    encoder: LemmaEncoder,
+}
+
+impl DictionaryInfo {
+    /// Parses the `.info` file, a Java properties file. Only the keys needed to decode the entries are used.
+    fn parse(content: &str, path: &Path) -> std::io::Result<Self> {
+        let mut separator = "+".to_string();
+        let mut encoding = None;
+        let mut encoder = None;
+        let (mut prefixes, mut infixes) = (false, false);
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            }
+            let (key, value) = match line.find(['=', ':']) {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => continue,
+            };
+
+            match key {
+                "fsa.dict.separator" => separator = value.to_string(),
+                "fsa.dict.encoding" => encoding = Some(value.to_lowercase()),
+                "fsa.dict.encoder" => encoder = Some(value.to_uppercase()),
+                "fsa.dict.uses-prefixes" => prefixes = value == "true",
+                "fsa.dict.uses-infixes" => infixes = value == "true",
+                _ => {}
+            }
+        }
+
+        let encoding = match encoding.as_deref() {
+            Some("utf-8") | Some("utf8") => Encoding::Utf8,
+            Some("iso-8859-1") | Some("iso8859-1") | Some("latin1") => Encoding::Latin1,
+            Some(other) => {
+                return Err(invalid(
+                    path,
+                    format!(
+                        "unsupported encoding `{}`, only UTF-8 and ISO-8859-1 are supported",
+                        other
+                    ),
+                ))
+            }
+            None => return Err(invalid(path, "`fsa.dict.encoding` is missing")),
+        };
+        // dictionaries before the `encoder` key set the legacy flags instead
+        let encoder = match encoder.as_deref() {
+            Some("NONE") => LemmaEncoder::None,
+            Some("SUFFIX") => LemmaEncoder::Suffix,
+            Some("PREFIX") => LemmaEncoder::Prefix,
+            Some("INFIX") => LemmaEncoder::Infix,
+            Some(other) => return Err(invalid(path, format!("unknown encoder `{}`", other))),
+            None if infixes => LemmaEncoder::Infix,
+            None if prefixes => LemmaEncoder::Prefix,
+            None => LemmaEncoder::Suffix,
+        };
+        let separator = match separator.as_bytes() {
+            [x] => *x,
+            _ => {
+                return Err(invalid(
+                    path,
+                    format!("the separator `{}` is not a single byte", separator),
+                ))
+            }
+        };
+
+        Ok(DictionaryInfo {
+            separator,
+            encoding,
+            encoder,
+        })
+    }
+}
+
+/// The layout of the arcs of an automaton.
+#[derive(Debug)]
+enum Format {
+    /// Each arc is a label and the address of the target node with the flags in its lowest three bits.
+    /// The address takes `gtl` bytes, or one byte for the flags if the target node follows the arc.
+    Fsa5 { gtl: usize, node_data_length: usize },
+    /// Each arc is a flag byte with an index into `label_mapping` (or an explicit label if the index is zero)
+    /// and the address of the target node as variable-length integer unless the target node follows the arc.
+    Cfsa2 {
+        numbers: bool,
+        label_mapping: Vec<u8>,
+    },
+}
+
+mod fsa5 {
+    pub const FINAL: u8 = 1 << 0;
+    pub const LAST: u8 = 1 << 1;
+    pub const NEXT: u8 = 1 << 2;
+}
+
+mod cfsa2 {
+    pub const NEXT: u8 = 1 << 7;
+    pub const LAST: u8 = 1 << 6;
+    pub const FINAL: u8 = 1 << 5;
+    pub const LABEL_INDEX_MASK: u8 = 0x1f;
+    /// Set in the header if nodes store the number of sequences below them.
+    pub const NUMBERS: u16 = 1 << 8;
+}
+
+/// A morfologik automaton. Offsets which are out of bounds are `None`, the file is malformed then.
+#[derive(Debug)]
+pub(crate) struct Automaton {
+    arcs: Vec<u8>,
+    format: Format,
+}
+
+impl Automaton {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let read = |range: std::ops::Range<usize>| {
+            bytes
+                .get(range)
+                .ok_or_else(|| "the header is truncated".to_string())
+        };
+
+        if read(0..4)? != MAGIC {
+            return Err("not a morfologik automaton".into());
+        }
+
+        match read(4..5)?[0] {
+            VERSION_FSA5 => {
+                // the filler and annotation bytes are only used when building the automaton
+                let hgtl = read(7..8)?[0];
+                Ok(Automaton {
+                    arcs: bytes[8..].to_vec(),
+                    format: Format::Fsa5 {
+                        gtl: (hgtl & 0x0f) as usize,
+                        node_data_length: (hgtl >> 4) as usize,
+                    },
+                })
+            }
+            VERSION_CFSA2 => {
+                let flags = u16::from_be_bytes([read(5..6)?[0], read(6..7)?[0]]);
+                let mapping_size = read(7..8)?[0] as usize;
+                let label_mapping = read(8..8 + mapping_size)?.to_vec();
+                Ok(Automaton {
+                    arcs: bytes[8 + mapping_size..].to_vec(),
+                    format: Format::Cfsa2 {
+                        numbers: flags & cfsa2::NUMBERS != 0,
+                        label_mapping,
+                    },
+                })
+            }
+            version => Err(format!("unsupported automaton version {}", version)),
+        }
+    }
+
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.arcs.get(offset).copied()
+    }
+
+    fn read_vint(&self, mut offset: usize) -> Option<usize> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte(offset)?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+            offset += 1;
+            shift += 7;
+            if shift > 28 {
+                return None;
+            }
+        }
+    }
+
+    fn skip_vint(&self, mut offset: usize) -> Option<usize> {
+        while self.byte(offset)? & 0x80 != 0 {
+            offset += 1;
+        }
+        Some(offset + 1)
+    }
+
+    fn flags(&self, arc: usize) -> Option<u8> {
+        match self.format {
+            Format::Fsa5 { .. } => self.byte(arc + 1),
+            Format::Cfsa2 { .. } => self.byte(arc),
+        }
+    }
+
+    fn is_set(&self, arc: usize, fsa5: u8, cfsa2: u8) -> Option<bool> {
+        let flag = match self.format {
+            Format::Fsa5 { .. } => fsa5,
+            Format::Cfsa2 { .. } => cfsa2,
+        };
+        Some(self.flags(arc)? & flag != 0)
+    }
+
+    fn is_final(&self, arc: usize) -> Option<bool> {
+        self.is_set(arc, fsa5::FINAL, cfsa2::FINAL)
+    }
+
+    fn is_last(&self, arc: usize) -> Option<bool> {
+        self.is_set(arc, fsa5::LAST, cfsa2::LAST)
+    }
+
+    fn is_next_set(&self, arc: usize) -> Option<bool> {
+        self.is_set(arc, fsa5::NEXT, cfsa2::NEXT)
+    }
+
+    fn label(&self, arc: usize) -> Option<u8> {
+        match &self.format {
+            Format::Fsa5 { .. } => self.byte(arc),
+            Format::Cfsa2 { label_mapping, .. } => {
+                match (self.byte(arc)? & cfsa2::LABEL_INDEX_MASK) as usize {
+                    0 => self.byte(arc + 1),
+                    index => label_mapping.get(index).copied(),
+                }
+            }
+        }
+    }
+
+    fn skip_arc(&self, arc: usize) -> Option<usize> {
+        let next_set = self.is_next_set(arc)?;
+        match self.format {
+            Format::Fsa5 { gtl, .. } => Some(arc + if next_set { 2 } else { 1 + gtl }),
+            Format::Cfsa2 { .. } => {
+                let mut offset = arc + 1;
+                if self.byte(arc)? & cfsa2::LABEL_INDEX_MASK == 0 {
+                    offset += 1;
+                }
+                if next_set {
+                    Some(offset)
+                } else {
+                    self.skip_vint(offset)
+                }
+            }
+        }
+    }
+
+    /// The offset of the node the arc points to. Zero if the arc has no target i. e. no sequence continues after it.
+    fn target(&self, arc: usize) -> Option<usize> {
+        if self.is_next_set(arc)? {
+            return self.skip_arc(arc);
+        }
+
+        match self.format {
+            Format::Fsa5 { gtl, .. } => {
+                let mut address = 0;
+                for i in (0..gtl).rev() {
+                    address = address << 8 | self.byte(arc + 1 + i)? as usize;
+                }
+                Some(address >> 3)
+            }
+            Format::Cfsa2 { .. } => {
+                let explicit_label = self.byte(arc)? & cfsa2::LABEL_INDEX_MASK == 0;
+                self.read_vint(arc + if explicit_label { 2 } else { 1 })
+            }
+        }
+    }
+
+    fn first_arc(&self, node: usize) -> Option<usize> {
+        match self.format {
+            Format::Fsa5 {
+                node_data_length, ..
+            } => Some(node + node_data_length),
+            Format::Cfsa2 { numbers: true, .. } => self.skip_vint(node),
+            Format::Cfsa2 { numbers: false, .. } => Some(node),
+        }
+    }
+
+    fn next_arc(&self, arc: usize) -> Option<Option<usize>> {
+        if self.is_last(arc)? {
+            Some(None)
+        } else {
+            self.skip_arc(arc).map(Some)
+        }
+    }
+
+    fn root(&self) -> Option<usize> {
+        match self.format {
+            // the first node marks the terminal state, it is followed by an epsilon node pointing to the root
+            Format::Fsa5 { .. } => {
+                let epsilon = self.skip_arc(self.first_arc(0)?)?;
+                self.target(self.first_arc(epsilon)?)
+            }
+            Format::Cfsa2 { .. } => self.target(self.first_arc(0)?),
+        }
+    }
+
+    /// All sequences accepted by the automaton, in the order of the arcs.
+    pub(crate) fn sequences(&self) -> Option<Vec<Vec<u8>>> {
+        let mut sequences = Vec::new();
+        let mut sequence = Vec::new();
+        // the arcs from the root to the current arc
+        let mut path = vec![self.first_arc(self.root()?)?];
+
+        while let Some(&arc) = path.last() {
+            sequence.truncate(path.len() - 1);
+            sequence.push(self.label(arc)?);
+            if self.is_final(arc)? {
+                sequences.push(sequence.clone());
+            }
+
+            let target = self.target(arc)?;
+            if target != 0 {
+                if path.len() >= MAX_SEQUENCE_LENGTH {
+                    return None;
+                }
+                path.push(self.first_arc(target)?);
+                continue;
+            }
+
+            // continue with the next sibling of the deepest arc which has one
+            while let Some(arc) = path.pop() {
+                if let Some(next) = self.next_arc(arc)? {
+                    path.push(next);
+                    break;
+                }
+            }
+        }
+
+        Some(sequences)
+    }
+}
+
+/// Reads the entries of a morfologik dictionary as `(word, lemma, tag)`. The `.info` file must be next to
+/// the `.dict` file at `path`. Entries are in the order of the automaton i. e. sorted by their bytes.
+/// Entries which can not be decoded or have an empty word, lemma or tag are skipped with a warning.
+pub(crate) fn read_dictionary<P: AsRef<Path>>(
+    path: P,
+) -> std::io::Result<Vec<(String, String, String)>> {
+    let path = path.as_ref();
+    let info_path = path.with_extension("info");
+    let info = DictionaryInfo::parse(&std::fs::read_to_string(&info_path)?, &info_path)?;
+
+    let automaton = Automaton::from_bytes(&std::fs::read(path)?).map_err(|x| invalid(path, x))?;
+    let sequences = automaton
+        .sequences()
+        .ok_or_else(|| invalid(path, "the automaton is malformed"))?;
+
+    let mut skipped = 0;
+    let entries: Vec<_> = sequences
+        .iter()
+        .filter_map(|sequence| {
+            let entry = || -> Option<(String, String, String)> {
+                let mut parts = sequence.splitn(3, |x| *x == info.separator);
+                let (word, encoded, tag) = (parts.next()?, parts.next()?, parts.next()?);
+                let lemma = info.encoder.decode(word, encoded)?;
+
+                Some((
+                    info.encoding.decode(word)?,
+                    info.encoding.decode(&lemma)?,
+                    info.encoding.decode(tag)?,
+                ))
+            };
+
+            match entry() {
+                Some(entry)
+                    if !entry.0.is_empty() && !entry.1.is_empty() && !entry.2.is_empty() =>
+                {
+                    Some(entry)
+                }
+                _ => {
+                    log::warn!(
+                        "{}: skipping entry {:?}, expected `word{2}lemma{2}tag`",
+                        path.display(),
+                        String::from_utf8_lossy(sequence),
+                        info.separator as char,
+                    );
+                    skipped += 1;
+                    None
+                }
+            }
+        })
+        .collect();
+
+    if skipped > 0 {
+        log::warn!(
+            "{}: skipped {} of {} entries",
+            path.display(),
+            skipped,
+            sequences.len()
+        );
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        compile::{morfologik, testing::write_temp},
+        tokenizer::tag::Tagger,
+    };
+
+    /// Serializes the entries as morfologik automaton with suffix-encoded lemmas. The automaton is an
+    /// unminimized trie with fixed-size addresses which is enough for the reader. It is written from the
+    /// format description, not by morfologik itself.
+    fn morfologik_dictionary(entries: &[(&str, &str, &str)], cfsa2: bool) -> Vec<u8> {
+        let mut sequences: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(word, lemma, tag)| {
+                let prefix = word
+                    .bytes()
+                    .zip(lemma.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let mut sequence = word.as_bytes().to_vec();
+                sequence.push(b'+');
+                sequence.push(b'A' + (word.len() - prefix) as u8);
+                sequence.extend_from_slice(&lemma.as_bytes()[prefix..]);
+                sequence.push(b'+');
+                sequence.extend_from_slice(tag.as_bytes());
+                sequence
+            })
+            .collect();
+        sequences.sort();
+
+        // each node is a list of arcs (label, final, child node)
+        let mut nodes: Vec<Vec<(u8, bool, Option<usize>)>> = vec![Vec::new()];
+        for sequence in &sequences {
+            let mut node = 0;
+            for (i, label) in sequence.iter().enumerate() {
+                let is_last = i + 1 == sequence.len();
+                let arc = match nodes[node].iter().position(|x| x.0 == *label) {
+                    Some(arc) => arc,
+                    None => {
+                        nodes[node].push((*label, false, None));
+                        nodes[node].len() - 1
+                    }
+                };
+                if is_last {
+                    nodes[node][arc].1 = true;
+                } else {
+                    let child = match nodes[node][arc].2 {
+                        Some(child) => child,
+                        None => {
+                            nodes.push(Vec::new());
+                            nodes[node][arc].2 = Some(nodes.len() - 1);
+                            nodes.len() - 1
+                        }
+                    };
+                    node = child;
+                }
+            }
+        }
+
+        // FSA5: label and four address bytes, CFSA2: flags, explicit label and a four byte vint
+        let (arc_size, start) = if cfsa2 { (6, 6) } else { (5, 10) };
+        let mut offsets = vec![start];
+        for node in &nodes {
+            offsets.push(offsets.last().unwrap() + node.len() * arc_size);
+        }
+
+        let arc = |label: u8, is_final: bool, is_last: bool, target: usize| -> Vec<u8> {
+            if cfsa2 {
+                let flags = (is_final as u8) << 5 | (is_last as u8) << 6;
+                let mut bytes = vec![flags, label];
+                bytes.extend((0..4).map(|i| {
+                    let part = (target >> (7 * i) & 0x7f) as u8;
+                    if i < 3 {
+                        part | 0x80
+                    } else {
+                        part
+                    }
+                }));
+                bytes
+            } else {
+                let address = (target << 3) as u32 | is_final as u32 | (is_last as u32) << 1;
+                let mut bytes = vec![label];
+                bytes.extend_from_slice(&address.to_le_bytes());
+                bytes
+            }
+        };
+
+        let mut bytes = b"\\fsa".to_vec();
+        if cfsa2 {
+            bytes.extend_from_slice(&[0xc6, 0, 0, 0]);
+        } else {
+            bytes.extend_from_slice(&[5, b'_', b'+', 0x04]);
+            // the terminal node
+            bytes.extend(arc(0, false, true, 0));
+        }
+        bytes.extend(arc(b'^', false, true, offsets[0]));
+        for node in &nodes {
+            for (i, (label, is_final, child)) in node.iter().enumerate() {
+                let target = child.map_or(0, |x| offsets[x]);
+                bytes.extend(arc(*label, *is_final, i + 1 == node.len(), target));
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn morfologik_dictionaries_match_the_dump() {
+        let entries = [
+            ("went", "go", "VBD"),
+            ("gone", "go", "VBN"),
+            ("go", "go", "VB"),
+            ("goes", "go", "VBZ"),
+            ("here", "here", "RB"),
+            ("tools", "tool", "NNS"),
+            ("tool", "tool", "NN"),
+            ("tool", "tool", "VB"),
+            ("Über", "über", "IN"),
+        ];
+        let dump: String = entries
+            .iter()
+            .map(|(word, lemma, tag)| format!("{}\t{}\t{}\n", word, lemma, tag))
+            .collect();
+        let dump_path = write_temp("morfologik.txt", &dump);
+        let from_dump = Tagger::from_dumps::<_, &str, &str>(
+            &[dump_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+
+        let mut expected: Vec<_> = entries
+            .iter()
+            .map(|(a, b, c)| (a.to_string(), b.to_string(), c.to_string()))
+            .collect();
+        expected.sort();
+
+        for (name, cfsa2) in [("fsa5", false), ("cfsa2", true)].iter() {
+            let info = "# comment\nfsa.dict.separator=+\nfsa.dict.encoding=utf-8\nfsa.dict.encoder=SUFFIX\n";
+            write_temp(&format!("morfologik_{}.info", name), info);
+            let dict_path = std::env::temp_dir().join(format!(
+                "nlprule_{}_morfologik_{}.dict",
+                std::process::id(),
+                name
+            ));
+            // an entry without tag is skipped, the others are still read
+            let mut with_bad_entry = entries.to_vec();
+            with_bad_entry.push(("goer", "goer", ""));
+            std::fs::write(&dict_path, morfologik_dictionary(&with_bad_entry, *cfsa2)).unwrap();
+
+            let mut read = morfologik::read_dictionary(&dict_path).unwrap();
+            read.sort();
+            assert_eq!(read, expected, "{}", name);
+
+            let from_dict = Tagger::from_dumps::<_, &str, &str>(
+                &[dict_path.to_str().unwrap()],
+                &[],
+                &[],
+                &HashSet::new(),
+            )
+            .unwrap();
+            for (word, _, _) in entries.iter() {
+                let readings = |tagger: &Tagger| {
+                    let mut readings: Vec<_> = tagger
+                        .get_tags(word, false)
+                        .iter()
+                        .map(|x| (x.lemma().to_string(), x.pos().to_string()))
+                        .collect();
+                    readings.sort();
+                    readings
+                };
+                assert_eq!(readings(&from_dict), readings(&from_dump), "{}", name);
+            }
+        }
+
+        // the other encoders, `@` removes the whole word
+        use morfologik::LemmaEncoder;
+        assert_eq!(
+            LemmaEncoder::Suffix.decode(b"went", b"@go"),
+            Some(b"go".to_vec())
+        );
+        assert_eq!(LemmaEncoder::Suffix.decode(b"go", b"Dx"), None);
+        assert_eq!(
+            LemmaEncoder::None.decode(b"went", b"go"),
+            Some(b"go".to_vec())
+        );
+        assert_eq!(
+            LemmaEncoder::Prefix.decode(b"unhappier", b"CDy"),
+            Some(b"happy".to_vec())
+        );
+        assert_eq!(
+            LemmaEncoder::Infix.decode(b"abgesagt", b"CCBen"),
+            Some(b"absagen".to_vec())
+        );
+    }
+}
@@ -1,11 +1,24 @@
-use std::sync::Arc;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
 
 use super::structure;
-use crate::{filter::get_filter, utils, utils::regex::SerializeRegex, Error};
-use crate::{tokenizer::tag::Tagger, types::*};
-use lazy_static::lazy_static;
-use onig::Regex;
+use super::{CompileProgress, Error};
+use crate::{
+    filter::{get_filter, Filter},
+    utils,
+    utils::regex::{turkic_i, Regex, SerializeRegex},
+};
+use crate::{tokenizer::tag::Tagger, types::*, Header};
 use serde::{Deserialize, Serialize};
+use unicase::UniCase;
+use {lazy_static::lazy_static, once_cell::sync::OnceCell};
 
 pub use structure::{read_disambiguation_rules, read_rules};
 
@@ -14,55 +27,327 @@ use crate::rule::engine::composition::concrete::*;
 use crate::rule::engine::composition::*;
 use crate::rule::engine::*;
 use crate::rule::grammar::*;
-use crate::rule::{DisambiguationRule, Rule};
+use crate::rule::{DefaultState, DisambiguationRule, Rule};
 
 // TODO: should be an option in config OR restricted to one sentence
 fn max_matches() -> usize {
     20
 }
 
+/// The largest maximum number of tokens a part can take, see [Quantifier::try_new]. Far more than the tokens of
+/// any sentence, so larger maxima are most likely mistakes.
+pub(crate) const MAX_QUANTIFIER: usize = 1000;
+
+/// The case of tokens a regex of LanguageTool checks, if it is one of the common idioms for this. These are
+/// matched with a [CaseAtom] instead, which is much cheaper than the regex.
+pub(crate) fn case_idiom(regex: &str) -> Option<TokenCase> {
+    Some(match regex {
+        r"\p{Lu}.*" | r"[\p{Lu}].*" | r"\p{Lu}.+|\p{Lu}" => TokenCase::StartsUpper,
+        r"\p{Ll}.*" | r"[\p{Ll}].*" | r"\p{Ll}.+|\p{Ll}" => TokenCase::StartsLower,
+        r"\p{Lu}\p{Ll}+" | r"\p{Lu}[\p{Ll}]+" => TokenCase::Title,
+        r"\p{Lu}+" | r"[\p{Lu}]+" => TokenCase::Upper,
+        r"\p{Ll}+" | r"[\p{Ll}]+" => TokenCase::Lower,
+        _ => return None,
+    })
+}
+
+/// Parses a `min`, `max` or `skip` attribute named `name`, where `-1` means [max_matches].
+fn parse_count(value: Option<&str>, name: &str) -> Result<Option<usize>, Error> {
+    value
+        .map(|x| match x {
+            "-1" => Ok(max_matches()),
+            x => x
+                .parse()
+                .map_err(|_| Error::unexpected(format!("invalid `{}`: {}", name, x))),
+        })
+        .transpose()
+}
+
+/// Compiles a regex of a rule. On failure, the error keeps the regex for reporting.
+fn compile_regex(
+    regex: &str,
+    must_fully_match: bool,
+    case_sensitive: bool,
+) -> Result<SerializeRegex, Error> {
+    SerializeRegex::new(regex, must_fully_match, case_sensitive).map_err(|error| Error::Regex {
+        regex: regex.to_string(),
+        message: match error {
+            crate::Error::Unexpected(message) => message,
+            error => error.to_string(),
+        },
+        rule_id: None,
+    })
+}
+
+/// Compiles a regex matching the text of tokens, see [compile_regex]. If the regex is not case-sensitive, the
+/// dotted and dotless i are matched according to the case mapping.
+fn compile_text_regex(
+    regex: &str,
+    must_fully_match: bool,
+    case_sensitive: bool,
+    case_mapping: CaseMapping,
+) -> Result<SerializeRegex, Error> {
+    if case_mapping == CaseMapping::Turkic && (!case_sensitive || regex.contains("(?i")) {
+        compile_regex(&turkic_i(regex), must_fully_match, case_sensitive).map_err(|error| {
+            match error {
+                Error::Regex { message, .. } => Error::Regex {
+                    regex: regex.to_string(),
+                    message,
+                    rule_id: None,
+                },
+                error => error,
+            }
+        })
+    } else {
+        compile_regex(regex, must_fully_match, case_sensitive)
+    }
+}
+
+/// Gets the ID of a part-of-speech tag used in a rule. Fails if the tagger does not know the tag.
+fn id_tag<'a>(tag: &'a str, info: &BuildInfo) -> Result<PosId<'a>, Error> {
+    if info.tagger.tag_store().get_by_left(tag).is_some() {
+        Ok(info.tagger.id_tag(tag))
+    } else {
+        Err(Error::unknown_pos_tag(tag, &info.tagger))
+    }
+}
+
+type ProgressCallback = dyn Fn(CompileProgress) + Send + Sync;
+
+/// Identifies a regex in the [RegexCache]. Contains the full regex instead of a hash so entries
+/// can not collide and stay valid across compile runs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RegexCacheKey {
+    pub regex: String,
+    pub case_sensitive: bool,
+    pub negate: bool,
+    pub empty_always_false: bool,
+}
+
+/// Caches the word IDs matched by a regex. Can be shared between threads building rules in parallel
+/// and persisted between compile runs with [RegexCache::load] and [RegexCache::save].
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RegexCache {
-    cache: DefaultHashMap<u64, Option<DefaultHashSet<u32>>>,
+    cache: RwLock<DefaultHashMap<RegexCacheKey, Option<DefaultHashSet<u32>>>>,
     // this is compared with the hash of the word store of the tagger
     word_hash: u64,
+    #[serde(skip)]
+    hits: AtomicUsize,
+    #[serde(skip)]
+    misses: AtomicUsize,
+    #[serde(skip)]
+    fallbacks: AtomicUsize,
 }
 
 impl RegexCache {
     pub fn new(word_hash: u64) -> Self {
         RegexCache {
-            cache: DefaultHashMap::default(),
+            cache: RwLock::new(DefaultHashMap::default()),
             word_hash,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            fallbacks: AtomicUsize::new(0),
         }
     }
 
+    /// Loads the cache written by a previous run. Starts with an empty cache if the file does not exist,
+    /// can not be read or was written for a different word store.
+    pub fn load<P: AsRef<Path>>(path: P, word_hash: u64) -> Self {
+        let path = path.as_ref();
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return RegexCache::new(word_hash),
+        };
+
+        match bincode::deserialize_from::<_, RegexCache>(BufReader::new(file)) {
+            Ok(cache) if cache.word_hash == word_hash => cache,
+            Ok(_) => {
+                log::info!(
+                    "Word store changed, ignoring regex cache at {}.",
+                    path.display()
+                );
+                RegexCache::new(word_hash)
+            }
+            Err(error) => {
+                log::warn!(
+                    "Ignoring unreadable regex cache at {}: {}",
+                    path.display(),
+                    error
+                );
+                RegexCache::new(word_hash)
+            }
+        }
+    }
+
+    /// Writes the cache so it can be loaded by the next run.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let cached = self.hits.load(Ordering::Relaxed);
+        let total = cached + self.misses.load(Ordering::Relaxed);
+        log::info!(
+            "Regex cache: {} of {} regexes were cached, {} match too many words to be cached.",
+            cached,
+            total,
+            self.fallbacks()
+        );
+
+        let f = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(f, self).map_err(std::io::Error::other)?;
+        Ok(())
+    }
+
     pub fn word_hash(&self) -> &u64 {
         &self.word_hash
     }
 
+    /// The number of regexes which were not in the cache so far.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of regexes so far which match more words than [BuildInfo::max_regex_set_size]
+    /// and are thus matched against the text at runtime.
+    pub fn fallbacks(&self) -> usize {
+        self.fallbacks.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_fallback(&self) {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn set_word_hash(&self) -> &u64 {
         &self.word_hash
     }
 
-    pub fn get(&self, key: &u64) -> Option<&Option<DefaultHashSet<u32>>> {
-        self.cache.get(key)
+    pub(crate) fn get(&self, key: &RegexCacheKey) -> Option<Option<DefaultHashSet<u32>>> {
+        let value = self
+            .cache
+            .read()
+            .expect("regex cache lock is not poisoned")
+            .get(key)
+            .cloned();
+
+        if value.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        value
     }
 
-    pub fn insert(&mut self, key: u64, value: Option<DefaultHashSet<u32>>) {
-        self.cache.insert(key, value);
+    pub(crate) fn insert(&self, key: RegexCacheKey, value: Option<DefaultHashSet<u32>>) {
+        self.cache
+            .write()
+            .expect("regex cache lock is not poisoned")
+            .insert(key, value);
     }
 }
 
+/// The work done to build a rule which does not depend on the machine, see [RuleProfile][super::RuleProfile].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildStats {
+    pub regex_scans: usize,
+    pub set_words: usize,
+    /// Each regex matched against the words of the tagger with the number of words it matches if they are
+    /// stored, see [RegexSetReport][super::RegexSetReport].
+    pub regex_sets: Vec<(String, Option<usize>)>,
+}
+
+/// Information needed to build rules. Cheap to clone: clones share the tagger, the regex cache and the progress callback.
+/// The [BuildStats] are counted per clone so rules built in parallel are counted separately.
+#[derive(Clone)]
 pub struct BuildInfo {
     tagger: Arc<Tagger>,
-    regex_cache: RegexCache,
+    regex_cache: Arc<RegexCache>,
+    progress: Option<Arc<ProgressCallback>>,
+    lint: bool,
+    profile: bool,
+    max_regex_set_size: usize,
+    stats: BuildStats,
+    /// The case mapping of the language of the rule which is built.
+    case_mapping: CaseMapping,
+    /// The IDs of the words in the word store by their case-folded text. Built on first use.
+    folded_words: Arc<OnceCell<DefaultHashMap<UniCase<String>, Vec<u32>>>>,
 }
 
+/// The default of [BuildInfo::max_regex_set_size]. The vast majority of regexes matches less than 100 words
+/// from manual inspection, the few which match more would blow up the size of the binaries.
+pub const DEFAULT_MAX_REGEX_SET_SIZE: usize = 100;
+
 impl BuildInfo {
     pub fn new(tagger: Arc<Tagger>, regex_cache: RegexCache) -> Self {
         BuildInfo {
             tagger,
-            regex_cache,
+            regex_cache: Arc::new(regex_cache),
+            progress: None,
+            lint: false,
+            profile: false,
+            max_regex_set_size: DEFAULT_MAX_REGEX_SET_SIZE,
+            stats: BuildStats::default(),
+            case_mapping: CaseMapping::Default,
+            folded_words: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Sets whether the cost of building each rule is recorded, see [CompileReport::profiles][super::CompileReport::profiles].
+    pub fn with_profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub(crate) fn profiles(&self) -> bool {
+        self.profile
+    }
+
+    pub(crate) fn stats_mut(&mut self) -> &mut BuildStats {
+        &mut self.stats
+    }
+
+    /// Returns the stats counted since the last call.
+    pub(crate) fn take_stats(&mut self) -> BuildStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    /// Sets [BuildInfo::max_regex_set_size].
+    pub fn with_max_regex_set_size(mut self, max_regex_set_size: usize) -> Self {
+        self.max_regex_set_size = max_regex_set_size;
+        self
+    }
+
+    /// The maximum number of words a regex can match for the words to be stored in the binary. Regexes matching
+    /// more words are matched against the text at runtime instead. Cached sets of more words are also ignored,
+    /// but a cache written with a lower maximum has to be deleted for a higher maximum to take effect.
+    pub fn max_regex_set_size(&self) -> usize {
+        self.max_regex_set_size
+    }
+
+    /// Sets whether the compiled rules are checked for parts which can never match, see [CompileReport::lints][super::CompileReport::lints].
+    pub fn with_lint(mut self, lint: bool) -> Self {
+        self.lint = lint;
+        self
+    }
+
+    pub(crate) fn lints(&self) -> bool {
+        self.lint
+    }
+
+    /// Sets a callback which is called with the progress of building rules, see [CompileProgress].
+    pub fn with_progress<F: Fn(CompileProgress) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Whether a progress callback is set. Used to skip computing the progress if it is not.
+    pub(crate) fn reports_progress(&self) -> bool {
+        self.progress.is_some()
+    }
+
+    pub(crate) fn report(&self, progress: CompileProgress) {
+        if let Some(callback) = &self.progress {
+            callback(progress);
         }
     }
 
@@ -70,8 +355,52 @@ impl BuildInfo {
         &self.tagger
     }
 
-    pub fn mut_regex_cache(&mut self) -> &mut RegexCache {
-        &mut self.regex_cache
+    pub fn regex_cache(&self) -> &RegexCache {
+        &self.regex_cache
+    }
+
+    /// The [Header] of binaries built with this info.
+    pub(crate) fn header(&self, lang_code: Option<String>) -> Header {
+        Header {
+            lang_code,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            fingerprint: self.tagger.fingerprint(),
+        }
+    }
+
+    /// The case mapping of the language of the rule which is built.
+    pub(crate) fn case_mapping(&self) -> CaseMapping {
+        self.case_mapping
+    }
+
+    /// The IDs of the words in the word store which are equal to `word`, ignoring case unless `case_sensitive`.
+    pub(crate) fn word_ids(&self, word: &str, case_sensitive: bool) -> DefaultHashSet<u32> {
+        let word_store = self.tagger.words();
+
+        if case_sensitive {
+            return word_store.get_id(word).into_iter().collect();
+        }
+
+        // the folded words are only built for the default case mapping
+        if self.case_mapping != CaseMapping::Default {
+            return word_store
+                .iter()
+                .filter(|(x, _)| self.case_mapping.eq_ignore_case(x, word))
+                .map(|(_, id)| id)
+                .collect();
+        }
+
+        let folded_words = self.folded_words.get_or_init(|| {
+            let mut folded_words: DefaultHashMap<_, Vec<_>> = DefaultHashMap::default();
+            for (word, id) in word_store.iter() {
+                folded_words.entry(UniCase::new(word)).or_default().push(id);
+            }
+            folded_words
+        });
+
+        folded_words
+            .get(&UniCase::new(word.to_string()))
+            .map_or_else(DefaultHashSet::default, |ids| ids.iter().copied().collect())
     }
 }
 
@@ -135,9 +464,23 @@ fn parse_match_attribs(
     let mut inflect_matcher = None;
     let mut pos_matcher = None;
 
-    if text.is_some() || text_match_idx.is_some() {
+    let case = match text {
+        Some(text) if is_regex && case_sensitive && !inflected && text_match_idx.is_none() => {
+            case_idiom(text.trim())
+        }
+        _ => None,
+    };
+
+    if let Some(case) = case {
+        atoms.push(CaseAtom::case(case, negate));
+    } else if text.is_some() || text_match_idx.is_some() {
         let matcher = if is_regex && text_match_idx.is_none() {
-            let regex = SerializeRegex::new(text.unwrap().trim(), true, case_sensitive);
+            let regex = compile_text_regex(
+                text.unwrap().trim(),
+                true,
+                case_sensitive,
+                info.case_mapping(),
+            );
             Matcher::new_regex(regex?, negate, inflected)
         } else {
             Matcher::new_string(
@@ -149,6 +492,7 @@ fn parse_match_attribs(
                 case_sensitive,
                 inflected,
             )
+            .with_case_mapping(info.case_mapping())
         };
 
         if inflected {
@@ -165,7 +509,7 @@ fn parse_match_attribs(
 
     if let Some(postag) = attribs.postag() {
         let raw_matcher = if is_postag_regexp {
-            let regex = SerializeRegex::new(&postag.trim(), true, true);
+            let regex = compile_regex(postag.trim(), true, true);
             Matcher::new_regex(regex?, negate_pos, true)
         } else {
             Matcher::new_string(
@@ -175,13 +519,13 @@ fn parse_match_attribs(
                 true,
             )
         };
-        pos_matcher = Some(PosMatcher::new(raw_matcher, info));
+        pos_matcher = Some(PosMatcher::new(raw_matcher, info)?);
     }
 
     if pos_matcher.is_some() || inflect_matcher.is_some() {
         let matcher = WordDataMatcher {
             pos_matcher,
-            inflect_matcher: inflect_matcher.map(|x| TextMatcher::new(x, info)),
+            inflect_matcher: inflect_matcher.map(|x| TextMatcher::new_lemma(x, info)),
         };
         atoms.push(
             (WordDataAtom {
@@ -192,44 +536,54 @@ fn parse_match_attribs(
         );
     }
 
-    match (attribs.chunk(), attribs.chunk_re()) {
-        (Some(chunk), None) => {
-            let chunk_atom = ChunkAtom {
-                matcher: Matcher::new_string(
-                    either::Left(chunk.trim().to_string()),
-                    false,
-                    true,
-                    true,
-                ),
-            };
-            atoms.push(chunk_atom.into());
+    let negate_chunk = match attribs.negate_chunk().as_deref() {
+        Some("yes") => true,
+        None => false,
+        Some(x) => {
+            return Err(Error::unexpected(format!(
+                "unknown negate_chunk value `{}`",
+                x
+            )))
         }
+    };
+
+    let chunk_matcher = match (attribs.chunk(), attribs.chunk_re()) {
+        // an alternation like "B-NP-singular|E-NP-singular" is matched as regex
+        (Some(chunk), None) if chunk.contains('|') => {
+            let regex = compile_regex(chunk.trim(), true, true)?;
+            Some(Matcher::new_regex(regex, negate_chunk, true))
+        }
+        (Some(chunk), None) => Some(Matcher::new_string(
+            either::Left(chunk.trim().to_string()),
+            negate_chunk,
+            true,
+            true,
+        )),
         (None, Some(chunk_re)) => {
-            let regex = SerializeRegex::new(chunk_re.trim(), true, true)?;
-            let chunk_atom = ChunkAtom {
-                matcher: Matcher::new_regex(regex, false, true),
-            };
-            atoms.push(chunk_atom.into());
+            let regex = compile_regex(chunk_re.trim(), true, true)?;
+            Some(Matcher::new_regex(regex, negate_chunk, true))
         }
-        (None, None) => {}
-        _ => panic!("unexpected combination of chunk / chunk_re values."),
-    }
-
-    if let Some(chunk) = attribs.chunk() {
-        let chunk_atom = ChunkAtom {
-            matcher: Matcher::new_string(either::Left(chunk.trim().to_string()), false, true, true),
-        };
+        (None, None) => None,
+        (Some(_), Some(_)) => {
+            return Err(Error::unsupported(
+                "`chunk` and `chunk_re` on the same token",
+            ))
+        }
+    };
 
-        atoms.push(chunk_atom.into());
+    if let Some(matcher) = chunk_matcher {
+        atoms.push((ChunkAtom { matcher }).into());
     }
 
-    if let Some(space_before) = attribs.spacebefore() {
-        let value = match space_before.as_str() {
-            "yes" => true,
-            "no" => false,
-            _ => panic!("unknown spacebefore value {}", space_before),
-        };
+    // whether the token must (not) be preceded by whitespace, `ignore` is the same as leaving it out
+    let space_before = match attribs.spacebefore().as_deref() {
+        Some("yes") => Some(true),
+        Some("no") => Some(false),
+        Some("ignore") | None => None,
+        Some(x) => return Err(Error::unexpected(format!("invalid spacebefore={}", x))),
+    };
 
+    if let Some(value) = space_before {
         atoms.push((SpaceBeforeAtom { value }).into());
     }
 
@@ -242,48 +596,37 @@ fn get_exceptions(
     only_shifted: bool,
     info: &mut BuildInfo,
 ) -> Result<Atom, Error> {
-    if let Some(parts) = &token.parts {
-        let exceptions: Vec<Atom> = parts
-            .iter()
-            .filter_map(|x| match x {
-                structure::TokenPart::Exception(x) => Some(x),
-                _ => None,
-            })
-            .filter_map(|x| {
-                let exception_text = if let Some(exception_text) = &x.text {
-                    Some(exception_text.as_str())
-                } else {
-                    None
-                };
-                let mut atom =
-                    parse_match_attribs(x, exception_text, case_sensitive, None, info).unwrap();
-
-                let offset = if let Some(scope) = &x.scope {
-                    match scope.as_str() {
-                        "next" => 1,
-                        "current" => 0,
-                        "previous" => -1,
-                        _ => panic!("unknown scope value {}", scope),
-                    }
-                } else {
-                    0
-                };
+    let mut exceptions = Vec::new();
 
-                if offset != 0 {
-                    atom = OffsetAtom::new(atom, offset).into();
-                }
+    for exception in token.parts.iter().flatten().filter_map(|x| match x {
+        structure::TokenPart::Exception(x) => Some(x),
+        _ => None,
+    }) {
+        let exception_text = exception.text.as_ref().map(|x| x.as_str());
+
+        // an exception with `previous` or `next` scope applies to the token before or after the current token
+        let offset = match exception.scope.as_deref() {
+            None | Some("current") => 0,
+            Some("next") => 1,
+            Some("previous") => -1,
+            Some(x) => return Err(Error::unexpected(format!("unknown scope value `{}`", x))),
+        };
 
-                if !only_shifted || (offset != 0) {
-                    Some(atom)
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-        Ok(NotAtom::not(OrAtom::or(exceptions)))
-    } else {
-        Ok((TrueAtom {}).into())
+        if only_shifted && offset == 0 {
+            continue;
+        }
+
+        let mut atom = parse_match_attribs(exception, exception_text, case_sensitive, None, info)?;
+        if offset != 0 {
+            // like in LanguageTool, an exception for a token outside of the sentence is not triggered,
+            // also if it is negated
+            atom = OffsetAtom::new(atom, offset, false).into();
+        }
+
+        exceptions.push(atom);
     }
+
+    Ok(NotAtom::not(OrAtom::or(exceptions)))
 }
 
 fn parse_token(
@@ -301,42 +644,44 @@ fn parse_token(
         None
     };
 
+    // `no` of a `<match>` in a token is zero-based, group ids start at one
     let text_match_idx = if let Some(parts) = &token.parts {
-        parts.iter().find_map(|x| match x {
-            structure::TokenPart::Sub(sub) => Some(sub.no.parse::<usize>().unwrap() + 1),
-            _ => None,
-        })
+        parts
+            .iter()
+            .find_map(|x| match x {
+                structure::TokenPart::Sub(sub) => Some(
+                    sub.no
+                        .parse::<usize>()
+                        .map(|x| x + 1)
+                        .map_err(|_| Error::unexpected(format!("invalid match no={}", sub.no))),
+                ),
+                _ => None,
+            })
+            .transpose()?
     } else {
         None
     };
 
-    let min = token
-        .min
-        .clone()
-        .map(|x| {
-            if x == "-1" {
-                max_matches()
-            } else {
-                x.parse().expect("can't parse min as usize")
-            }
-        })
-        .unwrap_or(1usize);
-    let mut max = token
-        .max
-        .clone()
-        .map(|x| {
-            if x == "-1" {
-                max_matches()
-            } else {
-                x.parse().expect("can't parse max as usize")
-            }
-        })
-        .unwrap_or(1usize);
+    if matches!(text, Some(x) if !x.trim().is_empty()) && text_match_idx.is_some() {
+        return Err(Error::unsupported(
+            "text and `match` in the same token are not implemented.",
+        ));
+    }
+
+    let min = parse_count(token.min.as_deref(), "min")?.unwrap_or(1);
+    let mut max = parse_count(token.max.as_deref(), "max")?.unwrap_or(1);
     if min > 1 && max == 1 {
-        max = max_matches();
+        max = max_matches().max(min);
     }
 
-    let quantifier = Quantifier::new(min, max);
+    let greediness = match token.greediness.as_deref() {
+        Some("greedy") => Greediness::Greedy,
+        None | Some("lazy") => Greediness::Lazy,
+        Some("possessive") => Greediness::Possessive,
+        Some(x) => return Err(Error::unexpected(format!("unknown greediness value {}", x))),
+    };
+
+    let quantifier = Quantifier::try_new(min, max)?.with_greediness(greediness);
     let mut atom = parse_match_attribs(token, text, case_sensitive, text_match_idx, info)?;
     atom = AndAtom::and(vec![
         atom,
@@ -347,21 +692,13 @@ fn parse_token(
         atom,
         quantifier,
         visible: true,
-        greedy: true,
     });
 
-    if let Some(to_skip) = token.skip.clone() {
-        let to_skip = if to_skip == "-1" {
-            max_matches()
-        } else {
-            to_skip.parse().expect("can't parse skip as usize or -1")
-        };
-
+    if let Some(to_skip) = parse_count(token.skip.as_deref(), "skip")? {
         parts.push(Part {
             atom: get_exceptions(token, case_sensitive, true, info)?,
-            quantifier: Quantifier::new(0, to_skip),
+            quantifier: Quantifier::try_new(0, to_skip)?.with_greediness(Greediness::UntilNext),
             visible: false,
-            greedy: false,
         });
     }
 
@@ -371,35 +708,46 @@ fn parse_token(
 fn parse_match(
     m: structure::Match,
     composition: &Option<&Composition>,
+    case_mapping: CaseMapping,
     info: &mut BuildInfo,
 ) -> Result<Match, Error> {
-    if m.postag.is_some()
-        || m.postag_regex.is_some()
-        || m.postag_replace.is_some()
-        || m.text.is_some()
-    {
-        return Err(Error::Unimplemented(
-            "postag, postag_regex, postag_replace and text in `match` are not implemented.".into(),
-        ));
-    }
-
-    if m.include_skipped.is_some() {
-        return Err(Error::Unimplemented(
-            "include_skipped in `match` is not implemented.".into(),
+    if m.postag_replace.is_some() || m.text.is_some() {
+        return Err(Error::unsupported(
+            "postag_replace and text in `match` are not implemented.",
         ));
     }
 
-    let mut id =
+    let id =
         m.no.parse::<usize>()
-            .expect("no must be parsable as usize.");
+            .map_err(|_| Error::unexpected(format!("invalid match no={}", m.no)))?;
 
     if let Some(composition) = composition {
-        let last_id = get_last_id(&composition.parts) as usize - 1;
+        check_group_id("match no", id, get_last_id(&composition.parts) as usize - 1)?;
+    }
 
-        if id > last_id {
-            id = last_id;
+    let include_skipped = match (m.include_skipped.as_deref(), composition) {
+        (None, _) => false,
+        (Some("all"), Some(composition)) => {
+            // the skipped tokens are matched by an invisible part directly after the part of the token
+            let skip_part = composition
+                .group_ids_to_idx
+                .get(&id)
+                .and_then(|idx| composition.parts.get(*idx));
+
+            if !matches!(skip_part, Some(x) if !x.visible) {
+                return Err(Error::unexpected(
+                    "include_skipped in `match` must refer to a token with `skip`.",
+                ));
+            }
+            true
         }
-    }
+        (Some(x), _) => {
+            return Err(Error::unsupported(format!(
+                "include_skipped value {} in `match` is not implemented.",
+                x
+            )))
+        }
+    };
 
     let case_conversion = if let Some(conversion) = &m.case_conversion {
         Some(conversion.as_str())
@@ -409,31 +757,30 @@ fn parse_match(
 
     let pos_replacer = if let Some(postag) = m.postag {
         if postag.contains("+DT") || postag.contains("+INDT") {
-            return Err(Error::Unimplemented(
-                "+DT and +INDT determiners are not implemented.".into(),
+            return Err(Error::unsupported(
+                "+DT and +INDT determiners are not implemented.",
             ));
         }
 
         let matcher = match m.postag_regex.as_deref() {
             Some("yes") => {
-                let regex = SerializeRegex::new(&postag, true, false)?;
+                let regex = compile_regex(&postag, true, false)?;
                 Matcher::new_regex(regex, false, true)
             }
             None => Matcher::new_string(either::Left(postag), false, false, true),
             x => panic!("unknown postag_regex value {:?}", x),
         };
         Some(PosReplacer {
-            matcher: PosMatcher::new(matcher, info),
+            matcher: PosMatcher::new(matcher, info)?,
         })
     } else {
         None
     };
 
     let regex_replacer = match (m.regexp_match, m.regexp_replace) {
-        (Some(regex_match), Some(regex_replace)) => Some((
-            SerializeRegex::new(&regex_match, false, true)?,
-            regex_replace,
-        )),
+        (Some(regex_match), Some(regex_replace)) => {
+            Some((compile_regex(&regex_match, false, true)?, regex_replace))
+        }
         _ => None,
     };
 
@@ -444,8 +791,9 @@ fn parse_match(
             Some("startlower") => Conversion::StartLower,
             Some("startupper") => Conversion::StartUpper,
             Some("allupper") => Conversion::AllUpper,
+            Some("preserve") => Conversion::Preserve,
             Some(x) => {
-                return Err(Error::Unimplemented(format!(
+                return Err(Error::unsupported(format!(
                     "case conversion {} not supported.",
                     x
                 )))
@@ -454,6 +802,8 @@ fn parse_match(
         },
         pos_replacer,
         regex_replacer,
+        include_skipped,
+        case_mapping,
     })
 }
 
@@ -483,6 +833,8 @@ fn parse_synthesizer_text(text: &str) -> Vec<SynthesizerPart> {
             conversion: Conversion::Nop,
             pos_replacer: None,
             regex_replacer: None,
+            include_skipped: false,
+            case_mapping: CaseMapping::Default,
         }));
         end_index = end;
     }
@@ -496,6 +848,7 @@ fn parse_synthesizer_text(text: &str) -> Vec<SynthesizerPart> {
 fn parse_suggestion(
     data: structure::Suggestion,
     composition: &Option<&Composition>,
+    case_mapping: CaseMapping,
     info: &mut BuildInfo,
 ) -> Result<Synthesizer, Error> {
     let mut parts = Vec::new();
@@ -505,7 +858,12 @@ fn parse_suggestion(
                 parts.extend(parse_synthesizer_text(text.as_str()));
             }
             structure::SuggestionPart::Match(m) => {
-                parts.push(SynthesizerPart::Match(parse_match(m, composition, info)?));
+                parts.push(SynthesizerPart::Match(parse_match(
+                    m,
+                    composition,
+                    case_mapping,
+                    info,
+                )?));
             }
         }
     }
@@ -517,118 +875,230 @@ fn parse_suggestion(
     })
 }
 
+/// Errors if `id` e. g. the `no` of a `<match>` refers to a group which the pattern does not define.
+fn check_group_id(name: &str, id: usize, n_groups: usize) -> Result<(), Error> {
+    if id > n_groups {
+        Err(Error::unexpected(format!(
+            "{}={} but pattern defines {} group{}",
+            name,
+            id,
+            n_groups,
+            if n_groups == 1 { "" } else { "s" }
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Collects the group ids which the matchers of `atom` refer to with a `<match>` in a token.
+fn backreferences(atom: &Atom, ids: &mut Vec<usize>) {
+    let mut push = |matcher: &Matcher| {
+        if let either::Left(either::Right(id)) = &matcher.matcher {
+            ids.push(*id);
+        }
+    };
+
+    match atom {
+        Atom::TextAtom(atom) => push(&atom.matcher.matcher),
+        Atom::WordDataAtom(atom) => {
+            if let Some(matcher) = &atom.matcher.inflect_matcher {
+                push(&matcher.matcher);
+            }
+        }
+        Atom::AndAtom(atom) => atom.atoms.iter().for_each(|x| backreferences(x, ids)),
+        Atom::OrAtom(atom) => atom.atoms.iter().for_each(|x| backreferences(x, ids)),
+        Atom::NotAtom(atom) => backreferences(&atom.atom, ids),
+        Atom::OffsetAtom(atom) => backreferences(&atom.atom, ids),
+        Atom::ChunkAtom(_)
+        | Atom::CaseAtom(_)
+        | Atom::SpaceBeforeAtom(_)
+        | Atom::TrueAtom(_)
+        | Atom::FalseAtom(_) => {}
+    }
+}
+
+/// Errors if a `<match>` in a token refers to the token itself or to a later one.
+/// Their groups are not set yet when the token is matched.
+fn check_backreferences(composition: &Composition) -> Result<(), Error> {
+    for (i, part) in composition.parts.iter().enumerate() {
+        let mut ids = Vec::new();
+        backreferences(&part.atom, &mut ids);
+
+        for id in ids {
+            // the group of the part at index `i` is at index `i + 1`
+            if !matches!(composition.group_ids_to_idx.get(&id), Some(idx) if *idx <= i) {
+                return Err(Error::unexpected(format!(
+                    "match no={} in a token must refer to an earlier token",
+                    // `no` in a token is zero-based
+                    id - 1
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn get_last_id(parts: &[Part]) -> isize {
     parts.iter().fold(1, |a, x| a + x.visible as isize)
 }
 
+/// Parses the tokens of an `<and>` or `<or>` block which all match the same token, their atoms are merged with `combine`.
+/// As in LanguageTool, the quantifier and skip of the block are those of the first token.
 fn parse_parallel_tokens(
     tokens: &[structure::Token],
+    combine: fn(Vec<Atom>) -> Atom,
     case_sensitive: bool,
     info: &mut BuildInfo,
-) -> Result<Vec<Atom>, Error> {
-    tokens
-        .iter()
-        .map(|x| {
-            let mut parsed = parse_token(x, case_sensitive, info)?;
+) -> Result<Vec<Part>, Error> {
+    let (first, rest) = tokens
+        .split_first()
+        .ok_or_else(|| Error::unexpected("parallel tokens must not be empty"))?;
 
-            if parsed.len() != 1 || parsed[0].quantifier.min != 1 || parsed[0].quantifier.max != 1 {
-                return Err(Error::Unimplemented(
-                    "control flow in parallel tokens is not implemented.".into(),
-                ));
-            }
+    let mut parts = parse_token(first, case_sensitive, info)?;
+    let mut atoms = Vec::new();
 
-            Ok(parsed.remove(0).atom)
-        })
-        .collect()
+    for token in rest {
+        let mut parsed = parse_token(token, case_sensitive, info)?;
+
+        if parsed.len() != 1 || parsed[0].quantifier.min != 1 || parsed[0].quantifier.max != 1 {
+            return Err(Error::unsupported(
+                "control flow in parallel tokens other than the first is not implemented.",
+            ));
+        }
+
+        atoms.push(parsed.remove(0).atom);
+    }
+
+    let first_atom = std::mem::replace(&mut parts[0].atom, TrueAtom::default().into());
+    atoms.insert(0, first_atom);
+    parts[0].atom = combine(atoms);
+
+    Ok(parts)
 }
 
+/// Parses the tokens of a `<unify>` block and the constraint that they agree in the given features.
+/// The part indices of the [Unification] are relative to the first part of the block.
 fn parse_unify_tokens(
-    tokens: &[structure::UnifyTokenCombination],
+    unify: &structure::Unify,
+    definitions: &[structure::Unification],
     case_sensitive: bool,
     info: &mut BuildInfo,
-) -> Result<Vec<Part>, Error> {
+) -> Result<(Vec<Part>, Unification), Error> {
     let mut out = Vec::new();
+    let mut features = Vec::new();
+    let mut unified_parts = Vec::new();
 
-    for token_combination in tokens {
-        out.extend(match token_combination {
+    for token_combination in &unify.tokens {
+        let parts = match token_combination {
             structure::UnifyTokenCombination::Token(token) => {
                 parse_token(token, case_sensitive, info)?
             }
             structure::UnifyTokenCombination::And(tokens) => {
-                let atom =
-                    AndAtom::and(parse_parallel_tokens(&tokens.tokens, case_sensitive, info)?);
-                vec![Part {
-                    atom,
-                    quantifier: Quantifier::new(1, 1),
-                    greedy: true,
-                    visible: true,
-                }]
+                parse_parallel_tokens(&tokens.tokens, AndAtom::and, case_sensitive, info)?
             }
             structure::UnifyTokenCombination::Or(tokens) => {
-                let atom = OrAtom::or(parse_parallel_tokens(&tokens.tokens, case_sensitive, info)?);
-                vec![Part {
-                    atom,
-                    quantifier: Quantifier::new(1, 1),
-                    greedy: true,
-                    visible: true,
-                }]
+                parse_parallel_tokens(&tokens.tokens, OrAtom::or, case_sensitive, info)?
+            }
+            structure::UnifyTokenCombination::Feature(feature) => {
+                let definition = definitions
+                    .iter()
+                    .find(|x| x.feature == feature.id)
+                    .ok_or_else(|| {
+                        Error::unexpected(format!("unknown unification feature `{}`", feature.id))
+                    })?;
+
+                features.push(
+                    definition
+                        .equivalences
+                        .iter()
+                        .map(|equiv| {
+                            parse_pos_filter(
+                                &equiv.token.postag,
+                                equiv.token.postag_regexp.as_deref(),
+                                info,
+                            )
+                        })
+                        .collect::<Result<_, Error>>()?,
+                );
+                continue;
             }
-            structure::UnifyTokenCombination::Feature(_) => vec![],
             structure::UnifyTokenCombination::Ignore(ignore) => {
-                parse_tokens(&ignore.tokens, case_sensitive, info)?
+                let (parts, unifications) =
+                    parse_tokens(&ignore.tokens, definitions, case_sensitive, info)?;
+                if !unifications.is_empty() {
+                    return Err(Error::unsupported("nested unify is not implemented."));
+                }
+
+                out.extend(parts);
+                continue;
             }
-        });
+        };
+
+        unified_parts.extend(out.len()..out.len() + parts.len());
+        out.extend(parts);
     }
 
-    Ok(out)
+    if features.is_empty() {
+        return Err(Error::unsupported(
+            "unify without feature is not implemented.",
+        ));
+    }
+
+    Ok((
+        out,
+        Unification {
+            features,
+            parts: unified_parts,
+            negate: unify.negate.as_deref() == Some("yes"),
+        },
+    ))
 }
 
+/// Parses tokens which can contain `<unify>` blocks. The part indices of the unifications are relative to the first part.
 fn parse_tokens(
     tokens: &[structure::TokenCombination],
+    definitions: &[structure::Unification],
     case_sensitive: bool,
     info: &mut BuildInfo,
-) -> Result<Vec<Part>, Error> {
+) -> Result<(Vec<Part>, Vec<Unification>), Error> {
     let mut out = Vec::new();
+    let mut unifications = Vec::new();
 
     for token_combination in tokens {
-        out.extend(match token_combination {
+        let parts = match token_combination {
             structure::TokenCombination::Token(token) => parse_token(token, case_sensitive, info)?,
             structure::TokenCombination::And(tokens) => {
-                let atom =
-                    AndAtom::and(parse_parallel_tokens(&tokens.tokens, case_sensitive, info)?);
-                vec![Part {
-                    atom,
-                    quantifier: Quantifier::new(1, 1),
-                    greedy: true,
-                    visible: true,
-                }]
+                parse_parallel_tokens(&tokens.tokens, AndAtom::and, case_sensitive, info)?
             }
             structure::TokenCombination::Or(tokens) => {
-                let atom = OrAtom::or(parse_parallel_tokens(&tokens.tokens, case_sensitive, info)?);
-                vec![Part {
-                    atom,
-                    quantifier: Quantifier::new(1, 1),
-                    greedy: true,
-                    visible: true,
-                }]
+                parse_parallel_tokens(&tokens.tokens, OrAtom::or, case_sensitive, info)?
             }
             structure::TokenCombination::Unify(unify) => {
-                parse_unify_tokens(&unify.tokens, case_sensitive, info)?
+                let (parts, mut unification) =
+                    parse_unify_tokens(unify, definitions, case_sensitive, info)?;
+                unification.parts.iter_mut().for_each(|x| *x += out.len());
+                unifications.push(unification);
+                parts
             }
-        });
+        };
+
+        out.extend(parts);
     }
 
-    Ok(out)
+    Ok((out, unifications))
 }
 
 fn parse_pattern(
     pattern: structure::Pattern,
+    definitions: &[structure::Unification],
     info: &mut BuildInfo,
 ) -> Result<(Composition, usize, usize), Error> {
     let mut start = None;
     let mut end = None;
 
     let mut composition_parts = Vec::new();
+    let mut unifications = Vec::new();
     let case_sensitive = match &pattern.case_sensitive {
         Some(string) => string == "yes",
         None => false,
@@ -642,62 +1112,155 @@ fn parse_pattern(
             structure::PatternPart::Marker(marker) => {
                 start = Some(get_last_id(&composition_parts));
 
-                composition_parts.extend(parse_tokens(&marker.tokens, case_sensitive, info)?);
+                let (parts, marker_unifications) =
+                    parse_tokens(&marker.tokens, definitions, case_sensitive, info)?;
+                unifications.extend(marker_unifications.into_iter().map(|mut x| {
+                    x.parts
+                        .iter_mut()
+                        .for_each(|x| *x += composition_parts.len());
+                    x
+                }));
+                composition_parts.extend(parts);
 
                 end = Some(get_last_id(&composition_parts));
             }
             structure::PatternPart::And(tokens) => {
-                let atom =
-                    AndAtom::and(parse_parallel_tokens(&tokens.tokens, case_sensitive, info)?);
-
-                composition_parts.push(Part {
-                    atom,
-                    quantifier: Quantifier::new(1, 1),
-                    greedy: true,
-                    visible: true,
-                });
+                composition_parts.extend(parse_parallel_tokens(
+                    &tokens.tokens,
+                    AndAtom::and,
+                    case_sensitive,
+                    info,
+                )?);
             }
             structure::PatternPart::Or(tokens) => {
-                let atom = OrAtom::or(parse_parallel_tokens(&tokens.tokens, case_sensitive, info)?);
-
-                composition_parts.push(Part {
-                    atom,
-                    quantifier: Quantifier::new(1, 1),
-                    greedy: true,
-                    visible: true,
-                });
+                composition_parts.extend(parse_parallel_tokens(
+                    &tokens.tokens,
+                    OrAtom::or,
+                    case_sensitive,
+                    info,
+                )?);
             }
             structure::PatternPart::Unify(unify) => {
-                composition_parts.extend(parse_unify_tokens(&unify.tokens, case_sensitive, info)?)
+                let (parts, mut unification) =
+                    parse_unify_tokens(unify, definitions, case_sensitive, info)?;
+                unification
+                    .parts
+                    .iter_mut()
+                    .for_each(|x| *x += composition_parts.len());
+                unifications.push(unification);
+                composition_parts.extend(parts);
+            }
+            structure::PatternPart::Includephrases(_) => {
+                return Err(Error::unexpected(
+                    "alternatives between phrases must be expanded before parsing",
+                ));
             }
         }
     }
 
-    let start = start.unwrap_or(1) as usize;
-    let end = end.unwrap_or_else(|| get_last_id(&composition_parts)) as usize;
+    let last_id = get_last_id(&composition_parts);
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => {
+            if pattern.mark_from.is_some() || pattern.mark_to.is_some() {
+                return Err(Error::unexpected(
+                    "`mark_from` and `mark_to` must not be combined with `marker`",
+                ));
+            }
+            (start, end)
+        }
+        _ => {
+            let parse = |mark: &Option<String>, name: &str| {
+                mark.as_deref().map_or(Ok(0), |x| {
+                    x.parse::<isize>()
+                        .map_err(|_| Error::unexpected(format!("invalid `{}`: {}", name, x)))
+                })
+            };
+            let mark_from = parse(&pattern.mark_from, "mark_from")?;
+            let mark_to = parse(&pattern.mark_to, "mark_to")?;
+            let (start, end) = (1 + mark_from, last_id + mark_to);
+
+            if mark_from < 0 || mark_to > 0 || start >= end {
+                return Err(Error::unexpected(format!(
+                    "`mark_from` {} and `mark_to` {} do not mark any token",
+                    mark_from, mark_to
+                )));
+            }
+            (start, end)
+        }
+    };
+    let (start, end) = (start as usize, end as usize);
 
-    let composition = Composition::new(composition_parts);
+    let composition = Composition::try_new(composition_parts, unifications)?;
+    check_backreferences(&composition)?;
 
     Ok((composition, start, end))
 }
 
+/// Parses the `default` attribute of a rule, rule group or category. `None` if the attribute is not set.
+pub(crate) fn parse_default_state(default: Option<&str>) -> Result<Option<DefaultState>, Error> {
+    Ok(match default {
+        None => None,
+        Some("on") => Some(DefaultState::On),
+        Some("off") => Some(DefaultState::Off),
+        Some("temp_off") => Some(DefaultState::TempOff),
+        Some(x) => return Err(Error::unexpected(format!("unknown default `{}`", x))),
+    })
+}
+
+/// Computes the state of a rule from the `default` of the rule, its rule group and its category.
+/// The `default` of a rule takes precedence over the one of its group, so a rule can be turned on in a group
+/// which is off. Categories are turned on and off as a whole, so a category which is off turns off all of its rules.
+/// If both the rule (or group) and the category are off, the state of the rule (or group) is used.
+pub(crate) fn resolve_default_state(
+    rule: Option<DefaultState>,
+    group: Option<DefaultState>,
+    category: Option<DefaultState>,
+) -> DefaultState {
+    match (rule.or(group), category) {
+        (Some(state), _) if state != DefaultState::On => state,
+        (_, Some(state)) => state,
+        _ => DefaultState::On,
+    }
+}
+
+/// Parses a `<filter>` with arguments like `year:\3 month:\1` to the Rust implementation of its class.
+fn parse_filter(filter: structure::Filter) -> Result<Filter, Error> {
+    let args = filter
+        .args
+        .split_whitespace()
+        .map(|x| {
+            let idx = x
+                .find(':')
+                .ok_or_else(|| Error::unexpected(format!("invalid filter argument `{}`", x)))?;
+            Ok((
+                x[..idx].to_string(),
+                x[(idx + ':'.len_utf8())..].to_string(),
+            ))
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(get_filter(&filter.class, args)?)
+}
+
 impl Rule {
     pub fn from_rule_structure(data: structure::Rule, info: &mut BuildInfo) -> Result<Rule, Error> {
-        if data.filter.is_some() {
-            return Err(Error::Unimplemented(
-                "rules with filter are not implemented.".into(),
-            ));
-        }
+        let filter = data.filter.map(parse_filter).transpose()?;
+        let definitions = data.unifications.unwrap_or_default();
+        let case_mapping = data
+            .lang_code
+            .as_deref()
+            .map_or(CaseMapping::Default, CaseMapping::from_lang_code);
+        info.case_mapping = case_mapping;
 
         let (engine, start, end) = match (data.pattern, data.regex) {
-            (Some(_), Some(_)) => Err(Error::Unexpected(
-                "must not contain both `pattern` and `regexp`.".into(),
+            (Some(_), Some(_)) => Err(Error::unexpected(
+                "must not contain both `pattern` and `regexp`.",
             )),
-            (None, None) => Err(Error::Unexpected(
-                "either `pattern` or `regexp` must be supplied.".into(),
+            (None, None) => Err(Error::unexpected(
+                "either `pattern` or `regexp` must be supplied.",
             )),
             (Some(pattern), None) => {
-                let (composition, start, end) = parse_pattern(pattern, info)?;
+                let (composition, start, end) = parse_pattern(pattern, &definitions, info)?;
 
                 Ok((
                     Engine::Token(TokenEngine {
@@ -705,7 +1268,9 @@ impl Rule {
                         antipatterns: if let Some(antipatterns) = data.antipatterns {
                             antipatterns
                                 .into_iter()
-                                .map(|pattern| parse_pattern(pattern, info).map(|x| x.0))
+                                .map(|pattern| {
+                                    parse_pattern(pattern, &definitions, info).map(|x| x.0)
+                                })
                                 .collect::<Result<Vec<_>, Error>>()?
                         } else {
                             Vec::new()
@@ -721,8 +1286,12 @@ impl Rule {
                     None => false,
                     x => panic!("unknown case_sensitive value {:?}", x),
                 };
-                let mark = regex.mark.map_or(0, |x| x.parse().unwrap());
-                let regex = SerializeRegex::new(&regex.text, false, case_sensitive)?;
+                let mark = regex.mark.map_or(Ok(0), |x| {
+                    x.parse()
+                        .map_err(|_| Error::unexpected(format!("invalid mark={}", x)))
+                })?;
+                let regex = compile_text_regex(&regex.text, false, case_sensitive, case_mapping)?;
+                check_group_id("mark", mark, regex.captures_len())?;
                 let id_to_idx: DefaultHashMap<usize, usize> =
                     (0..regex.captures_len() + 1).enumerate().collect();
                 Ok((Engine::Text(regex, id_to_idx), mark, mark + 1))
@@ -741,10 +1310,16 @@ impl Rule {
         for part in data.message.parts {
             match part {
                 structure::MessagePart::Suggestion(suggestion) => {
-                    let suggester = parse_suggestion(suggestion.clone(), &maybe_composition, info)?;
+                    let suggester = parse_suggestion(
+                        suggestion.clone(),
+                        &maybe_composition,
+                        case_mapping,
+                        info,
+                    )?;
                     // simpler to just parse a second time than cloning the result
-                    message_parts
-                        .extend(parse_suggestion(suggestion, &maybe_composition, info)?.parts);
+                    message_parts.extend(
+                        parse_suggestion(suggestion, &maybe_composition, case_mapping, info)?.parts,
+                    );
                     suggesters.push(suggester);
                 }
                 structure::MessagePart::Text(text) => {
@@ -754,6 +1329,7 @@ impl Rule {
                     message_parts.push(SynthesizerPart::Match(parse_match(
                         m,
                         &maybe_composition,
+                        case_mapping,
                         info,
                     )?));
                 }
@@ -762,23 +1338,43 @@ impl Rule {
 
         if let Some(suggestions) = data.suggestions {
             for suggestion in suggestions {
-                suggesters.push(parse_suggestion(suggestion, &maybe_composition, info)?);
+                suggesters.push(parse_suggestion(
+                    suggestion,
+                    &maybe_composition,
+                    case_mapping,
+                    info,
+                )?);
             }
         }
 
         if suggesters.is_empty() {
-            return Err(Error::Unimplemented(
-                "rules with no suggestion are not implemented.".into(),
+            return Err(Error::unsupported(
+                "rules with no suggestion are not implemented.",
             ));
         }
 
+        // references like `\5` in the text are only known after parsing the synthesizers
+        let n_groups = match &engine {
+            Engine::Token(engine) => engine.composition.group_ids_to_idx.len() - 1,
+            Engine::Text(_, id_to_idx) => id_to_idx.len() - 1,
+        };
+        for part in suggesters
+            .iter()
+            .flat_map(|x| x.parts.iter())
+            .chain(message_parts.iter())
+        {
+            if let SynthesizerPart::Match(m) = part {
+                check_group_id("match no", m.id, n_groups)?;
+            }
+        }
+
         assert!(!message_parts.is_empty(), "Rules must have a message.");
 
         let mut examples = Vec::new();
         for example in &data.examples {
             if example.kind.is_some() {
-                return Err(Error::Unimplemented(
-                    "examples with `type` (i. e. 'triggers_error') are not implemented.".into(),
+                return Err(Error::unsupported(
+                    "examples with `type` (i. e. 'triggers_error') are not implemented.",
                 ));
             }
 
@@ -794,17 +1390,19 @@ impl Rule {
                     }
                     structure::ExamplePart::Marker(marker) => {
                         if suggestion.is_some() {
-                            return Err(Error::Unexpected(
-                                "example must have one or zero markers".into(),
-                            ));
+                            return Err(Error::unexpected("example must have one or zero markers"));
                         }
 
                         texts.push(marker.text.as_str());
                         let length = marker.text.chars().count();
 
                         if let Some(correction_text) = &example.correction {
-                            let mut replacements: Vec<_> =
-                                correction_text.split('|').map(|x| x.to_string()).collect();
+                            // an empty correction accepts any replacements
+                            let mut replacements: Vec<_> = correction_text
+                                .split('|')
+                                .filter(|x| !x.is_empty())
+                                .map(|x| x.to_string())
+                                .collect();
 
                             replacements = if char_length == 0 {
                                 // title case if at start
@@ -821,9 +1419,11 @@ impl Rule {
                             suggestion = Some(Suggestion {
                                 source: "_Test".to_string(),
                                 message: "_Test".to_string(),
+                                short: None,
                                 start: char_length,
                                 end: char_length + length,
                                 replacements,
+                                groups: None,
                             });
                         }
 
@@ -848,12 +1448,14 @@ impl Rule {
                 parts: message_parts,
                 use_titlecase_adjust: true,
             },
+            filter,
             url: data.url.map(|x| x.to_string()),
             short: data.short.map(|x| x.to_string()),
             // attributes below need information from rule group / category, so are set later
             id: String::new(),
             name: String::new(),
             on: true,
+            default_state: DefaultState::On,
             category_id: String::new(),
             category_name: String::new(),
             category_type: None,
@@ -897,38 +1499,37 @@ fn parse_tag_form(form: &str, info: &mut BuildInfo) -> owned::Word {
 }
 
 impl owned::WordData {
-    fn from_structure(data: structure::WordData, info: &mut BuildInfo) -> Self {
-        owned::WordData::new(
+    fn from_structure(data: structure::WordData, info: &mut BuildInfo) -> Result<Self, Error> {
+        Ok(owned::WordData::new(
             info.tagger
                 .id_word(data.lemma.unwrap_or_else(String::new).into())
                 .to_owned_id(),
-            info.tagger.id_tag(data.pos.as_str().trim()).to_owned_id(),
-        )
+            id_tag(data.pos.as_str().trim(), info)?.to_owned_id(),
+        ))
     }
 }
 
-fn parse_pos_filter(postag: &str, postag_regexp: Option<&str>, info: &mut BuildInfo) -> POSFilter {
-    match postag_regexp.as_deref() {
-        Some("yes") => POSFilter::new(PosMatcher::new(
-            Matcher::new_regex(
-                SerializeRegex::new(&postag, true, true).unwrap(),
-                false,
-                true,
-            ),
-            info,
-        )),
-        Some(_) | None => POSFilter::new(PosMatcher::new(
-            Matcher::new_string(either::Left(postag.into()), false, false, true),
-            info,
-        )),
-    }
+fn parse_pos_filter(
+    postag: &str,
+    postag_regexp: Option<&str>,
+    info: &mut BuildInfo,
+) -> Result<POSFilter, Error> {
+    let matcher = match postag_regexp {
+        Some("yes") => Matcher::new_regex(compile_regex(postag, true, true)?, false, true),
+        Some(_) | None => Matcher::new_string(either::Left(postag.into()), false, false, true),
+    };
+
+    Ok(POSFilter::new(PosMatcher::new(matcher, info)?))
 }
 
+/// The unified feature filters, the filter of each token and whether each token is unified.
+type UnifyFilters = (Vec<Vec<POSFilter>>, Vec<Option<POSFilter>>, Vec<bool>);
+
 fn parse_unify(
     unify: &structure::Unify,
     unifications: &Option<Vec<structure::Unification>>,
     info: &mut BuildInfo,
-) -> (Vec<Vec<POSFilter>>, Vec<Option<POSFilter>>, Vec<bool>) {
+) -> Result<UnifyFilters, Error> {
     let mut filters = Vec::new();
     let mut disambig = Vec::new();
     let mut mask = Vec::new();
@@ -936,13 +1537,13 @@ fn parse_unify(
     for token_combination in &unify.tokens {
         match token_combination {
             structure::UnifyTokenCombination::Feature(feature) => {
-                // TODO: no unwrap
                 let unification = unifications
-                    .as_ref()
-                    .unwrap()
                     .iter()
+                    .flatten()
                     .find(|x| x.feature == feature.id)
-                    .unwrap();
+                    .ok_or_else(|| {
+                        Error::unexpected(format!("unknown unification feature `{}`", feature.id))
+                    })?;
 
                 filters.push(
                     unification
@@ -955,16 +1556,20 @@ fn parse_unify(
                                 info,
                             )
                         })
-                        .collect(),
+                        .collect::<Result<_, Error>>()?,
                 );
             }
             structure::UnifyTokenCombination::And(tokens)
             | structure::UnifyTokenCombination::Or(tokens) => {
                 mask.push(true);
                 disambig.push(
-                    tokens.tokens[0].postag.as_ref().map(|x| {
-                        parse_pos_filter(x, tokens.tokens[0].postag_regexp.as_deref(), info)
-                    }),
+                    tokens.tokens[0]
+                        .postag
+                        .as_ref()
+                        .map(|x| {
+                            parse_pos_filter(x, tokens.tokens[0].postag_regexp.as_deref(), info)
+                        })
+                        .transpose()?,
                 )
             }
             structure::UnifyTokenCombination::Token(token) => {
@@ -973,7 +1578,8 @@ fn parse_unify(
                     token
                         .postag
                         .as_ref()
-                        .map(|x| parse_pos_filter(x, token.postag_regexp.as_deref(), info)),
+                        .map(|x| parse_pos_filter(x, token.postag_regexp.as_deref(), info))
+                        .transpose()?,
                 )
             }
             structure::UnifyTokenCombination::Ignore(tokens) => {
@@ -982,20 +1588,34 @@ fn parse_unify(
                         structure::TokenCombination::And(tokens)
                         | structure::TokenCombination::Or(tokens) => {
                             mask.push(false);
-                            disambig.push(tokens.tokens[0].postag.as_ref().map(|x| {
-                                parse_pos_filter(x, tokens.tokens[0].postag_regexp.as_deref(), info)
-                            }))
+                            disambig.push(
+                                tokens.tokens[0]
+                                    .postag
+                                    .as_ref()
+                                    .map(|x| {
+                                        parse_pos_filter(
+                                            x,
+                                            tokens.tokens[0].postag_regexp.as_deref(),
+                                            info,
+                                        )
+                                    })
+                                    .transpose()?,
+                            )
                         }
                         structure::TokenCombination::Token(token) => {
                             mask.push(false);
                             disambig.push(
-                                token.postag.as_ref().map(|x| {
-                                    parse_pos_filter(x, token.postag_regexp.as_deref(), info)
-                                }),
+                                token
+                                    .postag
+                                    .as_ref()
+                                    .map(|x| {
+                                        parse_pos_filter(x, token.postag_regexp.as_deref(), info)
+                                    })
+                                    .transpose()?,
                             )
                         }
                         structure::TokenCombination::Unify(_) => {
-                            panic!("nested unify not supported")
+                            return Err(Error::unsupported("nested unify is not implemented."));
                         }
                     }
                 }
@@ -1003,7 +1623,7 @@ fn parse_unify(
         }
     }
 
-    (filters, disambig, mask)
+    Ok((filters, disambig, mask))
 }
 
 impl DisambiguationRule {
@@ -1011,13 +1631,24 @@ impl DisambiguationRule {
         data: structure::DisambiguationRule,
         info: &mut BuildInfo,
     ) -> Result<DisambiguationRule, Error> {
+        // `filterall` relies on the `<marker>` to know which tokens are disambiguated
+        if data.pattern.mark_from.is_some() || data.pattern.mark_to.is_some() {
+            return Err(Error::unsupported(
+                "`mark_from` and `mark_to` in disambiguation rules",
+            ));
+        }
+        let definitions = data.unifications.clone().unwrap_or_default();
+        info.case_mapping = data
+            .lang_code
+            .as_deref()
+            .map_or(CaseMapping::Default, CaseMapping::from_lang_code);
         // might need the pattern later so clone it here
-        let (composition, start, end) = parse_pattern(data.pattern.clone(), info)?;
+        let (composition, start, end) = parse_pattern(data.pattern.clone(), &definitions, info)?;
 
         let antipatterns = if let Some(antipatterns) = data.antipatterns {
             antipatterns
                 .into_iter()
-                .map(|pattern| parse_pattern(pattern, info).map(|x| x.0))
+                .map(|pattern| parse_pattern(pattern, &definitions, info).map(|x| x.0))
                 .collect::<Result<Vec<_>, Error>>()?
         } else {
             Vec::new()
@@ -1027,15 +1658,18 @@ impl DisambiguationRule {
             wds.into_iter()
                 .map(|part| match part {
                     structure::DisambiguationPart::WordData(x) => {
-                        either::Left(owned::WordData::from_structure(x, info))
+                        owned::WordData::from_structure(x, info).map(either::Left)
+                    }
+                    structure::DisambiguationPart::Match(x) => {
+                        check_group_id("match no", x.no, composition.group_ids_to_idx.len() - 1)?;
+                        let postag = x.postag.ok_or_else(|| {
+                            Error::unsupported("match without postag in disambiguation")
+                        })?;
+                        parse_pos_filter(&postag, x.postag_regexp.as_deref(), info)
+                            .map(either::Right)
                     }
-                    structure::DisambiguationPart::Match(x) => either::Right(parse_pos_filter(
-                        &x.postag.unwrap(),
-                        x.postag_regexp.as_deref(),
-                        info,
-                    )),
                 })
-                .collect()
+                .collect::<Result<_, Error>>()?
         } else {
             Vec::new()
         };
@@ -1044,41 +1678,37 @@ impl DisambiguationRule {
             Some("remove") => {
                 if let Some(postag) = data.disambig.postag.as_ref() {
                     Ok(Disambiguation::Remove(vec![either::Right(
-                        parse_pos_filter(postag, Some("yes"), info),
+                        parse_pos_filter(postag, Some("yes"), info)?,
                     )]))
                 } else {
                     Ok(Disambiguation::Remove(word_datas.into_iter().collect()))
                 }
             }
-            Some("add") => {
-                if data.disambig.postag.is_some() {
-                    panic!("postag not supported for `add`.")
-                }
-
-                Ok(Disambiguation::Add(
-                    word_datas
-                        .into_iter()
-                        .map(|x| x.left().expect("match not supported for `add`"))
-                        .collect(),
-                ))
-            }
-            Some("replace") => {
+            Some(action @ "add") | Some(action @ "replace") => {
                 if data.disambig.postag.is_some() {
-                    panic!("postag not supported for `replace`.")
+                    return Err(Error::unsupported(format!(
+                        "postag in `{}` disambiguation",
+                        action
+                    )));
                 }
 
-                Ok(Disambiguation::Replace(
-                    word_datas
-                        .into_iter()
-                        .map(|x| {
-                            x.left()
-                                .expect("match not supported for `replace` disambiguation")
+                let word_datas = word_datas
+                    .into_iter()
+                    .map(|x| {
+                        x.left().ok_or_else(|| {
+                            Error::unsupported(format!("match in `{}` disambiguation", action))
                         })
-                        .collect(),
-                ))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+
+                Ok(if action == "add" {
+                    Disambiguation::Add(word_datas)
+                } else {
+                    Disambiguation::Replace(word_datas)
+                })
             }
-            Some("ignore_spelling") => Ok(Disambiguation::Nop), // ignore_spelling can be ignored since we dont check spelling
-            Some("immunize") => Ok(Disambiguation::Nop), // immunize can probably not be ignored
+            Some("ignore_spelling") => Ok(Disambiguation::IgnoreSpelling),
+            Some("immunize") => Ok(Disambiguation::Immunize),
             Some("filterall") => {
                 let mut disambig = Vec::new();
                 let mut marker_disambig = Vec::new();
@@ -1094,40 +1724,58 @@ impl DisambiguationRule {
                                     structure::TokenCombination::And(tokens)
                                     | structure::TokenCombination::Or(tokens) => &tokens.tokens[0],
                                     structure::TokenCombination::Unify(_) => {
-                                        panic!("`unify` not supported in `filterall`")
+                                        return Err(Error::unsupported("`unify` in `filterall`"));
                                     }
                                 };
 
-                                marker_disambig.push(token.postag.as_ref().map(|x| {
-                                    either::Right(parse_pos_filter(
-                                        x,
-                                        token.postag_regexp.as_deref(),
-                                        info,
-                                    ))
-                                }));
+                                marker_disambig.push(
+                                    token
+                                        .postag
+                                        .as_ref()
+                                        .map(|x| {
+                                            parse_pos_filter(
+                                                x,
+                                                token.postag_regexp.as_deref(),
+                                                info,
+                                            )
+                                            .map(either::Right)
+                                        })
+                                        .transpose()?,
+                                );
                             }
                         }
-                        structure::PatternPart::Token(token) => {
-                            disambig.push(token.postag.as_ref().map(|x| {
-                                either::Right(parse_pos_filter(
-                                    x,
-                                    token.postag_regexp.as_deref(),
-                                    info,
-                                ))
-                            }))
-                        }
+                        structure::PatternPart::Token(token) => disambig.push(
+                            token
+                                .postag
+                                .as_ref()
+                                .map(|x| {
+                                    parse_pos_filter(x, token.postag_regexp.as_deref(), info)
+                                        .map(either::Right)
+                                })
+                                .transpose()?,
+                        ),
                         structure::PatternPart::And(tokens)
-                        | structure::PatternPart::Or(tokens) => {
-                            disambig.push(tokens.tokens[0].postag.as_ref().map(|x| {
-                                either::Right(parse_pos_filter(
-                                    x,
-                                    tokens.tokens[0].postag_regexp.as_deref(),
-                                    info,
-                                ))
-                            }))
-                        }
+                        | structure::PatternPart::Or(tokens) => disambig.push(
+                            tokens.tokens[0]
+                                .postag
+                                .as_ref()
+                                .map(|x| {
+                                    parse_pos_filter(
+                                        x,
+                                        tokens.tokens[0].postag_regexp.as_deref(),
+                                        info,
+                                    )
+                                    .map(either::Right)
+                                })
+                                .transpose()?,
+                        ),
                         structure::PatternPart::Unify(_) => {
-                            panic!("`unify` not supported in `filterall`")
+                            return Err(Error::unsupported("`unify` in `filterall`"));
+                        }
+                        structure::PatternPart::Includephrases(_) => {
+                            return Err(Error::unexpected(
+                                "alternatives between phrases must be expanded before parsing",
+                            ));
                         }
                     }
                 }
@@ -1145,7 +1793,7 @@ impl DisambiguationRule {
             Some("filter") => {
                 if let Some(postag) = data.disambig.postag.as_ref() {
                     Ok(Disambiguation::Filter(vec![Some(either::Right(
-                        parse_pos_filter(postag, Some("yes"), info),
+                        parse_pos_filter(postag, Some("yes"), info)?,
                     ))]))
                 } else {
                     Ok(Disambiguation::Filter(
@@ -1161,20 +1809,26 @@ impl DisambiguationRule {
                 match &data.pattern.parts[..] {
                     [.., structure::PatternPart::Marker(marker)] => match &marker.tokens[..] {
                         [structure::TokenCombination::Unify(unify)] => {
-                            let (f, d, m) = parse_unify(&unify, &data.unifications, info);
+                            let (f, d, m) = parse_unify(unify, &data.unifications, info)?;
                             filters.extend(f);
                             disambig.extend(d);
                             mask.extend(m);
                         }
-                        _ => panic!("only `unify` as only element in `marker` is implemented"),
+                        _ => return Err(Error::unsupported(
+                            "`unify` disambiguation with other tokens than `unify` in the marker",
+                        )),
                     },
                     [structure::PatternPart::Unify(unify)] => {
-                        let (f, d, m) = parse_unify(&unify, &data.unifications, info);
+                        let (f, d, m) = parse_unify(unify, &data.unifications, info)?;
                         filters.extend(f);
                         disambig.extend(d);
                         mask.extend(m);
                     }
-                    _ => panic!("only `unify` as only element in `pattern` is implemented"),
+                    _ => {
+                        return Err(Error::unsupported(
+                            "`unify` disambiguation with other tokens than `unify` in the pattern",
+                        ))
+                    }
                 }
 
                 Ok(Disambiguation::Unify(filters, disambig, mask))
@@ -1184,7 +1838,7 @@ impl DisambiguationRule {
                     Ok(Disambiguation::Filter(vec![Some(either::Left(
                         owned::WordData::new(
                             info.tagger.id_word("".into()).to_owned_id(),
-                            info.tagger.id_tag(postag).to_owned_id(),
+                            id_tag(postag, info)?.to_owned_id(),
                         ),
                     ))]))
                 } else {
@@ -1193,29 +1847,10 @@ impl DisambiguationRule {
                     ))
                 }
             }
-            Some(x) => Err(Error::Unimplemented(format!("action {}", x))),
+            Some(x) => Err(Error::unsupported(format!("action {}", x))),
         }?;
 
-        let filter = if let Some(filter_data) = data.filter {
-            let args = filter_data
-                .args
-                .split(' ')
-                .map(|x| {
-                    let idx = x.find(':').unwrap();
-                    (
-                        x[..idx].to_string(),
-                        x[(idx + ':'.len_utf8())..].to_string(),
-                    )
-                })
-                .collect();
-
-            Some(get_filter(
-                filter_data.class.split('.').next_back().unwrap(),
-                args,
-            )?)
-        } else {
-            None
-        };
+        let filter = data.filter.map(parse_filter).transpose()?;
 
         let mut examples = Vec::new();
 
@@ -1233,8 +1868,8 @@ impl DisambiguationRule {
                         }
                         structure::ExamplePart::Marker(marker) => {
                             if char_span.is_some() {
-                                return Err(Error::Unexpected(
-                                    "example must have one or zero markers".into(),
+                                return Err(Error::unexpected(
+                                    "example must have one or zero markers",
                                 ));
                             }
 
@@ -1291,3 +1926,563 @@ impl DisambiguationRule {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        case_idiom, parse_default_state, read_rules, resolve_default_state, MAX_QUANTIFIER,
+    };
+    use crate::{
+        compile::{
+            testing::{assert_examples_pass, build_info_for, compile_rules, setup},
+            Error as CompileError, RuleOutcome,
+        },
+        rule::{
+            engine::{
+                composition::{Composition, Quantifier, TokenCase},
+                Engine,
+            },
+            DefaultState,
+        },
+        rules::{Rules, RulesOptions},
+        utils::regex::SerializeRegex,
+    };
+
+    #[test]
+    fn default_states_are_resolved() {
+        use DefaultState::*;
+
+        let states = [None, Some(On), Some(Off), Some(TempOff)];
+        for rule in states.iter().copied() {
+            for group in states.iter().copied() {
+                for category in states.iter().copied() {
+                    let expected = match (rule, group, category) {
+                        // the rule is off itself
+                        (Some(Off), _, _) => Off,
+                        (Some(TempOff), _, _) => TempOff,
+                        // the group is off and the rule does not turn itself on
+                        (None, Some(Off), _) => Off,
+                        (None, Some(TempOff), _) => TempOff,
+                        // the category is off
+                        (_, _, Some(Off)) => Off,
+                        (_, _, Some(TempOff)) => TempOff,
+                        _ => On,
+                    };
+
+                    assert_eq!(
+                        resolve_default_state(rule, group, category),
+                        expected,
+                        "rule: {:?}, group: {:?}, category: {:?}",
+                        rule,
+                        group,
+                        category
+                    );
+                }
+            }
+        }
+
+        assert_eq!(parse_default_state(None).unwrap(), None);
+        assert_eq!(
+            parse_default_state(Some("temp_off")).unwrap(),
+            Some(TempOff)
+        );
+        assert!(parse_default_state(Some("maybe")).is_err());
+
+        let rule = |id: Option<&str>, default: &str| {
+            format!(
+                r#"<rule{}{}>
+                <pattern>
+                    <token>here</token>
+                </pattern>
+                <message>Did you mean <suggestion>there</suggestion>?</message>
+                <example correction="there">It is <marker>here</marker>.</example>
+            </rule>"#,
+                id.map_or_else(String::new, |id| format!(r#" id="{}" name="{}""#, id, id)),
+                default
+            )
+        };
+        let grammar = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="ON" name="On">
+        {}
+        {}
+        <rulegroup id="GROUP_OFF" name="Group off" default="off">
+            {}
+            {}
+        </rulegroup>
+    </category>
+    <category id="OFF" name="Off" default="temp_off">
+        {}
+        {}
+    </category>
+</rules>
+"#,
+            rule(Some("PLAIN"), ""),
+            rule(Some("TEMP_OFF"), r#" default="temp_off""#),
+            rule(None, ""),
+            rule(None, r#" default="on""#),
+            rule(Some("IN_OFF_CATEGORY"), r#" default="on""#),
+            rule(Some("OFF_IN_OFF_CATEGORY"), r#" default="off""#),
+        );
+
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let rules = compile_rules(&grammar, &tokenizer);
+
+        let states: Vec<_> = rules
+            .rules()
+            .iter()
+            .map(|x| (x.id(), x.on(), x.default_state()))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("PLAIN", true, On),
+                ("TEMP_OFF", false, TempOff),
+                ("GROUP_OFF.0", false, Off),
+                ("GROUP_OFF.1", true, On),
+                ("IN_OFF_CATEGORY", false, TempOff),
+                ("OFF_IN_OFF_CATEGORY", false, Off),
+            ]
+        );
+
+        let grammar = grammar.replace("temp_off", "sometimes");
+        let (_, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        let errors: Vec<_> = errors.iter().map(|x| x.rule_id().unwrap()).collect();
+        assert_eq!(
+            errors,
+            vec!["TEMP_OFF", "IN_OFF_CATEGORY", "OFF_IN_OFF_CATEGORY"]
+        );
+    }
+
+    #[test]
+    fn phrases_are_inlined() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <phrases>
+        <phrase id="THE_TOOL">
+            <token>the</token>
+            <token>tool</token>
+        </phrase>
+        <phrase id="RUNS_THE_TOOL">
+            <marker>
+                <token>runs</token>
+            </marker>
+            <includephrases>
+                <phraseref idref="THE_TOOL"/>
+            </includephrases>
+        </phrase>
+        <phrase id="CYCLE">
+            <token>here</token>
+            <phraseref idref="CYCLE"/>
+        </phrase>
+    </phrases>
+    <category id="TEST" name="Test">
+        <rule id="HERE_RUNS" name="Here runs">
+            <pattern>
+                <token>here</token>
+                <phraseref idref="RUNS_THE_TOOL"/>
+            </pattern>
+            <message>Did you mean <suggestion>run</suggestion>?</message>
+            <example correction="run">We are here <marker>runs</marker> the tool.</example>
+        </rule>
+        <rule id="HERE_TOOL" name="Here tool">
+            <pattern>
+                <token>here</token>
+                <phraseref idref="THE_TOOL"/>
+            </pattern>
+            <message>Did you mean <suggestion><match no="3"/>s</suggestion>?</message>
+            <example correction="tools">We are <marker>here the tool</marker>.</example>
+        </rule>
+        <rule id="CYCLIC" name="Cyclic">
+            <pattern>
+                <phraseref idref="CYCLE"/>
+            </pattern>
+            <message>Cyclic.</message>
+        </rule>
+        <rule id="UNKNOWN" name="Unknown">
+            <pattern>
+                <phraseref idref="MISSING"/>
+            </pattern>
+            <message>Unknown.</message>
+        </rule>
+    </category>
+</rules>
+"#;
+
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let (rules, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(ids, vec!["HERE_RUNS", "HERE_TOOL"]);
+        // the marker of the phrase is the marker of the rule
+        // and the tokens of phrases are numbered as if they were part of the pattern
+        assert_examples_pass(&rules, &tokenizer);
+
+        let errors: Vec<_> = report
+            .rules
+            .iter()
+            .filter_map(|x| match &x.outcome {
+                RuleOutcome::Error { message, .. } => Some(message.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("\"CYCLE\" is nested deeper"));
+        assert!(errors[1].contains("unknown phrase \"MISSING\""));
+    }
+
+    #[test]
+    fn rules_are_read_lazily() {
+        // the document is cut off after the first rule, reading it to the end would fail
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <unification feature="number">
+        <equivalence type="singular">
+            <token postag="NN"/>
+        </equivalence>
+    </unification>
+    <category id="TEST" name="Test">
+        <rule id="FIRST" name="First">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">It is <marker>here</marker>.</example>
+        </rule>
+        <rule id="SECOND" name="Second">
+            <pattern>"#;
+
+        let mut readings = read_rules(grammar.as_bytes());
+        let (rule, group, category) = readings.next().unwrap().unwrap();
+
+        assert_eq!(rule.id.as_deref(), Some("FIRST"));
+        assert!(group.is_none());
+        assert_eq!(category.unwrap().id, "TEST");
+        assert_eq!(rule.lang_code.as_deref(), Some("en"));
+        // unifications defined before the rule are known to it
+        assert_eq!(rule.unifications.map(|x| x.len()), Some(1));
+
+        // the truncated rest is an error with the position in the document, then reading stops
+        let error = readings.next().unwrap().unwrap_err();
+        assert!(matches!(
+            error,
+            crate::compile::Error::Xml {
+                line: Some(17),
+                column: Some(22),
+                rule_id: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "malformed XML at line 17, column 22: Unexpected end of stream: still inside the root element"
+        );
+        assert!(readings.next().is_none());
+    }
+
+    #[test]
+    fn phrase_alternatives_compile_one_rule_each() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <phrases>
+        <phrase id="A_TOOL">
+            <token>a</token>
+            <token>tool</token>
+        </phrase>
+        <phrase id="THE_BIG_TOOL">
+            <token>the</token>
+            <token>big</token>
+            <token>tool</token>
+        </phrase>
+        <phrase id="ANY_TOOL">
+            <includephrases>
+                <phraseref idref="A_TOOL"/>
+                <phraseref idref="THE_BIG_TOOL"/>
+            </includephrases>
+        </phrase>
+    </phrases>
+    <category id="TEST" name="Test">
+        <rule id="HERE_TOOL_RUNS" name="Here tool runs">
+            <antipattern>
+                <token>here</token>
+                <includephrases>
+                    <phraseref idref="A_TOOL"/>
+                    <phraseref idref="THE_BIG_TOOL"/>
+                </includephrases>
+                <token>runs</token>
+                <token>fast</token>
+            </antipattern>
+            <pattern>
+                <marker>
+                    <token>here</token>
+                </marker>
+                <includephrases>
+                    <phraseref idref="A_TOOL"/>
+                    <phraseref idref="THE_BIG_TOOL"/>
+                </includephrases>
+                <token>runs</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there"><marker>here</marker> a tool runs.</example>
+            <example correction="there"><marker>here</marker> the big tool runs.</example>
+            <example>here the tool runs.</example>
+            <example>here the big tool runs fast.</example>
+        </rule>
+        <rulegroup id="TOOL_GROUP" name="Tool group">
+            <rule>
+                <pattern>
+                    <phraseref idref="ANY_TOOL"/>
+                    <token>walks</token>
+                </pattern>
+                <message>Did you mean <suggestion>it runs</suggestion>?</message>
+                <example correction="it runs">I saw <marker>a tool walks</marker>.</example>
+                <example correction="it runs">I saw <marker>the big tool walks</marker>.</example>
+            </rule>
+            <rule>
+                <pattern>
+                    <token>tool</token>
+                    <token>sings</token>
+                </pattern>
+                <message>Did you mean <suggestion>tool runs</suggestion>?</message>
+                <example correction="tool runs">The <marker>tool sings</marker>.</example>
+            </rule>
+        </rulegroup>
+    </category>
+</rules>
+"#;
+
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        // like in LanguageTool, the copies have the same ID and do not change the numbering of the group
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "HERE_TOOL_RUNS",
+                "HERE_TOOL_RUNS",
+                "TOOL_GROUP.0",
+                "TOOL_GROUP.0",
+                "TOOL_GROUP.1"
+            ]
+        );
+
+        // each copy only finds one of the examples with a correction
+        let report = rules.validate(&tokenizer);
+        assert_eq!(report.failed().count(), 0, "{:?}", report.rules);
+
+        let suggestions = |text: &str| -> Vec<(usize, usize)> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .map(|x| (x.start, x.end))
+                .collect()
+        };
+        assert_eq!(suggestions("here a tool runs."), vec![(0, 4)]);
+        assert_eq!(suggestions("here the big tool runs."), vec![(0, 4)]);
+        // the alternatives of the antipattern are separate antipatterns
+        assert!(suggestions("here a tool runs fast.").is_empty());
+        assert!(suggestions("here the big tool runs fast.").is_empty());
+    }
+
+    #[test]
+    fn invalid_quantifiers_are_compile_errors() {
+        let (tokenizer, _) = setup();
+        let grammar = |min: &str, max: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="QUANTIFIER" name="Quantifier">
+            <pattern>
+                <token>the</token>
+                <token min="{}" max="{}">tool</token>
+            </pattern>
+            <message>Did you mean <suggestion>the</suggestion>?</message>
+            <example correction="the">Next <marker>the tool</marker></example>
+        </rule>
+    </category>
+</rules>
+"#,
+                min, max
+            )
+        };
+        let mut build_info = build_info_for(&tokenizer);
+        let mut errors = |min: &str, max: &str| {
+            let (rules, errors) = Rules::from_xml_reader_with_errors(
+                grammar(min, max).as_bytes(),
+                &mut build_info,
+                RulesOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(rules.rules().len(), 1 - errors.len());
+            errors
+        };
+
+        assert!(errors("1", "2").is_empty());
+        assert!(errors("-1", "-1").is_empty());
+        for (min, max) in [
+            ("3", "2"),
+            ("-2", "1"),
+            ("1", "-5"),
+            ("1", "1001"),
+            ("1", "18446744073709551615"),
+            ("1", "99999999999999999999999"),
+            ("one", "1"),
+        ] {
+            let errors = errors(min, max);
+            assert!(
+                matches!(
+                    &errors[..],
+                    [CompileError::Unexpected { rule_id: Some(id), .. }] if id == "QUANTIFIER"
+                ),
+                "min {} max {}: {:?}",
+                min,
+                max,
+                errors
+            );
+        }
+
+        assert!(Quantifier::try_new(2, 1).is_err());
+        assert!(Quantifier::try_new(0, MAX_QUANTIFIER + 1).is_err());
+        assert!(Composition::try_new(Vec::new(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn case_atoms_match_like_the_regexes_they_replace() {
+        let (tokenizer, _) = setup();
+        let mut words: Vec<String> = tokenizer
+            .tagger()
+            .words()
+            .iter()
+            .map(|(word, _)| word.to_string())
+            .collect();
+        words.extend(
+            [
+                "",
+                "I",
+                "a",
+                "There",
+                "CAN",
+                "iPhone",
+                "McDonald",
+                "X-Ray",
+                "1st",
+                "ß",
+                "ẞ",
+                "Éclair",
+                "ÉCLAIR",
+                "İstanbul",
+                "Ωμέγα",
+                "ǅemal",
+                "Ǆ",
+                "nº",
+                "Ⅰ",
+                "Ⓐ",
+                "σς",
+                "ab1",
+            ]
+            .iter()
+            .map(|x| x.to_string()),
+        );
+
+        let regex = |pattern: &str| SerializeRegex::new(pattern, true, true).unwrap();
+        let idioms = [
+            r"\p{Lu}.*",
+            r"[\p{Lu}].*",
+            r"\p{Lu}.+|\p{Lu}",
+            r"\p{Ll}.*",
+            r"[\p{Ll}].*",
+            r"\p{Ll}.+|\p{Ll}",
+            r"\p{Lu}\p{Ll}+",
+            r"\p{Lu}[\p{Ll}]+",
+            r"\p{Lu}+",
+            r"[\p{Lu}]+",
+            r"\p{Ll}+",
+            r"[\p{Ll}]+",
+        ];
+        for pattern in idioms.iter() {
+            let case = case_idiom(pattern).unwrap();
+            let regex = regex(pattern);
+
+            for word in &words {
+                assert_eq!(
+                    case.is_match(word),
+                    regex.is_match(word),
+                    "{} {}",
+                    pattern,
+                    word
+                );
+            }
+        }
+
+        // mixed case has no idiom, it is only letters with both cases which are not in title case
+        let letters = regex(r"[\p{Lu}\p{Ll}]+");
+        let upper = regex(r".*\p{Lu}.*");
+        let lower = regex(r".*\p{Ll}.*");
+        let title = regex(r"\p{Lu}\p{Ll}+");
+        for word in &words {
+            assert_eq!(
+                TokenCase::Mixed.is_match(word),
+                letters.is_match(word)
+                    && upper.is_match(word)
+                    && lower.is_match(word)
+                    && !title.is_match(word),
+                "{}",
+                word
+            );
+        }
+
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="CASE" name="Case">
+            <pattern case_sensitive="yes">
+                <token>the</token>
+                <token regexp="yes" negate="yes">\p{Ll}+</token>
+            </pattern>
+            <message>Did you mean <suggestion>\1 tool</suggestion>?</message>
+            <example correction="the tool">Next <marker>the Tool</marker></example>
+            <example>Next the tool</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let rule = &rules.rules()[0];
+        match &rule.engine {
+            Engine::Token(engine) => {
+                let atoms: Vec<_> = engine
+                    .composition
+                    .parts
+                    .iter()
+                    .map(|x| x.atom.to_string())
+                    .collect();
+                assert_eq!(atoms, [r#"text "the""#, "not case Lower"]);
+            }
+            Engine::Text(..) => unreachable!(),
+        }
+        assert!(rule.validate(&tokenizer).is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+//! Extracts the literals for the [Prefilter] of a rule set i. e. strings one of which must occur
+//! in the text of the tokens for a rule to match.
+
+use crate::{
+    rule::{
+        engine::{
+            composition::{concrete::TextAtom, Atom, Composition, Matcher},
+            Engine,
+        },
+        grammar::CaseMapping,
+        Rule,
+    },
+    rules::{fold_into, Prefilter},
+    types::*,
+};
+
+fn fold(text: &str) -> String {
+    let mut out = String::new();
+    fold_into(text, &mut out);
+    out
+}
+
+/// The folded literal which a token equal to `text` contains. Case-insensitive literals are cut at the first
+/// non-ASCII character since other characters can be equal ignoring case without being equal after folding.
+fn literal(text: &str, case_sensitive: bool) -> Option<String> {
+    let text = if case_sensitive {
+        text
+    } else {
+        &text[..text.find(|c: char| !c.is_ascii()).unwrap_or(text.len())]
+    };
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(fold(text))
+    }
+}
+
+/// Splits a regex at the `|` which are not inside a group or character class.
+/// `None` if the groups are not balanced e. g. for the inner part of `^(a)|(b)$`.
+fn split_alternatives(regex: &str) -> Option<Vec<&str>> {
+    let mut alternatives = Vec::new();
+    let mut depth = 0usize;
+    let mut class_depth = 0usize;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in regex.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' => escaped = true,
+            '[' => class_depth += 1,
+            ']' if class_depth > 0 => class_depth -= 1,
+            '(' if class_depth == 0 => depth += 1,
+            ')' if class_depth == 0 => depth = depth.checked_sub(1)?,
+            '|' if class_depth == 0 && depth == 0 => {
+                alternatives.push(&regex[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    alternatives.push(&regex[start..]);
+    Some(alternatives)
+}
+
+/// The literal text every match of `regex` starts with. A character followed by a quantifier which allows
+/// zero repetitions is not part of it.
+fn regex_prefix(regex: &str) -> &str {
+    let is_literal = |c: char| c.is_alphanumeric() || [' ', '\'', '-'].contains(&c);
+    let end = regex.find(|c| !is_literal(c)).unwrap_or(regex.len());
+
+    match regex[end..].chars().next() {
+        Some('?') | Some('*') | Some('{') => {
+            let last = regex[..end].chars().last().map_or(0, |x| x.len_utf8());
+            &regex[..end - last]
+        }
+        _ => &regex[..end],
+    }
+}
+
+/// The folded literals one of which a token must contain to match `matcher`, `None` if there are none.
+fn matcher_literals(matcher: &Matcher) -> Option<Vec<String>> {
+    if matcher.negate {
+        return None;
+    }
+
+    match &matcher.matcher {
+        either::Left(either::Left(string)) => {
+            // the Turkic i are not folded like the other ASCII letters
+            let string = if !matcher.case_sensitive && matcher.case_mapping == CaseMapping::Turkic {
+                &string[..string.find(['i', 'I']).unwrap_or(string.len())]
+            } else {
+                string
+            };
+
+            literal(string, matcher.case_sensitive).map(|x| vec![x])
+        }
+        // references to other tokens depend on the text
+        either::Left(either::Right(_)) => None,
+        either::Right(regex) => {
+            let inner = regex
+                .regex_str()
+                .strip_prefix("^(")
+                .and_then(|x| x.strip_suffix(")$"))?;
+            // inline flags could turn off case sensitivity for some alternatives
+            let case_sensitive = regex.case_sensitive() && !inner.contains("(?");
+
+            split_alternatives(inner)?
+                .into_iter()
+                .map(|x| literal(regex_prefix(x), case_sensitive))
+                .collect()
+        }
+    }
+}
+
+/// Orders sets of literals by how rarely one of them is expected to occur: Longer literals are rarer,
+/// as are fewer alternatives.
+fn selectivity(literals: &[String]) -> (usize, std::cmp::Reverse<usize>) {
+    (
+        literals.iter().map(|x| x.len()).min().unwrap_or(0),
+        std::cmp::Reverse(literals.len()),
+    )
+}
+
+fn atom_literals(atom: &Atom) -> Option<Vec<String>> {
+    match atom {
+        Atom::TextAtom(TextAtom { matcher }) => matcher_literals(&matcher.matcher),
+        // all of the atoms must match, so the literals of any of them are required
+        Atom::AndAtom(atom) => atom
+            .atoms
+            .iter()
+            .filter_map(atom_literals)
+            .max_by_key(|x| selectivity(x)),
+        Atom::OrAtom(atom) if !atom.atoms.is_empty() => atom
+            .atoms
+            .iter()
+            .map(atom_literals)
+            .collect::<Option<Vec<_>>>()
+            .map(|x| x.concat()),
+        _ => None,
+    }
+}
+
+/// The literals of the rarest part which every match of the composition contains.
+fn composition_literals(composition: &Composition) -> Option<Vec<String>> {
+    composition
+        .parts
+        .iter()
+        .filter(|x| x.quantifier.min > 0)
+        .filter_map(|x| atom_literals(&x.atom))
+        .max_by_key(|x| selectivity(x))
+}
+
+impl Prefilter {
+    pub(crate) fn new(rules: &[Rule]) -> Self {
+        let mut literal_ids: DefaultHashMap<String, usize> = DefaultHashMap::default();
+        let mut literals = Vec::new();
+        let mut literal_rules: Vec<Vec<usize>> = Vec::new();
+        let mut filtered = Vec::new();
+
+        for (i, rule) in rules.iter().enumerate() {
+            let rule_literals = match &rule.engine {
+                Engine::Token(engine) => composition_literals(&engine.composition),
+                // text rules match the text of the sentence, not of the tokens
+                Engine::Text(..) => None,
+            };
+
+            filtered.push(rule_literals.is_some());
+            for literal in rule_literals.into_iter().flatten() {
+                let id = *literal_ids.entry(literal.clone()).or_insert_with(|| {
+                    literals.push(literal);
+                    literal_rules.push(Vec::new());
+                    literals.len() - 1
+                });
+
+                if literal_rules[id].last() != Some(&i) {
+                    literal_rules[id].push(i);
+                }
+            }
+        }
+
+        Prefilter {
+            literals,
+            literal_rules,
+            filtered,
+            ..Default::default()
+        }
+    }
+}
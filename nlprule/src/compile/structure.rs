@@ -1,122 +1,311 @@
+use super::Error;
 use serde::Deserialize;
-use std::fs::File;
-use std::io::BufReader;
+use std::io::BufRead;
 use xml::reader::EventReader;
 
 mod preprocess {
-    use xml::reader::EventReader;
-    use xml::writer::EmitterConfig;
+    use std::collections::HashMap;
+    use std::io::Read;
 
-    use super::Category;
+    use xml::common::{Position, TextPosition};
+    use xml::reader::{EventReader, XmlEvent};
+    use xml::writer::EmitterConfig;
 
-    pub fn sanitize(input: impl std::io::Read, whitespace_sensitive_tags: &[&str]) -> String {
-        let mut sanitized = Vec::new();
+    use super::{Category, Error};
+
+    /// Phrases can reference other phrases. References nested deeper than this are an error
+    /// so that cyclic phrases terminate.
+    const MAX_PHRASE_DEPTH: usize = 16;
+
+    /// The XML of a rule with inlined phrases or the reason why phrases could not be inlined.
+    pub type RuleXml = Result<String, String>;
+
+    /// The XML of a rule element with its category, the line it starts at and its `id` attribute.
+    pub type RuleElement = (RuleXml, Option<Category>, u64, Option<String>);
+
+    /// Reads the rules of an XML document one at a time instead of loading the whole document,
+    /// so the memory needed does not grow with the size of the document.
+    ///
+    /// Yields the XML of each rule, rule group and unification with its category, the (one-based) line it starts at
+    /// and its `id` attribute, if any.
+    /// In the XML, text is converted
+    /// to `<text text=".."/>` elements so it keeps its position relative to the other children when deserializing.
+    /// Whitespace is dropped unless it is inside one of the `whitespace_sensitive_tags`.
+    ///
+    /// Phrases defined in a `<phrases>` section are inlined at each `<phraseref>`, a reference which can not be
+    /// resolved is an error of the rule. As in LanguageTool, phrases must be defined before the rules using them.
+    ///
+    /// If the document can not be read e. g. because it is truncated or not well-formed, an [Error::Xml] with the
+    /// position in the document is yielded and reading stops.
+    pub struct RuleReader<'a, R: Read> {
+        events: EventReader<R>,
+        whitespace_sensitive_tags: &'a [&'a str],
+        parents: Vec<String>,
+        category: Option<Category>,
+        lang_code: Option<String>,
+        phrases: HashMap<String, String>,
+        failed: bool,
+    }
 
-        let mut writer = EmitterConfig::new()
-            .perform_indent(true)
-            .create_writer(&mut sanitized);
+    /// An error at `position` of the document.
+    fn error_at(position: TextPosition, message: impl ToString) -> Error {
+        Error::Xml {
+            line: Some(position.row + 1),
+            column: Some(position.column + 1),
+            message: message.to_string(),
+            rule_id: None,
+        }
+    }
 
-        let parser = EventReader::new(input);
+    impl<'a, R: Read> RuleReader<'a, R> {
+        pub fn new(input: R, whitespace_sensitive_tags: &'a [&'a str]) -> Self {
+            RuleReader {
+                events: EventReader::new(input),
+                whitespace_sensitive_tags,
+                parents: Vec::new(),
+                category: None,
+                lang_code: None,
+                phrases: HashMap::new(),
+                failed: false,
+            }
+        }
 
-        let events = parser
-            .into_iter()
-            .map(|x| x.expect("error reading XML"))
-            .filter(|x| {
-                // processing instructions break the writer and are useless to us
-                !matches!(x, xml::reader::XmlEvent::ProcessingInstruction { .. })
-            })
-            .collect::<Vec<_>>();
+        /// The language code in the `lang` attribute of the root element, known once the first rule is read.
+        pub fn lang_code(&self) -> Option<&str> {
+            self.lang_code.as_deref()
+        }
 
-        let mut out_events: Vec<xml::writer::XmlEvent> = Vec::new();
-        let mut parents = Vec::new();
+        fn next_event(&mut self) -> Result<XmlEvent, Error> {
+            self.events
+                .next()
+                .map_err(|x| error_at(x.position(), x.msg()))
+        }
 
-        for event in &events {
-            match event {
-                xml::reader::XmlEvent::StartElement { name, .. } => {
-                    parents.push(name.local_name.as_str());
-                }
-                xml::reader::XmlEvent::EndElement { .. } => {
-                    parents.pop();
-                }
-                xml::reader::XmlEvent::Characters(chars) => {
-                    out_events.push(
-                        xml::writer::XmlEvent::start_element("text")
-                            .attr("text", chars)
-                            .into(),
-                    );
-                    out_events.push(xml::writer::XmlEvent::end_element().into());
-                    continue;
-                }
-                xml::reader::XmlEvent::Whitespace(whitespace) => {
-                    if parents
-                        .iter()
-                        .any(|x| whitespace_sensitive_tags.contains(x))
+        /// Writes the element starting with `start` including all of its children.
+        fn read_element(&mut self, start: XmlEvent) -> Result<String, Error> {
+            let mut out = Vec::new();
+            let mut writer = EmitterConfig::new()
+                .perform_indent(true)
+                .write_document_declaration(false)
+                .create_writer(&mut out);
+
+            let mut parents: Vec<String> = Vec::new();
+            let mut event = start;
+
+            loop {
+                let text = match &event {
+                    XmlEvent::StartElement { name, .. } => {
+                        parents.push(name.local_name.clone());
+                        None
+                    }
+                    XmlEvent::EndElement { .. } => {
+                        parents.pop();
+                        None
+                    }
+                    XmlEvent::Characters(chars) => Some(chars.as_str()),
+                    XmlEvent::Whitespace(whitespace)
+                        if parents
+                            .iter()
+                            .any(|x| self.whitespace_sensitive_tags.contains(&x.as_str())) =>
                     {
-                        out_events.push(
-                            xml::writer::XmlEvent::start_element("text")
-                                .attr("text", whitespace)
-                                .into(),
-                        );
-                        out_events.push(xml::writer::XmlEvent::end_element().into());
-                        continue;
+                        Some(whitespace.as_str())
+                    }
+                    _ => None,
+                };
+
+                if let Some(text) = text {
+                    writer
+                        .write(xml::writer::XmlEvent::start_element("text").attr("text", text))
+                        .and_then(|_| writer.write(xml::writer::XmlEvent::end_element()))
+                        .map_err(|x| error_at(self.events.position(), x))?;
+                // processing instructions break the writer and are useless to us
+                } else if !matches!(event, XmlEvent::ProcessingInstruction { .. }) {
+                    if let Some(writer_event) = event.as_writer_event() {
+                        writer
+                            .write(writer_event)
+                            .map_err(|x| error_at(self.events.position(), x))?;
                     }
                 }
-                _ => {}
+
+                if parents.is_empty() {
+                    break;
+                }
+                event = self.next_event()?;
             }
 
-            if let Some(writer_event) = event.as_writer_event() {
-                out_events.push(writer_event);
+            drop(writer);
+            String::from_utf8(out).map_err(|x| error_at(self.events.position(), x))
+        }
+
+        fn inline_phrases(&self, xml: String) -> RuleXml {
+            if !xml.contains("phraseref") && !xml.contains("includephrases") {
+                return Ok(xml);
             }
+
+            let document = roxmltree::Document::parse(&xml).map_err(|x| x.to_string())?;
+            let mut out = String::new();
+            inline_phrases(&xml, document.root_element(), &self.phrases, 0, &mut out)?;
+            Ok(out)
         }
+    }
 
-        for event in out_events {
-            writer.write(event).expect("error writing to output XML");
+    impl<'a, R: Read> RuleReader<'a, R> {
+        fn read_next(&mut self) -> Result<Option<RuleElement>, Error> {
+            loop {
+                let event = self.next_event()?;
+
+                match &event {
+                    XmlEvent::StartElement {
+                        name, attributes, ..
+                    } => {
+                        let attribute = |key: &str| {
+                            attributes
+                                .iter()
+                                .find(|x| x.name.local_name == key)
+                                .map(|x| x.value.clone())
+                        };
+                        let name = name.local_name.as_str();
+                        let parent = self.parents.last().map(|x| x.as_str());
+
+                        if parent.is_none() {
+                            self.lang_code = attribute("lang");
+                        }
+
+                        if name == "phrase" && parent == Some("phrases") {
+                            let id = attribute("id");
+                            let xml = self.read_element(event)?;
+                            // references to a phrase without ID are reported as unknown by the rules using them
+                            if let Some(id) = id {
+                                self.phrases.insert(id, xml);
+                            }
+                        } else if name == "unification"
+                            || name == "rulegroup"
+                            || (name == "rule" && parent != Some("rulegroup"))
+                        {
+                            let category = if parent == Some("category") {
+                                self.category.clone()
+                            } else {
+                                None
+                            };
+                            let line = self.events.position().row + 1;
+                            let id = attribute("id");
+                            let xml = self.read_element(event)?;
+
+                            return Ok(Some((self.inline_phrases(xml), category, line, id)));
+                        } else {
+                            if name == "category" {
+                                // the rules of a category without ID or name are reported as not having a category
+                                self.category = match (attribute("id"), attribute("name")) {
+                                    (Some(id), Some(name)) => Some(Category {
+                                        id,
+                                        name,
+                                        kind: attribute("type"),
+                                        default: attribute("default"),
+                                    }),
+                                    _ => None,
+                                };
+                            }
+                            self.parents.push(name.to_owned());
+                        }
+                    }
+                    XmlEvent::EndElement { name } => {
+                        if name.local_name == "category" {
+                            self.category = None;
+                        }
+                        self.parents.pop();
+                    }
+                    XmlEvent::EndDocument => return Ok(None),
+                    _ => {}
+                }
+            }
         }
+    }
 
-        std::str::from_utf8(&sanitized)
-            .expect("invalid UTF-8")
-            .to_string()
+    impl<'a, R: Read> Iterator for RuleReader<'a, R> {
+        type Item = Result<RuleElement, Error>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.failed {
+                return None;
+            }
+
+            let element = self.read_next();
+            self.failed = element.is_err();
+            element.transpose()
+        }
     }
 
-    pub fn extract_rules(mut xml: impl std::io::Read) -> Vec<(String, Option<Category>)> {
-        let mut string = String::new();
-        xml.read_to_string(&mut string)
-            .expect("error writing to string.");
-
-        let document = roxmltree::Document::parse(&string).expect("error parsing XML");
-
-        document
-            .descendants()
-            .filter(|x| {
-                let name = x.tag_name().name();
-
-                name == "unification"
-                    || name == "rulegroup"
-                    || (name == "rule"
-                        && x.parent_element()
-                            .expect("must have parent")
-                            .tag_name()
-                            .name()
-                            != "rulegroup")
-            })
-            .map(|x| {
-                let xml = string[x.range()].to_string();
-                let parent = x.parent_element().expect("must have parent");
-
-                let category = if parent.tag_name().name() == "category" {
-                    Some(Category {
-                        id: parent.attribute("id").unwrap().to_owned(),
-                        name: parent.attribute("name").unwrap().to_owned(),
-                        kind: parent.attribute("type").map(|x| x.to_owned()),
-                        default: parent.attribute("default").map(|x| x.to_owned()),
-                    })
+    /// Writes the XML of the node to `out`, replacing each `<phraseref>` with the content
+    /// of the referenced phrase. An `<includephrases>` element with one reference is replaced by its content.
+    /// With multiple references, each referenced phrase is written to a `<phrase>` element inside
+    /// the `<includephrases>`, see [IncludePhrases][super::IncludePhrases].
+    fn inline_phrases(
+        string: &str,
+        node: roxmltree::Node,
+        phrases: &HashMap<String, String>,
+        depth: usize,
+        out: &mut String,
+    ) -> Result<(), String> {
+        match node.tag_name().name() {
+            "phraseref" => {
+                let id = node
+                    .attribute("idref")
+                    .ok_or("phraseref must have an idref")?;
+                let phrase = phrases
+                    .get(id)
+                    .ok_or_else(|| format!("unknown phrase \"{}\"", id))?;
+
+                if depth >= MAX_PHRASE_DEPTH {
+                    return Err(format!(
+                        "phrase \"{}\" is nested deeper than {} levels",
+                        id, MAX_PHRASE_DEPTH
+                    ));
+                }
+
+                let document = roxmltree::Document::parse(phrase).map_err(|x| x.to_string())?;
+                for child in document.root_element().children() {
+                    inline_phrases(phrase, child, phrases, depth + 1, out)?;
+                }
+            }
+            "includephrases" => {
+                let n_refs = node
+                    .children()
+                    .filter(|x| x.tag_name().name() == "phraseref")
+                    .count();
+
+                if n_refs > 1 {
+                    out.push_str("<includephrases>");
+                    for child in node.children().filter(|x| x.is_element()) {
+                        out.push_str("<phrase>");
+                        inline_phrases(string, child, phrases, depth, out)?;
+                        out.push_str("</phrase>");
+                    }
+                    out.push_str("</includephrases>");
                 } else {
-                    None
-                };
+                    for child in node.children() {
+                        inline_phrases(string, child, phrases, depth, out)?;
+                    }
+                }
+            }
+            _ => {
+                let has_refs = node
+                    .descendants()
+                    .any(|x| matches!(x.tag_name().name(), "phraseref" | "includephrases"));
+
+                match (has_refs, node.first_child(), node.last_child()) {
+                    (true, Some(first), Some(last)) => {
+                        out.push_str(&string[node.range().start..first.range().start]);
+                        for child in node.children() {
+                            inline_phrases(string, child, phrases, depth, out)?;
+                        }
+                        out.push_str(&string[last.range().end..node.range().end]);
+                    }
+                    _ => out.push_str(&string[node.range()]),
+                }
+            }
+        }
 
-                (xml, category)
-            })
-            .collect()
+        Ok(())
     }
 }
 
@@ -254,6 +443,7 @@ pub struct Exception {
     pub spacebefore: Option<String>,
     pub negate: Option<String>,
     pub negate_pos: Option<String>,
+    pub negate_chunk: Option<String>,
     pub scope: Option<String>,
     pub text: Option<XMLString>,
 }
@@ -280,6 +470,9 @@ pub struct Sub {
 pub struct Token {
     pub min: Option<String>,
     pub max: Option<String>,
+    /// How many tokens to take with `min` and `max` if the pattern can match with different counts:
+    /// `greedy` (the default), `lazy` or `possessive`. Not part of the LanguageTool format.
+    pub greediness: Option<String>,
     pub skip: Option<String>,
     pub case_sensitive: Option<String>,
     pub inflected: Option<String>,
@@ -291,6 +484,7 @@ pub struct Token {
     pub spacebefore: Option<String>,
     pub negate: Option<String>,
     pub negate_pos: Option<String>,
+    pub negate_chunk: Option<String>,
     #[serde(rename = "$value")]
     pub parts: Option<Vec<TokenPart>>,
 }
@@ -307,6 +501,7 @@ pub trait MatchAttributes {
     fn spacebefore(&self) -> &Option<String>;
     fn negate(&self) -> &Option<String>;
     fn negate_pos(&self) -> &Option<String>;
+    fn negate_chunk(&self) -> &Option<String>;
 }
 
 macro_rules! impl_match_attributes {
@@ -351,6 +546,10 @@ macro_rules! impl_match_attributes {
             fn negate_pos(&self) -> &Option<String> {
                 &self.negate_pos
             }
+
+            fn negate_chunk(&self) -> &Option<String> {
+                &self.negate_chunk
+            }
         }
     };
 }
@@ -393,6 +592,7 @@ pub enum UnifyTokenCombination {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Unify {
+    pub negate: Option<String>,
     #[serde(rename = "$value")]
     pub tokens: Vec<UnifyTokenCombination>,
 }
@@ -423,16 +623,87 @@ pub enum PatternPart {
     Or(TokenVector),
     And(TokenVector),
     Unify(Unify),
+    Includephrases(IncludePhrases),
+}
+
+/// Alternatives between phrases i. e. an `<includephrases>` with multiple `<phraseref>`s after inlining the phrases.
+/// Like in LanguageTool, the rule is compiled once for each alternative, see [Pattern::alternatives].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IncludePhrases {
+    #[serde(rename = "phrase")]
+    pub phrases: Vec<Phrase>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Phrase {
+    #[serde(rename = "$value", default)]
+    pub parts: Vec<PatternPart>,
+}
+
+/// All sequences of parts with each alternative between phrases replaced by the parts of one of the phrases.
+fn part_alternatives(parts: &[PatternPart]) -> Vec<Vec<PatternPart>> {
+    let mut alternatives = vec![Vec::new()];
+
+    for part in parts {
+        match part {
+            PatternPart::Includephrases(include) => {
+                let options: Vec<_> = include
+                    .phrases
+                    .iter()
+                    .flat_map(|phrase| part_alternatives(&phrase.parts))
+                    .collect();
+
+                alternatives = alternatives
+                    .iter()
+                    .flat_map(|prefix| {
+                        options.iter().map(move |option| {
+                            let mut parts = prefix.clone();
+                            parts.extend(option.iter().cloned());
+                            parts
+                        })
+                    })
+                    .collect();
+            }
+            part => alternatives.iter_mut().for_each(|x| x.push(part.clone())),
+        }
+    }
+
+    alternatives
 }
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Pattern {
     pub case_sensitive: Option<String>,
+    /// The number of tokens at the start of the pattern which are not marked. Predates `<marker>`.
+    pub mark_from: Option<String>,
+    /// The negated number of tokens at the end of the pattern which are not marked e. g. `-1`. Predates `<marker>`.
+    pub mark_to: Option<String>,
     #[serde(rename = "$value")]
     pub parts: Vec<PatternPart>,
 }
 
+impl Pattern {
+    /// One pattern for each combination of the phrases of its alternatives between phrases,
+    /// in the order of the `<phraseref>`s. A pattern without alternatives is returned as-is.
+    pub fn alternatives(&self) -> Vec<Pattern> {
+        part_alternatives(&self.parts)
+            .into_iter()
+            .map(|parts| Pattern {
+                parts,
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// One pattern for each alternative of each of the patterns, see [Pattern::alternatives].
+fn antipattern_alternatives(antipatterns: Option<Vec<Pattern>>) -> Option<Vec<Pattern>> {
+    antipatterns.map(|x| x.iter().flat_map(Pattern::alternatives).collect())
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Regex {
@@ -460,6 +731,30 @@ pub struct Rule {
     pub url: Option<XMLText>,
     pub default: Option<String>,
     pub filter: Option<Filter>,
+    #[serde(rename = "__unused_unifications")]
+    pub unifications: Option<Vec<Unification>>,
+    #[serde(rename = "__unused_lang_code")]
+    pub lang_code: Option<String>,
+}
+
+impl Rule {
+    /// One copy of the rule for each alternative of its pattern, see [Pattern::alternatives].
+    /// Like in LanguageTool, the copies have the same ID. Alternatives of antipatterns are separate antipatterns.
+    pub fn expand_phrases(mut self) -> Vec<Rule> {
+        self.antipatterns = antipattern_alternatives(self.antipatterns.take());
+
+        match self.pattern.take() {
+            Some(pattern) => pattern
+                .alternatives()
+                .into_iter()
+                .map(|pattern| Rule {
+                    pattern: Some(pattern),
+                    ..self.clone()
+                })
+                .collect(),
+            None => vec![self],
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -482,6 +777,7 @@ pub struct RuleGroup {
 pub enum RuleContainer {
     Rule(Rule),
     RuleGroup(RuleGroup),
+    Unification(Unification),
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -553,6 +849,24 @@ pub struct DisambiguationRule {
     #[serde(rename = "__unused_unifications")]
     pub unifications: Option<Vec<Unification>>,
     pub default: Option<String>,
+    #[serde(rename = "__unused_lang_code")]
+    pub lang_code: Option<String>,
+}
+
+impl DisambiguationRule {
+    /// See [Rule::expand_phrases].
+    pub fn expand_phrases(mut self) -> Vec<DisambiguationRule> {
+        self.antipatterns = antipattern_alternatives(self.antipatterns.take());
+
+        self.pattern
+            .alternatives()
+            .into_iter()
+            .map(|pattern| DisambiguationRule {
+                pattern,
+                ..self.clone()
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -601,11 +915,8 @@ pub enum DisambiguationRuleContainer {
 
 macro_rules! flatten_group {
     ($rulegroup:expr, $category:expr) => {{
-        let group_antipatterns = if let Some(antipatterns) = $rulegroup.antipatterns {
-            antipatterns
-        } else {
-            Vec::new()
-        };
+        let group_antipatterns =
+            antipattern_alternatives($rulegroup.antipatterns).unwrap_or_default();
 
         let group = Group {
             id: $rulegroup.id,
@@ -618,6 +929,8 @@ macro_rules! flatten_group {
             .rules
             .into_iter()
             .enumerate()
+            // the copies of a rule for alternatives between phrases have the same number in the group
+            .flat_map(|(i, rule)| rule.expand_phrases().into_iter().map(move |rule| (i, rule)))
             .map(|(i, mut rule)| {
                 if let Some(antipatterns) = &mut rule.antipatterns {
                     antipatterns.extend(group_antipatterns.clone());
@@ -633,71 +946,172 @@ macro_rules! flatten_group {
     }};
 }
 
+/// Converts an error reading a rule to a compile error with the ID of the rule, if known.
+fn rule_error(error: serde_xml_rs::Error, id: Option<&str>) -> Error {
+    let error = Error::from(error);
+
+    match id {
+        Some(id) => error.with_rule_id(id),
+        None => error,
+    }
+}
+
+/// An error for a rule which is valid XML but lacks information needed to compile it.
+fn malformed(line: u64, message: &str) -> serde_xml_rs::Error {
+    serde::de::Error::custom(format!("rule at line {} {}", line, message))
+}
+
+/// Rules outside of a rule group take their ID and name from their own attributes.
+fn check_ungrouped(line: u64, has_id: bool, has_name: bool) -> Result<(), serde_xml_rs::Error> {
+    if !has_id {
+        Err(malformed(line, "has no `id` and is not in a `rulegroup`"))
+    } else if !has_name {
+        Err(malformed(line, "has no `name` and is not in a `rulegroup`"))
+    } else {
+        Ok(())
+    }
+}
+
 type GrammarRuleReading = (Rule, Option<Group>, Option<Category>);
 type DisambiguationRuleReading = (DisambiguationRule, Option<Group>, Option<Category>);
 
-pub fn read_rules<P: AsRef<std::path::Path>>(
-    path: P,
-) -> Vec<Result<GrammarRuleReading, serde_xml_rs::Error>> {
-    let file = File::open(path).unwrap();
-    let file = BufReader::new(file);
+/// Reads the readings of each element of `rule_reader` with `read` one element at a time.
+/// `read` collects the unifications, `finish` sets the unifications read so far and the language code
+/// on each reading.
+fn stream_readings<R: BufRead, T>(
+    mut rule_reader: preprocess::RuleReader<'static, R>,
+    mut read: impl FnMut(
+        preprocess::RuleXml,
+        Option<Category>,
+        u64,
+        &mut Vec<Unification>,
+    ) -> Vec<Result<T, serde_xml_rs::Error>>,
+    finish: fn(&mut T, &[Unification], Option<String>),
+) -> impl Iterator<Item = Result<T, Error>> {
+    let mut unifications = Vec::new();
+    let mut readings = Vec::new().into_iter();
 
-    let sanitized = preprocess::sanitize(file, &["suggestion"]);
-    let rules = preprocess::extract_rules(sanitized.as_bytes());
+    std::iter::from_fn(move || loop {
+        if let Some(reading) = readings.next() {
+            return Some(reading);
+        }
 
-    rules
-        .into_iter()
-        .map(|(xml, category)| {
-            let mut out = Vec::new();
+        let (xml, category, line, id) = match rule_reader.next()? {
+            Ok(element) => element,
+            Err(err) => return Some(Err(err)),
+        };
+        let lang_code = rule_reader.lang_code().map(|x| x.to_owned());
 
-            let deseralized = RuleContainer::deserialize(&mut serde_xml_rs::Deserializer::new(
-                EventReader::new(xml.as_bytes()),
-            ));
+        readings = read(xml, category, line, &mut unifications)
+            .into_iter()
+            .map(|reading| match reading {
+                Ok(mut reading) => {
+                    finish(&mut reading, &unifications, lang_code.clone());
+                    Ok(reading)
+                }
+                Err(err) => Err(rule_error(err, id.as_deref())),
+            })
+            .collect::<Vec<_>>()
+            .into_iter();
+    })
+}
+
+/// Reads the grammar rules of an XML document one rule element at a time, see [preprocess::RuleReader].
+/// Like phrases, unifications have to be defined before the rules using them.
+pub fn read_rules<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<GrammarRuleReading, Error>> {
+    let rule_reader = preprocess::RuleReader::new(reader, &["suggestion"]);
+
+    stream_readings(
+        rule_reader,
+        |xml, category, line, unifications| {
+            let deseralized = xml.map_err(serde::de::Error::custom).and_then(|xml| {
+                RuleContainer::deserialize(&mut serde_xml_rs::Deserializer::new(EventReader::new(
+                    xml.as_bytes(),
+                )))
+            });
+
+            // unifications are defined outside of categories
+            if category.is_none() && !matches!(deseralized, Ok(RuleContainer::Unification(_))) {
+                return vec![Err(malformed(
+                    line,
+                    "is not inside a `category` with `id` and `name`",
+                ))];
+            }
 
-            out.extend(match deseralized {
+            match deseralized {
                 Ok(rule_container) => match rule_container {
                     RuleContainer::Rule(rule) => {
-                        vec![Ok((rule, None, category))]
+                        match check_ungrouped(line, rule.id.is_some(), rule.name.is_some()) {
+                            Ok(()) => rule
+                                .expand_phrases()
+                                .into_iter()
+                                .map(|rule| Ok((rule, None, category.clone())))
+                                .collect(),
+                            Err(err) => vec![Err(err)],
+                        }
+                    }
+                    RuleContainer::RuleGroup(mut rule_group) => {
+                        // the rules inherit the metadata of the group unless they override it
+                        for rule in rule_group.rules.iter_mut() {
+                            if rule.short.is_none() {
+                                rule.short = rule_group.short.clone();
+                            }
+                            if rule.url.is_none() {
+                                rule.url = rule_group.url.clone();
+                            }
+                        }
+
+                        flatten_group!(rule_group, category)
+                            .into_iter()
+                            .map(Ok)
+                            .collect()
+                    }
+                    RuleContainer::Unification(unification) => {
+                        unifications.push(unification);
+
+                        vec![]
                     }
-                    RuleContainer::RuleGroup(rule_group) => flatten_group!(rule_group, category)
-                        .into_iter()
-                        .map(Ok)
-                        .collect(),
                 },
                 Err(err) => vec![Err(err)],
-            });
-            out
-        })
-        .flatten()
-        .collect()
+            }
+        },
+        |reading, unifications, lang_code| {
+            reading.0.unifications = Some(unifications.to_vec());
+            reading.0.lang_code = lang_code;
+        },
+    )
 }
 
-pub fn read_disambiguation_rules<P: AsRef<std::path::Path>>(
-    path: P,
-) -> Vec<Result<DisambiguationRuleReading, serde_xml_rs::Error>> {
-    let file = File::open(path).unwrap();
-    let file = BufReader::new(file);
-
-    let sanitized = preprocess::sanitize(file, &[]);
-    let rules = preprocess::extract_rules(sanitized.as_bytes());
-
-    let mut unifications = Vec::new();
-
-    let rules: Vec<_> = rules
-        .into_iter()
-        .map(|(xml, _)| {
-            let mut out = Vec::new();
-
-            let deseralized = DisambiguationRuleContainer::deserialize(
-                &mut serde_xml_rs::Deserializer::new(EventReader::new(xml.as_bytes())),
-            );
+/// Reads the disambiguation rules of an XML document one rule element at a time, see [read_rules].
+pub fn read_disambiguation_rules<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<DisambiguationRuleReading, Error>> {
+    let rule_reader = preprocess::RuleReader::new(reader, &[]);
+
+    stream_readings(
+        rule_reader,
+        |xml, _, line, unifications| {
+            let deseralized = xml.map_err(serde::de::Error::custom).and_then(|xml| {
+                DisambiguationRuleContainer::deserialize(&mut serde_xml_rs::Deserializer::new(
+                    EventReader::new(xml.as_bytes()),
+                ))
+            });
 
             let category: Option<Category> = None;
 
-            out.extend(match deseralized {
+            match deseralized {
                 Ok(rule_container) => match rule_container {
                     DisambiguationRuleContainer::Rule(rule) => {
-                        vec![Ok((rule, None, category))]
+                        match check_ungrouped(line, rule.id.is_some(), true) {
+                            Ok(()) => rule
+                                .expand_phrases()
+                                .into_iter()
+                                .map(|rule| Ok((rule, None, category.clone())))
+                                .collect(),
+                            Err(err) => vec![Err(err)],
+                        }
                     }
                     DisambiguationRuleContainer::RuleGroup(rule_group) => {
                         flatten_group!(rule_group, category)
@@ -712,21 +1126,11 @@ pub fn read_disambiguation_rules<P: AsRef<std::path::Path>>(
                     }
                 },
                 Err(err) => vec![Err(err)],
-            });
-            out
-        })
-        .flatten()
-        .collect();
-
-    rules
-        .into_iter()
-        .map(|result| match result {
-            Ok(mut x) => {
-                x.0.unifications = Some(unifications.clone());
-
-                Ok(x)
             }
-            Err(x) => Err(x),
-        })
-        .collect()
+        },
+        |reading, unifications, lang_code| {
+            reading.0.unifications = Some(unifications.to_vec());
+            reading.0.lang_code = lang_code;
+        },
+    )
 }
@@ -0,0 +1,299 @@
+//! Fixtures shared by the tests of compiling and applying rules.
+
+use std::{collections::HashSet, io::Write, sync::Arc};
+
+use super::{BuildInfo, Error, RegexCache};
+use crate::{
+    rules::{Rules, RulesOptions},
+    tokenizer::{
+        chunk::Chunker, multiword::MultiwordTagger, tag::Tagger, SpecialTokenRule, Tokenizer,
+        TokenizerOptions,
+    },
+};
+
+/// Grammar rules which cover matching tags, chunks, multiwords and special tokens.
+pub(crate) const GRAMMAR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="PARAGRAPH_END" name="Paragraph end">
+            <pattern>
+                <token postag="PARA_END">here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">It ends <marker>here</marker></example>
+        </rule>
+        <rule id="HERE_NEXT" name="Here next">
+            <pattern>
+                <token>here</token>
+                <token>Next</token>
+            </pattern>
+            <message>Did you mean <suggestion>here next</suggestion>?</message>
+            <example correction="here next">It ends <marker>here Next</marker> one.</example>
+        </rule>
+        <rule id="IN_PROPER_NOUN" name="In proper noun">
+            <pattern>
+                <token>in</token>
+                <token postag="NNP" />
+            </pattern>
+            <message>Did you mean <suggestion>at \2</suggestion>?</message>
+            <example correction="at New">He lives <marker>in New</marker> York.</example>
+        </rule>
+        <rule id="L_HOMME" name="L'homme">
+            <pattern>
+                <token>l'</token>
+                <token>homme</token>
+            </pattern>
+            <message>Did you mean <suggestion>l'humain</suggestion>?</message>
+            <example correction="l'humain">C'est <marker>l'homme</marker>.</example>
+        </rule>
+        <rule id="USES_NOUN" name="Uses noun">
+            <pattern>
+                <token>uses</token>
+                <token postag="NN" />
+            </pattern>
+            <message>Did you mean <suggestion>uses a \2</suggestion>?</message>
+            <example correction="uses a tool">He <marker>uses tool</marker>.</example>
+        </rule>
+        <rule id="HAVE_WENT" name="Have went">
+            <pattern>
+                <token>have</token>
+                <token>went</token>
+            </pattern>
+            <message>Did you mean <suggestion>\1 gone</suggestion> or <suggestion>\1 go</suggestion>?</message>
+            <example correction="have gone|have go">I <marker>have went</marker> home.</example>
+        </rule>
+        <rule id="PAST_AFTER_YESTERDAY" name="Past after yesterday">
+            <pattern>
+                <token>Yesterday</token>
+                <token>I</token>
+                <token postag="VB" />
+            </pattern>
+            <message>Did you mean <suggestion>\1 \2 <match no="3" postag="VBD" /></suggestion>?</message>
+            <example correction="Yesterday I went">
+                <marker>Yesterday I go</marker> home.
+            </example>
+        </rule>
+        <rule id="NUMBER_ITEMS" name="Number items">
+            <pattern>
+                <token postag="CD" />
+                <token>items</token>
+            </pattern>
+            <message>Did you mean <suggestion>\1 things</suggestion>?</message>
+            <example correction="42 things">There are <marker>42 items</marker>.</example>
+        </rule>
+        <rule id="NOUN_PHRASE_AFTER_THE" name="Noun phrase after the">
+            <pattern>
+                <token>the</token>
+                <token chunk="I-NP-singular|E-NP-singular" />
+            </pattern>
+            <message>Did you mean <suggestion>a \2</suggestion>?</message>
+            <example correction="a light"><marker>the light</marker> runs.</example>
+        </rule>
+        <rule id="RUNS_OUTSIDE_NOUN_PHRASE" name="Runs outside noun phrase">
+            <pattern>
+                <token chunk_re="[BIE]-NP-.*" negate_chunk="yes">runs</token>
+                <token chunk="E-NP-singular">lamp</token>
+            </pattern>
+            <message>Did you mean <suggestion>runs a lamp</suggestion>?</message>
+            <example correction="runs a lamp">He <marker>runs lamp</marker>.</example>
+        </rule>
+        <rule id="LAMP_OUTSIDE_NOUN_PHRASE" name="Lamp outside noun phrase">
+            <pattern>
+                <token chunk="B-NP-singular" negate_chunk="yes">lamp</token>
+                <token>runs</token>
+            </pattern>
+            <message>Did you mean <suggestion>a lamp runs</suggestion>?</message>
+            <example correction="a lamp runs">The <marker>lamp runs</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+
+/// Disambiguation rules which remove tags, filter tags and immunize tokens.
+pub(crate) const DISAMBIGUATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <rule id="NEXT_AFTER_HERE" name="Next after here">
+        <pattern>
+            <token>here</token>
+            <marker>
+                <token>Next</token>
+            </marker>
+        </pattern>
+        <disambig action="remove" postag="JJ" />
+    </rule>
+    <rule id="IMMUNIZE_AFTER_QUOTE" name="Immunize after quote">
+        <pattern>
+            <token>quote</token>
+            <marker>
+                <token>here</token>
+            </marker>
+        </pattern>
+        <disambig action="immunize" />
+    </rule>
+    <rule id="ORDINAL_BEFORE_CENTURY" name="Ordinal before century">
+        <pattern>
+            <marker>
+                <token postag="ORD" />
+            </marker>
+            <token>century</token>
+        </pattern>
+        <disambig action="filter" postag="ORD" />
+    </rule>
+</rules>
+"#;
+
+/// Writes `content` to a file in the temporary directory which is unique to this process.
+pub(crate) fn write_temp(name: &str, content: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("nlprule_{}_{}", std::process::id(), name));
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(content.as_bytes()).unwrap();
+    path
+}
+
+/// Compiles the tokenizer from [DISAMBIGUATION] and the rules from [GRAMMAR] with a small tag dictionary,
+/// multiwords and special token rules.
+pub(crate) fn setup() -> (Tokenizer, Rules) {
+    let tag_path = write_temp(
+        "tags.txt",
+        "here\there\tRB\nNext\tnext\tJJ\nruns\trun\tVBZ\nthe\tthe\tDT\ntool\ttool\tNN\n\
+         go\tgo\tVB\nwent\tgo\tVBD\ngone\tgo\tVBN\n",
+    );
+    let grammar_path = write_temp("grammar.xml", GRAMMAR);
+    let disambiguation_path = write_temp("disambiguation.xml", DISAMBIGUATION);
+    let multiword_path = write_temp(
+        "multiwords.txt",
+        "# comment\nNew York\tNNP\nNew York City\tNNP\n",
+    );
+
+    let options = TokenizerOptions::builder()
+        .special_token_rules(vec![
+            SpecialTokenRule::new("[0-9]+([.,][0-9]+)*", "CD"),
+            SpecialTokenRule::new("[0-9]*(1st|2nd|3rd|[04-9]th|1[1-3]th)", "JJ"),
+            SpecialTokenRule::new("[0-9]*(1st|2nd|3rd|[04-9]th|1[1-3]th)", "ORD"),
+        ])
+        .build()
+        .unwrap();
+
+    let mut extra_tags = MultiwordTagger::tags_from_dump(&multiword_path).unwrap();
+    extra_tags.extend(options.special_token_rules.iter().map(|x| x.tag.clone()));
+
+    let tagger = Arc::new(
+        Tagger::from_dumps::<_, &str, _>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &extra_tags,
+            &HashSet::new(),
+        )
+        .unwrap(),
+    );
+    let mut build_info = BuildInfo::new(tagger.clone(), RegexCache::new(0));
+    let rules = Rules::from_xml(&grammar_path, &mut build_info, RulesOptions::default()).unwrap();
+    let multiword_tagger = MultiwordTagger::from_dump(&multiword_path, &build_info).unwrap();
+
+    let tokenizer = Tokenizer::from_xml(
+        &disambiguation_path,
+        &mut build_info,
+        None,
+        Some(multiword_tagger),
+        options,
+    )
+    .unwrap();
+    assert_eq!(tokenizer.rules().len(), 3);
+
+    (tokenizer, rules)
+}
+
+/// Compiles a rule set with one rule using a regex, a part-of-speech tag and a match in the suggestion.
+pub(crate) fn tiny_rules() -> (Arc<Tagger>, Rules) {
+    let tag_path = write_temp(
+        "tiny_tags.txt",
+        "Yesterday\tyesterday\tNN\nwe\twe\tPRP\nthey\tthey\tPRP\n\
+         go\tgo\tVB\nwent\tgo\tVBD\n",
+    );
+    let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="GO_AFTER_YESTERDAY" name="Go after yesterday">
+            <pattern>
+                <token>Yesterday</token>
+                <token regexp="yes">we|they</token>
+                <token postag="VB" />
+            </pattern>
+            <message>Did you mean <suggestion>\1 \2 <match no="3" postag="VBD" /></suggestion>?</message>
+            <example correction="Yesterday we went"><marker>Yesterday we go</marker> home.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+    let tagger = Arc::new(
+        Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap(),
+    );
+    let mut build_info = BuildInfo::new(tagger.clone(), RegexCache::new(0));
+    let rules =
+        Rules::from_xml_reader(grammar.as_bytes(), &mut build_info, RulesOptions::default())
+            .unwrap();
+
+    (tagger, rules)
+}
+
+/// A maxent model which predicts nothing.
+pub(crate) const EMPTY_MODEL: &str = r#"{ "outcome_labels": ["O"], "pmap": {} }"#;
+
+/// A chunk model which predicts the same chunk for each word regardless of context.
+pub(crate) const WORD_CHUNK_MODEL: &str = r#"{
+    "outcome_labels": ["B-NP", "I-NP", "O"],
+    "pmap": {
+        "w0=the": { "parameters": [5.0], "outcomes": [0] },
+        "w0=light": { "parameters": [5.0], "outcomes": [1] },
+        "w0=runs": { "parameters": [5.0], "outcomes": [2] },
+        "w0=lamp": { "parameters": [5.0], "outcomes": [0] }
+    }
+}"#;
+
+pub(crate) fn word_chunker() -> Chunker {
+    Chunker::from_json(chunker_json(WORD_CHUNK_MODEL).as_bytes()).unwrap()
+}
+
+/// Compiles the grammar rules in `xml` with the tagger of `tokenizer`. Also returns the errors of the rules
+/// which could not be compiled.
+pub(crate) fn compile_rules_with_errors(xml: &str, tokenizer: &Tokenizer) -> (Rules, Vec<Error>) {
+    Rules::from_xml_reader_with_errors(
+        xml.as_bytes(),
+        &mut build_info_for(tokenizer),
+        RulesOptions::default(),
+    )
+    .unwrap()
+}
+
+/// Compiles the grammar rules in `xml` like [compile_rules_with_errors] and checks that all rules compile.
+pub(crate) fn compile_rules(xml: &str, tokenizer: &Tokenizer) -> Rules {
+    let (rules, errors) = compile_rules_with_errors(xml, tokenizer);
+    assert!(errors.is_empty(), "{:?}", errors);
+    rules
+}
+
+/// Information to compile rules with the tagger of `tokenizer`.
+pub(crate) fn build_info_for(tokenizer: &Tokenizer) -> BuildInfo {
+    BuildInfo::new(tokenizer.tagger().clone(), RegexCache::new(0))
+}
+
+/// Checks that all rules pass their examples.
+pub(crate) fn assert_examples_pass(rules: &Rules, tokenizer: &Tokenizer) {
+    for rule in rules.rules() {
+        assert!(rule.test(tokenizer), "{} fails its examples", rule.id());
+    }
+}
+
+/// The JSON of a chunker with the `chunk_model` whose token and POS models predict nothing.
+pub(crate) fn chunker_json(chunk_model: &str) -> String {
+    format!(
+        r#"{{ "token_model": {0}, "pos_model": {0}, "pos_tagdict": {{}}, "chunk_model": {1} }}"#,
+        EMPTY_MODEL, chunk_model
+    )
+}
@@ -0,0 +1,445 @@
+//! Downloads the tokenizer and rules binaries of a language from the release of this version of nlprule
+//! and caches them. Needs the `fetch` feature.
+//!
+//! ```no_run
+//! use nlprule::{fetch, Rules, Tokenizer};
+//!
+//! let (tokenizer_path, rules_path) = fetch::binaries("en", std::path::Path::new("cache"))?;
+//! let tokenizer = Tokenizer::new(tokenizer_path)?;
+//! let rules = Rules::load(rules_path, &tokenizer)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use thiserror::Error;
+
+/// Where the binaries of each release are published.
+pub const RELEASES_URL: &str = "https://github.com/bminixhofer/nlprule/releases/download";
+
+/// Distinguishes the temporary files of concurrent downloads in one process.
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Error, Debug)]
+pub enum FetchError {
+    #[error("invalid language code: {0:?}")]
+    InvalidLanguage(String),
+    #[error("failed to download {url}: {message}")]
+    Download { url: String, message: String },
+    #[error("invalid checksum file at {url}")]
+    InvalidChecksum { url: String },
+    /// The downloaded binary does not have the published checksum e. g. because the download was corrupted.
+    #[error("the SHA-256 of {url} is {found}, expected {expected}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        found: String,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Gets the paths of the tokenizer and rules binaries for the language code `lang` e. g. `en`. The binaries are
+/// downloaded from the release of this version of nlprule and checked against their published SHA-256 the first
+/// time, then they are read from `cache_dir`.
+///
+/// Next to each binary, the SHA-256 of its decompressed content is stored in `{name}.sha256`. A cached binary is
+/// hashed and compared to it on every call and downloaded again if it does not match, so a binary which was
+/// modified or corrupted after it was cached is never returned.
+///
+/// Can be called concurrently, also from multiple processes: each download is streamed to a temporary file which
+/// is renamed when complete, so the returned paths always point to complete binaries.
+pub fn binaries(lang: &str, cache_dir: &Path) -> Result<(PathBuf, PathBuf), FetchError> {
+    binaries_from(RELEASES_URL, lang, cache_dir)
+}
+
+/// Gets the binaries like [binaries] but downloads them from `url` instead of the releases e. g. from a mirror.
+/// For version `v` of nlprule, the tokenizer is downloaded from `{url}/{v}/{lang}_tokenizer.bin.gz` and its
+/// checksum from `{url}/{v}/{lang}_tokenizer.bin.gz.sha256`, the same for the rules.
+pub fn binaries_from(
+    url: &str,
+    lang: &str,
+    cache_dir: &Path,
+) -> Result<(PathBuf, PathBuf), FetchError> {
+    if lang.is_empty() || !lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(FetchError::InvalidLanguage(lang.to_string()));
+    }
+
+    let version = env!("CARGO_PKG_VERSION");
+    let dir = cache_dir.join(version);
+    fs::create_dir_all(&dir)?;
+
+    let url = format!("{}/{}", url.trim_end_matches('/'), version);
+    let tokenizer = fetch(&url, &format!("{}_tokenizer.bin", lang), &dir)?;
+    let rules = fetch(&url, &format!("{}_rules.bin", lang), &dir)?;
+
+    Ok((tokenizer, rules))
+}
+
+fn download(url: &str) -> Result<reqwest::blocking::Response, FetchError> {
+    reqwest::blocking::get(url)
+        .and_then(|x| x.error_for_status())
+        .map_err(|error| FetchError::Download {
+            url: url.to_string(),
+            message: error.to_string(),
+        })
+}
+
+/// Parses a checksum file in the format of `sha256sum` i. e. the hex digest followed by the file name.
+fn parse_checksum(bytes: &[u8]) -> Option<String> {
+    let digest = std::str::from_utf8(bytes).ok()?.split_whitespace().next()?;
+
+    if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(digest.to_lowercase())
+    } else {
+        None
+    }
+}
+
+/// Hashes everything read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Hashes everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether `path` is a cached binary with the SHA-256 stored in `checksum_path`.
+fn is_cached(path: &Path, checksum_path: &Path) -> bool {
+    let expected = match fs::read(checksum_path)
+        .ok()
+        .and_then(|x| parse_checksum(&x))
+    {
+        Some(expected) => expected,
+        None => return false,
+    };
+
+    matches!(hash_file(path), Ok(found) if found == expected)
+}
+
+/// Creates `path` by calling `write` on a temporary file in the same directory and renaming it. Renaming is
+/// atomic, a concurrent download of the same binary is replaced by an equal file.
+fn persist(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), FetchError>,
+) -> Result<(), FetchError> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.{}",
+        name,
+        std::process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create(&temp_path)
+        .map_err(FetchError::from)
+        .and_then(|mut file| write(&mut file))
+        .and_then(|_| fs::rename(&temp_path, path).map_err(FetchError::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Downloads `{url}/{name}.gz` if `dir` does not contain a valid `name` yet, checks it and stores it decompressed.
+fn fetch(url: &str, name: &str, dir: &Path) -> Result<PathBuf, FetchError> {
+    let path = dir.join(name);
+    let checksum_path = dir.join(format!("{}.sha256", name));
+    if is_cached(&path, &checksum_path) {
+        return Ok(path);
+    }
+
+    let url = format!("{}/{}.gz", url, name);
+    let checksum_url = format!("{}.sha256", url);
+    let mut checksum = Vec::new();
+    download(&checksum_url)?.read_to_end(&mut checksum)?;
+    let expected =
+        parse_checksum(&checksum).ok_or(FetchError::InvalidChecksum { url: checksum_url })?;
+
+    let mut digest = None;
+    persist(&path, |file| {
+        let mut reader = HashingReader {
+            inner: download(&url)?,
+            hasher: Sha256::new(),
+        };
+        let mut writer = HashingWriter {
+            inner: BufWriter::new(file),
+            hasher: Sha256::new(),
+        };
+
+        // stored decompressed so the binaries can be memory-mapped
+        let decompressed = io::copy(&mut GzDecoder::new(&mut reader), &mut writer)
+            .and_then(|_| writer.flush())
+            // anything after the gzip stream is part of the checksum too
+            .and_then(|_| io::copy(&mut reader, &mut io::sink()));
+
+        // a corrupted download is reported as such even if it also fails to decompress
+        let found = format!("{:x}", reader.hasher.finalize());
+        if found != expected {
+            return Err(FetchError::ChecksumMismatch {
+                url: url.clone(),
+                expected: expected.clone(),
+                found,
+            });
+        }
+        decompressed?;
+
+        digest = Some(format!("{:x}", writer.hasher.finalize()));
+        Ok(())
+    })?;
+
+    // written after the binary: if this fails, the binary is downloaded again next time
+    let digest = digest.expect("digest is set if the binary was persisted");
+    persist(&checksum_path, |file| {
+        writeln!(file, "{}  {}", digest, name)?;
+        Ok(())
+    })?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Rules, Tokenizer};
+    use flate2::write::GzEncoder;
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::Arc,
+        thread,
+    };
+
+    static TOKENIZER: &[u8] = include_bytes!("compile/fixtures/tokenizer.bin");
+    static RULES: &[u8] = include_bytes!("compile/fixtures/rules.bin");
+
+    /// Serves the files at their path over HTTP on a local port. Returns the URL of the server and
+    /// the number of requests it got.
+    fn serve(files: HashMap<String, Vec<u8>>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let files = Arc::new(files);
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let files = files.clone();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                thread::spawn(move || {
+                    let mut lines = BufReader::new(&stream).lines();
+                    let request = lines.next().unwrap().unwrap();
+                    // the request has to be read completely before responding
+                    for line in lines {
+                        if line.unwrap().is_empty() {
+                            break;
+                        }
+                    }
+
+                    let path = request.split_whitespace().nth(1).unwrap_or_default();
+                    let (status, body) = match files.get(path) {
+                        Some(body) => ("200 OK", body.as_slice()),
+                        None => ("404 Not Found", &[][..]),
+                    };
+                    write!(
+                        stream,
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(body).unwrap();
+                });
+            }
+        });
+
+        (url, requests)
+    }
+
+    /// The files of a release with the fixture binaries for the language `lang`.
+    fn release(lang: &str) -> HashMap<String, Vec<u8>> {
+        let mut files = HashMap::new();
+
+        for (name, binary) in [("tokenizer", TOKENIZER), ("rules", RULES)] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(binary).unwrap();
+            let bytes = encoder.finish().unwrap();
+
+            let path = format!("/{}/{}_{}.bin.gz", env!("CARGO_PKG_VERSION"), lang, name);
+            let checksum = format!("{:x}  {}_{}.bin.gz\n", Sha256::digest(&bytes), lang, name);
+            files.insert(format!("{}.sha256", path), checksum.into_bytes());
+            files.insert(path, bytes);
+        }
+
+        files
+    }
+
+    /// A cache directory which is not shared with other tests or concurrent test runs.
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("nlprule_fetch_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn binaries_are_downloaded_and_cached() {
+        let (url, requests) = serve(release("en"));
+        let dir = cache_dir("cached");
+
+        let (tokenizer_path, rules_path) = binaries_from(&url, "en", &dir).unwrap();
+        assert!(tokenizer_path.starts_with(dir.join(env!("CARGO_PKG_VERSION"))));
+        assert_eq!(fs::read(&tokenizer_path).unwrap(), TOKENIZER);
+        assert_eq!(fs::read(&rules_path).unwrap(), RULES);
+        let tokenizer = Tokenizer::new(&tokenizer_path).unwrap();
+        assert!(Rules::load(&rules_path, &tokenizer).is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // the second time the binaries are read from the cache
+        let paths = binaries_from(&url, "en", &dir).unwrap();
+        assert_eq!(paths, (tokenizer_path, rules_path));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn concurrent_downloads_give_complete_binaries() {
+        let (url, _) = serve(release("en"));
+        let dir = cache_dir("concurrent");
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (url, dir) = (url.clone(), dir.clone());
+                thread::spawn(move || binaries_from(&url, "en", &dir).unwrap())
+            })
+            .collect();
+
+        for handle in handles {
+            let (tokenizer_path, rules_path) = handle.join().unwrap();
+            assert_eq!(fs::read(tokenizer_path).unwrap(), TOKENIZER);
+            assert_eq!(fs::read(rules_path).unwrap(), RULES);
+        }
+        // no temporary files are left, only the binaries and their checksums
+        let files = fs::read_dir(dir.join(env!("CARGO_PKG_VERSION"))).unwrap();
+        assert_eq!(files.count(), 4);
+    }
+
+    #[test]
+    fn modified_binaries_are_downloaded_again() {
+        let (url, requests) = serve(release("en"));
+        let dir = cache_dir("modified");
+
+        let (tokenizer_path, rules_path) = binaries_from(&url, "en", &dir).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // e. g. truncated by a full disk
+        fs::write(&tokenizer_path, &TOKENIZER[..100]).unwrap();
+        binaries_from(&url, "en", &dir).unwrap();
+        assert_eq!(fs::read(&tokenizer_path).unwrap(), TOKENIZER);
+        assert_eq!(requests.load(Ordering::SeqCst), 6);
+
+        // e. g. cached by a version without checksums
+        fs::remove_file(
+            dir.join(env!("CARGO_PKG_VERSION"))
+                .join("en_rules.bin.sha256"),
+        )
+        .unwrap();
+        binaries_from(&url, "en", &dir).unwrap();
+        assert_eq!(fs::read(&rules_path).unwrap(), RULES);
+        assert_eq!(requests.load(Ordering::SeqCst), 8);
+
+        binaries_from(&url, "en", &dir).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn invalid_downloads_are_not_cached() {
+        let mut files = release("en");
+        let checksum = format!("/{}/en_rules.bin.gz.sha256", env!("CARGO_PKG_VERSION"));
+        files.insert(
+            checksum,
+            format!("{:x}  en_rules.bin.gz", Sha256::digest(b"")).into(),
+        );
+        let (url, _) = serve(files);
+        let dir = cache_dir("invalid");
+
+        assert!(matches!(
+            binaries_from(&url, "en", &dir),
+            Err(FetchError::ChecksumMismatch { url, .. }) if url.ends_with("/en_rules.bin.gz")
+        ));
+        assert!(!dir
+            .join(env!("CARGO_PKG_VERSION"))
+            .join("en_rules.bin")
+            .exists());
+
+        let error = binaries_from(&url, "de", &dir).unwrap_err();
+        assert!(
+            matches!(&error, FetchError::Download { url, .. } if url.ends_with("de_tokenizer.bin.gz.sha256"))
+        );
+        assert!(error.to_string().contains("404"));
+
+        // the published checksum matches but the binary is not valid gzip
+        let mut files = release("en");
+        let path = format!("/{}/en_tokenizer.bin.gz", env!("CARGO_PKG_VERSION"));
+        files.insert(path.clone(), b"not gzip".to_vec());
+        files.insert(
+            format!("{}.sha256", path),
+            format!("{:x}  en_tokenizer.bin.gz", Sha256::digest(b"not gzip")).into(),
+        );
+        let (url, _) = serve(files);
+        let dir = cache_dir("invalid_gzip");
+
+        assert!(matches!(
+            binaries_from(&url, "en", &dir),
+            Err(FetchError::Io(_))
+        ));
+        // the temporary file is removed
+        let files = fs::read_dir(dir.join(env!("CARGO_PKG_VERSION"))).unwrap();
+        assert_eq!(files.count(), 0);
+
+        assert!(matches!(
+            binaries_from(&url, "../en", &dir),
+            Err(FetchError::InvalidLanguage(_))
+        ));
+        assert_eq!(parse_checksum(b"not a checksum"), None);
+    }
+}
@@ -1,18 +1,59 @@
-use crate::tokenizer::Tokenizer;
-use crate::utils::regex::SerializeRegex;
 use crate::{rule::MatchGraph, Error};
+use crate::{tokenizer::Tokenizer, utils::regex::SerializeRegex};
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// The variants are named after the Java classes of LanguageTool.
 #[enum_dispatch]
 #[derive(Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
 pub enum Filter {
     NoDisambiguationEnglishPartialPosTagFilter,
+    InvalidDateFilter,
+    DateCheckFilter,
+    ProperNounCaseFilter,
 }
 
+impl Filter {
+    /// The name and the arguments of the filter, see [Rules::to_json_pretty][crate::Rules::to_json_pretty].
+    #[cfg(feature = "compile")]
+    pub(crate) fn inspect(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        match self {
+            Filter::NoDisambiguationEnglishPartialPosTagFilter(filter) => json!({
+                "name": "NoDisambiguationEnglishPartialPosTagFilter",
+                "index": filter.index,
+                "regexp": filter.regexp.regex_str(),
+                "postag_regexp": filter.postag_regexp.regex_str(),
+                "negate_postag": filter.negate_postag,
+            }),
+            Filter::InvalidDateFilter(filter) => json!({
+                "name": "InvalidDateFilter",
+                "year": filter.year,
+                "month": filter.month,
+                "day": filter.day,
+            }),
+            Filter::DateCheckFilter(filter) => json!({
+                "name": "DateCheckFilter",
+                "year": filter.year,
+                "month": filter.month,
+                "day": filter.day,
+                "weekday": filter.weekday,
+            }),
+            Filter::ProperNounCaseFilter(filter) => json!({
+                "name": "ProperNounCaseFilter",
+                "index": filter.index,
+            }),
+        }
+    }
+}
+
+/// Post-processes a match of a rule, the Rust counterpart of a Java filter class of LanguageTool.
 #[enum_dispatch(Filter)]
 pub trait Filterable {
+    /// Whether the match is kept.
     fn keep(&self, graph: &MatchGraph, tokenizer: &Tokenizer) -> bool;
 }
 
@@ -20,6 +61,42 @@ trait FromArgs: Sized {
     fn from_args(args: HashMap<String, String>) -> Result<Self, Error>;
 }
 
+fn get_arg<'a>(args: &'a HashMap<String, String>, key: &str) -> Result<&'a str, Error> {
+    args.get(key)
+        .map(|x| x.as_str())
+        .ok_or_else(|| Error::Unexpected(format!("filter argument `{}` is missing", key)))
+}
+
+/// Parses a reference to a token of the pattern like `\2` (or `2`) to the index of the group.
+fn get_group_arg(args: &HashMap<String, String>, key: &str) -> Result<usize, Error> {
+    let value = get_arg(args, key)?;
+
+    value.trim_start_matches('\\').parse().map_err(|_| {
+        Error::Unexpected(format!(
+            "filter argument `{}` is not a group: {}",
+            key, value
+        ))
+    })
+}
+
+/// The text of the tokens in the group with the given index, `None` if the group did not match.
+fn group_text(graph: &MatchGraph, index: usize) -> Option<String> {
+    let group = graph.by_id(index)?;
+    let tokens = group.tokens(graph.tokens());
+
+    if tokens.is_empty() {
+        None
+    } else {
+        Some(
+            tokens
+                .iter()
+                .map(|x| x.word.text.as_ref())
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct NoDisambiguationEnglishPartialPosTagFilter {
     index: usize,
@@ -32,14 +109,16 @@ pub struct NoDisambiguationEnglishPartialPosTagFilter {
 impl FromArgs for NoDisambiguationEnglishPartialPosTagFilter {
     fn from_args(args: HashMap<String, String>) -> Result<Self, Error> {
         if args.contains_key("negate_postag") {
-            panic!("negate_postag not supported in NoDisambiguationEnglishPartialPosTagFilter");
+            return Err(Error::Unimplemented(
+                "negate_postag in NoDisambiguationEnglishPartialPosTagFilter".into(),
+            ));
         }
 
         Ok(NoDisambiguationEnglishPartialPosTagFilter {
-            index: args.get("no").unwrap().parse::<usize>().unwrap(),
-            regexp: SerializeRegex::new(&args.get("regexp").unwrap(), true, true)?,
-            postag_regexp: SerializeRegex::new(&args.get("postag_regexp").unwrap(), true, true)?,
-            negate_postag: args.get("negate_postag").map_or(false, |x| x == "yes"),
+            index: get_group_arg(&args, "no")?,
+            regexp: SerializeRegex::new(get_arg(&args, "regexp")?, true, true)?,
+            postag_regexp: SerializeRegex::new(get_arg(&args, "postag_regexp")?, true, true)?,
+            negate_postag: false,
         })
     }
 }
@@ -52,10 +131,10 @@ impl Filterable for NoDisambiguationEnglishPartialPosTagFilter {
             tokens.iter().all(|token| {
                 if let Some(captures) = self.regexp.captures(&token.word.text.as_ref()) {
                     // get group 2 because `must_fully_match` adds one group
-                    let tags = tokenizer.tagger().get_tags(
+                    let tags = tokenizer.tagger().get_tags_with(
                         &captures.at(2).unwrap(),
                         tokenizer.options().always_add_lower_tags,
-                        tokenizer.options().use_compound_split_heuristic,
+                        tokenizer.options(),
                     );
 
                     tags.iter()
@@ -70,12 +149,365 @@ impl Filterable for NoDisambiguationEnglishPartialPosTagFilter {
     }
 }
 
+/// Parses an English month name (or its abbreviation) or number to the number of the month, starting at 1.
+fn parse_month(text: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    if let Ok(month) = text.parse::<u32>() {
+        return Some(month).filter(|x| (1..=12).contains(x));
+    }
+
+    position_by_prefix(&MONTHS, text).map(|x| x as u32 + 1)
+}
+
+/// The position of the name in `names` (each three letters long) which is the start of `text`, ignoring case and a
+/// trailing period.
+fn position_by_prefix(names: &[&str], text: &str) -> Option<usize> {
+    let text = text.trim_end_matches('.').to_lowercase();
+    names
+        .iter()
+        .position(|x| x.chars().eq(text.chars().take(3)))
+}
+
+/// Parses a day of the month like `3`, `03` or `3rd`.
+fn parse_day(text: &str) -> Option<u32> {
+    let text = text.to_lowercase();
+    let digits = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|x| text.strip_suffix(x))
+        .unwrap_or(&text);
+
+    digits.parse().ok()
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(month: u32, year: Option<i32>) -> u32 {
+    match month {
+        2 => match year {
+            Some(year) if !is_leap_year(year) => 28,
+            _ => 29,
+        },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The day of the week of a date in the Gregorian calendar, 0 is Sunday.
+fn weekday(year: i32, month: u32, day: u32) -> u32 {
+    const OFFSETS: [i32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let year = if month < 3 { year - 1 } else { year };
+
+    (year + year.div_euclid(4) - year.div_euclid(100)
+        + year.div_euclid(400)
+        + OFFSETS[month as usize - 1]
+        + day as i32)
+        .rem_euclid(7) as u32
+}
+
+/// Parses an English weekday name or its abbreviation, 0 is Sunday.
+fn parse_weekday(text: &str) -> Option<u32> {
+    const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+    position_by_prefix(&WEEKDAYS, text).map(|x| x as u32)
+}
+
+/// Keeps matches of dates which do not exist e. g. "February 30" or "31.4.2021".
+/// The year is optional, without it February 29 is valid.
+#[derive(Serialize, Deserialize)]
+pub struct InvalidDateFilter {
+    year: Option<usize>,
+    month: usize,
+    day: usize,
+}
+
+impl FromArgs for InvalidDateFilter {
+    fn from_args(args: HashMap<String, String>) -> Result<Self, Error> {
+        Ok(InvalidDateFilter {
+            year: if args.contains_key("year") {
+                Some(get_group_arg(&args, "year")?)
+            } else {
+                None
+            },
+            month: get_group_arg(&args, "month")?,
+            day: get_group_arg(&args, "day")?,
+        })
+    }
+}
+
+impl Filterable for InvalidDateFilter {
+    fn keep(&self, graph: &MatchGraph, _tokenizer: &Tokenizer) -> bool {
+        let month = group_text(graph, self.month).and_then(|x| parse_month(&x));
+        let day = group_text(graph, self.day).and_then(|x| parse_day(&x));
+        let year = self
+            .year
+            .and_then(|x| group_text(graph, x))
+            .and_then(|x| x.parse().ok());
+
+        match (month, day) {
+            (Some(month), Some(day)) => day == 0 || day > days_in_month(month, year),
+            // if the date can not be read it is not reported
+            _ => false,
+        }
+    }
+}
+
+/// Keeps matches of dates where the English weekday does not fit the date e. g. "Monday, 4 January 2021".
+#[derive(Serialize, Deserialize)]
+pub struct DateCheckFilter {
+    year: usize,
+    month: usize,
+    day: usize,
+    weekday: usize,
+}
+
+impl FromArgs for DateCheckFilter {
+    fn from_args(args: HashMap<String, String>) -> Result<Self, Error> {
+        Ok(DateCheckFilter {
+            year: get_group_arg(&args, "year")?,
+            month: get_group_arg(&args, "month")?,
+            day: get_group_arg(&args, "day")?,
+            weekday: get_group_arg(&args, "weekDay")?,
+        })
+    }
+}
+
+impl Filterable for DateCheckFilter {
+    fn keep(&self, graph: &MatchGraph, _tokenizer: &Tokenizer) -> bool {
+        let year = group_text(graph, self.year).and_then(|x| x.parse::<i32>().ok());
+        let month = group_text(graph, self.month).and_then(|x| parse_month(&x));
+        let day = group_text(graph, self.day).and_then(|x| parse_day(&x));
+        let expected = group_text(graph, self.weekday).and_then(|x| parse_weekday(&x));
+
+        match (year, month, day, expected) {
+            (Some(year), Some(month), Some(day), Some(expected))
+                if day >= 1 && day <= days_in_month(month, Some(year)) =>
+            {
+                weekday(year, month, day) != expected
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Keeps matches where the token is not capitalized but its capitalized form is a proper noun i. e.
+/// has a part-of-speech tag starting with `NNP`, e. g. "london".
+#[derive(Serialize, Deserialize)]
+pub struct ProperNounCaseFilter {
+    index: usize,
+}
+
+impl FromArgs for ProperNounCaseFilter {
+    fn from_args(args: HashMap<String, String>) -> Result<Self, Error> {
+        Ok(ProperNounCaseFilter {
+            index: get_group_arg(&args, "no")?,
+        })
+    }
+}
+
+impl Filterable for ProperNounCaseFilter {
+    fn keep(&self, graph: &MatchGraph, tokenizer: &Tokenizer) -> bool {
+        let text = match group_text(graph, self.index) {
+            Some(text) => text,
+            None => return false,
+        };
+
+        if !matches!(text.chars().next(), Some(c) if c.is_lowercase()) {
+            return false;
+        }
+
+        let capitalized = crate::utils::apply_to_first(&text, |x| x.to_uppercase().collect());
+        tokenizer
+            .tagger()
+            .get_tags_with(&capitalized, false, tokenizer.options())
+            .iter()
+            .any(|x| x.pos.as_ref().starts_with("NNP"))
+    }
+}
+
+/// Gets the filter for the fully qualified name of the Java class in LanguageTool. The filters read English words
+/// like the names of months, so only the classes of the English package are implemented. Classes of other packages
+/// and classes without Rust implementation are an [Error::Unimplemented].
 #[allow(dead_code)]
-pub fn get_filter(name: &str, args: HashMap<String, String>) -> Result<Filter, Error> {
-    match name {
-        "NoDisambiguationEnglishPartialPosTagFilter" => {
+pub fn get_filter(class: &str, args: HashMap<String, String>) -> Result<Filter, Error> {
+    match class {
+        "org.languagetool.rules.en.NoDisambiguationEnglishPartialPosTagFilter" => {
             Ok(NoDisambiguationEnglishPartialPosTagFilter::from_args(args)?.into())
         }
-        _ => Err(Error::Unexpected(format!("unsupported filter {}", name))),
+        "org.languagetool.rules.en.InvalidDateFilter" => {
+            Ok(InvalidDateFilter::from_args(args)?.into())
+        }
+        "org.languagetool.rules.en.DateCheckFilter" => Ok(DateCheckFilter::from_args(args)?.into()),
+        // has no counterpart in LanguageTool, so it has no package
+        "ProperNounCaseFilter" => Ok(ProperNounCaseFilter::from_args(args)?.into()),
+        _ => Err(Error::Unimplemented(format!("filter {}", class))),
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use super::{parse_month, parse_weekday};
+    use crate::{
+        compile::{
+            testing::{assert_examples_pass, build_info_for, setup, write_temp},
+            BuildInfo, RegexCache,
+        },
+        rules::{Rules, RulesOptions},
+        tokenizer::{tag::Tagger, Tokenizer, TokenizerOptions},
+    };
+
+    #[test]
+    fn filters_are_applied() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="INVALID_DATE" name="Invalid date">
+            <pattern>
+                <token regexp="yes">January|February|March|April|May|June|July|August|September|October|November|December</token>
+                <token regexp="yes">\d\d?</token>
+            </pattern>
+            <filter class="org.languagetool.rules.en.InvalidDateFilter" args="month:\1 day:\2"/>
+            <message>This date does not exist, did you mean <suggestion><match no="1"/> 28</suggestion>?</message>
+            <example correction="February 28">It was on <marker>February 30</marker>.</example>
+            <example>It was on February 28.</example>
+            <example>It was on April 30.</example>
+        </rule>
+        <rule id="DATE_WEEKDAY" name="Weekday of date">
+            <pattern>
+                <marker>
+                    <token regexp="yes">Monday|Tuesday|Wednesday|Thursday|Friday|Saturday|Sunday</token>
+                </marker>
+                <token>,</token>
+                <token regexp="yes">\d+</token>
+                <token regexp="yes">January|February|March|April|May|June|July|August|September|October|November|December</token>
+                <token regexp="yes">\d\d\d\d</token>
+            </pattern>
+            <filter class="org.languagetool.rules.en.DateCheckFilter" args="weekDay:\1 day:\3 month:\4 year:\5"/>
+            <message>The date is not a <match no="1"/>, did you mean <suggestion>Monday</suggestion>?</message>
+            <example correction="Monday">It starts on <marker>Tuesday</marker>, 4 January 2021.</example>
+            <example>It starts on Monday, 4 January 2021.</example>
+        </rule>
+        <rule id="UNKNOWN_FILTER" name="Unknown filter">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <filter class="org.languagetool.rules.UnknownFilter" args="no:1"/>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="GERMAN_DATE_FILTER" name="Filter of another language">
+            <pattern>
+                <token regexp="yes">Montag|Dienstag</token>
+                <token regexp="yes">\d+</token>
+                <token regexp="yes">Januar|Februar</token>
+                <token regexp="yes">\d\d\d\d</token>
+            </pattern>
+            <filter class="org.languagetool.rules.de.DateCheckFilter" args="weekDay:\1 day:\2 month:\3 year:\4"/>
+            <message>Did you mean <suggestion>Montag</suggestion>?</message>
+            <example>Montag 4 Januar 2021</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(ids, vec!["INVALID_DATE", "DATE_WEEKDAY"]);
+        assert_examples_pass(&rules, &tokenizer);
+
+        // unknown filter classes and the classes of other languages are unsupported instead of being ignored
+        let errors: Vec<_> = errors.iter().map(|x| (x.kind(), x.rule_id())).collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("UnsupportedFeature", Some("UNKNOWN_FILTER")),
+                ("UnsupportedFeature", Some("GERMAN_DATE_FILTER"))
+            ]
+        );
+
+        let sources = |text: &str| -> Vec<String> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .map(|x| x.source)
+                .collect()
+        };
+        // leap years
+        assert!(sources("It was on February 29, 2020.").is_empty());
+        assert_eq!(sources("It was on February 32."), vec!["INVALID_DATE"]);
+        assert!(sources("It starts on Friday, 1 January 2021.").is_empty());
+    }
+
+    #[test]
+    fn proper_noun_case_filter_is_applied() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="PROPER_NOUN_CASE" name="Proper noun case">
+            <pattern>
+                <token regexp="yes">[a-z]+</token>
+            </pattern>
+            <filter class="ProperNounCaseFilter" args="no:\1"/>
+            <message>Did you mean <suggestion><match no="1" case_conversion="startupper"/></suggestion>?</message>
+            <example correction="London">I live in <marker>london</marker>.</example>
+            <example>I live in London.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let tag_path = write_temp("filter_tags.txt", "London\tLondon\tNNP\nlive\tlive\tVBP\n");
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+        let tokenizer = Tokenizer::from_xml_reader(
+            r#"<rules lang="en"></rules>"#.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+
+        let rules =
+            Rules::from_xml_reader(grammar.as_bytes(), &mut build_info, RulesOptions::default())
+                .unwrap();
+        assert_eq!(rules.rules().len(), 1);
+        assert_examples_pass(&rules, &tokenizer);
+
+        let suggestions = rules.suggest("we live in london", &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].replacements, vec!["London"]);
+        // lowercase words which are not proper nouns are kept as they are
+        assert!(rules.suggest("we live here", &tokenizer).is_empty());
+    }
+
+    #[test]
+    fn names_are_parsed_by_characters() {
+        assert_eq!(parse_month("Sept."), Some(9));
+        assert_eq!(parse_month("ma"), None);
+        assert_eq!(parse_weekday("Wednesday"), Some(3));
+        // the third byte is in the middle of "é"
+        assert_eq!(parse_weekday("miércoles"), None);
+        assert_eq!(parse_month("März"), None);
+        assert_eq!(parse_weekday("Sá"), None);
     }
 }
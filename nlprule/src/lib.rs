@@ -11,13 +11,13 @@
 //! use nlprule::{Tokenizer, Rules};
 //!
 //! let tokenizer = Tokenizer::new("path/to/en_tokenizer.bin")?;
-//! let rules = Rules::new("path/to/en_rules.bin")?;
+//! let rules = Rules::load("path/to/en_rules.bin", &tokenizer)?;
 //!
 //! assert_eq!(
 //!     rules.correct("She was not been here since Monday.", &tokenizer),
 //!     String::from("She was not here since Monday.")
 //! );
-//! # Ok::<(), bincode::Error>(())
+//! # Ok::<(), nlprule::Error>(())
 //! ```
 //!
 //! # Example: get suggestions and correct a text
@@ -26,37 +26,58 @@
 //! use nlprule::{Tokenizer, Rules, types::Suggestion, rules::apply_suggestions};
 //!
 //! let tokenizer = Tokenizer::new("path/to/en_tokenizer.bin")?;
-//! let rules = Rules::new("path/to/en_rules.bin")?;
+//! let rules = Rules::load("path/to/en_rules.bin", &tokenizer)?;
 //!
 //! let text = "She was not been here since Monday.";
 //!
-//! let suggestions = rules.suggest(text, &tokenizer);
-//! assert_eq!(
-//!     suggestions,
-//!     vec![Suggestion {
-//!         start: 4, // these are character indices!
-//!         end: 16,
-//!         replacements: vec!["was not".into(), "has not been".into()],
-//!         source: "WAS_BEEN.1".into(),
-//!         message: "Did you mean was not or has not been?".into()
-//!     }]
-//! );
+//! let suggestions: Vec<Suggestion> = rules.suggest(text, &tokenizer);
+//! assert_eq!(suggestions.len(), 1);
+//!
+//! let suggestion = &suggestions[0];
+//! // these are character indices!
+//! assert_eq!((suggestion.start, suggestion.end), (4, 16));
+//! assert_eq!(suggestion.replacements, vec!["was not", "has not been"]);
+//! assert_eq!(suggestion.source, "WAS_BEEN.1");
+//! assert_eq!(suggestion.message, "Did you mean was not or has not been?");
 //!
 //! let corrected = apply_suggestions(text, &suggestions);
 //!
 //! assert_eq!(corrected, "She was not here since Monday.");
-//! # Ok::<(), bincode::Error>(())
+//! # Ok::<(), nlprule::Error>(())
 //! ```
 //!
 //! Binaries are distributed with [Github releases](https://github.com/bminixhofer/nlprule/releases).
+//! The tokenizer and the rules binaries of a language are compiled together, [Rules::load] checks that they match.
+//! Binaries compressed with gzip (or zstd with the `zstd` feature) are decompressed while loading, see [Compression].
+//! Binaries embedded with `include_bytes!` can be loaded with [Tokenizer::from_bytes] and [Rules::from_bytes].
+//!
+//! # Features
+//! Loading and applying the binaries only needs the default features. The `compile` feature adds the
+//! `compile` module to build the binaries from the LanguageTool XML, including the XML and JSON parsers
+//! it depends on. The `bin` feature adds the command line tools, see `BUILD.md`.
+//! The `mmap` feature adds [Rules::from_mmap] and [Tokenizer::from_mmap] which load binaries faster by
+//! memory-mapping them and compiling regexes on first use. The `fetch` feature adds the [fetch] module to download
+//! the binaries of a language and cache them. The default `gzip` and the `zstd` feature (de)compress binaries with
+//! gzip and zstd, see [Compression].
+//!
+//! Regexes are run by Oniguruma with the default `regex-onig` feature. For targets where Oniguruma, a C library,
+//! can not be built, disable the default features and enable `regex-fancy` to use the pure-Rust `regex` and
+//! `fancy-regex` crates instead. The binaries are the same for both.
 //!
 //! # The 't lifetime
 //! By convention the lifetime `'t` in this crate is the lifetime of the input text.
 //! Almost all structures with a lifetime are bound to this lifetime.
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    io::{BufReader, Read, Write},
+    ops::RangeInclusive,
+};
 use thiserror::Error;
 
 #[cfg(feature = "compile")]
 pub mod compile;
+#[cfg(feature = "fetch")]
+pub mod fetch;
 mod filter;
 pub mod rule;
 pub mod rules;
@@ -66,6 +87,7 @@ pub(crate) mod utils;
 
 pub use rules::Rules;
 pub use tokenizer::Tokenizer;
+pub use utils::parallelism::Parallelism;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -73,4 +95,822 @@ pub enum Error {
     Unexpected(String),
     #[error("feature not implemented: {0}")]
     Unimplemented(String),
+    #[error("rule ID is both in `ids` and `ignore_ids`: {0}")]
+    ConflictingIds(String),
+    #[error("part-of-speech tag is not known to the tagger: {0}")]
+    UnknownTag(String),
+    #[error("invalid user dictionary entry: {0}")]
+    InvalidEntry(String),
+    #[error("invalid option: {0}")]
+    InvalidOption(String),
+    /// The tagger can not be changed because it is shared, see [Tokenizer::mut_tagger].
+    #[error("the tagger is shared with another tokenizer")]
+    SharedTagger,
+    /// The rules were not compiled with the tagger of the tokenizer, see [Rules::load].
+    #[error("the rules ({rules}) do not belong to the tokenizer ({tokenizer})")]
+    IncompatibleBinaries { tokenizer: Header, rules: Header },
+    /// The binary can not be read by this version of nlprule, see [Rules::new] and [Tokenizer::new].
+    #[error(transparent)]
+    Binary(#[from] BinaryError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Bincode(#[from] bincode::Error),
+}
+
+/// The version of the serialized layout of the tokenizer and rules binaries. Must be increased whenever the layout
+/// changes.
+const BINARY_FORMAT_VERSION: u16 = 7;
+
+/// The format versions this version of nlprule can read.
+const SUPPORTED_BINARY_FORMAT_VERSIONS: RangeInclusive<u16> =
+    BINARY_FORMAT_VERSION..=BINARY_FORMAT_VERSION;
+
+/// The bytes every tokenizer and rules binary starts with.
+const BINARY_MAGIC: [u8; 8] = *b"NLPRULE\0";
+
+/// Binaries with a longer crate version in the header are not read.
+const MAX_CRATE_VERSION_LEN: u64 = 64;
+
+/// Why a tokenizer or rules binary can not be read by this version of nlprule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryError {
+    /// The binary does not start with the header, so it is not an nlprule binary or it was compiled by
+    /// an old version of nlprule which did not write the header.
+    MissingHeader,
+    /// The binary has a format version this version of nlprule can not read.
+    VersionMismatch {
+        /// The format version of the binary.
+        file: u16,
+        /// The format versions this version of nlprule reads.
+        supported: RangeInclusive<u16>,
+        /// The version of nlprule the binary was compiled with.
+        version: String,
+    },
+    /// The binary is (or should be) compressed with a compression this build of nlprule can not handle e. g. zstd
+    /// without the `zstd` feature, see [Compression::is_supported].
+    UnsupportedCompression(String),
+    /// Decompressing the binary failed, see [Compression].
+    Corrupted {
+        compression: Compression,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BinaryError::MissingHeader => write!(
+                f,
+                "the binary has no nlprule header, it is not an nlprule binary or was compiled by an older version \
+                 of nlprule. Please download binaries for nlprule {}",
+                env!("CARGO_PKG_VERSION")
+            ),
+            BinaryError::VersionMismatch {
+                file,
+                supported,
+                version,
+            } => {
+                write!(
+                    f,
+                    "the binary has format version {} (nlprule {}), nlprule {} reads versions {} to {}. ",
+                    file,
+                    version,
+                    env!("CARGO_PKG_VERSION"),
+                    supported.start(),
+                    supported.end()
+                )?;
+                if file < supported.start() {
+                    write!(
+                        f,
+                        "Please download binaries for nlprule {}",
+                        env!("CARGO_PKG_VERSION")
+                    )
+                } else {
+                    write!(f, "Please update nlprule to {} or later", version)
+                }
+            }
+            BinaryError::UnsupportedCompression(compression) => write!(
+                f,
+                "the binary is compressed with {}, which this build of nlprule can not decompress. \
+                 Enable the `{}` feature or decompress the binary",
+                compression, compression
+            ),
+            BinaryError::Corrupted {
+                compression,
+                message,
+            } => write!(
+                f,
+                "the {} stream of the binary is corrupted: {}",
+                compression, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BinaryError {}
+
+/// Serialized as the header at the start of the tokenizer and rules binaries: the [BINARY_MAGIC], the
+/// [BINARY_FORMAT_VERSION] and the version of nlprule. Binaries in another layout fail to load with a clear error
+/// instead of an arbitrary one, see [read_binary].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FormatVersion;
+
+impl Serialize for FormatVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (
+            BINARY_MAGIC,
+            BINARY_FORMAT_VERSION,
+            env!("CARGO_PKG_VERSION"),
+        )
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FormatVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (magic, version, crate_version) = <([u8; 8], u16, String)>::deserialize(deserializer)?;
+
+        if magic != BINARY_MAGIC {
+            Err(serde::de::Error::custom(BinaryError::MissingHeader))
+        } else if !SUPPORTED_BINARY_FORMAT_VERSIONS.contains(&version) {
+            Err(serde::de::Error::custom(BinaryError::VersionMismatch {
+                file: version,
+                supported: SUPPORTED_BINARY_FORMAT_VERSIONS,
+                version: crate_version,
+            }))
+        } else {
+            Ok(FormatVersion)
+        }
+    }
+}
+
+/// Reads the header of a binary as serialized by [FormatVersion] and returns its bytes.
+/// Fails with a [BinaryError] if the header is missing or has an unsupported version.
+fn read_header<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut header = Vec::new();
+    let mut read = |len: u64, header: &mut Vec<u8>| -> Result<(), Error> {
+        let start = header.len();
+        reader.by_ref().take(len).read_to_end(header)?;
+        if ((header.len() - start) as u64) < len {
+            return Err(BinaryError::MissingHeader.into());
+        }
+        Ok(())
+    };
+
+    read(BINARY_MAGIC.len() as u64, &mut header)?;
+    if header[..] != BINARY_MAGIC {
+        return Err(BinaryError::MissingHeader.into());
+    }
+
+    read(2, &mut header)?;
+    let version = u16::from_le_bytes([header[8], header[9]]);
+
+    read(8, &mut header)?;
+    let mut len = [0; 8];
+    len.copy_from_slice(&header[10..18]);
+    let len = u64::from_le_bytes(len);
+    if len > MAX_CRATE_VERSION_LEN {
+        return Err(BinaryError::MissingHeader.into());
+    }
+
+    read(len, &mut header)?;
+    if !SUPPORTED_BINARY_FORMAT_VERSIONS.contains(&version) {
+        return Err(BinaryError::VersionMismatch {
+            file: version,
+            supported: SUPPORTED_BINARY_FORMAT_VERSIONS,
+            version: String::from_utf8_lossy(&header[18..]).into_owned(),
+        }
+        .into());
+    }
+
+    Ok(header)
+}
+
+/// How a tokenizer or rules binary is compressed, see [Rules::to_writer_compressed] and
+/// [Tokenizer::to_writer_compressed]. The compression is detected when loading a binary.
+///
+/// All compressions can be named in every build, but (de)compressing needs the feature of the same name, otherwise
+/// it fails with [BinaryError::UnsupportedCompression].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    /// Needs the `gzip` feature, which is enabled by default.
+    Gzip,
+    /// Needs the `zstd` feature.
+    Zstd,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    /// Detects the compression from the first bytes of a binary.
+    fn detect(prefix: &[u8]) -> Self {
+        if prefix.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if prefix.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Whether this build of nlprule can (de)compress with this compression.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Returns the compression if it [is supported][Compression::is_supported].
+    pub(crate) fn supported(self) -> Result<Self, BinaryError> {
+        if self.is_supported() {
+            Ok(self)
+        } else {
+            Err(BinaryError::UnsupportedCompression(self.to_string()))
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(Error::InvalidOption(format!(
+                "unknown compression {:?}, expected `none`, `gzip` or `zstd`",
+                s
+            ))),
+        }
+    }
+}
+
+/// Wraps the errors of a decompressing reader in [BinaryError::Corrupted] so they can be told apart from
+/// errors of the underlying reader and of deserializing.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+struct Decompressed<R> {
+    inner: R,
+    compression: Compression,
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl<R: Read> Read for Decompressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).map_err(|error| {
+            std::io::Error::new(
+                error.kind(),
+                BinaryError::Corrupted {
+                    compression: self.compression,
+                    message: error.to_string(),
+                },
+            )
+        })
+    }
+}
+
+/// Turns the I/O errors of a [Decompressed] reader back into [Error::Binary].
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn unwrap_corrupted(error: Error) -> Error {
+    let io_error = match &error {
+        Error::Io(error) => Some(error),
+        Error::Bincode(error) => match error.as_ref() {
+            bincode::ErrorKind::Io(error) => Some(error),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    match io_error
+        .and_then(|x| x.get_ref())
+        .and_then(|x| x.downcast_ref::<BinaryError>())
+    {
+        Some(error) => Error::Binary(error.clone()),
+        None => error,
+    }
+}
+
+/// Deserializing from a slice is much faster than from a reader, and lengths read from garbage are checked
+/// against the length of the slice instead of being allocated.
+fn read_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    read_header(&mut &bytes[..])?;
+    Ok(bincode::deserialize(bytes)?)
+}
+
+/// The binaries are far smaller. Without a slice, lengths read from garbage can only be checked against this limit,
+/// see [read_stream].
+const MAX_BINARY_LEN: u64 = 1 << 30;
+
+/// Deserializes from a reader after checking the header like [read_slice], with the same encoding as
+/// [bincode::deserialize]. Lengths read from garbage above [MAX_BINARY_LEN] fail instead of being allocated.
+fn read_stream<T: DeserializeOwned, R: Read>(mut reader: R) -> Result<T, Error> {
+    use bincode::Options;
+
+    let header = read_header(&mut reader)?;
+    Ok(bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_BINARY_LEN)
+        .deserialize_from(header.as_slice().chain(reader))?)
+}
+
+/// Deserializes while decompressing so the decompressed binary is never in memory as a whole.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn read_decompressed<T: DeserializeOwned, R: Read>(
+    reader: R,
+    compression: Compression,
+) -> Result<T, Error> {
+    let mut reader = BufReader::new(Decompressed {
+        inner: reader,
+        compression,
+    });
+
+    let value = read_stream(&mut reader).map_err(unwrap_corrupted);
+    // the decompressor checks the checksum at the end of the stream once it is read. Garbage from a corrupted
+    // stream may already have failed deserializing, then the checksum tells why
+    let checked = std::io::copy(&mut reader, &mut std::io::sink())
+        .map_err(|error| unwrap_corrupted(error.into()));
+
+    match (value, checked) {
+        (_, Err(error @ Error::Binary(BinaryError::Corrupted { .. }))) => Err(error),
+        (value, checked) => {
+            checked?;
+            value
+        }
+    }
+}
+
+/// Deserializes a tokenizer or rules binary after checking its header, see [FormatVersion]. Compressed
+/// binaries are decompressed, see [Compression]. The binary is deserialized while it is read, so it is never
+/// in memory as a whole, see [read_stream].
+pub(crate) fn read_binary<T: DeserializeOwned, R: Read>(mut reader: R) -> Result<T, Error> {
+    let mut prefix = Vec::with_capacity(ZSTD_MAGIC.len());
+    reader
+        .by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut prefix)?;
+    let compression = Compression::detect(&prefix).supported()?;
+    let reader = prefix.as_slice().chain(reader);
+
+    match compression {
+        Compression::None => read_stream(BufReader::new(reader)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => read_decompressed(flate2::read::GzDecoder::new(reader), compression),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => read_decompressed(zstd::Decoder::new(reader)?, compression),
+        #[allow(unreachable_patterns)]
+        _ => unreachable!("the compression is supported"),
+    }
+}
+
+/// Deserializes a tokenizer or rules binary from bytes like [read_binary]. Uncompressed binaries are deserialized
+/// from the bytes directly, without copying them.
+pub(crate) fn read_binary_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let compression =
+        Compression::detect(&bytes[..bytes.len().min(ZSTD_MAGIC.len())]).supported()?;
+
+    match compression {
+        Compression::None => read_slice(bytes),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => read_decompressed(flate2::read::GzDecoder::new(bytes), compression),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => read_decompressed(zstd::Decoder::new(bytes)?, compression),
+        #[allow(unreachable_patterns)]
+        _ => unreachable!("the compression is supported"),
+    }
+}
+
+/// Deserializes a tokenizer or rules binary from a memory-mapped file, compiling its regexes on first use,
+/// see [Rules::from_mmap] and [Tokenizer::from_mmap].
+#[cfg(feature = "mmap")]
+pub(crate) fn read_binary_mmap<T: DeserializeOwned>(path: &std::path::Path) -> Result<T, Error> {
+    let file = std::fs::File::open(path)?;
+    // the constructors document that the file must not be modified while loading
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    let bytes = &mmap[..];
+
+    utils::regex::deserialize_lazily(|| read_binary_bytes(bytes))
+}
+
+/// Serializes a tokenizer or rules binary with the `compression`.
+pub(crate) fn write_binary<T: Serialize, W: Write>(
+    writer: W,
+    value: &T,
+    compression: Compression,
+) -> Result<(), Error> {
+    match compression.supported()? {
+        Compression::None => bincode::serialize_into(writer, value)?,
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+            bincode::serialize_into(&mut encoder, value)?;
+            encoder.finish()?;
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, 0)?;
+            encoder.include_checksum(true)?;
+            bincode::serialize_into(&mut encoder, value)?;
+            encoder.finish()?;
+        }
+        #[allow(unreachable_patterns)]
+        _ => unreachable!("the compression is supported"),
+    }
+
+    Ok(())
+}
+
+/// Identifies the data a tokenizer or rules binary was compiled from.
+///
+/// The rules refer to words and part-of-speech tags by their ID in the tagger, so they only work with a tokenizer
+/// with the same tagger. Using them with another tokenizer does not fail but gives wrong results.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The language code in the `lang` attribute of the XML e. g. `en`, if any.
+    pub lang_code: Option<String>,
+    /// The version of nlprule the binary was compiled with.
+    pub version: String,
+    /// A hash of the words and tags of the tagger, see [Tagger::fingerprint][tokenizer::tag::Tagger::fingerprint].
+    pub fingerprint: u64,
+}
+
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "language {}, nlprule {}, tagger {:016x}",
+            self.lang_code.as_deref().unwrap_or("unknown"),
+            self.version,
+            self.fingerprint
+        )
+    }
+}
+
+impl Header {
+    /// Whether rules with this header can be used with a tokenizer with the `tokenizer` header.
+    pub(crate) fn check(tokenizer: Option<&Header>, rules: Option<&Header>) -> Result<(), Error> {
+        match (tokenizer, rules) {
+            (Some(tokenizer), Some(rules))
+                if tokenizer.fingerprint != rules.fingerprint
+                    || matches!((&tokenizer.lang_code, &rules.lang_code), (Some(a), Some(b)) if a != b) =>
+            {
+                Err(Error::IncompatibleBinaries {
+                    tokenizer: tokenizer.clone(),
+                    rules: rules.clone(),
+                })
+            }
+            // binaries which are not compiled e. g. the default rules are not checked
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What to do if rules can not be compiled, see [RulesOptions::error_policy][rules::RulesOptions::error_policy]
+/// and [TokenizerOptions::error_policy][tokenizer::TokenizerOptions::error_policy].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Skip rules which can not be compiled without a warning.
+    Ignore,
+    /// Skip rules which can not be compiled and log the errors as warning if more than the given number of rules fail.
+    WarnAboveThreshold(usize),
+    /// Fail if any rule can not be compiled.
+    Fail,
+}
+
+/// What to do if rules in different files have the same ID, see
+/// [RulesOptions::duplicate_ids][rules::RulesOptions::duplicate_ids] and
+/// [TokenizerOptions::duplicate_ids][tokenizer::TokenizerOptions::duplicate_ids].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateIdPolicy {
+    /// The later rule can not be compiled, it is handled according to the [ErrorPolicy].
+    #[default]
+    Error,
+    /// The later rule replaces the earlier one e. g. to override rules with a user file.
+    LastWins,
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use std::io::Read;
+
+    use crate::{
+        compile::testing::{setup, write_temp},
+        rules::{Rules, RulesOptions},
+        tokenizer::{Tokenizer, TokenizerOptions},
+        BinaryError, Compression, Error, ErrorPolicy,
+    };
+
+    #[test]
+    fn binaries_can_be_loaded_from_bytes() {
+        static TOKENIZER: &[u8] = include_bytes!("compile/fixtures/tokenizer.bin");
+        static RULES: &[u8] = include_bytes!("compile/fixtures/rules.bin");
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Tokenizer>();
+        assert_send_sync::<Rules>();
+
+        let tokenizer = Tokenizer::from_bytes(TOKENIZER).unwrap();
+        let rules = Rules::from_bytes(RULES).unwrap();
+        rules.check_tokenizer(&tokenizer).unwrap();
+
+        let (expected_tokenizer, expected_rules) = setup();
+        let text = "He uses tool. I have went home.";
+        let suggestions = rules.suggest(text, &tokenizer);
+        assert!(!suggestions.is_empty());
+        assert_eq!(
+            suggestions,
+            expected_rules.suggest(text, &expected_tokenizer),
+            "the fixture binaries are outdated, run the `write_fixtures` test"
+        );
+
+        // compressed bytes and readers give the same rules
+        let mut compressed = Vec::new();
+        rules
+            .to_writer_compressed(&mut compressed, Compression::Gzip)
+            .unwrap();
+        for rules in [
+            Rules::from_bytes(&compressed).unwrap(),
+            Rules::new_from(RULES).unwrap(),
+        ] {
+            assert_eq!(rules.suggest(text, &tokenizer), suggestions);
+        }
+
+        // readers are deserialized while they are read, also if they return fewer bytes than asked for
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(self.0.len()).min(3);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        for bytes in [RULES, compressed.as_slice()] {
+            let rules = Rules::new_from(Trickle(bytes)).unwrap();
+            assert_eq!(rules.suggest(text, &tokenizer), suggestions);
+        }
+
+        // broken binaries fail with an error instead of a panic
+        assert!(matches!(
+            Tokenizer::from_bytes(&[]),
+            Err(Error::Binary(BinaryError::MissingHeader))
+        ));
+        for len in [RULES.len() / 2, RULES.len() - 1] {
+            assert!(Rules::from_bytes(&RULES[..len]).is_err());
+            assert!(Rules::new_from(&RULES[..len]).is_err());
+        }
+        let mut garbage = RULES.to_vec();
+        let header_len = 8 + 2 + 8 + env!("CARGO_PKG_VERSION").len();
+        for byte in &mut garbage[header_len..] {
+            *byte = 0xff;
+        }
+        assert!(Rules::from_bytes(&garbage).is_err());
+        assert!(Rules::new_from(garbage.as_slice()).is_err());
+    }
+
+    #[test]
+    fn binaries_have_a_versioned_header() {
+        let (tokenizer, rules) = setup();
+        let version = env!("CARGO_PKG_VERSION");
+        let header_len = 8 + 2 + 8 + version.len();
+
+        for bytes in [
+            bincode::serialize(&tokenizer).unwrap(),
+            bincode::serialize(&rules).unwrap(),
+        ] {
+            assert_eq!(&bytes[..8], b"NLPRULE\0");
+            assert_eq!(&bytes[18..header_len], version.as_bytes());
+        }
+
+        let bytes = bincode::serialize(&rules).unwrap();
+        let path = write_temp("versioned_rules.bin", "");
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            Rules::new(&path).unwrap().rules().len(),
+            rules.rules().len()
+        );
+        let tokenizer_bytes = bincode::serialize(&tokenizer).unwrap();
+        assert_eq!(
+            Tokenizer::new_from(tokenizer_bytes.as_slice())
+                .unwrap()
+                .rules()
+                .len(),
+            tokenizer.rules().len()
+        );
+
+        // a binary of a future format version from a newer nlprule
+        let mut newer = bytes[..8].to_vec();
+        newer.extend(&u16::MAX.to_le_bytes());
+        newer.extend(bincode::serialize("99.0.0").unwrap());
+        newer.extend(&bytes[header_len..]);
+        let error = match Rules::new_from(newer.as_slice()) {
+            Err(Error::Binary(error)) => error,
+            _ => panic!("binary with another format version is loaded"),
+        };
+        assert!(matches!(
+            &error,
+            BinaryError::VersionMismatch { file: u16::MAX, supported, version }
+                if supported.contains(&7) && version == "99.0.0"
+        ));
+        assert!(error
+            .to_string()
+            .ends_with("Please update nlprule to 99.0.0 or later"));
+        // deserializing directly also checks the version
+        assert!(bincode::deserialize::<Rules>(&newer).is_err());
+
+        // old binaries start with the format version
+        let mut old = 3u16.to_le_bytes().to_vec();
+        old.extend(&bytes[header_len..]);
+        let error = Tokenizer::new_from(old.as_slice()).err().unwrap();
+        assert!(matches!(error, Error::Binary(BinaryError::MissingHeader)));
+        assert!(error.to_string().contains("Please download binaries"));
+        assert!(matches!(
+            Rules::new_from(&b"NLPR"[..]),
+            Err(Error::Binary(BinaryError::MissingHeader))
+        ));
+        // nlprule 0.3 serialized the rules as a list without header
+        let mut unversioned = bincode::serialize(&Vec::<u8>::new()).unwrap();
+        unversioned.extend(&[0; 16]);
+        assert!(matches!(
+            Rules::new_from(unversioned.as_slice()),
+            Err(Error::Binary(BinaryError::MissingHeader))
+        ));
+    }
+
+    #[test]
+    fn compressed_binaries_are_detected() {
+        let (tokenizer, rules) = setup();
+        let uncompressed = bincode::serialize(&rules).unwrap();
+        let compressed = |compression| {
+            let mut bytes = Vec::new();
+            rules.to_writer_compressed(&mut bytes, compression).unwrap();
+            bytes
+        };
+
+        let compressions = [
+            (Compression::None, b"NLPR".to_vec()),
+            (Compression::Gzip, vec![0x1f, 0x8b]),
+            (Compression::Zstd, vec![0x28, 0xb5, 0x2f, 0xfd]),
+        ];
+
+        for (compression, magic) in compressions {
+            assert_eq!(
+                compression.to_string().parse::<Compression>().unwrap(),
+                compression
+            );
+
+            // the variants exist in every build, but (de)compressing needs the feature
+            if !compression.is_supported() {
+                let unsupported = |result: Result<_, Error>| {
+                    matches!(
+                        result,
+                        Err(Error::Binary(BinaryError::UnsupportedCompression(_)))
+                    )
+                };
+                assert!(unsupported(
+                    rules.to_writer_compressed(&mut Vec::new(), compression)
+                ));
+                let mut bytes = magic.clone();
+                bytes.extend(&[0; 16]);
+                assert!(unsupported(Rules::new_from(bytes.as_slice()).map(|_| ())));
+                continue;
+            }
+
+            let bytes = compressed(compression);
+            assert!(bytes.starts_with(&magic), "{}", compression);
+            let loaded = Rules::load_from(bytes.as_slice(), &tokenizer).unwrap();
+            assert_eq!(bincode::serialize(&loaded).unwrap(), uncompressed);
+
+            let mut tokenizer_bytes = Vec::new();
+            tokenizer
+                .to_writer_compressed(&mut tokenizer_bytes, compression)
+                .unwrap();
+            let loaded = Tokenizer::new_from(tokenizer_bytes.as_slice()).unwrap();
+            assert_eq!(
+                bincode::serialize(&loaded).unwrap(),
+                bincode::serialize(&tokenizer).unwrap()
+            );
+        }
+        assert!("brotli".parse::<Compression>().is_err());
+
+        // corrupted and truncated streams are detected by the decompressor, the data may be read before
+        #[cfg(feature = "gzip")]
+        {
+            let mut corrupted = compressed(Compression::Gzip);
+            let middle = corrupted.len() / 2;
+            corrupted[middle] = !corrupted[middle];
+            let truncated = compressed(Compression::Gzip)[..middle].to_vec();
+            for bytes in [corrupted, truncated] {
+                assert!(matches!(
+                    Rules::new_from(bytes.as_slice()),
+                    Err(Error::Binary(BinaryError::Corrupted {
+                        compression: Compression::Gzip,
+                        ..
+                    }))
+                ));
+            }
+        }
+
+        #[cfg(feature = "zstd")]
+        {
+            let mut corrupted = compressed(Compression::Zstd);
+            let middle = corrupted.len() / 2;
+            corrupted[middle] = !corrupted[middle];
+            let error = Rules::new_from(corrupted.as_slice()).err().unwrap();
+            assert!(
+                matches!(
+                    error,
+                    Error::Binary(BinaryError::Corrupted {
+                        compression: Compression::Zstd,
+                        ..
+                    })
+                ),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn memory_mapped_binaries_give_the_same_suggestions() {
+        use std::fs::File;
+
+        let (tokenizer, rules) = setup();
+        let texts: Vec<_> = rules
+            .rules()
+            .iter()
+            .flat_map(|x| x.examples())
+            .map(|x| x.text().to_string())
+            .collect();
+        let suggestions = |rules: &Rules, tokenizer: &Tokenizer| -> Vec<_> {
+            texts
+                .iter()
+                .map(|text| rules.suggest(text, tokenizer))
+                .collect()
+        };
+        let expected = suggestions(&rules, &tokenizer);
+        assert!(expected.iter().any(|x| !x.is_empty()));
+
+        for compression in [Compression::None, Compression::Gzip]
+            .iter()
+            .copied()
+            .filter(Compression::is_supported)
+        {
+            let tokenizer_path = write_temp("mmap_tokenizer.bin", "");
+            let rules_path = write_temp("mmap_rules.bin", "");
+            tokenizer
+                .to_writer_compressed(File::create(&tokenizer_path).unwrap(), compression)
+                .unwrap();
+            rules
+                .to_writer_compressed(File::create(&rules_path).unwrap(), compression)
+                .unwrap();
+
+            let mapped_tokenizer = Tokenizer::from_mmap(&tokenizer_path).unwrap();
+            let mapped_rules = Rules::from_mmap(&rules_path).unwrap();
+            mapped_rules.check_tokenizer(&mapped_tokenizer).unwrap();
+            assert_eq!(suggestions(&mapped_rules, &mapped_tokenizer), expected);
+        }
+
+        let path = write_temp("mmap_invalid.bin", "not a binary");
+        assert!(matches!(
+            Rules::from_mmap(&path),
+            Err(Error::Binary(BinaryError::MissingHeader))
+        ));
+    }
+
+    #[test]
+    fn options_can_be_partially_specified() {
+        let options: TokenizerOptions =
+            serde_json::from_str(r#"{ "ids": ["A"], "split_hyphens": "heuristic" }"#).unwrap();
+        assert_eq!(options.ids, vec!["A"]);
+        assert!(options.immunize_opaque_tokens);
+        assert_eq!(options.error_policy, ErrorPolicy::Fail);
+
+        let options: RulesOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options.error_policy, ErrorPolicy::WarnAboveThreshold(0));
+
+        let options: RulesOptions =
+            serde_json::from_str(r#"{ "error_policy": { "warn_above_threshold": 10 } }"#).unwrap();
+        assert_eq!(options.error_policy, ErrorPolicy::WarnAboveThreshold(10));
+    }
 }
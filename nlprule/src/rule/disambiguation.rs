@@ -1,4 +1,4 @@
-use crate::types::*;
+use crate::{types::*, utils::bitset::BitSet};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
@@ -22,18 +22,35 @@ impl POSFilter {
         data.tags.retain(|x| !self.is_word_data_match(x))
     }
 
-    fn and(filters: &[&Self], data: &Word) -> bool {
-        data.tags
-            .iter()
-            .any(|x| filters.iter().all(|filter| filter.is_word_data_match(x)))
+    /// The tags which match all of the filters, `None` if there are no filters.
+    fn intersection(filters: &[&Self]) -> Option<BitSet> {
+        BitSet::intersection(filters.iter().map(|x| &x.matcher.mask))
     }
 
-    fn apply(filters: &[Vec<&Self>], data: &mut Word) {
-        data.tags.retain(|x| {
-            filters
+    pub(crate) fn and(filters: &[&Self], data: &Word) -> bool {
+        match POSFilter::intersection(filters) {
+            Some(mask) => data
+                .tags
                 .iter()
-                .any(|filter| filter.iter().all(|f| f.is_word_data_match(x)))
-        })
+                .any(|x| mask.contains(*x.pos.id() as usize)),
+            None => !data.tags.is_empty(),
+        }
+    }
+
+    fn apply(filters: &[Vec<&Self>], data: &mut Word) {
+        let mut mask: Option<BitSet> = None;
+
+        for filter in filters {
+            match (POSFilter::intersection(filter), &mut mask) {
+                // no filters match every tag
+                (None, _) => return,
+                (Some(other), Some(mask)) => mask.union_with(&other),
+                (Some(other), None) => mask = Some(other),
+            }
+        }
+
+        data.tags
+            .retain(|x| matches!(&mask, Some(mask) if mask.contains(*x.pos.id() as usize)));
     }
 }
 
@@ -44,6 +61,8 @@ pub enum Disambiguation {
     Replace(Vec<owned::WordData>),
     Filter(Vec<Option<either::Either<owned::WordData, POSFilter>>>),
     Unify(Vec<Vec<POSFilter>>, Vec<Option<POSFilter>>, Vec<bool>),
+    Immunize,
+    IgnoreSpelling,
     Nop,
 }
 
@@ -190,6 +209,20 @@ impl Disambiguation {
                     }
                 }
             }
+            Disambiguation::Immunize => {
+                for group in groups.into_iter() {
+                    for token in group.into_iter() {
+                        token.immunized = true;
+                    }
+                }
+            }
+            Disambiguation::IgnoreSpelling => {
+                for group in groups.into_iter() {
+                    for token in group.into_iter() {
+                        token.ignore_spelling = true;
+                    }
+                }
+            }
             Disambiguation::Nop => {}
         }
     }
@@ -208,3 +241,105 @@ pub enum DisambiguationExample {
     Unchanged(String),
     Changed(DisambiguationChange),
 }
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use crate::{
+        compile::{testing::write_temp, BuildInfo, RegexCache},
+        tokenizer::{finalize, tag::Tagger, Tokenizer, TokenizerOptions},
+    };
+
+    #[test]
+    fn disambiguation_actions_are_applied() {
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <unification feature="number">
+        <equivalence type="singular">
+            <token postag=".*:SG" postag_regexp="yes" />
+        </equivalence>
+        <equivalence type="plural">
+            <token postag=".*:PL" postag_regexp="yes" />
+        </equivalence>
+    </unification>
+    <rule id="NOUN_AFTER_THE" name="Noun after the">
+        <pattern>
+            <token>the</token>
+            <marker>
+                <token postag="NN.*" postag_regexp="yes" />
+            </marker>
+        </pattern>
+        <disambig action="filterall" />
+        <example type="ambiguous" inputform="run[run/NN:SG,run/VB]" outputform="run[run/NN:SG]">I like the <marker>run</marker>.</example>
+        <example type="untouched">I run.</example>
+    </rule>
+    <rule id="VS_AS_VERSUS" name="vs as versus">
+        <pattern>
+            <marker>
+                <token>vs</token>
+            </marker>
+        </pattern>
+        <disambig action="replace"><wd lemma="versus" pos="IN" /></disambig>
+        <example type="ambiguous" inputform="vs[vs/NN:SG]" outputform="vs[versus/IN]">cats <marker>vs</marker> dogs</example>
+    </rule>
+    <rule id="DETERMINER_NUMBER" name="Number of determiner and noun">
+        <pattern>
+            <marker>
+                <unify>
+                    <feature id="number" />
+                    <token postag="DT:.*" postag_regexp="yes" />
+                    <token postag="(NN|VB).*" postag_regexp="yes" />
+                </unify>
+            </marker>
+        </pattern>
+        <disambig action="unify" />
+        <example type="ambiguous" inputform="runs[run/NNS:PL,run/VBZ:SG]" outputform="runs[run/NNS:PL]">I saw these <marker>runs</marker>.</example>
+        <example type="untouched">He runs.</example>
+    </rule>
+    <rule id="DE_FACTO" name="de facto">
+        <pattern>
+            <token>de</token>
+            <token>facto</token>
+        </pattern>
+        <disambig action="ignore_spelling" />
+    </rule>
+</rules>
+"#;
+        let tag_path = write_temp(
+            "disambiguation_tags.txt",
+            "the\tthe\tDT\nrun\trun\tNN:SG\nrun\trun\tVB\nruns\trun\tNNS:PL\nruns\trun\tVBZ:SG\n\
+             these\tthese\tDT:PL\nvs\tvs\tNN:SG\nversus\tversus\tIN\n",
+        );
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let tokenizer = Tokenizer::from_xml_reader(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(tokenizer.rules().len(), 4);
+        for rule in tokenizer.rules() {
+            assert!(rule.test(&tokenizer), "{} fails its examples", rule.id());
+        }
+
+        let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize("It is de facto run.")));
+        let ignored: Vec<_> = tokens
+            .iter()
+            .filter(|x| x.is_spelling_ignored())
+            .map(|x| x.word.text.as_ref())
+            .collect();
+        assert_eq!(ignored, vec!["de", "facto"]);
+    }
+}
@@ -1,15 +1,41 @@
-use crate::{types::*, utils::regex::SerializeRegex};
-use enum_dispatch::enum_dispatch;
-use lazy_static::lazy_static;
+use crate::{
+    rule::{disambiguation::POSFilter, grammar::CaseMapping},
+    types::*,
+    utils::{
+        bitset::BitSet,
+        regex::{Regex, RegexSet, SerializeRegex},
+    },
+};
 use serde::{Deserialize, Serialize};
-use unicase::UniCase;
+use std::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use {
+    enum_dispatch::enum_dispatch, itertools::Itertools, lazy_static::lazy_static,
+    once_cell::sync::OnceCell,
+};
+
+/// The default maximum number of steps of matching a composition at one position, see
+/// [Rules::set_match_budget][crate::Rules::set_match_budget]. A step is trying to match one token, so this is far
+/// more than any rule needs on natural text but bounds patterns which backtrack exponentially.
+pub const DEFAULT_MATCH_BUDGET: usize = 10_000;
+
+fn default_match_budget() -> usize {
+    DEFAULT_MATCH_BUDGET
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Matcher {
     pub matcher: either::Either<either::Either<String, usize>, SerializeRegex>,
     pub negate: bool,
     pub case_sensitive: bool,
+    /// How the case of strings is compared if they are not case-sensitive.
+    pub case_mapping: CaseMapping,
     pub empty_always_false: bool,
+    /// The lowercase forms of the string, if this matches a string.
+    #[serde(skip)]
+    pub(crate) lower: LowerText,
 }
 
 impl Matcher {
@@ -25,33 +51,92 @@ impl Matcher {
     }
 
     pub fn is_match(&self, input: &str, graph: &MatchGraph, case_sensitive: Option<bool>) -> bool {
+        self.is_match_inner(input, None, graph, case_sensitive, false)
+    }
+
+    /// Like [Matcher::is_match] for the text of a token. Compares the cached lowercase forms of the token
+    /// if the matcher is not case-sensitive.
+    pub fn is_token_match(
+        &self,
+        token: &Token,
+        graph: &MatchGraph,
+        case_sensitive: Option<bool>,
+    ) -> bool {
+        self.is_match_inner(
+            token.word.text.as_ref(),
+            Some(&token.lower),
+            graph,
+            case_sensitive,
+            false,
+        )
+    }
+
+    /// Like [Matcher::is_match] for a lemma of a token. A reference to another token matches
+    /// the lemmas of that token instead of its text.
+    pub fn is_lemma_match(
+        &self,
+        input: &str,
+        graph: &MatchGraph,
+        case_sensitive: Option<bool>,
+    ) -> bool {
+        self.is_match_inner(input, None, graph, case_sensitive, true)
+    }
+
+    /// `input_lower` are the lowercase forms of the input if they are cached.
+    fn is_match_inner(
+        &self,
+        input: &str,
+        input_lower: Option<&LowerText>,
+        graph: &MatchGraph,
+        case_sensitive: Option<bool>,
+        lemma: bool,
+    ) -> bool {
+        let empty_result = if self.empty_always_false {
+            false
+        } else {
+            self.negate
+        };
+
         if input.is_empty() {
-            return if self.empty_always_false {
-                false
-            } else {
-                self.negate
-            };
+            return empty_result;
         }
         let case_sensitive = case_sensitive.unwrap_or(self.case_sensitive);
+        let mapping = self.case_mapping;
+        let equals = |string: &str, string_lower: Option<&LowerText>| {
+            if case_sensitive {
+                string == input
+            } else if let (Some(a), Some(b)) = (string_lower, input_lower) {
+                CaseMapping::eq_ignore_case_lower(a.get(string, mapping), b.get(input, mapping))
+            } else {
+                mapping.eq_ignore_case(string, input)
+            }
+        };
 
         let matches = match &self.matcher {
             either::Left(string_or_idx) => match string_or_idx {
-                either::Left(string) => {
-                    if case_sensitive {
-                        string.as_str() == input
+                either::Left(string) => equals(string, Some(&self.lower)),
+                either::Right(idx) => {
+                    // a group without tokens i. e. of an optional part which matched no tokens or of a later
+                    // part which is not matched yet is the empty string. Like for an empty input,
+                    // `empty_always_false` decides the result
+                    let token = match graph
+                        .by_id(*idx)
+                        .and_then(|x| x.tokens(graph.tokens()).first().copied())
+                    {
+                        Some(token) => token,
+                        None => return empty_result,
+                    };
+
+                    if lemma && !token.word.tags.is_empty() {
+                        token
+                            .word
+                            .tags
+                            .iter()
+                            .any(|x| equals(x.lemma.as_ref(), None))
                     } else {
-                        UniCase::new(string) == UniCase::new(input)
+                        equals(token.word.text.as_ref(), Some(&token.lower))
                     }
                 }
-                either::Right(idx) => graph.by_id(*idx).map_or(false, |x| {
-                    x.tokens(&graph.tokens).get(0).map_or(false, |token| {
-                        if case_sensitive {
-                            token.word.text.as_ref() == input
-                        } else {
-                            UniCase::new(token.word.text.as_ref()) == UniCase::new(input)
-                        }
-                    })
-                }),
             },
             either::Right(regex) => regex.is_match(input),
         };
@@ -64,13 +149,41 @@ impl Matcher {
     }
 }
 
+/// A short description for [Rule::explain][crate::rule::Rule::explain] e. g. `"the"`, `/a|b/` or `\1`.
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negate {
+            write!(f, "not ")?;
+        }
+
+        match &self.matcher {
+            either::Left(either::Left(string)) => write!(f, "{:?}", string),
+            either::Left(either::Right(idx)) => write!(f, "\\{}", idx),
+            either::Right(regex) => write!(f, "/{}/", regex.regex_str()),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TextMatcher {
     pub matcher: Matcher,
     pub set: Option<DefaultHashSet<u32>>,
+    /// How often the regex was matched against a text because the words it matches are not stored in a set,
+    /// see [MatchStats::regex_fallbacks][crate::rules::MatchStats::regex_fallbacks]. `None` unless counting is
+    /// enabled so matching does not write to a counter shared by all threads.
+    #[serde(skip)]
+    pub(crate) fallbacks: Option<AtomicUsize>,
 }
 
 impl TextMatcher {
+    /// Counts matching the regex of the matcher if it has no set, see [TextMatcher::fallbacks].
+    #[inline]
+    fn count_fallback(&self) {
+        if let (Some(fallbacks), true) = (&self.fallbacks, self.matcher.matcher.is_right()) {
+            fallbacks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     pub fn is_match(
         &self,
         word_id: &WordId,
@@ -78,6 +191,7 @@ impl TextMatcher {
         case_sensitive: Option<bool>,
     ) -> bool {
         if self.set.is_none() {
+            self.count_fallback();
             return self
                 .matcher
                 .is_match(word_id.as_ref(), graph, case_sensitive);
@@ -90,16 +204,51 @@ impl TextMatcher {
                 .is_match(word_id.as_ref(), graph, case_sensitive)
         }
     }
+
+    /// Like [TextMatcher::is_match] for the text of a token, see [Matcher::is_token_match].
+    pub fn is_token_match(
+        &self,
+        token: &Token,
+        graph: &MatchGraph,
+        case_sensitive: Option<bool>,
+    ) -> bool {
+        match (&self.set, token.word.text.id()) {
+            (Some(set), Some(id)) => set.contains(id),
+            (set, _) => {
+                if set.is_none() {
+                    self.count_fallback();
+                }
+                self.matcher.is_token_match(token, graph, case_sensitive)
+            }
+        }
+    }
+
+    /// Like [TextMatcher::is_match] for a lemma, see [Matcher::is_lemma_match].
+    pub fn is_lemma_match(
+        &self,
+        lemma: &WordId,
+        graph: &MatchGraph,
+        case_sensitive: Option<bool>,
+    ) -> bool {
+        if self.set.is_none() {
+            self.count_fallback();
+            self.matcher
+                .is_lemma_match(lemma.as_ref(), graph, case_sensitive)
+        } else {
+            self.is_match(lemma, graph, case_sensitive)
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PosMatcher {
-    pub mask: Vec<bool>,
+    /// The IDs of the tags which match.
+    pub mask: BitSet,
 }
 
 impl PosMatcher {
     pub fn is_match(&self, pos: &PosId) -> bool {
-        self.mask[*pos.id() as usize]
+        self.mask.contains(*pos.id() as usize)
     }
 }
 
@@ -116,11 +265,32 @@ impl WordDataMatcher {
         graph: &MatchGraph,
         case_sensitive: Option<bool>,
     ) -> bool {
+        // a negated lemma matches if no reading has both the lemma and a matching part-of-speech tag, like in
+        // LanguageTool. So readings with the lemma and another tag do not matter e. g. a negated lemma "see"
+        // with tag NN matches "saw" with the readings (see, VBD) and (saw, NN). With a tag there must also be
+        // a reading with a matching tag.
+        if let Some(inflect_matcher) = self.inflect_matcher.as_ref().filter(|m| m.matcher.negate) {
+            let mut pos_matches = self.pos_matcher.is_none();
+
+            for x in input {
+                let reading_pos_matches = match &self.pos_matcher {
+                    Some(m) => m.is_match(&x.pos),
+                    None => true,
+                };
+
+                if reading_pos_matches {
+                    if !inflect_matcher.is_lemma_match(&x.lemma, graph, case_sensitive) {
+                        return false;
+                    }
+                    pos_matches = true;
+                }
+            }
+
+            return pos_matches;
+        }
+
         input.iter().any(|x| {
-            let pos_matches = self
-                .pos_matcher
-                .as_ref()
-                .map_or(true, |m| m.is_match(&x.pos));
+            let pos_matches = self.pos_matcher.as_ref().is_none_or(|m| m.is_match(&x.pos));
 
             // matching part-of-speech tag is faster than inflection, so check POS first and early exit if it doesn't match
             if !pos_matches {
@@ -130,22 +300,144 @@ impl WordDataMatcher {
             let inflect_matches = self
                 .inflect_matcher
                 .as_ref()
-                .map_or(true, |m| m.is_match(&x.lemma, graph, case_sensitive));
+                .is_none_or(|m| m.is_lemma_match(&x.lemma, graph, case_sensitive));
 
             inflect_matches
         })
     }
 }
 
+/// How many tokens a part with a [Quantifier] takes if the composition can match with different counts.
+/// Whether the composition matches at all only depends on the greediness for [Greediness::Possessive]
+/// and [Greediness::UntilNext], which never give back tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Greediness {
+    /// As many tokens as possible, fewer if the rest of the composition does not match otherwise.
+    Greedy,
+    /// As few tokens as possible, more if the rest of the composition does not match otherwise.
+    /// The last part of a composition takes as many tokens as possible. This is the default.
+    Lazy,
+    /// As many tokens as possible. The rest of the composition has to match after them.
+    Possessive,
+    /// Tokens up to the first token the following parts can match i. e. a `skip` of LanguageTool.
+    /// The rest of the composition has to match from there.
+    UntilNext,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Quantifier {
     pub min: usize,
     pub max: usize,
+    pub greediness: Greediness,
+}
+
+lazy_static! {
+    static ref UPPERCASE_LETTERS: Vec<(char, char)> = unicode_class(r"\p{Lu}");
+    static ref LOWERCASE_LETTERS: Vec<(char, char)> = unicode_class(r"\p{Ll}");
+}
+
+/// The ranges of the characters in a Unicode class.
+fn unicode_class(class: &str) -> Vec<(char, char)> {
+    let hir = regex_syntax::Parser::new()
+        .parse(class)
+        .expect("Unicode classes are valid");
+
+    match hir.kind() {
+        regex_syntax::hir::HirKind::Class(regex_syntax::hir::Class::Unicode(class)) => class
+            .ranges()
+            .iter()
+            .map(|x| (x.start(), x.end()))
+            .collect(),
+        _ => unreachable!("Unicode classes are parsed as classes"),
+    }
+}
+
+fn in_class(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|(start, end)| {
+            if *end < c {
+                std::cmp::Ordering::Less
+            } else if *start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Whether the character is an uppercase letter i. e. in the Unicode category `Lu` like `\p{Lu}`. Unlike
+/// [char::is_uppercase] this excludes e. g. `Ⅰ`.
+fn is_upper(c: char) -> bool {
+    if c.is_ascii() {
+        c.is_ascii_uppercase()
+    } else {
+        in_class(&UPPERCASE_LETTERS, c)
+    }
+}
+
+/// Whether the character is a lowercase letter i. e. in the Unicode category `Ll` like `\p{Ll}`. Unlike
+/// [char::is_lowercase] this excludes e. g. `º`.
+fn is_lower(c: char) -> bool {
+    if c.is_ascii() {
+        c.is_ascii_lowercase()
+    } else {
+        in_class(&LOWERCASE_LETTERS, c)
+    }
+}
+
+/// The case of the text of a token, see [concrete::CaseAtom]. Upper- and lowercase characters are the letters
+/// in the Unicode categories `Lu` and `Ll`, so e. g. the titlecase letter `ǅ` is neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenCase {
+    /// Starts with an uppercase character e. g. "There", "CAN" or "McDonald", like the regex `\p{Lu}.*`.
+    StartsUpper,
+    /// Starts with a lowercase character e. g. "there" or "iPhone", like the regex `\p{Ll}.*`.
+    StartsLower,
+    /// An uppercase character followed by lowercase characters e. g. "There", like the regex `\p{Lu}\p{Ll}+`.
+    Title,
+    /// Only uppercase characters e. g. "CAN" or "I", like the regex `\p{Lu}+`.
+    Upper,
+    /// Only lowercase characters e. g. "there", like the regex `\p{Ll}+`.
+    Lower,
+    /// Only upper- and lowercase characters in any other combination e. g. "iPhone" or "McDonald".
+    Mixed,
+}
+
+impl TokenCase {
+    pub fn is_match(self, text: &str) -> bool {
+        let mut chars = text.chars();
+        let first = match chars.next() {
+            Some(first) => first,
+            None => return false,
+        };
+
+        match self {
+            TokenCase::StartsUpper => is_upper(first),
+            TokenCase::StartsLower => is_lower(first),
+            TokenCase::Title => {
+                is_upper(first) && !chars.as_str().is_empty() && chars.all(is_lower)
+            }
+            TokenCase::Upper => is_upper(first) && chars.all(is_upper),
+            TokenCase::Lower => is_lower(first) && chars.all(is_lower),
+            TokenCase::Mixed => {
+                text.chars().all(|c| is_upper(c) || is_lower(c))
+                    && !TokenCase::Title.is_match(text)
+                    && !TokenCase::Upper.is_match(text)
+                    && !TokenCase::Lower.is_match(text)
+            }
+        }
+    }
 }
 
 #[enum_dispatch]
 pub trait Atomable: Send + Sync {
     fn is_match(&self, input: &[&Token], graph: &MatchGraph, position: usize) -> bool;
+
+    /// Whether this atom needs the chunks predicted by the [Chunker][crate::tokenizer::chunk::Chunker] to match.
+    fn uses_chunker(&self) -> bool {
+        false
+    }
 }
 
 #[enum_dispatch(Atomable)]
@@ -161,12 +453,148 @@ pub enum Atom {
     OrAtom,
     NotAtom,
     OffsetAtom,
+    CaseAtom(concrete::CaseAtom),
+}
+
+/// A short description for [Rule::explain][crate::rule::Rule::explain]. Part-of-speech tags are matched by ID,
+/// so they are not printable.
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, atoms: &[Atom], separator: &str| {
+            write!(f, "(")?;
+            for (i, atom) in atoms.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", separator)?;
+                }
+                write!(f, "{}", atom)?;
+            }
+            write!(f, ")")
+        };
+
+        match self {
+            Atom::ChunkAtom(atom) => write!(f, "chunk {}", atom.matcher),
+            Atom::SpaceBeforeAtom(atom) if atom.value => write!(f, "space before"),
+            Atom::SpaceBeforeAtom(_) => write!(f, "no space before"),
+            Atom::TextAtom(atom) => write!(f, "text {}", atom.matcher.matcher),
+            Atom::CaseAtom(atom) => write!(
+                f,
+                "{}case {:?}",
+                if atom.negate { "not " } else { "" },
+                atom.case
+            ),
+            Atom::WordDataAtom(atom) => {
+                let matcher = &atom.matcher;
+                write!(f, "reading")?;
+                if matcher.pos_matcher.is_some() {
+                    write!(f, " with postag")?;
+                }
+                if let Some(inflect_matcher) = &matcher.inflect_matcher {
+                    write!(f, " with lemma {}", inflect_matcher.matcher)?;
+                }
+                Ok(())
+            }
+            Atom::TrueAtom(_) => write!(f, "true"),
+            Atom::FalseAtom(_) => write!(f, "false"),
+            Atom::AndAtom(atom) => join(f, &atom.atoms, "and"),
+            Atom::OrAtom(atom) => join(f, &atom.atoms, "or"),
+            Atom::NotAtom(atom) => write!(f, "not {}", atom.atom),
+            Atom::OffsetAtom(atom) => write!(f, "{} at offset {}", atom.atom, atom.offset),
+        }
+    }
+}
+
+impl Atom {
+    /// The atom which is responsible for this atom not matching the token at `position`: the first atom of a
+    /// conjunction which does not match, otherwise this atom.
+    pub(crate) fn rejecting(&self, input: &[&Token], graph: &MatchGraph, position: usize) -> &Atom {
+        match self {
+            Atom::AndAtom(atom) => atom
+                .atoms
+                .iter()
+                .find(|x| !x.is_match(input, graph, position))
+                .map_or(self, |x| x.rejecting(input, graph, position)),
+            _ => self,
+        }
+    }
+
+    /// Sets whether the regexes of the alternatives of [OrAtom]s in this atom are matched at once.
+    pub(crate) fn set_batch_regexes(&mut self, batch: bool) {
+        match self {
+            Atom::AndAtom(atom) => atom
+                .atoms
+                .iter_mut()
+                .for_each(|x| x.set_batch_regexes(batch)),
+            Atom::OrAtom(atom) => {
+                atom.set_batch_regexes(batch);
+                atom.atoms
+                    .iter_mut()
+                    .for_each(|x| x.set_batch_regexes(batch));
+            }
+            Atom::NotAtom(atom) => atom.atom.set_batch_regexes(batch),
+            Atom::OffsetAtom(atom) => atom.atom.set_batch_regexes(batch),
+            _ => {}
+        }
+    }
+
+    /// Calls `f` with the fallback counters of the text matchers and regex sets in this atom.
+    pub(crate) fn visit_fallbacks(&self, f: &mut impl FnMut(&Option<AtomicUsize>)) {
+        match self {
+            Atom::TextAtom(atom) => f(&atom.matcher.fallbacks),
+            Atom::WordDataAtom(atom) => {
+                if let Some(matcher) = &atom.matcher.inflect_matcher {
+                    f(&matcher.fallbacks);
+                }
+            }
+            Atom::AndAtom(atom) => atom.atoms.iter().for_each(|x| x.visit_fallbacks(f)),
+            Atom::OrAtom(atom) => {
+                f(&atom.fallbacks);
+                atom.atoms.iter().for_each(|x| x.visit_fallbacks(f));
+            }
+            Atom::NotAtom(atom) => atom.atom.visit_fallbacks(f),
+            Atom::OffsetAtom(atom) => atom.atom.visit_fallbacks(f),
+            _ => {}
+        }
+    }
+
+    /// Like [Atom::visit_fallbacks], but allows enabling or disabling the counters.
+    pub(crate) fn visit_fallbacks_mut(&mut self, f: &mut impl FnMut(&mut Option<AtomicUsize>)) {
+        match self {
+            Atom::TextAtom(atom) => f(&mut atom.matcher.fallbacks),
+            Atom::WordDataAtom(atom) => {
+                if let Some(matcher) = &mut atom.matcher.inflect_matcher {
+                    f(&mut matcher.fallbacks);
+                }
+            }
+            Atom::AndAtom(atom) => atom.atoms.iter_mut().for_each(|x| x.visit_fallbacks_mut(f)),
+            Atom::OrAtom(atom) => {
+                f(&mut atom.fallbacks);
+                atom.atoms.iter_mut().for_each(|x| x.visit_fallbacks_mut(f));
+            }
+            Atom::NotAtom(atom) => atom.atom.visit_fallbacks_mut(f),
+            Atom::OffsetAtom(atom) => atom.atom.visit_fallbacks_mut(f),
+            _ => {}
+        }
+    }
 }
 
 pub mod concrete {
-    use super::{Atomable, MatchGraph, Matcher, TextMatcher, Token, WordDataMatcher};
+    use super::{Atomable, MatchGraph, Matcher, TextMatcher, Token, TokenCase, WordDataMatcher};
     use serde::{Deserialize, Serialize};
 
+    /// Matches tokens with a case. Much cheaper than the regexes LanguageTool uses for this, which are
+    /// replaced by this atom when compiling.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CaseAtom {
+        pub(crate) case: TokenCase,
+        pub(crate) negate: bool,
+    }
+
+    impl Atomable for CaseAtom {
+        fn is_match(&self, input: &[&Token], _graph: &MatchGraph, position: usize) -> bool {
+            self.case.is_match(input[position].word.text.as_ref()) != self.negate
+        }
+    }
+
     #[derive(Debug, Serialize, Deserialize)]
     pub struct TextAtom {
         pub(crate) matcher: TextMatcher,
@@ -174,8 +602,7 @@ pub mod concrete {
 
     impl Atomable for TextAtom {
         fn is_match(&self, input: &[&Token], graph: &MatchGraph, position: usize) -> bool {
-            self.matcher
-                .is_match(&input[position].word.text, graph, None)
+            self.matcher.is_token_match(input[position], graph, None)
         }
     }
 
@@ -186,8 +613,19 @@ pub mod concrete {
 
     impl Atomable for ChunkAtom {
         fn is_match(&self, input: &[&Token], graph: &MatchGraph, position: usize) -> bool {
-            self.matcher
-                .is_slice_match(&input[position].chunks, graph, None)
+            let chunks = &input[position].chunks;
+
+            // a token can have multiple chunks, a match on any of them counts.
+            // so a negated matcher only matches if none of the chunks match
+            if self.matcher.negate {
+                chunks.iter().all(|x| self.matcher.is_match(x, graph, None))
+            } else {
+                self.matcher.is_slice_match(chunks, graph, None)
+            }
+        }
+
+        fn uses_chunker(&self) -> bool {
+            true
         }
     }
 
@@ -247,18 +685,114 @@ impl Atomable for AndAtom {
             .iter()
             .all(|x| x.is_match(input, graph, position))
     }
+
+    fn uses_chunker(&self) -> bool {
+        self.atoms.iter().any(|x| x.uses_chunker())
+    }
+}
+
+/// The regexes of alternatives of an [OrAtom] which are matched at once.
+#[derive(Debug)]
+pub(crate) struct RegexBatch {
+    set: RegexSet,
+    /// Whether the alternative at each index is matched by the set.
+    batched: Vec<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OrAtom {
     pub(crate) atoms: Vec<Atom>,
+    /// The regexes of the alternatives in a set, built on first use so the serialized format only has the
+    /// individual regexes. `None` if they are not batched.
+    #[serde(skip)]
+    pub(crate) batch: OnceCell<Option<RegexBatch>>,
+    /// How often the set of regexes was matched, see [TextMatcher::fallbacks].
+    #[serde(skip)]
+    pub(crate) fallbacks: Option<AtomicUsize>,
+}
+
+impl OrAtom {
+    /// The regex of an alternative which can be part of a [RegexSet]: a text regex which is not negated and
+    /// which is always run i. e. has no precomputed set of matching words.
+    fn batchable_regex(atom: &Atom) -> Option<&Regex> {
+        match atom {
+            Atom::TextAtom(atom) => match &atom.matcher.matcher.matcher {
+                either::Right(regex)
+                    if atom.matcher.set.is_none()
+                        && !atom.matcher.matcher.negate
+                        && regex.can_batch() =>
+                {
+                    Some(regex)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn batch(&self) -> Option<&RegexBatch> {
+        self.batch
+            .get_or_init(|| {
+                let regexes: Vec<_> = self
+                    .atoms
+                    .iter()
+                    .filter_map(Self::batchable_regex)
+                    .collect();
+
+                // a set only pays off for multiple regexes
+                if regexes.len() < 2 {
+                    return None;
+                }
+
+                RegexSet::new(&regexes).map(|set| RegexBatch {
+                    set,
+                    batched: self
+                        .atoms
+                        .iter()
+                        .map(|x| Self::batchable_regex(x).is_some())
+                        .collect(),
+                })
+            })
+            .as_ref()
+    }
+
+    /// Sets whether the regexes of the alternatives are matched at once if the backend supports it.
+    pub(crate) fn set_batch_regexes(&mut self, batch: bool) {
+        self.batch = if batch {
+            OnceCell::new()
+        } else {
+            OnceCell::with_value(None)
+        };
+    }
 }
 
 impl Atomable for OrAtom {
     fn is_match(&self, input: &[&Token], graph: &MatchGraph, position: usize) -> bool {
-        self.atoms
-            .iter()
-            .any(|x| x.is_match(input, graph, position))
+        match self.batch() {
+            Some(batch) => {
+                let text = input[position].word.text.as_ref();
+
+                self.atoms
+                    .iter()
+                    .zip(&batch.batched)
+                    .any(|(x, batched)| !batched && x.is_match(input, graph, position))
+                    // like the matchers, regexes never match an empty text
+                    || (!text.is_empty() && {
+                        if let Some(fallbacks) = &self.fallbacks {
+                            fallbacks.fetch_add(1, Ordering::Relaxed);
+                        }
+                        batch.set.is_match(text)
+                    })
+            }
+            None => self
+                .atoms
+                .iter()
+                .any(|x| x.is_match(input, graph, position)),
+        }
+    }
+
+    fn uses_chunker(&self) -> bool {
+        self.atoms.iter().any(|x| x.uses_chunker())
     }
 }
 
@@ -271,12 +805,22 @@ impl Atomable for NotAtom {
     fn is_match(&self, input: &[&Token], graph: &MatchGraph, position: usize) -> bool {
         !self.atom.is_match(input, graph, position)
     }
+
+    fn uses_chunker(&self) -> bool {
+        self.atom.uses_chunker()
+    }
 }
 
+/// Matches the atom on the token `offset` positions away e. g. for exceptions with `previous` or `next` scope.
+/// The SENT_START token is part of the sentence, so the token before the first word is SENT_START and
+/// only the token before SENT_START and the token after the last token are out of bounds.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OffsetAtom {
     pub(crate) atom: Box<Atom>,
     pub(crate) offset: isize,
+    /// Whether this matches if the token is out of bounds. False for exceptions like in LanguageTool,
+    /// where an exception pointing outside of the sentence is not triggered.
+    pub(crate) out_of_bounds: bool,
 }
 
 impl Atomable for OffsetAtom {
@@ -284,11 +828,15 @@ impl Atomable for OffsetAtom {
         let new_position = position as isize + self.offset;
 
         if new_position < 0 || (new_position as usize) >= input.len() {
-            false
+            self.out_of_bounds
         } else {
             self.atom.is_match(input, graph, new_position as usize)
         }
     }
+
+    fn uses_chunker(&self) -> bool {
+        self.atom.uses_chunker()
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -383,6 +931,52 @@ impl<'t> MatchGraph<'t> {
         &self.tokens[..]
     }
 
+    /// The groups with their IDs and the text they cover, ordered by ID.
+    pub fn matched_groups(&self) -> Vec<MatchedGroup> {
+        let text = self.tokens.first().map_or("", |x| x.text);
+
+        self.id_to_idx
+            .iter()
+            .sorted_by_key(|(id, _)| **id)
+            .map(|(id, index)| {
+                let group = &self.groups[*index];
+                // empty groups between tokens span from the start of the next token to the end of the previous one
+                let (start, end) = group.char_span;
+                MatchedGroup {
+                    id: *id,
+                    char_span: (start, end.max(start)),
+                    text: group.text(text).to_string(),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the tokens covered by this graph are in more than one paragraph.
+    pub fn crosses_paragraph(&self) -> bool {
+        let spans = self
+            .groups
+            .iter()
+            .map(|x| x.char_span)
+            .filter(|(start, end)| start < end);
+        let start = spans.clone().map(|x| x.0).min().unwrap_or(0);
+        let end = spans.map(|x| x.1).max().unwrap_or(0);
+
+        let mut paragraphs = self
+            .tokens
+            .iter()
+            .filter(|x| {
+                x.char_span.1 > x.char_span.0 // special tokens have zero range
+                    && x.char_span.0 >= start
+                    && x.char_span.1 <= end
+            })
+            .map(|x| x.paragraph);
+
+        match paragraphs.next() {
+            Some(first) => paragraphs.any(|x| x != first),
+            None => false,
+        }
+    }
+
     pub fn fill_empty(&mut self) {
         let mut start = self
             .groups
@@ -440,18 +1034,127 @@ impl<'t> MatchGraph<'t> {
 pub struct Part {
     pub atom: Atom,
     pub quantifier: Quantifier,
-    pub greedy: bool,
     pub visible: bool,
 }
 
+/// Requires the tokens of some parts to agree in features like gender, number and case i. e. LanguageTool's `<unify>`.
+#[derive(Serialize, Deserialize)]
+pub struct Unification {
+    /// For each feature, a filter for every value it can take e. g. singular and plural for number.
+    pub(crate) features: Vec<Vec<POSFilter>>,
+    /// The indices of the unified parts. Parts in `<unify-ignore>` are not included.
+    pub(crate) parts: Vec<usize>,
+    pub(crate) negate: bool,
+}
+
+impl Unification {
+    /// Whether the tokens agree i. e. there is one value of each feature such that every unified token
+    /// has a reading with all of these values. Inverted if negated.
+    fn is_match(&self, graph: &MatchGraph) -> bool {
+        let tokens: Vec<_> = self
+            .parts
+            .iter()
+            .flat_map(|i| graph.by_index(i + 1).tokens(graph.tokens()))
+            .collect();
+
+        let unified = self
+            .features
+            .iter()
+            .multi_cartesian_product()
+            .any(|filters| {
+                tokens
+                    .iter()
+                    .all(|token| POSFilter::and(&filters, &token.word))
+            });
+
+        unified != self.negate
+    }
+}
+
+/// Observes why matching a composition fails, see [Rule::explain][crate::rule::Rule::explain]. Normal matching
+/// uses `()` which records nothing, so it does not pay for tracing.
+pub(crate) trait Tracer {
+    /// The atom of the part at `index` rejected the token at `position`, or there are no tokens left if
+    /// `position` is the number of tokens. Not every rejection is a failure e. g. if the part is optional.
+    fn reject(
+        &mut self,
+        _index: usize,
+        _position: usize,
+        _atom: &Atom,
+        _input: &[&Token],
+        _graph: &MatchGraph,
+    ) {
+    }
+
+    /// Matching exceeded the match budget.
+    fn exceed_budget(&mut self) {}
+
+    /// The parts matched, but the tokens do not agree in the features of a unification.
+    fn fail_unification(&mut self) {}
+}
+
+impl Tracer for () {}
+
+/// Matching a [Composition] took more steps than its match budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BudgetExceeded;
+
 #[derive(Serialize, Deserialize)]
 pub struct Composition {
     pub(crate) parts: Vec<Part>,
     pub(crate) group_ids_to_idx: DefaultHashMap<usize, usize>,
     pub(crate) can_stop_mask: Vec<bool>,
+    pub(crate) unifications: Vec<Unification>,
+    /// The maximum number of steps of matching at one position.
+    #[serde(skip, default = "default_match_budget")]
+    pub(crate) budget: usize,
+    /// How often matching at a position was stopped because it exceeded the budget.
+    #[serde(skip)]
+    pub(crate) overflows: AtomicUsize,
 }
 
 impl Composition {
+    /// Counts a step of matching. `false` if it exceeds the budget, then all further steps fail too.
+    #[inline]
+    fn step(&self, steps: &mut usize) -> bool {
+        *steps += 1;
+        *steps <= self.budget
+    }
+
+    /// How often matching this composition at a position exceeded the budget.
+    pub(crate) fn overflows(&self) -> usize {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset_overflows(&self) {
+        self.overflows.store(0, Ordering::Relaxed);
+    }
+
+    /// Sets whether the regexes of alternatives are matched at once, see [OrAtom].
+    pub(crate) fn set_batch_regexes(&mut self, batch: bool) {
+        for part in &mut self.parts {
+            part.atom.set_batch_regexes(batch);
+        }
+    }
+
+    /// Calls `f` with the fallback counters of the atoms of this composition, see [TextMatcher::fallbacks].
+    pub(crate) fn visit_fallbacks(&self, f: &mut impl FnMut(&Option<AtomicUsize>)) {
+        for part in &self.parts {
+            part.atom.visit_fallbacks(f);
+        }
+    }
+
+    pub(crate) fn visit_fallbacks_mut(&mut self, f: &mut impl FnMut(&mut Option<AtomicUsize>)) {
+        for part in &mut self.parts {
+            part.atom.visit_fallbacks_mut(f);
+        }
+    }
+
+    /// Whether any part of this composition needs chunk information to match.
+    pub fn uses_chunker(&self) -> bool {
+        self.parts.iter().any(|x| x.atom.uses_chunker())
+    }
+
     fn next_can_match<'t>(
         &self,
         tokens: &'t [&'t Token<'t>],
@@ -472,71 +1175,180 @@ impl Composition {
             .any(|x| x.atom.is_match(tokens, graph, position))
     }
 
-    fn apply_recursive<'t>(
+    /// Adds the token at `position` to the group of the part at `index`.
+    fn extend_group(graph: &mut MatchGraph, tokens: &[&Token], index: usize, position: usize) {
+        let group = &mut graph.groups[index + 1];
+
+        // set the group beginning if the char end was zero (i. e. the group was empty)
+        if group.char_span.1 == 0 {
+            group.char_span.0 = tokens[position].char_span.0;
+        }
+        group.char_span.1 = tokens[position].char_span.1;
+    }
+
+    /// Matches the parts from `index` on, starting at the token at `position`.
+    /// The alternatives are tried in a fixed order so the resulting graph is deterministic.
+    fn apply_recursive<'t, T: Tracer>(
         &'t self,
         tokens: &'t [&'t Token<'t>],
         mut position: usize,
-        mut cur_atom_idx: usize,
+        mut index: usize,
         mut graph: MatchGraph<'t>,
+        steps: &mut usize,
+        tracer: &mut T,
     ) -> Option<MatchGraph<'t>> {
-        let mut cur_count = 0;
-
-        let is_match = loop {
-            if cur_atom_idx >= self.parts.len() {
-                break true;
+        // parts with a fixed number of tokens have no alternatives so they are matched without recursing
+        while let Some(part) = self.parts.get(index) {
+            let Quantifier { min, max, .. } = part.quantifier;
+            if min != max {
+                break;
             }
 
-            let part = &self.parts[cur_atom_idx];
-
-            if cur_count >= part.quantifier.max {
-                cur_atom_idx += 1;
-                cur_count = 0;
-                if cur_atom_idx >= self.parts.len() {
-                    break false;
+            for _ in 0..min {
+                if position >= tokens.len()
+                    || !self.step(steps)
+                    || !part.atom.is_match(tokens, &graph, position)
+                {
+                    tracer.reject(index, position, &part.atom, tokens, &graph);
+                    return None;
                 }
-                continue;
+                Self::extend_group(&mut graph, tokens, index, position);
+                position += 1;
             }
+            index += 1;
+        }
 
-            if position >= tokens.len() {
-                break false;
+        let part = match self.parts.get(index) {
+            Some(part) => part,
+            None => {
+                graph.fill_empty();
+                return Some(graph);
             }
+        };
+        let Quantifier {
+            min,
+            max,
+            mut greediness,
+        } = part.quantifier;
+        // nothing follows the last part which could take the tokens instead
+        if greediness == Greediness::Lazy && index + 1 == self.parts.len() {
+            greediness = Greediness::Possessive;
+        }
 
-            if cur_count >= part.quantifier.min && cur_atom_idx + 1 < self.parts.len() {
-                if !part.greedy && self.next_can_match(tokens, &graph, position, cur_atom_idx) {
-                    cur_atom_idx += 1;
-                    cur_count = 0;
-                    continue;
+        match greediness {
+            Greediness::Greedy | Greediness::Possessive => {
+                // the graphs after taking each number of tokens
+                let mut graphs = vec![graph];
+                while graphs.len() <= max
+                    && position + graphs.len() - 1 < tokens.len()
+                    && self.step(steps)
+                    && part.atom.is_match(
+                        tokens,
+                        &graphs[graphs.len() - 1],
+                        position + graphs.len() - 1,
+                    )
+                {
+                    let mut next = graphs[graphs.len() - 1].clone();
+                    Self::extend_group(&mut next, tokens, index, position + graphs.len() - 1);
+                    graphs.push(next);
                 }
-                if part.greedy {
-                    if let Some(graph) =
-                        self.apply_recursive(tokens, position, cur_atom_idx + 1, graph.clone())
-                    {
+
+                let taken = graphs.len() - 1;
+                if taken < max {
+                    tracer.reject(index, position + taken, &part.atom, tokens, &graphs[taken]);
+                }
+                if taken < min {
+                    return None;
+                }
+                let least = if greediness == Greediness::Possessive {
+                    taken
+                } else {
+                    min
+                };
+
+                for count in (least..=taken).rev() {
+                    let graph = graphs.swap_remove(count);
+                    if let Some(graph) = self.apply_recursive(
+                        tokens,
+                        position + count,
+                        index + 1,
+                        graph,
+                        steps,
+                        tracer,
+                    ) {
                         return Some(graph);
                     }
                 }
+                None
             }
+            Greediness::Lazy => {
+                let mut count = 0;
+                loop {
+                    if count >= min {
+                        if let Some(graph) = self.apply_recursive(
+                            tokens,
+                            position,
+                            index + 1,
+                            graph.clone(),
+                            steps,
+                            tracer,
+                        ) {
+                            return Some(graph);
+                        }
+                    }
 
-            if part.atom.is_match(tokens, &graph, position) {
-                let mut group = &mut graph.groups[cur_atom_idx + 1];
-
-                // set the group beginning if the char end was zero (i. e. the group was empty)
-                if group.char_span.1 == 0 {
-                    group.char_span.0 = tokens[position].char_span.0;
+                    if count >= max {
+                        return None;
+                    }
+                    if position >= tokens.len()
+                        || !self.step(steps)
+                        || !part.atom.is_match(tokens, &graph, position)
+                    {
+                        tracer.reject(index, position, &part.atom, tokens, &graph);
+                        return None;
+                    }
+                    Self::extend_group(&mut graph, tokens, index, position);
+                    position += 1;
+                    count += 1;
                 }
-                group.char_span.1 = tokens[position].char_span.1;
-
-                position += 1;
-                cur_count += 1;
-            } else {
-                break false;
             }
-        };
+            Greediness::UntilNext => {
+                let mut count = 0;
+                loop {
+                    let at_next = count >= min
+                        && index + 1 < self.parts.len()
+                        && position < tokens.len()
+                        && self.step(steps)
+                        && self.next_can_match(tokens, &graph, position, index);
+
+                    if count >= max || at_next {
+                        return self.apply_recursive(
+                            tokens,
+                            position,
+                            index + 1,
+                            graph,
+                            steps,
+                            tracer,
+                        );
+                    }
 
-        if is_match || cur_atom_idx == self.parts.len() || self.can_stop_mask[cur_atom_idx] {
-            graph.fill_empty();
-            Some(graph)
-        } else {
-            None
+                    if position >= tokens.len()
+                        || !self.step(steps)
+                        || !part.atom.is_match(tokens, &graph, position)
+                    {
+                        tracer.reject(index, position, &part.atom, tokens, &graph);
+                        // the following parts can not match here, so this only matches if they are optional
+                        return if count >= min {
+                            self.apply_recursive(tokens, position, index + 1, graph, steps, tracer)
+                        } else {
+                            None
+                        };
+                    }
+                    Self::extend_group(&mut graph, tokens, index, position);
+                    position += 1;
+                    count += 1;
+                }
+            }
         }
     }
 
@@ -545,6 +1357,37 @@ impl Composition {
         tokens: &'t [&'t Token<'t>],
         start: usize,
     ) -> Option<MatchGraph<'t>> {
+        self.apply_traced(tokens, start, &mut ())
+    }
+
+    /// Like [Composition::apply], but `Err` if the match budget is exceeded. Then it is unknown whether the
+    /// composition matches, which matters for antipatterns.
+    pub(crate) fn try_apply<'t>(
+        &'t self,
+        tokens: &'t [&'t Token<'t>],
+        start: usize,
+    ) -> Result<Option<MatchGraph<'t>>, BudgetExceeded> {
+        self.try_apply_traced(tokens, start, &mut ())
+    }
+
+    /// Like [Composition::apply], reporting why matching fails to the `tracer`.
+    #[inline]
+    pub(crate) fn apply_traced<'t, T: Tracer>(
+        &'t self,
+        tokens: &'t [&'t Token<'t>],
+        start: usize,
+        tracer: &mut T,
+    ) -> Option<MatchGraph<'t>> {
+        self.try_apply_traced(tokens, start, tracer).unwrap_or(None)
+    }
+
+    #[inline]
+    fn try_apply_traced<'t, T: Tracer>(
+        &'t self,
+        tokens: &'t [&'t Token<'t>],
+        start: usize,
+        tracer: &mut T,
+    ) -> Result<Option<MatchGraph<'t>>, BudgetExceeded> {
         // this path is extremely hot so more optimizations are done
 
         // the first matcher can never rely on the match graph, so we use an empty default graph for the first match
@@ -556,7 +1399,8 @@ impl Composition {
         if self.parts[0].quantifier.min > 0
             && !self.parts[0].atom.is_match(tokens, &DEFAULT_GRAPH, start)
         {
-            return None;
+            tracer.reject(0, start, &self.parts[0].atom, tokens, &DEFAULT_GRAPH);
+            return Ok(None);
         }
 
         let position = start;
@@ -567,6 +1411,977 @@ impl Composition {
             tokens,
         );
 
-        self.apply_recursive(tokens, position, 0, graph)
+        let mut steps = 0;
+        let graph = self.apply_recursive(tokens, position, 0, graph, &mut steps, tracer);
+        if steps > self.budget {
+            // the match may be incomplete if the budget ran out, so it is discarded
+            self.overflows.fetch_add(1, Ordering::Relaxed);
+            tracer.exceed_budget();
+            return Err(BudgetExceeded);
+        }
+        let graph = match graph {
+            Some(graph) => graph,
+            None => return Ok(None),
+        };
+
+        if self.unifications.iter().all(|x| x.is_match(&graph)) {
+            Ok(Some(graph))
+        } else {
+            tracer.fail_unification();
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use crate::{
+        compile::{
+            testing::{
+                assert_examples_pass, build_info_for, compile_rules, compile_rules_with_errors,
+                setup, write_temp,
+            },
+            BuildInfo, RegexCache,
+        },
+        rule::{
+            engine::composition::{Atomable, FalseAtom, Matcher, OffsetAtom, DEFAULT_MATCH_BUDGET},
+            grammar::CaseMapping,
+            MatchGraph,
+        },
+        rules::{MatchStats, Rules, RulesOptions},
+        tokenizer::{finalize, tag::Tagger, Tokenizer, TokenizerOptions},
+        utils::regex::{turkic_i, SerializeRegex},
+    };
+
+    #[test]
+    fn unified_tokens_must_agree() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="de">
+    <unification feature="gender">
+        <equivalence type="mas">
+            <token postag="(ART|ADJ|SUB):.*:MAS" postag_regexp="yes" />
+        </equivalence>
+        <equivalence type="fem">
+            <token postag="(ART|ADJ|SUB):.*:FEM" postag_regexp="yes" />
+        </equivalence>
+        <equivalence type="neu">
+            <token postag="(ART|ADJ|SUB):.*:NEU" postag_regexp="yes" />
+        </equivalence>
+    </unification>
+    <unification feature="number">
+        <equivalence type="sin">
+            <token postag="(ART|ADJ|SUB):.*:SIN:.*" postag_regexp="yes" />
+        </equivalence>
+        <equivalence type="plu">
+            <token postag="(ART|ADJ|SUB):.*:PLU:.*" postag_regexp="yes" />
+        </equivalence>
+    </unification>
+    <category id="GRAMMAR" name="Grammatik">
+        <rule id="DE_AGREEMENT_ART_SUB" name="Kongruenz von Artikel und Substantiv">
+            <pattern>
+                <marker>
+                    <unify negate="yes">
+                        <feature id="gender" />
+                        <feature id="number" />
+                        <token postag="ART:.*" postag_regexp="yes" />
+                        <token postag="SUB:.*" postag_regexp="yes" />
+                    </unify>
+                </marker>
+            </pattern>
+            <message>Artikel und Substantiv stimmen nicht überein.</message>
+            <suggestion>der <match no="2"/></suggestion>
+            <example correction="der Hund">Ich sehe <marker>das Hund</marker>.</example>
+            <example>Der Hund bellt.</example>
+            <example>Die Hunde bellen.</example>
+        </rule>
+        <rule id="DE_AGREEMENT_ART_ADJ_SUB" name="Kongruenz von Artikel, Adjektiv und Substantiv">
+            <pattern>
+                <unify negate="yes">
+                    <feature id="gender" />
+                    <feature id="number" />
+                    <token postag="ART:.*" postag_regexp="yes" />
+                    <unify-ignore>
+                        <token postag="ADJ:.*" postag_regexp="yes" />
+                    </unify-ignore>
+                    <token postag="SUB:.*" postag_regexp="yes" />
+                </unify>
+            </pattern>
+            <message>Artikel und Substantiv stimmen nicht überein.</message>
+            <suggestion>der <match no="2"/> <match no="3"/></suggestion>
+            <example correction="der alte Hund">Ich sehe <marker>die alte Hund</marker>.</example>
+            <example>Der alte Hund bellt.</example>
+            <example>Die alte Frau lacht.</example>
+        </rule>
+        <rule id="DE_DOPPELTER_ARTIKEL" name="Doppelter Artikel">
+            <pattern>
+                <marker>
+                    <unify>
+                        <feature id="gender" />
+                        <feature id="number" />
+                        <token postag="ART:.*" postag_regexp="yes" />
+                        <token postag="ART:.*" postag_regexp="yes" />
+                    </unify>
+                </marker>
+                <token postag="SUB:.*" postag_regexp="yes" />
+            </pattern>
+            <message>Der Artikel ist doppelt.</message>
+            <suggestion><match no="1"/></suggestion>
+            <example correction="Der"><marker>Der der</marker> Hund bellt.</example>
+            <example>Ich sehe der die Frau.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="de">
+</rules>
+"#;
+        let tag_path = write_temp(
+            "de_tags.txt",
+            "der\tder\tART:DEF:NOM:SIN:MAS\ndie\tder\tART:DEF:NOM:SIN:FEM\n\
+             die\tder\tART:DEF:NOM:PLU:MAS\ndas\tder\tART:DEF:NOM:SIN:NEU\n\
+             Hund\tHund\tSUB:NOM:SIN:MAS\nHunde\tHund\tSUB:NOM:PLU:MAS\n\
+             Frau\tFrau\tSUB:NOM:SIN:FEM\nalte\talt\tADJ:NOM:SIN:FEM\n\
+             alte\talt\tADJ:NOM:SIN:MAS\n",
+        );
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let tokenizer = Tokenizer::from_xml_reader(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(rules.rules().len(), 3);
+        assert_examples_pass(&rules, &tokenizer);
+    }
+
+    #[test]
+    fn tokens_can_be_skipped() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="NEITHER_OR" name="neither ... or (nor)">
+            <pattern>
+                <token skip="-1">neither<exception scope="next">nor</exception></token>
+                <marker>
+                    <token>or</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>nor</suggestion>?</message>
+            <example correction="nor">Neither the tool <marker>or</marker> the guide helps.</example>
+            <example>Neither the tool nor the guide helps, or so it seems.</example>
+        </rule>
+        <rule id="EITHER_NOR" name="either ... nor (or)">
+            <pattern>
+                <token skip="3">either</token>
+                <marker>
+                    <token>nor</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>or</suggestion>?</message>
+            <example correction="or">Either you <marker>nor</marker> me.</example>
+            <example>Either way, it is not here nor there.</example>
+        </rule>
+        <rule id="BETWEEN_OR" name="between ... or (and)">
+            <pattern>
+                <marker>
+                    <token skip="3">between</token>
+                    <token>or</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" include_skipped="all"/> and</suggestion>?</message>
+            <example correction="between tea and">Choose <marker>between tea or</marker> coffee.</example>
+            <example>It is between the tool and the guide, or so it seems.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let rules = compile_rules(grammar, &tokenizer);
+        assert_examples_pass(&rules, &tokenizer);
+
+        let suggestions = rules.suggest("Choose between the new tool or the guide.", &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(
+            suggestions[0].replacements,
+            vec!["between the new tool and"]
+        );
+    }
+
+    #[test]
+    fn parallel_tokens_support_exceptions() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="GO_NOT_AFTER_HERE" name="Go not after here">
+            <pattern>
+                <and>
+                    <token postag="VB.*" postag_regexp="yes">
+                        <exception scope="previous">here</exception>
+                    </token>
+                    <token inflected="yes">go<exception scope="next" postag="DT"/></token>
+                </and>
+            </pattern>
+            <message>Did you mean <suggestion>left</suggestion>?</message>
+            <example correction="left">We <marker>went</marker> home.</example>
+            <example>We are here went home.</example>
+            <example>We went the way.</example>
+        </rule>
+        <rule id="TOOL_OR_GO" name="Tool or go">
+            <pattern>
+                <token>the</token>
+                <or>
+                    <token>tool</token>
+                    <token postag="VB.*" postag_regexp="yes">
+                        <exception postag="VBN" inflected="yes">go</exception>
+                    </token>
+                </or>
+            </pattern>
+            <message>Did you mean <suggestion>a <match no="2"/></suggestion>?</message>
+            <example correction="a tool">We have <marker>the tool</marker>.</example>
+            <example correction="a went">We have <marker>the went</marker>.</example>
+            <example>We have the gone.</example>
+        </rule>
+        <rule id="SKIP_TO_TOOL" name="Skip to tool">
+            <pattern>
+                <or>
+                    <token skip="-1">here</token>
+                    <token>Next</token>
+                </or>
+                <marker>
+                    <token>tool</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>tools</suggestion>?</message>
+            <example correction="tools">We are here with the <marker>tool</marker>.</example>
+            <example>The tool is here.</example>
+        </rule>
+        <rule id="BAD_SCOPE" name="Bad scope">
+            <pattern>
+                <token>here<exception scope="after">the</exception></token>
+            </pattern>
+            <message>Bad scope.</message>
+        </rule>
+    </category>
+</rules>
+"#;
+
+        let (tokenizer, _) = setup();
+        let (rules, _) = compile_rules_with_errors(grammar, &tokenizer);
+
+        // an unknown scope is an error of the rule instead of a panic
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(ids, vec!["GO_NOT_AFTER_HERE", "TOOL_OR_GO", "SKIP_TO_TOOL"]);
+        assert_examples_pass(&rules, &tokenizer);
+    }
+
+    #[test]
+    fn inflected_tokens_match_lemmas() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="INFLECTED_REGEX" name="Inflected regex">
+            <pattern>
+                <token>here</token>
+                <token inflected="yes" regexp="yes">go|run</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">We are <marker>here went</marker>.</example>
+            <example correction="there">It is <marker>here runs</marker>.</example>
+            <example>We are here tool.</example>
+        </rule>
+        <rule id="INFLECTED_STRING" name="Inflected string">
+            <pattern>
+                <token>the</token>
+                <token inflected="yes">GO</token>
+            </pattern>
+            <message>Did you mean <suggestion>a go</suggestion>?</message>
+            <example correction="a go">It is <marker>the gone</marker>.</example>
+            <example>It is the tool.</example>
+        </rule>
+        <rule id="INFLECTED_NEGATE" name="Inflected negate">
+            <pattern>
+                <token>here</token>
+                <token inflected="yes" negate="yes" regexp="yes">go|tool</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">It is <marker>here runs</marker>.</example>
+            <example>We are here went.</example>
+            <example>We are here tool.</example>
+        </rule>
+        <rule id="INFLECTED_EXCEPTION" name="Inflected exception">
+            <pattern>
+                <token postag="VB.*" postag_regexp="yes">
+                    <exception inflected="yes" regexp="yes">go|be</exception>
+                </token>
+            </pattern>
+            <message>Did you mean <suggestion>left</suggestion>?</message>
+            <example correction="left">It <marker>runs</marker>.</example>
+            <example>It went.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+
+        let (mut tokenizer, _) = setup();
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let ids: Vec<_> = rules.rules().iter().map(|x| x.id()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "INFLECTED_REGEX",
+                "INFLECTED_STRING",
+                "INFLECTED_NEGATE",
+                "INFLECTED_EXCEPTION"
+            ]
+        );
+        assert_examples_pass(&rules, &tokenizer);
+
+        // a negated lemma only matches if none of the readings have a matching lemma
+        let negate_matches = |tokenizer: &Tokenizer| {
+            rules
+                .suggest("It is here runs.", tokenizer)
+                .iter()
+                .any(|x| x.source == "INFLECTED_NEGATE")
+        };
+        assert!(negate_matches(&tokenizer));
+        tokenizer
+            .mut_tagger()
+            .unwrap()
+            .add_word("runs", "go", "VBZ")
+            .unwrap();
+        assert!(!negate_matches(&tokenizer));
+    }
+
+    #[test]
+    fn quantifiers_take_tokens_according_to_their_greediness() {
+        let (tokenizer, _) = setup();
+
+        // the span of the marked second part of the first match, if any
+        let marked = |pattern: &str, text: &str| {
+            let grammar = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="GREEDINESS" name="Greediness">
+            <pattern>{}</pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#,
+                pattern
+            );
+            let rules = compile_rules(&grammar, &tokenizer);
+
+            rules
+                .suggest(text, &tokenizer)
+                .first()
+                .map(|x| (x.start, x.end))
+        };
+
+        // "here the the tool" and "here the the went" have the tokens at 0..4, 5..8, 9..12 and 13..17
+        let overlapping = |greediness: &str| {
+            format!(
+                r#"<token regexp="yes" min="1" max="3"{}>here|the</token>
+                <marker><token regexp="yes" min="1" max="2">the|tool</token></marker>"#,
+                greediness
+            )
+        };
+        let backing_off = |greediness: &str| {
+            format!(
+                r#"<token regexp="yes" min="1" max="3"{}>here|the</token>
+                <marker><token>the</token></marker>"#,
+                greediness
+            )
+        };
+
+        let cases = [
+            ("", Some((5, 12)), Some((5, 8))),
+            (r#" greediness="greedy""#, Some((13, 17)), Some((9, 12))),
+            (r#" greediness="lazy""#, Some((5, 12)), Some((5, 8))),
+            (r#" greediness="possessive""#, Some((13, 17)), None),
+        ];
+        for (greediness, expected_overlapping, expected_backing_off) in cases.iter() {
+            assert_eq!(
+                marked(&overlapping(greediness), "here the the tool"),
+                *expected_overlapping,
+                "{}",
+                greediness
+            );
+            assert_eq!(
+                marked(&backing_off(greediness), "here the the went"),
+                *expected_backing_off,
+                "{}",
+                greediness
+            );
+        }
+
+        // a skip ends at the first token the next part matches
+        assert_eq!(
+            marked(
+                r#"<token skip="-1">here</token><marker><token>the</token></marker>"#,
+                "here the the went"
+            ),
+            Some((5, 8))
+        );
+    }
+
+    #[test]
+    fn backreferences_match_earlier_tokens() {
+        let (tokenizer, _) = setup();
+
+        // the span of the marked part of the first match, if any, and the compile errors
+        let marked = |pattern: &str, text: &str| {
+            let grammar = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="REPETITION" name="Repetition">
+            <pattern>{}</pattern>
+            <message>Possible typo: you repeated a word.</message>
+            <suggestion><match no="1"/></suggestion>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#,
+                pattern
+            );
+            let (rules, errors) = compile_rules_with_errors(&grammar, &tokenizer);
+            let errors: Vec<_> = errors.iter().map(|x| x.to_string()).collect();
+
+            let span = rules
+                .suggest(text, &tokenizer)
+                .first()
+                .map(|x| (x.start, x.end));
+            (span, errors)
+        };
+        let matched = |pattern: &str, text: &str| {
+            let (span, errors) = marked(pattern, text);
+            assert!(errors.is_empty(), "{:?}", errors);
+            span
+        };
+
+        let repetition = r#"<token/><marker><token><match no="0"/></token></marker>"#;
+        assert_eq!(matched(repetition, "here the the tool"), Some((9, 12)));
+        assert_eq!(matched(repetition, "Here here"), Some((5, 9)));
+        assert_eq!(matched(repetition, "here the tool"), None);
+        assert_eq!(
+            matched(
+                r#"<token/><marker><token case_sensitive="yes"><match no="0"/></token></marker>"#,
+                "Here here"
+            ),
+            None
+        );
+
+        // the referenced group is the one of the current match in every quantifier configuration,
+        // i. e. the first token of the quantified part
+        let quantified = |attribs: &str| {
+            format!(
+                r#"<token regexp="yes" min="1" max="3"{}>here|the</token>
+                <marker><token><match no="0"/></token></marker>"#,
+                attribs
+            )
+        };
+        let cases = [
+            ("", Some((9, 13))),
+            (r#" greediness="lazy""#, Some((9, 13))),
+            (r#" greediness="possessive""#, None),
+        ];
+        for (attribs, expected) in cases.iter() {
+            assert_eq!(
+                matched(&quantified(attribs), "here the here"),
+                *expected,
+                "{}",
+                attribs
+            );
+        }
+        assert_eq!(
+            matched(
+                r#"<token skip="-1"/><marker><token><match no="0"/></token></marker>"#,
+                "the tool the"
+            ),
+            Some((9, 12))
+        );
+
+        // an inflected reference matches tokens sharing a lemma with the referenced token
+        let lemma = r#"<token/><marker><token inflected="yes"><match no="0"/></token></marker>"#;
+        assert_eq!(matched(lemma, "went gone"), Some((5, 9)));
+        assert_eq!(matched(lemma, "went go"), Some((5, 7)));
+        assert_eq!(matched(lemma, "the went"), None);
+        assert_eq!(matched(repetition, "went gone"), None);
+
+        for pattern in [
+            r#"<token><match no="0"/></token><token/>"#,
+            r#"<token/><token><match no="2"/></token><token/>"#,
+        ]
+        .iter()
+        {
+            let (_, errors) = marked(pattern, "here");
+            assert_eq!(errors.len(), 1, "{}", pattern);
+            assert!(errors[0].contains("must refer to an earlier token"));
+        }
+    }
+
+    #[test]
+    fn case_insensitive_matching_folds_unicode() {
+        let (tokenizer, _) = setup();
+        let graph = MatchGraph::default();
+        // the pattern, the input, the case mapping and whether they are equal ignoring case
+        let cases = [
+            ("straße", "STRASSE", CaseMapping::Default, true),
+            ("STRASSE", "straße", CaseMapping::Default, true),
+            ("Straße", "strasse", CaseMapping::Default, true),
+            ("École", "école", CaseMapping::Default, true),
+            ("ÉCOLE", "école", CaseMapping::Default, true),
+            ("École", "ecole", CaseMapping::Default, false),
+            ("σοφός", "ΣΟΦΌΣ", CaseMapping::Default, true),
+            ("i", "I", CaseMapping::Default, true),
+            ("i", "I", CaseMapping::Turkic, false),
+            ("ı", "I", CaseMapping::Turkic, true),
+            ("i", "İ", CaseMapping::Turkic, true),
+            ("ı", "i", CaseMapping::Turkic, false),
+            ("İstanbul", "istanbul", CaseMapping::Turkic, true),
+            ("Istanbul", "istanbul", CaseMapping::Turkic, false),
+            ("ılık", "ILIK", CaseMapping::Turkic, true),
+            ("straße", "STRASSE", CaseMapping::Turkic, true),
+        ];
+
+        for (pattern, input, case_mapping, expected) in cases.iter().copied() {
+            let string =
+                Matcher::new_string(either::Left(pattern.to_string()), false, false, false)
+                    .with_case_mapping(case_mapping);
+            let regex = match case_mapping {
+                CaseMapping::Default => pattern.to_string(),
+                CaseMapping::Turkic => turkic_i(pattern),
+            };
+            let regex = Matcher::new_regex(
+                SerializeRegex::new(&regex, true, false).unwrap(),
+                false,
+                false,
+            );
+
+            assert_eq!(
+                string.is_match(input, &graph, None),
+                expected,
+                "string {:?} on {:?} with {:?}",
+                pattern,
+                input,
+                case_mapping
+            );
+            assert_eq!(
+                regex.is_match(input, &graph, None),
+                expected,
+                "regex {:?} on {:?} with {:?}",
+                pattern,
+                input,
+                case_mapping
+            );
+
+            // tokens compare their cached lowercase forms, twice to compare once they are cached
+            let tokens = finalize(tokenizer.tokenize(input));
+            for _ in 0..2 {
+                assert_eq!(
+                    string.is_token_match(&tokens[1], &graph, None),
+                    expected,
+                    "string {:?} on token {:?} with {:?}",
+                    pattern,
+                    input,
+                    case_mapping
+                );
+            }
+        }
+
+        // the case mapping is determined by the language of the rules
+        let matches = |lang_code: &str, text: &str| {
+            let grammar = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="{}">
+    <category id="TEST" name="Test">
+        <rule id="STRING" name="String">
+            <pattern><token>Istanbul</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="REGEX" name="Regex">
+            <pattern><token regexp="yes">Izmir|Ankara</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#,
+                lang_code
+            );
+            let rules = compile_rules(&grammar, &tokenizer);
+
+            rules.suggest(text, &tokenizer).len()
+        };
+
+        assert_eq!(matches("en", "istanbul izmir"), 2);
+        assert_eq!(matches("en", "ıstanbul ızmir"), 0);
+        assert_eq!(matches("tr", "istanbul izmir"), 0);
+        assert_eq!(matches("tr", "ıstanbul ızmir"), 2);
+    }
+
+    #[test]
+    fn match_budget_bounds_backtracking() {
+        let (tokenizer, rules) = setup();
+        // the default budget suffices for all examples of the test set
+        rules.validate(&tokenizer);
+        assert_eq!(rules.match_stats(), MatchStats::default());
+
+        // the optional tokens can split the words in exponentially many ways before failing on the number
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="BACKTRACKING" name="Backtracking">
+            <pattern>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes">\d+</token>
+            </pattern>
+            <message>Did you mean <suggestion>here</suggestion>?</message>
+            <example correction="here"><marker>the 1</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let mut build_info = build_info_for(&tokenizer);
+        let (mut rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(rules.match_budget(), DEFAULT_MATCH_BUDGET);
+
+        let text = "the tool runs the tool runs the tool runs the tool runs the tool runs";
+        assert!(rules.suggest(text, &tokenizer).is_empty());
+        assert_eq!(rules.match_stats(), MatchStats::default());
+
+        rules.set_match_budget(100);
+        assert!(rules.suggest(text, &tokenizer).is_empty());
+        let stats = rules.match_stats();
+        assert!(stats.overflows > 0);
+        assert_eq!(
+            stats.rules,
+            vec![("BACKTRACKING".to_string(), stats.overflows)]
+        );
+
+        // a match which exceeds the budget is discarded, matching only the number takes one step
+        let spans = |rules: &Rules| {
+            rules
+                .suggest("the 1", &tokenizer)
+                .iter()
+                .map(|x| (x.start, x.end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans(&rules), vec![(0, 5)]);
+        rules.set_match_budget(1);
+        rules.reset_match_stats();
+        assert_eq!(spans(&rules), vec![(4, 5)]);
+        assert!(rules.match_stats().overflows > 0);
+    }
+
+    #[test]
+    fn references_to_empty_groups_match_and_render_as_empty() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="EMPTY_GROUP" name="Empty group">
+            <pattern>
+                <token>the</token>
+                <token min="0">here</token>
+                <token negate="yes"><match no="1"/></token>
+            </pattern>
+            <message>Did you mean <suggestion>\2 \3</suggestion>, <suggestion>\3 \2</suggestion>,
+                <suggestion>\3 \2 \1</suggestion> or <suggestion>a <match no="2" postag="NN"/> \3</suggestion>?</message>
+            <example correction="tool|tool the|a tool">Next <marker>the tool</marker></example>
+        </rule>
+        <rule id="EMPTY_GROUP_TEXT" name="Empty group text">
+            <pattern>
+                <token>the</token>
+                <token min="0">here</token>
+                <token><match no="1"/></token>
+            </pattern>
+            <message>Did you mean <suggestion>\3</suggestion>?</message>
+            <example correction="here">Next <marker>the here here</marker></example>
+        </rule>
+        <rule id="EMPTY_GROUP_LEMMA" name="Empty group lemma">
+            <pattern>
+                <token>the</token>
+                <token min="0">here</token>
+                <token inflected="yes" negate="yes"><match no="1"/></token>
+            </pattern>
+            <message>Did you mean <suggestion>\3</suggestion>?</message>
+            <example correction="tool">Next <marker>the here tool</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let apply = |id: &str, text: &str| {
+            rules
+                .rule(id)
+                .unwrap()
+                .apply(
+                    &finalize(tokenizer.disambiguate(tokenizer.tokenize(text))),
+                    &tokenizer,
+                )
+                .into_iter()
+                .map(|x| x.replacements)
+                .collect::<Vec<_>>()
+        };
+
+        // the empty group 1 is the empty string: a negated text matches any token, a text no token
+        assert_eq!(
+            apply("EMPTY_GROUP", "Next the tool"),
+            [["tool", "tool the", "a tool"]]
+        );
+        assert_eq!(
+            apply("EMPTY_GROUP_TEXT", "Next the tool"),
+            Vec::<Vec<String>>::new()
+        );
+        assert_eq!(apply("EMPTY_GROUP_TEXT", "Next the here here"), [["here"]]);
+        // lemmas are `empty_always_false` so a negated lemma does not match an empty group
+        assert_eq!(
+            apply("EMPTY_GROUP_LEMMA", "Next the tool"),
+            Vec::<Vec<String>>::new()
+        );
+        assert_eq!(apply("EMPTY_GROUP_LEMMA", "Next the here tool"), [["tool"]]);
+    }
+
+    #[test]
+    fn tokens_can_require_or_forbid_space_before() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="FRENCH_COLON" name="Space before colon">
+            <pattern>
+                <token regexp="yes">\w+</token>
+                <marker><token spacebefore="no">:</token></marker>
+            </pattern>
+            <message>Did you mean <suggestion> :</suggestion>?</message>
+            <example correction=" :">Next<marker>:</marker></example>
+            <example>Next :</example>
+        </rule>
+        <rule id="PERCENT_SPACE" name="Space before percent sign">
+            <pattern>
+                <token regexp="yes">\d+</token>
+                <token spacebefore="yes">%</token>
+            </pattern>
+            <message>Did you mean <suggestion>\1%</suggestion>?</message>
+            <example correction="5%">It is <marker>5 %</marker>.</example>
+            <example>It is 5%.</example>
+        </rule>
+        <rule id="UNIT_SPACE" name="Space before unit">
+            <pattern>
+                <token regexp="yes">\d+</token>
+                <token regexp="yes" spacebefore="ignore">km|kg<exception spacebefore="no"/></token>
+            </pattern>
+            <message>Did you mean <suggestion>\1\2</suggestion>?</message>
+            <example correction="5km">It is <marker>5 km</marker> away.</example>
+            <example>It is 5km away.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let mut build_info = build_info_for(&tokenizer);
+        let rules = compile_rules(grammar, &tokenizer);
+        assert_eq!(rules.rules().len(), 3);
+
+        for rule in rules.rules() {
+            let failures = rule.validate(&tokenizer);
+            assert!(failures.is_empty(), "{}: {:?}", rule.id(), failures);
+        }
+
+        let invalid = grammar.replace(r#"spacebefore="yes""#, r#"spacebefore="maybe""#);
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            invalid.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rules.rules().len(), 2);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn exceptions_outside_of_the_sentence_are_not_triggered() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="PREVIOUS" name="Previous">
+            <pattern><token>here<exception scope="previous">the</exception></token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>the here</example>
+        </rule>
+        <rule id="PREVIOUS_NEGATED" name="Previous negated">
+            <pattern><token>here<exception scope="previous" negate="yes">the</exception></token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="there">the <marker>here</marker></example>
+        </rule>
+        <rule id="NEXT" name="Next">
+            <pattern><token>tool<exception scope="next">runs</exception></token></pattern>
+            <message>Did you mean <suggestion>tools</suggestion>?</message>
+            <example>the tool runs</example>
+        </rule>
+        <rule id="NEXT_NEGATED" name="Next negated">
+            <pattern><token>tool<exception scope="next" negate="yes">runs</exception></token></pattern>
+            <message>Did you mean <suggestion>tools</suggestion>?</message>
+            <example correction="tools">the <marker>tool</marker> runs</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let matches = |id: &str, text: &str| {
+            let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize(text)));
+            !rules
+                .rule(id)
+                .unwrap()
+                .apply(&tokens, &tokenizer)
+                .is_empty()
+        };
+
+        // at the first word the previous token is SENT_START, a negated exception is triggered by it
+        assert!(matches("PREVIOUS", "here the tool"));
+        assert!(!matches("PREVIOUS", "the here"));
+        assert!(!matches("PREVIOUS_NEGATED", "here the tool"));
+        assert!(matches("PREVIOUS_NEGATED", "the here"));
+
+        // after the last word there is no token, so neither exception is triggered
+        assert!(matches("NEXT", "the tool"));
+        assert!(!matches("NEXT", "the tool runs"));
+        assert!(matches("NEXT_NEGATED", "the tool"));
+        assert!(!matches("NEXT_NEGATED", "the tool here"));
+        assert!(matches("NEXT_NEGATED", "the tool runs"));
+
+        // the value out of bounds is configurable
+        let tokens = finalize(tokenizer.tokenize("the tool"));
+        let refs: Vec<_> = tokens.iter().collect();
+        let graph = MatchGraph::default();
+        for (offset, position) in [(-1, 0), (1, refs.len() - 1)] {
+            for out_of_bounds in [false, true] {
+                let atom = OffsetAtom::new(FalseAtom {}.into(), offset, out_of_bounds);
+                assert_eq!(atom.is_match(&refs, &graph, position), out_of_bounds);
+                // in bounds the atom decides
+                let position = if offset < 0 {
+                    position + 1
+                } else {
+                    position - 1
+                };
+                assert!(!atom.is_match(&refs, &graph, position));
+            }
+        }
+    }
+
+    #[test]
+    fn lemma_and_tag_are_matched_on_the_same_reading() {
+        let tag_path = write_temp(
+            "lemma_tags.txt",
+            "saw\tsee\tVBD\nsaw\tsaw\tNN\nsees\tsee\tVBZ\ntool\ttool\tNN\n",
+        );
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="SEE_NOUN" name="See as noun">
+            <pattern><token postag="NN" inflected="yes">see</token></pattern>
+            <message>Did you mean <suggestion>it</suggestion>?</message>
+            <example>saw</example>
+        </rule>
+        <rule id="NOT_SEE_NOUN" name="Noun except see">
+            <pattern><token postag="NN" inflected="yes" negate="yes">see</token></pattern>
+            <message>Did you mean <suggestion>it</suggestion>?</message>
+            <example correction="it"><marker>saw</marker></example>
+        </rule>
+        <rule id="NOT_SEE_EXCEPT_PAST" name="Any tag but past tense except see">
+            <pattern><token postag="VBD" negate_pos="yes" inflected="yes" negate="yes">see</token></pattern>
+            <message>Did you mean <suggestion>it</suggestion>?</message>
+            <example correction="it"><marker>saw</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let disambiguation = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+</rules>
+"#;
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+        let tokenizer = Tokenizer::from_xml_reader(
+            disambiguation.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let matches = |id: &str, text: &str| {
+            let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize(text)));
+            !rules
+                .rule(id)
+                .unwrap()
+                .apply(&tokens, &tokenizer)
+                .is_empty()
+        };
+
+        // no reading of "saw" has both the lemma "see" and the tag NN
+        assert!(!matches("SEE_NOUN", "saw"));
+        // like in LanguageTool, the reading (see, VBD) does not block the reading (saw, NN)
+        assert!(matches("NOT_SEE_NOUN", "saw"));
+        assert!(matches("NOT_SEE_NOUN", "tool"));
+        // there must be a reading with the tag
+        assert!(!matches("NOT_SEE_NOUN", "sees"));
+        // the reading (see, VBZ) has the lemma and a tag other than VBD
+        assert!(!matches("NOT_SEE_EXCEPT_PAST", "sees"));
+        assert!(matches("NOT_SEE_EXCEPT_PAST", "saw"));
+        assert!(matches("NOT_SEE_EXCEPT_PAST", "tool"));
     }
 }
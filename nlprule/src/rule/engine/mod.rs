@@ -1,8 +1,11 @@
 use crate::{types::*, utils::regex::SerializeRegex};
+use once_cell::unsync::OnceCell;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
 pub mod composition;
 
-use composition::{Composition, Group, MatchGraph};
+use super::explain::{AttemptTracer, MatchAttempt, MatchOutcome};
+use composition::{BudgetExceeded, Composition, Group, MatchGraph};
 
 #[derive(Serialize, Deserialize)]
 pub struct TokenEngine {
@@ -11,40 +14,99 @@ pub struct TokenEngine {
 }
 
 impl TokenEngine {
-    fn get_match<'t>(&'t self, tokens: &'t [&'t Token], i: usize) -> Option<MatchGraph<'t>> {
-        if let Some(graph) = self.composition.apply(tokens, i) {
-            let mut blocked = false;
-
-            // TODO: cache / move to outer loop
-            for i in 0..tokens.len() {
-                for antipattern in &self.antipatterns {
-                    if let Some(anti_graph) = antipattern.apply(tokens, i) {
+    fn uses_chunker(&self) -> bool {
+        self.composition.uses_chunker() || self.antipatterns.iter().any(|x| x.uses_chunker())
+    }
+
+    /// The character spans of all antipattern matches in the tokens. If an antipattern exceeds the match
+    /// budget at a token, it may have matched there with any end, so the span reaches to the end of the text.
+    /// A rule is rather suppressed than reported where an antipattern might have blocked it.
+    fn antipattern_spans(&self, tokens: &[&Token]) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+
+        for i in 0..tokens.len() {
+            for antipattern in &self.antipatterns {
+                match antipattern.try_apply(tokens, i) {
+                    Ok(Some(anti_graph)) => {
                         let anti_start = anti_graph.by_index(0).char_span.0;
                         let anti_end = anti_graph
                             .by_index(anti_graph.groups().len() - 1)
                             .char_span
                             .1;
 
-                        let rule_start = graph.by_index(0).char_span.0;
-                        let rule_end = graph.by_index(graph.groups().len() - 1).char_span.1;
+                        spans.push((anti_start, anti_end));
+                    }
+                    Ok(None) => {}
+                    Err(BudgetExceeded) => spans.push((tokens[i].char_span.0, usize::MAX)),
+                }
+            }
+        }
+
+        spans
+    }
+
+    /// The attempt at matching starting at each token, see [Rule::explain][crate::rule::Rule::explain].
+    pub(crate) fn explain(&self, tokens: &[&Token]) -> Vec<MatchAttempt> {
+        let antipattern_spans = self.antipattern_spans(tokens);
+        let parts = self.composition.parts.len();
+
+        (0..tokens.len())
+            .map(|start| {
+                let mut tracer = AttemptTracer::default();
+
+                match self.composition.apply_traced(tokens, start, &mut tracer) {
+                    Some(graph) => {
+                        let char_span = (
+                            graph.by_index(0).char_span.0,
+                            graph.by_index(graph.groups().len() - 1).char_span.1,
+                        );
+                        let blocked = antipattern_spans.iter().any(|(anti_start, anti_end)| {
+                            *anti_start <= char_span.1 && char_span.0 <= *anti_end
+                        });
 
-                        if anti_start <= rule_end && rule_start <= anti_end {
-                            blocked = true;
-                            break;
+                        MatchAttempt {
+                            start,
+                            parts_matched: parts,
+                            outcome: if blocked {
+                                MatchOutcome::Blocked { char_span }
+                            } else {
+                                MatchOutcome::Matched { char_span }
+                            },
                         }
                     }
+                    None => tracer.into_attempt(start, parts, tokens),
                 }
-                if blocked {
-                    break;
-                }
-            }
+            })
+            .collect()
+    }
 
-            if !blocked {
-                return Some(graph);
-            }
+    /// Gets the match starting at the token at index `i`, if it does not overlap with an antipattern match.
+    /// The antipattern spans are only computed once and only if the composition matches somewhere.
+    fn get_match<'t>(
+        &'t self,
+        tokens: &'t [&'t Token],
+        i: usize,
+        antipattern_spans: &OnceCell<Vec<(usize, usize)>>,
+    ) -> Option<MatchGraph<'t>> {
+        let graph = self.composition.apply(tokens, i)?;
+
+        if self.antipatterns.is_empty() {
+            return Some(graph);
         }
 
-        None
+        let rule_start = graph.by_index(0).char_span.0;
+        let rule_end = graph.by_index(graph.groups().len() - 1).char_span.1;
+
+        let blocked = antipattern_spans
+            .get_or_init(|| self.antipattern_spans(tokens))
+            .iter()
+            .any(|(anti_start, anti_end)| *anti_start <= rule_end && rule_start <= *anti_end);
+
+        if blocked {
+            None
+        } else {
+            Some(graph)
+        }
     }
 }
 
@@ -55,29 +117,165 @@ pub enum Engine {
 }
 
 impl Engine {
+    /// Whether this engine needs chunk information to match correctly.
+    /// This includes antipatterns since a missing chunk could fail to block a match.
+    pub fn uses_chunker(&self) -> bool {
+        match &self {
+            Engine::Token(engine) => engine.uses_chunker(),
+            Engine::Text(_, _) => false,
+        }
+    }
+
+    /// Sets the maximum number of steps of matching the compositions at one position. Text rules have no budget.
+    pub(crate) fn set_match_budget(&mut self, budget: usize) {
+        if let Engine::Token(engine) = self {
+            for composition in
+                std::iter::once(&mut engine.composition).chain(&mut engine.antipatterns)
+            {
+                composition.budget = budget;
+            }
+        }
+    }
+
+    /// Sets whether the regexes of alternatives are matched at once. Text rules have no alternatives.
+    pub(crate) fn set_batch_regexes(&mut self, batch: bool) {
+        if let Engine::Token(engine) = self {
+            for composition in
+                std::iter::once(&mut engine.composition).chain(&mut engine.antipatterns)
+            {
+                composition.set_batch_regexes(batch);
+            }
+        }
+    }
+
+    /// How often matching the compositions at a position exceeded the budget.
+    pub(crate) fn match_overflows(&self) -> usize {
+        match self {
+            Engine::Token(engine) => std::iter::once(&engine.composition)
+                .chain(&engine.antipatterns)
+                .map(|x| x.overflows())
+                .sum(),
+            Engine::Text(..) => 0,
+        }
+    }
+
+    /// Resets the counts of [Engine::match_overflows].
+    pub(crate) fn reset_match_overflows(&self) {
+        if let Engine::Token(engine) = self {
+            for composition in std::iter::once(&engine.composition).chain(&engine.antipatterns) {
+                composition.reset_overflows();
+            }
+        }
+    }
+
+    /// Calls `f` with the fallback counters of the compositions, see [Engine::regex_fallbacks].
+    fn visit_fallbacks(&self, mut f: impl FnMut(&Option<AtomicUsize>)) {
+        if let Engine::Token(engine) = self {
+            for composition in std::iter::once(&engine.composition).chain(&engine.antipatterns) {
+                composition.visit_fallbacks(&mut f);
+            }
+        }
+    }
+
+    /// Sets whether the [Engine::regex_fallbacks] are counted. Disabling resets the counts.
+    pub(crate) fn set_count_regex_fallbacks(&mut self, count: bool) {
+        if let Engine::Token(engine) = self {
+            for composition in
+                std::iter::once(&mut engine.composition).chain(&mut engine.antipatterns)
+            {
+                composition.visit_fallbacks_mut(&mut |x| {
+                    if count != x.is_some() {
+                        *x = count.then(|| AtomicUsize::new(0));
+                    }
+                });
+            }
+        }
+    }
+
+    /// How often a regex was matched against the text of a token because the words it matches are not
+    /// stored in the binary. Text rules always match their regex, which is not counted. Zero unless counting
+    /// is enabled with [Engine::set_count_regex_fallbacks].
+    pub(crate) fn regex_fallbacks(&self) -> usize {
+        let mut fallbacks = 0;
+        self.visit_fallbacks(|x| {
+            fallbacks += x.as_ref().map_or(0, |x| x.load(Ordering::Relaxed));
+        });
+        fallbacks
+    }
+
+    /// Resets the counts of [Engine::regex_fallbacks].
+    pub(crate) fn reset_regex_fallbacks(&self) {
+        self.visit_fallbacks(|x| {
+            if let Some(x) = x {
+                x.store(0, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// The character span of the groups `start..end` of a match i. e. of the marker.
+    /// For token rules the span is that of the marked tokens which matched, so optional tokens at
+    /// the edges of the marker which did not match do not extend it. The span is empty if no marked token matched.
+    pub fn marked_span(&self, graph: &MatchGraph, start: usize, end: usize) -> (usize, usize) {
+        let group = |id: usize| {
+            graph
+                .by_id(id)
+                .unwrap_or_else(|| panic!("group must exist in graph: {}", id))
+        };
+
+        match &self {
+            Engine::Token(_) => {
+                let tokens: Vec<_> = (start..end)
+                    .flat_map(|id| group(id).tokens(graph.tokens()))
+                    .collect();
+
+                match (tokens.first(), tokens.last()) {
+                    (Some(first), Some(last)) => (first.char_span.0, last.char_span.1),
+                    _ => {
+                        let position = group(start).char_span.0;
+                        (position, position)
+                    }
+                }
+            }
+            Engine::Text(..) => (group(start).char_span.0, group(end - 1).char_span.1),
+        }
+    }
+
     pub fn get_matches<'t>(
         &'t self,
         tokens: &'t [&'t Token],
         start: usize,
         end: usize,
+    ) -> Vec<MatchGraph<'t>> {
+        self.get_matches_at(tokens, start, end, None)
+    }
+
+    /// Like [Engine::get_matches] but token rules only try to match starting at the given `positions`
+    /// if there are any e. g. the positions where the first token of the rule can match.
+    pub fn get_matches_at<'t>(
+        &'t self,
+        tokens: &'t [&'t Token],
+        start: usize,
+        end: usize,
+        positions: Option<&[usize]>,
     ) -> Vec<MatchGraph<'t>> {
         let mut graphs = Vec::new();
 
+        if tokens.is_empty() {
+            return graphs;
+        }
+
         match &self {
             Engine::Token(engine) => {
-                let mut graph_info: Vec<_> = (0..tokens.len())
-                    .into_iter()
+                let antipattern_spans = OnceCell::new();
+                let positions = match positions {
+                    Some(positions) => either::Left(positions.iter().copied()),
+                    None => either::Right(0..tokens.len()),
+                };
+
+                let mut graph_info: Vec<_> = positions
                     .filter_map(|i| {
-                        if let Some(graph) = engine.get_match(&tokens, i) {
-                            let start_group = graph
-                                .by_id(start)
-                                .unwrap_or_else(|| panic!("group must exist in graph: {}", start));
-                            let end_group = graph.by_id(end - 1).unwrap_or_else(|| {
-                                panic!("group must exist in graph: {}", end - 1)
-                            });
-
-                            let start = start_group.char_span.0;
-                            let end = end_group.char_span.1;
+                        if let Some(graph) = engine.get_match(tokens, i, &antipattern_spans) {
+                            let (start, end) = self.marked_span(&graph, start, end);
                             Some((graph, start, end))
                         } else {
                             None
@@ -86,25 +284,37 @@ impl Engine {
                     .collect();
 
                 graph_info.sort_by(|(_, start, _), (_, end, _)| start.cmp(end));
-                let mut mask = vec![false; tokens[0].text.chars().count()];
+
+                // the mask only has to cover the matches, not the entire text
+                let offset = graph_info.iter().map(|x| x.1).min().unwrap_or(0);
+                let mask_end = graph_info.iter().map(|x| x.2).max().unwrap_or(0);
+                let mut mask = vec![false; mask_end.saturating_sub(offset)];
 
                 for (graph, start, end) in graph_info {
-                    if mask[start..end].iter().all(|x| !x) {
+                    let range = (start - offset)..(end - offset);
+
+                    if mask[range.clone()].iter().all(|x| !x) {
                         graphs.push(graph);
-                        mask[start..end].iter_mut().for_each(|x| *x = true);
+                        mask[range].iter_mut().for_each(|x| *x = true);
                     }
                 }
             }
             Engine::Text(regex, id_to_idx) => {
-                // this is the entire text, NOT the text of one token
-                let text = tokens[0].text;
+                // only the text covered by the tokens is searched (e. g. one sentence of the entire text)
+                let first = tokens
+                    .iter()
+                    .find(|x| x.char_span.1 > x.char_span.0)
+                    .copied()
+                    .unwrap_or(tokens[0]);
+                let last = tokens[tokens.len() - 1];
+                let text = &first.text[first.byte_span.0..last.byte_span.1];
 
                 let mut byte_to_char_idx: DefaultHashMap<usize, usize> = text
                     .char_indices()
                     .enumerate()
-                    .map(|(ci, (bi, _))| (bi, ci))
+                    .map(|(ci, (bi, _))| (bi, ci + first.char_span.0))
                     .collect();
-                byte_to_char_idx.insert(text.len(), byte_to_char_idx.len());
+                byte_to_char_idx.insert(text.len(), last.char_span.1);
 
                 graphs.extend(regex.captures_iter(text).map(|captures| {
                     let mut groups = Vec::new();
@@ -124,6 +334,203 @@ impl Engine {
             }
         }
 
+        // rules must never match across paragraph boundaries
+        graphs.retain(|graph| !graph.crosses_paragraph());
         graphs
     }
 }
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use crate::{
+        compile::testing::{assert_examples_pass, build_info_for, compile_rules, setup},
+        rule::engine::{composition::Atom, Engine},
+        rules::{Rules, RulesOptions},
+    };
+
+    #[test]
+    fn antipatterns_suppress_matches() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="COULD_OF" name="could of (could have)">
+            <antipattern>
+                <token>could</token>
+                <token>of</token>
+                <token>course</token>
+            </antipattern>
+            <pattern>
+                <marker>
+                    <token>could</token>
+                    <token>of</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>could have</suggestion>?</message>
+            <example correction="could have">I <marker>could of</marker> gone.</example>
+            <example>I could of course go.</example>
+        </rule>
+        <rule id="AND_ALSO" name="and also (and)">
+            <antipattern>
+                <token postag="SENT_START" />
+                <token>and</token>
+                <token>also</token>
+            </antipattern>
+            <pattern>
+                <marker>
+                    <token>and</token>
+                    <token>also</token>
+                </marker>
+            </pattern>
+            <message>Consider using <suggestion>and</suggestion>.</message>
+            <example correction="and">It runs <marker>and also</marker> stops.</example>
+            <example>And also it stops.</example>
+        </rule>
+        <rulegroup id="IN_PRINCIPAL" name="in principal (in principle)">
+            <antipattern>
+                <token>in</token>
+                <token>principal</token>
+                <token postag="NN" />
+            </antipattern>
+            <rule>
+                <pattern>
+                    <token>in</token>
+                    <marker>
+                        <token>principal</token>
+                    </marker>
+                </pattern>
+                <message>Did you mean <suggestion>principle</suggestion>?</message>
+                <example correction="principle">It works in <marker>principal</marker>.</example>
+                <example>It is stored in principal tool settings.</example>
+            </rule>
+        </rulegroup>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let rules = compile_rules(grammar, &tokenizer);
+        assert_eq!(rules.rules().len(), 3);
+        assert_examples_pass(&rules, &tokenizer);
+
+        let sources = |text: &str| -> Vec<_> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .map(|x| x.source)
+                .collect()
+        };
+        assert_eq!(sources("I could of gone."), vec!["COULD_OF"]);
+        assert!(sources("I could of course go.").is_empty());
+        assert_eq!(sources("It runs and also stops."), vec!["AND_ALSO"]);
+        assert!(sources("And also it stops.").is_empty());
+        assert_eq!(sources("It works in principal."), vec!["IN_PRINCIPAL.0"]);
+        assert!(sources("It is stored in principal tool settings.").is_empty());
+    }
+
+    #[test]
+    fn antipatterns_over_budget_suppress_matches() {
+        // the optional tokens of the antipattern can split the words in exponentially many ways
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="BACKTRACKING_ANTIPATTERN" name="Backtracking antipattern">
+            <antipattern>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes" min="0" max="-1">\w+</token>
+                <token regexp="yes">\d+</token>
+            </antipattern>
+            <pattern>
+                <token>runs</token>
+            </pattern>
+            <message>Did you mean <suggestion>goes</suggestion>?</message>
+            <example correction="goes">The tool <marker>runs</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let mut rules = compile_rules(grammar, &tokenizer);
+        assert_examples_pass(&rules, &tokenizer);
+
+        let text = "the tool runs the tool runs the tool runs the tool runs the tool runs";
+        assert_eq!(rules.suggest(text, &tokenizer).len(), 5);
+        assert_eq!(rules.match_stats().overflows, 0);
+
+        // the pattern itself stays within the budget, but the antipattern might have blocked it
+        rules.set_match_budget(100);
+        assert!(rules.suggest(text, &tokenizer).is_empty());
+        assert!(rules.match_stats().overflows > 0);
+    }
+
+    #[test]
+    fn batched_regexes_match_like_single_regexes() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="BATCH" name="Batch">
+            <pattern>
+                <token>the</token>
+                <or>
+                    <token regexp="yes">t[aeiou]+l</token>
+                    <token regexp="yes">r.*s|\d+</token>
+                    <token regexp="yes">x?</token>
+                    <token regexp="yes">g(?=o)o</token>
+                    <token regexp="yes" negate="yes">.+</token>
+                    <token>here</token>
+                </or>
+            </pattern>
+            <message>Did you mean <suggestion>a tool</suggestion>?</message>
+            <example correction="a tool">Next <marker>the tool</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let mut build_info = build_info_for(&tokenizer).with_max_regex_set_size(0);
+        let (mut rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let texts = [
+            "the tool", "the TOOL", "the runs", "the 42", "the go", "the here", "the x",
+            "the Next", "the the", "the",
+        ];
+        let spans = |rules: &Rules| -> Vec<Vec<(usize, usize)>> {
+            texts
+                .iter()
+                .map(|text| {
+                    rules
+                        .suggest(text, &tokenizer)
+                        .iter()
+                        .map(|x| (x.start, x.end))
+                        .collect()
+                })
+                .collect()
+        };
+
+        let batched = spans(&rules);
+        let matched: Vec<_> = batched.iter().map(|x| !x.is_empty()).collect();
+        assert_eq!(
+            matched,
+            [true, true, true, true, true, true, true, false, false, false]
+        );
+
+        // the set is only built with the pure-Rust backend
+        let batch_built = match &rules.rules()[0].engine {
+            Engine::Token(engine) => match &engine.composition.parts[1].atom {
+                Atom::OrAtom(atom) => matches!(atom.batch.get(), Some(Some(_))),
+                atom => panic!("expected an alternation, found {:?}", atom),
+            },
+            Engine::Text(..) => unreachable!(),
+        };
+        assert_eq!(batch_built, cfg!(not(feature = "regex-onig")));
+
+        rules.set_batch_regexes(false);
+        assert_eq!(spans(&rules), batched);
+    }
+}
@@ -0,0 +1,258 @@
+//! Explanations of why a rule does or does not match a sentence, see [Rule::explain][super::Rule::explain].
+
+use super::engine::composition::{Atom, MatchGraph, Tracer};
+use crate::types::*;
+use std::fmt;
+
+/// What happened when matching a rule starting at one token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchOutcome {
+    /// The pattern matched the characters in the span.
+    Matched { char_span: (usize, usize) },
+    /// The pattern matched but overlaps with a match of an antipattern.
+    Blocked { char_span: (usize, usize) },
+    /// The pattern matched but the tokens do not agree in the features of a unification.
+    NotUnified,
+    /// An atom rejected a token.
+    Rejected {
+        /// The index of the part with the atom.
+        part: usize,
+        /// A description of the atom e. g. `text "the"`.
+        atom: String,
+        /// The index of the rejected token.
+        position: usize,
+        /// The text of the rejected token, `None` if the sentence ended.
+        token: Option<String>,
+    },
+    /// Matching took more steps than the match budget, see [Rules::set_match_budget][crate::Rules::set_match_budget].
+    BudgetExceeded,
+}
+
+/// The best attempt at matching a rule starting at one token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchAttempt {
+    /// The index of the token the attempt starts at. The token at index 0 is the SENT_START token.
+    pub start: usize,
+    /// How many parts of the pattern matched, all of them unless the outcome is [MatchOutcome::Rejected]
+    /// or [MatchOutcome::BudgetExceeded].
+    pub parts_matched: usize,
+    pub outcome: MatchOutcome,
+}
+
+/// Why a rule does or does not match a sentence, see [Rule::explain][super::Rule::explain].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchExplanation {
+    pub rule_id: String,
+    /// The number of parts of the pattern i. e. of tokens in the XML. `None` for rules matching the text with a
+    /// regex, which are not explained.
+    pub parts: Option<usize>,
+    /// The attempt at each start token.
+    pub attempts: Vec<MatchAttempt>,
+}
+
+impl fmt::Display for MatchExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = match self.parts {
+            Some(parts) => parts,
+            None => return write!(f, "{}: matches the text with a regex", self.rule_id),
+        };
+        write!(f, "{}:", self.rule_id)?;
+
+        for attempt in &self.attempts {
+            write!(
+                f,
+                "\n  at token {}: {}/{} parts matched, ",
+                attempt.start, attempt.parts_matched, parts
+            )?;
+
+            match &attempt.outcome {
+                MatchOutcome::Matched { char_span } => {
+                    write!(f, "matched chars {}..{}", char_span.0, char_span.1)?
+                }
+                MatchOutcome::Blocked { char_span } => write!(
+                    f,
+                    "matched chars {}..{} but blocked by an antipattern",
+                    char_span.0, char_span.1
+                )?,
+                MatchOutcome::NotUnified => write!(f, "tokens are not unified")?,
+                MatchOutcome::Rejected {
+                    part,
+                    atom,
+                    position,
+                    token,
+                } => match token {
+                    Some(token) => write!(
+                        f,
+                        "part {} ({}) rejected token {} {:?}",
+                        part, atom, position, token
+                    )?,
+                    None => write!(
+                        f,
+                        "part {} ({}) reached the end of the sentence",
+                        part, atom
+                    )?,
+                },
+                MatchOutcome::BudgetExceeded => write!(f, "exceeded the match budget")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Records the rejection at the deepest part, i. e. how far the best attempt got.
+#[derive(Default)]
+pub(crate) struct AttemptTracer {
+    rejection: Option<(usize, usize, String)>,
+    budget_exceeded: bool,
+    not_unified: bool,
+}
+
+impl Tracer for AttemptTracer {
+    fn reject(
+        &mut self,
+        index: usize,
+        position: usize,
+        atom: &Atom,
+        input: &[&Token],
+        graph: &MatchGraph,
+    ) {
+        let deeper = match &self.rejection {
+            Some((deepest_index, deepest_position, _)) => {
+                (index, position) > (*deepest_index, *deepest_position)
+            }
+            None => true,
+        };
+
+        if deeper {
+            let atom = if position < input.len() {
+                atom.rejecting(input, graph, position)
+            } else {
+                atom
+            };
+            self.rejection = Some((index, position, atom.to_string()));
+        }
+    }
+
+    fn exceed_budget(&mut self) {
+        self.budget_exceeded = true;
+    }
+
+    fn fail_unification(&mut self) {
+        self.not_unified = true;
+    }
+}
+
+impl AttemptTracer {
+    /// The attempt at `start` if the composition with `parts` parts did not match.
+    pub(crate) fn into_attempt(self, start: usize, parts: usize, input: &[&Token]) -> MatchAttempt {
+        let parts_matched = self.rejection.as_ref().map_or(0, |(index, _, _)| *index);
+
+        let (parts_matched, outcome) = if self.budget_exceeded {
+            (parts_matched, MatchOutcome::BudgetExceeded)
+        } else if self.not_unified {
+            (parts, MatchOutcome::NotUnified)
+        } else {
+            match self.rejection {
+                Some((part, position, atom)) => (
+                    part,
+                    MatchOutcome::Rejected {
+                        part,
+                        atom,
+                        position,
+                        token: input
+                            .get(position)
+                            .map(|x| x.word.text.as_ref().to_string()),
+                    },
+                ),
+                None => unreachable!("matching only fails after an atom rejected a token"),
+            }
+        };
+
+        MatchAttempt {
+            start,
+            parts_matched,
+            outcome,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use crate::{
+        compile::testing::{compile_rules, setup},
+        rule::{MatchAttempt, MatchOutcome},
+        tokenizer::finalize,
+    };
+
+    #[test]
+    fn explanations_show_where_rules_stop_matching() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="EXPLAIN" name="Explain">
+            <antipattern>
+                <token>here</token>
+                <token>runs</token>
+                <token>Next</token>
+            </antipattern>
+            <pattern>
+                <token>the</token>
+                <token regexp="yes">tool|here<exception>tool</exception></token>
+                <token>runs</token>
+            </pattern>
+            <message>Did you mean <suggestion>\2 \3</suggestion>?</message>
+            <example correction="here runs">Next <marker>the here runs</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let rule = rules.rule("EXPLAIN").unwrap();
+        let explain =
+            |text: &str| rule.explain(&finalize(tokenizer.disambiguate(tokenizer.tokenize(text))));
+
+        // the exception of the second token rejects "tool"
+        let explanation = explain("the tool runs");
+        assert_eq!(explanation.parts, Some(3));
+        assert_eq!(
+            explanation.attempts[1],
+            MatchAttempt {
+                start: 1,
+                parts_matched: 1,
+                outcome: MatchOutcome::Rejected {
+                    part: 1,
+                    atom: r#"not text "tool""#.to_string(),
+                    position: 2,
+                    token: Some("tool".to_string()),
+                },
+            }
+        );
+        assert_eq!(
+            explanation.to_string().lines().nth(2),
+            Some(
+                r#"  at token 1: 1/3 parts matched, part 1 (not text "tool") rejected token 2 "tool""#
+            )
+        );
+
+        let outcome = |text: &str| explain(text).attempts[1].outcome.clone();
+        assert_eq!(
+            outcome("the here runs"),
+            MatchOutcome::Matched { char_span: (0, 13) }
+        );
+        assert_eq!(
+            outcome("the here runs Next"),
+            MatchOutcome::Blocked { char_span: (0, 13) }
+        );
+        assert!(matches!(
+            explain("Next the here").attempts[2].outcome,
+            MatchOutcome::Rejected {
+                part: 2,
+                token: None,
+                ..
+            }
+        ));
+    }
+}
@@ -1,12 +1,13 @@
-use super::engine::composition::{MatchGraph, PosMatcher};
+use super::engine::composition::{Group, MatchGraph, PosMatcher};
 use crate::types::*;
+use crate::utils::regex::Captures;
 use crate::{
     tokenizer::Tokenizer,
     utils::{self, regex::SerializeRegex},
 };
-use onig::Captures;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::{borrow::Cow, collections::HashSet};
+use unicase::UniCase;
 
 impl std::cmp::PartialEq for Suggestion {
     fn eq(&self, other: &Suggestion) -> bool {
@@ -17,6 +18,127 @@ impl std::cmp::PartialEq for Suggestion {
     }
 }
 
+/// How to map the case of single characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaseMapping {
+    Default,
+    /// Maps the dotted and dotless i like Turkish and Azerbaijani i. e. `i` <-> `İ` and `ı` <-> `I`.
+    Turkic,
+}
+
+impl CaseMapping {
+    /// The case mapping used by the language with the given code e. g. `tr` or `en-US`.
+    #[cfg(feature = "compile")]
+    pub fn from_lang_code(lang_code: &str) -> Self {
+        match lang_code.split(['-', '_']).next() {
+            Some("tr") | Some("az") => CaseMapping::Turkic,
+            _ => CaseMapping::Default,
+        }
+    }
+
+    fn char_to_uppercase(self, c: char) -> String {
+        match (self, c) {
+            (CaseMapping::Turkic, 'i') => "İ".to_string(),
+            (CaseMapping::Turkic, 'ı') => "I".to_string(),
+            _ => c.to_uppercase().collect(),
+        }
+    }
+
+    fn char_to_lowercase(self, c: char) -> String {
+        match (self, c) {
+            (CaseMapping::Turkic, 'İ') => "i".to_string(),
+            (CaseMapping::Turkic, 'I') => "ı".to_string(),
+            _ => c.to_lowercase().collect(),
+        }
+    }
+
+    fn to_uppercase(self, input: &str) -> String {
+        match self {
+            CaseMapping::Default => input.to_uppercase(),
+            CaseMapping::Turkic => input.chars().map(|c| self.char_to_uppercase(c)).collect(),
+        }
+    }
+
+    pub(crate) fn to_lowercase(self, input: &str) -> String {
+        match self {
+            CaseMapping::Default => input.to_lowercase(),
+            CaseMapping::Turkic => input.chars().map(|c| self.char_to_lowercase(c)).collect(),
+        }
+    }
+
+    /// Folds the case of `input` for comparisons ignoring case. Uses full case folding e. g. `ß` and `ss` are
+    /// equal.
+    pub(crate) fn fold(self, input: &str) -> UniCase<Cow<'_, str>> {
+        match self {
+            CaseMapping::Default => UniCase::new(Cow::Borrowed(input)),
+            // the Turkic i are mapped first since folding would map them like any other i
+            CaseMapping::Turkic => UniCase::new(Cow::Owned(self.to_lowercase(input))),
+        }
+    }
+
+    /// Whether `a` and `b` are equal ignoring case.
+    pub(crate) fn eq_ignore_case(self, a: &str, b: &str) -> bool {
+        self.fold(a) == self.fold(b)
+    }
+
+    /// Like [CaseMapping::eq_ignore_case] for strings which are already lowercase by the mapping. Only folds
+    /// if they differ and are not both ASCII since the lowercase form of ASCII is its folded form.
+    pub(crate) fn eq_ignore_case_lower(a: &str, b: &str) -> bool {
+        a == b || (!(a.is_ascii() && b.is_ascii()) && UniCase::new(a) == UniCase::new(b))
+    }
+}
+
+/// The case of a text, by its letters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CaseClass {
+    /// No uppercase letters, also if there are no letters at all.
+    Lower,
+    /// An uppercase letter followed by lowercase letters e. g. "There" or "I".
+    Title,
+    /// More than one letter and all of them uppercase e. g. "CAN".
+    Upper,
+    /// Any other combination e. g. "iPhone" or "McDonald".
+    Mixed,
+}
+
+impl CaseClass {
+    pub(crate) fn of(text: &str) -> Self {
+        let mut letters = text.chars().filter(|c| c.is_alphabetic());
+
+        let first = match letters.next() {
+            Some(first) => first,
+            None => return CaseClass::Lower,
+        };
+        let (mut any_upper, mut any_lower, mut count) = (false, false, 1);
+        for c in letters {
+            any_upper |= c.is_uppercase();
+            any_lower |= c.is_lowercase();
+            count += 1;
+        }
+
+        match (first.is_uppercase(), any_upper, any_lower) {
+            (false, false, _) => CaseClass::Lower,
+            (true, false, _) if count == 1 || any_lower => CaseClass::Title,
+            (true, true, false) | (true, false, false) => CaseClass::Upper,
+            _ => CaseClass::Mixed,
+        }
+    }
+
+    /// Gives `text` this case. Text in mixed case like "iPhone" is never changed, and lowercase or mixed
+    /// case do not change any text.
+    fn transfer(self, text: &str) -> String {
+        match (self, CaseClass::of(text)) {
+            (CaseClass::Upper, CaseClass::Lower) | (CaseClass::Upper, CaseClass::Title) => {
+                text.to_uppercase()
+            }
+            (CaseClass::Title, CaseClass::Lower) => {
+                utils::apply_to_first_grapheme(text, |x| x.to_uppercase())
+            }
+            _ => text.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Conversion {
     Nop,
@@ -24,16 +146,41 @@ pub enum Conversion {
     StartLower,
     AllUpper,
     StartUpper,
+    /// Copies the casing of the matched text i. e. all uppercase, uppercase start or lowercase start.
+    Preserve,
 }
 
 impl Conversion {
-    fn convert(&self, input: &str) -> String {
+    /// Converts the case of `input`. `original` is the matched text before replacing it.
+    fn convert(&self, input: &str, original: &str, mapping: CaseMapping) -> String {
         match &self {
             Conversion::Nop => input.to_string(),
-            Conversion::AllLower => input.to_lowercase(),
-            Conversion::StartLower => utils::apply_to_first(input, |c| c.to_lowercase().collect()),
-            Conversion::AllUpper => input.to_uppercase(),
-            Conversion::StartUpper => utils::apply_to_first(input, |c| c.to_uppercase().collect()),
+            Conversion::AllLower => mapping.to_lowercase(input),
+            Conversion::StartLower => {
+                utils::apply_to_first(input, |c| mapping.char_to_lowercase(c))
+            }
+            Conversion::AllUpper => mapping.to_uppercase(input),
+            Conversion::StartUpper => {
+                utils::apply_to_first(input, |c| mapping.char_to_uppercase(c))
+            }
+            Conversion::Preserve => {
+                let mut chars = original.chars().filter(|c| c.is_alphabetic());
+
+                match chars.next() {
+                    // a single uppercase letter like "I" is not considered all uppercase
+                    Some(first) if first.is_uppercase() => {
+                        if chars.clone().next().is_some() && utils::is_uppercase(original) {
+                            Conversion::AllUpper.convert(input, original, mapping)
+                        } else {
+                            Conversion::StartUpper.convert(input, original, mapping)
+                        }
+                    }
+                    Some(first) if first.is_lowercase() => {
+                        Conversion::StartLower.convert(input, original, mapping)
+                    }
+                    _ => input.to_string(),
+                }
+            }
         }
     }
 }
@@ -54,6 +201,7 @@ impl Example {
     /// Gets the suggestion for this example.
     /// * If this is `None`, the associated rule should not trigger for this example.
     /// * If it is `Some`, the associated rule should return a suggestion with equivalent range and suggestions.
+    ///   If it has no replacements, any replacements are accepted.
     pub fn suggestion(&self) -> Option<&Suggestion> {
         self.suggestion.as_ref()
     }
@@ -65,45 +213,43 @@ pub struct PosReplacer {
 }
 
 impl PosReplacer {
-    fn apply(&self, text: &str, tokenizer: &Tokenizer) -> Option<String> {
-        let mut candidates: Vec<_> = tokenizer
-            .tagger()
-            .get_tags(
+    /// Synthesizes the inflections of the lemmas of `tokens` with a matching part-of-speech tag.
+    /// The lemmas come from the readings of the tokens after disambiguation, `text` is only tagged
+    /// from scratch if there are no tokens. Returns an empty vector if nothing can be synthesized.
+    fn apply(&self, tokens: &[&Token], text: &str, tokenizer: &Tokenizer) -> Vec<String> {
+        let tagger = tokenizer.tagger();
+
+        let mut lemmas: Vec<String> = Vec::new();
+        let mut add_lemma = |lemma: &str| {
+            if !lemma.is_empty() && !lemmas.iter().any(|x| x == lemma) {
+                lemmas.push(lemma.to_string());
+            }
+        };
+
+        if tokens.is_empty() {
+            for data in tagger.get_tags_with(
                 text,
                 tokenizer.options().always_add_lower_tags,
-                tokenizer.options().use_compound_split_heuristic,
-            )
-            .iter()
-            .map(|x| {
-                let group_words = tokenizer
-                    .tagger()
-                    .get_group_members(&x.lemma.as_ref().to_string());
-                let mut data = Vec::new();
-                for word in group_words {
-                    if let Some(i) = tokenizer
-                        .tagger()
-                        .get_tags(
-                            word,
-                            tokenizer.options().always_add_lower_tags,
-                            tokenizer.options().use_compound_split_heuristic,
-                        )
-                        .iter()
-                        .position(|x| self.matcher.is_match(&x.pos))
-                    {
-                        data.push((word.to_string(), i));
-                    }
-                }
-                data
-            })
-            .rev()
-            .flatten()
-            .collect();
-        candidates.sort_by(|(_, a), (_, b)| a.cmp(b));
-        if candidates.is_empty() {
-            None
+                tokenizer.options(),
+            ) {
+                add_lemma(data.lemma.as_ref());
+            }
         } else {
-            Some(candidates.remove(0).0)
+            for data in tokens.iter().flat_map(|token| token.word.tags.iter()) {
+                add_lemma(data.lemma.as_ref());
+            }
         }
+
+        let mut output: Vec<String> = Vec::new();
+        for lemma in lemmas {
+            for (word, pos) in tagger.get_inflections(&lemma) {
+                if self.matcher.is_match(&pos) && !output.iter().any(|x| *x == word) {
+                    output.push(word.into_owned());
+                }
+            }
+        }
+
+        output
     }
 }
 
@@ -113,31 +259,74 @@ pub struct Match {
     pub(crate) conversion: Conversion,
     pub(crate) pos_replacer: Option<PosReplacer>,
     pub(crate) regex_replacer: Option<(SerializeRegex, String)>,
+    /// Whether the tokens skipped after the token are part of the text i. e. `include_skipped="all"`.
+    pub(crate) include_skipped: bool,
+    pub(crate) case_mapping: CaseMapping,
 }
 
 impl Match {
-    fn apply(&self, graph: &MatchGraph, tokenizer: &Tokenizer) -> Option<String> {
-        let text = graph
+    /// Computes the text of this match. There can be multiple candidates if multiple inflections are
+    /// synthesized, and none if synthesis fails unless `keep_unsynthesized` is set. Then the matched text is used instead.
+    fn apply(
+        &self,
+        graph: &MatchGraph,
+        tokenizer: &Tokenizer,
+        keep_unsynthesized: bool,
+    ) -> Vec<String> {
+        let group = graph
             .by_id(self.id)
-            .unwrap_or_else(|| panic!("group must exist in graph: {}", self.id))
-            .text(graph.tokens()[0].text);
+            .unwrap_or_else(|| panic!("group must exist in graph: {}", self.id));
 
-        let mut text = if let Some(replacer) = &self.pos_replacer {
-            replacer.apply(text, tokenizer)?
+        let text = if self.include_skipped {
+            // the group of the skipped tokens is directly after the group of the token
+            let index = graph.get_index(self.id).expect("group must exist in graph");
+            let skipped = graph.by_index(index + 1);
+
+            Group::new((
+                group.char_span.0,
+                group.char_span.1.max(skipped.char_span.1),
+            ))
+            .text(graph.tokens()[0].text)
         } else {
-            text.to_string()
+            group.text(graph.tokens()[0].text)
         };
 
-        text = if let Some((regex, replacement)) = &self.regex_replacer {
-            regex.replace_all(&text, |caps: &Captures| {
-                utils::dollar_replace(replacement.to_string(), caps)
-            })
-        } else {
-            text
+        // a group without tokens is rendered as empty, nothing is synthesized or replaced
+        if text.is_empty() {
+            return vec![String::new()];
+        }
+
+        let original = text;
+        let texts = match &self.pos_replacer {
+            Some(replacer) => {
+                let texts = replacer.apply(&group.tokens(graph.tokens()), text, tokenizer);
+
+                if texts.is_empty() && keep_unsynthesized {
+                    vec![text.to_string()]
+                } else {
+                    texts
+                }
+            }
+            None => vec![text.to_string()],
         };
 
-        // TODO: maybe return a vector here and propagate accordingly
-        Some(self.conversion.convert(&text))
+        let mut output: Vec<String> = Vec::new();
+        for text in texts {
+            let text = if let Some((regex, replacement)) = &self.regex_replacer {
+                regex.replace_all(&text, |caps: &Captures| {
+                    utils::dollar_replace(replacement.to_string(), caps)
+                })
+            } else {
+                text
+            };
+
+            let text = self.conversion.convert(&text, original, self.case_mapping);
+            if !output.contains(&text) {
+                output.push(text);
+            }
+        }
+
+        output
     }
 
     fn has_conversion(&self) -> bool {
@@ -158,14 +347,71 @@ pub struct Synthesizer {
 }
 
 impl Synthesizer {
+    /// The case to give the suggestions, see [CaseClass::transfer].
+    fn replaced_case(&self, graph: &MatchGraph, start: usize) -> CaseClass {
+        let tokens: Vec<_> = graph.groups()[graph.get_index(start).unwrap()..]
+            .iter()
+            .flat_map(|x| x.tokens(graph.tokens()))
+            .collect();
+        let first_token = match tokens.first() {
+            Some(token) => token,
+            None => return CaseClass::Lower,
+        };
+
+        // special tokens like SENT_START have an empty span
+        let sentence_start = graph
+            .tokens()
+            .iter()
+            .find(|x| x.char_span.1 > x.char_span.0);
+        let at_sentence_start = first_token.byte_span.0 == 0
+            || matches!(sentence_start, Some(x) if std::ptr::eq(*x, *first_token));
+
+        let text: Vec<_> = tokens.iter().map(|x| x.word.text.as_ref()).collect();
+        let starts_uppercase = first_token
+            .word
+            .text
+            .as_ref()
+            .starts_with(char::is_uppercase);
+
+        if self.use_titlecase_adjust && CaseClass::of(&text.join(" ")) == CaseClass::Upper {
+            CaseClass::Upper
+        } else if at_sentence_start || (self.use_titlecase_adjust && starts_uppercase) {
+            CaseClass::Title
+        } else {
+            CaseClass::Lower
+        }
+    }
+
+    /// Computes all candidate texts i. e. one for each combination of the candidates of the matches.
+    /// Returns an empty vector if any match has no candidates.
     pub fn apply(
         &self,
         graph: &MatchGraph,
         tokenizer: &Tokenizer,
         start: usize,
         _end: usize,
-    ) -> Option<String> {
-        let mut output = Vec::new();
+    ) -> Vec<String> {
+        self.candidates(graph, tokenizer, start, false)
+    }
+
+    /// Computes the text of a message. Like in LanguageTool, matches which can not be synthesized
+    /// keep the matched text so there always is a message.
+    pub fn apply_message(&self, graph: &MatchGraph, tokenizer: &Tokenizer, start: usize) -> String {
+        self.candidates(graph, tokenizer, start, true)
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+    }
+
+    fn candidates(
+        &self,
+        graph: &MatchGraph,
+        tokenizer: &Tokenizer,
+        start: usize,
+        keep_unsynthesized: bool,
+    ) -> Vec<String> {
+        // the outputs and whether they end with an empty match
+        let mut outputs = vec![(String::new(), false)];
 
         let starts_with_conversion = match &self.parts[..] {
             [SynthesizerPart::Match(m), ..] => m.has_conversion(),
@@ -174,43 +420,369 @@ impl Synthesizer {
 
         for part in &self.parts {
             match part {
-                SynthesizerPart::Text(t) => output.push(t.clone()),
+                SynthesizerPart::Text(t) => {
+                    for (output, after_empty) in outputs.iter_mut() {
+                        // the whitespace separating an empty match is dropped so it is not doubled
+                        if *after_empty
+                            && (output.is_empty() || output.ends_with(char::is_whitespace))
+                        {
+                            output.push_str(t.trim_start());
+                        } else {
+                            output.push_str(t);
+                        }
+                        *after_empty = false;
+                    }
+                }
                 SynthesizerPart::Match(m) => {
-                    output.push(m.apply(graph, tokenizer)?);
+                    let texts = m.apply(graph, tokenizer, keep_unsynthesized);
+
+                    outputs = outputs
+                        .iter()
+                        .flat_map(|(output, _)| {
+                            texts
+                                .iter()
+                                .map(move |text| (output.clone() + text, text.is_empty()))
+                        })
+                        .collect();
                 }
             }
         }
 
-        let suggestion = utils::normalize_whitespace(&output.join(""));
-
-        // if the suggestion does not start with a case conversion match, make it title case if:
-        // * at sentence start
-        // * the replaced text is title case
-        let make_uppercase = !starts_with_conversion
-            && graph.groups()[graph.get_index(start).unwrap()..]
-                .iter()
-                .find(|x| !x.tokens(graph.tokens()).is_empty())
-                .map(|group| {
-                    let first_token = group.tokens(graph.tokens())[0];
-                    (self.use_titlecase_adjust
-                        && first_token
-                            .word
-                            .text
-                            .as_ref()
-                            .chars()
-                            .next()
-                            .expect("token must have at least one char")
-                            .is_uppercase())
-                        || first_token.byte_span.0 == 0
-                })
-                .unwrap_or(false);
-
-        if make_uppercase {
-            Some(utils::apply_to_first(&suggestion, |x| {
-                x.to_uppercase().collect()
-            }))
+        // unless the suggestion starts with a case conversion match, it gets the case of the replaced text:
+        // * all uppercase if the replaced text is e. g. "CAN NOT" in a headline
+        // * title case if the replaced text starts uppercase or is at sentence start
+        let case = if starts_with_conversion {
+            CaseClass::Lower
         } else {
-            Some(suggestion)
+            self.replaced_case(graph, start)
+        };
+
+        let mut suggestions: Vec<String> = Vec::new();
+        for (mut output, after_empty) in outputs {
+            if after_empty {
+                output.truncate(output.trim_end().len());
+            }
+            let suggestion = case.transfer(&utils::normalize_whitespace(&output));
+
+            if !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+        }
+
+        suggestions
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use crate::{
+        compile::{
+            testing::{assert_examples_pass, build_info_for, compile_rules, setup, write_temp},
+            BuildInfo, RegexCache,
+        },
+        rules::{Rules, RulesOptions},
+        tokenizer::{tag::Tagger, Tokenizer, TokenizerOptions},
+    };
+
+    #[test]
+    fn case_conversion_is_applied() {
+        let grammar = |lang_code: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="{}">
+    <category id="TEST" name="Test">
+        <rule id="HERE_THERE" name="Here (there)">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" regexp_match="(?i)here" regexp_replace="there" case_conversion="preserve"/></suggestion>?</message>
+            <example correction="there">It is <marker>here</marker>.</example>
+        </rule>
+        <rule id="NEXT_LOWER" name="Next (lowercase)">
+            <pattern>
+                <token>next</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" case_conversion="startlower"/></suggestion>?</message>
+            <example correction="next">The <marker>Next</marker> one.</example>
+        </rule>
+        <rule id="CITY_UPPER" name="City (uppercase)">
+            <pattern>
+                <token regexp="yes">istanbul|straße</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" case_conversion="startupper"/></suggestion> or <suggestion><match no="1" case_conversion="allupper"/></suggestion>?</message>
+            <example correction="Straße|STRASSE">Die <marker>straße</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#,
+                lang_code
+            )
+        };
+
+        let (tokenizer, _) = setup();
+        let rules = |lang_code: &str| {
+            let mut build_info = build_info_for(&tokenizer);
+            Rules::from_xml_reader(
+                grammar(lang_code).as_bytes(),
+                &mut build_info,
+                RulesOptions::default(),
+            )
+            .unwrap()
+        };
+        let replacements = |rules: &Rules, text: &str| -> Vec<String> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .flat_map(|x| x.replacements)
+                .collect()
+        };
+
+        let english = rules("en");
+        assert_eq!(english.rules().len(), 3);
+        for rule in english.rules() {
+            assert!(rule.test(&tokenizer), "{} fails its examples", rule.id());
+        }
+
+        // `preserve` copies the casing of the matched token
+        assert_eq!(replacements(&english, "Here it is."), vec!["There"]);
+        assert_eq!(replacements(&english, "It is HERE now."), vec!["THERE"]);
+        assert_eq!(replacements(&english, "It is HeRe now."), vec!["There"]);
+        assert_eq!(replacements(&english, "It is hERE now."), vec!["there"]);
+
+        // sentence-initial tokens are not made title case if the match converts the case
+        assert_eq!(replacements(&english, "Next one."), vec!["next"]);
+        assert_eq!(replacements(&english, "The NEXT one."), vec!["nEXT"]);
+
+        assert_eq!(
+            replacements(&english, "Take the istanbul road."),
+            vec!["Istanbul", "ISTANBUL"]
+        );
+        let turkish = rules("tr");
+        assert_eq!(
+            replacements(&turkish, "Take the istanbul road."),
+            vec!["İstanbul", "İSTANBUL"]
+        );
+    }
+
+    #[test]
+    fn regex_replacement_is_applied() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="OXFORD_SPELLING_IZE" name="Oxford spelling: -ize">
+            <pattern>
+                <token regexp="yes">(organ|real|recogn)is(e|es|ed|ing)</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" regexp_match="(?i)(.*)is(e|es|ed|ing)$" regexp_replace="$1iz$2" case_conversion="preserve"/></suggestion>?</message>
+            <example correction="realized">We <marker>realised</marker> it.</example>
+        </rule>
+        <rule id="COLOR_US" name="US spelling: color">
+            <pattern>
+                <token regexp="yes">colou?rs?</token>
+                <token>red</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" regexp_match="our" regexp_replace="or"/> red</suggestion>?</message>
+            <example correction="color red">It is <marker>colour red</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+
+        let (tokenizer, _) = setup();
+        let rules = compile_rules(grammar, &tokenizer);
+        let replacements = |text: &str| -> Vec<String> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .flat_map(|x| x.replacements)
+                .collect()
+        };
+
+        assert_eq!(rules.rules().len(), 2);
+        assert_examples_pass(&rules, &tokenizer);
+
+        // backreferences and case conversion of the replaced text
+        assert_eq!(replacements("They organise it."), vec!["organize"]);
+        assert_eq!(replacements("Recognised in time."), vec!["Recognized"]);
+        assert_eq!(replacements("We are REALISING it."), vec!["REALIZING"]);
+        // the text is left as-is if the regex does not match
+        assert_eq!(replacements("It is color red."), vec!["color red"]);
+        assert_eq!(replacements("It is colours red."), vec!["colors red"]);
+    }
+
+    #[test]
+    fn inflected_suggestions_include_all_forms() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="GRAMMAR" name="Grammar">
+        <rule id="HE_VERB_AGR" name="Agreement: he + verb">
+            <pattern>
+                <token>he</token>
+                <token postag="VB"/>
+            </pattern>
+            <message>Did you mean <suggestion>\1 <match no="2" postag="VBZ"/></suggestion>?</message>
+            <example correction="he has">Then <marker>he have</marker> it.</example>
+            <example>Then he dream of it.</example>
+        </rule>
+        <rule id="DID_BASEFORM" name="did + baseform">
+            <pattern>
+                <token>did</token>
+                <token postag="VBD"/>
+            </pattern>
+            <message>Did you mean <suggestion>\1 <match no="2" postag="VB"/></suggestion>?</message>
+            <example correction="did go">Then I <marker>did went</marker>.</example>
+        </rule>
+        <rule id="HAVE_PARTICIPLE" name="have + participle">
+            <pattern>
+                <token>have</token>
+                <token postag="VB"/>
+            </pattern>
+            <message>Did you mean <suggestion>\1 <match no="2" postag="VB[DN]" postag_regexp="yes"/></suggestion>?</message>
+            <example correction="have gone|have went">I <marker>have go</marker>.</example>
+        </rule>
+        <rule id="SHE_VERB" name="she + verb">
+            <pattern>
+                <token>she</token>
+                <token postag="VB"/>
+            </pattern>
+            <message>Did you mean <suggestion>\1 <match no="2" postag="VBZ"/></suggestion> or <suggestion>\1 will \2</suggestion>?</message>
+            <example correction="she goes|she will go">Then <marker>she go</marker> home.</example>
+            <example correction="she will dream">Then <marker>she dream</marker> of it.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let tag_path = write_temp(
+            "en_inflection_tags.txt",
+            "he\the\tPRP\nshe\tshe\tPRP\nI\tI\tPRP\ndid\tdo\tVBD\ndo\tdo\tVB\nhave\thave\tVB\nhas\thave\tVBZ\n\
+             had\thave\tVBD\nhad\thave\tVBN\ngo\tgo\tVB\ngoes\tgo\tVBZ\nwent\tgo\tVBD\n\
+             gone\tgo\tVBN\ndream\tdream\tVB\ndreamed\tdream\tVBD\ndreamed\tdream\tVBN\n\
+             dreamt\tdream\tVBD\ndreamt\tdream\tVBN\n",
+        );
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[&tag_path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+        let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+        let tokenizer = Tokenizer::from_xml_reader(
+            r#"<?xml version="1.0" encoding="UTF-8"?><rules lang="en"></rules>"#.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+        let (rules, errors) = Rules::from_xml_reader_with_errors(
+            grammar.as_bytes(),
+            &mut build_info,
+            RulesOptions::default(),
+        )
+        .unwrap();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(rules.rules().len(), 4);
+        assert_examples_pass(&rules, &tokenizer);
+
+        let replacements = |text: &str| -> Vec<String> {
+            let mut replacements: Vec<String> = rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .flat_map(|x| x.replacements)
+                .collect();
+            replacements.sort();
+            replacements
+        };
+
+        assert_eq!(replacements("Then he go home."), vec!["he goes"]);
+        assert_eq!(replacements("Then I did had it."), vec!["did have"]);
+        // all forms are suggested, without duplicates
+        assert_eq!(
+            replacements("I have dream."),
+            vec!["have dreamed", "have dreamt"]
+        );
+        // the suggestion is omitted if there is no form with the tag (there is no "dreams")
+        assert_eq!(replacements("Then he dream of it."), Vec::<String>::new());
+
+        // a match in the message which can not be synthesized keeps the matched text
+        let messages: Vec<_> = rules
+            .suggest("Then she dream of it.", &tokenizer)
+            .into_iter()
+            .map(|x| x.message)
+            .collect();
+        assert_eq!(messages, vec!["Did you mean she dream or she will dream?"]);
+    }
+
+    #[test]
+    fn replacements_get_the_case_of_the_replaced_text() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="THEIR" name="Their">
+            <pattern>
+                <marker><token>there</token></marker>
+                <token>tool</token>
+            </pattern>
+            <message>Did you mean <suggestion>their</suggestion>?</message>
+            <example correction="their">Next <marker>there</marker> tool.</example>
+        </rule>
+        <rule id="CANNOT" name="Cannot">
+            <pattern>
+                <token>can</token>
+                <token>not</token>
+            </pattern>
+            <message>Did you mean <suggestion>cannot</suggestion>?</message>
+            <example correction="cannot">We <marker>can not</marker> go.</example>
+        </rule>
+        <rule id="IPHONE" name="iPhone">
+            <pattern>
+                <token>iphone</token>
+            </pattern>
+            <message>Did you mean <suggestion>iPhone</suggestion>?</message>
+            <example correction="iPhone">It is an <marker>iphone</marker>.</example>
+        </rule>
+        <rule id="ECOLE" name="École">
+            <pattern>
+                <token>ecole</token>
+            </pattern>
+            <message>Did you mean <suggestion>e&#x301;cole</suggestion>?</message>
+            <example correction="e&#x301;cole">It is an <marker>ecole</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let cases = [
+            // mid-sentence
+            ("Next there tool.", "their"),
+            ("We can not go.", "cannot"),
+            ("It is an iphone.", "iPhone"),
+            ("It is an ecole.", "e\u{301}cole"),
+            // sentence-initial
+            ("There tool runs.", "Their"),
+            ("Next. There tool runs.", "Their"),
+            ("Can not go.", "Cannot"),
+            ("Iphone runs.", "iPhone"),
+            ("Ecole runs.", "E\u{301}cole"),
+            // all uppercase headline
+            ("NEXT THERE TOOL", "THEIR"),
+            ("WE CAN NOT GO", "CANNOT"),
+            ("AN IPHONE", "iPhone"),
+            // only the first word is uppercase, mid-sentence
+            ("Next There tool.", "Their"),
+        ];
+
+        for (text, expected) in cases.iter() {
+            let suggestions = rules.suggest(text, &tokenizer);
+            assert_eq!(suggestions.len(), 1, "{}", text);
+            assert_eq!(suggestions[0].replacements, vec![*expected], "{}", text);
         }
     }
 }
@@ -3,7 +3,7 @@
 use crate::types::*;
 use crate::{
     filter::{Filter, Filterable},
-    tokenizer::{finalize, Tokenizer},
+    tokenizer::{finalize, AnalyzeOptions, Tokenizer},
     utils,
 };
 use log::{error, info, warn};
@@ -12,11 +12,13 @@ use std::collections::HashSet;
 
 pub(crate) mod disambiguation;
 pub(crate) mod engine;
+pub(crate) mod explain;
 pub(crate) mod grammar;
 
 use engine::Engine;
 
 pub(crate) use engine::composition::MatchGraph;
+pub use explain::{MatchAttempt, MatchExplanation, MatchOutcome};
 pub use grammar::Example;
 
 /// A disambiguation rule.
@@ -63,6 +65,11 @@ impl DisambiguationRule {
         self.id.as_str()
     }
 
+    /// Whether this rule needs chunk information i. e. whether it is skipped if chunking is turned off.
+    pub fn uses_chunker(&self) -> bool {
+        self.engine.uses_chunker()
+    }
+
     pub(crate) fn apply<'t>(&'t self, tokens: &[Token<'t>], tokenizer: &Tokenizer) -> Changes {
         if matches!(self.disambiguations, disambiguation::Disambiguation::Nop) {
             return Changes::default();
@@ -142,8 +149,12 @@ impl DisambiguationRule {
                 disambiguation::DisambiguationExample::Changed(x) => x.text.as_str(),
             };
 
-            let tokens_before =
-                tokenizer.disambiguate_up_to_id(tokenizer.tokenize(text), Some(&self.id));
+            let tokens_before = tokenizer.disambiguate_up_to_id(
+                tokenizer.tokenize(text),
+                Some(&self.id),
+                &AnalyzeOptions::default(),
+                None,
+            );
             let finalized = finalize(tokens_before.clone());
             let changes = self.apply(&finalized, tokenizer);
             let mut tokens_after = tokens_before.clone();
@@ -214,6 +225,18 @@ impl DisambiguationRule {
     }
 }
 
+/// Whether a grammar rule is turned on according to the `default` attributes in the XML of the rule,
+/// its rule group and its category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DefaultState {
+    /// The rule is turned on. Also used if no `default` is set.
+    On,
+    /// The rule is turned off by design e. g. because it is too picky for most texts.
+    Off,
+    /// The rule is turned off temporarily e. g. until false positives are fixed.
+    TempOff,
+}
+
 /// A grammar rule.
 /// Returns a [Suggestion][crate::types::Suggestion] for change if it matches.
 /// Sourced from LanguageTool. An example of how a simple rule might look in the original XML format:
@@ -238,7 +261,9 @@ pub struct Rule {
     pub(crate) message: grammar::Synthesizer,
     pub(crate) start: usize,
     pub(crate) end: usize,
+    pub(crate) filter: Option<Filter>,
     pub(crate) on: bool,
+    pub(crate) default_state: DefaultState,
     pub(crate) url: Option<String>,
     pub(crate) short: Option<String>,
     pub(crate) name: String,
@@ -258,6 +283,13 @@ impl Rule {
         self.on
     }
 
+    /// Gets the state of this rule according to the `default` attributes in the XML,
+    /// e. g. to distinguish rules which are off by design from rules which are off temporarily.
+    /// Unlike [Rule::on] this is not changed by [Rule::set_on].
+    pub fn default_state(&self) -> DefaultState {
+        self.default_state
+    }
+
     /// Gets a short text describing this rule e.g. "Possible typo" if there is one.
     pub fn short(&self) -> Option<&str> {
         self.short.as_deref()
@@ -298,23 +330,75 @@ impl Rule {
         self.category_type.as_deref()
     }
 
+    /// Drops the name, message, URL and short message of this rule and the name and type of its category,
+    /// see [Rules::strip_metadata][crate::Rules::strip_metadata]. The ID and category ID are kept.
+    pub(crate) fn strip_metadata(&mut self) {
+        self.name = String::new();
+        self.message = grammar::Synthesizer {
+            use_titlecase_adjust: false,
+            parts: Vec::new(),
+        };
+        self.url = None;
+        self.short = None;
+        self.category_name = String::new();
+        self.category_type = None;
+    }
+
+    /// Whether this rule needs chunk information i. e. whether it is skipped if chunking is turned off.
+    pub fn uses_chunker(&self) -> bool {
+        self.engine.uses_chunker()
+    }
+
     pub(crate) fn apply(&self, tokens: &[Token], tokenizer: &Tokenizer) -> Vec<Suggestion> {
+        self.apply_at(tokens, tokenizer, None, false)
+    }
+
+    /// Like [Rule::apply] but only tries to match starting at the given `positions` if there are any,
+    /// see [Engine::get_matches_at]. Sets [Suggestion::groups] if `keep_groups` is true.
+    pub(crate) fn apply_at(
+        &self,
+        tokens: &[Token],
+        tokenizer: &Tokenizer,
+        positions: Option<&[usize]>,
+        keep_groups: bool,
+    ) -> Vec<Suggestion> {
         let refs: Vec<&Token> = tokens.iter().collect();
         let mut suggestions = Vec::new();
 
-        for graph in self.engine.get_matches(&refs, self.start, self.end) {
-            let start_group = graph
-                .by_id(self.start)
-                .unwrap_or_else(|| panic!("{} group must exist in graph: {}", self.id, self.start));
-            let end_group = graph.by_id(self.end - 1).unwrap_or_else(|| {
-                panic!("{} group must exist in graph: {}", self.id, self.end - 1)
-            });
-
-            let replacements: Vec<String> = self
+        for graph in self
+            .engine
+            .get_matches_at(&refs, self.start, self.end, positions)
+        {
+            // immunized tokens must not be part of the marked range
+            if (self.start..self.end)
+                .filter_map(|group_idx| graph.by_id(group_idx))
+                .any(|group| group.tokens(graph.tokens()).iter().any(|x| x.immunized))
+            {
+                continue;
+            }
+
+            if let Some(filter) = &self.filter {
+                if !filter.keep(&graph, tokenizer) {
+                    continue;
+                }
+            }
+
+            let (marked_start, end) = self.engine.marked_span(&graph, self.start, self.end);
+            // e. g. the marker only covers optional tokens which did not match, there is no text to replace
+            if marked_start == end {
+                continue;
+            }
+
+            let mut replacements: Vec<String> = Vec::new();
+            for replacement in self
                 .suggesters
                 .iter()
-                .filter_map(|x| x.apply(&graph, tokenizer, self.start, self.end))
-                .collect();
+                .flat_map(|x| x.apply(&graph, tokenizer, self.start, self.end))
+            {
+                if !replacements.contains(&replacement) {
+                    replacements.push(replacement);
+                }
+            }
 
             let start = if replacements
                 .iter()
@@ -334,29 +418,32 @@ impl Rule {
                 if idx > 0 {
                     tokens[idx - 1].char_span.1
                 } else {
-                    start_group.char_span.0
+                    marked_start
                 }
             } else {
-                start_group.char_span.0
+                marked_start
             };
-            let end = end_group.char_span.1;
 
             // fix e. g. "Super , dass"
-            let replacements: Vec<String> = replacements
+            let mut replacements: Vec<String> = replacements
                 .into_iter()
                 .map(|x| utils::fix_nospace_chars(&x))
                 .collect();
+            tokenizer.tagger().sort_by_frequency(&mut replacements);
 
             if !replacements.is_empty() {
                 suggestions.push(Suggestion {
-                    message: self
-                        .message
-                        .apply(&graph, tokenizer, self.start, self.end)
-                        .expect("Rules must have a message."),
+                    message: self.message.apply_message(&graph, tokenizer, self.start),
                     source: self.id.to_string(),
+                    short: self.short.clone(),
                     start,
                     end,
                     replacements,
+                    groups: if keep_groups {
+                        Some(graph.matched_groups())
+                    } else {
+                        None
+                    },
                 });
             }
         }
@@ -364,10 +451,47 @@ impl Rule {
         suggestions
     }
 
+    /// Explains at which token and part the pattern of this rule stops matching the tokens of a sentence, for
+    /// developing rules. `tokens` are finalized like the tokens given to rules e. g. from
+    /// [finalize][crate::tokenizer::finalize]. Tries every start token without the index of the [Rules][crate::Rules],
+    /// filters and immunization are not considered.
+    pub fn explain(&self, tokens: &[Token]) -> MatchExplanation {
+        let refs: Vec<&Token> = tokens.iter().collect();
+
+        let (parts, attempts) = match &self.engine {
+            Engine::Token(engine) => (Some(engine.composition.parts.len()), engine.explain(&refs)),
+            Engine::Text(..) => (None, Vec::new()),
+        };
+
+        MatchExplanation {
+            rule_id: self.id.clone(),
+            parts,
+            attempts,
+        }
+    }
+
     /// Grammar rules always have at least one example associated with them.
     /// This method checks whether the correct action is taken in the examples.
     pub fn test(&self, tokenizer: &Tokenizer) -> bool {
-        let mut passes = Vec::new();
+        let failures = self.validate(tokenizer);
+
+        for failure in &failures {
+            warn!(
+                "Rule {}: test \"{}\" failed. Expected: {:#?}. Found: {:#?}.",
+                self.id, failure.text, failure.expected, failure.found
+            );
+        }
+
+        failures.is_empty()
+    }
+
+    /// Checks the examples of this rule, returning the ones for which the rule does not behave as expected.
+    /// An example fails if the rule returns more than one suggestion, or:
+    /// * if it is correct and the rule returns a suggestion.
+    /// * if it is incorrect and the rule does not return a suggestion with the range of the marker
+    ///   and (if the example has a correction) at least one of the expected replacements.
+    pub fn validate(&self, tokenizer: &Tokenizer) -> Vec<ExampleFailure> {
+        let mut failures = Vec::new();
 
         for test in self.examples.iter() {
             let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize(&test.text())));
@@ -377,27 +501,167 @@ impl Rule {
             let pass = if suggestions.len() > 1 {
                 false
             } else {
-                match test.suggestion() {
-                    Some(correct_suggestion) => {
-                        suggestions.len() == 1 && correct_suggestion == &suggestions[0]
+                match (test.suggestion(), suggestions.first()) {
+                    (Some(expected), Some(found)) => {
+                        expected.start == found.start
+                            && expected.end == found.end
+                            && (expected.replacements.is_empty()
+                                || expected
+                                    .replacements
+                                    .iter()
+                                    .any(|x| found.replacements.contains(x)))
                     }
-                    None => suggestions.is_empty(),
+                    (Some(_), None) => false,
+                    (None, found) => found.is_none(),
                 }
             };
 
             if !pass {
-                warn!(
-                    "Rule {}: test \"{}\" failed. Expected: {:#?}. Found: {:#?}.",
-                    self.id,
-                    test.text(),
-                    test.suggestion(),
-                    suggestions
-                );
+                failures.push(ExampleFailure {
+                    text: test.text().to_string(),
+                    expected: test.suggestion().cloned(),
+                    found: suggestions,
+                });
             }
-
-            passes.push(pass);
         }
 
-        passes.iter().all(|x| *x)
+        failures
+    }
+}
+
+/// An example for which a grammar rule does not behave as expected, see [Rule::validate].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExampleFailure {
+    /// The text of the example.
+    pub text: String,
+    /// The expected suggestion. `None` if the rule should not return any suggestion.
+    pub expected: Option<Suggestion>,
+    /// The suggestions the rule returned.
+    pub found: Vec<Suggestion>,
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use crate::compile::testing::{assert_examples_pass, compile_rules_with_errors, setup};
+
+    #[test]
+    fn suggestions_cover_the_marked_tokens() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="OPTIONAL_IN_MARKER" name="Optional token in marker">
+            <pattern>
+                <token>a</token>
+                <marker>
+                    <token min="0">very</token>
+                    <token>big</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>large</suggestion>?</message>
+            <example correction="large">It is a <marker>big</marker> house.</example>
+            <example correction="large">It is a <marker>very big</marker> house.</example>
+        </rule>
+        <rule id="SKIP_IN_MARKER" name="Skip in marker">
+            <pattern>
+                <marker>
+                    <token skip="-1">if</token>
+                </marker>
+                <token>then</token>
+            </pattern>
+            <message>Did you mean <suggestion>when</suggestion>?</message>
+            <example correction="when">Ask <marker>if</marker> it rains then.</example>
+        </rule>
+        <rule id="MARK_FROM" name="Mark from">
+            <pattern mark_from="1">
+                <token>could</token>
+                <token>of</token>
+            </pattern>
+            <message>Did you mean <suggestion>have</suggestion>?</message>
+            <example correction="have">He could <marker>of</marker> known.</example>
+        </rule>
+        <rule id="MARK_TO" name="Mark to">
+            <pattern mark_to="-1">
+                <token>alot</token>
+                <token>of</token>
+            </pattern>
+            <message>Did you mean <suggestion>a lot</suggestion>?</message>
+            <example correction="a lot">There is <marker>alot</marker> of it.</example>
+        </rule>
+        <rule id="EMPTY_MARKER" name="Empty marker">
+            <pattern>
+                <token>so</token>
+                <marker>
+                    <token min="0">very</token>
+                </marker>
+                <token>much</token>
+            </pattern>
+            <message>Did you mean <suggestion>really</suggestion>?</message>
+            <example correction="really">I like it so <marker>very</marker> much.</example>
+        </rule>
+        <rule id="MARKER_AND_MARK_FROM" name="Marker and mark from">
+            <pattern mark_from="1">
+                <token>could</token>
+                <marker>
+                    <token>of</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>have</suggestion>?</message>
+            <example correction="have">He could <marker>of</marker> known.</example>
+        </rule>
+        <rule id="MARK_OUT_OF_RANGE" name="Mark out of range">
+            <pattern mark_from="1" mark_to="-1">
+                <token>could</token>
+                <token>of</token>
+            </pattern>
+            <message>Did you mean <suggestion>have</suggestion>?</message>
+            <example correction="have">He could <marker>of</marker> known.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let (rules, errors) = compile_rules_with_errors(grammar, &tokenizer);
+
+        let errors: Vec<_> = errors.iter().map(|x| (x.kind(), x.rule_id())).collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Unexpected", Some("MARKER_AND_MARK_FROM")),
+                ("Unexpected", Some("MARK_OUT_OF_RANGE"))
+            ]
+        );
+        assert_examples_pass(&rules, &tokenizer);
+
+        let spans = |text: &str| -> Vec<(usize, usize, Vec<String>)> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .map(|x| (x.start, x.end, x.replacements))
+                .collect()
+        };
+        // the whitespace before the optional token which did not match is not replaced
+        assert_eq!(
+            spans("It is a big house."),
+            vec![(8, 11, vec!["large".into()])]
+        );
+        assert_eq!(
+            spans("It is a very big house."),
+            vec![(8, 16, vec!["large".into()])]
+        );
+        // the skipped tokens are not part of the marker
+        assert_eq!(
+            spans("Ask if it rains then."),
+            vec![(4, 6, vec!["when".into()])]
+        );
+        assert_eq!(
+            spans("He could of known."),
+            vec![(9, 11, vec!["have".into()])]
+        );
+        assert_eq!(
+            spans("There is alot of it."),
+            vec![(9, 13, vec!["a lot".into()])]
+        );
+        // there is nothing to replace if no marked token matched
+        assert!(spans("I like it so much.").is_empty());
     }
 }
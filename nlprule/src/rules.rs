@@ -1,55 +1,551 @@
 //! Sets of grammatical error correction rules.
 
-use crate::tokenizer::Tokenizer;
+use crate::tokenizer::{AnalyzeOptions, Tokenizer};
 use crate::types::*;
-use crate::utils::parallelism::MaybeParallelRefIterator;
-use crate::{rule::Rule, tokenizer::finalize};
+use crate::utils::parallelism::{
+    MaybeParallelIterator, MaybeParallelRefIterator, Parallelism, ParallelismConfig,
+};
+use crate::{
+    rule::{engine::composition::DEFAULT_MATCH_BUDGET, ExampleFailure, Rule},
+    tokenizer::{finalize, split_sentences},
+    Compression, DuplicateIdPolicy, Error, ErrorPolicy, FormatVersion, Header,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     fs::File,
-    io::{BufReader, Read},
+    io::{BufReader, Read, Write},
     path::Path,
 };
+use {aho_corasick::AhoCorasick, once_cell::sync::OnceCell, rayon_cond::CondIterator};
 
 /// Options for a rule set.
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct RulesOptions {
-    /// Whether to allow errors while constructing the rules.
-    pub allow_errors: bool,
+    /// What to do with rules which can not be compiled.
+    pub error_policy: ErrorPolicy,
     /// Grammar Rule IDs to use in this set.
-    #[serde(default)]
     pub ids: Vec<String>,
     /// Grammar Rule IDs to ignore in this set.
-    #[serde(default)]
     pub ignore_ids: Vec<String>,
+    /// Whether to drop the metadata of the rules at compile time to reduce the size of the binary,
+    /// see [Rules::strip_metadata].
+    pub strip_metadata: bool,
+    /// What to do if rules in different files have the same ID when compiling from multiple files.
+    pub duplicate_ids: DuplicateIdPolicy,
 }
 
 impl Default for RulesOptions {
     fn default() -> Self {
         RulesOptions {
-            allow_errors: true,
+            error_policy: ErrorPolicy::WarnAboveThreshold(0),
             ids: Vec::new(),
             ignore_ids: Vec::new(),
+            strip_metadata: false,
+            duplicate_ids: DuplicateIdPolicy::default(),
         }
     }
 }
 
-/// A set of grammatical error correction rules.
+impl RulesOptions {
+    /// Creates a builder starting from the default options.
+    pub fn builder() -> RulesOptionsBuilder {
+        RulesOptionsBuilder::default()
+    }
+}
+
+/// The result of checking a rule against its examples, see [Rules::validate].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleValidation {
+    /// The ID of the rule.
+    pub id: String,
+    /// The examples for which the rule does not behave as expected.
+    pub failures: Vec<ExampleFailure>,
+}
+
+impl RuleValidation {
+    /// Whether the rule behaves as expected for all of its examples.
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The result of checking all rules of a rule set against their examples, see [Rules::validate].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// One entry per rule, in the order of [Rules::rules].
+    pub rules: Vec<RuleValidation>,
+}
+
+impl ValidationReport {
+    /// The rules which behave as expected for all of their examples.
+    pub fn passed(&self) -> impl Iterator<Item = &RuleValidation> {
+        self.rules.iter().filter(|x| x.passed())
+    }
+
+    /// The rules which do not behave as expected for at least one of their examples.
+    pub fn failed(&self) -> impl Iterator<Item = &RuleValidation> {
+        self.rules.iter().filter(|x| !x.passed())
+    }
+}
+
+/// Removes the failures of examples with a correction from the validations of copies of one rule
+/// if another copy finds the correction.
+fn merge_copies(copies: &mut [RuleValidation]) {
+    if copies.len() < 2 {
+        return;
+    }
+
+    let found_by_any = |text: &str| {
+        copies
+            .iter()
+            .any(|copy| !copy.failures.iter().any(|x| x.text == text))
+    };
+    let keep: Vec<Vec<bool>> = copies
+        .iter()
+        .map(|copy| {
+            copy.failures
+                .iter()
+                .map(|x| x.expected.is_none() || !found_by_any(&x.text))
+                .collect()
+        })
+        .collect();
+
+    for (copy, keep) in copies.iter_mut().zip(keep) {
+        let mut keep = keep.into_iter();
+        copy.failures.retain(|_| keep.next().unwrap_or(true));
+    }
+}
+
+/// How often matching rules was stopped because it exceeded the match budget, see [Rules::set_match_budget],
+/// and how often regexes were matched at runtime.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchStats {
+    /// The number of times matching any rule at a position exceeded the budget.
+    pub overflows: usize,
+    /// The IDs of the rules which exceeded the budget and how often they did, in the order of [Rules::rules].
+    pub rules: Vec<(String, usize)>,
+    /// The number of times a regex of a token was matched against a text because it matches more words than
+    /// the maximum set size at compile time, so the matched words are not stored in the binary. Helps to tune
+    /// the maximum, see `CompileOptions::max_regex_set_size`. Only counted if enabled with
+    /// [Rules::set_count_regex_fallbacks], zero otherwise.
+    pub regex_fallbacks: usize,
+}
+
+/// Builder for [RulesOptions]. See [RulesOptions::builder].
+#[derive(Default)]
+pub struct RulesOptionsBuilder {
+    options: RulesOptions,
+}
+
+impl RulesOptionsBuilder {
+    /// Sets [RulesOptions::error_policy].
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.options.error_policy = error_policy;
+        self
+    }
+
+    /// Sets [RulesOptions::ids].
+    pub fn ids(mut self, ids: Vec<String>) -> Self {
+        self.options.ids = ids;
+        self
+    }
+
+    /// Sets [RulesOptions::ignore_ids].
+    pub fn ignore_ids(mut self, ignore_ids: Vec<String>) -> Self {
+        self.options.ignore_ids = ignore_ids;
+        self
+    }
+
+    /// Sets [RulesOptions::strip_metadata].
+    pub fn strip_metadata(mut self, strip_metadata: bool) -> Self {
+        self.options.strip_metadata = strip_metadata;
+        self
+    }
+
+    /// Sets [RulesOptions::duplicate_ids].
+    pub fn duplicate_ids(mut self, duplicate_ids: DuplicateIdPolicy) -> Self {
+        self.options.duplicate_ids = duplicate_ids;
+        self
+    }
+
+    /// Validates and returns the options.
+    pub fn build(self) -> Result<RulesOptions, Error> {
+        crate::utils::validate_ids(&self.options.ids, &self.options.ignore_ids)?;
+        Ok(self.options)
+    }
+}
+
+/// Words for which no suggestions are made, like the `ignore.txt` and `spelling.txt` word lists of LanguageTool.
+/// A suggestion is suppressed if the text it replaces is exactly one of the words.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct IgnoreWords {
+    pub(crate) words: DefaultHashSet<String>,
+    /// Words which are only ignored by the rules in the category with the key as ID.
+    pub(crate) category_words: DefaultHashMap<String, DefaultHashSet<String>>,
+}
+
+impl IgnoreWords {
+    /// Adds a word which is ignored by all rules or, if `category_id` is set, by the rules in that category.
+    pub fn insert(&mut self, word: &str, category_id: Option<&str>) {
+        match category_id {
+            Some(category_id) => self
+                .category_words
+                .entry(category_id.to_string())
+                .or_default()
+                .insert(word.to_string()),
+            None => self.words.insert(word.to_string()),
+        };
+    }
+
+    /// Whether rules in the category with the given ID ignore `word`.
+    pub fn contains(&self, word: &str, category_id: &str) -> bool {
+        self.words.contains(word)
+            || matches!(self.category_words.get(category_id), Some(x) if x.contains(word))
+    }
+
+    /// Whether no words are ignored.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.category_words.values().all(|x| x.is_empty())
+    }
+}
+
+/// Folds `text` into `out` for the [Prefilter]: Characters are lowercased and characters equal to ASCII
+/// letters ignoring case (e. g. `ß` and `ss`) are replaced by them. So if an ASCII string equals
+/// a part of `text` ignoring case, the folded string is contained in the folded text.
+pub(crate) fn fold_into(text: &str, out: &mut String) {
+    for c in text.chars() {
+        if c.is_ascii() {
+            out.push(c.to_ascii_lowercase());
+            continue;
+        }
+
+        match c {
+            'ß' | 'ẞ' => out.push_str("ss"),
+            'ſ' => out.push('s'),
+            'ﬀ' => out.push_str("ff"),
+            'ﬁ' => out.push_str("fi"),
+            'ﬂ' => out.push_str("fl"),
+            'ﬃ' => out.push_str("ffi"),
+            'ﬄ' => out.push_str("ffl"),
+            'ﬅ' | 'ﬆ' => out.push_str("st"),
+            // these fold to an ASCII letter followed by a combining mark
+            'ǰ' => out.push('j'),
+            'ẖ' => out.push('h'),
+            'ẗ' => out.push('t'),
+            'ẘ' => out.push('w'),
+            'ẙ' => out.push('y'),
+            'ẚ' => out.push('a'),
+            c => out.extend(c.to_lowercase()),
+        }
+    }
+}
+
+/// Skips the rules which can not match a sentence because none of the literals they require occur in it.
+/// The literals are extracted at compile time from the string matchers and regex prefixes of the rules,
+/// rules without literals are always checked.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Prefilter {
+    /// The literals, folded with [fold_into].
+    pub(crate) literals: Vec<String>,
+    /// For each literal, the indices of the rules which require it.
+    pub(crate) literal_rules: Vec<Vec<usize>>,
+    /// For each rule, whether it is only checked if one of its literals occurs in the sentence.
+    pub(crate) filtered: Vec<bool>,
+    /// Built on first use from the literals so it does not have to be serialized.
+    #[serde(skip)]
+    pub(crate) automaton: OnceCell<AhoCorasick>,
+}
+
+impl Prefilter {
+    /// For each of the `n_rules` rules, whether it can match the tokens. The text of all tokens is
+    /// searched once for all literals.
+    pub(crate) fn candidates(&self, tokens: &[Token], n_rules: usize) -> Vec<bool> {
+        let mut candidates: Vec<_> = (0..n_rules)
+            .map(|i| !self.filtered.get(i).copied().unwrap_or(false))
+            .collect();
+
+        if self.literals.is_empty() {
+            return candidates;
+        }
+
+        let automaton = self.automaton.get_or_init(|| {
+            AhoCorasick::new(&self.literals).expect("literals must be valid for aho-corasick")
+        });
+
+        let mut text = String::new();
+        for token in tokens {
+            fold_into(token.word.text.as_ref(), &mut text);
+            // tokens are separated so literals spanning multiple tokens are rarely found
+            text.push('\n');
+        }
+
+        for found in automaton.find_overlapping_iter(&text) {
+            for i in &self.literal_rules[found.pattern().as_usize()] {
+                candidates[*i] = true;
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Finds the positions in a sentence at which the rules can start to match. A rule whose first part is required
+/// and only matches tokens with some word IDs or some part-of-speech tags (its anchor) can only start at such tokens,
+/// rules without anchor can start anywhere.
 #[derive(Serialize, Deserialize, Default)]
+pub(crate) struct RuleIndex {
+    /// For each word ID, the rules whose anchor includes it.
+    pub(crate) words: DefaultHashMap<u32, Vec<usize>>,
+    /// For each part-of-speech tag ID, the rules whose anchor includes it.
+    pub(crate) tags: DefaultHashMap<u16, Vec<usize>>,
+    /// The rules whose anchor includes words. They can start at every token without word ID
+    /// since the text of such a token is compared instead.
+    pub(crate) unknown_words: Vec<usize>,
+    /// For each rule, whether it has an anchor.
+    pub(crate) anchored: Vec<bool>,
+}
+
+impl RuleIndex {
+    /// For each of the `n_rules` rules, the positions at which it can start to match the tokens in ascending order.
+    /// `None` if the rule can start anywhere.
+    pub(crate) fn starts(&self, tokens: &[Token], n_rules: usize) -> Vec<Option<Vec<usize>>> {
+        let mut starts: Vec<_> = (0..n_rules)
+            .map(|i| {
+                if self.anchored.get(i).copied().unwrap_or(false) {
+                    Some(Vec::new())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let mut push = |rules: &[usize], position: usize| {
+            for i in rules {
+                if let Some(positions) = &mut starts[*i] {
+                    // a rule can be found through multiple readings of the same token
+                    if positions.last() != Some(&position) {
+                        positions.push(position);
+                    }
+                }
+            }
+        };
+
+        for (position, token) in tokens.iter().enumerate() {
+            match token.word.text.id() {
+                Some(id) => {
+                    if let Some(rules) = self.words.get(id) {
+                        push(rules, position);
+                    }
+                }
+                // a non-negated string never matches an empty text e. g. of the sentence start
+                None if !token.word.text.as_ref().is_empty() => push(&self.unknown_words, position),
+                None => {}
+            }
+
+            for data in &token.word.tags {
+                if let Some(rules) = self.tags.get(data.pos.id()) {
+                    push(rules, position);
+                }
+            }
+        }
+
+        starts
+    }
+}
+
+/// A set of grammatical error correction rules.
+///
+/// The rules do not contain a tagger. They refer to words and part-of-speech tags by their ID in the tagger
+/// they were compiled with and use the tagger of the tokenizer they are applied with, so a tokenizer and rules pair
+/// holds the tagger once. [Rules::load] checks that the IDs belong to the tagger of the tokenizer.
+///
+/// Rules are `Send + Sync`, so they can be loaded once and shared between threads e. g. in a static `OnceCell`.
+#[derive(Serialize, Deserialize)]
 pub struct Rules {
+    pub(crate) format_version: FormatVersion,
+    pub(crate) header: Option<Header>,
     pub(crate) rules: Vec<Rule>,
+    pub(crate) ignore_words: IgnoreWords,
+    pub(crate) prefilter: Prefilter,
+    pub(crate) index: RuleIndex,
+    #[serde(skip)]
+    pub(crate) parallelism: ParallelismConfig,
+    #[serde(skip, default = "default_match_budget")]
+    pub(crate) match_budget: usize,
+}
+
+fn default_match_budget() -> usize {
+    DEFAULT_MATCH_BUDGET
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            format_version: FormatVersion,
+            header: None,
+            rules: Vec::new(),
+            ignore_words: IgnoreWords::default(),
+            prefilter: Prefilter::default(),
+            index: RuleIndex::default(),
+            parallelism: ParallelismConfig::default(),
+            match_budget: DEFAULT_MATCH_BUDGET,
+        }
+    }
 }
 
 impl Rules {
-    /// Creates a new rules set from a file.
-    pub fn new<P: AsRef<Path>>(p: P) -> bincode::Result<Self> {
-        let reader = BufReader::new(File::open(p).unwrap());
-        bincode::deserialize_from(reader)
+    /// Creates a new rules set from a file. Fails with [Error::Binary] if the file is not a rules binary this
+    /// version of nlprule can read. Does not check that the rules belong to the tokenizer they are used with,
+    /// see [Rules::load].
+    pub fn new<P: AsRef<Path>>(p: P) -> Result<Self, Error> {
+        Self::new_from(File::open(p)?)
+    }
+
+    /// Creates a new rules set from a reader. Does not check that the rules belong to the tokenizer
+    /// they are used with, see [Rules::load_from].
+    pub fn new_from<R: Read>(reader: R) -> Result<Self, Error> {
+        crate::read_binary(reader)
+    }
+
+    /// Creates a new rules set from bytes e. g. a binary embedded with `include_bytes!`. Uncompressed binaries
+    /// are deserialized without copying the bytes. Does not check that the rules belong to the tokenizer they are
+    /// used with, see [Rules::check_tokenizer].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        crate::read_binary_bytes(bytes)
+    }
+
+    /// Creates a new rules set from a file like [Rules::new], but faster: the file is memory-mapped instead of
+    /// read and the regexes are compiled on first use instead of while loading. The suggestions are the same.
+    /// Regexes of binaries built with the other regex backend are still compiled while loading, so they fail here.
+    /// The file must not be modified while loading. Does not check that the rules belong to the tokenizer they are used
+    /// with, see [Rules::check_tokenizer].
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap<P: AsRef<Path>>(p: P) -> Result<Self, Error> {
+        crate::read_binary_mmap(p.as_ref())
+    }
+
+    /// Writes the rules as binary with the `compression`. The compression is detected when loading the binary,
+    /// see [Rules::new_from].
+    pub fn to_writer_compressed<W: Write>(
+        &self,
+        writer: W,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        crate::write_binary(writer, self, compression)
+    }
+
+    /// Drops the metadata which is not needed to find suggestions to reduce the size of the binary: the names,
+    /// messages, URLs and short messages of the rules and the names and types of their categories. IDs, category IDs
+    /// and examples are kept. Suggestions have the same spans and replacements but an empty message.
+    ///
+    /// [RulesOptions::strip_metadata] does the same at compile time. Binaries compiled without it can be stripped by
+    /// loading, stripping and writing them again with [Rules::to_writer_compressed].
+    pub fn strip_metadata(&mut self) {
+        for rule in &mut self.rules {
+            rule.strip_metadata();
+        }
+    }
+
+    /// Creates a new rules set from a file. Fails with [Error::IncompatibleBinaries] if the rules were not
+    /// compiled with the tagger of `tokenizer` e. g. because they are for another language.
+    pub fn load<P: AsRef<Path>>(p: P, tokenizer: &Tokenizer) -> Result<Self, Error> {
+        Rules::load_from(BufReader::new(File::open(p)?), tokenizer)
+    }
+
+    /// Creates a new rules set from a reader, see [Rules::load].
+    pub fn load_from<R: Read>(reader: R, tokenizer: &Tokenizer) -> Result<Self, Error> {
+        let rules = Rules::new_from(reader)?;
+        rules.check_tokenizer(tokenizer)?;
+        Ok(rules)
+    }
+
+    /// Fails with [Error::IncompatibleBinaries] if the rules were not compiled with the tagger of `tokenizer`.
+    /// Rules or tokenizers which were not compiled (e. g. the default) are not checked.
+    pub fn check_tokenizer(&self, tokenizer: &Tokenizer) -> Result<(), Error> {
+        Header::check(tokenizer.header(), self.header())
+    }
+
+    /// What the rules were compiled from, `None` if they were not compiled.
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// How checking is parallelized. See [Rules::set_parallelism].
+    pub fn parallelism(&self) -> Parallelism {
+        self.parallelism.parallelism()
+    }
+
+    /// Sets how checking is parallelized for this rule set. Defaults to [Parallelism::Auto].
+    /// Fails if the thread pool for [Parallelism::Threads] can not be created.
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) -> Result<(), Error> {
+        self.parallelism = ParallelismConfig::new(parallelism)?;
+        Ok(())
+    }
+
+    /// The maximum number of steps of matching a rule at one position, where a step is trying to match one token.
+    /// See [Rules::set_match_budget].
+    pub fn match_budget(&self) -> usize {
+        self.match_budget
+    }
+
+    /// Sets the maximum number of steps of matching a rule at one position. Defaults to [DEFAULT_MATCH_BUDGET]
+    /// which no rule needs on natural text. Rules with many optional tokens can backtrack exponentially on some
+    /// inputs, the budget bounds the time they take: if it is exceeded, the rule does not match at that position
+    /// and the overflow is counted in the [Rules::match_stats]. If an antipattern exceeds it, the rule does not
+    /// match from that position on, since the antipattern could have blocked the match.
+    pub fn set_match_budget(&mut self, budget: usize) {
+        self.match_budget = budget;
+        for rule in &mut self.rules {
+            rule.engine.set_match_budget(budget);
+        }
+    }
+
+    /// Sets whether the regexes of the alternatives of a token are matched at once with a set instead of one by
+    /// one. Defaults to `true`. Only has an effect with the `regex-fancy` backend since Oniguruma has no sets.
+    /// The matches are the same either way.
+    pub fn set_batch_regexes(&mut self, batch: bool) {
+        for rule in &mut self.rules {
+            rule.engine.set_batch_regexes(batch);
+        }
+    }
+
+    /// Sets whether the [MatchStats::regex_fallbacks] are counted. Defaults to `false`: counting writes to a
+    /// counter shared by all threads on every fallback, which slows down checking text in parallel. Disabling
+    /// resets the count.
+    pub fn set_count_regex_fallbacks(&mut self, count: bool) {
+        for rule in &mut self.rules {
+            rule.engine.set_count_regex_fallbacks(count);
+        }
+    }
+
+    /// How often matching rules exceeded the match budget and regexes were matched at runtime since the rules
+    /// were loaded or the stats were reset.
+    pub fn match_stats(&self) -> MatchStats {
+        let rules: Vec<_> = self
+            .rules
+            .iter()
+            .map(|rule| (rule.id().to_string(), rule.engine.match_overflows()))
+            .filter(|(_, overflows)| *overflows > 0)
+            .collect();
+
+        MatchStats {
+            overflows: rules.iter().map(|(_, overflows)| overflows).sum(),
+            rules,
+            regex_fallbacks: self
+                .rules
+                .iter()
+                .map(|rule| rule.engine.regex_fallbacks())
+                .sum(),
+        }
     }
 
-    /// Creates a new rules set from a reader.
-    pub fn new_from<R: Read>(reader: R) -> bincode::Result<Self> {
-        bincode::deserialize_from(reader)
+    /// Resets the [Rules::match_stats].
+    pub fn reset_match_stats(&self) {
+        for rule in &self.rules {
+            rule.engine.reset_match_overflows();
+            rule.engine.reset_regex_fallbacks();
+        }
     }
 
     /// All rules ordered by priority.
@@ -57,45 +553,126 @@ impl Rules {
         &self.rules
     }
 
-    /// Finds a rule by ID.
+    /// The words for which no suggestions are made.
+    pub fn ignore_words(&self) -> &IgnoreWords {
+        &self.ignore_words
+    }
+
+    /// Sets the words for which no suggestions are made, replacing the ones from compile time.
+    pub fn set_ignore_words(&mut self, ignore_words: IgnoreWords) {
+        self.ignore_words = ignore_words;
+    }
+
+    /// Removes the index of the positions at which the rules can start to match, so every rule is tried at
+    /// every position. Only useful to measure the effect of the index.
+    #[doc(hidden)]
+    pub fn clear_index(&mut self) {
+        self.index = RuleIndex::default();
+    }
+
+    /// Finds a rule by ID. The copies of a rule compiled for alternatives between phrases have the same ID,
+    /// the first one is returned.
     pub fn rule(&self, id: &str) -> Option<&Rule> {
         self.rules.iter().find(|x| x.id() == id)
     }
 
+    /// Checks all rules against their examples, see [Rule::validate].
+    /// Rules compiled once for each alternative between phrases have the same ID, an example with a correction
+    /// only has to be found by one of them.
+    pub fn validate(&self, tokenizer: &Tokenizer) -> ValidationReport {
+        let mut rules: Vec<RuleValidation> = self.parallelism.install(|| {
+            self.rules
+                .maybe_par_iter_with(&self.parallelism)
+                .map(|rule| RuleValidation {
+                    id: rule.id().to_string(),
+                    failures: rule.validate(tokenizer),
+                })
+                .collect()
+        });
+
+        // the copies of a rule are next to each other
+        let mut start = 0;
+        while start < rules.len() {
+            let end = start
+                + rules[start..]
+                    .iter()
+                    .take_while(|x| x.id == rules[start].id)
+                    .count();
+            merge_copies(&mut rules[start..end]);
+            start = end;
+        }
+
+        ValidationReport { rules }
+    }
+
     /// Compute the suggestions for the given tokens by checking all rules.
+    /// The tokens are checked as one sentence, see [Rules::suggest] to check each sentence of a text separately.
     pub fn apply(&self, tokens: &[Token], tokenizer: &Tokenizer) -> Vec<Suggestion> {
+        self.apply_with(tokens, tokenizer, &AnalyzeOptions::default())
+    }
+
+    /// Compute the suggestions for the given tokens by checking all rules with the given options.
+    /// If chunking is turned off, rules which need chunk information are skipped.
+    pub fn apply_with(
+        &self,
+        tokens: &[Token],
+        tokenizer: &Tokenizer,
+        options: &AnalyzeOptions,
+    ) -> Vec<Suggestion> {
         if tokens.is_empty() {
             return Vec::new();
         }
 
-        let mut output: Vec<(usize, Suggestion)> = self
-            .rules
-            .maybe_par_iter()
-            .enumerate()
-            .filter(|(_, x)| x.on())
-            .map(|(i, rule)| {
-                let mut output = Vec::new();
+        let candidates = self.prefilter.candidates(tokens, self.rules.len());
+        let starts = self.index.starts(tokens, self.rules.len());
 
-                for suggestion in rule.apply(tokens, tokenizer) {
-                    output.push((i, suggestion));
-                }
+        let mut output: Vec<(usize, Suggestion)> = self.parallelism.install(|| {
+            self.rules
+                .maybe_par_iter_with(&self.parallelism)
+                .enumerate()
+                .filter(|(i, x)| candidates[*i] && x.on() && (options.chunk || !x.uses_chunker()))
+                .map(|(i, rule)| {
+                    let mut output = Vec::new();
 
-                output
-            })
-            .flatten()
-            .collect();
+                    for suggestion in
+                        rule.apply_at(tokens, tokenizer, starts[i].as_deref(), options.keep_groups)
+                    {
+                        if !self.ignore_words.is_empty() {
+                            let text: String = tokens[0]
+                                .text
+                                .chars()
+                                .skip(suggestion.start)
+                                .take(suggestion.end - suggestion.start)
+                                .collect();
+
+                            if self.ignore_words.contains(&text, rule.category_id()) {
+                                continue;
+                            }
+                        }
+
+                        output.push((i, suggestion));
+                    }
+
+                    output
+                })
+                .flatten()
+                .collect()
+        });
 
         output.sort_by(|(ia, a), (ib, b)| a.start.cmp(&b.start).then_with(|| ib.cmp(ia)));
 
-        let mut mask = vec![false; tokens[0].text.chars().count()];
+        // the mask only has to cover the suggestions, not the entire text
+        let offset = output.iter().map(|(_, x)| x.start).min().unwrap_or(0);
+        let end = output.iter().map(|(_, x)| x.end).max().unwrap_or(0);
+        let mut mask = vec![false; end.saturating_sub(offset)];
 
         output
             .into_iter()
             .filter_map(|(_, suggestion)| {
-                if mask[suggestion.start..suggestion.end].iter().all(|x| !x) {
-                    mask[suggestion.start..suggestion.end]
-                        .iter_mut()
-                        .for_each(|x| *x = true);
+                let range = (suggestion.start - offset)..(suggestion.end - offset);
+
+                if mask[range.clone()].iter().all(|x| !x) {
+                    mask[range].iter_mut().for_each(|x| *x = true);
                     Some(suggestion)
                 } else {
                     None
@@ -105,9 +682,39 @@ impl Rules {
     }
 
     /// Compute the suggestions for a text by checking all rules.
+    ///
+    /// Each sentence is checked separately like in LanguageTool: rules do not match across sentences and
+    /// every sentence starts with `SENT_START`. This can give other suggestions than [Rules::apply] on the
+    /// tokens of the whole text.
     pub fn suggest(&self, text: &str, tokenizer: &Tokenizer) -> Vec<Suggestion> {
-        let tokens = tokenizer.disambiguate(tokenizer.tokenize(text));
-        self.apply(&finalize(tokens), tokenizer)
+        self.suggest_with(text, tokenizer, &AnalyzeOptions::default())
+    }
+
+    /// Compute the suggestions for a text by checking all rules with the given options.
+    /// Each sentence is disambiguated and checked separately, in parallel for long texts.
+    pub fn suggest_with(
+        &self,
+        text: &str,
+        tokenizer: &Tokenizer,
+        options: &AnalyzeOptions,
+    ) -> Vec<Suggestion> {
+        let sentences = split_sentences(tokenizer.tokenize_with(text, options));
+        let iter = if sentences.len() >= options.min_parallel_sentences {
+            sentences.into_maybe_par_iter_with(&self.parallelism)
+        } else {
+            CondIterator::from_serial(sentences)
+        };
+
+        self.parallelism.install(|| {
+            iter.map(|sentence| {
+                let tokens = tokenizer.disambiguate_with(sentence, options);
+                self.apply_with(&finalize(tokens), tokenizer, options)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+            .collect()
+        })
     }
 
     /// Correct a text by first tokenizing, then finding all suggestions and choosing the first replacement of each suggestion.
@@ -135,3 +742,951 @@ pub fn apply_suggestions(text: &str, suggestions: &[Suggestion]) -> String {
 
     chars.into_iter().collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchStats, Prefilter, Rules, RulesOptions};
+    use crate::{Error, ErrorPolicy, Tokenizer};
+
+    #[test]
+    fn options_can_be_built_and_validated() {
+        let options = RulesOptions::builder()
+            .error_policy(ErrorPolicy::Fail)
+            .ids(vec!["A".into()])
+            .build()
+            .unwrap();
+        assert_eq!(options.error_policy, ErrorPolicy::Fail);
+        assert_eq!(options.ids, vec!["A"]);
+
+        let result = RulesOptions::builder()
+            .ids(vec!["A".into(), "B".into()])
+            .ignore_ids(vec!["B".into()])
+            .build();
+        assert!(matches!(result, Err(Error::ConflictingIds(id)) if id == "B"));
+    }
+
+    /// Checks all examples of the English rules with the default match budget.
+    /// Needs the binaries in `storage`, see `BUILD.md`.
+    #[test]
+    #[ignore]
+    fn default_match_budget_suffices_for_english() {
+        let tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+        let rules = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+
+        rules.validate(&tokenizer);
+        assert_eq!(rules.match_stats(), MatchStats::default());
+    }
+
+    /// Checks that the English rules find the same suggestions in all examples with and without matching the
+    /// regexes of alternatives at once. Needs the binaries in `storage`, see `BUILD.md`.
+    #[test]
+    #[ignore]
+    fn batched_regexes_match_the_same_on_english() {
+        let tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+        let rules = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+        let mut unbatched = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+        unbatched.set_batch_regexes(false);
+
+        for rule in rules.rules() {
+            for example in rule.examples() {
+                let text = example.text();
+                assert_eq!(
+                    rules.suggest(text, &tokenizer),
+                    unbatched.suggest(text, &tokenizer),
+                    "{}",
+                    text
+                );
+            }
+        }
+    }
+
+    /// Checks that the English rules find the same suggestions in all examples with and without the
+    /// [Prefilter] and the [RuleIndex][super::RuleIndex]. Needs the binaries in `storage`, see `BUILD.md`.
+    #[test]
+    #[ignore]
+    fn prefilter_and_index_find_the_same_on_english() {
+        let tokenizer = Tokenizer::new("../storage/en_tokenizer.bin").unwrap();
+        let rules = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+        let mut unfiltered = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+        unfiltered.prefilter = Prefilter::default();
+        let mut unindexed = Rules::load("../storage/en_rules.bin", &tokenizer).unwrap();
+        unindexed.clear_index();
+
+        for rule in rules.rules() {
+            for example in rule.examples() {
+                let text = example.text();
+                let suggestions = rules.suggest(text, &tokenizer);
+                assert_eq!(
+                    suggestions,
+                    unfiltered.suggest(text, &tokenizer),
+                    "{}",
+                    text
+                );
+                assert_eq!(suggestions, unindexed.suggest(text, &tokenizer), "{}", text);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod compile_tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use crate::{
+        compile::{
+            testing::{
+                build_info_for, compile_rules, setup, word_chunker, write_temp, DISAMBIGUATION,
+                GRAMMAR,
+            },
+            BuildInfo, RegexCache,
+        },
+        rules::{IgnoreWords, Prefilter, RuleIndex, Rules, RulesOptions},
+        tokenizer::{finalize, tag::Tagger, AnalyzeOptions, Tokenizer, TokenizerOptions},
+        types::MatchedGroup,
+        Parallelism,
+    };
+
+    #[test]
+    fn rules_use_the_tagger_of_the_tokenizer() {
+        let tag_path = write_temp(
+            "shared_tags.txt",
+            "here\there\tRB\nNext\tnext\tJJ\nruns\trun\tVBZ\nthe\tthe\tDT\ntool\ttool\tNN\n\
+             go\tgo\tVB\nwent\tgo\tVBD\ngone\tgo\tVBN\n",
+        );
+        let tagger = Arc::new(
+            Tagger::from_dumps::<_, &str, _>(
+                &[&tag_path.to_str().unwrap()],
+                &[],
+                &["NNP", "CD", "ORD", "UNUSED_TAG"],
+                &HashSet::new(),
+            )
+            .unwrap(),
+        );
+        let mut build_info = BuildInfo::new(tagger.clone(), RegexCache::new(0));
+        let rules =
+            Rules::from_xml_reader(GRAMMAR.as_bytes(), &mut build_info, RulesOptions::default())
+                .unwrap();
+        let tokenizer = Tokenizer::from_xml_reader(
+            DISAMBIGUATION.as_bytes(),
+            &mut build_info,
+            None,
+            None,
+            TokenizerOptions::default(),
+        )
+        .unwrap();
+        // the tokenizer holds the tagger the rules were compiled with, not a copy
+        assert!(Arc::ptr_eq(tokenizer.tagger(), &tagger));
+
+        // only the tokenizer binary contains the tagger
+        let contains_tagger = |bytes: &[u8]| bytes.windows(10).any(|x| x == b"UNUSED_TAG");
+        let tokenizer_bytes = bincode::serialize(&tokenizer).unwrap();
+        let rules_bytes = bincode::serialize(&rules).unwrap();
+        assert!(contains_tagger(&tokenizer_bytes));
+        assert!(!contains_tagger(&rules_bytes));
+
+        // the loaded rules refer to words and tags by the IDs of the loaded tokenizer
+        let loaded_tokenizer = Tokenizer::new_from(tokenizer_bytes.as_slice()).unwrap();
+        let loaded_rules = Rules::load_from(rules_bytes.as_slice(), &loaded_tokenizer).unwrap();
+        let loaded_tagger = loaded_tokenizer.tagger();
+        for word in ["here", "runs", "went", "gone"] {
+            assert_eq!(
+                loaded_tagger.words().get_id(word),
+                tagger.words().get_id(word)
+            );
+        }
+        assert_eq!(loaded_tagger.tag_store(), tagger.tag_store());
+
+        let text = "He uses tool. It ends here Next one.";
+        let suggestions = rules.suggest(text, &tokenizer);
+        assert!(!suggestions.is_empty());
+        assert_eq!(loaded_rules.suggest(text, &loaded_tokenizer), suggestions);
+    }
+
+    #[test]
+    fn ignored_words_suppress_suggestions() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TYPOS" name="Typos">
+        <rule id="NLPRULE_CASE" name="NLPRule">
+            <pattern>
+                <token regexp="yes">nlprules?</token>
+            </pattern>
+            <message>Did you mean <suggestion>NLPRule</suggestion>?</message>
+            <example correction="NLPRule">I use <marker>nlprule</marker>.</example>
+        </rule>
+    </category>
+    <category id="STYLE" name="Style">
+        <rule id="FORGE_CASE" name="Forge names">
+            <pattern>
+                <token regexp="yes">github|gitlab</token>
+            </pattern>
+            <message>Did you mean <suggestion><match no="1" case_conversion="startupper"/></suggestion>?</message>
+            <example correction="Github">I use <marker>github</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let ignore_path = write_temp(
+            "ignore.txt",
+            "# words which are never flagged\n\nnlprule/S\nTYPOS:github\nSTYLE:gitlab\n",
+        );
+
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let mut rules =
+            Rules::from_xml_reader(grammar.as_bytes(), &mut build_info, RulesOptions::default())
+                .unwrap();
+        let text = "I use nlprule, nlprules, github and gitlab.";
+        let sources = |rules: &Rules| -> Vec<(String, usize)> {
+            rules
+                .suggest(text, &tokenizer)
+                .into_iter()
+                .map(|x| (x.source, x.start))
+                .collect()
+        };
+        assert_eq!(sources(&rules).len(), 4);
+
+        let ignore_words = IgnoreWords::from_dumps(&[&ignore_path]).unwrap();
+        assert!(ignore_words.contains("nlprule", "STYLE"));
+        assert!(!ignore_words.contains("# words which are never flagged", "STYLE"));
+        assert!(ignore_words.contains("github", "TYPOS"));
+        assert!(!ignore_words.contains("github", "STYLE"));
+        rules.set_ignore_words(ignore_words);
+
+        // the words are persisted with the rules
+        let rules: Rules = bincode::deserialize(&bincode::serialize(&rules).unwrap()).unwrap();
+        assert_eq!(
+            sources(&rules),
+            vec![
+                ("NLPRULE_CASE".to_string(), 15),
+                ("FORGE_CASE".to_string(), 25),
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_is_stored() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="PLAIN" name="Plain">
+            <pattern>
+                <token>tool</token>
+            </pattern>
+            <message>Did you mean <suggestion>tools</suggestion>?</message>
+            <example correction="tools">The <marker>tool</marker>.</example>
+        </rule>
+        <rule id="WITH_METADATA" name="With metadata">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <url>https://example.com/here</url>
+            <short>Possible typo</short>
+            <example correction="there">It is <marker>here</marker>.</example>
+        </rule>
+        <rulegroup id="GROUP" name="Group">
+            <url>https://example.com/group</url>
+            <short>Group message</short>
+            <rule>
+                <pattern>
+                    <token>runs</token>
+                </pattern>
+                <message>Did you mean <suggestion>run</suggestion>?</message>
+                <example correction="run">They <marker>runs</marker>.</example>
+            </rule>
+            <rule>
+                <pattern>
+                    <token>go</token>
+                </pattern>
+                <message>Did you mean <suggestion>went</suggestion>?</message>
+                <short>Wrong tense</short>
+                <example correction="went">I <marker>go</marker>.</example>
+            </rule>
+        </rulegroup>
+    </category>
+</rules>
+"#;
+
+        let (tokenizer, _) = setup();
+        let mut build_info = build_info_for(&tokenizer);
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let metadata: Vec<_> = rules
+            .rules()
+            .iter()
+            .map(|x| (x.id(), x.url(), x.short()))
+            .collect();
+        assert_eq!(
+            metadata,
+            vec![
+                ("PLAIN", None, None),
+                (
+                    "WITH_METADATA",
+                    Some("https://example.com/here"),
+                    Some("Possible typo")
+                ),
+                (
+                    "GROUP.0",
+                    Some("https://example.com/group"),
+                    Some("Group message")
+                ),
+                (
+                    "GROUP.1",
+                    Some("https://example.com/group"),
+                    Some("Wrong tense")
+                ),
+            ]
+        );
+
+        let shorts: Vec<_> = rules
+            .suggest("The tool is here.", &tokenizer)
+            .into_iter()
+            .map(|x| x.short)
+            .collect();
+        assert_eq!(shorts, vec![None, Some("Possible typo".to_string())]);
+
+        let options = RulesOptions::builder()
+            .strip_metadata(true)
+            .build()
+            .unwrap();
+        let rules = Rules::from_xml_reader(grammar.as_bytes(), &mut build_info, options).unwrap();
+        assert!(rules
+            .rules()
+            .iter()
+            .all(|x| x.url().is_none() && x.short().is_none()));
+    }
+
+    #[test]
+    fn stripped_rules_give_the_same_suggestions() {
+        let (tokenizer, rules) = setup();
+        let bytes = bincode::serialize(&rules).unwrap();
+        let mut stripped = Rules::from_bytes(&bytes).unwrap();
+        stripped.strip_metadata();
+        let stripped_bytes = bincode::serialize(&stripped).unwrap();
+        assert!(stripped_bytes.len() < bytes.len());
+
+        let stripped = Rules::load_from(stripped_bytes.as_slice(), &tokenizer).unwrap();
+        assert!(stripped.rules().iter().all(|x| x.name().is_empty()
+            && x.category_name().is_empty()
+            && x.url().is_none()
+            && !x.category_id().is_empty()));
+
+        let mut n_suggestions = 0;
+        for example in rules.rules().iter().flat_map(|x| x.examples()) {
+            let expected = rules.suggest(example.text(), &tokenizer);
+            let suggestions = stripped.suggest(example.text(), &tokenizer);
+            n_suggestions += suggestions.len();
+
+            assert_eq!(suggestions.len(), expected.len());
+            for (suggestion, expected) in suggestions.iter().zip(&expected) {
+                assert_eq!(
+                    (
+                        suggestion.start,
+                        suggestion.end,
+                        &suggestion.replacements,
+                        &suggestion.source
+                    ),
+                    (
+                        expected.start,
+                        expected.end,
+                        &expected.replacements,
+                        &expected.source
+                    )
+                );
+                assert!(suggestion.message.is_empty() && suggestion.short.is_none());
+            }
+        }
+        assert!(n_suggestions > 0);
+
+        // stripping at compile time gives the same binary
+        let tagger = tokenizer.tagger().clone();
+        let mut build_info = BuildInfo::new(tagger, RegexCache::new(0));
+        let options = RulesOptions::builder()
+            .strip_metadata(true)
+            .build()
+            .unwrap();
+        let compiled =
+            Rules::from_xml_reader(GRAMMAR.as_bytes(), &mut build_info, options).unwrap();
+        assert_eq!(bincode::serialize(&compiled).unwrap(), stripped_bytes);
+    }
+
+    #[test]
+    fn prefilter_only_skips_rules_which_can_not_match() {
+        let (tokenizer, rules) = setup();
+
+        let mut texts: Vec<String> = rules
+            .rules()
+            .iter()
+            .flat_map(|x| x.examples().iter().map(|x| x.text().to_string()))
+            .collect();
+        texts.extend(
+            [
+                "Here Next. HERE NEXT!",
+                "Yesterday I go there.\n\nhere",
+                "L'homme est là. Have went, have gone.",
+                "",
+            ]
+            .iter()
+            .map(|x| x.to_string()),
+        );
+
+        // the suggestions must be the same as without prefilter
+        let assert_unchanged = |mut rules: Rules, texts: &[String]| {
+            let filtered: Vec<_> = texts.iter().map(|x| rules.suggest(x, &tokenizer)).collect();
+            assert!(rules.prefilter.filtered.iter().any(|x| *x));
+
+            rules.prefilter = Prefilter::default();
+            let unfiltered: Vec<_> = texts.iter().map(|x| rules.suggest(x, &tokenizer)).collect();
+            assert_eq!(filtered, unfiltered);
+            assert!(filtered.iter().any(|x| !x.is_empty()));
+        };
+        assert_unchanged(rules, &texts);
+
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="STRING" name="String">
+            <pattern><token>Tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="ALTERNATION" name="Alternation">
+            <pattern><token regexp="yes">runs?|wen[td]</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="CASE_INSENSITIVE_UNICODE" name="Case-insensitive Unicode">
+            <pattern><token>Straße</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>STRASSE</example>
+        </rule>
+        <rule id="CASE_SENSITIVE_UNICODE" name="Case-sensitive Unicode">
+            <pattern><token case_sensitive="yes">Straße</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="OPTIONAL" name="Optional">
+            <pattern><token min="0">here</token><token>the</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="RAREST_PART" name="Rarest part">
+            <pattern><token>the</token><token>tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="NEGATED" name="Negated">
+            <pattern><token negate="yes">the</token><token regexp="yes">[a-z]+</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="INFLECTED" name="Inflected">
+            <pattern><token inflected="yes">go</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="CLASS" name="Class">
+            <pattern><token regexp="yes">[a-z]+|tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let literals = |id: &str| {
+            let i = rules.rules().iter().position(|x| x.id() == id).unwrap();
+            if !rules.prefilter.filtered[i] {
+                return None;
+            }
+
+            let mut literals: Vec<_> = rules
+                .prefilter
+                .literals
+                .iter()
+                .zip(&rules.prefilter.literal_rules)
+                .filter(|(_, rules)| rules.contains(&i))
+                .map(|(literal, _)| literal.as_str())
+                .collect();
+            literals.sort_unstable();
+            Some(literals)
+        };
+
+        assert_eq!(literals("STRING"), Some(vec!["tool"]));
+        assert_eq!(literals("ALTERNATION"), Some(vec!["run", "wen"]));
+        assert_eq!(literals("CASE_INSENSITIVE_UNICODE"), Some(vec!["stra"]));
+        assert_eq!(literals("CASE_SENSITIVE_UNICODE"), Some(vec!["strasse"]));
+        assert_eq!(literals("OPTIONAL"), Some(vec!["the"]));
+        assert_eq!(literals("RAREST_PART"), Some(vec!["tool"]));
+        assert_eq!(literals("NEGATED"), None);
+        assert_eq!(literals("INFLECTED"), None);
+        assert_eq!(literals("CLASS"), None);
+
+        let texts: Vec<_> = [
+            "STRASSE",
+            "die Straße",
+            "Wend the TOOL",
+            "here the went runs",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+        assert_unchanged(rules, &texts);
+    }
+
+    #[test]
+    fn rule_index_only_skips_positions_where_rules_can_not_start() {
+        let (tokenizer, rules) = setup();
+
+        // the suggestions must be the same as without index
+        let assert_unchanged = |mut rules: Rules, texts: &[String]| {
+            let indexed: Vec<_> = texts.iter().map(|x| rules.suggest(x, &tokenizer)).collect();
+            assert!(rules.index.anchored.iter().any(|x| *x));
+
+            rules.index = RuleIndex::default();
+            let unindexed: Vec<_> = texts.iter().map(|x| rules.suggest(x, &tokenizer)).collect();
+            assert_eq!(indexed, unindexed);
+            assert!(indexed.iter().any(|x| !x.is_empty()));
+        };
+
+        let mut texts: Vec<String> = rules
+            .rules()
+            .iter()
+            .flat_map(|x| x.examples().iter().map(|x| x.text().to_string()))
+            .collect();
+        texts.push("Here Next. HERE NEXT! Yesterday I go there.\n\nhere".to_string());
+        assert_unchanged(rules, &texts);
+
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="STRING" name="String">
+            <pattern><token>the</token><token>tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="POS" name="POS">
+            <pattern><token postag="DT"/><token>tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="OR" name="Or">
+            <pattern><or><token>here</token><token postag="DT"/></or></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="OPTIONAL_FIRST" name="Optional first">
+            <pattern><token min="0">here</token><token>the</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="NEGATED" name="Negated">
+            <pattern><token negate="yes">the</token><token>tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="ANY_POS" name="Any POS">
+            <pattern><token postag=".+" postag_regexp="yes"/><token>tool</token></pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let index = |id: &str| rules.rules().iter().position(|x| x.id() == id).unwrap();
+        let anchored: Vec<_> = rules
+            .rules()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| rules.index.anchored[*i])
+            .map(|(_, x)| x.id())
+            .collect();
+        assert_eq!(anchored, vec!["STRING", "POS", "OR"]);
+
+        // "the" is the second token after the sentence start, "The" is not in the word store but tagged as "the"
+        let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize("here the tool The")));
+        let starts = rules.index.starts(&tokens, rules.rules().len());
+        assert_eq!(starts[index("STRING")], Some(vec![2, 4]));
+        assert_eq!(starts[index("POS")], Some(vec![2, 4]));
+        assert_eq!(starts[index("OR")], Some(vec![1, 2, 4]));
+        assert_eq!(starts[index("NEGATED")], None);
+
+        let texts: Vec<_> = ["here the tool", "The tool", "THE TOOL here", "Next the"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_unchanged(rules, &texts);
+    }
+
+    #[test]
+    fn profiles_record_the_cost_of_each_rule() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="SINGLE_WORD" name="Single word">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+        <rule id="REGEXES" name="Regexes">
+            <pattern>
+                <token regexp="yes">went|gone</token>
+                <token regexp="yes">went|go</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example>here</example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let build_info = build_info_for(&tokenizer);
+
+        let (_, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info.clone(),
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert!(report.profiles.is_empty());
+
+        // the regex cache is shared between clones so a new one is needed to count the scans
+        let build_info = build_info_for(&tokenizer);
+        let (_, report) = Rules::from_xml_reader_with_report(
+            grammar.as_bytes(),
+            &mut build_info.with_profile(true),
+            RulesOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.profiles.len(), 2);
+        assert!(report
+            .profiles
+            .windows(2)
+            .all(|x| (x[0].micros, x[0].size) >= (x[1].micros, x[1].size)));
+        assert!(report.profiles.iter().all(|x| x.size > 0));
+
+        let regexes = report.profiles.iter().find(|x| x.id == "REGEXES").unwrap();
+        assert_eq!(regexes.regex_scans, 2);
+        assert_eq!(regexes.set_words, 4);
+        let single = report
+            .profiles
+            .iter()
+            .find(|x| x.id == "SINGLE_WORD")
+            .unwrap();
+        assert_eq!((single.regex_scans, single.set_words), (0, 0));
+    }
+
+    #[test]
+    fn examples_are_validated() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="PASSING" name="Passing">
+            <pattern>
+                <token>tool</token>
+            </pattern>
+            <message>Did you mean <suggestion>tools</suggestion>?</message>
+            <example correction="tools">The <marker>tool</marker>.</example>
+            <example correction="">The <marker>tool</marker> works.</example>
+            <example>The tools.</example>
+        </rule>
+        <rule id="WRONG_CORRECTION" name="Wrong correction">
+            <pattern>
+                <token>here</token>
+            </pattern>
+            <message>Did you mean <suggestion>there</suggestion>?</message>
+            <example correction="where">It is <marker>here</marker>.</example>
+        </rule>
+        <rule id="WRONG_SPAN" name="Wrong span">
+            <pattern>
+                <token>the</token>
+                <token>tool</token>
+            </pattern>
+            <message>Did you mean <suggestion>a tool</suggestion>?</message>
+            <example correction="a tool">I see the <marker>tool</marker>.</example>
+            <example>I see the tool.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+
+        let (tokenizer, _) = setup();
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let report = rules.validate(&tokenizer);
+        assert_eq!(report.rules.len(), 3);
+        let passed: Vec<_> = report.passed().map(|x| x.id.as_str()).collect();
+        assert_eq!(passed, vec!["PASSING"]);
+
+        let wrong_correction = &report.rules[1];
+        assert_eq!(wrong_correction.id, "WRONG_CORRECTION");
+        assert_eq!(wrong_correction.failures.len(), 1);
+        assert_eq!(
+            wrong_correction.failures[0].found[0].replacements,
+            vec!["there"]
+        );
+
+        // both examples fail: the suggestion covers "the tool" and the correct example triggers the rule
+        let wrong_span = &report.rules[2];
+        assert_eq!(wrong_span.failures.len(), 2);
+        assert_eq!(
+            wrong_span.failures[0]
+                .expected
+                .as_ref()
+                .map(|x| (x.start, x.end)),
+            Some((10, 14))
+        );
+        assert_eq!(
+            (
+                wrong_span.failures[0].found[0].start,
+                wrong_span.failures[0].found[0].end
+            ),
+            (6, 14)
+        );
+        assert!(wrong_span.failures[1].expected.is_none());
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["rules"][1]["failures"][0]["text"], "It is here.");
+    }
+
+    #[test]
+    fn rules_can_match_paragraph_end() {
+        let (tokenizer, rules) = setup();
+        assert_eq!(rules.rules().len(), 11);
+
+        let suggestions = rules.suggest("It ends here\n\nNext one.", &tokenizer);
+        let ids: Vec<_> = suggestions.iter().map(|x| x.source.as_str()).collect();
+        assert_eq!(ids, vec!["PARAGRAPH_END"]);
+    }
+
+    #[test]
+    fn rules_do_not_match_across_paragraphs() {
+        let (tokenizer, rules) = setup();
+
+        let suggestions = rules.suggest("It ends here Next one.", &tokenizer);
+        let ids: Vec<_> = suggestions.iter().map(|x| x.source.as_str()).collect();
+        assert_eq!(ids, vec!["HERE_NEXT"]);
+
+        let suggestions = rules.suggest("It ends here\n\nNext one.", &tokenizer);
+        assert!(suggestions.iter().all(|x| x.source != "HERE_NEXT"));
+    }
+
+    #[test]
+    fn rules_ignore_immunized_tokens() {
+        let (tokenizer, rules) = setup();
+
+        assert_eq!(rules.suggest("I quote here Next one.", &tokenizer).len(), 0);
+
+        let text = "It ends here Next one.";
+        let mut tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize(text)));
+        assert_eq!(rules.apply(&tokens, &tokenizer).len(), 1);
+
+        let next = tokens
+            .iter_mut()
+            .find(|x| x.word.text.as_ref() == "Next")
+            .unwrap();
+        next.set_immunized(true);
+        assert!(next.is_immunized());
+        assert_eq!(rules.apply(&tokens, &tokenizer).len(), 0);
+    }
+
+    #[test]
+    fn parallel_suggestions_equal_sequential_suggestions() {
+        let (tokenizer, rules) = setup();
+
+        let paragraph = "It ends here Next one. He lives in New York City. I quote here Next one. \
+                         It ends here Next time.";
+        let text = vec![paragraph; 10].join("\n\n");
+
+        let sequential = rules.suggest_with(
+            &text,
+            &tokenizer,
+            &AnalyzeOptions {
+                min_parallel_sentences: usize::MAX,
+                ..AnalyzeOptions::default()
+            },
+        );
+        let parallel = rules.suggest_with(
+            &text,
+            &tokenizer,
+            &AnalyzeOptions {
+                min_parallel_sentences: 0,
+                ..AnalyzeOptions::default()
+            },
+        );
+
+        assert_eq!(sequential.len(), 30);
+        assert_eq!(sequential, parallel);
+
+        let chars: Vec<_> = text.chars().collect();
+        for suggestion in sequential {
+            let matched: String = chars[suggestion.start..suggestion.end].iter().collect();
+            assert!(matched == "here Next" || matched == "in New");
+        }
+    }
+
+    #[test]
+    fn sentences_are_checked_separately() {
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="NEXT_AT_START" name="Next at sentence start">
+            <pattern>
+                <token postag="SENT_START" />
+                <marker>
+                    <token>Next</token>
+                </marker>
+            </pattern>
+            <message>Did you mean <suggestion>Then</suggestion>?</message>
+            <example correction="Then"><marker>Next</marker> one.</example>
+        </rule>
+        <rule id="HERE_DOT_NEXT" name="Here dot next">
+            <pattern>
+                <token>here</token>
+                <token>.</token>
+                <token>Next</token>
+            </pattern>
+            <message>Did you mean <suggestion>here next</suggestion>?</message>
+            <example correction="here next">It ends <marker>here. Next</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let (tokenizer, _) = setup();
+        let rules = compile_rules(grammar, &tokenizer);
+        let text = "It ends here. Next one.";
+
+        // each sentence starts with SENT_START and rules do not match across sentences
+        let sources: Vec<_> = rules
+            .suggest(text, &tokenizer)
+            .into_iter()
+            .map(|x| x.source)
+            .collect();
+        assert_eq!(sources, vec!["NEXT_AT_START"]);
+
+        // the tokens of a whole text are one sentence for `apply`
+        let tokens = finalize(tokenizer.disambiguate(tokenizer.tokenize(text)));
+        let sources: Vec<_> = rules
+            .apply(&tokens, &tokenizer)
+            .into_iter()
+            .map(|x| x.source)
+            .collect();
+        assert_eq!(sources, vec!["HERE_DOT_NEXT"]);
+    }
+
+    #[test]
+    fn parallelism_is_set_per_instance() {
+        let (mut tokenizer, mut rules) = setup();
+        let text = vec!["It ends here Next one."; 20].join(" ");
+        let expected = rules.suggest(&text, &tokenizer);
+
+        tokenizer.set_parallelism(Parallelism::Off).unwrap();
+        rules.set_parallelism(Parallelism::Off).unwrap();
+        assert_eq!(rules.parallelism(), Parallelism::Off);
+        assert_eq!(rules.suggest(&text, &tokenizer), expected);
+
+        tokenizer.set_parallelism(Parallelism::Threads(2)).unwrap();
+        rules.set_parallelism(Parallelism::Threads(3)).unwrap();
+        assert_eq!(tokenizer.parallelism(), Parallelism::Threads(2));
+        assert_eq!(rules.suggest(&text, &tokenizer), expected);
+    }
+
+    #[test]
+    fn suggestions_keep_groups_on_request() {
+        let (tokenizer, _) = setup();
+        let grammar = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="GROUPS" name="Groups">
+            <pattern>
+                <token>the</token>
+                <token min="0">here</token>
+                <marker><token regexp="yes" min="1" max="2">tool|runs</token></marker>
+            </pattern>
+            <message>Did you mean <suggestion>go</suggestion>?</message>
+            <example correction="go">the <marker>tool</marker></example>
+        </rule>
+    </category>
+</rules>
+"#;
+        let rules = compile_rules(grammar, &tokenizer);
+
+        let text = "Next the tool runs";
+        assert_eq!(rules.suggest(text, &tokenizer)[0].groups, None);
+
+        let options = AnalyzeOptions {
+            keep_groups: true,
+            ..AnalyzeOptions::default()
+        };
+        let suggestions = rules.suggest_with(text, &tokenizer, &options);
+        assert_eq!(suggestions.len(), 1);
+        let group = |id: usize, char_span: (usize, usize), text: &str| MatchedGroup {
+            id,
+            char_span,
+            text: text.to_string(),
+        };
+        assert_eq!(
+            suggestions[0].groups.as_deref().unwrap(),
+            [
+                group(0, (5, 5), ""),
+                group(1, (5, 8), "the"),
+                group(2, (9, 9), ""),
+                group(3, (9, 18), "tool runs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_using_chunks_are_skipped_without_chunking() {
+        let (mut tokenizer, rules) = setup();
+        tokenizer.set_chunker(Some(word_chunker()));
+        let options = AnalyzeOptions {
+            chunk: false,
+            ..AnalyzeOptions::default()
+        };
+
+        let uses_chunker: Vec<_> = rules
+            .rules()
+            .iter()
+            .filter(|x| x.uses_chunker())
+            .map(|x| x.id())
+            .collect();
+        assert_eq!(
+            uses_chunker,
+            vec![
+                "NOUN_PHRASE_AFTER_THE",
+                "RUNS_OUTSIDE_NOUN_PHRASE",
+                "LAMP_OUTSIDE_NOUN_PHRASE"
+            ]
+        );
+
+        let text = "the light runs lamp here Next";
+        assert!(tokenizer
+            .tokenize_with(text, &options)
+            .iter()
+            .all(|x| x.chunks.is_empty()));
+
+        let sources = |options: &AnalyzeOptions| -> Vec<_> {
+            rules
+                .suggest_with(text, &tokenizer, options)
+                .into_iter()
+                .map(|x| x.source)
+                .collect()
+        };
+        assert_eq!(
+            sources(&AnalyzeOptions::default()),
+            vec![
+                "NOUN_PHRASE_AFTER_THE",
+                "RUNS_OUTSIDE_NOUN_PHRASE",
+                "HERE_NEXT"
+            ]
+        );
+        assert_eq!(sources(&options), vec!["HERE_NEXT"]);
+    }
+}
@@ -4,24 +4,32 @@
 //! Tokens are *disambiguated* (i. e. information from the initial assignment is changed) in a rule-based way by
 //! [DisambiguationRule][crate::rule::DisambiguationRule]s.
 
-use crate::{types::*, utils::parallelism::MaybeParallelRefIterator};
-use lazy_static::lazy_static;
-use onig::Regex;
+use crate::{
+    types::*,
+    utils::{
+        parallelism::{MaybeParallelRefIterator, Parallelism, ParallelismConfig},
+        regex::{Regex, SerializeRegex},
+    },
+    Compression, DuplicateIdPolicy, Error, ErrorPolicy, FormatVersion, Header,
+};
 use serde::{Deserialize, Serialize};
 use std::{
+    borrow::Cow,
     collections::HashSet,
     fs::File,
-    io::{BufReader, Read},
+    io::{Read, Write},
+    ops::Range,
     path::Path,
     sync::Arc,
 };
 use unicode_segmentation::UnicodeSegmentation;
+use {lazy_static::lazy_static, once_cell::sync::OnceCell};
 
 pub mod chunk;
+pub mod multiword;
 pub mod tag;
 
-use chunk::Chunker;
-use tag::Tagger;
+use {chunk::Chunker, multiword::MultiwordTagger, tag::Tagger};
 
 use crate::rule::DisambiguationRule;
 
@@ -46,28 +54,103 @@ where
     result
 }
 
-fn get_token_strs(text: &str) -> Vec<&str> {
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '’'
+}
+
+/// Whether the char is whitespace or a control character. Tokens consisting only of these are dropped.
+fn is_ignorable(c: char) -> bool {
+    c.is_whitespace() || c.is_control()
+}
+
+/// Splits the text into token strings. Each string is returned together with a flag indicating whether it is
+/// an opaque token i. e. a URL, email address or file path which is never split further.
+fn get_token_strs(text: &str) -> Vec<(&str, bool)> {
     let mut tokens = Vec::new();
 
     lazy_static! {
-        // see https://stackoverflow.com/a/17773849
-        static ref URL_REGEX: Regex = Regex::new(r"(https?:\/\/(?:www\.|(?!www))[a-zA-Z0-9][a-zA-Z0-9-]+[a-zA-Z0-9]\.[^\s]{2,}|www\.[a-zA-Z0-9][a-zA-Z0-9-]+[a-zA-Z0-9]\.[^\s]{2,}|https?:\/\/(?:www\.|(?!www))[a-zA-Z0-9]+\.[^\s]{2,}|www\.[a-zA-Z0-9]+\.[^\s]{2,})").unwrap();
+        // URLs, see https://stackoverflow.com/a/17773849, then email addresses, then unix-style and windows-style file paths
+        static ref OPAQUE_REGEX: Regex = Regex::new(r"(https?:\/\/(?:www\.|(?!www))[a-zA-Z0-9][a-zA-Z0-9-]+[a-zA-Z0-9]\.[^\s]{2,}|www\.[a-zA-Z0-9][a-zA-Z0-9-]+[a-zA-Z0-9]\.[^\s]{2,}|https?:\/\/(?:www\.|(?!www))[a-zA-Z0-9]+\.[^\s]{2,}|www\.[a-zA-Z0-9]+\.[^\s]{2,})|[a-zA-Z0-9._%+-]+@[a-zA-Z0-9-]+(?:\.[a-zA-Z0-9-]+)*\.[a-zA-Z]{2,}|(?<![\w\/.~])(?:~|\.{1,2})?(?:\/[\w.-]+){2,}\/?|\b[a-zA-Z]:\\[\w.-]+(?:\\[\w.-]+)*").unwrap();
     }
 
     let mut prev = 0;
-    let split_func = |c: char| c.is_whitespace() || crate::utils::splitting_chars().contains(c);
+    // control characters are split off so they end up as their own (ignored) tokens
+    let split_func = |c: char| {
+        c.is_whitespace() || c.is_control() || crate::utils::splitting_chars().contains(c)
+    };
+
+    for (start, end) in OPAQUE_REGEX.find_iter(text) {
+        // punctuation directly after e. g. a URL is almost never part of it
+        let end = start
+            + text[start..end]
+                .trim_end_matches(|c| ".,:;!?)]}'\"".contains(c))
+                .len();
 
-    for (start, end) in URL_REGEX.find_iter(text) {
-        tokens.extend(split(&text[prev..start], split_func));
-        tokens.push(&text[start..end]);
+        if start == end {
+            continue;
+        }
+
+        tokens.extend(
+            split(&text[prev..start], split_func)
+                .into_iter()
+                .map(|x| (x, false)),
+        );
+        tokens.push((&text[start..end], true));
         prev = end;
     }
 
-    tokens.extend(split(&text[prev..text.len()], split_func));
+    tokens.extend(
+        split(&text[prev..text.len()], split_func)
+            .into_iter()
+            .map(|x| (x, false)),
+    );
 
     tokens
 }
 
+/// Splits a hyphenated compound like "state-of-the-art" into its fragments and the hyphens.
+/// Returns `None` if the text is not a hyphenated compound.
+fn split_hyphenated(text: &str) -> Option<Vec<&str>> {
+    if !text.contains('-') || text.starts_with('-') || text.ends_with('-') || text.contains("--") {
+        return None;
+    }
+
+    Some(split(text, |c| c == '-'))
+}
+
+/// Gets the byte indices at which a new paragraph starts i. e. the ends of sequences of two or more newlines.
+fn get_paragraph_starts(text: &str) -> Vec<usize> {
+    lazy_static! {
+        static ref PARAGRAPH_BREAK_REGEX: Regex = Regex::new(r"\n[^\S\n]*\n\s*").unwrap();
+    }
+
+    PARAGRAPH_BREAK_REGEX
+        .find_iter(text)
+        .map(|(_, end)| end)
+        .collect()
+}
+
+/// Splits the tokens into sentences i. e. after each token which ends a sentence.
+pub(crate) fn split_sentences(tokens: Vec<IncompleteToken>) -> Vec<Vec<IncompleteToken>> {
+    let mut sentences = Vec::new();
+    let mut sentence = Vec::new();
+
+    for token in tokens {
+        let is_sentence_end = token.is_sentence_end;
+        sentence.push(token);
+
+        if is_sentence_end {
+            sentences.push(std::mem::take(&mut sentence));
+        }
+    }
+
+    if !sentence.is_empty() {
+        sentences.push(sentence);
+    }
+
+    sentences
+}
+
 /// *Finalizes* the tokens by e. g. adding a specific UNKNOWN part-of-speech tag.
 /// After finalization grammatical error correction rules can be used on the tokens.
 pub fn finalize(tokens: Vec<IncompleteToken>) -> Vec<Token> {
@@ -81,89 +164,781 @@ pub fn finalize(tokens: Vec<IncompleteToken>) -> Vec<Token> {
     finalized
 }
 
+/// How to treat hyphenated compounds such as "state-of-the-art".
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HyphenSplit {
+    /// Compounds are never split i. e. they are always one token.
+    #[default]
+    Never,
+    /// Compounds are always split into their fragments and the hyphens.
+    Always,
+    /// Compounds are split if the tagger does not know the whole compound.
+    Heuristic,
+}
+
+/// Which part-of-speech tags the chunker uses as input. The chunker predicts its own tags
+/// which can disagree with the readings of the tagger since both come from different sources.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkerTagSource {
+    /// Use the tags predicted by the chunker.
+    #[default]
+    Chunker,
+    /// Use the tags predicted by the chunker only if the tagger agrees i. e. if the predicted tag is one of the
+    /// readings of the token. Otherwise the chunk model gets the first reading of the tagger instead.
+    Tagger,
+}
+
+/// A heuristic to guess a reading of a word which is not in the tagger dictionary from its
+/// prefix and / or suffix (e. g. English words ending in "ly" are often adverbs).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnknownWordHeuristic {
+    /// The prefix the word must start with.
+    #[serde(default)]
+    pub prefix: String,
+    /// The suffix the word must end with.
+    #[serde(default)]
+    pub suffix: String,
+    /// The part-of-speech tag to assign.
+    pub tag: String,
+    /// What to replace the suffix with to get the lemma (e. g. "" for "dockerizes" → "dockerize").
+    /// If not set, the lemma is the word itself.
+    #[serde(default)]
+    pub lemma_suffix: Option<String>,
+}
+
+impl UnknownWordHeuristic {
+    fn guess<'t>(&'t self, word: &str, tagger: &'t Tagger) -> Option<WordData<'t>> {
+        // the affixes alone are not enough to guess anything
+        if word.len() <= self.prefix.len() + self.suffix.len()
+            || !word.starts_with(self.prefix.as_str())
+            || !word.ends_with(self.suffix.as_str())
+        {
+            return None;
+        }
+
+        let lemma = match &self.lemma_suffix {
+            Some(lemma_suffix) => {
+                format!(
+                    "{}{}",
+                    &word[..word.len() - self.suffix.len()],
+                    lemma_suffix
+                )
+            }
+            None => word.to_string(),
+        };
+
+        Some(WordData::new(
+            tagger.id_word(lemma.into()),
+            tagger.id_tag(self.tag.as_str()),
+        ))
+    }
+}
+
+/// A rule to tag words which are not in the tagger dictionary by their form, e. g. numbers, ordinals or symbols.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpecialTokenRule {
+    /// A regular expression the whole word must match.
+    pub pattern: String,
+    /// The part-of-speech tag to assign.
+    pub tag: String,
+    /// The lemma to assign. If not set, the lemma is the word itself.
+    #[serde(default)]
+    pub lemma: Option<String>,
+    #[serde(skip)]
+    regex: OnceCell<Arc<SerializeRegex>>,
+}
+
+impl SpecialTokenRule {
+    /// Creates a rule assigning `tag` to words matching `pattern`.
+    pub fn new<S1: Into<String>, S2: Into<String>>(pattern: S1, tag: S2) -> Self {
+        SpecialTokenRule {
+            pattern: pattern.into(),
+            tag: tag.into(),
+            lemma: None,
+            regex: OnceCell::new(),
+        }
+    }
+
+    /// The compiled pattern. Compiled on first use since the options are deserialized from JSON.
+    pub(crate) fn regex(&self) -> Result<&SerializeRegex, Error> {
+        self.regex
+            .get_or_try_init(|| SerializeRegex::new(&self.pattern, true, true).map(Arc::new))
+            .map(|x| x.as_ref())
+    }
+
+    fn apply<'t>(&'t self, word: &str, tagger: &'t Tagger) -> Option<WordData<'t>> {
+        if !self.regex().ok()?.is_match(word) {
+            return None;
+        }
+
+        let lemma = match &self.lemma {
+            Some(lemma) => lemma.as_str(),
+            None => word,
+        };
+
+        Some(WordData::new(
+            tagger.id_word(lemma.to_string().into()),
+            tagger.id_tag(self.tag.as_str()),
+        ))
+    }
+}
+
 /// Options for a tokenizer.
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct TokenizerOptions {
-    /// Whether to allow errors while constructing the tokenizer.
-    pub allow_errors: bool,
+    /// What to do with disambiguation rules which can not be compiled.
+    /// Since disambiguation rules depend on each other, all rules after the first failing rule are skipped.
+    pub error_policy: ErrorPolicy,
     /// Whether to retain the last tag if disambiguation leads to an empty tag.
     /// Language-specific in LT so it has to be an option.
     pub retain_last: bool,
     /// Whether to use a heuristic to split potential compound words.
     pub use_compound_split_heuristic: bool,
     /// Whether to always add tags for a lowercase version of the word when assigning part-of-speech tags.
+    /// Otherwise they are only added at sentence start or if the word itself has no tags.
     pub always_add_lower_tags: bool,
+    /// Whether to add tags for the title case version of uppercase words e. g. for "Paris" if the word is "PARIS".
+    pub add_title_case_tags: bool,
+    /// Prefixes of part-of-speech tags which are never added from the lowercase version of a word.
+    pub never_lowercase_tags: Vec<String>,
     /// Disambiguation Rule IDs to use in this tokenizer.
-    #[serde(default)]
     pub ids: Vec<String>,
     /// Disambiguation Rule IDs to ignore in this tokenizer.
-    #[serde(default)]
     pub ignore_ids: Vec<String>,
+    /// What to do if disambiguation rules in different files have the same ID when compiling from multiple files.
+    pub duplicate_ids: DuplicateIdPolicy,
     /// Specific examples in the notation `{id}:{example_index}` which are known to fail.
-    #[serde(default)]
     pub known_failures: Vec<String>,
     /// Used part-of-speech tags which are not in the tagger dictionary.
-    #[serde(default)]
     pub extra_tags: Vec<String>,
+    /// Whether URLs, email addresses and file paths are immunized i. e. immune to rule matches.
+    /// These are always kept as one token with the special `OPAQUE` part-of-speech tag.
+    pub immunize_opaque_tokens: bool,
+    /// How to split hyphenated compounds. If a compound is split, each fragment keeps the
+    /// readings of the whole compound in addition to its own readings.
+    pub split_hyphens: HyphenSplit,
+    /// Heuristics to guess readings of words which are not in the tagger dictionary.
+    /// Guessed words additionally get the special `GUESSED` part-of-speech tag.
+    pub unknown_word_heuristics: Vec<UnknownWordHeuristic>,
+    /// Rules to tag words which are not in the tagger dictionary by their form (e. g. "42" as a cardinal number).
+    /// These take precedence over the [unknown word heuristics][TokenizerOptions::unknown_word_heuristics]
+    /// and do not add the `GUESSED` tag.
+    pub special_token_rules: Vec<SpecialTokenRule>,
+    /// Abbreviations (including the trailing period, e. g. "Dr." or "e.g.") after which there is usually no sentence break.
+    pub abbreviations: Vec<String>,
+    /// Additional abbreviations e. g. for a specific domain. Can be changed at runtime with [Tokenizer::mut_options].
+    pub extra_abbreviations: Vec<String>,
+    /// Elided words including the apostrophe (e. g. "l'" or "qu'" in French) which are split off
+    /// from the following word as one token. Straight and typographic apostrophes are treated the same.
+    pub elisions: Vec<String>,
+    /// Which part-of-speech tags the chunker uses if its own predictions disagree with the tagger.
+    pub chunker_tag_source: ChunkerTagSource,
 }
 
 impl Default for TokenizerOptions {
     fn default() -> Self {
         TokenizerOptions {
-            allow_errors: false,
+            error_policy: ErrorPolicy::Fail,
             retain_last: false,
             use_compound_split_heuristic: false,
             always_add_lower_tags: false,
+            add_title_case_tags: false,
+            never_lowercase_tags: Vec::new(),
             ids: Vec::new(),
             ignore_ids: Vec::new(),
+            duplicate_ids: DuplicateIdPolicy::default(),
             known_failures: Vec::new(),
             extra_tags: Vec::new(),
+            immunize_opaque_tokens: true,
+            split_hyphens: HyphenSplit::default(),
+            unknown_word_heuristics: Vec::new(),
+            special_token_rules: Vec::new(),
+            abbreviations: Vec::new(),
+            extra_abbreviations: Vec::new(),
+            elisions: Vec::new(),
+            chunker_tag_source: ChunkerTagSource::default(),
         }
     }
 }
 
+impl TokenizerOptions {
+    /// Creates a builder starting from the default options.
+    pub fn builder() -> TokenizerOptionsBuilder {
+        TokenizerOptionsBuilder::default()
+    }
+}
+
+/// Builder for [TokenizerOptions]. See [TokenizerOptions::builder].
+#[derive(Default)]
+pub struct TokenizerOptionsBuilder {
+    options: TokenizerOptions,
+}
+
+impl TokenizerOptionsBuilder {
+    /// Sets [TokenizerOptions::error_policy].
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.options.error_policy = error_policy;
+        self
+    }
+
+    /// Sets [TokenizerOptions::retain_last].
+    pub fn retain_last(mut self, retain_last: bool) -> Self {
+        self.options.retain_last = retain_last;
+        self
+    }
+
+    /// Sets [TokenizerOptions::use_compound_split_heuristic].
+    pub fn use_compound_split_heuristic(mut self, use_compound_split_heuristic: bool) -> Self {
+        self.options.use_compound_split_heuristic = use_compound_split_heuristic;
+        self
+    }
+
+    /// Sets [TokenizerOptions::always_add_lower_tags].
+    pub fn always_add_lower_tags(mut self, always_add_lower_tags: bool) -> Self {
+        self.options.always_add_lower_tags = always_add_lower_tags;
+        self
+    }
+
+    /// Sets [TokenizerOptions::add_title_case_tags].
+    pub fn add_title_case_tags(mut self, add_title_case_tags: bool) -> Self {
+        self.options.add_title_case_tags = add_title_case_tags;
+        self
+    }
+
+    /// Sets [TokenizerOptions::never_lowercase_tags].
+    pub fn never_lowercase_tags(mut self, never_lowercase_tags: Vec<String>) -> Self {
+        self.options.never_lowercase_tags = never_lowercase_tags;
+        self
+    }
+
+    /// Sets [TokenizerOptions::ids].
+    pub fn ids(mut self, ids: Vec<String>) -> Self {
+        self.options.ids = ids;
+        self
+    }
+
+    /// Sets [TokenizerOptions::ignore_ids].
+    pub fn ignore_ids(mut self, ignore_ids: Vec<String>) -> Self {
+        self.options.ignore_ids = ignore_ids;
+        self
+    }
+
+    /// Sets [TokenizerOptions::duplicate_ids].
+    pub fn duplicate_ids(mut self, duplicate_ids: DuplicateIdPolicy) -> Self {
+        self.options.duplicate_ids = duplicate_ids;
+        self
+    }
+
+    /// Sets [TokenizerOptions::known_failures].
+    pub fn known_failures(mut self, known_failures: Vec<String>) -> Self {
+        self.options.known_failures = known_failures;
+        self
+    }
+
+    /// Sets [TokenizerOptions::extra_tags].
+    pub fn extra_tags(mut self, extra_tags: Vec<String>) -> Self {
+        self.options.extra_tags = extra_tags;
+        self
+    }
+
+    /// Sets [TokenizerOptions::immunize_opaque_tokens].
+    pub fn immunize_opaque_tokens(mut self, immunize_opaque_tokens: bool) -> Self {
+        self.options.immunize_opaque_tokens = immunize_opaque_tokens;
+        self
+    }
+
+    /// Sets [TokenizerOptions::split_hyphens].
+    pub fn split_hyphens(mut self, split_hyphens: HyphenSplit) -> Self {
+        self.options.split_hyphens = split_hyphens;
+        self
+    }
+
+    /// Sets [TokenizerOptions::unknown_word_heuristics].
+    pub fn unknown_word_heuristics(
+        mut self,
+        unknown_word_heuristics: Vec<UnknownWordHeuristic>,
+    ) -> Self {
+        self.options.unknown_word_heuristics = unknown_word_heuristics;
+        self
+    }
+
+    /// Sets [TokenizerOptions::special_token_rules].
+    pub fn special_token_rules(mut self, special_token_rules: Vec<SpecialTokenRule>) -> Self {
+        self.options.special_token_rules = special_token_rules;
+        self
+    }
+
+    /// Sets [TokenizerOptions::abbreviations].
+    pub fn abbreviations(mut self, abbreviations: Vec<String>) -> Self {
+        self.options.abbreviations = abbreviations;
+        self
+    }
+
+    /// Sets [TokenizerOptions::extra_abbreviations].
+    pub fn extra_abbreviations(mut self, extra_abbreviations: Vec<String>) -> Self {
+        self.options.extra_abbreviations = extra_abbreviations;
+        self
+    }
+
+    /// Sets [TokenizerOptions::elisions].
+    pub fn elisions(mut self, elisions: Vec<String>) -> Self {
+        self.options.elisions = elisions;
+        self
+    }
+
+    /// Sets [TokenizerOptions::chunker_tag_source].
+    pub fn chunker_tag_source(mut self, chunker_tag_source: ChunkerTagSource) -> Self {
+        self.options.chunker_tag_source = chunker_tag_source;
+        self
+    }
+
+    /// Validates and returns the options.
+    pub fn build(self) -> Result<TokenizerOptions, Error> {
+        crate::utils::validate_ids(&self.options.ids, &self.options.ignore_ids)?;
+        for rule in &self.options.special_token_rules {
+            rule.regex()?;
+        }
+        Ok(self.options)
+    }
+}
+
+/// Options for a single call to the tokenizer or the rules.
+/// Unlike the [TokenizerOptions], these are not stored in the binary and can be changed for each call.
+#[derive(Debug, Clone)]
+pub struct AnalyzeOptions {
+    /// Whether to run the chunker. If false, tokens have no chunks and rules which need
+    /// chunk information are skipped.
+    pub chunk: bool,
+    /// The minimum number of sentences in a text for the sentences to be processed in parallel.
+    /// Shorter texts are processed sequentially since spawning threads would not pay off.
+    pub min_parallel_sentences: usize,
+    /// Whether to keep what the groups of the rules matched in [Suggestion::groups][crate::types::Suggestion::groups]
+    /// e. g. to debug rules. Off by default since it costs time and memory for every suggestion.
+    pub keep_groups: bool,
+}
+
+impl Default for AnalyzeOptions {
+    fn default() -> Self {
+        AnalyzeOptions {
+            chunk: true,
+            min_parallel_sentences: 8,
+            keep_groups: false,
+        }
+    }
+}
+
+/// How the readings of a token were changed by a disambiguation rule.
+#[derive(Debug, PartialEq)]
+pub enum ReadingChange {
+    /// Readings were added and none were removed.
+    Added(Vec<owned::WordData>),
+    /// Readings were removed and none were added.
+    Removed(Vec<owned::WordData>),
+    /// Some readings were removed and others added (e. g. by replacing them).
+    Modified {
+        removed: Vec<owned::WordData>,
+        added: Vec<owned::WordData>,
+    },
+}
+
+impl ReadingChange {
+    fn new(before: &[WordData], after: &[WordData]) -> Option<Self> {
+        let removed: Vec<_> = before
+            .iter()
+            .filter(|x| !after.contains(x))
+            .map(|x| x.to_owned_word_data())
+            .collect();
+        let added: Vec<_> = after
+            .iter()
+            .filter(|x| !before.contains(x))
+            .map(|x| x.to_owned_word_data())
+            .collect();
+
+        match (removed.is_empty(), added.is_empty()) {
+            (true, true) => None,
+            (true, false) => Some(ReadingChange::Added(added)),
+            (false, true) => Some(ReadingChange::Removed(removed)),
+            (false, false) => Some(ReadingChange::Modified { removed, added }),
+        }
+    }
+}
+
+/// An entry in the log of a traced disambiguation.
+#[derive(Debug, PartialEq)]
+pub struct DisambiguationTrace {
+    /// The ID of the [DisambiguationRule] which made the change.
+    pub rule_id: String,
+    /// The range of token indices which were changed in the same way.
+    pub token_range: Range<usize>,
+    /// How the readings of the tokens were changed.
+    pub change: ReadingChange,
+}
+
+/// Appends the changes `rule_id` made from `before` to `after` to the trace.
+/// Consecutive tokens with the same change are merged into one entry.
+fn trace_changes(
+    trace: &mut Vec<DisambiguationTrace>,
+    rule_id: &str,
+    before: &[IncompleteToken],
+    after: &[IncompleteToken],
+) {
+    let mut entries: Vec<DisambiguationTrace> = Vec::new();
+
+    for (i, (before, after)) in before.iter().zip(after).enumerate() {
+        if let Some(change) = ReadingChange::new(&before.word.tags, &after.word.tags) {
+            match entries.last_mut() {
+                Some(last) if last.token_range.end == i && last.change == change => {
+                    last.token_range.end = i + 1;
+                }
+                _ => entries.push(DisambiguationTrace {
+                    rule_id: rule_id.to_string(),
+                    token_range: i..i + 1,
+                    change,
+                }),
+            }
+        }
+    }
+
+    trace.extend(entries);
+}
+
 /// The complete Tokenizer doing tagging, chunking and disambiguation.
+///
+/// A tokenizer is `Send + Sync`, so it can be loaded once and shared between threads e. g. in a static `OnceCell`.
 #[derive(Serialize, Deserialize, Default)]
 pub struct Tokenizer {
+    pub(crate) format_version: FormatVersion,
+    pub(crate) header: Option<Header>,
     pub(crate) rules: Vec<DisambiguationRule>,
     pub(crate) chunker: Option<Chunker>,
+    pub(crate) multiword_tagger: Option<MultiwordTagger>,
     pub(crate) tagger: Arc<Tagger>,
     pub(crate) options: TokenizerOptions,
+    #[serde(skip)]
+    pub(crate) parallelism: ParallelismConfig,
 }
 
 impl Tokenizer {
-    /// Creates a new tokenizer from a file.
-    pub fn new<P: AsRef<Path>>(p: P) -> bincode::Result<Self> {
-        let reader = BufReader::new(File::open(p).unwrap());
-        bincode::deserialize_from(reader)
+    /// Creates a new tokenizer from a file. Fails with [Error::Binary] if the file is not a tokenizer binary
+    /// this version of nlprule can read.
+    pub fn new<P: AsRef<Path>>(p: P) -> Result<Self, Error> {
+        Self::new_from(File::open(p)?)
     }
 
     /// Creates a new tokenizer from a reader.
-    pub fn new_from<R: Read>(reader: R) -> bincode::Result<Self> {
-        bincode::deserialize_from(reader)
+    pub fn new_from<R: Read>(reader: R) -> Result<Self, Error> {
+        crate::read_binary(reader)
+    }
+
+    /// Creates a new tokenizer from bytes e. g. a binary embedded with `include_bytes!`. Uncompressed binaries
+    /// are deserialized without copying the bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        crate::read_binary_bytes(bytes)
+    }
+
+    /// Creates a new tokenizer from a file like [Tokenizer::new], but faster: the file is memory-mapped instead of
+    /// read and the regexes are compiled on first use instead of while loading. The suggestions are the same.
+    /// Regexes of binaries built with the other regex backend are still compiled while loading, so they fail here.
+    /// The file must not be modified while loading.
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap<P: AsRef<Path>>(p: P) -> Result<Self, Error> {
+        crate::read_binary_mmap(p.as_ref())
+    }
+
+    /// Writes the tokenizer as binary with the `compression`. The compression is detected when loading the binary,
+    /// see [Tokenizer::new_from].
+    pub fn to_writer_compressed<W: Write>(
+        &self,
+        writer: W,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        crate::write_binary(writer, self, compression)
     }
 
     pub fn rules(&self) -> &Vec<DisambiguationRule> {
         &self.rules
     }
 
+    /// What the tokenizer was compiled from, `None` if it was not compiled.
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
     pub fn tagger(&self) -> &Arc<Tagger> {
         &self.tagger
     }
 
+    /// Mutable access to the tagger e. g. to add words to the user dictionary.
+    /// Fails with [Error::SharedTagger] if the tagger is shared i. e. the `Arc` returned by [Tokenizer::tagger]
+    /// was cloned. The tagger is large, so it is not copied implicitly.
+    pub fn mut_tagger(&mut self) -> Result<&mut Tagger, Error> {
+        Arc::get_mut(&mut self.tagger).ok_or(Error::SharedTagger)
+    }
+
     pub fn chunker(&self) -> &Option<Chunker> {
         &self.chunker
     }
 
+    /// Mutable access to the chunker e. g. to change its [options][chunk::Chunker::set_options].
+    pub fn mut_chunker(&mut self) -> Option<&mut Chunker> {
+        self.chunker.as_mut()
+    }
+
+    /// Replaces the chunker e. g. with one loaded by [Chunker::from_reader]. If `None`, no chunking is done.
+    pub fn set_chunker(&mut self, chunker: Option<Chunker>) {
+        self.chunker = chunker;
+    }
+
+    pub fn multiword_tagger(&self) -> &Option<MultiwordTagger> {
+        &self.multiword_tagger
+    }
+
     pub fn options(&self) -> &TokenizerOptions {
         &self.options
     }
 
+    /// Mutable access to the options e. g. to add abbreviations at runtime.
+    pub fn mut_options(&mut self) -> &mut TokenizerOptions {
+        &mut self.options
+    }
+
+    /// How disambiguation is parallelized. See [Tokenizer::set_parallelism].
+    pub fn parallelism(&self) -> Parallelism {
+        self.parallelism.parallelism()
+    }
+
+    /// Sets how disambiguation is parallelized for this tokenizer. Defaults to [Parallelism::Auto].
+    /// Fails if the thread pool for [Parallelism::Threads] can not be created.
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) -> Result<(), Error> {
+        self.parallelism = ParallelismConfig::new(parallelism)?;
+        Ok(())
+    }
+
+    fn abbreviation(&self, word: &str) -> Option<&str> {
+        self.options
+            .abbreviations
+            .iter()
+            .chain(self.options.extra_abbreviations.iter())
+            .find(|x| x.eq_ignore_ascii_case(word))
+            .map(|x| x.as_str())
+    }
+
+    /// Whether the break between `prev` (including trailing whitespace) and `next` is caused by an
+    /// abbreviation or an initial like the "J." in "J. R. R. Tolkien" i. e. is not a sentence break.
+    fn is_abbreviation_break(&self, prev: &str, next: &str) -> bool {
+        // a newline is a strong indicator of a new sentence
+        if prev[prev.trim_end().len()..].contains('\n') {
+            return false;
+        }
+
+        let last_word = prev
+            .split_whitespace()
+            .last()
+            .unwrap_or("")
+            .trim_start_matches(|c: char| !c.is_alphanumeric());
+        let next_word = next
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .trim_start_matches(|c: char| !c.is_alphanumeric())
+            .trim_end_matches(|c: char| !c.is_alphanumeric());
+
+        let mut chars = last_word.chars();
+        let is_initial = matches!(
+            (chars.next(), chars.next(), chars.next()),
+            (Some(c), Some('.'), None) if c.is_uppercase()
+        );
+
+        if is_initial {
+            return true;
+        }
+
+        match self.abbreviation(last_word) {
+            // abbreviations starting with an uppercase char are titles like "Dr." which precede names
+            Some(abbreviation) if abbreviation.starts_with(char::is_uppercase) => true,
+            // other abbreviations like "etc." can end a sentence: assume they do if the next
+            // word is capitalized and its lowercase variant is a known word (i. e. it is not a name)
+            Some(_) => {
+                !next_word.starts_with(char::is_uppercase)
+                    || self
+                        .tagger
+                        .get_tags(&next_word.to_lowercase(), false)
+                        .is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Gets the start and end pointers of each sentence in the text.
+    /// Sentence ends exclude trailing whitespace. Sentences never end inside of one of the `opaque` tokens
+    /// e. g. at a period in a URL.
+    fn get_sentence_indices(
+        &self,
+        text: &str,
+        opaque: &[&str],
+    ) -> (HashSet<usize>, HashSet<usize>) {
+        let mut sentences: Vec<(usize, usize)> = Vec::new();
+        let opaque_spans: Vec<_> = opaque
+            .iter()
+            .map(|x| {
+                let start = x.as_ptr() as usize - text.as_ptr() as usize;
+                (start, start + x.len())
+            })
+            .collect();
+
+        for sentence in text.unicode_sentences() {
+            let start = sentence.as_ptr() as usize - text.as_ptr() as usize;
+            let end = start + sentence.len();
+            let is_in_opaque = opaque_spans
+                .iter()
+                .any(|(opaque_start, opaque_end)| *opaque_start < start && start < *opaque_end);
+
+            match sentences.last_mut() {
+                Some(last)
+                    if is_in_opaque
+                        || self.is_abbreviation_break(&text[last.0..last.1], sentence) =>
+                {
+                    last.1 = end;
+                }
+                _ => sentences.push((start, end)),
+            }
+        }
+
+        let ptr = text.as_ptr() as usize;
+        sentences
+            .into_iter()
+            .fold((HashSet::new(), HashSet::new()), |mut a, (start, end)| {
+                a.0.insert(ptr + start);
+                a.1.insert(ptr + start + text[start..end].trim_end().len());
+                a
+            })
+    }
+
+    /// Gets the fragments of a hyphenated compound if it should be split according to the options.
+    fn split_compound<'t>(&self, text: &'t str) -> Option<Vec<&'t str>> {
+        if self.options.split_hyphens == HyphenSplit::Never {
+            return None;
+        }
+
+        let fragments = split_hyphenated(text)?;
+
+        match self.options.split_hyphens {
+            HyphenSplit::Never => None,
+            HyphenSplit::Always => Some(fragments),
+            HyphenSplit::Heuristic => {
+                if self
+                    .tagger
+                    .get_tags(text, self.options.always_add_lower_tags)
+                    .is_empty()
+                {
+                    Some(fragments)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Gets the normalized form (i. e. with a straight apostrophe) of the word if it is an elision.
+    fn elision(&self, word: &str) -> Option<String> {
+        if !word.ends_with(is_apostrophe) {
+            return None;
+        }
+
+        let normalized = word.replace('’', "'");
+        if self
+            .options
+            .elisions
+            .iter()
+            .any(|x| x.replace('’', "'").to_lowercase() == normalized.to_lowercase())
+        {
+            Some(normalized)
+        } else {
+            None
+        }
+    }
+
+    /// Merges elided words and their apostrophe (e. g. "l" and "'" in "l'homme") into one token.
+    fn merge_elisions<'t>(
+        &self,
+        text: &'t str,
+        token_strs: Vec<(&'t str, bool)>,
+    ) -> Vec<(&'t str, bool)> {
+        if self.options.elisions.is_empty() {
+            return token_strs;
+        }
+
+        let mut merged = Vec::new();
+        let mut i = 0;
+
+        while i < token_strs.len() {
+            if let [(word, false), (apostrophe, false), (next, _), ..] = token_strs[i..] {
+                let start = word.as_ptr() as usize - text.as_ptr() as usize;
+                let end = start + word.len() + apostrophe.len();
+
+                if apostrophe.chars().all(is_apostrophe)
+                    && apostrophe.chars().count() == 1
+                    && next.starts_with(char::is_alphabetic)
+                    && self.elision(&text[start..end]).is_some()
+                {
+                    merged.push((&text[start..end], false));
+                    i += 2;
+                    continue;
+                }
+            }
+
+            merged.push(token_strs[i]);
+            i += 1;
+        }
+
+        merged
+    }
+
+    /// Guesses readings for a word which is not in the tagger dictionary using the special token rules
+    /// or, if none of them match, the unknown word heuristics.
+    fn guess_tags<'t>(&'t self, word: &str) -> Vec<WordData<'t>> {
+        let special_tags: Vec<_> = self
+            .options
+            .special_token_rules
+            .iter()
+            .filter_map(|x| x.apply(word, &self.tagger))
+            .collect();
+
+        if !special_tags.is_empty() {
+            return special_tags;
+        }
+
+        let mut tags: Vec<_> = self
+            .options
+            .unknown_word_heuristics
+            .iter()
+            .filter_map(|x| x.guess(word, &self.tagger))
+            .collect();
+
+        if !tags.is_empty() {
+            tags.push(WordData::new(
+                self.tagger.id_word(word.to_string().into()),
+                self.tagger.id_tag("GUESSED"),
+            ));
+        }
+
+        tags
+    }
+
     pub(crate) fn disambiguate_up_to_id<'t>(
         &'t self,
         mut tokens: Vec<IncompleteToken<'t>>,
         id: Option<&str>,
+        options: &AnalyzeOptions,
+        mut trace: Option<&mut Vec<DisambiguationTrace>>,
     ) -> Vec<IncompleteToken<'t>> {
+        if tokens.is_empty() {
+            return tokens;
+        }
+
         let n = id.map_or(self.rules.len(), |id| {
             self.rules.iter().position(|x| x.id == id).unwrap()
         });
@@ -171,21 +946,30 @@ impl Tokenizer {
 
         while i < n {
             let finalized = finalize(tokens.clone());
-            let result = self.rules[i..n]
-                .maybe_par_iter()
-                .enumerate()
-                .filter_map(|(j, rule)| {
-                    let changes = rule.apply(&finalized, &self);
-                    if changes.is_empty() {
-                        None
-                    } else {
-                        Some((j + i, changes))
-                    }
-                })
-                .find_first(|_| true);
+            let result = self.parallelism.install(|| {
+                self.rules[i..n]
+                    .maybe_par_iter_with(&self.parallelism)
+                    .enumerate()
+                    .filter(|(_, rule)| options.chunk || !rule.uses_chunker())
+                    .filter_map(|(j, rule)| {
+                        let changes = rule.apply(&finalized, self);
+                        if changes.is_empty() {
+                            None
+                        } else {
+                            Some((j + i, changes))
+                        }
+                    })
+                    .find_first(|_| true)
+            });
 
             if let Some((index, changes)) = result {
+                // only copy the tokens if the trace is requested
+                let before = trace.as_ref().map(|_| tokens.clone());
                 self.rules[index].change(&mut tokens, &self, changes);
+
+                if let (Some(trace), Some(before)) = (trace.as_mut(), before) {
+                    trace_changes(trace, &self.rules[index].id, &before, &tokens);
+                }
                 i = index + 1;
             } else {
                 i = n;
@@ -201,52 +985,133 @@ impl Tokenizer {
         &'t self,
         tokens: Vec<IncompleteToken<'t>>,
     ) -> Vec<IncompleteToken<'t>> {
-        self.disambiguate_up_to_id(tokens, None)
+        self.disambiguate_with(tokens, &AnalyzeOptions::default())
+    }
+
+    /// Apply rule-based disambiguation to the tokens with the given options.
+    /// If chunking is turned off, disambiguation rules which need chunk information are skipped.
+    pub fn disambiguate_with<'t>(
+        &'t self,
+        tokens: Vec<IncompleteToken<'t>>,
+        options: &AnalyzeOptions,
+    ) -> Vec<IncompleteToken<'t>> {
+        self.disambiguate_up_to_id(tokens, None, options, None)
+    }
+
+    /// Tokenizes and disambiguates the given text while recording which [DisambiguationRule]
+    /// changed which readings. Useful for finding out why a grammar rule does not match.
+    /// Returns the disambiguated tokens together with the changes in the order they were made.
+    pub fn analyze_traced<'t>(
+        &'t self,
+        text: &'t str,
+    ) -> (Vec<IncompleteToken<'t>>, Vec<DisambiguationTrace>) {
+        let options = AnalyzeOptions::default();
+        let mut trace = Vec::new();
+
+        let tokens = self.disambiguate_up_to_id(
+            self.tokenize_with(text, &options),
+            None,
+            &options,
+            Some(&mut trace),
+        );
+
+        (tokens, trace)
     }
 
     /// Tokenize the given text. This applies chunking and tagging, but does not do disambiguation.
     pub fn tokenize<'t>(&'t self, text: &'t str) -> Vec<IncompleteToken<'t>> {
-        let sentence_indices = text
-            .unicode_sentences()
-            .map(|sentence| {
-                let ptr = sentence.as_ptr() as usize;
-                (ptr, ptr + sentence.len())
-            })
-            .fold((HashSet::new(), HashSet::new()), |mut a, x| {
-                a.0.insert(x.0);
-                a.1.insert(x.1);
-                a
-            });
+        self.tokenize_with(text, &AnalyzeOptions::default())
+    }
+
+    /// Tokenize the given text with the given options. See [tokenize][Tokenizer::tokenize].
+    pub fn tokenize_with<'t>(
+        &'t self,
+        text: &'t str,
+        options: &AnalyzeOptions,
+    ) -> Vec<IncompleteToken<'t>> {
+        let token_strs = self.merge_elisions(text, get_token_strs(text));
+        let opaque: Vec<_> = token_strs
+            .iter()
+            .filter_map(|(x, is_opaque)| if *is_opaque { Some(*x) } else { None })
+            .collect();
+        let sentence_indices = self.get_sentence_indices(text, &opaque);
+
+        let paragraph_starts = get_paragraph_starts(text);
 
         let mut current_char = 0;
-        let token_strs = get_token_strs(text);
+        let token_strs = token_strs
+            .into_iter()
+            .flat_map(|(x, is_opaque)| match (is_opaque, self.split_compound(x)) {
+                (false, Some(fragments)) => fragments
+                    .into_iter()
+                    .map(|fragment| (fragment, false, Some(x)))
+                    .collect(),
+                _ => vec![(x, is_opaque, None)],
+            })
+            .collect::<Vec<_>>();
         let mut tokens: Vec<_> = token_strs
             .into_iter()
-            .map(|x| {
+            .map(|(x, is_opaque, parent)| {
                 let char_start = current_char;
                 let ptr = x.as_ptr() as usize;
                 current_char += x.chars().count();
 
                 let byte_start = ptr - text.as_ptr() as usize;
-                let trimmed = x.trim();
+                let trimmed = x.trim_matches(is_ignorable);
+                // elisions are looked up with a straight apostrophe
+                let word: Cow<str> = match self.elision(trimmed) {
+                    Some(normalized) => normalized.into(),
+                    None => trimmed.into(),
+                };
 
                 let is_sentence_start = sentence_indices.0.contains(&ptr);
                 let is_sentence_end = sentence_indices.1.contains(&(ptr + x.len()));
 
-                IncompleteToken {
-                    word: Word::new_with_tags(
+                let mut tags = if is_opaque {
+                    vec![WordData::new(
                         self.tagger.id_word(trimmed.into()),
-                        self.tagger.get_tags(
-                            trimmed,
-                            is_sentence_start || self.options.always_add_lower_tags,
-                            self.options.use_compound_split_heuristic,
-                        ),
-                    ),
+                        self.tagger.id_tag("OPAQUE"),
+                    )]
+                } else {
+                    let tags = self.tagger.get_tags_with(
+                        &word,
+                        is_sentence_start || self.options.always_add_lower_tags,
+                        &self.options,
+                    );
+
+                    if tags.is_empty() {
+                        self.guess_tags(&word)
+                    } else {
+                        tags
+                    }
+                };
+
+                // fragments of a split compound keep the readings of the whole compound
+                if let Some(parent) = parent.filter(|_| trimmed != "-") {
+                    tags.extend(self.tagger.get_tags_with(
+                        parent,
+                        sentence_indices.0.contains(&(parent.as_ptr() as usize))
+                            || self.options.always_add_lower_tags,
+                        &self.options,
+                    ));
+                }
+
+                IncompleteToken {
+                    word: Word::new_with_tags(self.tagger.id_word(word), tags),
                     char_span: (char_start, current_char),
                     byte_span: (byte_start, byte_start + x.len()),
                     is_sentence_end,
-                    has_space_before: text[..byte_start].ends_with(char::is_whitespace),
+                    has_space_before: text[..byte_start].ends_with(is_ignorable),
                     chunks: Vec::new(),
+                    chunk_confidence: None,
+                    paragraph: paragraph_starts
+                        .iter()
+                        .take_while(|x| **x <= byte_start)
+                        .count(),
+                    is_paragraph_start: false,
+                    is_paragraph_end: false,
+                    immunized: is_opaque && self.options.immunize_opaque_tokens,
+                    ignore_spelling: false,
                     text,
                     tagger: self.tagger.as_ref(),
                 }
@@ -258,8 +1123,26 @@ impl Tokenizer {
             let last_idx = tokens.len() - 1;
             tokens[last_idx].is_sentence_end = true;
 
+            for i in 0..tokens.len() {
+                tokens[i].is_paragraph_start =
+                    i == 0 || tokens[i - 1].paragraph != tokens[i].paragraph;
+                tokens[i].is_paragraph_end =
+                    i == last_idx || tokens[i + 1].paragraph != tokens[i].paragraph;
+            }
+
             if let Some(chunker) = &self.chunker {
-                chunker.apply(&mut tokens);
+                if options.chunk {
+                    chunker.apply_with(
+                        &mut tokens,
+                        self.options.chunker_tag_source,
+                        &self.parallelism,
+                    );
+                }
+            }
+
+            // multiword tags are added after chunking like in LanguageTool where the multiword chunker is a disambiguator
+            if let Some(multiword_tagger) = &self.multiword_tagger {
+                multiword_tagger.apply(&mut tokens);
             }
         }
 
@@ -269,11 +1152,16 @@ impl Tokenizer {
 
 #[cfg(test)]
 mod tests {
-    use super::Tokenizer;
-    use lazy_static::lazy_static;
-    use quickcheck_macros::quickcheck;
-    use std::fs::File;
-    use std::io::BufReader;
+    use super::{
+        get_paragraph_starts, get_token_strs, split_hyphenated, HyphenSplit, ReadingChange,
+        Tokenizer, TokenizerOptions,
+    };
+    use crate::{
+        types::{owned, PosId, WordData, WordId},
+        Error, ErrorPolicy,
+    };
+    use std::{fs::File, io::BufReader};
+    use {lazy_static::lazy_static, quickcheck_macros::quickcheck};
 
     #[quickcheck]
     fn can_tokenize_anything(text: String) -> bool {
@@ -287,4 +1175,488 @@ mod tests {
         TOKENIZER.tokenize(&text);
         true
     }
+
+    #[test]
+    fn options_can_be_built_and_validated() {
+        let options = TokenizerOptions::builder()
+            .error_policy(ErrorPolicy::Ignore)
+            .split_hyphens(HyphenSplit::Always)
+            .build()
+            .unwrap();
+        assert_eq!(options.error_policy, ErrorPolicy::Ignore);
+        assert!(options.immunize_opaque_tokens);
+        assert_eq!(options.split_hyphens, HyphenSplit::Always);
+
+        let result = TokenizerOptions::builder()
+            .ids(vec!["A".into()])
+            .ignore_ids(vec!["A".into()])
+            .build();
+        assert!(matches!(result, Err(Error::ConflictingIds(id)) if id == "A"));
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(Tokenizer::new("does/not/exist.bin").is_err());
+    }
+
+    #[test]
+    fn opaque_tokens_are_not_split() {
+        let text =
+            "see https://example.com/foo_bar?x=1. Mail me@example.org, or open /usr/local/lib!";
+
+        let opaque: Vec<_> = get_token_strs(text)
+            .into_iter()
+            .filter_map(|(x, is_opaque)| if is_opaque { Some(x) } else { None })
+            .collect();
+
+        assert_eq!(
+            opaque,
+            vec![
+                "https://example.com/foo_bar?x=1",
+                "me@example.org",
+                "/usr/local/lib"
+            ]
+        );
+    }
+
+    #[test]
+    fn punctuation_after_opaque_token_is_own_token() {
+        let tokens: Vec<_> = get_token_strs("Visit www.example.com.")
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+
+        assert_eq!(tokens, vec!["Visit", " ", "www.example.com", "."]);
+    }
+
+    #[test]
+    fn slashes_in_words_are_not_paths() {
+        assert!(get_token_strs("and/or he/she 1/2/3")
+            .into_iter()
+            .all(|(_, is_opaque)| !is_opaque));
+    }
+
+    #[test]
+    fn hyphenated_compounds_are_split_into_fragments() {
+        assert_eq!(
+            split_hyphenated("state-of-the-art"),
+            Some(vec!["state", "-", "of", "-", "the", "-", "art"])
+        );
+        assert_eq!(split_hyphenated("-art"), None);
+        assert_eq!(split_hyphenated("state--art"), None);
+        assert_eq!(split_hyphenated("art"), None);
+    }
+
+    #[test]
+    fn paragraphs_start_after_two_newlines() {
+        let text = "First line.\nStill first.\n\nSecond. \n \r\n\nThird.";
+
+        let starts: Vec<_> = get_paragraph_starts(text)
+            .into_iter()
+            .map(|x| &text[x..])
+            .collect();
+
+        assert_eq!(starts, vec!["Second. \n \r\n\nThird.", "Third."]);
+    }
+
+    #[test]
+    fn reading_changes_are_classified() {
+        let noun = WordData::new(WordId("run".into(), None), PosId("NN", 0));
+        let verb = WordData::new(WordId("run".into(), None), PosId("VB", 1));
+        let owned_verb = || {
+            owned::WordData::new(
+                owned::WordId("run".into(), None),
+                owned::PosId("VB".into(), 1),
+            )
+        };
+
+        assert_eq!(ReadingChange::new(&[noun.clone()], &[noun.clone()]), None);
+        assert_eq!(
+            ReadingChange::new(&[noun.clone(), verb.clone()], &[noun.clone()]),
+            Some(ReadingChange::Removed(vec![owned_verb()]))
+        );
+        assert_eq!(
+            ReadingChange::new(&[noun.clone()], &[noun.clone(), verb.clone()]),
+            Some(ReadingChange::Added(vec![owned_verb()]))
+        );
+        assert!(matches!(
+            ReadingChange::new(&[noun], &[verb]),
+            Some(ReadingChange::Modified { .. })
+        ));
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod compile_tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        compile::testing::{chunker_json, setup},
+        tokenizer::{chunk::Chunker, finalize, ChunkerTagSource, Tokenizer, UnknownWordHeuristic},
+        Error,
+    };
+
+    #[test]
+    fn multiwords_get_phrase_tag() {
+        let (tokenizer, rules) = setup();
+
+        let text = "He lives in New York City.";
+        let tokens = tokenizer.tokenize(text);
+        let phrase_tags: Vec<_> = tokens
+            .iter()
+            .filter(|x| {
+                x.word.tags.iter().any(|data| {
+                    data.pos.as_ref() == "NNP" && data.lemma.as_ref() == "New York City"
+                })
+            })
+            .map(|x| x.word.text.as_ref())
+            .collect();
+        assert_eq!(phrase_tags, vec!["New", "York", "City"]);
+
+        let suggestions = rules.suggest(text, &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].source, "IN_PROPER_NOUN");
+        assert_eq!(suggestions[0].replacements, vec!["at New"]);
+
+        assert!(rules
+            .suggest("He lives in New Jersey.", &tokenizer)
+            .is_empty());
+    }
+
+    #[test]
+    fn unknown_words_get_guessed_readings() {
+        let (mut tokenizer, _) = setup();
+        tokenizer.options.unknown_word_heuristics = vec![
+            UnknownWordHeuristic {
+                prefix: String::new(),
+                suffix: "ly".into(),
+                tag: "RB".into(),
+                lemma_suffix: None,
+            },
+            UnknownWordHeuristic {
+                prefix: String::new(),
+                suffix: "s".into(),
+                tag: "VBZ".into(),
+                lemma_suffix: Some(String::new()),
+            },
+        ];
+
+        let tokens = tokenizer.tokenize("He dockerizes and runs it weekly.");
+        let readings: Vec<Vec<_>> = tokens
+            .iter()
+            .map(|x| {
+                x.word
+                    .tags
+                    .iter()
+                    .map(|data| (data.lemma.as_ref(), data.pos.as_ref()))
+                    .collect()
+            })
+            .collect();
+
+        assert_eq!(
+            readings,
+            vec![
+                vec![],
+                vec![("dockerize", "VBZ"), ("dockerizes", "GUESSED")],
+                vec![],
+                vec![("run", "VBZ")],
+                vec![],
+                vec![("weekly", "RB"), ("weekly", "GUESSED")],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn special_tokens_are_tagged() {
+        let (tokenizer, rules) = setup();
+
+        let suggestions = rules.suggest("There are 42 items.", &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].source, "NUMBER_ITEMS");
+        assert_eq!(suggestions[0].replacements, vec!["42 things"]);
+
+        let ordinal_tags = |text: &str| -> Vec<String> {
+            let tokens = tokenizer.disambiguate(tokenizer.tokenize(text));
+            let token = tokens
+                .iter()
+                .find(|x| x.word.text.as_ref() == "21st")
+                .unwrap();
+            token
+                .word
+                .tags
+                .iter()
+                .map(|x| x.pos.as_ref().to_string())
+                .collect()
+        };
+
+        // the ordinal reading is the only one left before "century"
+        assert_eq!(ordinal_tags("The 21st is a Monday."), vec!["JJ", "ORD"]);
+        assert_eq!(ordinal_tags("In the 21st century."), vec!["ORD"]);
+    }
+
+    #[test]
+    fn chunker_tag_source_is_applied() {
+        let (mut tokenizer, _) = setup();
+        // the POS model predicts "O" for every word, which disagrees with the tagger
+        let chunk_model = r#"{
+            "outcome_labels": ["B-NP", "I-NP", "O"],
+            "pmap": {
+                "t0=NN": { "parameters": [5.0], "outcomes": [0] },
+                "t0=DT": { "parameters": [5.0], "outcomes": [2] },
+                "t0=O": { "parameters": [5.0], "outcomes": [2] }
+            }
+        }"#;
+        let json = chunker_json(chunk_model);
+        tokenizer.set_chunker(Some(Chunker::from_json(json.as_bytes()).unwrap()));
+
+        let chunks = |tokenizer: &Tokenizer| -> Vec<_> {
+            tokenizer
+                .tokenize("the tool")
+                .iter()
+                .map(|x| x.chunks.clone())
+                .collect()
+        };
+
+        assert_eq!(chunks(&tokenizer), vec![vec!["O"], vec!["O"]]);
+
+        tokenizer.mut_options().chunker_tag_source = ChunkerTagSource::Tagger;
+        assert_eq!(
+            chunks(&tokenizer),
+            vec![vec!["O"], vec!["B-NP-singular", "E-NP-singular"]]
+        );
+    }
+
+    #[test]
+    fn pipeline_does_not_panic_on_adversarial_input() {
+        let (tokenizer, rules) = setup();
+
+        let texts = [
+            "",
+            " ",
+            "\t \n ",
+            "\n\n\n",
+            "\r\n\r\n",
+            "\u{0}",
+            "a\u{0}b",
+            "\u{7}\u{1b}[0m",
+            "\u{301}",
+            "\u{301}here",
+            "here\u{301}\u{301}",
+            "\u{200b}",
+            "\u{feff}It ends here",
+            "-",
+            "- -",
+            "--here--",
+            "here-",
+            "https://",
+            "@",
+            "/",
+            ".",
+            ". . .",
+            "?!",
+            "\"",
+            "🙂🙂 here 🙂",
+            "New York",
+            "New\n\nYork City",
+            "in New York City",
+            "ŉ here ǅ",
+        ];
+
+        for text in texts.iter() {
+            let n_chars = text.chars().count();
+
+            let tokens = tokenizer.disambiguate(tokenizer.tokenize(text));
+            for token in tokens.iter() {
+                assert!(token.char_span.0 <= token.char_span.1 && token.char_span.1 <= n_chars);
+                assert!(token.byte_span.0 <= token.byte_span.1 && token.byte_span.1 <= text.len());
+            }
+
+            for suggestion in rules.suggest(text, &tokenizer) {
+                assert!(suggestion.start <= suggestion.end && suggestion.end <= n_chars);
+            }
+            rules.correct(text, &tokenizer);
+        }
+
+        assert!(tokenizer.tokenize("").is_empty());
+
+        let words: Vec<_> = tokenizer
+            .tokenize("a\u{7}b\u{0}")
+            .into_iter()
+            .map(|x| x.word.text.as_ref().to_string())
+            .collect();
+        assert_eq!(words, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn abbreviations_do_not_end_sentences() {
+        let (mut tokenizer, _) = setup();
+        tokenizer.mut_options().abbreviations = vec!["Dr.".into(), "p.m.".into(), "etc.".into()];
+        tokenizer.mut_options().extra_abbreviations = vec!["e.g.".into()];
+
+        let sentence_ends = |text: &str| -> Vec<String> {
+            tokenizer
+                .tokenize(text)
+                .iter()
+                .filter(|x| x.is_sentence_end)
+                .map(|x| text[..x.byte_span.1].to_string())
+                .collect()
+        };
+
+        for text in [
+            "He met Dr. Smith at 5 p.m. on Monday.",
+            "Use a language, e.g. Rust.",
+            "The Hobbit is by J. R. R. Tolkien.",
+        ]
+        .iter()
+        {
+            assert_eq!(sentence_ends(text), vec![text.to_string()]);
+        }
+
+        assert_eq!(
+            sentence_ends("Bring pens, paper etc. The rest is here."),
+            vec![
+                "Bring pens, paper etc.",
+                "Bring pens, paper etc. The rest is here."
+            ]
+        );
+        assert_eq!(
+            sentence_ends("It ends at 5 p.m.\nNext one."),
+            vec!["It ends at 5 p.m.", "It ends at 5 p.m.\nNext one."]
+        );
+    }
+
+    #[test]
+    fn sentences_do_not_end_inside_opaque_tokens() {
+        let (tokenizer, _) = setup();
+        // the question mark would end a sentence if it was not part of the URL
+        let text = "See https://example.com/a.html?Q=1. It works.";
+        let url = &text[4..34];
+        assert_eq!(url, "https://example.com/a.html?Q=1");
+
+        let (starts, ends) = tokenizer.get_sentence_indices(text, &[url]);
+        let offsets = |pointers: HashSet<usize>| -> Vec<usize> {
+            let mut offsets: Vec<_> = pointers
+                .into_iter()
+                .map(|x| x - text.as_ptr() as usize)
+                .collect();
+            offsets.sort_unstable();
+            offsets
+        };
+        assert_eq!(offsets(starts), vec![0, 36]);
+        assert_eq!(offsets(ends), vec![35, text.len()]);
+
+        // without the URL the text is split at the question mark
+        let (starts, _) = tokenizer.get_sentence_indices(text, &[]);
+        assert_eq!(offsets(starts), vec![0, 31, 36]);
+
+        let tokens = tokenizer.tokenize(text);
+        let url_token = tokens.iter().find(|x| x.word.text.as_ref() == url).unwrap();
+        assert!(url_token.immunized && !url_token.is_sentence_end);
+    }
+
+    #[test]
+    fn user_words_are_tagged() {
+        let (mut tokenizer, rules) = setup();
+        let text = "He uses nlprule.";
+
+        assert!(rules.suggest(text, &tokenizer).is_empty());
+        assert_eq!(rules.suggest("He uses tool.", &tokenizer).len(), 1);
+
+        let shared = tokenizer.tagger().clone();
+        assert!(matches!(tokenizer.mut_tagger(), Err(Error::SharedTagger)));
+        drop(shared);
+
+        let tagger = tokenizer.mut_tagger().unwrap();
+        assert!(matches!(
+            tagger.add_word("nlprule", "nlprule", "NOT_A_TAG"),
+            Err(Error::UnknownTag(_))
+        ));
+        assert!(tagger
+            .add_words(&[("nlprules", "nlprule", "NN"), ("x", "x", "NOT_A_TAG")])
+            .is_err());
+        assert!(tagger.get_tags("nlprules", false).is_empty());
+        tagger.add_word("nlprule", "nlprule", "NN").unwrap();
+
+        let suggestions = rules.suggest(text, &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].source, "USES_NOUN");
+        assert_eq!(suggestions[0].replacements, vec!["uses a nlprule"]);
+
+        let mut dump = Vec::new();
+        tokenizer.tagger().write_user_words(&mut dump).unwrap();
+        assert_eq!(
+            String::from_utf8(dump.clone()).unwrap(),
+            "nlprule\tnlprule\tNN\n"
+        );
+
+        let tagger = tokenizer.mut_tagger().unwrap();
+        tagger.clear_user_words();
+        assert!(tagger.get_tags("nlprule", false).is_empty());
+        tagger.read_user_words(dump.as_slice()).unwrap();
+        assert_eq!(rules.suggest(text, &tokenizer).len(), 1);
+    }
+
+    #[test]
+    fn readings_can_be_inspected_and_retained() {
+        let (tokenizer, _) = setup();
+
+        let mut tokens = tokenizer.tokenize("It runs here");
+        assert_eq!(
+            tokens[1].readings().collect::<Vec<_>>(),
+            vec![("run", "VBZ")]
+        );
+
+        tokens[1].retain_readings(|_, pos| pos == "VBZ");
+        assert_eq!(
+            tokens[1].readings().collect::<Vec<_>>(),
+            vec![("run", "VBZ")]
+        );
+
+        tokens[1].retain_readings(|_, _| false);
+        assert_eq!(
+            tokens[1].readings().collect::<Vec<_>>(),
+            vec![("runs", "UNKNOWN")]
+        );
+
+        let mut tokens = finalize(tokens);
+        let here = tokens.last_mut().unwrap();
+        assert!(here.readings().any(|(_, pos)| pos == "SENT_END"));
+        here.retain_readings(|lemma, _| lemma == "here");
+        assert!(here.readings().all(|(lemma, _)| lemma == "here"));
+    }
+
+    #[test]
+    fn elisions_are_split_off() {
+        let (mut tokenizer, rules) = setup();
+
+        let words = |tokenizer: &Tokenizer, text: &str| -> Vec<String> {
+            tokenizer
+                .tokenize(text)
+                .into_iter()
+                .map(|x| x.word.text.as_ref().to_string())
+                .collect()
+        };
+
+        assert_eq!(words(&tokenizer, "don't"), vec!["don", "'", "t"]);
+        assert!(rules.suggest("C'est l'homme.", &tokenizer).is_empty());
+
+        tokenizer.mut_options().elisions = vec!["l'".into(), "qu’".into()];
+        assert_eq!(words(&tokenizer, "don't"), vec!["don", "'", "t"]);
+        assert_eq!(
+            words(&tokenizer, "L’homme qu'il"),
+            vec!["L'", "homme", "qu'", "il"]
+        );
+
+        for text in ["C'est l'homme.", "C’est l’homme."].iter() {
+            let suggestions = rules.suggest(text, &tokenizer);
+            assert_eq!(suggestions.len(), 1);
+            assert_eq!((suggestions[0].start, suggestions[0].end), (6, 13));
+        }
+
+        let tokens = tokenizer.tokenize("l’homme");
+        assert_eq!(tokens[0].char_span, (0, 2));
+        assert_eq!(tokens[0].byte_span, (0, 4));
+        assert_eq!(tokens[1].char_span, (2, 7));
+    }
 }
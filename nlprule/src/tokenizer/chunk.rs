@@ -2,11 +2,16 @@
 
 use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
 use std::{cmp::Ordering, collections::BinaryHeap};
 
-use crate::types::{DefaultHashMap, DefaultHasher};
+use crate::{
+    types::{DefaultHashMap, DefaultHasher},
+    utils::parallelism::{MaybeParallelRefIterator, ParallelismConfig},
+    Error,
+};
 
-use super::IncompleteToken;
+use super::{ChunkerTagSource, IncompleteToken};
 
 fn softmax(vec: &mut Vec<f32>) {
     for x in vec.iter_mut() {
@@ -21,8 +26,8 @@ fn softmax(vec: &mut Vec<f32>) {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Context {
-    parameters: Vec<f32>,
-    outcomes: Vec<usize>,
+    pub(crate) parameters: Vec<f32>,
+    pub(crate) outcomes: Vec<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -95,6 +100,17 @@ pub(crate) mod hash {
         hasher.finish()
     }
 
+    /// Hashes like [hash_slice] if `prefix` has already been written the parts before `slice`.
+    /// Lets features sharing a dynamic prefix reuse the hasher state instead of building a new string.
+    pub fn hash_slice_with(prefix: &DefaultHasher, slice: &[&str]) -> u64 {
+        let mut hasher = prefix.clone();
+        for s in slice.iter() {
+            hasher.write(s.as_bytes());
+        }
+        "".hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn hash_slice(slice: &[&str]) -> u64 {
         let mut hasher = DefaultHasher::new();
         for s in slice.iter() {
@@ -156,38 +172,51 @@ impl Model {
             .collect::<Vec<_>>()
     }
 
+    /// Finds the most likely outcome sequences. The context of each position is split into the features which only
+    /// depend on the input (computed once per position by `static_fn`) and the features which depend on the previous
+    /// outcomes (added for each hypothesis by `context_fn`).
     fn beam_search<
         S,
-        C: Fn(&[S], &[&str], usize) -> Vec<u64>,
+        T,
+        F: Fn(&[S], usize) -> T,
+        C: Fn(&T, &[&str], usize, &mut Vec<u64>),
         H: Fn(&[&str], usize) -> u64,
         V: Fn(&[S], &[&str], usize, &str) -> bool,
     >(
         &self,
         tokens: &[S],
+        static_fn: F,
         context_fn: C,
         hash_fn: H,
         valid_fn: V,
-        size: usize,
-    ) -> Vec<Sequence> {
+        beam: &BeamOptions,
+    ) -> Vec<Sequence<'_>> {
+        // a beam must hold at least one sequence
+        let size = beam.size.max(1);
+
         let mut prev: BinaryHeap<Sequence> = BinaryHeap::new();
         let mut next: BinaryHeap<Sequence> = BinaryHeap::new();
         prev.push(Sequence::default());
 
         let mut cache: DefaultHashMap<u64, Vec<f32>> = DefaultHashMap::default();
+        let mut context = Vec::new();
 
         for i in 0..tokens.len() {
             while prev.len() > size {
                 prev.pop();
             }
 
+            let static_features = static_fn(tokens, i);
+
             for seq in prev.iter() {
                 let hash = hash_fn(seq.outcomes(), i);
                 if cache.get(&hash).is_none() {
-                    let context = context_fn(tokens, seq.outcomes(), i);
+                    context.clear();
+                    context_fn(&static_features, seq.outcomes(), i, &mut context);
                     cache.insert(hash, self.eval(&context));
                 }
                 let scores = cache.get(&hash).unwrap();
-                let top_n = self.get_top_n(&scores, size, 1e-1);
+                let top_n = self.get_top_n(scores, size, beam.threshold);
 
                 for (_, p, pred) in top_n {
                     if valid_fn(tokens, &seq.outcomes(), i, pred) {
@@ -399,7 +428,8 @@ impl MaxentPosTagger {
         s.finish()
     }
 
-    fn context(tokens: &[&str], tags: &[&str], i: usize) -> Vec<u64> {
+    /// The features which do not depend on the previous tags.
+    fn static_context(tokens: &[&str], i: usize) -> Vec<u64> {
         let mut context = Vec::new();
 
         let lex = tokens[i];
@@ -413,16 +443,8 @@ impl MaxentPosTagger {
         } else {
             "*SE*"
         };
-        let (prev, tagprev) = if i >= 1 {
-            (tokens[i - 1], Some(tags[i - 1]))
-        } else {
-            ("*SB*", None)
-        };
-        let (prevprev, tagprevprev) = if i >= 2 {
-            (tokens[i - 2], Some(tags[i - 2]))
-        } else {
-            ("*SB*", None)
-        };
+        let prev = if i >= 1 { tokens[i - 1] } else { "*SB*" };
+        let prevprev = if i >= 2 { tokens[i - 2] } else { "*SB*" };
 
         context.push(hash::hash_str("default"));
         context.push(hash::hash_slice(&["w=", lex]));
@@ -449,15 +471,21 @@ impl MaxentPosTagger {
             context.push(hash::hash_slice(&["nn=", nextnext]));
         }
 
-        if let Some(tagprev) = tagprev {
+        context
+    }
+
+    /// Writes the complete context i. e. the static features followed by the features of the previous tags.
+    fn context(static_context: &[u64], tags: &[&str], i: usize, context: &mut Vec<u64>) {
+        context.extend_from_slice(static_context);
+
+        if i >= 1 {
+            let tagprev = tags[i - 1];
             context.push(hash::hash_slice(&["t=", tagprev]));
 
-            if let Some(tagprevprev) = tagprevprev {
-                context.push(hash::hash_slice(&["t2=", tagprevprev, ",", tagprev]));
+            if i >= 2 {
+                context.push(hash::hash_slice(&["t2=", tags[i - 2], ",", tagprev]));
             }
         }
-
-        context
     }
 
     fn valid(&self, tokens: &[&str], _outcomes: &[&str], i: usize, outcome: &str) -> bool {
@@ -468,19 +496,27 @@ impl MaxentPosTagger {
         }
     }
 
-    fn tag(&self, tokens: &[&str]) -> Sequence {
+    fn tag(&self, tokens: &[&str], beam: &BeamOptions) -> Sequence<'_> {
         self.model
             .beam_search(
                 tokens,
-                Self::context,
+                Self::static_context,
+                |a, b, c, d| Self::context(a, b, c, d),
                 Self::hash,
                 |a, b, c, d| self.valid(a, b, c, d),
-                3,
+                beam,
             )
             .remove(0)
     }
 }
 
+/// The features of one position of the chunker input which do not depend on the previous outcomes.
+struct ChunkerFeatures {
+    hashes: Vec<u64>,
+    words: [String; 5],
+    tags: [String; 5],
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct MaxentChunker {
     pub(crate) model: Model,
@@ -513,26 +549,25 @@ impl MaxentChunker {
         s.finish()
     }
 
-    fn context(input: &[(&str, &str)], preds: &[&str], i: usize) -> Vec<u64> {
+    /// The features which do not depend on the previous outcomes.
+    fn static_context(input: &[(&str, &str)], i: usize) -> ChunkerFeatures {
         let (tokens, tags): (Vec<&str>, Vec<&str>) = input.iter().cloned().unzip();
 
-        let (w_2, t_2, p_2) = if i < 2 {
-            ("w_2=bos".into(), "t_2=bos".into(), "p_2=bos".into())
+        let (w_2, t_2) = if i < 2 {
+            ("w_2=bos".into(), "t_2=bos".into())
         } else {
             (
                 format!("w_2={}", tokens[i - 2]),
                 format!("t_2={}", tags[i - 2]),
-                format!("p_2{}", preds[i - 2]),
             )
         };
 
-        let (w_1, t_1, p_1) = if i < 1 {
-            ("w_1=bos".into(), "t_1=bos".into(), "p_1=bos".into())
+        let (w_1, t_1) = if i < 1 {
+            ("w_1=bos".into(), "t_1=bos".into())
         } else {
             (
                 format!("w_1={}", tokens[i - 1]),
                 format!("t_1={}", tags[i - 1]),
-                format!("p_1={}", preds[i - 1]),
             )
         };
 
@@ -557,7 +592,7 @@ impl MaxentChunker {
             )
         };
 
-        return vec![
+        let hashes = vec![
             // add word features
             hash::hash_str(&w_2),
             hash::hash_str(&w_1),
@@ -579,47 +614,189 @@ impl MaxentChunker {
             hash::hash_slice(&[&t_2, &t_1, &t0]),
             hash::hash_slice(&[&t_1, &t0, &t1]),
             hash::hash_slice(&[&t0, &t1, &t2]),
+        ];
+
+        ChunkerFeatures {
+            hashes,
+            words: [w_2, w_1, w0, w1, w2],
+            tags: [t_2, t_1, t0, t1, t2],
+        }
+    }
+
+    /// Writes the complete context i. e. the static features followed by the features of the previous outcomes.
+    /// The features combining the previous outcome with words or tags share the hasher state of the outcome.
+    fn context(features: &ChunkerFeatures, preds: &[&str], i: usize, context: &mut Vec<u64>) {
+        // the missing "=" after "p_2" is intentional, the model was trained with these features
+        let (p_2_key, p_2) = if i < 2 {
+            ("p_2=bos", "")
+        } else {
+            ("p_2", preds[i - 2])
+        };
+        let p_1 = if i < 1 { "bos" } else { preds[i - 1] };
+
+        let mut p_1_hasher = DefaultHasher::new();
+        p_1_hasher.write(b"p_1=");
+        p_1_hasher.write(p_1.as_bytes());
+
+        let [w_2, w_1, w0, w1, w2] = &features.words;
+        let [t_2, t_1, t0, t1, t2] = &features.tags;
+        let with_p_1 = |slice: &[&str]| hash::hash_slice_with(&p_1_hasher, slice);
+
+        context.extend_from_slice(&features.hashes);
+        context.extend_from_slice(&[
             // add pred tags
-            hash::hash_str(&p_2),
-            hash::hash_str(&p_1),
-            hash::hash_slice(&[&p_2, &p_1]),
+            hash::hash_slice(&[p_2_key, p_2]),
+            hash::hash_slice_with(&p_1_hasher, &[]),
+            hash::hash_slice(&[p_2_key, p_2, "p_1=", p_1]),
             // add pred and tag
-            hash::hash_slice(&[&p_1, &t_2]),
-            hash::hash_slice(&[&p_1, &t_1]),
-            hash::hash_slice(&[&p_1, &t0]),
-            hash::hash_slice(&[&p_1, &t1]),
-            hash::hash_slice(&[&p_1, &t2]),
-            hash::hash_slice(&[&p_1, &t_2, &t_1]),
-            hash::hash_slice(&[&p_1, &t_1, &t0]),
-            hash::hash_slice(&[&p_1, &t0, &t1]),
-            hash::hash_slice(&[&p_1, &t1, &t2]),
-            hash::hash_slice(&[&p_1, &t_2, &t_1, &t0]),
-            hash::hash_slice(&[&p_1, &t_1, &t0, &t1]),
-            hash::hash_slice(&[&p_1, &t0, &t1, &t2]),
+            with_p_1(&[t_2]),
+            with_p_1(&[t_1]),
+            with_p_1(&[t0]),
+            with_p_1(&[t1]),
+            with_p_1(&[t2]),
+            with_p_1(&[t_2, t_1]),
+            with_p_1(&[t_1, t0]),
+            with_p_1(&[t0, t1]),
+            with_p_1(&[t1, t2]),
+            with_p_1(&[t_2, t_1, t0]),
+            with_p_1(&[t_1, t0, t1]),
+            with_p_1(&[t0, t1, t2]),
             // add pred and word
-            hash::hash_slice(&[&p_1, &w_2]),
-            hash::hash_slice(&[&p_1, &w_1]),
-            hash::hash_slice(&[&p_1, &w0]),
-            hash::hash_slice(&[&p_1, &w1]),
-            hash::hash_slice(&[&p_1, &w2]),
-            hash::hash_slice(&[&p_1, &w_1, &w0]),
-            hash::hash_slice(&[&p_1, &w0, &w1]),
-        ];
+            with_p_1(&[w_2]),
+            with_p_1(&[w_1]),
+            with_p_1(&[w0]),
+            with_p_1(&[w1]),
+            with_p_1(&[w2]),
+            with_p_1(&[w_1, w0]),
+            with_p_1(&[w0, w1]),
+        ]);
     }
 
-    fn chunk(&self, input: &[(&str, &str)]) -> Sequence {
+    fn chunk(&self, input: &[(&str, &str)], beam: &BeamOptions) -> Sequence<'_> {
         self.model
             .beam_search(
                 input,
+                Self::static_context,
                 Self::context,
                 Self::hash,
                 |a, b, c, d| self.valid(a, b, c, d),
-                7,
+                beam,
             )
             .remove(0)
     }
 }
 
+/// Options for the beam search of one of the maxent models.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeamOptions {
+    /// How many sequences are kept at each position. Smaller beams are faster but less accurate.
+    pub size: usize,
+    /// Outcomes with a probability below this threshold are pruned before they enter the beam.
+    /// Higher thresholds are faster but less accurate.
+    pub threshold: f32,
+}
+
+impl BeamOptions {
+    fn validate(&self, name: &str) -> Result<(), Error> {
+        if self.size == 0 {
+            return Err(Error::InvalidOption(format!(
+                "{}: beam size must be at least 1",
+                name
+            )));
+        }
+
+        if !(0. ..1.).contains(&self.threshold) {
+            return Err(Error::InvalidOption(format!(
+                "{}: pruning threshold must be in [0, 1), found {}",
+                name, self.threshold
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Options for the [Chunker]. These are not stored in the binary and can be changed at runtime with [Chunker::set_options].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkerOptions {
+    /// The beam of the part-of-speech tagger.
+    pub pos_beam: BeamOptions,
+    /// The beam of the chunker.
+    pub chunk_beam: BeamOptions,
+    /// Whether to chunk each sentence separately. The sentences are then chunked in parallel according to the
+    /// parallelism of the tokenizer. By default the tokens of one call are chunked together, so the chunks at the
+    /// start of a sentence can depend on the previous sentence.
+    pub per_sentence: bool,
+}
+
+impl Default for ChunkerOptions {
+    fn default() -> Self {
+        ChunkerOptions {
+            pos_beam: BeamOptions {
+                size: 3,
+                threshold: 0.1,
+            },
+            chunk_beam: BeamOptions {
+                size: 7,
+                threshold: 0.1,
+            },
+            per_sentence: false,
+        }
+    }
+}
+
+impl ChunkerOptions {
+    /// Creates a builder starting from the default options.
+    pub fn builder() -> ChunkerOptionsBuilder {
+        ChunkerOptionsBuilder::default()
+    }
+}
+
+/// Builder for [ChunkerOptions]. See [ChunkerOptions::builder].
+#[derive(Default)]
+pub struct ChunkerOptionsBuilder {
+    options: ChunkerOptions,
+}
+
+impl ChunkerOptionsBuilder {
+    /// Sets the beam size of [ChunkerOptions::pos_beam].
+    pub fn pos_beam_size(mut self, size: usize) -> Self {
+        self.options.pos_beam.size = size;
+        self
+    }
+
+    /// Sets the pruning threshold of [ChunkerOptions::pos_beam].
+    pub fn pos_threshold(mut self, threshold: f32) -> Self {
+        self.options.pos_beam.threshold = threshold;
+        self
+    }
+
+    /// Sets the beam size of [ChunkerOptions::chunk_beam].
+    pub fn chunk_beam_size(mut self, size: usize) -> Self {
+        self.options.chunk_beam.size = size;
+        self
+    }
+
+    /// Sets the pruning threshold of [ChunkerOptions::chunk_beam].
+    pub fn chunk_threshold(mut self, threshold: f32) -> Self {
+        self.options.chunk_beam.threshold = threshold;
+        self
+    }
+
+    /// Sets [ChunkerOptions::per_sentence].
+    pub fn per_sentence(mut self, per_sentence: bool) -> Self {
+        self.options.per_sentence = per_sentence;
+        self
+    }
+
+    /// Validates and returns the options. Fails if a beam size is zero or a threshold is not in [0, 1).
+    pub fn build(self) -> Result<ChunkerOptions, Error> {
+        self.options.pos_beam.validate("pos_beam")?;
+        self.options.chunk_beam.validate("chunk_beam")?;
+        Ok(self.options)
+    }
+}
+
 /// Predicts noun chunks and verb chunks through a [Maximum Entropy Model](https://www.aclweb.org/anthology/W00-0729.pdf).
 /// Grammatical number (i. e. singular and plural) is also assigned through the part-of-speech tags of the tokens.
 #[derive(Serialize, Deserialize)]
@@ -627,46 +804,188 @@ pub struct Chunker {
     pub(crate) token_model: MaxentTokenizer,
     pub(crate) pos_model: MaxentPosTagger,
     pub(crate) chunk_model: MaxentChunker,
+    #[serde(skip)]
+    pub(crate) options: ChunkerOptions,
 }
 
+/// Written at the start of a standalone chunker binary, followed by the format version.
+const CHUNKER_MAGIC: &[u8; 8] = b"NLPRCHNK";
+/// Must be increased whenever the serialized layout of the [Chunker] changes.
+const CHUNKER_FORMAT_VERSION: u16 = 1;
+
 impl Chunker {
+    /// Writes the chunker as standalone binary which can be loaded with [Chunker::from_reader]
+    /// and swapped into a tokenizer with [Tokenizer::set_chunker][super::Tokenizer::set_chunker].
+    pub fn to_writer<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        writer.write_all(CHUNKER_MAGIC)?;
+        writer.write_all(&CHUNKER_FORMAT_VERSION.to_le_bytes())?;
+        bincode::serialize_into(writer, self)
+    }
+
+    /// Reads a chunker written with [Chunker::to_writer].
+    /// Fails if the binary is not a chunker or was written in a different format version.
+    pub fn from_reader<R: Read>(mut reader: R) -> bincode::Result<Self> {
+        let mut magic = [0u8; 8];
+        let mut version = [0u8; 2];
+        reader.read_exact(&mut magic)?;
+        reader.read_exact(&mut version)?;
+
+        if &magic != CHUNKER_MAGIC {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "not a chunker binary: missing header".into(),
+            )));
+        }
+
+        let version = u16::from_le_bytes(version);
+        if version != CHUNKER_FORMAT_VERSION {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "incompatible chunker format version {}, this version of nlprule reads version {}",
+                version, CHUNKER_FORMAT_VERSION
+            ))));
+        }
+
+        bincode::deserialize_from(reader)
+    }
+
+    /// The options of the beam searches. See [Chunker::set_options].
+    pub fn options(&self) -> &ChunkerOptions {
+        &self.options
+    }
+
+    /// Sets the options of the beam searches e. g. to trade accuracy for speed. Defaults to [ChunkerOptions::default].
+    pub fn set_options(&mut self, options: ChunkerOptions) {
+        self.options = options;
+    }
+
     /// Populates the `.chunks` field of the passed tokens by predicting with the maximum entropy model.
     pub fn apply(&self, tokens: &mut Vec<IncompleteToken>) {
+        self.apply_with(
+            tokens,
+            ChunkerTagSource::default(),
+            &ParallelismConfig::default(),
+        );
+    }
+
+    /// Like [Chunker::apply], but chunks the sentences in parallel according to `parallelism`
+    /// if [ChunkerOptions::per_sentence] is set. Each sentence is then chunked independently so the chunks
+    /// do not depend on the number of threads.
+    pub(crate) fn apply_with(
+        &self,
+        tokens: &mut [IncompleteToken],
+        tag_source: ChunkerTagSource,
+        parallelism: &ParallelismConfig,
+    ) {
+        let sentences: Vec<_> = if self.options.per_sentence {
+            tokens
+                .split_inclusive(|token| token.is_sentence_end)
+                .collect()
+        } else {
+            vec![&*tokens]
+        };
+
+        let sentence_chunks: Vec<_> = parallelism.install(|| {
+            sentences
+                .maybe_par_iter_with(parallelism)
+                .map(|sentence| self.chunk_sentence(sentence, tag_source))
+                .collect()
+        });
+
+        #[cfg(debug_assertions)]
+        {
+            let n_tokens: usize = sentence_chunks.iter().map(|x| x.chunks.len()).sum();
+            let n_disagreements: usize = sentence_chunks.iter().map(|x| x.disagreements).sum();
+            if n_tokens > 0 {
+                log::debug!(
+                    "chunker tags disagree with the tagger for {} of {} tokens ({:.1}%)",
+                    n_disagreements,
+                    n_tokens,
+                    100. * n_disagreements as f32 / n_tokens as f32
+                );
+            }
+        }
+
+        // chunks with exactly the same char span as the input tokens get assigned to the token to match LT
+        let lengths: Vec<_> = sentences.iter().map(|x| x.len()).collect();
+        let mut rest = tokens;
+        for (length, chunks) in lengths.into_iter().zip(sentence_chunks) {
+            let (sentence, next) = std::mem::take(&mut rest).split_at_mut(length);
+            rest = next;
+
+            let char_spans: Vec<_> = chunks.chunks.iter().map(|(_, span, _)| *span).collect();
+            let aligned: Vec<_> = aligned_tokens(&char_spans, sentence).collect();
+            for (i, j) in aligned {
+                let (chunk, _, prob) = &chunks.chunks[i];
+                sentence[j].chunks = chunk.clone();
+                sentence[j].chunk_confidence = Some(*prob);
+            }
+        }
+    }
+
+    /// Predicts the chunks of one sentence.
+    /// All intermediate state is local to the call so sentences can be chunked concurrently.
+    fn chunk_sentence(
+        &self,
+        tokens: &[IncompleteToken],
+        tag_source: ChunkerTagSource,
+    ) -> SentenceChunks {
+        let (first, last) = match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return SentenceChunks::default(),
+        };
+        let char_offset = first.char_span.0;
+
         // replacements must not change char indices
-        let text = tokens[0].text.replace('’', "\'");
+        let text = first.text[first.byte_span.0..last.byte_span.1].replace('’', "\'");
 
         let mut byte_to_char_idx: DefaultHashMap<usize, usize> = text
             .char_indices()
             .enumerate()
-            .map(|(ci, (bi, _))| (bi, ci))
+            .map(|(ci, (bi, _))| (bi, ci + char_offset))
             .collect();
-        byte_to_char_idx.insert(text.len(), text.chars().count());
+        byte_to_char_idx.insert(text.len(), text.chars().count() + char_offset);
 
         // the chunker expects tokens tokenized with a maximum entropy tokenizer
         let internal_tokens = self.token_model.tokenize(&text);
+        // compute the char span of each internal token to be able to match it with the input tokens
+        let char_spans: Vec<_> = internal_tokens
+            .iter()
+            .map(|token| {
+                let byte_start = token.as_ptr() as usize - text.as_ptr() as usize;
+                let char_start = *byte_to_char_idx.get(&byte_start).unwrap();
+                let char_end = *byte_to_char_idx.get(&(byte_start + token.len())).unwrap();
+
+                (char_start, char_end)
+            })
+            .collect();
+
         // the chunker gets part-of-speech tags as input so we also have to run a maximum entropy POSTagger before the chunker
-        let tags = self.pos_model.tag(&internal_tokens);
+        let tags = self.pos_model.tag(&internal_tokens, &self.options.pos_beam);
+        let mut tags = tags.outcomes().to_vec();
+        let aligned: Vec<_> = aligned_tokens(&char_spans, tokens).collect();
+        let disagreements = reconcile_tags(&mut tags, &aligned, tokens, tag_source);
+        let is_plural: Vec<_> = {
+            let mut is_plural = vec![false; char_spans.len()];
+            for &(i, j) in &aligned {
+                is_plural[i] |= tokens[j]
+                    .word
+                    .tags
+                    .iter()
+                    .any(|tag| tag.pos.as_ref() == "NNS");
+            }
+            is_plural
+        };
+
         let chunks = self.chunk_model.chunk(
             &internal_tokens
                 .iter()
                 .cloned()
-                .zip(tags.outcomes().iter().cloned())
+                .zip(tags.iter().cloned())
                 .collect::<Vec<_>>(),
+            &self.options.chunk_beam,
         );
 
-        // compute the char span of each chunk to be able to match it with the input tokens
-        let internal_chunks: Vec<_> = chunks
-            .outcomes()
-            .iter()
-            .zip(internal_tokens)
-            .map(|(chunk, token)| {
-                let byte_start = token.as_ptr() as usize - text.as_ptr() as usize;
-                let char_start = *byte_to_char_idx.get(&byte_start).unwrap();
-                let char_end = *byte_to_char_idx.get(&(byte_start + token.len())).unwrap();
-
-                (*chunk, (char_start, char_end))
-            })
-            .collect();
+        let internal_chunks: Vec<_> = chunks.outcomes().iter().cloned().zip(char_spans).collect();
+        let probs = chunks.probs();
         let mut chunks = Vec::new();
         let mut number = "singular";
 
@@ -676,17 +995,12 @@ impl Chunker {
             if chunk == "B-NP" {
                 number = "singular";
 
-                for (next_chunk, char_span) in internal_chunks[i..].iter().cloned() {
-                    if next_chunk != "I-NP" && next_chunk != "B-NP" {
+                for (k, (next_chunk, _)) in internal_chunks.iter().enumerate().skip(i) {
+                    if *next_chunk != "I-NP" && *next_chunk != "B-NP" {
                         break;
                     }
 
-                    if tokens
-                        .iter()
-                        .find(|token| token.char_span == char_span)
-                        .map(|token| token.word.tags.iter().any(|tag| tag.pos.as_ref() == "NNS"))
-                        .unwrap_or(false)
-                    {
+                    if is_plural[k] {
                         number = "plural";
                     }
                 }
@@ -713,13 +1027,642 @@ impl Chunker {
             chunks.push(to_push);
         }
 
-        // chunks with exactly the same char span as the input tokens get assigned to the token to match LT
-        for token in tokens.iter_mut() {
-            for (chunk, (_, char_span)) in chunks.iter().zip(internal_chunks.iter()) {
-                if *char_span == token.char_span {
-                    token.chunks = (*chunk).clone();
+        SentenceChunks {
+            chunks: chunks
+                .into_iter()
+                .zip(internal_chunks)
+                .zip(probs.iter().cloned())
+                .map(|((chunk, (_, char_span)), prob)| (chunk, char_span, prob))
+                .collect(),
+            disagreements,
+        }
+    }
+}
+
+/// The chunks, char span and probability of each internal token of one sentence.
+#[derive(Default)]
+struct SentenceChunks {
+    chunks: Vec<(Vec<String>, (usize, usize), f32)>,
+    /// The number of internal tokens where the tag predicted by the chunker is not a reading of the tagger.
+    disagreements: usize,
+}
+
+/// Pairs of (internal token index, token index) with exactly the same char span.
+/// Both the char spans and the tokens are in text order so they are matched in one pass.
+fn aligned_tokens<'a>(
+    char_spans: &'a [(usize, usize)],
+    tokens: &'a [IncompleteToken],
+) -> impl Iterator<Item = (usize, usize)> + 'a {
+    let mut start = 0;
+
+    char_spans
+        .iter()
+        .enumerate()
+        .flat_map(move |(i, char_span)| {
+            while start < tokens.len() && tokens[start].char_span.0 < char_span.0 {
+                start += 1;
+            }
+
+            (start..tokens.len())
+                .take_while(move |&j| tokens[j].char_span.0 == char_span.0)
+                .filter(move |&j| tokens[j].char_span == *char_span)
+                .map(move |j| (i, j))
+        })
+}
+
+/// Compares the tags predicted by the chunker with the readings of the tokens with the same char span.
+/// If the tags should come from the tagger, disagreeing tags are replaced with the first reading.
+/// Returns the number of disagreements.
+fn reconcile_tags<'a>(
+    tags: &mut [&'a str],
+    aligned: &[(usize, usize)],
+    tokens: &'a [IncompleteToken],
+    tag_source: ChunkerTagSource,
+) -> usize {
+    let mut disagreements = 0;
+    let mut previous = None;
+
+    for &(i, j) in aligned {
+        // only the first token with the same char span counts
+        if previous == Some(i) {
+            continue;
+        }
+        previous = Some(i);
+
+        let tag = &mut tags[i];
+        let token = &tokens[j];
+
+        let mut readings = token
+            .word
+            .tags
+            .iter()
+            .map(|x| x.pos.as_ref())
+            .filter(|x| !x.is_empty())
+            .peekable();
+
+        // unknown words can not disagree
+        let first = match readings.peek() {
+            Some(first) => *first,
+            None => continue,
+        };
+
+        if !readings.any(|x| x == *tag) {
+            disagreements += 1;
+
+            if tag_source == ChunkerTagSource::Tagger {
+                *tag = first;
+            }
+        }
+    }
+
+    disagreements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the features as they were computed before the static features were split off, to check that the
+    // restructured feature generation gives exactly the same contexts (and therefore the same predictions)
+    fn reference_pos_context(tokens: &[&str], tags: &[&str], i: usize) -> Vec<u64> {
+        let mut context = Vec::new();
+
+        let lex = tokens[i];
+        let next = if i + 1 < tokens.len() {
+            tokens[i + 1]
+        } else {
+            "*SE*"
+        };
+        let nextnext = if i + 2 < tokens.len() {
+            tokens[i + 2]
+        } else {
+            "*SE*"
+        };
+        let (prev, tagprev) = if i >= 1 {
+            (tokens[i - 1], Some(tags[i - 1]))
+        } else {
+            ("*SB*", None)
+        };
+        let (prevprev, tagprevprev) = if i >= 2 {
+            (tokens[i - 2], Some(tags[i - 2]))
+        } else {
+            ("*SB*", None)
+        };
+
+        context.push(hash::hash_str("default"));
+        context.push(hash::hash_slice(&["w=", lex]));
+
+        context.extend(MaxentPosTagger::get_suffixes_prefixes(&lex));
+
+        if lex.contains('-') {
+            context.push(hash::hash_str("h"));
+        }
+        if lex.chars().any(|c| c.is_ascii_uppercase()) {
+            context.push(hash::hash_str("c"));
+        }
+        if lex.chars().any(|c| c.is_ascii_digit()) {
+            context.push(hash::hash_str("d"));
+        }
+
+        context.push(hash::hash_slice(&["p=", prev]));
+        if prev != "*SB*" {
+            context.push(hash::hash_slice(&["pp=", prevprev]));
+        }
+
+        context.push(hash::hash_slice(&["n=", next]));
+        if next != "*SE*" {
+            context.push(hash::hash_slice(&["nn=", nextnext]));
+        }
+
+        if let Some(tagprev) = tagprev {
+            context.push(hash::hash_slice(&["t=", tagprev]));
+
+            if let Some(tagprevprev) = tagprevprev {
+                context.push(hash::hash_slice(&["t2=", tagprevprev, ",", tagprev]));
+            }
+        }
+
+        context
+    }
+
+    fn reference_chunk_context(input: &[(&str, &str)], preds: &[&str], i: usize) -> Vec<u64> {
+        let (tokens, tags): (Vec<&str>, Vec<&str>) = input.iter().cloned().unzip();
+
+        let (w_2, t_2, p_2) = if i < 2 {
+            ("w_2=bos".into(), "t_2=bos".into(), "p_2=bos".into())
+        } else {
+            (
+                format!("w_2={}", tokens[i - 2]),
+                format!("t_2={}", tags[i - 2]),
+                format!("p_2{}", preds[i - 2]),
+            )
+        };
+
+        let (w_1, t_1, p_1) = if i < 1 {
+            ("w_1=bos".into(), "t_1=bos".into(), "p_1=bos".into())
+        } else {
+            (
+                format!("w_1={}", tokens[i - 1]),
+                format!("t_1={}", tags[i - 1]),
+                format!("p_1={}", preds[i - 1]),
+            )
+        };
+
+        let w0 = format!("w0={}", tokens[i]);
+        let t0 = format!("t0={}", tags[i]);
+
+        let (w1, t1) = if i + 1 >= tokens.len() {
+            ("w1=eos".into(), "t1=eos".into())
+        } else {
+            (
+                format!("w1={}", tokens[i + 1]),
+                format!("t1={}", tags[i + 1]),
+            )
+        };
+
+        let (w2, t2) = if i + 2 >= tokens.len() {
+            ("w2=eos".into(), "t2=eos".into())
+        } else {
+            (
+                format!("w2={}", tokens[i + 2]),
+                format!("t2={}", tags[i + 2]),
+            )
+        };
+
+        vec![
+            // add word features
+            hash::hash_str(&w_2),
+            hash::hash_str(&w_1),
+            hash::hash_str(&w0),
+            hash::hash_str(&w1),
+            hash::hash_str(&w2),
+            hash::hash_slice(&[&w_1, &w0]),
+            hash::hash_slice(&[&w0, &w1]),
+            // add tag features
+            hash::hash_str(&t_2),
+            hash::hash_str(&t_1),
+            hash::hash_str(&t0),
+            hash::hash_str(&t1),
+            hash::hash_str(&t2),
+            hash::hash_slice(&[&t_2, &t_1]),
+            hash::hash_slice(&[&t_1, &t0]),
+            hash::hash_slice(&[&t0, &t1]),
+            hash::hash_slice(&[&t1, &t2]),
+            hash::hash_slice(&[&t_2, &t_1, &t0]),
+            hash::hash_slice(&[&t_1, &t0, &t1]),
+            hash::hash_slice(&[&t0, &t1, &t2]),
+            // add pred tags
+            hash::hash_str(&p_2),
+            hash::hash_str(&p_1),
+            hash::hash_slice(&[&p_2, &p_1]),
+            // add pred and tag
+            hash::hash_slice(&[&p_1, &t_2]),
+            hash::hash_slice(&[&p_1, &t_1]),
+            hash::hash_slice(&[&p_1, &t0]),
+            hash::hash_slice(&[&p_1, &t1]),
+            hash::hash_slice(&[&p_1, &t2]),
+            hash::hash_slice(&[&p_1, &t_2, &t_1]),
+            hash::hash_slice(&[&p_1, &t_1, &t0]),
+            hash::hash_slice(&[&p_1, &t0, &t1]),
+            hash::hash_slice(&[&p_1, &t1, &t2]),
+            hash::hash_slice(&[&p_1, &t_2, &t_1, &t0]),
+            hash::hash_slice(&[&p_1, &t_1, &t0, &t1]),
+            hash::hash_slice(&[&p_1, &t0, &t1, &t2]),
+            // add pred and word
+            hash::hash_slice(&[&p_1, &w_2]),
+            hash::hash_slice(&[&p_1, &w_1]),
+            hash::hash_slice(&[&p_1, &w0]),
+            hash::hash_slice(&[&p_1, &w1]),
+            hash::hash_slice(&[&p_1, &w2]),
+            hash::hash_slice(&[&p_1, &w_1, &w0]),
+            hash::hash_slice(&[&p_1, &w0, &w1]),
+        ]
+    }
+
+    const CORPUS: &[&[&str]] = &[
+        &["The", "dog", "runs", "."],
+        &[
+            "A",
+            "well-known",
+            "author",
+            "wrote",
+            "3",
+            "books",
+            "in",
+            "2019",
+            ".",
+        ],
+        &["Ça", "va", "?"],
+        &["x"],
+        &[
+            "He", "said", ":", "\"", "Hello", "\"", "to", "Dr.", "Müller", ".",
+        ],
+    ];
+    const LABELS: &[&str] = &["B-NP", "I-NP", "O", "NN", "VBZ"];
+
+    #[test]
+    fn features_equal_reference_features() {
+        for tokens in CORPUS {
+            let tags: Vec<_> = tokens
+                .iter()
+                .enumerate()
+                .map(|(i, _)| LABELS[i % LABELS.len()])
+                .collect();
+            let input: Vec<_> = tokens.iter().cloned().zip(tags.iter().cloned()).collect();
+
+            for i in 0..tokens.len() {
+                let pos_features = MaxentPosTagger::static_context(tokens, i);
+                let chunk_features = MaxentChunker::static_context(&input, i);
+
+                for a in LABELS {
+                    for b in LABELS {
+                        let preds: Vec<_> =
+                            (0..i).map(|j| if j + 1 == i { *b } else { *a }).collect();
+
+                        let mut context = Vec::new();
+                        MaxentPosTagger::context(&pos_features, &preds, i, &mut context);
+                        assert_eq!(context, reference_pos_context(tokens, &preds, i));
+
+                        let mut context = Vec::new();
+                        MaxentChunker::context(&chunk_features, &preds, i, &mut context);
+                        assert_eq!(context, reference_chunk_context(&input, &preds, i));
+                    }
                 }
             }
         }
     }
+
+    #[test]
+    fn options_are_validated() {
+        let options = ChunkerOptions::builder()
+            .pos_beam_size(1)
+            .chunk_threshold(0.5)
+            .build()
+            .unwrap();
+        assert_eq!(options.pos_beam.size, 1);
+        assert_eq!(options.chunk_beam.size, 7);
+
+        assert!(ChunkerOptions::builder()
+            .chunk_beam_size(0)
+            .build()
+            .is_err());
+        assert!(ChunkerOptions::builder().pos_threshold(1.).build().is_err());
+        assert!(ChunkerOptions::builder()
+            .pos_threshold(-0.1)
+            .build()
+            .is_err());
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod compile_tests {
+    use crate::{
+        compile::{
+            testing::{chunker_json, setup, word_chunker, EMPTY_MODEL},
+            ChunkerLoadError,
+        },
+        tokenizer::{
+            chunk::{Chunker, ChunkerOptions},
+            Tokenizer,
+        },
+        Parallelism,
+    };
+
+    #[test]
+    fn truncated_chunker_is_a_syntax_error() {
+        let json = format!(
+            r#"{{ "token_model": {}, "pos_model": {{ "outcome_la"#,
+            EMPTY_MODEL
+        );
+
+        match Chunker::from_json(json.as_bytes()) {
+            Err(ChunkerLoadError::Syntax(error)) => {
+                assert_eq!(error.line(), 1);
+                assert_eq!(error.column(), json.len());
+            }
+            other => panic!("expected syntax error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn incomplete_chunker_is_invalid() {
+        let json = format!(
+            r#"{{ "token_model": {0}, "pos_model": {0}, "chunk_model": {0} }}"#,
+            EMPTY_MODEL
+        );
+
+        match Chunker::from_json(json.as_bytes()) {
+            Err(ChunkerLoadError::Invalid(message)) => assert!(message.contains("pos_tagdict")),
+            other => panic!("expected invalid chunker, got {:?}", other.err()),
+        }
+
+        let bad_model = r#"{ "outcome_labels": ["O"], "pmap": { "w=a": { "parameters": [0.5], "outcomes": [1] } } }"#;
+        let json = format!(
+            r#"{{ "token_model": {0}, "pos_model": {1}, "pos_tagdict": {{}}, "chunk_model": {0} }}"#,
+            EMPTY_MODEL, bad_model
+        );
+
+        match Chunker::from_json(json.as_bytes()) {
+            Err(ChunkerLoadError::Invalid(message)) => {
+                assert!(message.contains("pos_model") && message.contains("outcome 1"))
+            }
+            other => panic!("expected invalid chunker, got {:?}", other.err()),
+        }
+
+        let json = chunker_json(EMPTY_MODEL);
+        assert!(Chunker::from_json(json.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn chunker_can_be_stored_and_swapped() {
+        let (mut tokenizer, _) = setup();
+        let json = chunker_json(EMPTY_MODEL);
+
+        let mut bytes = Vec::new();
+        Chunker::from_json(json.as_bytes())
+            .unwrap()
+            .to_writer(&mut bytes)
+            .unwrap();
+
+        assert!(tokenizer.chunker().is_none());
+        tokenizer.set_chunker(Some(Chunker::from_reader(&bytes[..]).unwrap()));
+        assert!(tokenizer.chunker().is_some());
+
+        // a chunker from another format version is rejected
+        bytes[8] += 1;
+        let error = Chunker::from_reader(&bytes[..]).err().unwrap();
+        assert!(error.to_string().contains("format version"));
+
+        assert!(Chunker::from_reader(&b"garbage garbage"[..]).is_err());
+    }
+
+    /// A model in the OpenNLP binary format and the equivalent JSON. Every context gets its own outcome pattern.
+    fn opennlp_model(labels: &[&str], contexts: &[(&str, &[usize], &[f64])]) -> (Vec<u8>, String) {
+        fn write_utf(bytes: &mut Vec<u8>, text: &str) {
+            bytes.extend_from_slice(&(text.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(text.as_bytes());
+        }
+
+        let mut bytes = Vec::new();
+        write_utf(&mut bytes, "GIS");
+        bytes.extend_from_slice(&1i32.to_be_bytes());
+        bytes.extend_from_slice(&0f64.to_be_bytes());
+
+        bytes.extend_from_slice(&(labels.len() as i32).to_be_bytes());
+        for label in labels {
+            write_utf(&mut bytes, label);
+        }
+
+        bytes.extend_from_slice(&(contexts.len() as i32).to_be_bytes());
+        for (_, outcomes, _) in contexts {
+            let pattern: Vec<_> = std::iter::once(1)
+                .chain(outcomes.iter().copied())
+                .map(|x| x.to_string())
+                .collect();
+            write_utf(&mut bytes, &pattern.join(" "));
+        }
+
+        bytes.extend_from_slice(&(contexts.len() as i32).to_be_bytes());
+        for (pred, _, _) in contexts {
+            write_utf(&mut bytes, pred);
+        }
+
+        for (_, _, parameters) in contexts {
+            for parameter in parameters.iter() {
+                bytes.extend_from_slice(&parameter.to_be_bytes());
+            }
+        }
+
+        let json = serde_json::json!({
+            "outcome_labels": labels,
+            "pmap": contexts
+                .iter()
+                .map(|(pred, outcomes, parameters)| {
+                    (pred.to_string(), serde_json::json!({ "outcomes": outcomes, "parameters": parameters }))
+                })
+                .collect::<serde_json::Map<_, _>>(),
+        });
+
+        (bytes, json.to_string())
+    }
+
+    #[test]
+    fn opennlp_chunker_equals_json_chunker() {
+        let (token_bin, token_json) = opennlp_model(&["S", "T"], &[]);
+        let (pos_bin, pos_json) = opennlp_model(
+            &["DT", "NN", "VBZ", "."],
+            &[
+                ("w=the", &[0], &[4.]),
+                ("w=dog", &[1, 2], &[2., 0.5]),
+                ("w=runs", &[1, 2], &[0.5, 2.]),
+                ("w=.", &[3], &[4.]),
+                ("suf=s", &[1, 2], &[0.25, 0.75]),
+            ],
+        );
+        let (chunk_bin, chunk_json) = opennlp_model(
+            &["B-NP", "I-NP", "B-VP", "O"],
+            &[
+                ("t0=DT", &[0], &[3.]),
+                ("t0=NN", &[0, 1], &[1., 2.]),
+                ("t0=VBZ", &[2], &[3.]),
+                ("t0=.", &[3], &[3.]),
+                ("w0=light", &[0, 1, 2], &[0.5, 0.5, 0.5]),
+            ],
+        );
+        let tagdict = r#"<?xml version="1.0" encoding="UTF-8"?>
+<dictionary case_sensitive="false">
+<entry tags="DT"><token>the</token></entry>
+<entry tags="NN VBZ"><token>light</token></entry>
+</dictionary>"#;
+
+        let opennlp = Chunker::from_opennlp(
+            &token_bin[..],
+            &pos_bin[..],
+            &chunk_bin[..],
+            tagdict.as_bytes(),
+        )
+        .unwrap();
+        let json = format!(
+            r#"{{ "token_model": {}, "pos_model": {}, "pos_tagdict": {{ "the": ["DT"], "light": ["NN", "VBZ"] }}, "chunk_model": {} }}"#,
+            token_json, pos_json, chunk_json
+        );
+        let json = Chunker::from_json(json.as_bytes()).unwrap();
+
+        let (mut opennlp_tokenizer, _) = setup();
+        let (mut json_tokenizer, _) = setup();
+        opennlp_tokenizer.set_chunker(Some(opennlp));
+        json_tokenizer.set_chunker(Some(json));
+
+        for text in &[
+            "the dog runs.",
+            "the light runs.",
+            "dog runs the light",
+            "The dog, the light.",
+        ] {
+            let chunks = |tokenizer: &Tokenizer| -> Vec<_> {
+                tokenizer
+                    .tokenize(text)
+                    .into_iter()
+                    .map(|x| (x.chunks.clone(), x.chunk_confidence()))
+                    .collect()
+            };
+
+            let expected = chunks(&json_tokenizer);
+            assert!(expected.iter().any(|(chunks, _)| !chunks.is_empty()));
+            assert_eq!(chunks(&opennlp_tokenizer), expected);
+        }
+
+        // truncated models are rejected
+        assert!(Chunker::from_opennlp(
+            &token_bin[..],
+            &pos_bin[..pos_bin.len() - 1],
+            &chunk_bin[..],
+            tagdict.as_bytes(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn chunk_confidence_is_set() {
+        let (mut tokenizer, _) = setup();
+        let chunk_model = r#"{
+            "outcome_labels": ["B-NP", "I-NP", "O"],
+            "pmap": {
+                "w0=the": { "parameters": [5.0], "outcomes": [0] },
+                "w0=light": { "parameters": [0.1, 0.1], "outcomes": [0, 1] }
+            }
+        }"#;
+        let json = chunker_json(chunk_model);
+        tokenizer.set_chunker(Some(Chunker::from_json(json.as_bytes()).unwrap()));
+
+        let tokens = tokenizer.tokenize("the light");
+        let confidence: Vec<_> = tokens.iter().filter_map(|x| x.chunk_confidence()).collect();
+
+        assert_eq!(confidence.len(), 2);
+        assert!(confidence.iter().all(|x| *x > 0. && *x <= 1.));
+        // the function word is unambiguous, "light" could start or continue a noun phrase
+        assert!(confidence[0] > 0.9);
+        assert!(confidence[1] < confidence[0]);
+    }
+
+    #[test]
+    fn rules_can_match_chunks() {
+        let (mut tokenizer, rules) = setup();
+        tokenizer.set_chunker(Some(word_chunker()));
+
+        let tokens = tokenizer.tokenize("the light runs lamp");
+        let chunks: Vec<_> = tokens.iter().map(|x| x.chunks.clone()).collect();
+        assert_eq!(
+            chunks,
+            vec![
+                vec!["B-NP-singular".to_string()],
+                vec!["E-NP-singular".to_string()],
+                vec!["O".to_string()],
+                vec!["B-NP-singular".to_string(), "E-NP-singular".to_string()],
+            ]
+        );
+
+        // alternations, negation and tokens with multiple chunks
+        let suggestions = rules.suggest("the light runs lamp", &tokenizer);
+        let ids: Vec<_> = suggestions.iter().map(|x| x.source.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec!["NOUN_PHRASE_AFTER_THE", "RUNS_OUTSIDE_NOUN_PHRASE"]
+        );
+
+        // "lamp" is also the end of a noun phrase but a negated chunk must not match any of the chunks
+        let suggestions = rules.suggest("the light runs lamp runs", &tokenizer);
+        let ids: Vec<_> = suggestions.iter().map(|x| x.source.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec!["NOUN_PHRASE_AFTER_THE", "RUNS_OUTSIDE_NOUN_PHRASE"]
+        );
+    }
+
+    #[test]
+    fn parallel_chunks_equal_sequential_chunks() {
+        let (mut tokenizer, _) = setup();
+        let mut chunker = word_chunker();
+        assert!(!chunker.options().per_sentence);
+        chunker.set_options(
+            ChunkerOptions::builder()
+                .per_sentence(true)
+                .build()
+                .unwrap(),
+        );
+        tokenizer.set_chunker(Some(chunker));
+
+        let sentences = [
+            "The light runs.",
+            "the lamp runs lamp!",
+            "Runs the light lamp?",
+        ];
+        let text: String = (0..1000)
+            .map(|i| sentences[i % sentences.len()])
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let chunks = |tokenizer: &Tokenizer| -> Vec<_> {
+            tokenizer
+                .tokenize(&text)
+                .iter()
+                .map(|x| (x.chunks.clone(), x.chunk_confidence()))
+                .collect()
+        };
+
+        tokenizer.set_parallelism(Parallelism::Off).unwrap();
+        let sequential = chunks(&tokenizer);
+        // each sentence has at least one noun phrase
+        let starts = sequential
+            .iter()
+            .filter(|x| x.0.contains(&"B-NP-singular".to_string()));
+        assert!(starts.count() >= 1000);
+
+        for threads in &[1, 2, 4] {
+            tokenizer
+                .set_parallelism(Parallelism::Threads(*threads))
+                .unwrap();
+            assert_eq!(chunks(&tokenizer), sequential);
+        }
+    }
 }
@@ -0,0 +1,68 @@
+//! Tagging of multiword expressions like "New York" or "of course" as done by LanguageTool's `MultiWordChunker`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{owned, DefaultHashMap, IncompleteToken, WordData};
+
+/// A sequence of words which together get a phrase tag.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Multiword {
+    pub(crate) words: Vec<String>,
+    pub(crate) phrase: String,
+    pub(crate) tag: owned::PosId,
+}
+
+/// Adds a reading with the phrase as lemma and the phrase tag to each token of a multiword expression.
+/// The tokens themselves are kept so spans stay fine-grained.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MultiwordTagger {
+    /// Maps the first word of each expression to all expressions starting with it, longest first.
+    pub(crate) multiwords: DefaultHashMap<String, Vec<Multiword>>,
+}
+
+impl MultiwordTagger {
+    fn find_match<'a>(&'a self, tokens: &[IncompleteToken], start: usize) -> Option<&'a Multiword> {
+        let candidates = self.multiwords.get(tokens[start].word.text.as_ref())?;
+
+        candidates.iter().find(|multiword| {
+            let end = start + multiword.words.len();
+
+            end <= tokens.len()
+                && tokens[start..end]
+                    .iter()
+                    .zip(multiword.words.iter())
+                    .all(|(token, word)| token.word.text.as_ref() == word)
+                // expressions can not span across sentences or paragraphs
+                && tokens[start..end - 1].iter().all(|x| !x.is_sentence_end)
+                && tokens[start..end]
+                    .iter()
+                    .all(|x| x.paragraph == tokens[start].paragraph)
+        })
+    }
+
+    /// Tags the longest matching expression at each position. Expressions do not overlap.
+    pub fn apply<'t>(&'t self, tokens: &mut Vec<IncompleteToken<'t>>) {
+        let mut i = 0;
+
+        while i < tokens.len() {
+            if let Some(multiword) = self.find_match(tokens, i) {
+                let n = multiword.words.len();
+
+                for token in tokens[i..i + n].iter_mut() {
+                    let data = WordData::new(
+                        token.tagger.id_word(multiword.phrase.as_str().into()),
+                        multiword.tag.as_ref_id(),
+                    );
+
+                    if !token.word.tags.contains(&data) {
+                        token.word.tags.push(data);
+                    }
+                }
+
+                i += n;
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
@@ -1,21 +1,291 @@
 //! A dictionary-based tagger. The raw format is tuples of the form `(word, lemma, part-of-speech)`
 //! where each word typically has multiple entries with different part-of-speech tags.
 
-use crate::types::*;
-use bimap::BiMap;
-use indexmap::IndexMap;
+use super::TokenizerOptions;
+use crate::{types::*, utils::regex::SerializeRegex};
+use bimap::{BiHashMap, BiMap};
+use fst::{Map, Streamer};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::io::BufRead;
+use std::io::{BufRead, Write};
 use std::{borrow::Cow, fs::File};
+use std::{collections::HashSet, convert::TryFrom};
+use {indexmap::IndexMap, once_cell::sync::OnceCell};
+
+/// A bidirectional map between words and their IDs stored as a finite state transducer.
+/// The ID of a word is its index in the lexicographically sorted set of all words.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct WordStore {
+    map: Map<Vec<u8>>,
+}
+
+impl WordStore {
+    /// Creates a word store from words which must be sorted and unique.
+    fn from_sorted<S: AsRef<[u8]>, I: IntoIterator<Item = S>>(
+        words: I,
+    ) -> Result<Self, fst::Error> {
+        let map = Map::from_iter(
+            words
+                .into_iter()
+                .enumerate()
+                .map(|(i, word)| (word, i as u64)),
+        )?;
+
+        Ok(WordStore { map })
+    }
+
+    /// Gets the ID of the given word.
+    pub fn get_id(&self, word: &str) -> Option<u32> {
+        self.map.get(word).map(|x| x as u32)
+    }
+
+    /// Gets the word with the given ID.
+    pub fn get_word(&self, id: u32) -> Option<String> {
+        let fst = self.map.as_fst();
+        let mut node = fst.root();
+        let mut remaining = id as u64;
+        let mut bytes = Vec::new();
+
+        // IDs are indices in sorted order so the outputs on the transitions of a node are increasing.
+        // The word is found by following the transition with the largest output not exceeding the remaining ID.
+        loop {
+            if node.is_final() && node.final_output().value() == remaining {
+                return String::from_utf8(bytes).ok();
+            }
+
+            let transition = node
+                .transitions()
+                .take_while(|x| x.out.value() <= remaining)
+                .last()?;
+
+            remaining -= transition.out.value();
+            bytes.push(transition.inp);
+            node = fst.node(transition.addr);
+        }
+    }
+
+    /// Iterates over all (word, ID) pairs in order of their IDs.
+    pub fn iter(&self) -> impl Iterator<Item = (String, u32)> {
+        let mut stream = self.map.stream();
+        let mut output = Vec::with_capacity(self.len());
+
+        while let Some((word, id)) = stream.next() {
+            output.push((String::from_utf8_lossy(word).into_owned(), id as u32));
+        }
+
+        output.into_iter()
+    }
+
+    /// The number of words in this store.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl Default for WordStore {
+    fn default() -> Self {
+        WordStore::from_sorted(std::iter::empty::<&[u8]>()).unwrap()
+    }
+}
+
+impl TryFrom<Vec<u8>> for WordStore {
+    type Error = fst::Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(WordStore {
+            map: Map::new(bytes)?,
+        })
+    }
+}
+
+impl From<WordStore> for Vec<u8> {
+    fn from(store: WordStore) -> Self {
+        store.map.into_fst().into_inner()
+    }
+}
+
+/// Lists of values for dense IDs stored in one flat buffer. Deserializing two vectors is much faster than
+/// rebuilding a hash map with one allocation per entry, which dominated the time to load a tagger.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct IdMap<T> {
+    /// The values of the ID `i` are at `values[offsets[i]..offsets[i + 1]]`.
+    offsets: Vec<u32>,
+    values: Vec<T>,
+}
+
+impl<T> IdMap<T> {
+    /// Creates the map from lists of values. IDs must be smaller than `len`.
+    fn new(mut map: DefaultHashMap<u32, Vec<T>>, len: usize) -> Self {
+        let mut offsets = Vec::with_capacity(len + 1);
+        let mut values = Vec::new();
+
+        offsets.push(0);
+        for id in 0..len as u32 {
+            if let Some(list) = map.remove(&id) {
+                values.extend(list);
+            }
+            offsets.push(values.len() as u32);
+        }
+        assert!(map.is_empty(), "IDs must be smaller than the length");
+
+        IdMap { offsets, values }
+    }
+
+    /// Gets the values of the given ID. `None` if there are none.
+    fn get(&self, id: u32) -> Option<&[T]> {
+        let start = *self.offsets.get(id as usize)? as usize;
+        let end = *self.offsets.get(id as usize + 1)? as usize;
+
+        if start == end {
+            None
+        } else {
+            Some(&self.values[start..end])
+        }
+    }
+
+    fn contains_key(&self, id: u32) -> bool {
+        self.get(id).is_some()
+    }
+}
+
+/// The text of all lemmas in the dictionary stored in one buffer.
+/// Readings borrow their lemma from here instead of allocating it from the word store for every token.
+#[derive(Clone, Default)]
+struct LemmaStore {
+    text: String,
+    spans: DefaultHashMap<u32, (usize, usize)>,
+}
+
+impl LemmaStore {
+    fn new(tags: &IdMap<(u32, u16)>, word_store: &WordStore) -> Self {
+        let ids: HashSet<u32> = tags.values.iter().map(|(lemma_id, _)| *lemma_id).collect();
+        let lemmas: Vec<_> = word_store
+            .iter()
+            .filter(|(_, id)| ids.contains(id))
+            .collect();
+        let mut text = String::new();
+        let mut spans = DefaultHashMap::default();
+        let mut last: Option<(&str, usize)> = None;
+
+        // In reverse sorted order a lemma which is a prefix of another lemma directly follows the lemma it is
+        // a prefix of (or another lemma with the same prefix), so it is stored once as part of that lemma.
+        for (word, id) in lemmas.iter().rev() {
+            let start = match last {
+                Some((previous, start)) if previous.starts_with(word.as_str()) => start,
+                _ => {
+                    text.push_str(word);
+                    text.len() - word.len()
+                }
+            };
+
+            spans.insert(*id, (start, start + word.len()));
+            last = Some((&text[start..start + word.len()], start));
+        }
+
+        LemmaStore { text, spans }
+    }
+
+    fn get(&self, id: u32) -> Option<&str> {
+        self.spans
+            .get(&id)
+            .map(|(start, end)| &self.text[*start..*end])
+    }
+}
+
+/// How to look up other casings of a word in addition to the word itself.
+#[derive(Default)]
+struct CaseFallback<'a> {
+    add_title_case: bool,
+    never_lowercase_tags: &'a [String],
+}
 
 /// The lexical tagger.
-#[derive(Serialize, Deserialize, Default)]
+///
+/// Words added at runtime with [Tagger::add_word] are kept apart from the compiled dictionary:
+/// they are not interned in the word store (so matchers precomputed on word IDs fall back to matching the text)
+/// and they are not serialized with the tagger. Use [Tagger::write_user_words] and [Tagger::read_user_words] to persist them.
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Tagger {
-    tags: DefaultHashMap<u32, IndexMap<u32, Vec<u16>>>,
-    tag_store: BiMap<String, u16>,
-    word_store: BiMap<String, u32>,
-    groups: DefaultHashMap<u32, Vec<u32>>,
+    /// Maps each word to its readings as (lemma, tag) pairs. Readings with the same lemma are adjacent.
+    tags: IdMap<(u32, u16)>,
+    tag_store: BiHashMap<String, u16, DefaultBuildHasher, DefaultBuildHasher>,
+    word_store: WordStore,
+    /// Maps each lemma to its inflections as (word, tag) pairs. Used to synthesize words.
+    inflections: IdMap<(u32, u16)>,
+    /// Maps words to their frequency class. Empty if no frequency data was given at compile time.
+    frequencies: DefaultHashMap<u32, u8>,
+    /// Maps tags to a human-readable description. Empty if no descriptions were given at compile time.
+    tag_descriptions: DefaultHashMap<u16, String>,
+    #[serde(skip)]
+    user_tags: DefaultHashMap<String, IndexMap<String, Vec<u16>>>,
+    #[serde(skip)]
+    user_inflections: DefaultHashMap<String, Vec<(String, u16)>>,
+    /// Built on first use from the dictionary so it does not have to be serialized.
+    #[serde(skip)]
+    lemmas: OnceCell<LemmaStore>,
+    /// All words of the word store, only built for the deprecated methods which borrow words.
+    #[serde(skip)]
+    word_map: OnceCell<BiMap<String, u32>>,
+}
+
+/// Parses entries of the form `word<TAB>lemma<TAB>tag`, one per line. Empty lines and lines starting with `#` are skipped.
+/// Surrounding whitespace and Windows line endings are tolerated, and fields may be separated by spaces if there is no tab.
+/// `source` is used to point to the offending line in errors.
+fn parse_entries<R: BufRead>(
+    reader: R,
+    source: &str,
+) -> std::io::Result<Vec<(String, String, String)>> {
+    let mut output = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<_> = if trimmed.contains('\t') {
+            trimmed.split('\t').map(|x| x.trim()).collect()
+        } else {
+            trimmed.split_whitespace().collect()
+        };
+
+        match parts[..] {
+            [word, lemma, tag] if !word.is_empty() && !lemma.is_empty() && !tag.is_empty() => {
+                output.push((word.to_string(), lemma.to_string(), tag.to_string()))
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: expected `word<TAB>lemma<TAB>tag`, found {:?}",
+                        source,
+                        i + 1,
+                        line
+                    ),
+                ))
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Reads the entries of a dump or, with the `compile` feature, of a morfologik dictionary if the path ends with `.dict`.
+fn read_entries(path: &str) -> std::io::Result<Vec<(String, String, String)>> {
+    #[cfg(feature = "compile")]
+    {
+        if path.ends_with(".dict") {
+            return crate::compile::morfologik::read_dictionary(path);
+        }
+    }
+
+    let file = File::open(path)?;
+    parse_entries(std::io::BufReader::new(file), path)
 }
 
 impl Tagger {
@@ -24,43 +294,17 @@ impl Tagger {
         remove_paths: &[S2],
     ) -> std::io::Result<Vec<(String, String, String)>> {
         let mut output = Vec::new();
-        let mut disallowed: Vec<String> = Vec::new();
+        let mut disallowed = HashSet::new();
 
         for path in remove_paths {
-            let file = File::open(path.as_ref())?;
-            let reader = std::io::BufReader::new(file);
-
-            for line in reader.lines() {
-                let line = line?;
-                if line.starts_with('#') {
-                    continue;
-                }
-
-                disallowed.push(line.to_string());
-            }
+            disallowed.extend(read_entries(path.as_ref())?);
         }
 
         for path in paths {
-            let file = File::open(path.as_ref())?;
-            let reader = std::io::BufReader::new(file);
-
-            for line in reader.lines() {
-                let line = line?;
-                if line.starts_with('#') {
-                    continue;
+            for entry in read_entries(path.as_ref())? {
+                if !disallowed.contains(&entry) {
+                    output.push(entry);
                 }
-
-                let parts: Vec<_> = line.split('\t').collect();
-
-                if disallowed.contains(&line) {
-                    continue;
-                }
-
-                let word = parts[0].to_string();
-                let inflection = parts[1].to_string();
-                let tag = parts[2].to_string();
-
-                output.push((word, inflection, tag))
             }
         }
 
@@ -71,17 +315,21 @@ impl Tagger {
     ///
     /// # Arguments
     /// * `paths`: Paths to files where each line contains the word, lemma and tag, respectively,
-    /// separated by tabs, to be added to the tagger.
+    ///   separated by tabs, to be added to the tagger. Typically the dump of the LanguageTool dictionary and its `added.txt`.
+    ///   With the `compile` feature, paths ending with `.dict` are read as morfologik dictionary instead
+    ///   e. g. the `english.dict` shipped with LanguageTool. The `.info` file has to be next to it.
     /// * `remove_paths`: Paths to files where each line contains the word, lemma and tag, respectively,
-    /// separated by tabs, to be removed from the tagger if present in the files from `paths`.
+    ///   separated by tabs, to be removed from the tagger if present in the files from `paths`. Typically LanguageTool's `removed.txt`.
+    ///
+    /// Fails with the file and line number if a line is malformed.
     pub fn from_dumps<S1: AsRef<str>, S2: AsRef<str>, S3: AsRef<str>>(
         paths: &[S1],
         remove_paths: &[S2],
         extra_tags: &[S3],
         common_words: &HashSet<String>,
     ) -> std::io::Result<Self> {
-        let mut tags = DefaultHashMap::default();
-        let mut groups = DefaultHashMap::default();
+        let mut tags: DefaultHashMap<u32, IndexMap<u32, Vec<u16>>> = DefaultHashMap::default();
+        let mut inflections: DefaultHashMap<u32, Vec<(u32, u16)>> = DefaultHashMap::default();
 
         let mut tag_store = HashSet::new();
         let mut word_store = HashSet::new();
@@ -90,7 +338,11 @@ impl Tagger {
         tag_store.insert("");
         tag_store.insert("SENT_START");
         tag_store.insert("SENT_END");
+        tag_store.insert("PARA_START");
+        tag_store.insert("PARA_END");
         tag_store.insert("UNKNOWN");
+        tag_store.insert("OPAQUE");
+        tag_store.insert("GUESSED");
 
         // add language specific special tags
         tag_store.extend(extra_tags.iter().map(|x| x.as_ref()));
@@ -118,64 +370,317 @@ impl Tagger {
         let mut tag_store: Vec<_> = tag_store.iter().collect();
         tag_store.sort();
 
-        let word_store: BiMap<_, _> = word_store
-            .iter()
-            .enumerate()
-            .map(|(i, x)| (x.to_string(), i as u32))
-            .collect();
-        let tag_store: BiMap<_, _> = tag_store
+        let word_store = WordStore::from_sorted(word_store).map_err(std::io::Error::other)?;
+        let tag_store: BiHashMap<_, _, DefaultBuildHasher, DefaultBuildHasher> = tag_store
             .iter()
             .enumerate()
             .map(|(i, x)| (x.to_string(), i as u16))
             .collect();
 
         for (word, inflection, tag) in lines.iter() {
-            let word_id = word_store.get_by_left(word).unwrap();
-            let inflection_id = word_store.get_by_left(inflection).unwrap();
+            let word_id = word_store.get_id(word).unwrap();
+            let inflection_id = word_store.get_id(inflection).unwrap();
             let tag_id = tag_store.get_by_left(tag).unwrap();
 
-            let group = groups.entry(*inflection_id).or_insert_with(Vec::new);
-            if !group.contains(word_id) {
-                group.push(*word_id);
+            let lemma_inflections = inflections.entry(inflection_id).or_default();
+            if !lemma_inflections.contains(&(word_id, *tag_id)) {
+                lemma_inflections.push((word_id, *tag_id));
             }
 
-            tags.entry(*word_id)
-                .or_insert_with(IndexMap::new)
-                .entry(*inflection_id)
-                .or_insert_with(Vec::new)
+            tags.entry(word_id)
+                .or_default()
+                .entry(inflection_id)
+                .or_default()
                 .push(*tag_id);
         }
 
+        let tags = tags
+            .into_iter()
+            .map(|(word_id, map)| {
+                let readings = map
+                    .into_iter()
+                    .flat_map(|(lemma_id, tag_ids)| {
+                        tag_ids.into_iter().map(move |tag_id| (lemma_id, tag_id))
+                    })
+                    .collect();
+                (word_id, readings)
+            })
+            .collect();
+
         Ok(Tagger {
-            tags,
-            groups,
+            tags: IdMap::new(tags, word_store.len()),
+            inflections: IdMap::new(inflections, word_store.len()),
             word_store,
             tag_store,
+            ..Default::default()
         })
     }
 
-    #[allow(clippy::clippy::ptr_arg)]
-    fn get_raw(&self, word: &String) -> Vec<WordData> {
-        if let Some(map) = self
-            .word_store
-            .get_by_left(word)
-            .and_then(|x| self.tags.get(x))
+    /// Reads word frequencies from a file where each line contains a word and its count, separated by a tab.
+    /// The count of each word is stored as a frequency class i. e. the number of bits needed to represent it,
+    /// so the most frequent words have the highest class. Words which are not known to the tagger are ignored.
+    pub fn add_frequencies_from_dump<S: AsRef<str>>(&mut self, path: S) -> std::io::Result<()> {
+        let file = File::open(path.as_ref())?;
+        let reader = std::io::BufReader::new(file);
+        let mut counts: DefaultHashMap<u32, u64> = DefaultHashMap::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parts: Vec<_> = line.split('\t').collect();
+            let count = match parts[..] {
+                [_, count] => count.trim().parse::<u64>().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid frequency line: {:?}", line),
+                )
+            })?;
+
+            if let Some(id) = self.word_store.get_id(parts[0]) {
+                let total = counts.entry(id).or_default();
+                *total = total.saturating_add(count);
+            }
+        }
+
+        for (id, count) in counts {
+            self.frequencies
+                .insert(id, (64 - count.leading_zeros()) as u8);
+        }
+
+        Ok(())
+    }
+
+    /// Reads tag descriptions from a file where each line contains a tag and its description, separated by a tab.
+    /// Tags which are not known to the tagger are ignored.
+    pub fn add_tag_descriptions_from_dump<S: AsRef<str>>(
+        &mut self,
+        path: S,
+    ) -> std::io::Result<()> {
+        let file = File::open(path.as_ref())?;
+        let reader = std::io::BufReader::new(file);
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let (tag, description) = trimmed
+                .split_once('\t')
+                .map(|(tag, description)| (tag.trim(), description.trim()))
+                .filter(|(tag, description)| !tag.is_empty() && !description.is_empty())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "{}:{}: expected `tag<TAB>description`, found {:?}",
+                            path.as_ref(),
+                            i + 1,
+                            line
+                        ),
+                    )
+                })?;
+
+            if let Some(id) = self.tag_store.get_by_left(tag) {
+                self.tag_descriptions.insert(*id, description.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes all tag descriptions e. g. to reduce the size of the serialized tagger.
+    pub fn clear_tag_descriptions(&mut self) {
+        self.tag_descriptions = DefaultHashMap::default();
+    }
+
+    /// Iterates over all part-of-speech tags known to the tagger, including special tags like `SENT_START`, in order of their IDs.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        let mut tags: Vec<_> = self.tag_store.iter().collect();
+        tags.sort_by_key(|(_, id)| **id);
+
+        tags.into_iter().map(|(tag, _)| tag.as_str())
+    }
+
+    /// Gets the human-readable description of the given tag e. g. "verb, past tense" for "VBD" in English.
+    /// Returns `None` if the tag is unknown or the tagger has no tag descriptions.
+    pub fn tag_description(&self, tag: &str) -> Option<&str> {
+        self.tag_store
+            .get_by_left(tag)
+            .and_then(|id| self.tag_descriptions.get(id))
+            .map(|x| x.as_str())
+    }
+
+    /// Gets the frequency class of the given word. Higher is more frequent.
+    /// Returns `None` if the word is unknown or the tagger has no frequency data.
+    pub fn frequency(&self, word: &str) -> Option<u8> {
+        self.word_store
+            .get_id(word)
+            .and_then(|id| self.frequencies.get(&id))
+            .copied()
+    }
+
+    /// Sorts the given phrases from most to least frequent. The frequency of a phrase is the lowest
+    /// frequency class of its words with frequency data, looking up the lowercase variant of a word if it has no frequency itself.
+    /// The sort is stable so the order does not change if the tagger has no frequency data.
+    pub fn sort_by_frequency<S: AsRef<str>>(&self, phrases: &mut [S]) {
+        if self.frequencies.is_empty() {
+            return;
+        }
+
+        phrases.sort_by_cached_key(|phrase| {
+            let frequency = phrase
+                .as_ref()
+                .split_whitespace()
+                .filter_map(|word| {
+                    self.frequency(word)
+                        .or_else(|| self.frequency(&word.to_lowercase()))
+                })
+                .min()
+                .unwrap_or(0);
+
+            std::cmp::Reverse(frequency)
+        });
+    }
+
+    /// Checks that an entry can be added to the user dictionary and returns the ID of its tag.
+    fn validate_entry(&self, word: &str, lemma: &str, pos: &str) -> Result<u16, crate::Error> {
+        if word.is_empty() || lemma.is_empty() {
+            return Err(crate::Error::InvalidEntry(format!(
+                "word and lemma must not be empty: ({:?}, {:?}, {:?})",
+                word, lemma, pos
+            )));
+        }
+
+        self.tag_store
+            .get_by_left(pos)
+            .copied()
+            .ok_or_else(|| crate::Error::UnknownTag(pos.to_string()))
+    }
+
+    /// Adds a reading to the user dictionary. The word is tagged with the given lemma and part-of-speech
+    /// in addition to any readings it already has.
+    /// Only tags which are already known to the tagger can be used since rules are compiled against
+    /// the set of known tags.
+    pub fn add_word(&mut self, word: &str, lemma: &str, pos: &str) -> Result<(), crate::Error> {
+        let tag_id = self.validate_entry(word, lemma, pos)?;
+
+        let tag_ids = self
+            .user_tags
+            .entry(word.to_string())
+            .or_default()
+            .entry(lemma.to_string())
+            .or_default();
+        if !tag_ids.contains(&tag_id) {
+            tag_ids.push(tag_id);
+        }
+
+        let lemma_inflections = self.user_inflections.entry(lemma.to_string()).or_default();
+        if !lemma_inflections
+            .iter()
+            .any(|(x, id)| x == word && *id == tag_id)
         {
-            let mut output = Vec::new();
+            lemma_inflections.push((word.to_string(), tag_id));
+        }
 
-            for (key, value) in map.iter() {
-                for tag_id in value {
-                    output.push(WordData::new(
-                        self.id_word(self.word_store.get_by_right(key).unwrap().as_str().into()),
-                        self.id_tag(self.tag_store.get_by_right(tag_id).unwrap().as_str()),
-                    ))
+        Ok(())
+    }
+
+    /// Adds multiple readings of the form `(word, lemma, part-of-speech)` to the user dictionary.
+    /// All entries are validated before any is added so either all or none are added.
+    pub fn add_words<S1: AsRef<str>, S2: AsRef<str>, S3: AsRef<str>>(
+        &mut self,
+        entries: &[(S1, S2, S3)],
+    ) -> Result<(), crate::Error> {
+        for (word, lemma, pos) in entries {
+            self.validate_entry(word.as_ref(), lemma.as_ref(), pos.as_ref())?;
+        }
+
+        for (word, lemma, pos) in entries {
+            self.add_word(word.as_ref(), lemma.as_ref(), pos.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the user dictionary in the same tab-separated format as the raw tagger dumps.
+    pub fn write_user_words<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        for (word, map) in self.user_tags.iter() {
+            for (lemma, tag_ids) in map.iter() {
+                for tag_id in tag_ids {
+                    let tag = self.tag_store.get_by_right(tag_id).unwrap();
+                    writeln!(writer, "{}\t{}\t{}", word, lemma, tag)?;
                 }
             }
+        }
 
-            output
-        } else {
-            Vec::new()
+        Ok(())
+    }
+
+    /// Reads entries in the format written by [Tagger::write_user_words] and adds them to the user dictionary.
+    pub fn read_user_words<R: BufRead>(&mut self, reader: R) -> Result<(), crate::Error> {
+        let entries = parse_entries(reader, "user dictionary")?;
+        self.add_words(&entries)
+    }
+
+    /// Removes all words added to the user dictionary.
+    pub fn clear_user_words(&mut self) {
+        self.user_tags.clear();
+        self.user_inflections.clear();
+    }
+
+    fn lemmas(&self) -> &LemmaStore {
+        self.lemmas
+            .get_or_init(|| LemmaStore::new(&self.tags, &self.word_store))
+    }
+
+    fn word_map(&self) -> &BiMap<String, u32> {
+        self.word_map
+            .get_or_init(|| self.word_store.iter().collect())
+    }
+
+    fn get_raw(&self, word: &str) -> Vec<WordData<'_>> {
+        let map = self.word_store.get_id(word).and_then(|x| self.tags.get(x));
+        let user_map = self.user_tags.get(word);
+
+        let n_readings =
+            map.map_or(0, <[_]>::len) + user_map.map_or(0, |x| x.values().map(Vec::len).sum());
+        let mut output = Vec::with_capacity(n_readings);
+
+        if let Some(map) = map {
+            let lemmas = self.lemmas();
+
+            for (key, tag_id) in map {
+                let lemma = lemmas.get(*key).unwrap();
+
+                output.push(WordData::new(
+                    WordId(lemma.into(), Some(*key)),
+                    self.pos_id(*tag_id),
+                ))
+            }
         }
+
+        if let Some(map) = user_map {
+            for (lemma, value) in map.iter() {
+                for tag_id in value {
+                    let data =
+                        WordData::new(self.id_word(lemma.as_str().into()), self.pos_id(*tag_id));
+
+                    if !output.contains(&data) {
+                        output.push(data);
+                    }
+                }
+            }
+        }
+
+        output
     }
 
     fn get_strict_tags(
@@ -183,52 +688,171 @@ impl Tagger {
         word: &str,
         add_lower: bool,
         add_lower_if_empty: bool,
-    ) -> Vec<WordData> {
-        let mut tags = self.get_raw(&word.to_string());
+        case: &CaseFallback,
+    ) -> Vec<WordData<'_>> {
+        let mut tags = self.get_raw(word);
         let lower = word.to_lowercase();
+        let is_uppercase = crate::utils::is_uppercase(word);
 
         if (add_lower || (add_lower_if_empty && tags.is_empty()))
-            && (word != lower
-                && (crate::utils::is_title_case(word) || crate::utils::is_uppercase(word)))
+            && (word != lower && (crate::utils::is_title_case(word) || is_uppercase))
         {
-            tags.extend(self.get_raw(&lower));
+            tags.extend(self.get_raw(&lower).into_iter().filter(|x| {
+                !case
+                    .never_lowercase_tags
+                    .iter()
+                    .any(|prefix| x.pos.as_ref().starts_with(prefix.as_str()))
+            }));
+        }
+
+        if case.add_title_case && is_uppercase {
+            let title = crate::utils::apply_to_first(&lower, |c| c.to_uppercase().collect());
+
+            if title != word {
+                for data in self.get_raw(&title) {
+                    if !tags.contains(&data) {
+                        tags.push(data);
+                    }
+                }
+            }
         }
 
         tags
     }
 
-    pub fn tag_store(&self) -> &BiMap<String, u16> {
+    pub fn tag_store(&self) -> &BiHashMap<String, u16, DefaultBuildHasher, DefaultBuildHasher> {
         &self.tag_store
     }
 
-    pub fn word_store(&self) -> &BiMap<String, u32> {
+    /// The words known to the tagger and their IDs.
+    pub fn words(&self) -> &WordStore {
         &self.word_store
     }
 
+    /// The words known to the tagger and their IDs as map. The map is built on the first call, use [Tagger::words]
+    /// instead.
+    #[deprecated(note = "use `Tagger::words` instead")]
+    pub fn word_store(&self) -> &BiMap<String, u32> {
+        self.word_map()
+    }
+
+    /// A hash of the words and tags. Words added with [Tagger::add_word] are not included since rules
+    /// do not refer to them by ID.
+    pub fn fingerprint(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::default();
+        self.word_store.map.as_fst().as_bytes().hash(&mut hasher);
+
+        let mut tags: Vec<_> = self
+            .tag_store
+            .iter()
+            .map(|(tag, id)| (*id, tag.as_str()))
+            .collect();
+        tags.sort_unstable();
+        tags.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
     pub fn id_tag<'a>(&self, tag: &'a str) -> PosId<'a> {
         PosId(tag, *self.tag_store.get_by_left(tag).unwrap())
     }
 
+    /// The tag with the given ID, without looking the ID up again like [Tagger::id_tag] does.
+    fn pos_id(&self, tag_id: u16) -> PosId<'_> {
+        PosId(
+            self.tag_store.get_by_right(&tag_id).unwrap().as_str(),
+            tag_id,
+        )
+    }
+
     pub fn id_word<'t>(&'t self, text: Cow<'t, str>) -> WordId<'t> {
-        let id = self.word_store.get_by_left(text.as_ref()).copied();
+        let id = self.word_store.get_id(text.as_ref());
         WordId(text, id)
     }
 
+    /// Whether the word is known to the tagger as a word with readings or as a lemma (including the user dictionary).
+    /// The lookup is case sensitive.
+    pub fn contains(&self, word: &str) -> bool {
+        let in_dictionary = self
+            .word_store
+            .get_id(word)
+            .filter(|id| self.tags.contains_key(*id) || self.inflections.contains_key(*id))
+            .is_some();
+
+        in_dictionary
+            || self.user_tags.contains_key(word)
+            || self.user_inflections.contains_key(word)
+    }
+
     /// Get the tags and lemmas (as [WordData][crate::types::WordData]) for the given word.
     ///
+    /// The lookup is case sensitive. Data for the lowercase variant of the word is added if the word is title case
+    /// or uppercase and either `add_lower` is true or the word itself has no data. So "Walked" gets the readings of "walked"
+    /// if it has none itself, but "wALKED" never does. Lowercase words are never uppercased.
+    ///
     /// # Arguments
     /// * `word`: The word to lookup data for.
-    /// * `add_lower`: Whether to add data for the lowercase variant of the word.
-    /// * `use_compound_split_heuristic`: Whether to use a heuristic to split compound words.
-    /// If true, will attempt to find tags for words which are longer than some cutoff and unknown by looking up tags
-    /// for substrings from left to right until tags are found or a minimum length reached.
-    pub fn get_tags(
+    /// * `add_lower`: Whether to always add data for the lowercase variant of the word.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use nlprule::tokenizer::tag::Tagger;
+    /// use std::{collections::HashSet, io::Write};
+    ///
+    /// let path = std::env::temp_dir().join("nlprule_doctest_tags.txt");
+    /// std::fs::File::create(&path)?.write_all(b"walked\twalk\tVBD\nwalked\twalk\tVBN\n")?;
+    /// let tagger = Tagger::from_dumps::<_, &str, &str>(&[path.to_str().unwrap()], &[], &[], &HashSet::new())?;
+    ///
+    /// let tags = tagger.get_tags("Walked", false);
+    /// let readings: Vec<_> = tags
+    ///     .iter()
+    ///     .map(|x| (x.lemma(), x.pos()))
+    ///     .collect();
+    /// assert_eq!(readings, vec![("walk", "VBD"), ("walk", "VBN")]);
+    ///
+    /// assert!(tagger.contains("walk"));
+    /// assert!(!tagger.contains("Walk"));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn get_tags(&self, word: &str, add_lower: bool) -> Vec<WordData<'_>> {
+        self.lookup(word, add_lower, false, &CaseFallback::default())
+    }
+
+    /// Get the tags and lemmas for the given word like [Tagger::get_tags] with the language-specific lookup options
+    /// of a tokenizer:
+    /// * [TokenizerOptions::never_lowercase_tags]: Readings with these tags are never added from the lowercase variant.
+    /// * [TokenizerOptions::add_title_case_tags]: Whether to add data for the title case variant of uppercase words
+    ///   e. g. "Paris" for "PARIS".
+    /// * [TokenizerOptions::use_compound_split_heuristic]: Whether to use a heuristic to split compound words.
+    ///   If true, will attempt to find tags for words which are longer than some cutoff and unknown by looking up tags
+    ///   for substrings from left to right until tags are found or a minimum length reached.
+    ///
+    /// `add_lower` is not taken from the options since it typically depends on the position of the word e. g. at sentence start.
+    pub fn get_tags_with(
+        &self,
+        word: &str,
+        add_lower: bool,
+        options: &TokenizerOptions,
+    ) -> Vec<WordData<'_>> {
+        let case = CaseFallback {
+            add_title_case: options.add_title_case_tags,
+            never_lowercase_tags: &options.never_lowercase_tags,
+        };
+
+        self.lookup(word, add_lower, options.use_compound_split_heuristic, &case)
+    }
+
+    fn lookup(
         &self,
         word: &str,
         add_lower: bool,
         use_compound_split_heuristic: bool,
-    ) -> Vec<WordData> {
-        let mut tags = self.get_strict_tags(word, add_lower, true);
+        case: &CaseFallback,
+    ) -> Vec<WordData<'_>> {
+        let mut tags = self.get_strict_tags(word, add_lower, true, case);
 
         // compound splitting heuristic, seems to work reasonably well
         if use_compound_split_heuristic && tags.is_empty() {
@@ -248,7 +872,7 @@ impl Tagger {
                         word[i..].to_string()
                     };
 
-                    let next_tags = self.get_strict_tags(&next, add_lower, false);
+                    let next_tags = self.get_strict_tags(&next, add_lower, false, case);
 
                     if !next_tags.is_empty() {
                         tags = next_tags
@@ -270,17 +894,529 @@ impl Tagger {
         tags
     }
 
+    /// Get all inflections of the given lemma as (word, part-of-speech tag) pairs, including the user dictionary.
+    /// Inflections from the dictionary come first, in the order of the dumps the tagger was created from.
+    pub fn get_inflections(&self, lemma: &str) -> Vec<(Cow<'_, str>, PosId<'_>)> {
+        let dictionary = self
+            .word_store
+            .get_id(lemma)
+            .and_then(|x| self.inflections.get(x))
+            .into_iter()
+            .flatten()
+            .map(|(word_id, tag_id)| {
+                let word: Cow<str> = self.word_store.get_word(*word_id).unwrap().into();
+                (word, tag_id)
+            });
+        let user = self
+            .user_inflections
+            .get(lemma)
+            .into_iter()
+            .flatten()
+            .map(|(word, tag_id)| (word.as_str().into(), tag_id));
+
+        let mut output: Vec<(Cow<str>, PosId)> = Vec::new();
+        for (word, tag_id) in dictionary.chain(user) {
+            let pos = self.pos_id(*tag_id);
+
+            if !output.iter().any(|(x, y)| *x == word && *y == pos) {
+                output.push((word, pos));
+            }
+        }
+
+        output
+    }
+
+    /// Synthesizes all words with the given lemma and part-of-speech tag e. g. "went" from "go" and "VBD".
+    pub fn synthesize(&self, lemma: &str, pos: &str) -> Vec<String> {
+        self.synthesize_with(lemma, |x| x == pos)
+    }
+
+    /// Synthesizes all words with the given lemma and a part-of-speech tag fully matching the given regex.
+    /// Fails if the regex is invalid.
+    pub fn synthesize_regex(
+        &self,
+        lemma: &str,
+        pos_regex: &str,
+    ) -> Result<Vec<String>, crate::Error> {
+        let regex = SerializeRegex::new(pos_regex, true, true)?;
+        Ok(self.synthesize_with(lemma, |x| regex.is_match(x)))
+    }
+
+    /// Synthesizes all words with the given lemma and a part-of-speech tag for which `f` returns true.
+    pub fn synthesize_with<F: Fn(&str) -> bool>(&self, lemma: &str, f: F) -> Vec<String> {
+        let mut output: Vec<String> = Vec::new();
+
+        for (word, pos) in self.get_inflections(lemma) {
+            if f(pos.as_ref()) && !output.iter().any(|x| *x == word) {
+                output.push(word.into_owned());
+            }
+        }
+
+        output
+    }
+
+    /// Get the words with the same lemma as the given lemma.
+    pub fn group_members(&self, lemma: &str) -> Vec<Cow<'_, str>> {
+        let mut members = Vec::new();
+
+        for (word, _) in self.get_inflections(lemma) {
+            if !members.contains(&word) {
+                members.push(word);
+            }
+        }
+
+        members
+    }
+
     /// Get the words with the same lemma as the given lemma.
-    #[allow(clippy::clippy::ptr_arg)]
+    ///
+    /// The words are borrowed from a map of all words which is built on the first call, use [Tagger::group_members]
+    /// instead.
+    #[deprecated(note = "use `Tagger::group_members` instead")]
+    #[allow(clippy::ptr_arg)]
     pub fn get_group_members(&self, lemma: &String) -> Vec<&str> {
-        self.word_store
-            .get_by_left(lemma)
-            .and_then(|x| self.groups.get(x))
-            .map(|vec| {
-                vec.iter()
-                    .map(|x| self.word_store.get_by_right(x).unwrap().as_str())
-                    .collect()
-            })
-            .unwrap_or_else(Vec::new)
+        let word_map = self.word_map();
+        let mut members: Vec<&str> = Vec::new();
+
+        let dictionary = self
+            .word_store
+            .get_id(lemma)
+            .and_then(|x| self.inflections.get(x))
+            .into_iter()
+            .flatten()
+            .map(|(word_id, _)| word_map.get_by_right(word_id).unwrap().as_str());
+        let user = self
+            .user_inflections
+            .get(lemma.as_str())
+            .into_iter()
+            .flatten()
+            .map(|(word, _)| word.as_str());
+
+        for word in dictionary.chain(user) {
+            if !members.contains(&word) {
+                members.push(word);
+            }
+        }
+
+        members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_store_ids_are_sorted_indices() {
+        let mut words = vec!["walked", "walk", "Walk", "über", "a", "walks", "wal"];
+        words.sort_unstable();
+        let store = WordStore::from_sorted(&words).unwrap();
+
+        assert_eq!(store.len(), words.len());
+        for (i, word) in words.iter().enumerate() {
+            assert_eq!(store.get_id(word), Some(i as u32));
+            assert_eq!(store.get_word(i as u32).as_deref(), Some(*word));
+        }
+        assert_eq!(store.get_id("wa"), None);
+        assert_eq!(store.get_word(words.len() as u32), None);
+
+        let iterated: Vec<_> = store.iter().map(|(word, _)| word).collect();
+        assert_eq!(iterated, words);
+
+        let bytes = bincode::serialize(&store).unwrap();
+        let deserialized: WordStore = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(deserialized.get_id("über"), store.get_id("über"));
+    }
+
+    #[test]
+    fn lemmas_are_stored_once() {
+        let dump = write_temp(
+            "lemmas.txt",
+            "walker\twalker\tNN\nwalks\twalk\tVBZ\nwalked\twalk\tVBD\nwal\twal\tNN\nWalk\tWalk\tNNP\n",
+        );
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[dump.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+
+        // "walk" and "wal" are prefixes of "walker"
+        let lemmas = tagger.lemmas();
+        assert_eq!(lemmas.text.len(), "walker".len() + "Walk".len());
+        for lemma in ["walker", "walk", "wal", "Walk"] {
+            let id = tagger.words().get_id(lemma).unwrap();
+            assert_eq!(lemmas.get(id), Some(lemma));
+        }
+        assert_eq!(
+            readings(&tagger, "walks"),
+            vec![("walk".to_string(), "VBZ".to_string())]
+        );
+    }
+
+    fn write_temp(name: &str, content: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("nlprule_tag_{}_{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn readings(tagger: &Tagger, word: &str) -> Vec<(String, String)> {
+        tagger
+            .get_tags(word, false)
+            .iter()
+            .map(|x| (x.lemma().to_string(), x.pos().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn added_and_removed_entries_are_applied() {
+        let dump = write_temp(
+            "dump.txt",
+            "walks\twalk\tNNS\nwalks\twalk\tVBZ\nwalked\twalk\tVBD\n",
+        );
+        let added = write_temp(
+            "added.txt",
+            "# added entries\r\nwalkies\twalkies\tNN\r\n\r\n  walked walk VBN  \r\n",
+        );
+        let removed = write_temp("removed.txt", "#removed entries\nwalks\twalk\tNNS\n");
+
+        let tagger = Tagger::from_dumps::<_, _, &str>(
+            &[dump.to_str().unwrap(), added.to_str().unwrap()],
+            &[removed.to_str().unwrap()],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            readings(&tagger, "walks"),
+            vec![("walk".to_string(), "VBZ".to_string())]
+        );
+        assert_eq!(
+            readings(&tagger, "walked"),
+            vec![
+                ("walk".to_string(), "VBD".to_string()),
+                ("walk".to_string(), "VBN".to_string())
+            ]
+        );
+        assert_eq!(
+            readings(&tagger, "walkies"),
+            vec![("walkies".to_string(), "NN".to_string())]
+        );
+    }
+
+    #[test]
+    fn malformed_entries_report_line() {
+        let dump = write_temp(
+            "malformed.txt",
+            "# comment\nwalks\twalk\tVBZ\nwalked\twalk\n",
+        );
+
+        let error = Tagger::from_dumps::<_, &str, &str>(
+            &[dump.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("malformed.txt:3:"));
+        assert!(error.to_string().contains("walked\\twalk"));
+    }
+
+    #[test]
+    fn tags_can_be_listed_and_described() {
+        let dump = write_temp("described.txt", "walked\twalk\tVBD\nwalks\twalk\tVBZ\n");
+        let descriptions = write_temp(
+            "descriptions.txt",
+            "# tag descriptions\nVBD\tverb, past tense\nXYZ\tunknown tag\n",
+        );
+
+        let mut tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[dump.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+
+        let tags: Vec<_> = tagger.tags().collect();
+        assert!(tags.contains(&"VBD") && tags.contains(&"VBZ") && tags.contains(&"SENT_START"));
+        assert_eq!(tags.len(), tagger.tag_store().len());
+        assert_eq!(tagger.tag_description("VBD"), None);
+
+        tagger
+            .add_tag_descriptions_from_dump(descriptions.to_str().unwrap())
+            .unwrap();
+        assert_eq!(tagger.tag_description("VBD"), Some("verb, past tense"));
+        assert_eq!(tagger.tag_description("VBZ"), None);
+        assert_eq!(tagger.tag_description("XYZ"), None);
+
+        let size = bincode::serialize(&tagger).unwrap().len();
+        tagger.clear_tag_descriptions();
+        assert_eq!(tagger.tag_description("VBD"), None);
+        assert!(bincode::serialize(&tagger).unwrap().len() < size);
+    }
+
+    type CaseTable<'a> = &'a [(&'a str, bool, &'a [(&'a str, &'a str)])];
+
+    fn check_case_table(dump: &str, options: &TokenizerOptions, table: CaseTable) {
+        let path = write_temp(&format!("case_{}.txt", dump.len()), dump);
+        let tagger = Tagger::from_dumps::<_, &str, &str>(
+            &[path.to_str().unwrap()],
+            &[],
+            &[],
+            &HashSet::new(),
+        )
+        .unwrap();
+
+        for (word, sentence_start, expected) in table {
+            let tags = tagger.get_tags_with(
+                word,
+                *sentence_start || options.always_add_lower_tags,
+                options,
+            );
+            let readings: Vec<_> = tags.iter().map(|x| (x.lemma(), x.pos())).collect();
+            assert_eq!(&readings, expected, "readings of {:?}", word);
+        }
+    }
+
+    #[test]
+    fn case_fallback_english() {
+        let dump =
+            "the\tthe\tDT\nhowever\thowever\tRB\nimportant\timportant\tJJ\nParis\tParis\tNNP\n\
+                    will\twill\tMD\nWill\tWill\tNNP\nus\twe\tPRP\nUS\tUS\tNNP\n";
+        let options = TokenizerOptions::builder()
+            .always_add_lower_tags(true)
+            .add_title_case_tags(true)
+            .build()
+            .unwrap();
+
+        check_case_table(
+            dump,
+            &options,
+            &[
+                ("The", true, &[("the", "DT")]),
+                ("However", true, &[("however", "RB")]),
+                ("IMPORTANT", false, &[("important", "JJ")]),
+                ("PARIS", false, &[("Paris", "NNP")]),
+                ("Will", false, &[("Will", "NNP"), ("will", "MD")]),
+                ("US", false, &[("US", "NNP"), ("we", "PRP")]),
+                ("paris", false, &[]),
+                ("iMPORTANT", false, &[]),
+            ],
+        );
+
+        let options = TokenizerOptions::builder()
+            .always_add_lower_tags(true)
+            .never_lowercase_tags(vec!["PRP".into()])
+            .build()
+            .unwrap();
+
+        check_case_table(
+            dump,
+            &options,
+            &[
+                ("US", false, &[("US", "NNP")]),
+                ("PARIS", false, &[]),
+                ("The", true, &[("the", "DT")]),
+            ],
+        );
+    }
+
+    #[test]
+    fn case_fallback_german() {
+        let dump = "Essen\tEssen\tSUB:NOM:SIN:NEU\nessen\tessen\tVER:INF:NON\n\
+                    Arm\tArm\tSUB:NOM:SIN:MAS\narm\tarm\tADJ:PRD:GRU\nder\tder\tART:DEF:NOM:SIN:MAS\n";
+        let options = TokenizerOptions::builder()
+            .add_title_case_tags(true)
+            .build()
+            .unwrap();
+
+        check_case_table(
+            dump,
+            &options,
+            &[
+                ("Der", true, &[("der", "ART:DEF:NOM:SIN:MAS")]),
+                ("Essen", false, &[("Essen", "SUB:NOM:SIN:NEU")]),
+                (
+                    "Essen",
+                    true,
+                    &[("Essen", "SUB:NOM:SIN:NEU"), ("essen", "VER:INF:NON")],
+                ),
+                (
+                    "ESSEN",
+                    false,
+                    &[("essen", "VER:INF:NON"), ("Essen", "SUB:NOM:SIN:NEU")],
+                ),
+                ("Arm", false, &[("Arm", "SUB:NOM:SIN:MAS")]),
+                ("essen", false, &[("essen", "VER:INF:NON")]),
+            ],
+        );
+    }
+
+    fn case_fallback_fixture(lang: &str, extension: &str) -> String {
+        let path = format!(
+            "src/tokenizer/fixtures/case_fallback_{}.{}",
+            lang, extension
+        );
+        std::fs::read_to_string(&path).unwrap_or_else(|_| {
+            panic!(
+                "{} is missing, write it with `scripts/case_fallback_fixtures.sh`",
+                path
+            )
+        })
+    }
+
+    /// Parses one line of the output of LanguageTool's `--taggeronly` mode, e.g.
+    /// `<S> Will[will/MD,Will/NNP,B-NP-singular]`, into the words with their sorted readings.
+    /// Chunk tags, readings without a tag and the sentence and paragraph markers are skipped.
+    fn parse_languagetool_line(line: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let mut words = Vec::new();
+        let mut rest = line.trim_start().trim_start_matches("<S>");
+
+        while let Some(open) = rest.find('[') {
+            let close = open + rest[open..].find(']').expect("unclosed readings");
+            let word = rest[..open].trim();
+
+            if !word.is_empty() {
+                let mut readings: Vec<_> = rest[open + 1..close]
+                    .split(',')
+                    .filter_map(|reading| {
+                        let index = reading.rfind('/')?;
+                        let (lemma, pos) = (&reading[..index], &reading[index + 1..]);
+                        if ["null", "SENT_END", "PARA_END"].contains(&pos) {
+                            None
+                        } else {
+                            Some((lemma.to_string(), pos.to_string()))
+                        }
+                    })
+                    .collect();
+                readings.sort();
+                words.push((word.to_string(), readings));
+            }
+
+            rest = &rest[close + 1..];
+        }
+
+        words
+    }
+
+    /// Checks the readings of sentence-initial, all-caps and mixed-case words after tagging and
+    /// disambiguation against the output of LanguageTool's `--taggeronly` mode for the same sentences.
+    /// Needs the binaries in `storage` (see `BUILD.md`) and the fixtures written by
+    /// `scripts/case_fallback_fixtures.sh`.
+    #[test]
+    #[ignore]
+    fn case_fallback_matches_languagetool() {
+        for lang in &["en", "de"] {
+            let tokenizer =
+                crate::Tokenizer::new(format!("../storage/{}_tokenizer.bin", lang)).unwrap();
+            let sentences = case_fallback_fixture(lang, "in");
+            let expected: Vec<_> = case_fallback_fixture(lang, "txt")
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(parse_languagetool_line)
+                .collect();
+            assert_eq!(
+                sentences.lines().count(),
+                expected.len(),
+                "LanguageTool must tag one sentence per line of case_fallback_{}.in",
+                lang
+            );
+
+            for (sentence, expected) in sentences.lines().zip(expected) {
+                let tokens = tokenizer.disambiguate(tokenizer.tokenize(sentence));
+                let words: Vec<_> = tokens
+                    .iter()
+                    .filter(|token| !token.word.text.as_ref().trim().is_empty())
+                    .map(|token| {
+                        let mut readings: Vec<_> = token
+                            .readings()
+                            .filter(|(_, pos)| !["UNKNOWN", "SENT_END", "PARA_END"].contains(pos))
+                            .map(|(lemma, pos)| (lemma.to_string(), pos.to_string()))
+                            .collect();
+                        readings.sort();
+                        (token.word.text.as_ref().to_string(), readings)
+                    })
+                    .collect();
+
+                assert_eq!(words, expected, "readings in {:?}", sentence);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod compile_tests {
+    use crate::compile::testing::{setup, write_temp};
+
+    #[test]
+    fn inflections_are_synthesized() {
+        let (mut tokenizer, rules) = setup();
+
+        let tagger = tokenizer.tagger();
+        assert_eq!(tagger.synthesize("go", "VBD"), vec!["went"]);
+        assert_eq!(
+            tagger.synthesize_regex("go", "VB[DN]").unwrap(),
+            vec!["went", "gone"]
+        );
+        assert!(tagger.synthesize("go", "NN").is_empty());
+        assert!(tagger.synthesize("went", "VBD").is_empty());
+        assert_eq!(tagger.group_members("go"), vec!["go", "went", "gone"]);
+        #[allow(deprecated)]
+        let members = tagger.get_group_members(&"go".to_string());
+        assert_eq!(members, tagger.group_members("go"));
+        #[allow(deprecated)]
+        let word_map = tagger.word_store();
+        assert_eq!(word_map.len(), tagger.words().len());
+        assert_eq!(
+            word_map.get_by_left("went").copied(),
+            tagger.words().get_id("went")
+        );
+
+        let suggestions = rules.suggest("Yesterday I go home.", &tokenizer);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].replacements, vec!["Yesterday I went"]);
+
+        tokenizer
+            .mut_tagger()
+            .unwrap()
+            .add_words(&[("nlp", "nlp", "VB"), ("nlped", "nlp", "VBD")])
+            .unwrap();
+        let suggestions = rules.suggest("Yesterday I nlp home.", &tokenizer);
+        assert_eq!(suggestions[0].replacements, vec!["Yesterday I nlped"]);
+    }
+
+    #[test]
+    fn replacements_are_sorted_by_frequency() {
+        let (mut tokenizer, rules) = setup();
+        let text = "I have went home.";
+
+        assert_eq!(tokenizer.tagger().frequency("go"), None);
+        let suggestions = rules.suggest(text, &tokenizer);
+        assert_eq!(suggestions[0].replacements, vec!["have gone", "have go"]);
+
+        let frequency_path = write_temp(
+            "frequencies.txt",
+            "# word\tcount\ngo\t1000\ngone\t100\nunknownword\t5000\n",
+        );
+        tokenizer
+            .mut_tagger()
+            .unwrap()
+            .add_frequencies_from_dump(frequency_path.to_str().unwrap())
+            .unwrap();
+
+        let tagger = tokenizer.tagger();
+        assert_eq!(tagger.frequency("go"), Some(10));
+        assert_eq!(tagger.frequency("gone"), Some(7));
+        assert_eq!(tagger.frequency("unknownword"), None);
+
+        let suggestions = rules.suggest(text, &tokenizer);
+        assert_eq!(suggestions[0].replacements, vec!["have go", "have gone"]);
     }
 }
@@ -1,17 +1,22 @@
 //! Fundamental types used by this crate.
 
 use derivative::Derivative;
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::Cow,
     collections::{hash_map, HashMap, HashSet},
+    hash::BuildHasherDefault,
 };
 
-use crate::tokenizer::tag::Tagger;
+use crate::{rule::grammar::CaseMapping, tokenizer::tag::Tagger};
 
-pub(crate) type DefaultHashMap<K, V> = HashMap<K, V>;
-pub(crate) type DefaultHashSet<T> = HashSet<T>;
 pub(crate) type DefaultHasher = hash_map::DefaultHasher;
+/// Unlike the `RandomState` of the standard library this is not seeded randomly, so the iteration order
+/// of the maps and sets (and with it the serialized binaries) is the same for every compile.
+pub(crate) type DefaultBuildHasher = BuildHasherDefault<DefaultHasher>;
+pub(crate) type DefaultHashMap<K, V> = HashMap<K, V, DefaultBuildHasher>;
+pub(crate) type DefaultHashSet<T> = HashSet<T, DefaultBuildHasher>;
 
 /// Owned versions of the types for use in longer-living structures not bound to the `'t` lifetime e.g. rule tests.
 pub mod owned {
@@ -72,6 +77,8 @@ pub mod owned {
         pub byte_span: (usize, usize),
         pub has_space_before: bool,
         pub chunks: Vec<String>,
+        pub chunk_confidence: Option<f32>,
+        pub paragraph: usize,
     }
 }
 
@@ -127,6 +134,16 @@ impl<'t> WordData<'t> {
         WordData { lemma, pos }
     }
 
+    /// The lemma of this reading.
+    pub fn lemma(&self) -> &str {
+        self.lemma.as_ref()
+    }
+
+    /// The part-of-speech tag of this reading.
+    pub fn pos(&self) -> &str {
+        self.pos.as_ref()
+    }
+
     pub fn to_owned_word_data(&self) -> owned::WordData {
         owned::WordData {
             lemma: self.lemma.to_owned_id(),
@@ -154,11 +171,27 @@ impl<'t> Word<'t> {
             tags: self.tags.iter().map(|x| x.to_owned_word_data()).collect(),
         }
     }
+
+    /// Iterates over the readings of this word as (lemma, part-of-speech tag) pairs.
+    pub fn readings(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags.iter().map(|x| (x.lemma.as_ref(), x.pos.as_ref()))
+    }
+
+    /// Keeps only the readings for which `f(lemma, pos)` returns true.
+    /// If no reading remains, the word gets the UNKNOWN reading so there is always at least one.
+    fn retain_readings<F: FnMut(&str, &str) -> bool>(&mut self, tagger: &'t Tagger, mut f: F) {
+        self.tags.retain(|x| f(x.lemma.as_ref(), x.pos.as_ref()));
+
+        if self.tags.is_empty() {
+            self.tags
+                .push(WordData::new(self.text.clone(), tagger.id_tag("UNKNOWN")));
+        }
+    }
 }
 
 /// A token where varying levels of information are set.
-#[derive(Derivative)]
-#[derivative(Debug, Clone, PartialEq)]
+#[derive(Derivative, Clone)]
+#[derivative(Debug, PartialEq)]
 pub struct IncompleteToken<'t> {
     pub word: Word<'t>,
     pub byte_span: (usize, usize),
@@ -166,11 +199,60 @@ pub struct IncompleteToken<'t> {
     pub is_sentence_end: bool,
     pub has_space_before: bool,
     pub chunks: Vec<String>,
+    pub(crate) chunk_confidence: Option<f32>,
+    /// The index of the paragraph this token is in. Paragraphs are separated by two or more newlines.
+    pub paragraph: usize,
+    /// Whether this token is the first token of its paragraph.
+    pub is_paragraph_start: bool,
+    /// Whether this token is the last token of its paragraph.
+    pub is_paragraph_end: bool,
+    /// Whether grammar rules must ignore this token. Set by disambiguation rules with the `immunize` action.
+    pub immunized: bool,
+    /// Whether a spellchecker must ignore this token. Set by disambiguation rules with the `ignore_spelling` action.
+    pub ignore_spelling: bool,
     pub text: &'t str,
     #[derivative(PartialEq = "ignore", Debug = "ignore")]
     pub tagger: &'t Tagger,
 }
 
+impl<'t> IncompleteToken<'t> {
+    /// The probability the chunker assigned to the chunk of this token. `None` if the token was not chunked.
+    pub fn chunk_confidence(&self) -> Option<f32> {
+        self.chunk_confidence
+    }
+
+    /// Iterates over the readings of this token as (lemma, part-of-speech tag) pairs.
+    pub fn readings(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.word.readings()
+    }
+
+    /// Keeps only the readings for which `f(lemma, pos)` returns true.
+    /// At least one reading always remains: if all are removed, the token gets the UNKNOWN reading.
+    pub fn retain_readings<F: FnMut(&str, &str) -> bool>(&mut self, f: F) {
+        self.word.retain_readings(self.tagger, f)
+    }
+}
+
+/// The lowercase forms of a text by each case mapping, computed on first use. Case-insensitive matchers
+/// compare the text of the same token or pattern many times, see [CaseMapping::eq_ignore_case_lower].
+#[derive(Debug, Default)]
+pub(crate) struct LowerText {
+    default: OnceCell<String>,
+    turkic: OnceCell<String>,
+}
+
+impl LowerText {
+    /// The lowercase form of `text` by `mapping`. Must always be called with the same text.
+    pub(crate) fn get(&self, text: &str, mapping: CaseMapping) -> &str {
+        let cell = match mapping {
+            CaseMapping::Default => &self.default,
+            CaseMapping::Turkic => &self.turkic,
+        };
+
+        cell.get_or_init(|| mapping.to_lowercase(text))
+    }
+}
+
 /// A finished token with all information set.
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -180,9 +262,17 @@ pub struct Token<'t> {
     pub byte_span: (usize, usize),
     pub has_space_before: bool,
     pub chunks: Vec<String>,
+    pub(crate) chunk_confidence: Option<f32>,
+    /// The index of the paragraph this token is in. Paragraphs are separated by two or more newlines.
+    pub paragraph: usize,
+    pub(crate) immunized: bool,
+    pub(crate) ignore_spelling: bool,
     pub text: &'t str,
     #[derivative(Debug = "ignore")]
     pub tagger: &'t Tagger,
+    /// The lowercase forms of the text of the word.
+    #[derivative(Debug = "ignore")]
+    pub(crate) lower: LowerText,
 }
 
 impl<'t> Token<'t> {
@@ -202,11 +292,55 @@ impl<'t> Token<'t> {
             byte_span: (0, 0),
             has_space_before: false,
             chunks: Vec::new(),
+            chunk_confidence: None,
+            paragraph: 0,
+            immunized: false,
+            ignore_spelling: false,
             text,
             tagger,
+            lower: LowerText::default(),
         }
     }
 
+    /// Iterates over the readings of this token as (lemma, part-of-speech tag) pairs.
+    /// This includes special readings like SENT_END.
+    pub fn readings(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.word.readings()
+    }
+
+    /// Keeps only the readings for which `f(lemma, pos)` returns true.
+    /// At least one reading always remains: if all are removed, the token gets the UNKNOWN reading.
+    pub fn retain_readings<F: FnMut(&str, &str) -> bool>(&mut self, f: F) {
+        self.word.retain_readings(self.tagger, f)
+    }
+
+    /// The probability the chunker assigned to the chunk of this token (e. g. a `B-NP` at 0.51 is less certain than at 0.99).
+    /// `None` if the token was not chunked.
+    pub fn chunk_confidence(&self) -> Option<f32> {
+        self.chunk_confidence
+    }
+
+    /// Whether grammar rules ignore this token i. e. no rule matches if its marked range overlaps the token.
+    pub fn is_immunized(&self) -> bool {
+        self.immunized
+    }
+
+    /// Sets whether grammar rules should ignore this token. Can be used to exclude
+    /// e. g. code spans from checking.
+    pub fn set_immunized(&mut self, immunized: bool) {
+        self.immunized = immunized;
+    }
+
+    /// Whether a spellchecker should ignore this token e. g. because it is part of a foreign phrase.
+    pub fn is_spelling_ignored(&self) -> bool {
+        self.ignore_spelling
+    }
+
+    /// Sets whether a spellchecker should ignore this token.
+    pub fn set_ignore_spelling(&mut self, ignore_spelling: bool) {
+        self.ignore_spelling = ignore_spelling;
+    }
+
     pub fn to_owned_token(&self) -> owned::Token {
         owned::Token {
             word: self.word.to_owned_word(),
@@ -214,6 +348,8 @@ impl<'t> Token<'t> {
             byte_span: self.byte_span,
             has_space_before: self.has_space_before,
             chunks: self.chunks.clone(),
+            chunk_confidence: self.chunk_confidence,
+            paragraph: self.paragraph,
         }
     }
 }
@@ -234,6 +370,20 @@ impl<'t> From<IncompleteToken<'t>> for Token<'t> {
             ));
         }
 
+        if data.is_paragraph_start {
+            word.tags.push(WordData::new(
+                data.word.text.clone(),
+                data.tagger.id_tag("PARA_START"),
+            ));
+        }
+
+        if data.is_paragraph_end {
+            word.tags.push(WordData::new(
+                data.word.text.clone(),
+                data.tagger.id_tag("PARA_END"),
+            ));
+        }
+
         if data.is_sentence_end {
             word.tags.push(WordData::new(
                 data.word.text,
@@ -247,23 +397,69 @@ impl<'t> From<IncompleteToken<'t>> for Token<'t> {
             char_span: data.char_span,
             has_space_before: data.has_space_before,
             chunks: data.chunks,
+            chunk_confidence: data.chunk_confidence,
+            paragraph: data.paragraph,
+            immunized: data.immunized,
+            ignore_spelling: data.ignore_spelling,
             text: data.text,
             tagger: data.tagger,
+            lower: LowerText::default(),
         }
     }
 }
 
 /// Suggestion for change in a text.
+/// Suggestions are created by [Rules][crate::Rules], more fields may be added in minor versions.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[non_exhaustive]
 pub struct Suggestion {
     /// The ID of the rule this suggestion is from.
     pub source: String,
     /// A human-readable message.
     pub message: String,
+    /// A short message for compact display e. g. "Possible typo", if the rule has one.
+    pub short: Option<String>,
     /// The start character index (inclusive).
     pub start: usize,
     /// The end character index (exclusive).
     pub end: usize,
     /// The suggested replacement options for the text.
     pub replacements: Vec<String>,
+    /// The groups of the pattern of the rule and what they matched, ordered by ID.
+    /// Only set if [AnalyzeOptions::keep_groups][crate::tokenizer::AnalyzeOptions::keep_groups] is true.
+    #[serde(default)]
+    pub groups: Option<Vec<MatchedGroup>>,
+}
+
+impl Suggestion {
+    /// Creates a suggestion without a short message and groups e. g. to pass it to
+    /// [apply_suggestions][crate::rules::apply_suggestions].
+    pub fn new(
+        source: String,
+        message: String,
+        start: usize,
+        end: usize,
+        replacements: Vec<String>,
+    ) -> Self {
+        Suggestion {
+            source,
+            message,
+            short: None,
+            start,
+            end,
+            replacements,
+            groups: None,
+        }
+    }
+}
+
+/// What a group of the pattern of a rule matched, see [Suggestion::groups].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MatchedGroup {
+    /// The ID of the group i. e. the number a `<match no=".."/>` refers to it by.
+    pub id: usize,
+    /// The start and end character index. Empty if the group matched no tokens e. g. an optional token.
+    pub char_span: (usize, usize),
+    /// The text covered by the group.
+    pub text: String,
 }
@@ -0,0 +1,136 @@
+//! A compact set of the integers below a fixed bound e. g. of the part-of-speech tags a matcher accepts.
+
+use serde::{Deserialize, Serialize};
+
+const BITS: usize = u64::BITS as usize;
+
+/// A set of the integers in `0..capacity`, one bit per integer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BitSet {
+    capacity: usize,
+    blocks: Vec<u64>,
+}
+
+impl BitSet {
+    /// An empty set of the integers in `0..capacity`.
+    #[cfg(feature = "compile")]
+    pub fn new(capacity: usize) -> Self {
+        BitSet {
+            capacity,
+            blocks: vec![0; capacity.div_ceil(BITS)],
+        }
+    }
+
+    /// The set of the integers in `0..capacity` for which `f` returns true.
+    #[cfg(feature = "compile")]
+    pub fn from_fn<F: FnMut(usize) -> bool>(capacity: usize, mut f: F) -> Self {
+        let mut set = BitSet::new(capacity);
+        for i in (0..capacity).filter(|i| f(*i)) {
+            set.insert(i);
+        }
+        set
+    }
+
+    /// The bound of the integers in the set.
+    #[cfg(feature = "compile")]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Whether the set contains `i`. Integers out of bounds are never contained.
+    #[inline]
+    pub fn contains(&self, i: usize) -> bool {
+        i < self.capacity && self.blocks[i / BITS] & (1 << (i % BITS)) != 0
+    }
+
+    /// Adds `i` to the set. Panics if `i` is out of bounds.
+    #[cfg(feature = "compile")]
+    pub fn insert(&mut self, i: usize) {
+        assert!(i < self.capacity, "{} is out of bounds of the set", i);
+        self.blocks[i / BITS] |= 1 << (i % BITS);
+    }
+
+    #[cfg(feature = "compile")]
+    pub fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|x| *x == 0)
+    }
+
+    /// The integers in the set in ascending order.
+    #[cfg(feature = "compile")]
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.blocks.iter().enumerate().flat_map(|(i, block)| {
+            let mut block = *block;
+            std::iter::from_fn(move || {
+                if block == 0 {
+                    None
+                } else {
+                    let bit = block.trailing_zeros() as usize;
+                    block &= block - 1;
+                    Some(i * BITS + bit)
+                }
+            })
+        })
+    }
+
+    /// Adds all integers of `other`. Panics if the sets have different capacities.
+    pub fn union_with(&mut self, other: &BitSet) {
+        assert_eq!(self.capacity, other.capacity);
+        for (a, b) in self.blocks.iter_mut().zip(&other.blocks) {
+            *a |= b;
+        }
+    }
+
+    /// Removes all integers which are not in `other`. Panics if the sets have different capacities.
+    pub fn intersect_with(&mut self, other: &BitSet) {
+        assert_eq!(self.capacity, other.capacity);
+        for (a, b) in self.blocks.iter_mut().zip(&other.blocks) {
+            *a &= b;
+        }
+    }
+
+    /// The intersection of the sets, `None` if there are none. Panics if the sets have different capacities.
+    pub fn intersection<'a, I: IntoIterator<Item = &'a BitSet>>(sets: I) -> Option<BitSet> {
+        let mut sets = sets.into_iter();
+        let mut intersection = sets.next()?.clone();
+        for set in sets {
+            intersection.intersect_with(set);
+        }
+        Some(intersection)
+    }
+
+    /// Whether all integers of the set are in `other`.
+    #[cfg(feature = "compile")]
+    pub fn is_subset(&self, other: &BitSet) -> bool {
+        self.blocks.iter().enumerate().all(|(i, a)| {
+            let b = other.blocks.get(i).copied().unwrap_or(0);
+            a & !b == 0
+        })
+    }
+}
+
+#[cfg(all(test, feature = "compile"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations_span_blocks() {
+        let a = BitSet::from_fn(130, |i| i % 2 == 0);
+        let b = BitSet::from_fn(130, |i| i % 3 == 0);
+
+        assert!(a.contains(128) && !a.contains(129) && !a.contains(130));
+        assert_eq!(a.iter().take(3).collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert_eq!(a.iter().last(), Some(128));
+
+        let intersection = BitSet::intersection(vec![&a, &b]).unwrap();
+        assert_eq!(intersection, BitSet::from_fn(130, |i| i % 6 == 0));
+        assert!(intersection.is_subset(&a) && intersection.is_subset(&b));
+        assert!(!a.is_subset(&b));
+
+        let mut union = a.clone();
+        union.union_with(&b);
+        assert_eq!(union, BitSet::from_fn(130, |i| i % 2 == 0 || i % 3 == 0));
+
+        assert!(BitSet::new(130).is_empty());
+        assert_eq!(BitSet::intersection(Vec::new()), None);
+    }
+}
@@ -1,6 +1,8 @@
+use self::regex::{Captures, Regex};
 use lazy_static::lazy_static;
-use onig::{Captures, Regex};
+use unicode_segmentation::UnicodeSegmentation;
 
+pub mod bitset;
 pub mod parallelism;
 pub mod regex;
 
@@ -16,6 +18,17 @@ where
     }
 }
 
+/// Like [apply_to_first] for the first grapheme e. g. a letter with combining accents.
+pub fn apply_to_first_grapheme<F>(string: &str, func: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    match string.graphemes(true).next() {
+        None => String::new(),
+        Some(first) => func(first) + &string[first.len()..],
+    }
+}
+
 pub fn is_title_case(string: &str) -> bool {
     let mut char_case = string.chars().map(|x| x.is_uppercase());
 
@@ -28,7 +41,8 @@ pub fn is_uppercase(string: &str) -> bool {
 
 // see https://github.com/rust-onig/rust-onig/issues/59#issuecomment-340160520
 pub fn dollar_replace(mut replacement: String, caps: &Captures) -> String {
-    for i in 1..caps.len() {
+    // in reverse so that e. g. `$1` does not replace the start of `$10`
+    for i in (1..caps.len()).rev() {
         replacement = replacement.replace(&format!("${}", i), caps.at(i).unwrap_or(""));
     }
     replacement
@@ -67,3 +81,31 @@ pub fn fix_nospace_chars(text: &str) -> String {
         .map(|x| x.1)
         .collect()
 }
+
+/// Checks that no rule ID is both selected and ignored.
+pub fn validate_ids(ids: &[String], ignore_ids: &[String]) -> Result<(), crate::Error> {
+    match ids.iter().find(|id| ignore_ids.contains(id)) {
+        Some(id) => Err(crate::Error::ConflictingIds(id.clone())),
+        None => Ok(()),
+    }
+}
+
+/// The Levenshtein distance between two strings in characters.
+#[cfg(feature = "compile")]
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
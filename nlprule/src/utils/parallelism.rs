@@ -5,7 +5,9 @@
 
 use rayon::iter::IterBridge;
 use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use rayon_cond::CondIterator;
+use std::sync::Arc;
 
 pub const ENV_VARIABLE: &str = "NLPRULE_PARALLELISM";
 
@@ -38,6 +40,64 @@ pub fn _set_parallelism(val: bool) {
     std::env::set_var(ENV_VARIABLE, if val { "true" } else { "false" })
 }
 
+/// How to parallelize the work of one instance (e. g. of the [Rules][crate::Rules] or the [Tokenizer][crate::Tokenizer]).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Parallelism {
+    /// Always run sequentially on the calling thread.
+    Off,
+    /// Run in a thread pool with the given number of threads which belongs to the instance.
+    Threads(usize),
+    /// Run in the global thread pool if the `NLPRULE_PARALLELISM` environment variable allows it.
+    #[default]
+    Auto,
+}
+
+/// The [Parallelism] of an instance together with its thread pool, if it has one.
+#[derive(Clone, Default)]
+pub struct ParallelismConfig {
+    parallelism: Parallelism,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl ParallelismConfig {
+    pub fn new(parallelism: Parallelism) -> Result<Self, crate::Error> {
+        let pool = match parallelism {
+            Parallelism::Threads(n) => Some(Arc::new(
+                ThreadPoolBuilder::new()
+                    .num_threads(n)
+                    .thread_name(|i| format!("nlprule-{}", i))
+                    .build()
+                    .map_err(|x| crate::Error::Unexpected(x.to_string()))?,
+            )),
+            Parallelism::Off | Parallelism::Auto => None,
+        };
+
+        Ok(ParallelismConfig { parallelism, pool })
+    }
+
+    pub fn parallelism(&self) -> Parallelism {
+        self.parallelism
+    }
+
+    /// Whether iterators should run in parallel with this config.
+    pub fn is_parallel(&self) -> bool {
+        match self.parallelism {
+            Parallelism::Off => false,
+            Parallelism::Threads(_) => true,
+            Parallelism::Auto => get_parallelism(),
+        }
+    }
+
+    /// Runs `f` in the thread pool of this config, or on the calling thread if there is none.
+    /// Parallel iterators in `f` use this thread pool.
+    pub fn install<R: Send, F: FnOnce() -> R + Send>(&self, f: F) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
+}
+
 /// Allows to convert into an iterator that can be executed either parallelly or serially.
 ///
 /// The choice is made according to the currently set `NLPRULE_PARALLELISM` environment variable.
@@ -51,12 +111,9 @@ where
     S: Iterator<Item = P::Item>,
 {
     /// Convert ourself in a CondIterator, that will be executed either in parallel or serially,
-    /// based solely on the `NLPRULE_PARALLELISM` environment variable
-    fn into_maybe_par_iter(self) -> CondIterator<P, S>;
-    /// Convert ourself in a CondIterator, that will be executed either in parallel or serially,
-    /// based on both the `NLPRULE_PARALLELISM` environment variable and the provided bool.
-    /// Both must be true to run with parallelism activated.
-    fn into_maybe_par_iter_cond(self, cond: bool) -> CondIterator<P, S>;
+    /// based solely on the given config. Must be run with [ParallelismConfig::install].
+    /// The default config runs in parallel according to the `NLPRULE_PARALLELISM` environment variable.
+    fn into_maybe_par_iter_with(self, config: &ParallelismConfig) -> CondIterator<P, S>;
 }
 
 impl<P, S, I> MaybeParallelIterator<P, S> for I
@@ -65,21 +122,13 @@ where
     P: ParallelIterator,
     S: Iterator<Item = P::Item>,
 {
-    fn into_maybe_par_iter(self) -> CondIterator<P, S> {
-        let parallelism = get_parallelism();
+    fn into_maybe_par_iter_with(self, config: &ParallelismConfig) -> CondIterator<P, S> {
+        let parallelism = config.is_parallel();
         if parallelism {
             unsafe { USED_PARALLELISM = true };
         }
         CondIterator::new(self, parallelism)
     }
-
-    fn into_maybe_par_iter_cond(self, cond: bool) -> CondIterator<P, S> {
-        if cond {
-            self.into_maybe_par_iter()
-        } else {
-            CondIterator::from_serial(self)
-        }
-    }
 }
 
 /// Shared reference version of MaybeParallelIterator, works the same but returns an iterator
@@ -90,8 +139,7 @@ where
     S: Iterator<Item = P::Item>,
     P::Item: 'data,
 {
-    fn maybe_par_iter(&'data self) -> CondIterator<P, S>;
-    fn maybe_par_iter_cond(&'data self, cond: bool) -> CondIterator<P, S>;
+    fn maybe_par_iter_with(&'data self, config: &ParallelismConfig) -> CondIterator<P, S>;
 }
 
 impl<'data, P, S, I: 'data + ?Sized> MaybeParallelRefIterator<'data, P, S> for I
@@ -101,12 +149,8 @@ where
     S: Iterator<Item = P::Item>,
     P::Item: 'data,
 {
-    fn maybe_par_iter(&'data self) -> CondIterator<P, S> {
-        self.into_maybe_par_iter()
-    }
-
-    fn maybe_par_iter_cond(&'data self, cond: bool) -> CondIterator<P, S> {
-        self.into_maybe_par_iter_cond(cond)
+    fn maybe_par_iter_with(&'data self, config: &ParallelismConfig) -> CondIterator<P, S> {
+        self.into_maybe_par_iter_with(config)
     }
 }
 
@@ -130,11 +174,19 @@ where
     P::Item: 'data,
 {
     fn maybe_par_iter_mut(&'data mut self) -> CondIterator<P, S> {
-        self.into_maybe_par_iter()
+        self.into_maybe_par_iter_with(&ParallelismConfig::default())
     }
 
     fn maybe_par_iter_mut_cond(&'data mut self, cond: bool) -> CondIterator<P, S> {
-        self.into_maybe_par_iter_cond(cond)
+        let parallelism = if cond {
+            Parallelism::Auto
+        } else {
+            Parallelism::Off
+        };
+        self.into_maybe_par_iter_with(&ParallelismConfig {
+            parallelism,
+            pool: None,
+        })
     }
 }
 
@@ -180,8 +232,9 @@ mod tests {
     #[test]
     fn test_maybe_parallel_iterator() {
         let mut v = vec![1u32, 2, 3, 4, 5, 6];
+        let auto = ParallelismConfig::default();
 
-        assert_eq!(v.maybe_par_iter().sum::<u32>(), 21);
+        assert_eq!(v.maybe_par_iter_with(&auto).sum::<u32>(), 21);
         assert_eq!(
             v.maybe_par_iter_mut()
                 .map(|v| {
@@ -191,7 +244,29 @@ mod tests {
                 .sum::<u32>(),
             42
         );
-        assert_eq!(v.maybe_par_iter().sum::<u32>(), 42);
-        assert_eq!(v.into_maybe_par_iter().sum::<u32>(), 42);
+        assert_eq!(v.maybe_par_iter_with(&auto).sum::<u32>(), 42);
+        assert_eq!(v.into_maybe_par_iter_with(&auto).sum::<u32>(), 42);
+    }
+
+    #[test]
+    fn config_determines_threads() {
+        let v: Vec<u32> = (0..64).collect();
+        let thread_names = |config: &ParallelismConfig| -> Vec<Option<String>> {
+            config.install(|| {
+                v.maybe_par_iter_with(config)
+                    .map(|_| std::thread::current().name().map(|x| x.to_string()))
+                    .collect()
+            })
+        };
+
+        let current = std::thread::current().name().map(|x| x.to_string());
+        let off = ParallelismConfig::new(Parallelism::Off).unwrap();
+        assert!(thread_names(&off).into_iter().all(|x| x == current));
+        assert!(!off.is_parallel());
+
+        let threads = ParallelismConfig::new(Parallelism::Threads(2)).unwrap();
+        assert!(thread_names(&threads)
+            .into_iter()
+            .all(|x| x.is_some_and(|name| name.starts_with("nlprule-"))));
     }
 }
@@ -1,9 +1,31 @@
+//! Regexes with the syntax of LanguageTool i. e. of Java. They are run by Oniguruma with the `regex-onig`
+//! feature (the default) or by the pure-Rust `regex` and `fancy-regex` crates with the `regex-fancy` feature.
+
 use crate::Error;
-use onig::{Regex, RegexOptions};
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Deserializer, Serialize};
+use std::cell::Cell;
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
+#[cfg(not(any(feature = "regex-onig", feature = "regex-fancy")))]
+compile_error!("either the `regex-onig` or the `regex-fancy` feature must be enabled");
+
+#[cfg(feature = "regex-onig")]
+use onig_backend::{OnigRegex as Backend, OnigRegexSet as SetBackend};
+#[cfg(all(feature = "regex-fancy", not(feature = "regex-onig")))]
+use rust_backend::{RustRegex as Backend, RustRegexSet as SetBackend};
+
+/// The backend which runs the regexes of this build.
+const ACTIVE_BACKEND: RegexBackend = if cfg!(feature = "regex-onig") {
+    RegexBackend::Onig
+} else {
+    RegexBackend::Rust
+};
+
+/// The byte spans of the groups of one match, `None` for groups which did not participate.
+type Positions = Vec<Option<(usize, usize)>>;
+
 fn unescape<S: AsRef<str>>(string: S, c: &str) -> String {
     let placeholder = "###escaped_backslash###";
 
@@ -14,18 +36,602 @@ fn unescape<S: AsRef<str>>(string: S, c: &str) -> String {
         .replace(placeholder, r"\\")
 }
 
+/// Translates a pattern in Java syntax to the syntax of `regex` and `fancy-regex` such that both match the same
+/// as Oniguruma:
+/// - `\Q..\E` quotes are escaped.
+/// - Escaped punctuation which is not a metacharacter is unescaped, e. g. `\<` is a word boundary in `fancy-regex`.
+/// - `\b` in a character class is a backspace, `[:` in a character class is no POSIX class.
+/// - Unicode classes such as `\p{Lu}` are case-sensitive even if the pattern is not.
+/// - `ß` and `ss` match each other if the pattern is case-insensitive since Oniguruma folds the case of
+///   `ß` to `ss` while `regex` only folds single characters.
+fn translate(pattern: &str, case_sensitive: bool) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    let mut class_depth = 0usize;
+
+    if !case_sensitive {
+        out.push_str("(?i)");
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('Q') => {
+                    let mut quoted = String::new();
+                    while let Some(c) = chars.next() {
+                        if c == '\\' && chars.peek() == Some(&'E') {
+                            chars.next();
+                            break;
+                        }
+                        quoted.push(c);
+                    }
+                    out.push_str(&regex_syntax::escape(&quoted));
+                }
+                Some('b') if class_depth > 0 => out.push_str(r"\x08"),
+                Some(c @ 'p') | Some(c @ 'P') if !case_sensitive && class_depth == 0 => {
+                    let mut class = format!("\\{}", c);
+                    if chars.peek() == Some(&'{') {
+                        for c in chars.by_ref() {
+                            class.push(c);
+                            if c == '}' {
+                                break;
+                            }
+                        }
+                    } else {
+                        class.extend(chars.next());
+                    }
+                    out.push_str(&format!("(?-i:{})", class));
+                }
+                Some(c) if c.is_ascii_punctuation() => {
+                    out.push_str(&regex_syntax::escape(&c.to_string()))
+                }
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push('\\'),
+            },
+            '[' => {
+                out.push('[');
+                if class_depth > 0 && chars.peek() == Some(&':') {
+                    chars.next();
+                    out.push_str(r"\:");
+                }
+                class_depth += 1;
+                if chars.peek() == Some(&'^') {
+                    out.push(chars.next().unwrap());
+                }
+                // a `]` directly after the opening bracket is a literal
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    out.push_str(r"\]");
+                }
+            }
+            ']' if class_depth > 0 => {
+                class_depth -= 1;
+                out.push(']');
+            }
+            'ß' | 'ẞ' if !case_sensitive && class_depth == 0 => out.push_str("(?:ß|ss)"),
+            's' | 'S' if !case_sensitive && class_depth == 0 => {
+                let mut rest = chars.clone();
+                let is_double = matches!(rest.next(), Some('s') | Some('S'))
+                    && !matches!(rest.next(), Some('*') | Some('+') | Some('?') | Some('{'));
+
+                if is_double {
+                    out.push_str("(?:ss|ß)");
+                    chars.next();
+                } else {
+                    out.push(c);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Makes the dotted and dotless i of a case-insensitive pattern match like in Turkic languages i. e. `i` only
+/// matches itself and `İ`, `ı` only matches itself and `I`. Characters in character classes are not changed.
+#[cfg(feature = "compile")]
+pub fn turkic_i(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    let mut class_depth = 0usize;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                let escaped = chars.next();
+                out.extend(escaped);
+                // the name of a Unicode class e. g. `\p{IsLatin}`
+                if matches!(escaped, Some('p') | Some('P')) && chars.peek() == Some(&'{') {
+                    for c in chars.by_ref() {
+                        out.push(c);
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                }
+            }
+            // the flags or the name of a group e. g. `(?i)` or `(?<name>`, but not lookaround
+            '(' if chars.peek() == Some(&'?') => {
+                out.push(c);
+                out.extend(chars.next());
+                if chars.peek() == Some(&'<') {
+                    out.extend(chars.next());
+                }
+                if !matches!(chars.peek(), Some('=') | Some('!')) {
+                    for c in chars.by_ref() {
+                        out.push(c);
+                        if matches!(c, ':' | ')' | '>') {
+                            break;
+                        }
+                    }
+                }
+            }
+            '[' => {
+                class_depth += 1;
+                out.push(c);
+            }
+            ']' if class_depth > 0 => {
+                class_depth -= 1;
+                out.push(c);
+            }
+            'i' | 'İ' if class_depth == 0 => out.push_str("(?-i:[iİ])"),
+            'I' | 'ı' if class_depth == 0 => out.push_str("(?-i:[Iı])"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// The pure-Rust engine which runs a regex: `regex` if it supports the syntax, otherwise `fancy-regex`
+/// e. g. for lookaround, backreferences and possessive quantifiers. Detected when the regex is built and stored
+/// in the binary so that the detection does not have to be repeated when loading.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegexEngine {
+    Regex,
+    Fancy,
+}
+
+/// The backend which compiled a regex when the binary was built. Both backends read the same binaries, but a regex
+/// which compiles with one may not compile with the other, see [deserialize_lazily].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum RegexBackend {
+    Onig,
+    Rust,
+}
+
+/// Whether the pattern has a possessive quantifier e. g. `a++`. `regex` accepts these but parses them as
+/// repetitions of repetitions.
+fn has_possessive_quantifier(pattern: &str) -> bool {
+    let mut class_depth = 0usize;
+    let mut escaped = false;
+    // whether the last character ends a quantifier
+    let mut quantifier = false;
+    let mut prev = None;
+
+    for c in pattern.chars() {
+        if escaped {
+            escaped = false;
+            quantifier = false;
+            prev = Some(c);
+            continue;
+        }
+
+        if c == '+' && quantifier && class_depth == 0 {
+            return true;
+        }
+
+        quantifier = class_depth == 0
+            && match c {
+                '*' | '+' | '}' => true,
+                // `(?` starts a group
+                '?' => prev != Some('('),
+                _ => false,
+            };
+
+        match c {
+            '\\' => escaped = true,
+            '[' => class_depth += 1,
+            ']' if class_depth > 0 => class_depth -= 1,
+            _ => {}
+        }
+        prev = Some(c);
+    }
+
+    false
+}
+
+impl RegexEngine {
+    fn detect(pattern: &str, case_sensitive: bool) -> Self {
+        let translated = translate(pattern, case_sensitive);
+
+        if !has_possessive_quantifier(&translated)
+            && regex_syntax::Parser::new().parse(&translated).is_ok()
+        {
+            RegexEngine::Regex
+        } else {
+            RegexEngine::Fancy
+        }
+    }
+}
+
+#[cfg(feature = "regex-onig")]
+mod onig_backend {
+    use super::{Positions, RegexEngine};
+    use onig::{RegexOptions, Syntax};
+
+    #[derive(Debug)]
+    pub(super) struct OnigRegex(onig::Regex);
+
+    impl OnigRegex {
+        pub fn new(
+            pattern: &str,
+            case_sensitive: bool,
+            _engine: RegexEngine,
+        ) -> Result<Self, String> {
+            let options = if case_sensitive {
+                RegexOptions::REGEX_OPTION_NONE
+            } else {
+                RegexOptions::REGEX_OPTION_IGNORECASE
+            };
+
+            onig::Regex::with_options(pattern, options, Syntax::java())
+                .map(OnigRegex)
+                .map_err(|x| x.to_string())
+        }
+
+        pub fn is_match(&self, text: &str) -> bool {
+            self.0.is_match(text)
+        }
+
+        #[cfg(any(test, feature = "compile"))]
+        pub fn captures_len(&self) -> usize {
+            self.0.captures_len()
+        }
+
+        pub fn captures_iter<'a>(&'a self, text: &'a str) -> impl Iterator<Item = Positions> + 'a {
+            self.0.captures_iter(text).map(|x| x.iter_pos().collect())
+        }
+
+        pub fn can_batch(&self) -> bool {
+            false
+        }
+    }
+
+    /// Oniguruma can not match multiple regexes at once, so there are no sets.
+    #[derive(Debug)]
+    pub(super) enum OnigRegexSet {}
+
+    impl OnigRegexSet {
+        pub fn new(_regexes: &[&OnigRegex]) -> Option<Self> {
+            None
+        }
+
+        pub fn is_match(&self, _text: &str) -> bool {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(feature = "regex-fancy")]
+#[cfg_attr(feature = "regex-onig", allow(dead_code))]
+mod rust_backend {
+    use super::{translate, Positions, RegexEngine};
+    use once_cell::sync::OnceCell;
+
+    /// Some LanguageTool regexes are alternations of hundreds of words which exceed the default limit.
+    const SIZE_LIMIT: usize = 1 << 26;
+
+    #[derive(Debug)]
+    enum Inner {
+        Regex(regex::Regex),
+        Fancy(fancy_regex::Regex),
+    }
+
+    impl Inner {
+        fn new(pattern: &str, engine: RegexEngine) -> Result<Self, String> {
+            match engine {
+                RegexEngine::Regex => regex::RegexBuilder::new(pattern)
+                    .size_limit(SIZE_LIMIT)
+                    .build()
+                    .map(Inner::Regex)
+                    .map_err(|x| x.to_string()),
+                RegexEngine::Fancy => fancy_regex::RegexBuilder::new(pattern)
+                    .delegate_size_limit(SIZE_LIMIT)
+                    .build()
+                    .map(Inner::Fancy)
+                    .map_err(|x| x.to_string()),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub(super) struct RustRegex {
+        pattern: String,
+        engine: RegexEngine,
+        inner: Inner,
+        /// The pattern anchored at both ends, compiled on first use by `is_match`.
+        full: OnceCell<Inner>,
+    }
+
+    impl RustRegex {
+        pub fn new(
+            pattern: &str,
+            case_sensitive: bool,
+            engine: RegexEngine,
+        ) -> Result<Self, String> {
+            let pattern = translate(pattern, case_sensitive);
+
+            Ok(RustRegex {
+                inner: Inner::new(&pattern, engine)?,
+                pattern,
+                engine,
+                full: OnceCell::new(),
+            })
+        }
+
+        fn full_pattern(&self) -> String {
+            format!(r"\A(?:{})\z", self.pattern)
+        }
+
+        /// Whether the regex matches the entire text, like Oniguruma's `is_match`. Searching is not enough since
+        /// the leftmost match is not necessarily the longest one.
+        pub fn is_match(&self, text: &str) -> bool {
+            let full = self.full.get_or_init(|| {
+                Inner::new(&self.full_pattern(), self.engine)
+                    .expect("anchoring a valid pattern keeps it valid")
+            });
+
+            match full {
+                Inner::Regex(regex) => regex.is_match(text),
+                // exceeding the backtrack limit counts as no match
+                Inner::Fancy(regex) => regex.is_match(text).unwrap_or(false),
+            }
+        }
+
+        #[cfg(any(test, feature = "compile"))]
+        pub fn captures_len(&self) -> usize {
+            // the `regex` crates count the implicit group of the whole match
+            match &self.inner {
+                Inner::Regex(regex) => regex.captures_len() - 1,
+                Inner::Fancy(regex) => regex.captures_len() - 1,
+            }
+        }
+
+        pub fn captures_iter<'a>(
+            &'a self,
+            text: &'a str,
+        ) -> Box<dyn Iterator<Item = Positions> + 'a> {
+            match &self.inner {
+                Inner::Regex(regex) => Box::new(regex.captures_iter(text).map(|captures| {
+                    captures
+                        .iter()
+                        .map(|x| x.map(|x| (x.start(), x.end())))
+                        .collect()
+                })),
+                Inner::Fancy(regex) => Box::new(
+                    regex
+                        .captures_iter(text)
+                        .map_while(Result::ok)
+                        .map(|captures| {
+                            captures
+                                .iter()
+                                .map(|x| x.map(|x| (x.start(), x.end())))
+                                .collect()
+                        }),
+                ),
+            }
+        }
+
+        /// Only regexes run by `regex` can be part of a [RustRegexSet].
+        pub fn can_batch(&self) -> bool {
+            self.engine == RegexEngine::Regex
+        }
+    }
+
+    #[derive(Debug)]
+    pub(super) struct RustRegexSet(regex::RegexSet);
+
+    impl RustRegexSet {
+        /// `None` if the set exceeds the size limit, then the regexes are matched one by one.
+        pub fn new(regexes: &[&RustRegex]) -> Option<Self> {
+            debug_assert!(regexes.iter().all(|x| x.can_batch()));
+
+            regex::RegexSetBuilder::new(regexes.iter().map(|x| x.full_pattern()))
+                .size_limit(SIZE_LIMIT)
+                .build()
+                .ok()
+                .map(RustRegexSet)
+        }
+
+        pub fn is_match(&self, text: &str) -> bool {
+            self.0.is_match(text)
+        }
+    }
+}
+
+/// The groups of one match of a [Regex] in `text`.
+#[derive(Debug)]
+pub struct Captures<'t> {
+    text: &'t str,
+    positions: Positions,
+}
+
+impl<'t> Captures<'t> {
+    /// The number of groups including the implicit group 0 of the whole match.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// The byte span of group `i`, `None` if it did not participate in the match.
+    pub fn pos(&self, i: usize) -> Option<(usize, usize)> {
+        self.positions.get(i).copied().flatten()
+    }
+
+    /// The text of group `i`, `None` if it did not participate in the match.
+    pub fn at(&self, i: usize) -> Option<&'t str> {
+        self.pos(i).map(|(start, end)| &self.text[start..end])
+    }
+
+    pub fn iter_pos(&self) -> impl Iterator<Item = Option<(usize, usize)>> + '_ {
+        self.positions.iter().copied()
+    }
+}
+
+/// A compiled regex with Java syntax.
+#[derive(Debug)]
+pub struct Regex {
+    backend: Backend,
+}
+
+impl Regex {
+    fn with_options(
+        pattern: &str,
+        case_sensitive: bool,
+        engine: RegexEngine,
+    ) -> Result<Self, Error> {
+        Ok(Regex {
+            backend: Backend::new(pattern, case_sensitive, engine).map_err(Error::Unexpected)?,
+        })
+    }
+
+    /// Compiles a case-sensitive regex.
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        Regex::with_options(pattern, true, RegexEngine::detect(pattern, true))
+    }
+
+    /// Whether the regex matches the entire text.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.backend.is_match(text)
+    }
+
+    /// Whether the regex can be part of a [RegexSet] with the chosen backend.
+    pub fn can_batch(&self) -> bool {
+        self.backend.can_batch()
+    }
+
+    /// The number of groups, not counting the implicit group 0 of the whole match.
+    #[cfg(feature = "compile")]
+    pub fn captures_len(&self) -> usize {
+        self.backend.captures_len()
+    }
+
+    /// Iterates over the successive non-overlapping matches.
+    pub fn captures_iter<'a, 't: 'a>(
+        &'a self,
+        text: &'t str,
+    ) -> impl Iterator<Item = Captures<'t>> + 'a {
+        self.backend
+            .captures_iter(text)
+            .map(move |positions| Captures { text, positions })
+    }
+
+    /// The first match, if any.
+    pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        self.captures_iter(text).next()
+    }
+
+    /// The byte spans of the successive non-overlapping matches.
+    pub fn find_iter<'a, 't: 'a>(
+        &'a self,
+        text: &'t str,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.captures_iter(text).filter_map(|x| x.pos(0))
+    }
+
+    /// Replaces every match with the output of `replacer`.
+    pub fn replace_all<F: FnMut(&Captures) -> String>(
+        &self,
+        text: &str,
+        mut replacer: F,
+    ) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+
+        for captures in self.captures_iter(text) {
+            let (start, end) = captures.pos(0).expect("group 0 always participates");
+            out.push_str(&text[last..start]);
+            out.push_str(&replacer(&captures));
+            last = end;
+        }
+
+        out.push_str(&text[last..]);
+        out
+    }
+}
+
+/// Regexes which are matched against a text at once, which is faster than matching them one by one if there
+/// are many. Only the `regex-fancy` backend supports sets, and only for regexes which do not need `fancy-regex`.
+#[derive(Debug)]
+pub struct RegexSet {
+    backend: SetBackend,
+}
+
+impl RegexSet {
+    /// Builds a set of the regexes. `None` if the backend does not support it, see [Regex::can_batch].
+    pub fn new(regexes: &[&Regex]) -> Option<Self> {
+        if !regexes.iter().all(|x| x.can_batch()) {
+            return None;
+        }
+
+        let backends: Vec<_> = regexes.iter().map(|x| &x.backend).collect();
+        SetBackend::new(&backends).map(|backend| RegexSet { backend })
+    }
+
+    /// Whether any of the regexes matches the entire text.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.backend.is_match(text)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct RegexFields {
     regex_str: String,
     case_sensitive: bool,
+    engine: RegexEngine,
+    backend: RegexBackend,
+}
+
+thread_local! {
+    /// Whether regexes deserialized on this thread are compiled on first use, see [deserialize_lazily].
+    static LAZY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `deserialize` such that the regexes it deserializes are compiled on first use instead of right away,
+/// which is most of the time it takes to load binaries. The regexes have been compiled when the binary was built.
+/// Regexes built with the other backend are still compiled right away so that a regex which this backend can not
+/// compile fails deserializing instead of panicking on first use.
+#[cfg(any(
+    feature = "mmap",
+    all(test, feature = "regex-onig", feature = "regex-fancy")
+))]
+pub(crate) fn deserialize_lazily<T>(deserialize: impl FnOnce() -> T) -> T {
+    struct Reset(bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            LAZY.with(|x| x.set(self.0));
+        }
+    }
+
+    let _reset = Reset(LAZY.with(|x| x.replace(true)));
+    deserialize()
 }
 
 #[derive(Serialize, Debug)]
 pub struct SerializeRegex {
     regex_str: String,
     case_sensitive: bool,
+    engine: RegexEngine,
+    backend: RegexBackend,
+    /// Compiled on first use if the regex is deserialized lazily, see [deserialize_lazily].
     #[serde(skip_serializing)]
-    regex: Regex,
+    regex: OnceCell<Regex>,
 }
 
 impl Hash for SerializeRegex {
@@ -41,27 +647,26 @@ impl<'de> Deserialize<'de> for SerializeRegex {
         D: Deserializer<'de>,
     {
         let fields: RegexFields = Deserialize::deserialize(deserializer)?;
+        let regex = if fields.backend == ACTIVE_BACKEND && LAZY.with(|x| x.get()) {
+            OnceCell::new()
+        } else {
+            OnceCell::with_value(
+                Regex::with_options(&fields.regex_str, fields.case_sensitive, fields.engine)
+                    .map_err(serde::de::Error::custom)?,
+            )
+        };
+
         Ok(SerializeRegex {
-            regex: SerializeRegex::compile(&fields.regex_str, fields.case_sensitive).unwrap(),
+            regex,
             regex_str: fields.regex_str,
             case_sensitive: fields.case_sensitive,
+            engine: fields.engine,
+            backend: fields.backend,
         })
     }
 }
 
 impl SerializeRegex {
-    fn compile(regex_str: &str, case_sensitive: bool) -> Result<Regex, onig::Error> {
-        Regex::with_options(
-            regex_str,
-            if case_sensitive {
-                RegexOptions::REGEX_OPTION_NONE
-            } else {
-                RegexOptions::REGEX_OPTION_IGNORECASE
-            },
-            onig::Syntax::java(),
-        )
-    }
-
     pub fn new(
         regex_str: &str,
         must_fully_match: bool,
@@ -95,19 +700,291 @@ impl SerializeRegex {
             fixed
         };
 
+        let engine = RegexEngine::detect(&fixed, case_sensitive);
+
         Ok(SerializeRegex {
-            regex: SerializeRegex::compile(&fixed, case_sensitive)
-                .map_err(|x| Error::Unexpected(format!("{}", x)))?,
+            regex: OnceCell::with_value(Regex::with_options(&fixed, case_sensitive, engine)?),
             regex_str: fixed,
             case_sensitive,
+            engine,
+            backend: ACTIVE_BACKEND,
         })
     }
+
+    /// The regex as passed to the regex engine i. e. after the Java specific fixes.
+    pub fn regex_str(&self) -> &str {
+        &self.regex_str
+    }
+
+    #[cfg(feature = "compile")]
+    pub fn case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
 }
 
 impl Deref for SerializeRegex {
     type Target = Regex;
 
     fn deref(&self) -> &Self::Target {
-        &self.regex
+        self.regex.get_or_init(|| {
+            Regex::with_options(&self.regex_str, self.case_sensitive, self.engine).unwrap_or_else(
+                |error| panic!("regex {:?} can not be compiled: {}", self.regex_str, error),
+            )
+        })
+    }
+}
+
+#[cfg(all(test, feature = "regex-onig", feature = "regex-fancy"))]
+mod tests {
+    use super::*;
+    use onig_backend::OnigRegex;
+    use rust_backend::RustRegex;
+
+    const SAMPLES: &[&str] = &[
+        "",
+        "a",
+        "the",
+        "The",
+        "THE",
+        "it's",
+        "its",
+        "well-known",
+        "Straße",
+        "STRASSE",
+        "strasse",
+        "class",
+        "naïve",
+        "Éclair",
+        "x-ray",
+        "1990s",
+        "3.5",
+        "e. g.",
+        "don't",
+        "a\nb",
+        "word word",
+        "<tag>",
+        "$10",
+        "über",
+        "ſ",
+    ];
+
+    /// Compiles the regex like [SerializeRegex::new] with both backends and compares the matches on the samples.
+    fn compare(
+        pattern: &str,
+        must_fully_match: bool,
+        case_sensitive: bool,
+        samples: &[&str],
+    ) -> Vec<String> {
+        let regex = match SerializeRegex::new(pattern, must_fully_match, case_sensitive) {
+            Ok(regex) => regex,
+            Err(_) => return Vec::new(),
+        };
+        let onig = OnigRegex::new(&regex.regex_str, regex.case_sensitive, regex.engine).unwrap();
+        let rust = match RustRegex::new(&regex.regex_str, regex.case_sensitive, regex.engine) {
+            Ok(rust) => rust,
+            Err(error) => return vec![format!("{:?}: {}", pattern, error)],
+        };
+
+        let mut mismatches = Vec::new();
+        if onig.captures_len() != rust.captures_len() {
+            mismatches.push(format!("{:?}: number of groups differs", pattern));
+        }
+
+        for sample in samples {
+            let onig_captures: Vec<_> = onig.captures_iter(sample).collect();
+            let rust_captures: Vec<_> = rust.captures_iter(sample).collect();
+
+            if onig.is_match(sample) != rust.is_match(sample) || onig_captures != rust_captures {
+                mismatches.push(format!(
+                    "{:?} on {:?}: {:?} != {:?}",
+                    pattern, sample, onig_captures, rust_captures
+                ));
+            }
+        }
+
+        mismatches
+    }
+
+    #[test]
+    fn engine_is_detected() {
+        let engine = |pattern| SerializeRegex::new(pattern, true, true).unwrap().engine;
+
+        assert_eq!(engine("the|a|an"), RegexEngine::Regex);
+        assert_eq!(engine(r"\p{Lu}\w+"), RegexEngine::Regex);
+        assert_eq!(engine(r"(?<!a)b"), RegexEngine::Fancy);
+        assert_eq!(engine(r"(a)\1"), RegexEngine::Fancy);
+        assert_eq!(engine(r"a++"), RegexEngine::Fancy);
+        assert_eq!(engine(r"a{2}+"), RegexEngine::Fancy);
+        assert_eq!(engine(r"a+?"), RegexEngine::Regex);
+        assert_eq!(engine(r"\++[+]+"), RegexEngine::Regex);
+    }
+
+    #[test]
+    fn backends_agree() {
+        let corpus = [
+            "the|a|an",
+            "[Tt]he",
+            r"\p{Lu}.*",
+            r"\p{Ll}+",
+            r"\d+(\.\d+)?",
+            r"\d{4}s",
+            r"[^a-z]+",
+            r"(?iu)the",
+            r"it'?s",
+            r"\w+-\w+",
+            r"\bword\b",
+            r"\bword\b.*",
+            r"strasse|straße",
+            "STRASSE",
+            "ss",
+            "s+",
+            "clas{2}",
+            r"\Q$10\E",
+            r"\Q<tag>",
+            r"\<tag\>",
+            r"[\<\>a-z]+",
+            r"e\. g\.",
+            r"don\'t",
+            r"\!|\,|\/",
+            r"(?<!x)ray",
+            r"(?<=x-)ray",
+            r"(\w)\1",
+            r".+(?=s)",
+            r"(a|the) (\w+)",
+            r"(a)|(b)",
+            "^a",
+            "b$",
+            "[[:alpha:]]+",
+            r"[]a]+",
+            r"[a-z&&[^aeiou]]+",
+            "(?i)über",
+            "na[ïi]ve",
+            r"\w+",
+            r"\W",
+            "[a-z]++",
+            "[a-z]++s",
+            "[a-z]{2,}+s",
+            "[a-z]*?s",
+            "(?>a|ab)c",
+            r"\s",
+            ".",
+            "",
+        ];
+
+        let mut mismatches = Vec::new();
+        for pattern in corpus.iter() {
+            for &must_fully_match in &[true, false] {
+                for &case_sensitive in &[true, false] {
+                    mismatches.extend(compare(pattern, must_fully_match, case_sensitive, SAMPLES));
+                }
+            }
+        }
+
+        assert!(mismatches.is_empty(), "{:#?}", mismatches);
+    }
+
+    /// Compares the backends on every regex of the English rules, using the words of the examples as samples.
+    /// Needs the LanguageTool XML in `data/en`, see `BUILD.md`.
+    #[test]
+    #[ignore]
+    #[cfg(feature = "compile")]
+    fn backends_agree_on_english_rules() {
+        use std::collections::BTreeSet;
+
+        let mut patterns = BTreeSet::new();
+        let mut words = BTreeSet::new();
+
+        for path in &[
+            "../data/en/grammar.canonic.xml",
+            "../data/en/disambiguation.canonic.xml",
+        ] {
+            let xml = std::fs::read_to_string(path).unwrap();
+            let document = roxmltree::Document::parse(&xml).unwrap();
+
+            for node in document.descendants().filter(|x| x.is_element()) {
+                let case_sensitive = node.attribute("case_sensitive") == Some("yes");
+                let text = node.text().unwrap_or("").trim().to_string();
+
+                match node.tag_name().name() {
+                    "token" | "exception" if node.attribute("regexp") == Some("yes") => {
+                        patterns.insert((text, true, case_sensitive));
+                    }
+                    "regexp" => {
+                        patterns.insert((text, false, case_sensitive));
+                    }
+                    "example" => words.extend(
+                        node.descendants()
+                            .filter_map(|x| x.text())
+                            .flat_map(|x| x.split_whitespace())
+                            .map(|x| x.to_string()),
+                    ),
+                    _ => {}
+                }
+
+                if node.attribute("postag_regexp") == Some("yes") {
+                    if let Some(postag) = node.attribute("postag") {
+                        patterns.insert((postag.to_string(), true, true));
+                    }
+                }
+            }
+        }
+
+        let samples: Vec<_> = SAMPLES
+            .iter()
+            .copied()
+            .chain(words.iter().map(|x| x.as_str()).take(1000))
+            .collect();
+        let mismatches: Vec<_> = patterns
+            .iter()
+            .flat_map(|(pattern, must_fully_match, case_sensitive)| {
+                compare(pattern, *must_fully_match, *case_sensitive, &samples)
+            })
+            .collect();
+
+        assert!(mismatches.is_empty(), "{:#?}", mismatches);
+    }
+
+    #[test]
+    fn regexes_are_compiled_lazily() {
+        let bytes = bincode::serialize(&SerializeRegex::new("a+", true, true).unwrap()).unwrap();
+
+        let eager: SerializeRegex = bincode::deserialize(&bytes).unwrap();
+        assert!(eager.regex.get().is_some());
+
+        let lazy: SerializeRegex = deserialize_lazily(|| bincode::deserialize(&bytes).unwrap());
+        assert!(lazy.regex.get().is_none());
+        assert!(lazy.is_match("aa"));
+        assert!(lazy.regex.get().is_some());
+        assert!(!LAZY.with(|x| x.get()));
+
+        // invalid regexes fail to deserialize, or panic on first use if they are deserialized lazily
+        let invalid = |backend| {
+            bincode::serialize(&RegexFields {
+                regex_str: "(".into(),
+                case_sensitive: true,
+                engine: RegexEngine::Regex,
+                backend,
+            })
+            .unwrap()
+        };
+        assert!(bincode::deserialize::<SerializeRegex>(&invalid(ACTIVE_BACKEND)).is_err());
+        let lazy: SerializeRegex =
+            deserialize_lazily(|| bincode::deserialize(&invalid(ACTIVE_BACKEND)).unwrap());
+        assert!(std::panic::catch_unwind(|| lazy.is_match("(")).is_err());
+
+        // unless they were built with the other backend, then they are compiled right away
+        let other = match ACTIVE_BACKEND {
+            RegexBackend::Onig => RegexBackend::Rust,
+            RegexBackend::Rust => RegexBackend::Onig,
+        };
+        assert!(deserialize_lazily(|| {
+            bincode::deserialize::<SerializeRegex>(&invalid(other)).is_err()
+        }));
+        let mut fields: RegexFields = bincode::deserialize(&bytes).unwrap();
+        fields.backend = other;
+        let eager: SerializeRegex = deserialize_lazily(|| {
+            bincode::deserialize(&bincode::serialize(&fields).unwrap()).unwrap()
+        });
+        assert!(eager.regex.get().is_some());
     }
 }
@@ -0,0 +1,59 @@
+//! Compiles a tokenizer and rules from XML in memory through the public API, as a crate using nlprule would.
+#![cfg(feature = "compile")]
+
+use std::{collections::HashSet, sync::Arc};
+
+use nlprule::{
+    compile::{BuildInfo, RegexCache},
+    rules::{Rules, RulesOptions},
+    tokenizer::{tag::Tagger, Tokenizer, TokenizerOptions},
+};
+
+const GRAMMAR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rules lang="en">
+    <category id="TEST" name="Test">
+        <rule id="USES_NOUN" name="Uses noun">
+            <pattern>
+                <token>uses</token>
+                <token postag="NN"/>
+            </pattern>
+            <message>Did you mean <suggestion>uses the <match no="2"/></suggestion>?</message>
+            <example correction="uses the tool">He <marker>uses tool</marker>.</example>
+        </rule>
+    </category>
+</rules>
+"#;
+
+#[test]
+fn rules_can_be_compiled_from_readers() {
+    let tag_path =
+        std::env::temp_dir().join(format!("nlprule_{}_memory_tags.txt", std::process::id()));
+    std::fs::write(&tag_path, "uses\tuse\tVBZ\ntool\ttool\tNN\n").unwrap();
+
+    let tagger = Tagger::from_dumps::<_, &str, &str>(
+        &[tag_path.to_str().unwrap()],
+        &[],
+        &[],
+        &HashSet::new(),
+    )
+    .unwrap();
+    let mut build_info = BuildInfo::new(Arc::new(tagger), RegexCache::new(0));
+
+    let tokenizer = Tokenizer::from_xml_reader(
+        r#"<rules lang="en"></rules>"#.as_bytes(),
+        &mut build_info,
+        None,
+        None,
+        TokenizerOptions::default(),
+    )
+    .unwrap();
+    let rules =
+        Rules::from_xml_reader(GRAMMAR.as_bytes(), &mut build_info, RulesOptions::default())
+            .unwrap();
+
+    rules.check_tokenizer(&tokenizer).unwrap();
+    assert_eq!(
+        rules.correct("He uses tool.", &tokenizer),
+        "He uses the tool."
+    );
+}
@@ -0,0 +1,9 @@
+# Writes the LanguageTool tagger output compared by the `case_fallback_matches_languagetool` test.
+# Usage: scripts/case_fallback_fixtures.sh path/to/languagetool-commandline.jar
+# Use the LanguageTool version the binaries in `storage` are built from.
+set -e
+
+fixtures=nlprule/src/tokenizer/fixtures
+
+java -jar $1 -l en-US --taggeronly $fixtures/case_fallback_en.in > $fixtures/case_fallback_en.txt
+java -jar $1 -l de-DE --taggeronly $fixtures/case_fallback_de.in > $fixtures/case_fallback_de.txt